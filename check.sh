@@ -30,6 +30,19 @@ diff_cmd() {
     diff -a /tmp/mine /tmp/ref
 }
 
+# List the entries of a zip file with their attributes and content, and its
+# comment, everything but the compressed data.
+zip_entries() {
+    python3 - "$1" <<'EOF'
+import sys, zipfile
+z = zipfile.ZipFile(sys.argv[1])
+print(z.comment)
+for i in z.infolist():
+    print(i.filename, i.create_system, i.create_version, i.extract_version, i.flag_bits,
+          oct(i.external_attr), i.internal_attr, i.date_time, i.extra.hex(), z.read(i))
+EOF
+}
+
 assert_init() {
     (
         cd "$1"
@@ -161,6 +174,32 @@ touch foo
 diff_cmd hash-object -w foo
 cleanup
 
+setup "git hash-object -w <file> (small and large objects)"
+"$TARGET" init >/dev/null
+echo small >small
+head -c 200000 /dev/urandom >large
+for f in small large; do
+    BLOB=$("$TARGET" hash-object -w "$f")
+    test "$BLOB" = "$(git hash-object "$f")"
+    diff "$f" <(git cat-file -p "$BLOB")
+    test "$(stat -c %a ".git/objects/${BLOB:0:2}/${BLOB:2}")" = 444
+done
+test -z "$(find .git/objects -maxdepth 1 -name 'tmp*')"
+cleanup
+
+setup "git hash-object [-w] --stdin"
+"$TARGET" init >/dev/null
+diff <("$TARGET" hash-object --stdin <"$ROOT/Cargo.toml") <(git hash-object --stdin <"$ROOT/Cargo.toml")
+BLOB=$(head -c 200000 /dev/zero | "$TARGET" hash-object -w --stdin)
+test "$BLOB" = "$(head -c 200000 /dev/zero | git hash-object --stdin)"
+test "$(git cat-file -s "$BLOB")" = 200000
+# content is spilled next to the objects when writing, not in $TMPDIR
+test "$(head -c 200001 /dev/zero | TMPDIR=/nonexistent "$TARGET" hash-object -w --stdin)" = \
+    "$(head -c 200001 /dev/zero | git hash-object --stdin)"
+test -z "$(find .git/objects -maxdepth 1 -name 'tmp*')"
+test "$(printf '' | "$TARGET" hash-object --stdin)" = "$(git hash-object --stdin </dev/null)"
+cleanup
+
 setup "git ls-tree [--name-only] <tree>"
 "$TARGET" init >/dev/null
 populate_tree
@@ -168,6 +207,23 @@ git add . >/dev/null
 TREE=$(git write-tree)
 diff_cmd ls-tree --name-only "$TREE"
 diff_cmd ls-tree "$TREE"
+diff <("$TARGET" --format=json ls-tree "$TREE" | jq -r '"\(.mode) \(.type) \(.hash)\t\(.name)"') \
+    <(git ls-tree "$TREE")
+diff <("$TARGET" --format=json ls-tree --name-only "$TREE" | jq -r .name) <(git ls-tree --name-only "$TREE")
+cleanup
+
+setup "git ls-tree --abbrev[=<n>] <tree> (and core.abbrev)"
+"$TARGET" init >/dev/null
+mkdir dir && for i in $(seq 1 1000); do echo "$i" > "dir/$i"; done
+git add . >/dev/null
+TREE=$(git write-tree --prefix=dir/)
+git repack -qd && ls .git/objects/pack/*.pack >/dev/null
+diff_cmd ls-tree --abbrev "$TREE"
+diff_cmd ls-tree --abbrev=4 "$TREE"
+grep -q ' [0-9a-f]\{5\}	' /tmp/mine
+echo more >> dir/1 && echo more >> dir/2
+diff_cmd -c core.abbrev=4 diff
+diff_cmd -c core.abbrev=no diff
 cleanup
 
 setup "git cat-file -p <tree>"
@@ -178,6 +234,22 @@ TREE=$(git write-tree)
 diff_cmd cat-file -p "$TREE"
 cleanup
 
+setup "git cat-file --batch-check"
+git init -q
+printf 'a\tb\n' > 'quoted "name"' && echo b > b && git add -A && git commit -q -m base && git tag -a -m v1 v1
+BLOB=$(git rev-parse HEAD:b)
+printf '%s\n' HEAD HEAD: v1 "HEAD:quoted \"name\"" "$BLOB" nope "${BLOB%?}0" HEAD~ > names
+git cat-file --batch-check < names > "$OTHERDIR/ref"
+"$TARGET" cat-file --batch-check < names > "$OTHERDIR/mine"
+diff "$OTHERDIR/mine" "$OTHERDIR/ref"
+"$TARGET" --format=json cat-file --batch-check < names |
+    jq -r 'if .missing then "\(.name) missing" else "\(.hash) \(.type) \(.size)" end' > "$OTHERDIR/mine"
+diff "$OTHERDIR/mine" "$OTHERDIR/ref"
+"$TARGET" --format=json ls-tree HEAD | jq -e 'select(.name == "quoted \"name\"")' >/dev/null
+if "$TARGET" --format=xml ls-tree HEAD 2>/dev/null; then false; fi
+"$TARGET" --format=json cat-file -p HEAD 2>&1 | grep -q "only supported by"
+cleanup
+
 setup "git cat-file -p <commit>"
 "$TARGET" init >/dev/null
 git commit --allow-empty -mtest-commit >/dev/null
@@ -193,6 +265,79 @@ TAG=$(git rev-parse test-tag)
 diff_cmd cat-file -p "$TAG"
 cleanup
 
+setup "git mktag"
+git init -q
+git commit -q --allow-empty -m commit
+COMMIT=$(git rev-parse HEAD)
+tag() { printf 'object %s\ntype %s\ntag %s\ntagger A U Thor <author@example.com> 0 +0100\n%b' "$@"; }
+tag "$COMMIT" commit v1 "\nmessage\n" >good
+test "$("$TARGET" mktag <good)" = "$(git mktag <good)"
+tag "$COMMIT" commit v1 "" >no-message
+test "$("$TARGET" mktag <no-message)" = "$(git mktag <no-message)"
+diff_cmd cat-file -p "$(git mktag <good)"
+# rejected: wrong type, missing object, bad tag name, extra header, no tagger
+for bad in "$(tag "$COMMIT" tree v1 "")" "$(tag "$(git rev-parse HEAD^{tree})" commit v1 "")" \
+    "$(tag 0123456789012345678901234567890123456789 commit v1 "")" \
+    "$(tag "$COMMIT" commit bad..name "")" "$(tag "$COMMIT" commit v1 "extra header\n")" \
+    "$(printf 'object %s\ntype commit\ntag v1\n\nmessage\n' "$COMMIT")"; do
+    if printf '%s\n' "$bad" | git mktag 2>/dev/null; then false; fi
+    if printf '%s\n' "$bad" | "$TARGET" mktag 2>/dev/null; then false; fi
+done
+cleanup
+
+setup "git config [--get | --get-all | --add | --list] [--type=<type>] [<name> [<value>]]"
+git init -q
+cat >>.git/config <<'EOF'
+# a comment to keep
+[foo] ; another one
+	flag
+	size = 2k ; comment
+	quoted = "a b ; c" # comment
+	multi = one
+	multi = two
+[include]
+	path = included
+[includeIf "onbranch:main"]
+	path = on-main
+[includeIf "onbranch:other"]
+	path = on-other
+[includeIf "gitdir:/nowhere/"]
+	path = on-other
+EOF
+printf '[Bar "Sub"]\n\tName = "with \\"escapes\\"\\t"\n' > .git/included
+printf '[main]\n\tonly = yes\n' > .git/on-main
+printf '[other]\n\tonly = yes\n' > .git/on-other
+(
+    export HOME="$OTHERDIR" GIT_CONFIG_NOSYSTEM=1
+    diff_cmd config --list
+    diff_cmd -c x.y=z config --list
+    diff_cmd config --get foo.flag
+    diff_cmd config --type=bool foo.flag
+    diff_cmd config --type=int foo.size
+    diff_cmd config --type=bool main.only
+    diff_cmd config foo.multi
+    diff_cmd config --get-all foo.multi
+    diff_cmd config bar.Sub.name
+    if "$TARGET" config other.only; then false; fi
+    if "$TARGET" config --type=int foo.quoted 2>/dev/null; then false; fi
+    if "$TARGET" config foo.multi three 2>/dev/null; then false; fi
+)
+cp -r .git "$OTHERDIR/copy.git"
+for args in "foo.size 3m" "foo.new value" "bar.Sub.x y" "new.key with#hash" "foo.quoted with\"quote"; do
+    git config $args
+    GIT_DIR="$OTHERDIR/copy.git" "$TARGET" config $args
+done
+git config --add foo.multi three
+GIT_DIR="$OTHERDIR/copy.git" "$TARGET" config --add foo.multi three
+diff "$OTHERDIR/copy.git/config" .git/config
+touch .git/config.lock
+"$TARGET" config foo.locked yes 2>&1 | grep -q "another process is running"
+rm .git/config.lock
+"$TARGET" config foo.locked yes
+test ! -e .git/config.lock
+test "$(git config foo.locked)" = yes
+cleanup
+
 setup "git write-tree"
 "$TARGET" init >/dev/null
 populate_tree
@@ -224,6 +369,29 @@ git add .
 diff_cmd write-tree
 cleanup
 
+setup "git write-tree (many files, hashed in parallel)"
+"$TARGET" init >/dev/null
+for d in $(seq 20); do
+    mkdir "d$d" "d$d/sub"
+    for f in $(seq 20); do echo "$d $f" >"d$d/f$f"; echo "$f" >"d$d/sub/f$f"; done
+done
+git add .
+diff_cmd write-tree
+git fsck --no-dangling
+cleanup
+
+setup "git write-tree (objects already packed are not written)"
+"$TARGET" init >/dev/null
+populate_tree
+rm -r ignored-dir # its empty tree would be written
+git add .
+git commit -q -m first
+git gc -q
+test "$(git count-objects | cut -d' ' -f1)" = 0
+diff_cmd write-tree
+test "$(git count-objects | cut -d' ' -f1)" = 0
+cleanup
+
 setup "git commit-tree <tree> -m <message> [-p <parent>]"
 "$TARGET" init >/dev/null
 TREE=$("$TARGET" write-tree)
@@ -248,6 +416,1382 @@ TREE=$("$TARGET" write-tree)
 )
 cleanup
 
+setup "git mktree / git commit-tree <tree> (message from stdin)"
+git init -q
+BLOB=$(echo hello | git hash-object -w --stdin)
+SUB=$(printf '100644 blob %s\tfile\n' "$BLOB" | "$TARGET" mktree)
+test "$SUB" = "$(printf '100644 blob %s\tfile\n' "$BLOB" | git mktree)"
+# any order, leading zeroes in modes, directories sorted with a '/' appended
+printf '100644 blob %s\tdir.c\n040000 tree %s\tdir\n100755 blob %s\tx\n160000 commit %s\tsub\n' \
+    "$BLOB" "$SUB" "$BLOB" "$(git hash-object -t commit --literally /dev/null)" >entries
+diff <(git mktree <entries) <("$TARGET" mktree <entries)
+TREE=$("$TARGET" mktree <entries)
+git fsck --no-dangling 2>/dev/null
+if printf '100644 tree %s\tx\n' "$SUB" | "$TARGET" mktree 2>/dev/null; then false; fi
+if printf '100644 blob %s\ta/b\n' "$BLOB" | "$TARGET" mktree 2>/dev/null; then false; fi
+(
+    export GIT_AUTHOR_NAME="A. Hacker" GIT_AUTHOR_EMAIL="hacker@example.org"
+    export GIT_COMMITTER_NAME="A. Maintainer" GIT_COMMITTER_EMAIL="maint@example.org"
+    export GIT_AUTHOR_DATE="@0 +0000" GIT_COMMITTER_DATE="@86400 +0000"
+    printf 'subject\n\nbody, kept as is\n\n\n' >message
+    test "$(git commit-tree "$TREE" <message)" = "$("$TARGET" commit-tree "$TREE" <message)"
+)
+cleanup
+
+setup "git commit-tree -S / git verify-commit / git verify-tag (gpg and ssh)"
+git init -q
+git commit -q --allow-empty -m unsigned
+TREE=$(git rev-parse HEAD^{tree})
+(
+    export GNUPGHOME="$OTHERDIR/gnupg"
+    mkdir -m 700 "$GNUPGHOME"
+    gpg -q --batch --passphrase '' --quick-gen-key "Signer <signer@example.org>" ed25519 sign never 2>/dev/null
+    export GIT_COMMITTER_NAME=Signer GIT_COMMITTER_EMAIL=signer@example.org
+    SIGNED=$("$TARGET" commit-tree -S -m signed "$TREE")
+    git verify-commit "$SIGNED" 2>/dev/null
+    "$TARGET" verify-commit "$SIGNED" 2>"$OTHERDIR/out"
+    grep -q 'Good signature from "Signer <signer@example.org>"' "$OTHERDIR/out"
+    git commit -q --allow-empty -S -m "signed by git"
+    "$TARGET" verify-commit HEAD 2>/dev/null
+    if "$TARGET" verify-commit HEAD~1 2>/dev/null; then false; fi
+    # a signed commit with a changed message
+    FORGED=$(git cat-file commit "$SIGNED" | sed s/^signed/forged/ | git hash-object -t commit -w --stdin)
+    if git verify-commit "$FORGED" 2>/dev/null; then false; fi
+    if "$TARGET" verify-commit "$FORGED" 2>/dev/null; then false; fi
+    git tag -s -m "signed tag" signed
+    git tag -a -m "unsigned tag" unsigned
+    "$TARGET" verify-tag signed 2>/dev/null
+    if "$TARGET" verify-tag unsigned 2>/dev/null; then false; fi
+    if "$TARGET" verify-tag HEAD 2>/dev/null; then false; fi
+    gpgconf --kill gpg-agent
+)
+ssh-keygen -q -t ed25519 -N '' -f "$OTHERDIR/key"
+echo "signer@example.org $(cat "$OTHERDIR/key.pub")" >"$OTHERDIR/allowed"
+git config gpg.format ssh
+git config user.signingKey "$OTHERDIR/key"
+git config gpg.ssh.allowedSignersFile "$OTHERDIR/allowed"
+SIGNED=$("$TARGET" commit-tree -S -m "ssh signed" "$TREE")
+git verify-commit "$SIGNED" 2>/dev/null
+"$TARGET" verify-commit "$SIGNED" 2>"$OTHERDIR/out"
+grep -q 'Good "git" signature for signer@example.org' "$OTHERDIR/out"
+# commit.gpgSign, for commit-tree (unless --no-gpg-sign) and snapshot
+git config commit.gpgSign true
+git verify-commit "$("$TARGET" commit-tree -m "signed by config" "$TREE")" 2>/dev/null
+if git verify-commit "$("$TARGET" commit-tree --no-gpg-sign -m unsigned "$TREE")" 2>/dev/null; then false; fi
+echo new > new && "$TARGET" snapshot -m "signed snapshot" >/dev/null
+git verify-commit HEAD 2>/dev/null
+git config commit.gpgSign false
+git commit -q --allow-empty -S -m "ssh signed by git"
+"$TARGET" verify-commit HEAD 2>/dev/null
+: >"$OTHERDIR/allowed"
+if "$TARGET" verify-commit HEAD 2>/dev/null; then false; fi
+cleanup
+
+setup "git snapshot -m <message>"
+git init >/dev/null
+mkdir -p src build && echo code > src/main.c && echo obj > src/main.o && echo out > build/out
+printf '*.o\n/build/\n' > .gitignore
+"$TARGET" snapshot -m first | grep -q '^\[main (root-commit) [0-9a-f]\{7\}\] first$'
+FIRST=$(git rev-parse HEAD)
+"$TARGET" snapshot -m again | grep -q "nothing to snapshot"
+echo more >> src/main.c
+"$TARGET" snapshot -m second >/dev/null
+test "$(git rev-parse HEAD^)" = "$FIRST"
+test "$(git log --format=%s)" = "$(printf 'second\nfirst')"
+git add -A
+test "$(git write-tree)" = "$(git rev-parse HEAD^{tree})"
+test "$(git reflog --format=%gs)" = "$(printf 'snapshot: second\nsnapshot (initial): first')"
+cleanup
+
+setup "git snapshot (pre-commit, commit-msg and post-commit hooks)"
+git init >/dev/null
+echo a > f
+printf '#!/bin/sh\ntest ! -e block\n' > .git/hooks/pre-commit
+printf '#!/bin/sh\necho "$1" > .git/msg-arg\necho "Signed-off-by: me" >> "$1"\n' > .git/hooks/commit-msg
+printf '#!/bin/sh\necho done > .git/post-commit-ran\nexit 1\n' > .git/hooks/post-commit
+chmod +x .git/hooks/pre-commit .git/hooks/commit-msg .git/hooks/post-commit
+"$TARGET" snapshot -m first 2>/dev/null | grep -q '^\[main (root-commit) [0-9a-f]\{7\}\] first$'
+test "$(git log --format=%B)" = "$(printf 'first\nSigned-off-by: me\n')"
+grep -q 'COMMIT_EDITMSG$' .git/msg-arg
+test -e .git/post-commit-ran
+touch block && echo b > f
+if "$TARGET" snapshot -m blocked 2>/dev/null; then false; fi
+test "$(git rev-list --count HEAD)" = 1
+"$TARGET" snapshot --no-verify -m unverified >/dev/null 2>&1
+test "$(git log -1 --format=%B)" = unverified
+rm block && echo c > f
+printf '#!/bin/sh\nexit 1\n' > .git/hooks/commit-msg
+if "$TARGET" snapshot -m rejected 2>/dev/null; then false; fi
+test "$(git log -1 --format=%s)" = unverified
+cleanup
+
+setup "hooks (failure output, GIT_HOOKS_DISABLE, core.hooksPath)"
+git init -q && echo a > f
+printf '#!/bin/sh\necho to stdout\necho to stderr >&2\nexit 1\n' > .git/hooks/pre-commit
+chmod +x .git/hooks/pre-commit
+# A failing hook aborts the command, with its output in the error.
+if "$TARGET" snapshot -m blocked >/dev/null 2>"$OTHERDIR/err"; then false; fi
+grep -q "hook pre-commit failed" "$OTHERDIR/err"
+grep -qx "to stdout" "$OTHERDIR/err"
+grep -qx "to stderr" "$OTHERDIR/err"
+if git rev-parse -q --verify HEAD; then false; fi
+GIT_HOOKS_DISABLE=1 "$TARGET" snapshot -m unhooked >/dev/null
+test "$(git log -1 --format=%s)" = unhooked
+# core.hooksPath is relative to the root of the worktree.
+mv .git/hooks hooks && git config core.hooksPath hooks
+mkdir sub && echo b > f
+if (cd sub && "$TARGET" snapshot -m blocked >/dev/null 2>&1); then false; fi
+test "$(git rev-list --count HEAD)" = 1
+chmod -x hooks/pre-commit
+(cd sub && "$TARGET" snapshot -m unblocked >/dev/null)
+test "$(git log -1 --format=%s)" = unblocked
+cleanup
+
+setup "read-only repositories (chmod -w)"
+git init -q && echo a > a && git add a && git commit -q -m first
+chmod -R a-w .git
+# Root can write anyway.
+if [ "$(id -u)" != 0 ]; then
+    # Reading commands work, without trying to write.
+    diff_cmd cat-file -p HEAD
+    diff_cmd ls-tree HEAD
+    diff_cmd rev-list HEAD
+    echo b > a && diff_cmd status --porcelain=v2
+    "$TARGET" fsck >/dev/null
+    # Writing commands fail, saying why.
+    if echo b | "$TARGET" hash-object -w --stdin >/dev/null 2>"$OTHERDIR/err"; then false; fi
+    grep -q "not writable by the current user" "$OTHERDIR/err"
+    if "$TARGET" snapshot -m second >/dev/null 2>"$OTHERDIR/err"; then false; fi
+    grep -q "not writable by the current user" "$OTHERDIR/err"
+    test "$(git rev-list --count HEAD)" = 1
+fi
+chmod -R u+w .git
+cleanup
+
+setup "core.autocrlf (snapshot, hash-object, reset --hard)"
+git init -q
+printf 'one\r\ntwo\r\n' > text
+printf 'bin\0ary\r\n' > binary
+printf 'lone\rcr\n' > lone-cr
+git config core.autocrlf true
+diff_cmd hash-object text
+"$TARGET" snapshot -m crlf >/dev/null
+test "$(git cat-file -p HEAD:text)" = "$(printf 'one\ntwo')"
+cmp <(git cat-file -p HEAD:binary) binary
+cmp <(git cat-file -p HEAD:lone-cr) lone-cr
+git add -A
+test -z "$(git status --porcelain)"
+rm text binary lone-cr
+"$TARGET" reset --hard >/dev/null
+cmp text <(printf 'one\r\ntwo\r\n')
+cmp binary <(printf 'bin\0ary\r\n')
+test -z "$(git status --porcelain)"
+git config core.autocrlf input
+printf 'three\r\n' >> text
+"$TARGET" snapshot -m input >/dev/null
+test "$(git cat-file -p HEAD:text)" = "$(printf 'one\ntwo\nthree')"
+cleanup
+
+setup ".gitattributes text / eol / binary (hash-object, checkout-empty)"
+git init -q
+printf '*.txt text\n*.bat eol=crlf\n*.sh eol=lf\n*.bin binary\nauto/* text=auto\n' > .gitattributes
+mkdir auto sub && printf 'sub/*.md -text\n' > sub/.gitattributes
+printf 'a\r\nb\r\n' > x.txt && printf 'a\nb\n' > y.bat && printf 'a\r\nb\r\n' > z.sh
+printf 'a\r\nb\r\n' > w.bin && printf 'a\r\nb\n' > auto/t && printf 'a\r\n\0' > auto/b
+printf 'a\r\n' > sub/m.md && printf 'a\r\n' > plain
+FILES="x.txt y.bat z.sh w.bin auto/t auto/b sub/m.md plain"
+for autocrlf in false true input; do
+    git config core.autocrlf $autocrlf
+    for file in $FILES; do diff_cmd hash-object $file; done
+done
+git config core.autocrlf false
+git add -A 2>/dev/null && git commit -q -m attributes
+COMMIT=$(git rev-parse HEAD)
+for autocrlf in false true; do
+    rm -rf "$OTHERDIR/expected" "$OTHERDIR/actual"
+    for dir in expected actual; do
+        mkdir "$OTHERDIR/$dir" && cp -a .git "$OTHERDIR/$dir/"
+        git -C "$OTHERDIR/$dir" config core.autocrlf $autocrlf
+    done
+    git -C "$OTHERDIR/expected" checkout -q -f "$COMMIT" -- .
+    (cd "$OTHERDIR/actual" && "$TARGET" checkout-empty "$COMMIT" >/dev/null)
+    diff -r -x .git "$OTHERDIR/expected" "$OTHERDIR/actual"
+done
+cleanup
+
+setup "filter.<driver>.clean / smudge (hash-object, checkout-empty)"
+git init -q
+printf '*.up filter=upper\n*.tag filter=tagged\n*.bad filter=broken\n*.need filter=needed\n' > .gitattributes
+git config filter.upper.clean 'tr a-z A-Z' && git config filter.upper.smudge 'tr A-Z a-z'
+git config filter.tagged.clean '{ echo %f; cat; }' && git config filter.tagged.smudge 'sed 1d'
+git config filter.broken.clean false && git config filter.needed.clean false
+git config filter.needed.required true
+mkdir "sub dir" && echo hello > a.up && echo hello > "sub dir/b.tag" && echo hello > c.bad
+for file in a.up "sub dir/b.tag" c.bad; do diff_cmd hash-object "$file"; done
+echo hello > d.need
+if "$TARGET" hash-object d.need 2>/dev/null; then false; fi
+rm d.need && git add -A 2>/dev/null && git commit -q -m filters
+COMMIT=$(git rev-parse HEAD)
+for dir in expected actual; do
+    mkdir "$OTHERDIR/$dir" && cp -a .git "$OTHERDIR/$dir/"
+done
+git -C "$OTHERDIR/expected" checkout -q -f "$COMMIT" -- .
+(cd "$OTHERDIR/actual" && "$TARGET" checkout-empty "$COMMIT" >/dev/null 2>&1)
+diff -r -x .git "$OTHERDIR/expected" "$OTHERDIR/actual"
+cleanup
+
+setup "git merge <branch> (fast-forward, three-way, conflict)"
+git init -b main >/dev/null
+seq 1 20 > lines && echo x > conflict && echo y > gone
+git add . && git commit -m base >/dev/null
+git checkout -b feat 2>/dev/null
+sed -i 's/^3$/three/' lines && echo new > new && git rm -q gone
+git add . && git commit -m feat >/dev/null
+git checkout main 2>/dev/null
+"$TARGET" merge feat > .git/merge-out
+grep -q Fast-forward .git/merge-out
+test "$(git rev-parse main)" = "$(git rev-parse feat)"
+test -z "$(git status --porcelain)"
+git checkout feat 2>/dev/null
+sed -i 's/^19$/nineteen/' lines && echo theirs > conflict
+git commit -am feat2 >/dev/null
+git checkout main 2>/dev/null
+sed -i 's/^1$/one/' lines
+git commit -am main2 >/dev/null
+cp -a . "$OTHERDIR"
+"$TARGET" merge feat > .git/merge-out
+grep -q "Merge made" .git/merge-out
+test "$(git log -1 --format=%P)" = "$(git rev-parse HEAD^1 feat | tr '\n' ' ' | sed 's/ $//')"
+test -z "$(git status --porcelain)"
+(cd "$OTHERDIR" && git merge -m merged feat >/dev/null)
+test "$(git rev-parse HEAD^{tree})" = "$(cd "$OTHERDIR" && git rev-parse HEAD^{tree})"
+git reset -q --hard HEAD^
+echo ours > conflict
+git commit -am main3 >/dev/null
+"$TARGET" merge feat > .git/merge-out 2>&1 || true
+grep -q "CONFLICT (content): Merge conflict in conflict" .git/merge-out
+test "$(git ls-files -u conflict | wc -l)" = 3
+grep -q '^>>>>>>> feat$' conflict
+echo resolved > conflict
+"$TARGET" snapshot -m "merge feat" >/dev/null
+test "$(git rev-parse HEAD^2)" = "$(git rev-parse feat)"
+test -z "$(git status --porcelain)"
+cleanup
+
+setup "git rev-parse [--verify] [--short[=<n>]] [--git-dir] <rev>..."
+git init -b main >/dev/null
+echo a > f && git add f && git commit -q -m first
+git checkout -q -b side && echo b > g && git add g && git commit -q -m side
+git checkout -q main && echo c > f && git commit -q -am second
+git merge -q --no-edit side
+git tag -a -m tag t HEAD~1
+diff_cmd rev-parse HEAD @ main HEAD~2 HEAD^2 HEAD^2~1 "HEAD^{tree}" t "t^{}" "t^{tree}" t~1
+diff_cmd rev-parse "$(git rev-parse HEAD | cut -c1-6)"
+diff_cmd rev-parse --verify HEAD^
+diff_cmd rev-parse --short HEAD
+diff_cmd rev-parse --short=10 side
+diff_cmd rev-parse --git-dir
+(cd "$OTHERDIR" && git init -q && mkdir sub && cd sub && diff_cmd rev-parse --git-dir)
+"$TARGET" rev-parse --verify HEAD~5 2>&1 | grep -q "no parent"
+"$TARGET" rev-parse nope 2>&1 | grep -q "unknown revision nope"
+cleanup
+
+setup "<rev>:<path> and ancestry operators in object arguments"
+git init -b main >/dev/null
+mkdir -p dir/sub && echo a > dir/sub/f && echo top > t && git add . && git commit -q -m first
+git checkout -q -b side && echo b > dir/g && git add dir/g && git commit -q -m side
+git checkout -q main && echo c > t && git commit -q -am second
+git merge -q --no-edit side
+diff_cmd rev-parse HEAD~1:t "HEAD^2:dir/g" main:dir "main@{1}:dir/sub/f" "HEAD^{tree}:dir/" main:
+diff_cmd cat-file -p main^2~1:dir/sub/f
+diff_cmd cat-file -p HEAD~1:t
+diff_cmd ls-tree main:dir
+diff_cmd ls-tree HEAD^2
+"$TARGET" commit-tree -p HEAD^2 -m msg main:dir >/dev/null
+"$TARGET" rev-parse main:nope 2>&1 | grep -q "does not exist"
+cleanup
+
+setup "git show [<object>...]"
+git init -b main >/dev/null
+mkdir d && echo a > f && echo x > d/x && git add .
+GIT_AUTHOR_DATE="@1700000000 -0730" git commit -q -m "first" -m "second paragraph"
+echo b > f && rm d/x && echo y > d/y && git add -A && git commit -q -m second
+git tag -a -m "tag message" v1
+git checkout -q -b side HEAD~1 && echo z > z && git add z && git commit -q -m side
+git checkout -q main && git merge -q --no-edit side
+diff_cmd show
+diff_cmd show HEAD~1 HEAD~2
+diff_cmd show v1
+diff_cmd show main:d main~1:f v1:d
+cleanup
+
+setup "commands run from a subdirectory (rev-parse --show-prefix, ls-tree, <rev>:./<path>)"
+git init -b main >/dev/null
+mkdir -p a/b && echo x > a/b/x && echo y > a/y && echo top > top && git add . && git commit -q -m first
+cd a
+diff_cmd rev-parse --show-prefix HEAD:./y HEAD:../top HEAD:./ HEAD:../
+diff_cmd ls-tree HEAD
+diff_cmd ls-tree --full-tree HEAD
+diff_cmd write-tree
+(cd b && diff_cmd ls-tree --name-only HEAD && diff_cmd rev-parse --show-prefix HEAD:./x)
+mkdir new && (cd new && diff_cmd ls-tree HEAD)
+cd ..
+diff_cmd rev-parse --show-prefix
+cleanup
+
+setup "git symbolic-ref / update-ref (compare-and-swap, reflog)"
+git init -b main >/dev/null
+git commit -q --allow-empty -m first && git commit -q --allow-empty -m second
+diff_cmd symbolic-ref HEAD
+diff_cmd symbolic-ref --short HEAD
+test "$("$TARGET" symbolic-ref -q refs/heads/main || echo no)" = no
+"$TARGET" symbolic-ref refs/heads/main 2>&1 | grep -q "not a symbolic ref"
+"$TARGET" symbolic-ref HEAD refs/heads/other
+test "$(git symbolic-ref HEAD)" = refs/heads/other
+"$TARGET" symbolic-ref HEAD refs/heads/main
+"$TARGET" update-ref -m "create side" refs/heads/side HEAD~1 ""
+test "$(git rev-parse side)" = "$(git rev-parse main~1)"
+"$TARGET" update-ref refs/heads/side HEAD main 2>&1 | grep -q "cannot update refs/heads/side"
+"$TARGET" update-ref -m "move side" refs/heads/side HEAD side
+test "$(git reflog --format=%gs side)" = "$(printf 'move side\ncreate side')"
+"$TARGET" update-ref -m "rewind main" HEAD HEAD~1
+test "$(git reflog --format=%gs -1 main)" = "rewind main"
+test "$(git reflog --format=%gs -1 HEAD)" = "rewind main"
+"$TARGET" update-ref --no-deref HEAD side
+test "$(git rev-parse HEAD)" = "$(git rev-parse side)"
+test "$(git symbolic-ref -q HEAD || echo detached)" = detached
+test "$(git rev-parse main)" = "$(git rev-parse side~1)"
+"$TARGET" update-ref refs/heads/tree "HEAD^{tree}" 2>&1 | grep -q "non-commit"
+"$TARGET" update-ref -d refs/heads/side main 2>&1 | grep -q "does not point to"
+"$TARGET" update-ref -d refs/heads/side side
+test -z "$(git branch --list side)"
+cleanup
+
+setup "git reflog [-n <count>] [<ref>]"
+git init -b main >/dev/null
+echo a > f && git add f && git commit -q -m one
+echo b > f && "$TARGET" snapshot -m two >/dev/null
+"$TARGET" update-ref -m "back to one" HEAD HEAD~1
+"$TARGET" checkout-empty main@{1} >/dev/null
+diff_cmd reflog
+diff_cmd reflog main
+diff_cmd reflog -n 2 refs/heads/main
+cleanup
+
+setup "git reset [--soft | --mixed | --hard] [<commit>]"
+git init -q -b main ref
+mkdir ref/d
+echo a > ref/a && echo x > ref/d/x && git -C ref add . && git -C ref commit -q -m one
+echo b > ref/a && echo y > ref/d/y && git -C ref add . && git -C ref commit -q -m two
+rm ref/d/x && echo c > ref/c && git -C ref add -A && git -C ref commit -q -m three
+cp -r ref mine
+# run the same command with git in ref and with us in mine, compare the results
+both() {
+    (cd ref && git "$@") > /tmp/ref
+    (cd mine && "$TARGET" "$@") > /tmp/mine
+    diff -a /tmp/mine /tmp/ref
+    test "$(git -C mine ls-files -s)" = "$(git -C ref ls-files -s)"
+    test "$(git -C mine status --porcelain)" = "$(git -C ref status --porcelain)"
+    test "$(git -C mine rev-parse HEAD)" = "$(git -C ref rev-parse HEAD)"
+    test "$(git -C mine reflog -1)" = "$(git -C ref reflog -1)"
+    test "$(git -C mine reflog main)" = "$(git -C ref reflog main)"
+}
+both reset --soft HEAD~1
+both reset
+both reset --mixed HEAD~1
+both reset main@{1}
+for repo in ref mine; do
+    echo changed > $repo/a && echo untracked > $repo/u && git -C $repo add a
+    # old enough that git trusts the file system data in the index
+    touch -d "1 hour ago" $repo/a
+done
+both reset HEAD
+both reset --hard HEAD~1
+test "$(cat mine/u)" = untracked
+both reset --hard main@{2}
+# a conflicted merge is forgotten
+for repo in ref mine; do
+    git -C $repo checkout -q -b side && echo side > $repo/a && git -C $repo commit -q -a -m side
+    git -C $repo checkout -q main && echo main > $repo/a && git -C $repo commit -q -a -m main
+    git -C $repo merge -q side >/dev/null 2>&1 || true
+done
+if (cd mine && "$TARGET" reset --soft 2>/dev/null); then false; fi
+both reset --hard
+test ! -e mine/.git/MERGE_HEAD
+cleanup
+
+setup "git stash [push [-m <message>] | list | apply | pop | drop]"
+git init -q -b main ref
+mkdir ref/d
+echo a > ref/a && echo x > ref/d/x && echo k > ref/k && git -C ref add . && git -C ref commit -q -m one
+cp -r ref mine
+# same commits as git, for identical stashes
+export GIT_AUTHOR_NAME="A. Hacker" GIT_AUTHOR_EMAIL="hacker@example.org"
+export GIT_COMMITTER_NAME="A. Maintainer" GIT_COMMITTER_EMAIL="maint@example.org"
+export GIT_AUTHOR_DATE="@0 +0000" GIT_COMMITTER_DATE="@86400 +0000"
+# run the same command with git in ref and with us in mine, compare the results
+both() {
+    (cd ref && git "$@") > /tmp/ref
+    (cd mine && "$TARGET" "$@") > /tmp/mine
+    test "$(git -C mine ls-files -s)" = "$(git -C ref ls-files -s)"
+    test "$(git -C mine status --porcelain)" = "$(git -C ref status --porcelain)"
+    test "$(git -C mine stash list --format='%H %gs')" = "$(git -C ref stash list --format='%H %gs')"
+}
+both stash
+diff -a /tmp/mine /tmp/ref
+for repo in ref mine; do
+    echo b > $repo/a && echo y > $repo/d/y && git -C $repo add a d/y && echo c > $repo/a
+    rm $repo/k && echo untracked > $repo/u
+done
+both stash
+diff -a /tmp/mine /tmp/ref
+test "$(cat mine/a mine/k mine/u)" = "$(printf 'a\nk\nuntracked')" && test ! -e mine/d/y
+for repo in ref mine; do
+    echo z > $repo/d/x && git -C $repo add d/x
+done
+both stash push -m "second one"
+diff -a /tmp/mine /tmp/ref
+both stash list
+diff -a /tmp/mine /tmp/ref
+both stash apply stash@{1}
+both reset --hard
+both stash pop
+both stash drop
+diff -a /tmp/mine /tmp/ref
+both stash list
+# conflicts keep the stash
+for repo in ref mine; do
+    git -C $repo reset -q --hard && echo stashed > $repo/d/x && git -C $repo stash -q
+    echo other > $repo/d/x && git -C $repo commit -q -a -m two
+done
+if (cd mine && "$TARGET" stash pop >/dev/null 2>&1); then false; fi
+git -C ref stash pop >/dev/null 2>&1 || true
+test "$(git -C mine ls-files -s)" = "$(git -C ref ls-files -s)"
+test "$(cat mine/d/x)" = "$(cat ref/d/x)"
+test -n "$(git -C mine stash list)" && test "$(git -C mine stash list)" = "$(git -C ref stash list)"
+# nothing to apply over local changes
+cd mine
+echo dirty > a
+if "$TARGET" stash apply 2>/dev/null; then false; fi
+unset GIT_AUTHOR_NAME GIT_AUTHOR_EMAIL GIT_COMMITTER_NAME GIT_COMMITTER_EMAIL
+unset GIT_AUTHOR_DATE GIT_COMMITTER_DATE
+cleanup
+
+setup "git worktree add|list|remove, linked worktrees"
+git init -q -b main && echo a > a && git add a && git commit -q -m a
+"$TARGET" worktree add "$OTHERDIR/new" 2>/dev/null | grep -q "^HEAD is now at"
+"$TARGET" worktree add -b topic "$OTHERDIR/topic" main 2>/dev/null >/dev/null
+"$TARGET" worktree add --detach "$OTHERDIR/detached" 2>/dev/null >/dev/null
+git worktree add -q "$OTHERDIR/by-git" 2>/dev/null
+diff_cmd worktree list
+diff_cmd worktree list --porcelain
+test "$(cat "$OTHERDIR/new/a")" = a
+test "$(git -C "$OTHERDIR/new" status --porcelain)" = ""
+# A branch can only be checked out in one worktree.
+if "$TARGET" worktree add "$OTHERDIR/again" main 2>/dev/null; then false; fi
+if "$TARGET" worktree add "$OTHERDIR/again" topic 2>/dev/null; then false; fi
+test ! -e "$OTHERDIR/again"
+# Branches are shared, HEAD and refs/worktree/ are not.
+(cd "$OTHERDIR/new" && echo b > b && "$TARGET" snapshot -m b > /dev/null)
+test "$(git log --format=%s -1 new)" = b
+test "$(git log --format=%s -1)" = a
+(cd "$OTHERDIR/topic" && "$TARGET" update-ref refs/worktree/mine HEAD)
+test "$(git -C "$OTHERDIR/topic" rev-parse refs/worktree/mine)" = "$(git rev-parse main)"
+if git rev-parse -q --verify refs/worktree/mine; then false; fi
+(cd "$OTHERDIR/by-git" && diff_cmd rev-parse --git-dir --git-common-dir HEAD)
+# Objects used by other worktrees survive gc.
+echo staged > "$OTHERDIR/detached/staged"
+git -C "$OTHERDIR/detached" add staged
+"$TARGET" gc --prune=now
+git -C "$OTHERDIR/detached" fsck 2>/dev/null
+git -C "$OTHERDIR/new" fsck 2>/dev/null
+# Only clean linked worktrees are removed, unless forced.
+if "$TARGET" worktree remove "$OTHERDIR/detached" 2>/dev/null; then false; fi
+if "$TARGET" worktree remove . 2>/dev/null; then false; fi
+"$TARGET" worktree remove "$OTHERDIR/topic"
+test ! -e "$OTHERDIR/topic"
+"$TARGET" worktree remove --force "$OTHERDIR/detached"
+test "$(git worktree list | wc -l)" = 3
+test -z "$(git worktree prune -n)"
+cleanup
+
+setup "a .git file pointing to the git directory (gitdir: <path>)"
+git init -q -b main --separate-git-dir "$OTHERDIR/repo.git" checkout
+cd checkout
+echo a > a && mkdir d && echo b > d/b && git add a d && git commit -q -m first
+echo "gitdir: ../../$(basename "$OTHERDIR")/repo.git" > .git
+(cd d && diff_cmd rev-parse --git-dir --git-common-dir --show-prefix HEAD)
+(cd d && diff_cmd ls-files)
+echo c > c && "$TARGET" snapshot -m second > /dev/null
+test "$(git log --format=%s -1)" = second
+"$TARGET" clone . ../copy > /dev/null
+test "$(git -C ../copy config remote.origin.url)" = "$PWD"
+test "$(git -C ../copy rev-parse HEAD)" = "$(git rev-parse HEAD)"
+echo "gitdir: nowhere" > .git
+if "$TARGET" rev-parse HEAD 2>/dev/null; then false; fi
+cleanup
+
+setup "git notes [--ref <ref>] add|show|list, notes in git show"
+git init -q -b main
+for i in $(seq 300); do
+    printf 'commit refs/heads/main\ncommitter C <c@example.org> %s +0000\ndata <<EOT\nc%s\nEOT\n' $i $i
+    printf 'M 644 inline f\ndata <<EOT\n%s\nEOT\n\n' $i
+done | git fast-import --quiet
+git checkout -q main
+git clone -q --mirror . "$OTHERDIR/git.git"
+(
+export GIT_AUTHOR_NAME="A. Hacker" GIT_AUTHOR_EMAIL="hacker@example.org"
+export GIT_COMMITTER_NAME="A. Maintainer" GIT_COMMITTER_EMAIL="maint@example.org"
+export GIT_AUTHOR_DATE="@0 +0000" GIT_COMMITTER_DATE="@86400 +0000"
+"$TARGET" notes add -m first -m "second paragraph" main~1
+git -C "$OTHERDIR/git.git" notes add -m first -m "second paragraph" main~1
+test "$(git rev-parse refs/notes/commits)" = "$(git -C "$OTHERDIR/git.git" rev-parse refs/notes/commits)"
+diff_cmd notes list
+diff_cmd notes list main~1
+diff_cmd notes show main~1
+diff_cmd show main~1
+if "$TARGET" notes add -m again main~1 2>/dev/null; then false; fi
+if "$TARGET" notes show main 2>/dev/null; then false; fi
+"$TARGET" notes add -f -m replaced main~1
+diff_cmd notes show main~1
+"$TARGET" notes --ref review add -m "looks good"
+diff_cmd notes --ref review list
+GIT_NOTES_REF=refs/notes/review diff_cmd show
+# With many notes, they are spread in fan-out directories like git does.
+for commit in $(git rev-list main~2); do
+    "$TARGET" notes add -m "note $commit" $commit
+    git -C "$OTHERDIR/git.git" notes add -m "note $commit" $commit
+done
+git -C "$OTHERDIR/git.git" notes add -f -m replaced main~1
+test "$(git rev-parse refs/notes/commits^{tree})" = "$(git -C "$OTHERDIR/git.git" rev-parse refs/notes/commits^{tree})"
+git ls-tree --name-only refs/notes/commits | grep -q '^[0-9a-f][0-9a-f]$'
+diff_cmd notes list
+diff_cmd show main~100
+# An empty note removes it.
+"$TARGET" notes add -f -m "" main~1 2>/dev/null
+if git notes show main~1 2>/dev/null; then false; fi
+)
+cleanup
+
+setup "git shortlog [-s] [-n] [-e] [<rev>...], .mailmap"
+git init -q -b main
+for author in "Bob <b@x>" "alice <a@x>" "Bob <b@x>" "Zed <z@x>" "Alice <A@X>"; do
+    git commit -q --allow-empty --author="$author" -m "by $author" -m "body"
+done
+git commit -q --allow-empty --author="Bob <b@x>" -m "[PATCH 2/3] applied" -m "body"
+git commit -q --allow-empty --allow-empty-message -m ""
+for options in "" -s -sn -e "-n -e"; do
+    diff_cmd shortlog $options HEAD
+done
+diff_cmd shortlog HEAD ^HEAD~3
+cat > .mailmap <<EOF
+# Comments are ignored
+Alice Proper <alice@new> <a@x>
+Bobby <b@x>
+<bob@new> <b@x>
+Z Z <z@new> Zed <Z@x>
+Nobody <n@x> Other <z@x>
+EOF
+diff_cmd shortlog -se HEAD
+diff_cmd shortlog HEAD
+mv .mailmap "$OTHERDIR/mailmap"
+git config mailmap.file "$OTHERDIR/mailmap"
+diff_cmd shortlog -sne main
+cleanup
+
+setup "git check-attr <attr>... [--] <path>..., git check-mailmap"
+git init -q -b main
+mkdir d
+printf '*.c text eol=crlf\nd/* -text foo=bar\n*.bin binary\n' > .gitattributes
+printf 'x.c !eol\n' > d/.gitattributes
+diff_cmd check-attr text a.c
+diff_cmd check-attr eol a.c d/x.c
+diff_cmd check-attr text eol foo -- a.c d/x.c d/y.bin
+diff_cmd check-attr binary diff merge -- y.bin
+echo "*.c -text" > .git/info/attributes
+(cd d && diff_cmd check-attr text -- x.c ../a.c)
+cat > .mailmap <<EOF
+Alice Proper <alice@new> <a@x>
+Bobby <b@x>
+<bob@new> <b@x>
+Nobody <n@x> Other <z@x>
+EOF
+for contact in "Bob <b@x>" "<a@x>" "x <A@X>" "Other <z@x>" "Zed <z@x>" "<n@x>"; do
+    diff_cmd check-mailmap "$contact"
+done
+diff_cmd check-mailmap "<a@x>" "Q <b@x>"
+test "$(echo "<a@x>" | "$TARGET" check-mailmap --stdin)" = "Alice Proper <alice@new>"
+if "$TARGET" check-mailmap nobody 2>/dev/null; then false; fi
+cleanup
+
+setup "git cherry-pick <commit>"
+git init -q -b main ref
+printf '1\n2\n3\n4\n5\n' > ref/f && echo a > ref/a && git -C ref add . && git -C ref commit -q -m base
+git -C ref checkout -q -b side
+printf '1\n2\n3\n4\nfive\n' > ref/f && echo b > ref/b && git -C ref add . && git -C ref commit -q -m "side one" -m body
+echo a2 > ref/a && GIT_AUTHOR_DATE="@1000 +0100" git -C ref commit -q -a -m "side two"
+git -C ref checkout -q main
+printf 'one\n2\n3\n4\n5\n' > ref/f && git -C ref commit -q -a -m "main one"
+cp -r ref mine
+(
+    # same commits as git
+    export GIT_COMMITTER_NAME="A. Maintainer" GIT_COMMITTER_EMAIL="maint@example.org"
+    export GIT_COMMITTER_DATE="@86400 +0000"
+    git -C ref cherry-pick side~1 >/dev/null
+    (cd mine && "$TARGET" cherry-pick side~1 >/dev/null)
+    test "$(git -C mine rev-parse HEAD)" = "$(git -C ref rev-parse HEAD)"
+    test "$(git -C mine reflog -1)" = "$(git -C ref reflog -1)"
+    test "$(git -C mine status --porcelain)" = "$(git -C ref status --porcelain)"
+    test "$(cat mine/f)" = "$(printf 'one\n2\n3\n4\nfive')"
+    # conflicts are recorded in the index
+    for repo in ref mine; do echo a3 > $repo/a && git -C $repo commit -q -a -m a3; done
+    if git -C ref cherry-pick side >/dev/null 2>&1; then false; fi
+    if (cd mine && "$TARGET" cherry-pick side >/dev/null 2>&1); then false; fi
+    test "$(git -C mine ls-files -s)" = "$(git -C ref ls-files -s)"
+    diff mine/a ref/a
+    diff mine/.git/CHERRY_PICK_HEAD ref/.git/CHERRY_PICK_HEAD
+    cd mine
+    echo resolved > a && "$TARGET" snapshot -m resolved >/dev/null
+    test -z "$(git status --porcelain)" && test ! -e .git/CHERRY_PICK_HEAD
+    if "$TARGET" cherry-pick side~1 2>/dev/null; then false; fi
+)
+cleanup
+
+setup "git revert <commit>"
+git init -q -b main ref
+printf '1\n2\n3\n4\n5\n' > ref/f && git -C ref add . && git -C ref commit -q -m base
+printf '1\n2\n3\n4\nfive\n' > ref/f && echo b > ref/b && git -C ref add . && git -C ref commit -q -m "add b"
+printf 'one\n2\n3\n4\nfive\n' > ref/f && git -C ref commit -q -a -m "change one"
+cp -r ref mine
+(
+    # same commits as git
+    export GIT_AUTHOR_NAME="A. Hacker" GIT_AUTHOR_EMAIL="hacker@example.org"
+    export GIT_COMMITTER_NAME="A. Maintainer" GIT_COMMITTER_EMAIL="maint@example.org"
+    export GIT_AUTHOR_DATE="@0 +0000" GIT_COMMITTER_DATE="@86400 +0000"
+    git -C ref revert --no-edit HEAD~1 >/dev/null
+    (cd mine && "$TARGET" revert HEAD~1 >/dev/null)
+    test "$(git -C mine rev-parse HEAD)" = "$(git -C ref rev-parse HEAD)"
+    test "$(git -C mine reflog -1)" = "$(git -C ref reflog -1)"
+    test "$(git -C mine status --porcelain)" = "$(git -C ref status --porcelain)"
+    test ! -e mine/b && test "$(cat mine/f)" = "$(printf 'one\n2\n3\n4\n5')"
+    # conflicts are recorded in the index
+    for repo in ref mine; do
+        printf 'uno\n2\n3\n4\n5\n' > $repo/f && git -C $repo commit -q -a -m uno
+    done
+    if git -C ref revert --no-edit HEAD~2 >/dev/null 2>&1; then false; fi
+    if (cd mine && "$TARGET" revert HEAD~2 >/dev/null 2>&1); then false; fi
+    test "$(git -C mine ls-files -s)" = "$(git -C ref ls-files -s)"
+    diff mine/f ref/f
+    diff mine/.git/REVERT_HEAD ref/.git/REVERT_HEAD
+    diff mine/.git/MERGE_MSG <(head -n 3 ref/.git/MERGE_MSG)
+)
+cleanup
+
+setup "git rebase <upstream>"
+git init -q -b main ref
+printf '1\n2\n3\n4\n5\n' > ref/f && git -C ref add . && git -C ref commit -q -m base
+git -C ref checkout -q -b topic
+echo a > ref/a && git -C ref add a && git -C ref commit -q -m "add a"
+printf '1\n2\n3\n4\nfive\n' > ref/f && git -C ref commit -q -a -m "five"
+echo b > ref/b && git -C ref add b && git -C ref commit -q -m "add b"
+git -C ref checkout -q main
+printf 'one\n2\n3\n4\n5\n' > ref/f && git -C ref commit -q -a -m "one"
+# already upstream: dropped
+echo b > ref/b && git -C ref add b && git -C ref commit -q -m "add b upstream"
+git -C ref checkout -q topic
+cp -r ref mine
+(
+    # same commits as git
+    export GIT_COMMITTER_NAME="A. Maintainer" GIT_COMMITTER_EMAIL="maint@example.org"
+    export GIT_COMMITTER_DATE="@86400 +0000"
+    git -C ref rebase main >/dev/null 2>&1
+    (cd mine && "$TARGET" rebase main >/dev/null)
+    test "$(git -C mine rev-parse HEAD)" = "$(git -C ref rev-parse HEAD)"
+    test "$(git -C mine symbolic-ref HEAD)" = refs/heads/topic
+    test "$(git -C mine reflog)" = "$(git -C ref reflog)"
+    test "$(git -C mine reflog topic)" = "$(git -C ref reflog topic)"
+    test "$(git -C mine status --porcelain)" = "$(git -C ref status --porcelain)"
+    test ! -e mine/.git/rebase-merge
+    cd mine
+    "$TARGET" rebase main | grep -q "is up to date"
+    # conflicts abort the rebase
+    git checkout -q -b conflicting main~2
+    printf 'uno\n2\n3\n4\n5\n' > f && git commit -q -a -m uno
+    echo c > c && git add c && git commit -q -m "add c"
+    BEFORE=$(git rev-parse HEAD)
+    if "$TARGET" rebase main >/dev/null 2>&1; then false; fi
+    test "$(git rev-parse HEAD)" = "$BEFORE"
+    test "$(git symbolic-ref HEAD)" = refs/heads/conflicting
+    test -z "$(git status --porcelain)" && test "$(cat f)" = "$(printf 'uno\n2\n3\n4\n5')"
+    test ! -e .git/rebase-merge
+)
+cleanup
+
+setup "git undo [--list] [--worktree] [<steps>]"
+git init -b main >/dev/null
+echo a > f && git add f && git commit -q -m one
+echo b > f && "$TARGET" snapshot -m two >/dev/null
+echo c > g && "$TARGET" snapshot -m three >/dev/null
+diff <("$TARGET" undo --list) <(git reflog)
+"$TARGET" undo --worktree >/dev/null
+test "$(git log --format=%s -1)" = two && test "$(cat f)" = b && test ! -e g
+test -z "$(git status --porcelain)"
+"$TARGET" undo >/dev/null
+test "$(git log --format=%s -1)" = three && test ! -e g
+"$TARGET" undo 2 >/dev/null
+test "$(git log --format=%s -1)" = three
+test "$(git reflog --format=%gs -1)" = "undo: moving to HEAD@{2}"
+echo d > f
+"$TARGET" undo --worktree 2>&1 | grep -q "changes not in HEAD"
+cleanup
+
+setup "git count-objects [-v]"
+git init -b main >/dev/null
+for i in 1 2 3; do echo $i > f$i && git add f$i && git commit -q -m $i; done
+diff_cmd count-objects
+diff_cmd count-objects -v
+git repack -q && echo 4 > f4 && git add f4
+touch .git/objects/pack/junk
+diff_cmd count-objects -v 2>/dev/null
+cleanup
+
+setup "git fsck [--unreachable]"
+git init -b main >/dev/null
+mkdir d && echo a > d/f && echo b > g && git add -A && git commit -q -m one
+echo c > g && git commit -q -a -m two && git tag -a -m tag v1
+echo dangling | git hash-object -w --stdin >/dev/null
+git commit-tree -m orphan "HEAD^{tree}" >/dev/null
+git branch tmp HEAD~1 && git branch -q -D tmp
+diff <("$TARGET" fsck | sort) <(git fsck 2>/dev/null | sort)
+diff <("$TARGET" fsck --unreachable | sort) <(git fsck --unreachable 2>/dev/null | sort)
+git repack -q -a -d
+diff <("$TARGET" fsck | sort) <(git fsck 2>/dev/null | sort)
+git commit -q --allow-empty -m three
+BLOB=$(git rev-parse HEAD:d/f)
+mkdir .git/objects/pack/saved && mv .git/objects/pack/pack-* .git/objects/pack/saved/
+git unpack-objects -q < .git/objects/pack/saved/*.pack
+rm ".git/objects/${BLOB:0:2}/${BLOB:2}"
+"$TARGET" fsck >.git/fsck-out 2>&1 || echo failed >>.git/fsck-out
+grep -q "broken link from    tree $(git rev-parse HEAD:d)" .git/fsck-out
+grep -q "failed" .git/fsck-out
+cleanup
+
+setup "git archive [--format=<fmt>] [--prefix=<prefix>] [-o <file>] <tree-ish>"
+git init -b main >/dev/null
+populate_tree
+rm -r ignored-dir
+LONG=$(printf '%0120d' 0)
+mkdir -p "dir/$LONG" && echo long > "dir/$LONG/$LONG"
+ln -s "$LONG$LONG" long-link
+git add -A && git commit -q -m first && git tag -a -m tag v1
+git archive HEAD >.git/ref.tar && "$TARGET" archive HEAD >.git/mine.tar
+cmp .git/ref.tar .git/mine.tar
+git archive --prefix=top/ v1 >.git/ref.tar && "$TARGET" archive --prefix=top/ v1 >.git/mine.tar
+cmp .git/ref.tar .git/mine.tar
+"$TARGET" archive -o .git/mine.tgz HEAD
+git archive HEAD | cmp - <(gunzip -c .git/mine.tgz)
+diff <(git archive HEAD:dir | tar -t) <("$TARGET" archive HEAD:dir | tar -t)
+# Zip dates are in local time with git, in UTC with us.
+TZ=UTC git archive -o .git/ref.zip HEAD && "$TARGET" archive -o .git/mine.zip HEAD
+diff <(zip_entries .git/mine.zip) <(zip_entries .git/ref.zip)
+TZ=UTC git archive --format=zip --prefix=top/ v1 >.git/ref.zip
+"$TARGET" archive --format=zip --prefix=top/ v1 >.git/mine.zip
+diff <(zip_entries .git/mine.zip) <(zip_entries .git/ref.zip)
+unzip -tq .git/mine.zip >/dev/null
+"$TARGET" archive --remote=https://example.com/repo HEAD 2>&1 | grep -q "not supported"
+cleanup
+
+setup "git archive (export-ignore) / git diff (diff attribute)"
+git init -q
+mkdir -p d/e skip && echo a > keep && echo b > d/x.log && echo c > d/e/y && echo s > skip/z
+printf '*.log export-ignore\nskip export-ignore\n' > .gitattributes
+printf 'y export-ignore\n' > d/e/.gitattributes
+printf 'one\0\n' > nul && echo t > t.dat && echo u > u.bin
+git add -A && git commit -q -m first
+cmp <(git archive HEAD) <("$TARGET" archive HEAD)
+cmp <(git archive --prefix=top/ HEAD) <("$TARGET" archive --prefix=top/ HEAD)
+mkdir -p .git/info && echo 'keep export-ignore' > .git/info/attributes
+cmp <(git archive HEAD) <("$TARGET" archive HEAD)
+printf '*.dat -diff\nnul diff\n*.bin diff=bb\n' >> .gitattributes
+git config diff.bb.binary true
+echo more >> t.dat && printf 'two\0\n' > nul && echo more >> u.bin
+diff_cmd diff
+cleanup
+
+setup "git archive --remote=<url> (upload-archive over ssh and git://)"
+git init -q work && mkdir work/d && echo a > work/a && echo b > work/d/b
+git -C work add -A && git -C work commit -q -m first && git -C work tag -a -m tag v1
+git init -q --bare -b main repo.git && git -C work push -q ../repo.git HEAD:main v1
+cat >"$OTHERDIR/ssh" <<'EOF'
+#!/bin/sh
+while [ "$1" = -o ] || [ "$1" = -p ]; do shift 2; done
+shift
+exec sh -c "$1"
+EOF
+chmod +x "$OTHERDIR/ssh"
+export GIT_SSH_COMMAND="$OTHERDIR/ssh"
+URL="git@example.com:$TESTDIR/repo.git"
+SERVE="$TARGET serve upload-archive"
+git -C repo.git archive v1 >"$OTHERDIR/ref.tar"
+# our client with git's server, git's client with our server, and both ours
+"$TARGET" archive --remote="$URL" v1 | cmp - "$OTHERDIR/ref.tar"
+git archive --remote="$URL" --exec="$SERVE" v1 | cmp - "$OTHERDIR/ref.tar"
+"$TARGET" archive --remote="$URL" --exec="$SERVE" v1 | cmp - "$OTHERDIR/ref.tar"
+git archive --remote="$URL" --exec="$SERVE" HEAD:d | cmp - <(git -C repo.git archive HEAD:d)
+TZ=UTC git -C repo.git archive --prefix=top/ -o "$OTHERDIR/ref.zip" HEAD
+git archive --remote="$URL" --exec="$SERVE" --prefix=top/ -o "$OTHERDIR/theirs.zip" HEAD
+"$TARGET" archive --remote="$URL" --exec="$SERVE" --prefix=top/ -o "$OTHERDIR/mine.zip" HEAD
+diff <(zip_entries "$OTHERDIR/theirs.zip") <(zip_entries "$OTHERDIR/ref.zip")
+diff <(zip_entries "$OTHERDIR/mine.zip") <(zip_entries "$OTHERDIR/ref.zip")
+"$TARGET" archive --remote="$URL" --format=tgz HEAD | gunzip | cmp - <(git -C repo.git archive HEAD)
+# only what refs point to, unless allowed
+COMMIT=$(git -C repo.git rev-parse HEAD)
+if git archive --remote="$URL" --exec="$SERVE" "$COMMIT" >/dev/null 2>&1; then false; fi
+if "$TARGET" archive --remote="$URL" "$COMMIT" >/dev/null 2>&1; then false; fi
+"$TARGET" archive --remote="$URL" --exec="$SERVE" nope 2>&1 | grep -q "no such ref: nope"
+git -C repo.git config uploadArchive.allowUnreachable true
+git archive --remote="$URL" --exec="$SERVE" "$COMMIT" | cmp - <(git -C repo.git archive "$COMMIT")
+unset GIT_SSH_COMMAND
+# git daemon only runs git's server
+PORT=$((20000 + $$ % 10000))
+git daemon --export-all --enable=upload-archive --base-path="$TESTDIR" --listen=127.0.0.1 \
+    --port="$PORT" --reuseaddr --detach --pid-file="$OTHERDIR/pid"
+for _ in $(seq 50); do git ls-remote "git://127.0.0.1:$PORT/repo.git" >/dev/null 2>&1 && break; sleep 0.1; done
+"$TARGET" archive --remote="git://127.0.0.1:$PORT/repo.git" v1 >"$OTHERDIR/mine.tar"
+kill "$(cat "$OTHERDIR/pid")"
+cmp "$OTHERDIR/mine.tar" "$OTHERDIR/ref.tar"
+cleanup
+
+setup "git gc [--prune=<date> | --no-prune]"
+git init -b main >/dev/null
+for i in 1 2 3; do echo $i > f$i && git add f$i && git commit -q -m $i; done
+git tag -a -m tag v1 HEAD~1
+git checkout -q -b side && echo s > s && git add s && git commit -q -m side
+git checkout -q main && git repack -q -a -d && git branch -q -D side
+git reflog expire --expire=now --all
+OLD=$(echo old | git hash-object -w --stdin)
+touch -d 2020-01-01 ".git/objects/${OLD:0:2}/${OLD:2}"
+NEW=$(echo new | git hash-object -w --stdin)
+echo 4 > f4 && git add f4
+"$TARGET" gc
+test "$(git count-objects -v | grep -E '^(count|packs|in-pack):' | tr '\n' ' ')" = \
+    "count: 4 in-pack: 11 packs: 1 "
+git fsck 2>&1 | grep -q "dangling commit" && git fsck --unreachable | grep -q "blob $NEW"
+test "$(git fsck --unreachable | grep -c "$OLD" || true)" = 0
+"$TARGET" gc --no-prune && test "$(git count-objects | cut -d' ' -f1)" = 4
+"$TARGET" gc --prune=now && test "$(git count-objects | cut -d' ' -f1)" = 0
+test "$(git fsck --unreachable)" = "" && git fsck
+test "$(git log --oneline | wc -l)" = 3 && git cat-file -e "$(git rev-parse :f4)"
+# gc.pid.lock: held by a running process, or left by one which is gone.
+echo "$$ $(hostname)" > .git/gc.pid.lock
+if "$TARGET" gc 2>"$OTHERDIR/err"; then false; fi
+grep -q "gc is already running on machine '$(hostname)' pid $$" "$OTHERDIR/err"
+sleep 0 & GONE=$!
+wait $GONE
+echo "$GONE $(hostname)" > .git/gc.pid.lock
+"$TARGET" gc
+test ! -e .git/gc.pid.lock
+cleanup
+
+setup "reachability bitmaps (gc with repack.writeBitmaps, pack-objects --revs)"
+git init -q -b main
+for i in $(seq 150); do
+    printf 'commit refs/heads/main\ncommitter C <c@example.org> %s +0000\ndata 0\n' $i
+    printf 'M 644 inline f%s\ndata <<EOT\n%s\nEOT\n\n' $((i % 7)) $i
+done | git fast-import --quiet
+git checkout -q main && git tag -a -m tag v1 main~20
+"$TARGET" -c repack.writeBitmaps=true gc
+git rev-list --test-bitmap main 2>&1 | grep -q "^OK!"
+# Objects created since are walked as usual, whether the bitmap is ours or git's.
+echo new > new && git add new && git commit -q -m new
+for repack in : "git repack -a -d -b -q"; do
+    $repack
+    for revs in "main" "main ^v1" "v1 ^main~50"; do
+        printf '%s\n' $revs | "$TARGET" pack-objects --revs > pack
+        git index-pack -o idx pack > /dev/null
+        diff <(git show-index < idx | cut -d' ' -f2 | sort) \
+            <(git rev-list --objects $revs | cut -d' ' -f1 | sort)
+    done
+done
+cleanup
+
+setup "git prune [-n] [-v] [--expire=<date>]"
+git init -b main >/dev/null
+echo a > a && git add a && git commit -q -m a
+git checkout -q -b side && echo s > s && git add s && git commit -q -m s
+git checkout -q main && git branch -q -D side
+echo loose | git hash-object -w --stdin >/dev/null
+echo staged > staged && git add staged
+diff_cmd prune -n
+git reflog expire --expire=now --all
+diff_cmd prune -n
+test "$("$TARGET" prune -n --expire=1.hour.ago)" = ""
+test "$("$TARGET" prune -v | wc -l)" = 4
+test "$(git fsck --unreachable)" = "" && git fsck
+git cat-file -e "$(git rev-parse :staged)"
+cleanup
+
+setup "git init --object-format=sha256 (SHA-256 repositories)"
+"$TARGET" init --object-format=sha256 >/dev/null
+test "$(git rev-parse --show-object-format)" = sha256
+echo a > a && mkdir d && echo b > d/b && ln -s a l
+diff_cmd hash-object a
+git add -A && git commit -q -m first
+git checkout -q -b side && echo c > d/b && git commit -q -a -m side
+git checkout -q main && echo a2 > a && git commit -q -a -m main
+git tag -a -m tag v1 HEAD~1
+diff_cmd ls-tree HEAD
+diff_cmd rev-list --objects main side
+"$TARGET" merge side >/dev/null
+test -z "$(git status --porcelain)" && git fsck
+echo e > e && "$TARGET" snapshot -m snap >/dev/null
+git fsck && test "$("$TARGET" rev-parse HEAD^{tree})" = "$(git rev-parse HEAD^{tree})"
+"$TARGET" gc --prune=now && test "$(git count-objects | cut -d' ' -f1)" = 0
+git verify-pack .git/objects/pack/*.idx && git fsck
+git repack -q -a -d && "$TARGET" fsck
+diff_cmd cat-file -p side:d/b
+diff_cmd diff HEAD~2 HEAD
+"$TARGET" fetch origin 2>&1 | grep -q "not supported with object format sha256"
+# local clones run post-checkout with a null hash of the right length
+mkdir -p "$OTHERDIR/tpl/hooks"
+printf '#!/bin/sh\necho "$1" >"%s/old"\n' "$OTHERDIR" >"$OTHERDIR/tpl/hooks/post-checkout"
+chmod +x "$OTHERDIR/tpl/hooks/post-checkout"
+"$TARGET" clone --template="$OTHERDIR/tpl" . "$OTHERDIR/clone" >/dev/null
+test "$(cat "$OTHERDIR/old")" = "$(printf "%064d" 0)"
+cleanup
+
+setup "GIT_DIR, GIT_WORK_TREE and GIT_OBJECT_DIRECTORY"
+mkdir -p "$OTHERDIR/gd" wt/sub && echo a > wt/a && echo b > wt/sub/b
+GIT_DIR="$OTHERDIR/gd" git init -q && git --git-dir="$OTHERDIR/gd" config core.bare false
+export GIT_DIR="$OTHERDIR/gd" GIT_WORK_TREE="$TESTDIR/wt"
+cd wt/sub
+diff_cmd rev-parse --git-dir --show-prefix
+git add -A && git commit -q -m first
+diff_cmd write-tree
+"$TARGET" snapshot -m second >/dev/null
+git fsck && diff_cmd rev-list HEAD
+# outside of the worktree, paths are relative to its root
+cd "$TESTDIR"
+diff_cmd write-tree
+# without GIT_WORK_TREE, the current directory is the root of the worktree
+unset GIT_WORK_TREE
+cd wt
+diff_cmd write-tree
+mkdir "$OTHERDIR/objects" && echo d > d
+GIT_OBJECT_DIRECTORY="$OTHERDIR/objects" "$TARGET" hash-object -w d >/dev/null
+HASH=$(git hash-object d)
+GIT_OBJECT_DIRECTORY="$OTHERDIR/objects" git cat-file -e "$HASH"
+if git cat-file -e "$HASH" 2>/dev/null; then false; fi
+unset GIT_DIR
+cleanup
+
+setup "objects/info/alternates"
+git init -q src && echo a > src/a && git -C src add a && git -C src commit -q -m first
+git -C src gc -q
+git clone -q --shared src dst && cd dst
+echo b > b && git add b && git commit -q -m second
+SECOND=$(git rev-parse HEAD)
+diff_cmd rev-list --objects HEAD
+diff_cmd cat-file -p HEAD~1:a
+diff_cmd rev-parse --short HEAD~1
+"$TARGET" fsck
+# objects only in the source repository are not copied by gc
+"$TARGET" gc --prune=now
+git fsck && test "$(git count-objects -v | sed -n 's/^in-pack: //p')" = 3
+cleanup
+
+setup "git ls-files [-s] [-o [-i] --exclude-standard]"
+git init -q
+mkdir -p a/b build
+echo x > a/b/t && echo y > a-b && echo z > top
+git add . && git commit -q -m first
+printf 'build/\n*.log\n' > .gitignore
+echo 1 > build/out && echo 2 > a/x.log && echo 3 > a/u && echo 4 > new
+echo 5 > build/tracked && git add -f build/tracked
+mkdir other && git -C other init -q
+for args in "" "-s" "-o" "-o --exclude-standard" "-o -i --exclude-standard" "-c -o --exclude-standard"; do
+    diff_cmd ls-files $args
+done
+(cd a && diff_cmd ls-files -o && diff_cmd ls-files -s)
+# conflicted paths have one entry per stage
+git checkout -q -b side && echo side > top && git commit -q -a -m side
+git checkout -q main && echo main > top && git commit -q -a -m main
+git merge -q side >/dev/null 2>&1 || true
+diff_cmd ls-files -s
+cleanup
+
+setup "git add -p [<path>...] (y, n, q, a, d, j, J, k, K, s)"
+git init -q -b main
+seq 1 20 > f
+seq 1 5 > g
+printf 'int main()\n{\n' > h.c
+seq 1 10 >> h.c
+git add f g h.c
+git commit -q -m first
+sed -i 's/^2$/two/; s/^4$/four/; s/^6$/six/; s/^15$/fifteen/' f
+sed -i 's/^3$/three/' g
+sed -i 's/^9$/nine/' h.c
+# Compare the prompts and what was staged with git, leaving out the
+# answers which are not supported (g, / and e) from its prompts and help.
+add_patch() {
+    git reset -q
+    printf "$1" | git add -p ${2-} 2>&1 |
+        sed -E 's/,g,\/(,s)?,e,\?\]/\1,?]/; s/,e,\?\]/,?]/; /^[g\/e] - /d' > "$OTHERDIR/git.out"
+    git diff --cached > "$OTHERDIR/git.diff"
+    git reset -q
+    printf "$1" | "$TARGET" add -p ${2-} > "$OTHERDIR/ours.out" 2>&1
+    git diff --cached > "$OTHERDIR/ours.diff"
+    diff "$OTHERDIR/git.out" "$OTHERDIR/ours.out"
+    diff "$OTHERDIR/git.diff" "$OTHERDIR/ours.diff"
+}
+add_patch 'y\nn\ny\ny\n'
+add_patch 's\ny\nn\ny\nn\nq\n'
+add_patch 's\nj\nj\nJ\nk\nn\ny\ny\nK\ny\nd\n'
+add_patch 'a\nd\na\n'
+add_patch '?\nx\n\nK\nJ\ns\n' g
+add_patch 'y\n' f
+add_patch 'y\n' nothing
+add_patch ''
+# A partly staged file still shows as modified, a fully staged one does not.
+printf 'n\ny\ny\ny\n' | "$TARGET" add -p > /dev/null
+test "$(git diff --name-only)" = f
+test "$(git diff --cached --name-only | tr '\n' ' ')" = "f g h.c "
+git fsck
+cleanup
+
+setup "git rm [--cached] [-r] [-f] / git mv"
+git init -q ref
+mkdir -p ref/d/e ref/f
+for f in a b c d/x d/e/y f/z; do echo "$f" > "ref/$f"; done
+git -C ref add . && git -C ref commit -q -m first
+cp -r ref mine
+# run the same command with git in ref and with us in mine, compare the results
+both() {
+    (cd ref && git "$@") > /tmp/ref
+    (cd mine && "$TARGET" "$@") > /tmp/mine
+    diff -a /tmp/mine /tmp/ref
+    test "$(git -C mine ls-files -s)" = "$(git -C ref ls-files -s)"
+    test "$(git -C mine status --porcelain)" = "$(git -C ref status --porcelain)"
+}
+both rm a
+both rm --cached b
+both rm -r d/e
+both mv c f
+both mv f/c c2
+both mv d dd
+(cd mine/dd && "$TARGET" mv x ../x2) && (cd ref/dd && git mv x ../x2)
+both rm -r f
+# changes that would be lost need -f
+for repo in ref mine; do
+    echo changed > $repo/x2 && git -C $repo add x2 && echo again > $repo/x2
+    # old enough that git trusts the file system data in the index
+    touch -d "1 hour ago" $repo/x2
+    echo new > $repo/n && git -C $repo add n
+done
+if (cd mine && "$TARGET" rm x2 2>/dev/null); then false; fi
+if (cd mine && "$TARGET" rm n 2>/dev/null); then false; fi
+both rm --cached n
+both rm -f x2
+# errors
+cd mine
+mkdir g && echo h > g/h && git add g && git commit -q -m g
+if "$TARGET" rm nope 2>/dev/null; then false; fi
+if "$TARGET" rm g 2>/dev/null; then false; fi
+if "$TARGET" mv nope c3 2>/dev/null; then false; fi
+if "$TARGET" mv n c3 2>/dev/null; then false; fi
+if "$TARGET" mv c2 b 2>/dev/null; then false; fi
+if "$TARGET" mv c2 g/h 2>/dev/null; then false; fi
+if "$TARGET" mv c2 no/such 2>/dev/null; then false; fi
+if "$TARGET" mv g g/sub 2>/dev/null; then false; fi
+test -f c2 && git ls-files --error-unmatch c2 >/dev/null
+cleanup
+
+setup "git clean [-n] [-f] [-d] [-x | -X] [-q] [<path>...]"
+git init -q -b main
+mkdir -p t u/v mix/sub lg empty nested
+echo a > t/a
+printf '*.log\nbuild/\n' > .gitignore
+git add t/a .gitignore
+git commit -q -m first
+for f in t/x t/l.log u/v/y z lg/m.log mix/a mix/l.log mix/sub/b mix/sub/l.log; do
+    echo "$f" > "$f"
+done
+git init -q nested
+for opts in -n -nd -nx -ndx -nX -ndX -nffd "-nd t u/v" "-n mix" "-nX mix/sub" "-n ."; do
+    diff_cmd clean $opts
+done
+(cd t && git clean -n > /tmp/ref && "$TARGET" clean -n > /tmp/mine && diff /tmp/mine /tmp/ref)
+(cd t && git clean -ndx .. > /tmp/ref && "$TARGET" clean -ndx .. > /tmp/mine && diff /tmp/mine /tmp/ref)
+# nothing is removed without -f, unless clean.requireForce is false
+if "$TARGET" clean 2>/dev/null; then false; fi
+test -f z
+"$TARGET" clean -q -f
+test "$(git status --porcelain --ignored | tr '\n' ' ')" = \
+    "?? mix/ ?? nested/ ?? u/ !! lg/ !! mix/l.log !! mix/sub/l.log !! t/l.log "
+git config clean.requireForce false
+"$TARGET" clean -dX > /dev/null
+test "$(git status --porcelain --ignored | tr '\n' ' ')" = "?? mix/ ?? nested/ ?? u/ "
+test -d empty
+"$TARGET" clean -d > /dev/null
+test "$(git status --porcelain --ignored | tr '\n' ' ')" = "?? nested/ "
+test ! -e empty
+"$TARGET" clean -ffd > /dev/null
+test "$(git status --porcelain --ignored)" = ""
+test ! -e nested
+cleanup
+
+setup "git status --porcelain=v2 [-z] [-u<mode>]"
+git init -q
+mkdir -p d/e u/v && echo 1 > a && echo 2 > b && seq 1 50 > r && echo x > d/t && echo y > 'sp ace'
+printf q > 'q"uote' && ln -s a l && git add -A && git commit -q -m base
+echo m >> a && git add a && echo m2 >> a && git rm -q --cached b && git mv r r2 && chmod +x d/t
+rm 'sp ace' l && echo l > l && echo z > d/e/new && echo w > u/v/w && echo n > new && echo q >> 'q"uote'
+printf 'ig*\n' > .gitignore && touch ignored u/ignored && git add .gitignore
+diff_cmd status --porcelain=v2 && diff_cmd status --porcelain=v2 -z && diff_cmd status --porcelain=v2 -uall
+diff_cmd status --porcelain=v2 -uno && diff_cmd -c status.showUntrackedFiles=no status --porcelain=v2
+diff_cmd -c status.renames=false status --porcelain=v2 && diff_cmd -c diff.renames=0 status --porcelain=v2
+(cd d/e && diff_cmd status --porcelain=v2 && diff_cmd status --porcelain=v2 -z)
+# The same fields in JSON, in the order of text output.
+"$TARGET" --format=json status --porcelain=v2 |
+    jq -r '([.kind, .XY, .sub, .mH, .m1, .m2, .m3, .mI, .mW, .h1, .h2, .h3, .hH, .hI, .Xscore, .path] |
+        map(select(. != null)) | join(" ")), (.origPath // empty)' |
+    diff - <(git status --porcelain=v2 -z | tr '\0' '\n')
+if "$TARGET" status --porcelain=v1 2>/dev/null; then false; fi
+git commit -q -a -m second && test "$("$TARGET" status --porcelain=v2 -uno)" = ""
+# Conflicts, with all possible stages.
+echo 1 > uu && echo 1 > ud && echo 1 > du && git add -A && git commit -q -m base && git checkout -q -b side
+echo 2 > uu && echo 2 > du && git rm -q ud && echo s > aa && git add -A && git commit -q -m side
+git checkout -q - && echo 3 > uu && echo 3 > ud && git rm -q du && echo m > aa && git add -A
+git commit -q -m main && if git merge -q side >/dev/null 2>&1; then false; fi
+diff_cmd status --porcelain=v2
+cleanup
+
+setup "git blame <path>"
+git init -q -b main
+mkdir dir
+seq 1 12 > dir/f
+git add . && git commit -q -m one
+sed -i 's/^3$/three/' dir/f
+GIT_AUTHOR_NAME="Someone Else" GIT_AUTHOR_DATE="@1234567890 +0530" git commit -q -a -m two
+git checkout -q -b side HEAD~
+sed -i 's/^10$/ten/' dir/f
+printf 'last' >> dir/f
+git commit -q -a -m side
+git checkout -q main
+git merge -q --no-edit side
+sed -i 's/^1$/un/; 5d' dir/f
+git commit -q -a -m three
+diff_cmd blame dir/f
+(cd dir && diff_cmd blame ./f)
+cleanup
+
+setup "git grep [--untracked] [--threads=<n>] <pattern> [<rev>]"
+git init -q -b main
+mkdir -p dir/sub
+printf 'hello world\nbye\nhello again' > a
+printf 'say hello\n' > dir/b
+printf 'no match\nhello\n' > dir/sub/c
+printf 'bin\0ary hello\n' > bin
+printf 'hello\n' > ignored.txt
+echo "*.txt" > .gitignore
+git add a dir bin .gitignore && git commit -q -m one
+echo "hello there" >> dir/b
+printf 'hello untracked\n' > dir/new
+grep_cmd() {
+    git grep -F -n "$@" > /tmp/ref
+    "$TARGET" grep "$@" > /tmp/mine
+    diff -a /tmp/mine /tmp/ref
+    "$TARGET" grep --threads=0 "$@" > /tmp/mine
+    diff -a /tmp/mine /tmp/ref
+}
+grep_cmd hello
+grep_cmd hello HEAD
+grep_cmd hello HEAD:dir
+grep_cmd --untracked hello
+(cd dir && grep_cmd hello)
+(cd dir && grep_cmd hello HEAD)
+if "$TARGET" grep nothing-like-this; then false; fi
+cleanup
+
+setup "git describe [--tags] [--dirty[=<mark>]]"
+git init -q -b main
+commit() { echo "$1" > "$1" && git add "$1" && git commit -q -m "$1"; }
+commit a
+git tag light
+if "$TARGET" describe 2>/dev/null; then false; fi
+diff_cmd describe --tags
+commit b
+GIT_COMMITTER_DATE="@1000000000 +0000" git tag -a -m old v1.0
+GIT_COMMITTER_DATE="@1100000000 +0000" git tag -a -m new v1.1
+diff_cmd describe
+commit c && commit d
+git tag v2-light
+diff_cmd describe
+diff_cmd describe --tags
+# the tag with the fewest commits on top wins, even if older
+git checkout -q -b side v1.0 && commit e
+git tag -a -m side v1.2
+git checkout -q main && git merge -q --no-edit side
+diff_cmd describe
+echo change >> a
+diff_cmd describe --dirty
+diff_cmd describe --dirty=-modified
+git checkout -q a
+diff_cmd describe --dirty
+cleanup
+
+setup "git show-ref [--heads] [--tags] [-d] [-s] [<pattern>...] / git pack-refs [--all]"
+git init -q -b main ref
+echo a > ref/a && git -C ref add a && git -C ref commit -q -m first
+git -C ref branch feature/x && git -C ref branch other
+git -C ref tag light && git -C ref tag -a -m annotated v1
+git -C ref symbolic-ref refs/remotes/origin/HEAD refs/heads/main
+cp -r ref mine
+loose() { (cd "$1/.git" && find refs | sort); }
+both() {
+    git -C ref "$@"
+    (cd mine && "$TARGET" "$@")
+    diff ref/.git/packed-refs mine/.git/packed-refs
+    diff <(loose ref) <(loose mine)
+}
+both pack-refs
+both pack-refs --all
+cd mine
+for args in "" "--heads" "--tags" "-d" "--tags -d -s" "main" "x v1" "--heads v1"; do
+    # shellcheck disable=SC2086 # word splitting wanted
+    diff_cmd show-ref $args || exit 1
+done
+if "$TARGET" show-ref no-such-ref; then false; fi
+# packed references can be read, updated (a loose file) and deleted
+diff_cmd rev-parse main feature/x v1 "v1^{commit}"
+diff_cmd symbolic-ref refs/remotes/origin/HEAD
+git commit -q --allow-empty -m second
+HASH=$(git rev-parse HEAD)
+test "$("$TARGET" rev-parse main)" = "$HASH"
+"$TARGET" update-ref -d refs/heads/other
+if grep -q refs/heads/other .git/packed-refs; then false; fi
+if git rev-parse -q --verify other; then false; fi
+"$TARGET" update-ref refs/heads/feature/x "$HASH"
+diff_cmd show-ref
+git fsck
+cd ..
+# clones have packed references
+git clone -q ref cloned
+(cd cloned && diff_cmd show-ref && diff_cmd rev-parse origin/main v1)
+cleanup
+
+setup "git check-ignore [-v] <path>..."
+git init -q
+mkdir -p sub/deep build
+echo "*.o" > .git/info/exclude
+printf '# comment\nbuild/\n!keep.o\n/top\n' > .gitignore
+echo "x*" > sub/.gitignore
+touch a.o keep.o tracked.o sub/xa build/q top sub/top
+git add -f tracked.o
+diff_cmd check-ignore a.o keep.o tracked.o sub/xa build/q build top sub/top sub/deep/xz
+diff_cmd check-ignore -v a.o keep.o tracked.o sub/xa build/q build top sub/top sub/deep/xz
+(cd sub && diff_cmd check-ignore -v xa ../a.o ../build/q)
+# nothing reported: exit status 1
+if "$TARGET" check-ignore keep.o sub/top tracked.o; then false; fi
+if "$TARGET" check-ignore -v nothing; then false; fi
+cleanup
+
+setup "git merge-base [--all | --is-ancestor] <commit> <commit>"
+git init -b main >/dev/null
+commit() { GIT_COMMITTER_DATE="@$1 +0000" git commit -q --allow-empty -m "$2" "${@:3}"; }
+commit 1000 base
+git checkout -q -b side
+commit 2000 side
+git checkout -q main
+commit 3000 main
+git tag -m tag t
+# criss-cross: two merge bases
+git checkout -q side
+GIT_COMMITTER_DATE="@4000 +0000" git merge -q --no-ff -m m1 main
+git checkout -q main
+GIT_COMMITTER_DATE="@5000 +0000" git merge -q --no-ff -m m2 side~1
+diff_cmd merge-base main side
+diff_cmd merge-base --all main side
+test "$(wc -l < /tmp/mine)" = 2
+diff_cmd merge-base t side
+"$TARGET" merge-base --is-ancestor t main
+test "$("$TARGET" merge-base --is-ancestor main t || echo no)" = no
+cleanup
+
+setup "git rev-list [--objects] <commit>... [--not <commit>...]"
+git init -b main >/dev/null
+populate_tree && rm -r ignored-dir
+git add . && git commit -q -m first
+git checkout -q -b side
+echo side > side && mkdir -p sub/dir && echo deep > sub/dir/file
+git add . && GIT_COMMITTER_DATE="@2000000000 +0000" git commit -q -m side
+git checkout -q main
+echo more >> afile && git commit -q -am second
+git tag -m tag t
+diff_cmd rev-list main side
+diff_cmd rev-list --objects main side
+diff_cmd rev-list --objects side --not main
+diff_cmd rev-list --objects t "^$(git rev-parse side~1)"
+cleanup
+
+setup "git rev-list --since/--until/--author/--grep, <ref>@{<n>|<date>}"
+git init -b main >/dev/null
+NOW=$(date +%s)
+for i in 9 6 3 1; do
+    echo $i > f && git add f
+    GIT_COMMITTER_DATE="@$((NOW - i * 86400)) +0000" GIT_AUTHOR_NAME="author$((i % 2))" \
+        git commit -q -m "commit $i$([ $i -gt 5 ] && echo ' old')"
+done
+diff_cmd rev-list --since=5.days.ago main
+diff_cmd rev-list --until=2.days.ago main
+diff_cmd rev-list --after="@$((NOW - 7 * 86400))" --before=yesterday main
+diff_cmd rev-list --since="$(date -u -d @$((NOW - 4 * 86400)) +%Y-%m-%d)" main
+diff_cmd rev-list --author=author1 main
+diff_cmd rev-list --grep=old main
+diff_cmd rev-parse main@{1} @{0} HEAD@{3} main@{2}~1
+diff_cmd rev-parse main@{4.days.ago} @{yesterday}
+cleanup
+
+setup "git refs verify [--fix]"
+git init -b main >/dev/null
+echo a > f && git add f && git commit -q -m first
+echo b > f && git commit -q -am second
+git tag -a -m tag v1 && git branch packed && git pack-refs --all
+"$TARGET" refs verify
+TREE=$(git rev-parse HEAD^{tree}) && echo "$TREE" > .git/refs/heads/main
+printf '%040d\n' 1 > .git/refs/heads/missing
+echo garbage > .git/refs/heads/bad
+echo 'ref: refs/heads/nope' > .git/refs/tags/dangling
+sed -i 's/^[0-9a-f]* refs\/heads\/packed$/1111111111111111111111111111111111111111 refs\/heads\/packed/' .git/packed-refs
+"$TARGET" refs verify 2>&1 | grep -q "5 broken reference(s) found"
+"$TARGET" refs verify 2>&1 | grep -q "refs/heads/main: points to a tree"
+yes n | "$TARGET" refs verify --fix >/dev/null 2>&1 || true
+test "$(cat .git/refs/heads/bad)" = garbage
+yes | "$TARGET" refs verify --fix >/dev/null 2>&1
+"$TARGET" refs verify
+test "$(git rev-parse main)" = "$(git rev-parse v1^{commit})"
+test ! -e .git/refs/heads/bad
+test ! -e .git/refs/heads/missing
+test ! -e .git/refs/tags/dangling
+test "$(git rev-parse packed)" = "$(git rev-parse main)"
+test -z "$(grep refs/heads/packed .git/packed-refs)"
+cleanup
+
+setup "git merge <branch> (change summary)"
+git init -b main >/dev/null
+seq 1 100 > big && seq 1 20 > lines && echo x > gone && echo m > mode
+git add . && git commit -m base >/dev/null
+git checkout -b feat 2>/dev/null
+seq 1 70 | sed 's/$/x/' > big && sed -i 's/^3$/three/' lines && git rm -q gone
+chmod +x mode && echo new > new && touch empty
+git add . && git commit -m feat >/dev/null
+git checkout main 2>/dev/null
+cp -a . "$OTHERDIR"
+"$TARGET" merge feat > .git/merge-out
+(cd "$OTHERDIR" && git merge feat) > .git/merge-ref
+diff .git/merge-out .git/merge-ref
+cleanup
+
 setup "git checkout-empty <commit>"
 "$TARGET" init >/dev/null
 populate_tree
@@ -257,12 +1801,276 @@ COMMIT=$("$TARGET" commit-tree "$TREE" -m initial)
 (
     cd "$OTHERDIR"
     cp -a "$TESTDIR/.git" .
-    "$TARGET" checkout-empty "$COMMIT"
+    "$TARGET" checkout-empty "$COMMIT" >/dev/null
 )
 # This is racy: gets false negative when run close to a change of minute
 diff <(ls -lR) <(cd "$OTHERDIR" && ls -lR)
 cleanup
 
+setup "git checkout-empty <commit> (locked)"
+"$TARGET" init >/dev/null
+TREE=$("$TARGET" write-tree)
+COMMIT=$("$TARGET" commit-tree "$TREE" -m initial)
+touch .git/index.lock
+"$TARGET" checkout-empty "$COMMIT" 2>&1 | grep -q "another process is running"
+rm .git/index.lock
+"$TARGET" checkout-empty "$COMMIT" >/dev/null
+test ! -e .git/index.lock
+test "$(git rev-parse HEAD)" = "$COMMIT"
+cleanup
+
+setup "git clone / git checkout-empty <commit> (index)"
+git init -q work && mkdir work/d && echo a > work/a && echo b > work/d/b && ln -s a work/l
+git -C work add -A && git -C work commit -q -m first
+"$TARGET" clone work mine >/dev/null && git clone -q work theirs
+"$TARGET" clone --sparse work mine-sparse >/dev/null && git clone -q --sparse work theirs-sparse
+mkdir empty && cp -a work/.git empty/ && rm empty/.git/index
+(cd empty && "$TARGET" checkout-empty HEAD >/dev/null)
+for dir in mine theirs mine-sparse theirs-sparse empty; do
+    (cd "$dir" && "$TARGET" status --porcelain=v2 && "$TARGET" ls-files -s \
+        && git status --porcelain=v2 && git ls-files -s -t) >"$OTHERDIR/$dir"
+done
+diff "$OTHERDIR/mine" "$OTHERDIR/theirs"
+diff "$OTHERDIR/mine-sparse" "$OTHERDIR/theirs-sparse"
+diff "$OTHERDIR/empty" "$OTHERDIR/theirs"
+(cd mine && "$TARGET" rm a >/dev/null) && test "$(git -C mine status --porcelain)" = "D  a"
+cleanup
+
+setup "git checkout-empty <commit> (unsafe paths)"
+git init -q
+BLOB=$(echo content | git hash-object -w --stdin)
+LINK=$(printf '%s' "$OTHERDIR/target" | git hash-object -w --stdin)
+for entries in "100644 blob $BLOB\t.." "100644 blob $BLOB\t.GIT" \
+    "040000 tree $(printf "100644 blob $BLOB\tconfig\n" | git mktree)\t.git" \
+    "120000 blob $LINK\tx\n100644 blob $BLOB\tx"; do
+    COMMIT=$(git commit-tree -m unsafe "$(printf "$entries\n" | git mktree)")
+    if "$TARGET" checkout-empty "$COMMIT" 2>/dev/null; then false; fi
+    test ! -e "$OTHERDIR/target"
+    git rm -rfq --cached --ignore-unmatch . && git clean -fdxq
+done
+if "$TARGET" extract "$COMMIT" . "$OTHERDIR/out" 2>/dev/null; then false; fi
+test ! -e "$OTHERDIR/target"
+cleanup
+
+setup "core.symlinks=false / core.filemode=false (checkout-empty, diff, snapshot)"
+git init -q
+echo content > file && echo '#!/bin/sh' > exe && chmod +x exe && ln -s file link
+git add -A && git commit -q -m initial
+COMMIT=$(git rev-parse HEAD)
+git config core.symlinks false && git config core.filemode false
+rm -f file exe link
+"$TARGET" checkout-empty "$COMMIT" >/dev/null
+test -f link && ! test -L link && test "$(cat link)" = file
+test -z "$(git status --porcelain)"
+chmod -x exe && chmod +x file
+test -z "$("$TARGET" diff)"
+"$TARGET" snapshot -m "no change" >/dev/null
+test "$(git rev-parse HEAD^{tree})" = "$(git rev-parse "$COMMIT^{tree}")"
+cleanup
+
+setup "git extract <commit> <pathspec>... <dest>"
+"$TARGET" init >/dev/null
+populate_tree
+rm -r ignored-dir
+TREE=$("$TARGET" write-tree)
+COMMIT=$("$TARGET" commit-tree "$TREE" -m initial)
+"$TARGET" extract "$COMMIT" . "$OTHERDIR/all" >/dev/null
+diff -r --no-dereference --exclude=.git . "$OTHERDIR/all"
+test -x "$OTHERDIR/all/script"
+"$TARGET" extract "$TREE" dir/f "$OTHERDIR/some" >/dev/null
+test "$(cd "$OTHERDIR/some" && find . -type f)" = "./dir/f"
+"$TARGET" extract "$TREE" afile 'link-*' ./dir/ "$OTHERDIR/many" >/dev/null
+test "$(cd "$OTHERDIR/many" && find . ! -type d | sort | tr '\n' ' ')" = \
+    "./afile ./dir/f ./link-abs ./link-rel "
+"$TARGET" extract "$TREE" '*file' "$OTHERDIR/glob" >/dev/null
+test "$(cd "$OTHERDIR/glob" && find . -type f | sort | tr '\n' ' ')" = "./afile ./empty-file "
+for spec in ../x dir/../afile .git/config /afile nothing; do
+    if "$TARGET" extract "$TREE" afile "$spec" "$OTHERDIR/bad" 2>/dev/null; then false; fi
+done
+test ! -e "$OTHERDIR/bad/afile"
+cleanup
+
+setup "git diff [--cached] [<commit> [<commit>]]"
+git init >/dev/null
+seq 1 30 > lines
+printf 'no newline' > nonl
+printf 'bin\0ary' > bin
+mkdir dir && echo x > dir/gone && echo y > dir/mode
+git add . && git commit -m v1 >/dev/null
+V1=$(git rev-parse HEAD)
+sed -i 's/^5$/five/; s/^25$/twenty-five/' lines
+printf 'no newline either' > nonl
+printf 'bin\0ary2' > bin
+rm dir/gone && echo new > dir/new && chmod +x dir/mode
+git add . && git commit -m v2 >/dev/null
+echo staged >> lines && git add lines && echo unstaged >> lines
+rm nonl
+diff_cmd -c diff.renames=false diff "$V1" "$(git rev-parse HEAD)"
+diff_cmd diff
+diff_cmd diff --cached
+diff_cmd -c diff.renames=false diff "$V1"
+cleanup
+
+setup "git diff [-M[=<n>] | --no-renames], git show (renames)"
+git init -q
+seq 1 30 > lines
+seq 100 140 > other
+seq 1 5 > exact
+: > empty
+mkdir dir && seq 50 80 > dir/moved
+git add . && git commit -q -m v1
+git mv exact dir/exact && chmod +x dir/exact
+git mv empty empty2
+git mv dir/moved moved && echo more >> moved
+git mv lines renamed && sed -i 's/^5$/five/; s/^6$/six/; s/^25$/twenty-five/' renamed
+git rm -q other && seq 100 125 > other2
+git add -A
+diff_cmd diff --cached
+diff_cmd diff --cached --find-renames=90%
+diff_cmd diff --cached --find-renames=95
+diff_cmd diff --cached --no-renames
+diff_cmd -c diff.renames=false diff --cached
+diff_cmd -c diff.renames=false diff --cached -M
+# git takes -M97%, we need an equals sign, like for -S
+test "$("$TARGET" diff --cached -M=97% | grep -c '^rename from')" = \
+    "$(git diff --cached -M97% --name-status | grep -c '^R')"
+git commit -q -m v2
+diff_cmd show
+diff_cmd diff HEAD~ HEAD
+cleanup
+
+setup "git apply [--cached] [--check] [-p<n>] [-C<n>] <patch>"
+git init -q
+seq 1 30 > lines && printf 'no eol' > noeol && echo x > gone && echo s > "with space"
+echo m > mode && seq 1 10 > moved && ln -s lines link
+git add -A && git commit -q -m base
+sed -i 's/^5$/five/; s/^25$/twenty-five/' lines && printf 'no eol\nmore' > noeol
+git rm -q gone && echo new > "with space" && chmod +x mode && git mv moved renamed
+mkdir d && echo deep > d/file && touch empty && ln -sf noeol link
+git add -A && git diff --cached -M HEAD > "$OTHERDIR/patch" && git reset -q --hard
+cp -a . "$OTHERDIR/expected" && cp -a . "$OTHERDIR/actual"
+(cd "$OTHERDIR/expected" && git apply ../patch && git apply --cached ../patch)
+(cd "$OTHERDIR/actual" && "$TARGET" apply --check ../patch)
+git -C "$OTHERDIR/actual" diff --quiet
+(cd "$OTHERDIR/actual" && "$TARGET" apply ../patch)
+(cd "$OTHERDIR/actual" && "$TARGET" apply --cached < ../patch)
+diff -r -x .git "$OTHERDIR/expected" "$OTHERDIR/actual"
+diff <(git -C "$OTHERDIR/expected" ls-files -s) <(git -C "$OTHERDIR/actual" ls-files -s)
+diff <(git -C "$OTHERDIR/expected" status --porcelain) <(git -C "$OTHERDIR/actual" status --porcelain)
+# Hunks moved by other changes, and fuzz when some context changed.
+sed -i 's/^20$/twenty/' lines && git diff > "$OTHERDIR/patch" && git checkout -q lines
+sed -i '1i top\ntop' lines && sed -i 's/^18$/eighteen/' lines && cp lines lines.orig
+if "$TARGET" apply "$OTHERDIR/patch" 2>/dev/null; then false; fi
+cmp lines lines.orig
+"$TARGET" apply -C1 "$OTHERDIR/patch" 2>/dev/null
+mv lines lines.actual && cp lines.orig lines
+git apply -C1 "$OTHERDIR/patch" 2>/dev/null
+cmp lines lines.actual
+# Traditional diffs, with timestamps.
+cp lines.orig lines.new && sed -i 's/^30$/thirty/' lines.new
+diff -u lines lines.new > "$OTHERDIR/patch" || true
+"$TARGET" apply -p0 "$OTHERDIR/patch"
+cmp lines lines.new
+cleanup
+
+setup "git diff --binary, git apply (binary patches)"
+git init -q
+printf 'bin\0ary' > bin && head -c 3000 /dev/urandom > big && printf 'old\0' > gone
+echo text > text && git add -A && git commit -q -m base
+printf 'BIN\0ARY\0' > bin && printf 'new\0' > new && git rm -q gone && touch empty
+git add -A && diff_cmd diff --cached && diff_cmd diff --cached --binary
+# Deltas, for a small change to a larger file, are not the same as git's, so
+# check that both patches apply.
+head -c 2000 big > big.new && echo change >> big.new && mv big.new big && echo more >> text
+git add -A && git diff --cached --binary HEAD > "$OTHERDIR/git.patch" && tree=$(git write-tree)
+"$TARGET" diff --cached --binary > "$OTHERDIR/our.patch" && grep -q '^delta ' "$OTHERDIR/our.patch"
+git clone -q . "$OTHERDIR/ref" && git clone -q . "$OTHERDIR/mine" && git clone -q . "$OTHERDIR/index"
+(cd "$OTHERDIR/ref" && git apply --index ../our.patch && git diff --quiet "$tree")
+(cd "$OTHERDIR/mine" && "$TARGET" apply ../git.patch && git add -A && git diff --cached --quiet "$tree")
+(cd "$OTHERDIR/index" && "$TARGET" apply --cached ../our.patch && git diff --cached --quiet "$tree")
+# The patch must be for the current contents, and must have the data or full
+# names of the objects.
+git -C "$OTHERDIR/ref" reset -q --hard && echo x >> "$OTHERDIR/ref/bin"
+if (cd "$OTHERDIR/ref" && "$TARGET" apply ../our.patch 2>/dev/null); then false; fi
+git -C "$OTHERDIR/ref" diff --quiet HEAD -- big
+git diff --cached > "$OTHERDIR/plain.patch" && git -C "$OTHERDIR/ref" reset -q --hard
+if (cd "$OTHERDIR/ref" && "$TARGET" apply ../plain.patch 2>/dev/null); then false; fi
+cleanup
+
+setup "git diff --color[=<when>], --word-diff[=<mode>], --color-words, git show --color"
+git init -q
+printf 'a b c\n\tfoo\nline three\nfour\n' > f && printf 'x\n' > g && git add -A && git commit -q -m base
+printf 'a B c d\n  \tfoo \nline three\nfive\n\n\n' > f && printf 'y' > g && echo new > h && git add h
+diff_cmd diff --color && diff_cmd diff --color=always HEAD && diff_cmd diff --color=auto
+diff_cmd diff --word-diff && diff_cmd diff --word-diff=porcelain && diff_cmd diff --color-words
+diff_cmd diff --color --word-diff=plain HEAD
+git commit -q -a -m change && diff_cmd show --color && diff_cmd show
+git config color.diff.old "blue reverse" && git config color.diff.meta "#ff8000 ul"
+git config color.diff always && diff_cmd diff HEAD~ && diff_cmd diff --no-color HEAD~
+git config color.diff false && git config color.ui always && diff_cmd diff HEAD~
+git config color.diff.new nocolor && if "$TARGET" diff --color HEAD~ 2>/dev/null; then false; fi
+cleanup
+
+setup "git diff / show / blame with a pager, git --no-pager, pager.<cmd>"
+git init -q
+printf 'one\ntwo\n' > f && git add f && git commit -q -m base && git config log.decorate false
+# script(1) gives a terminal, for which colors are on (also when paging).
+tty_cmd() {
+    for bin in git "$TARGET"; do
+        rm -f "$OTHERDIR/paged"
+        TERM=xterm script -qec "$bin $*" /dev/null > "$OTHERDIR/tty-$(basename "$bin")"
+        touch "$OTHERDIR/paged" && mv "$OTHERDIR/paged" "$OTHERDIR/paged-$(basename "$bin")"
+    done
+    cmp "$OTHERDIR/tty-git" "$OTHERDIR/tty-codecrafters-git"
+    cmp "$OTHERDIR/paged-git" "$OTHERDIR/paged-codecrafters-git"
+}
+export GIT_PAGER="sed 's/^/paged: /' > $OTHERDIR/paged"
+tty_cmd show && tty_cmd blame f && echo three >> f
+tty_cmd diff && grep -q '^paged: .*@@' "$OTHERDIR/paged-git"
+tty_cmd --no-pager diff && test ! -s "$OTHERDIR/paged-git" && tty_cmd -c pager.diff=false diff
+tty_cmd -c pager.show=false show && tty_cmd -c "'pager.diff=tr a-z A-Z'" diff
+unset GIT_PAGER
+tty_cmd -c "'pager.diff=tr a-z A-Z > $OTHERDIR/paged'" -c core.pager=false diff
+tty_cmd -c "'core.pager=cat > $OTHERDIR/paged'" diff && tty_cmd -c core.pager= diff
+# Not to a terminal, without a pager.
+git -c core.pager=false diff > "$OTHERDIR/ref" && "$TARGET" -c core.pager=false diff > "$OTHERDIR/mine"
+cmp "$OTHERDIR/ref" "$OTHERDIR/mine"
+cleanup
+
+setup "git format-patch [-o <dir>] [--stdout] <range>, git am <mbox>..."
+git init -q
+seq 1 20 > lines && echo a > a && git add -A && git commit -q -m base
+sed -i 's/^3$/three/' lines && echo "with space" > "with space" && git add -A
+git commit -q -m "A long subject line that git folds when it writes the header of the email" -m "Body."
+export GIT_AUTHOR_NAME="Jörg Ümlaut" GIT_AUTHOR_EMAIL="jorg@example.org"
+sed -i 's/^13$/thirteen/' lines && git commit -q -a -m "Ünïcode subject with = and ? and _"
+export GIT_AUTHOR_NAME="A. U. Thor" GIT_AUTHOR_EMAIL="thor@example.org"
+git rm -q a && git commit -q -m "Dots. and (special) chars: yes!"
+unset GIT_AUTHOR_NAME GIT_AUTHOR_EMAIL
+git -c diff.renames=false format-patch -q --no-signature -o "$OTHERDIR/expected" HEAD~3
+"$TARGET" format-patch --no-signature -o "$OTHERDIR/actual" HEAD~3 > /dev/null
+diff -r "$OTHERDIR/expected" "$OTHERDIR/actual"
+"$TARGET" format-patch --stdout --no-signature HEAD~3..HEAD~1 > "$OTHERDIR/mbox"
+git -c diff.renames=false format-patch --stdout --no-signature HEAD~3..HEAD~1 | cmp - "$OTHERDIR/mbox"
+# Same commits as git am, from files and from standard input.
+export GIT_COMMITTER_NAME="A. Maintainer" GIT_COMMITTER_EMAIL="maint@example.org"
+export GIT_COMMITTER_DATE="@86400 +0000"
+git clone -q . "$OTHERDIR/ref" && git clone -q . "$OTHERDIR/mine"
+git -C "$OTHERDIR/ref" reset -q --hard HEAD~3 && git -C "$OTHERDIR/mine" reset -q --hard HEAD~3
+(cd "$OTHERDIR/ref" && git am -q ../expected/*.patch)
+(cd "$OTHERDIR/mine" && "$TARGET" am ../actual/*.patch > /dev/null)
+test "$(git -C "$OTHERDIR/mine" rev-parse HEAD)" = "$(git -C "$OTHERDIR/ref" rev-parse HEAD)"
+git -C "$OTHERDIR/mine" reset -q --hard HEAD~3
+(cd "$OTHERDIR/mine" && "$TARGET" am < ../mbox > /dev/null)
+test "$(git -C "$OTHERDIR/mine" rev-parse HEAD)" = "$(git -C "$OTHERDIR/ref" rev-parse HEAD~1)"
+git -C "$OTHERDIR/mine" diff --quiet HEAD
+# A patch that doesn't apply stops, leaving the worktree alone.
+echo conflict > "$OTHERDIR/mine/a" && git -C "$OTHERDIR/mine" commit -q -a -m conflict
+if (cd "$OTHERDIR/mine" && "$TARGET" am ../actual/0003-* > /dev/null 2>&1); then false; fi
+git -C "$OTHERDIR/mine" diff --quiet HEAD
+unset GIT_COMMITTER_NAME GIT_COMMITTER_EMAIL GIT_COMMITTER_DATE
+cleanup
+
 setup "git unpack-objects (undeltified, 2 blobs)"
 git init >/dev/null
 FILE1="$ROOT"/your_program.sh
@@ -308,10 +2116,117 @@ diff <(git cat-file -p $A) a
 diff <(git cat-file -p $B) b
 cleanup
 
-setup "git ls-remote <url> HEAD"
+setup "git unpack-objects (deltified: chain)"
+git init >/dev/null
+cp "$ROOT/your_program.sh" a
+git add a && git commit -m v1 >/dev/null
+sed -i 's/Copied/COPIED/' a
+git add a && git commit -m v2 >/dev/null
+echo "# bla" >> a
+git add a && git commit -m v3 >/dev/null
+git rev-list --objects HEAD | git pack-objects -q --stdout >mypack
+git rev-list --objects HEAD | cut -d" " -f1 >objects
+BLOB=$(git rev-parse HEAD:a)
+rm -rf .git
+"$TARGET" init >/dev/null
+"$TARGET" unpack-objects < mypack >/dev/null
+test "$(git cat-file --batch-check <objects | grep -c missing)" = 0
+diff <(git cat-file -p $BLOB) a
+cleanup
+
+setup "git pack-objects"
+git init >/dev/null
+cp "$ROOT/your_program.sh" a
+git add a
+git commit -m "first" >/dev/null
+git rev-list --objects HEAD | "$TARGET" pack-objects >mine.pack
+git index-pack mine.pack >/dev/null
+test "$(git verify-pack -v mine.idx | grep -c '^[0-9a-f]\{40\} ')" = 3
+cleanup
+
+setup "git index-pack <pack> / --stdin"
+git init >/dev/null
+cp "$ROOT/your_program.sh" a
+cp "$ROOT/your_program.sh" b
+sed -i 's/Copied/COPIED/' b
+git add a b
+git commit -m "first" >/dev/null
+git rev-list --objects HEAD | git pack-objects -q --stdout >ref.pack
+git rev-list --objects HEAD | git pack-objects -q --stdout --delta-base-offset >ofs.pack
+git index-pack -o ref-ref.idx ref.pack >/dev/null
+"$TARGET" index-pack ref.pack >/dev/null
+cmp ref.idx ref-ref.idx
+git index-pack -o ofs-ref.idx ofs.pack >/dev/null
+"$TARGET" index-pack ofs.pack >/dev/null
+cmp ofs.idx ofs-ref.idx
+rm -rf .git
+"$TARGET" init >/dev/null
+"$TARGET" index-pack --stdin <ofs.pack >/dev/null
+git verify-pack .git/objects/pack/pack-*.idx
+# sizes in entry headers are not trusted before decompressing
+printf 'PACK\0\0\0\2\0\0\0\1\277\377\377\377\377\377\377\377\177\170\234\3\0\0\0\0\1' >body
+{ cat body; printf "$(sha1sum body | cut -c1-40 | sed 's/../\\x&/g')"; } >big.pack
+"$TARGET" index-pack big.pack 2>&1 | grep -q "size mismatch"
+# nor sizes and base offsets longer than fit in 64 bits
+printf 'PACK\0\0\0\2\0\0\0\1\277\377\377\377\377\377\377\377\377\377\377\177' >body
+{ cat body; printf "$(sha1sum body | cut -c1-40 | sed 's/../\\x&/g')"; } >big.pack
+"$TARGET" index-pack big.pack 2>&1 | grep -q "size too large"
+"$TARGET" unpack-objects <big.pack 2>&1 | grep -q "size too large"
+printf 'PACK\0\0\0\2\0\0\0\1\140\377\377\377\377\377\377\377\377\377\177' >body
+{ cat body; printf "$(sha1sum body | cut -c1-40 | sed 's/../\\x&/g')"; } >big.pack
+"$TARGET" index-pack big.pack 2>&1 | grep -q "base offset too large"
+cleanup
+
+setup "git verify-pack [-v | -s] <idx>..."
+git init -q
+seq 1 1000 >f
+# each version changes one more line: deltas against deltas
+for i in 1 2 3 4 5; do sed -i "$((i * 150))s/.*/changed $i/" f && git add f && git commit -q -m $i; done
+git rev-list --objects HEAD | git pack-objects -q --stdout >ref.pack
+git rev-list --objects HEAD | git pack-objects -q --stdout --delta-base-offset >ofs.pack
+git index-pack ref.pack >/dev/null
+git index-pack ofs.pack >/dev/null
+grep -q "chain length = 2" <(git verify-pack -s ref.idx ofs.idx)
+diff_cmd verify-pack ref.idx ofs.idx
+diff_cmd verify-pack -v ref.idx ofs.pack
+diff_cmd verify-pack -s ref ofs.idx
+cp ofs.pack good.pack
+printf X | dd of=ofs.pack bs=1 seek=100 conv=notrunc 2>/dev/null
+if "$TARGET" verify-pack ofs.idx 2>/dev/null; then false; fi
+cp good.pack ofs.pack
+head -c 100 ref.idx >ofs.idx
+if "$TARGET" verify-pack ofs.idx 2>/dev/null; then false; fi
+cleanup
+
+setup "reading packs (offset deltas must have their base before them)"
+git init -q
+OID=$(echo hi | git hash-object --stdin)
+# A pack with a single offset delta whose base is itself (offset 0 back).
+python3 - "$OID" <<'EOF'
+import hashlib, struct, sys, zlib
+oid = bytes.fromhex(sys.argv[1])
+delta = b'\x01\x01\x90\x01'
+entry = bytes([0x60 | len(delta), 0]) + zlib.compress(delta)
+pack = b'PACK' + struct.pack('>II', 2, 1) + entry
+pack += hashlib.sha1(pack).digest()
+fanout = b''.join(struct.pack('>I', int(i >= oid[0])) for i in range(256))
+idx = b'\xfftOc' + struct.pack('>I', 2) + fanout + oid
+idx += struct.pack('>II', zlib.crc32(entry), 12) + pack[-20:]
+idx += hashlib.sha1(idx).digest()
+name = '.git/objects/pack/pack-' + pack[-20:].hex()
+open(name + '.pack', 'wb').write(pack)
+open(name + '.idx', 'wb').write(idx)
+EOF
+if "$TARGET" cat-file -p "$OID" 2>err; then false; fi
+grep -q "not before it" err
+cleanup
+
+setup "git ls-remote <url> [<patterns>...]"
 REPO="https://github.com/mpg/ct"
 diff_cmd ls-remote "$REPO" HEAD
 diff_cmd ls-remote "$REPO/" HEAD
+diff_cmd ls-remote "$REPO"
+diff_cmd ls-remote "$REPO" main 'refs/tags/*'
 cleanup
 
 setup "git clone <url> <dir>"
@@ -319,6 +2234,617 @@ REPO="https://github.com/mpg/ct"
 "$TARGET" clone "$REPO" foo >/dev/null
 test -f foo/results
 test -x foo/rungcc
+# all branches and tags, and a local branch for HEAD
+diff <(git -C foo for-each-ref --format='%(objectname)%09%(refname)' refs/remotes refs/tags |
+        grep -v 'origin/HEAD$') \
+    <(git ls-remote "$REPO" 'refs/heads/*' 'refs/tags/*' | grep -v '\^{}$' |
+        sed 's#refs/heads/#refs/remotes/origin/#' | LC_ALL=C sort -k2)
+git -C foo symbolic-ref HEAD >/dev/null
+test "$(git -C foo rev-parse HEAD)" = "$(git ls-remote "$REPO" HEAD | cut -f1)"
+# reflogs for the local branch and HEAD
+test "$(git -C foo reflog --format=%gs HEAD)" = "clone: from $REPO"
+test "$(git -C foo reflog --format=%gs "$(git -C foo symbolic-ref HEAD)")" = "clone: from $REPO"
+test ! -e foo/.git/HEAD.lock
+cleanup
+
+setup "git clone [--unpack] <url> <dir> (pack kept or unpacked)"
+REPO="https://github.com/mpg/ct"
+"$TARGET" clone "$REPO" foo >/dev/null
+test "$(find foo/.git/objects -type f -not -path '*/pack/*')" = ""
+git verify-pack foo/.git/objects/pack/pack-*.idx
+git -C foo fsck >/dev/null
+"$TARGET" clone --unpack "$REPO" bar >/dev/null
+test ! -d bar/.git/objects/pack
+git -C bar fsck >/dev/null
+cleanup
+
+setup "git clone --depth 1 <url> <dir>"
+REPO="https://github.com/mpg/ct"
+"$TARGET" clone --depth 1 "$REPO" foo >/dev/null
+test -f foo/.git/shallow
+test "$(git -C foo rev-list --count HEAD)" = 1
+git -C foo fsck >/dev/null
+cleanup
+
+setup "git clone --sparse --template=<dir> <url> <dir>"
+REPO="https://github.com/mpg/ct"
+mkdir -p "$OTHERDIR/tpl/info"
+echo '*.o' > "$OTHERDIR/tpl/info/exclude"
+"$TARGET" clone --sparse --template="$OTHERDIR/tpl" "$REPO" foo >/dev/null
+test -f foo/results
+test "$(find foo -mindepth 1 -maxdepth 1 -type d -not -name .git)" = ""
+test "$(git -C foo config core.sparseCheckout)" = true
+cmp foo/.git/info/exclude "$OTHERDIR/tpl/info/exclude"
+cleanup
+
+setup "git clone -c <name>=<value> <url> <dir> (core.autocrlf)"
+REPO="https://github.com/mpg/ct"
+"$TARGET" clone -c core.autocrlf=true "$REPO" foo >/dev/null
+git clone -q -c core.autocrlf=true "$REPO" bar
+test "$(git -C foo config core.autocrlf)" = true
+diff -r -x .git foo bar
+cleanup
+
+setup "git clone --filter=blob:none <url> <dir>"
+REPO="https://github.com/mpg/ct"
+"$TARGET" clone --filter=blob:none "$REPO" foo >/dev/null
+test -f foo/results
+test -x foo/rungcc
+test "$(git -C foo config remote.origin.partialclonefilter)" = blob:none
+cleanup
+
+setup "git clone --bare <url> / git init --bare"
+REPO="https://github.com/mpg/ct"
+"$TARGET" clone --bare "$REPO" >/dev/null
+test "$(git -C ct.git rev-parse --is-bare-repository)" = true
+test ! -e ct.git/index
+# branches and tags as on the remote, and no reflogs
+diff <(git -C ct.git for-each-ref --format='%(objectname)%09%(refname)') \
+    <(git ls-remote "$REPO" 'refs/heads/*' 'refs/tags/*' | grep -v '\^{}$' | LC_ALL=C sort -k2)
+test "$(git -C ct.git symbolic-ref HEAD)" = "$(git ls-remote --symref "$REPO" HEAD | sed -n 's/^ref: \(.*\)\tHEAD$/\1/p')"
+test ! -e ct.git/logs
+test "$(git -C ct.git config --get-all remote.origin.fetch || true)" = ""
+git -C ct.git fsck >/dev/null
+# commands that read the repository work from inside, worktree ones don't
+cd ct.git
+test "$("$TARGET" rev-parse --git-dir --is-bare-repository)" = "$(git rev-parse --git-dir --is-bare-repository)"
+diff_cmd rev-list HEAD
+diff_cmd ls-tree HEAD
+"$TARGET" write-tree 2>&1 | grep -q "must be run in a work tree"
+cd ..
+# init --bare produces a repository git agrees is bare
+"$TARGET" init --bare empty.git >/dev/null
+test "$(git -C empty.git rev-parse --is-bare-repository)" = true
+HASH=$(git -C ct.git rev-parse HEAD)
+git -C ct.git push -q ../empty.git HEAD:refs/heads/main
+test "$(cd empty.git && "$TARGET" rev-parse main)" = "$HASH"
+cleanup
+
+setup "git clone <path> (local clone)"
+git init -q src && echo a > src/a && git -C src add a && git -C src commit -q -m first
+git -C src tag -a -m tag v1 && git -C src gc -q
+git -C src branch side && echo b > src/b && git -C src add b && git -C src commit -q -m second
+"$TARGET" --offline clone src foo >/dev/null
+test -f foo/a && test -f foo/b
+test "$(git -C foo config remote.origin.url)" = "$(cd src && pwd -P)"
+diff <(git -C foo for-each-ref --format='%(objectname) %(refname)' refs/remotes refs/tags |
+        grep -v 'origin/HEAD$') \
+    <(git -C src for-each-ref --format='%(objectname) %(refname)' refs/heads refs/tags |
+        sed 's# refs/heads/# refs/remotes/origin/#')
+test "$(git -C foo rev-parse HEAD)" = "$(git -C src rev-parse HEAD)"
+# packs are hard-linked rather than copied
+test "$(stat -c %h foo/.git/objects/pack/*.pack)" = 2
+git -C foo fsck
+"$TARGET" clone --bare src/.git bar.git >/dev/null
+diff <(git -C bar.git for-each-ref) <(git -C src for-each-ref refs/heads refs/tags)
+"$TARGET" clone --depth 1 src baz 2>&1 | grep -q "not supported when cloning a local repository"
+test ! -e baz
+# the path recorded as remote.origin.url works for ls-remote and fetch
+diff <(cd foo && "$TARGET" --offline ls-remote 2>/dev/null) <(cd foo && git ls-remote 2>/dev/null)
+echo c > src/c && git -C src add c && git -C src commit -q -m third && git -C src tag v2
+(cd foo && "$TARGET" --offline fetch --dry-run) | grep -q "new tag.*v2"
+test -z "$(git -C foo tag -l v2)"
+(cd foo && "$TARGET" --offline fetch) >/dev/null
+diff <(git -C foo for-each-ref --format='%(objectname) %(refname)' refs/remotes refs/tags |
+        grep -v 'origin/HEAD$') \
+    <(git -C src for-each-ref --format='%(objectname) %(refname)' refs/heads refs/tags |
+        sed 's# refs/heads/# refs/remotes/origin/#')
+git -C foo fsck
+cleanup
+
+setup "git clone --branch <name> [--single-branch] <url|path> <dir>"
+git init -q -b main src && git -C src commit -q --allow-empty -m first
+git -C src tag -a -m tag v1 && git -C src checkout -q -b side
+git -C src commit -q --allow-empty -m side && git -C src tag light
+git -C src checkout -q main && git -C src commit -q --allow-empty -m second
+refs_and_config() {
+    git -C "$1" for-each-ref --format='%(objectname) %(refname) %(symref)'
+    git -C "$1" config --get-all remote.origin.fetch
+    git -C "$1" config --get-regexp '^branch\.'
+    git -C "$1" symbolic-ref HEAD
+}
+for args in "--branch side" "--single-branch" "--single-branch -b side"; do
+    rm -rf foo bar
+    "$TARGET" clone $args src foo >/dev/null
+    git clone -q $args src bar
+    diff <(refs_and_config foo) <(refs_and_config bar)
+done
+if "$TARGET" clone --single-branch -b nope src baz 2>/dev/null; then false; fi
+REPO="https://github.com/mpg/ct"
+rm -rf foo bar
+"$TARGET" clone --single-branch "$REPO" foo >/dev/null
+git clone -q --single-branch "$REPO" bar
+diff <(refs_and_config foo) <(refs_and_config bar)
+git -C foo fsck
+cleanup
+
+setup "git bundle create|verify|list-heads|unbundle, clone/fetch <bundle>"
+git init -q -b main src && echo a > src/a && git -C src add a && git -C src commit -q -m first
+git -C src tag -a -m tag v1 && echo b > src/b && git -C src add b && git -C src commit -q -m second
+git -C src branch side main~
+header() { sed -n '1,/^$/p' "$1"; }
+(cd src && "$TARGET" bundle create ../all.bundle --all && git bundle create -q ../ref.bundle --all)
+diff <(header all.bundle) <(header ref.bundle)
+git -C src bundle verify -q ../all.bundle
+(cd src && diff_cmd bundle list-heads ../ref.bundle && diff_cmd bundle verify ../ref.bundle)
+# clone from a bundle, ours or git's
+"$TARGET" clone all.bundle foo >/dev/null
+"$TARGET" clone ref.bundle bar >/dev/null
+for clone in foo bar; do
+    test "$(git -C $clone rev-parse HEAD)" = "$(git -C src rev-parse HEAD)"
+    test "$(git -C $clone rev-parse origin/side)" = "$(git -C src rev-parse side)"
+    test "$(git -C $clone rev-parse v1)" = "$(git -C src rev-parse v1)"
+    git -C $clone fsck
+done
+test "$(git -C foo config remote.origin.url)" = "$PWD/all.bundle"
+# incremental bundles have prerequisites
+echo c > src/c && git -C src add c && git -C src commit -q -m "third one"
+(cd src && "$TARGET" bundle create ../inc.bundle main ^side && git bundle create -q ../ref.bundle main ^side)
+diff <(header inc.bundle) <(header ref.bundle)
+git -C foo bundle verify -q ../inc.bundle
+git init -q empty
+if (cd empty && "$TARGET" bundle unbundle ../inc.bundle 2>/dev/null); then false; fi
+if "$TARGET" clone inc.bundle baz >/dev/null 2>&1; then false; fi
+test ! -e baz
+(cd foo && "$TARGET" bundle unbundle ../inc.bundle > ../mine && git bundle list-heads ../inc.bundle > ../ref)
+diff mine ref
+git -C foo cat-file -e "$(git -C src rev-parse HEAD)"
+git -C bar config remote.origin.url "$PWD/inc.bundle"
+(cd bar && "$TARGET" fetch >/dev/null)
+test "$(git -C bar rev-parse origin/main)" = "$(git -C src rev-parse main)"
+git -C bar fsck
+# git's packs have deltas against a base at an offset, or a prerequisite (thin)
+seq 1000 > src/big && git -C src add big && git -C src commit -q -m big
+seq 1001 > src/big && git -C src commit -q -a -m bigger
+git -C src bundle create -q ../big.bundle main
+seq 1002 > src/big && git -C src commit -q -a -m biggest
+git -C src bundle create -q ../thin.bundle main ^main~
+git -C bar config remote.origin.url "$PWD/big.bundle"
+(cd bar && "$TARGET" fetch >/dev/null)
+test "$(git -C bar rev-parse origin/main)" = "$(git -C src rev-parse main~)"
+git -C bar config remote.origin.url "$PWD/thin.bundle"
+(cd bar && "$TARGET" fetch >/dev/null)
+test "$(git -C bar rev-parse origin/main)" = "$(git -C src rev-parse main)"
+git -C bar fsck
+(cd foo && "$TARGET" bundle unbundle ../big.bundle >/dev/null && "$TARGET" bundle unbundle ../thin.bundle >/dev/null)
+git -C foo cat-file -e "$(git -C src rev-parse main:big)"
+cleanup
+
+setup "git fast-export [--all] <ref>... [^<rev>], git fast-import [--force]"
+git init -q -b main src && echo a > src/a && mkdir src/d && echo x > "src/d/with space"
+git -C src add -A && git -C src commit -q -m first && git -C src tag light
+echo b >> src/a && git -C src rm -q "d/with space" && printf q > src/é && ln -s a src/link
+git -C src add -A && git -C src commit -q -m second -m body && git -C src tag -a -m tag v1
+git -C src checkout -q -b side main~ && echo s > src/s && git -C src add s
+git -C src commit -q -m side && git -C src checkout -q main && git -C src merge -q --no-edit side
+(cd src && diff_cmd fast-export main)
+(cd src && "$TARGET" fast-export main ^main~ > ../mine)
+(cd src && git fast-export --reference-excluded-parents main ^main~ > ../ref)
+diff mine ref
+# Same history whoever exports or imports.
+(cd src && "$TARGET" fast-export --all > ../mine && git fast-export --all > ../ref)
+for stream in mine ref; do
+    git init -q --bare $stream.git
+    (cd $stream.git && "$TARGET" fast-import < ../$stream)
+    diff <(git -C src show-ref) <(git -C $stream.git show-ref)
+done
+git init -q --bare git.git
+(cd git.git && git fast-import --quiet < ../mine)
+diff <(git -C src show-ref) <(git -C git.git show-ref)
+# Other forms of data and file commands.
+cat > stream <<'END'
+blob
+mark :1
+data <<EOT
+# not a comment
+EOT
+
+commit refs/heads/main
+mark :2
+committer C O Mitter <c@example.org> 1000 +0100
+data 6
+first
+M 644 :1 "dir/a b"
+M 100755 inline exe
+data 4
+#!x
+
+commit refs/heads/main
+author A U Thor <a@example.org> 5 -0230
+committer C O Mitter <c@example.org> 2000 +0100
+data 7
+second
+C "dir/a b" copy
+R dir moved
+M 100644 inline dir
+data 2
+f
+
+commit refs/heads/other
+committer C O Mitter <c@example.org> 3000 +0000
+data 0
+from :2
+deleteall
+M 100644 :1 sub/x
+D sub
+M 100644 :1 "\"q\".txt"
+
+tag v1
+from :2
+tagger T <t@example.org> 9 +0000
+data 4
+tag
+done
+END
+git init -q --bare ours.git && git init -q --bare theirs.git
+(cd ours.git && "$TARGET" fast-import < ../stream)
+(cd theirs.git && git fast-import --quiet < ../stream)
+diff <(git -C ours.git show-ref) <(git -C theirs.git show-ref)
+# Branches are not rewound unless forced.
+printf 'commit refs/heads/main\ncommitter C <c> 0 +0000\ndata 0\nfrom main~\n' > stream
+if (cd ours.git && "$TARGET" fast-import < ../stream 2>/dev/null); then false; fi
+test "$(git -C ours.git rev-parse main)" = "$(git -C theirs.git rev-parse main)"
+(cd ours.git && "$TARGET" fast-import --force < ../stream)
+test "$(git -C ours.git rev-parse main~)" = "$(git -C theirs.git rev-parse main~)"
+cleanup
+
+setup "git clone git://<host>:<port>/<path> (git daemon)"
+git init -q work && echo a > work/a && git -C work add a && git -C work commit -q -m first
+git -C work tag -a -m tag v1
+# so that the pack sent has deltas, against bases at an offset in the pack
+seq 1000 > work/big && git -C work add big && git -C work commit -q -m big
+seq 1001 > work/big && git -C work commit -q -a -m bigger
+git clone -q --bare work repo.git
+PORT=$((20000 + $$ % 10000))
+URL="git://127.0.0.1:$PORT/repo.git"
+git daemon --export-all --base-path="$TESTDIR" --listen=127.0.0.1 --port="$PORT" \
+    --reuseaddr --detach --pid-file="$OTHERDIR/pid"
+for _ in $(seq 50); do git ls-remote "$URL" >/dev/null 2>&1 && break; sleep 0.1; done
+git ls-remote "$URL" >"$OTHERDIR/ref"
+"$TARGET" ls-remote "$URL" >"$OTHERDIR/mine"
+"$TARGET" --format=json ls-remote "$URL" >"$OTHERDIR/json"
+"$TARGET" clone "$URL" foo >/dev/null
+# Remotes by name, by default the one of the current branch, or origin.
+(cd foo && "$TARGET" ls-remote origin) | diff - "$OTHERDIR/ref"
+(cd foo && "$TARGET" ls-remote) | diff - "$OTHERDIR/ref"
+git -C foo remote rename origin other && git -C foo config remote.origin.url "git://127.0.0.1:1/nope"
+(cd foo && "$TARGET" ls-remote other HEAD) | diff - <(grep '	HEAD$' "$OTHERDIR/ref")
+(cd foo && "$TARGET" ls-remote) | diff - "$OTHERDIR/ref"
+BRANCH=$(git -C foo symbolic-ref HEAD) && git -C foo symbolic-ref HEAD refs/heads/topic
+if (cd foo && "$TARGET" ls-remote 2>/dev/null); then false; fi
+git -C foo config --unset remote.origin.url
+(cd foo && "$TARGET" ls-remote 2>&1) | grep -q "No remote configured"
+git -C foo symbolic-ref HEAD "$BRANCH"
+"$TARGET" clone --unpack "$URL" bar >/dev/null
+kill "$(cat "$OTHERDIR/pid")"
+git -C bar fsck
+diff "$OTHERDIR/mine" "$OTHERDIR/ref"
+jq -r '"\(.hash)\t\(.name)", if .peeled then "\(.peeled)\t\(.name)^{}" else empty end' \
+    "$OTHERDIR/json" | diff - "$OTHERDIR/ref"
+test "$(git -C foo rev-parse HEAD)" = "$(git -C work rev-parse HEAD)"
+git verify-pack -v foo/.git/objects/pack/*.idx | grep -q "^chain length = 1"
+git -C foo fsck
+cleanup
+
+setup "git clone <user>@<host>:<path> (ssh)"
+git init -q work && echo a > work/a && git -C work add a && git -C work commit -q -m first
+git -C work tag -a -m tag v1 && git clone -q --bare work repo.git
+# A fake ssh, running the command locally instead of on <host>.
+cat >"$OTHERDIR/ssh" <<'EOF'
+#!/bin/sh
+while [ "$1" = -o ] || [ "$1" = -p ]; do shift 2; done
+shift
+exec sh -c "$1"
+EOF
+chmod +x "$OTHERDIR/ssh"
+export GIT_SSH_COMMAND="$OTHERDIR/ssh"
+git ls-remote "git@example.com:$TESTDIR/repo.git" >"$OTHERDIR/ref"
+"$TARGET" ls-remote "git@example.com:$TESTDIR/repo.git" >"$OTHERDIR/mine"
+diff "$OTHERDIR/mine" "$OTHERDIR/ref"
+"$TARGET" ls-remote "ssh://git@example.com:22$TESTDIR/repo.git" >"$OTHERDIR/mine"
+diff "$OTHERDIR/mine" "$OTHERDIR/ref"
+"$TARGET" clone "git@example.com:$TESTDIR/repo.git" >/dev/null
+test "$(git -C repo rev-parse HEAD)" = "$(git -C work rev-parse HEAD)"
+git -C repo fsck
+"$TARGET" --offline clone "example.com:$TESTDIR/repo.git" foo 2>&1 | grep -q "network access disabled"
+unset GIT_SSH_COMMAND
+cleanup
+
+setup "SIGINT during clone (temporary files removed)"
+git init -q work && head -c 1000000 /dev/urandom > work/big
+git -C work add big && git -C work commit -q -m big && git clone -q --bare work repo.git
+# A fake ssh, running the command locally, and slowly sending its output.
+cat >"$OTHERDIR/ssh" <<'EOF'
+#!/bin/sh
+while [ "$1" = -o ] || [ "$1" = -p ]; do shift 2; done
+shift
+sh -c "$1" | python3 -c '
+import sys, time
+src, dst = sys.stdin.buffer, sys.stdout.buffer
+while data := src.read1(4096):
+    dst.write(data)
+    dst.flush()
+    time.sleep(0.02)
+'
+EOF
+chmod +x "$OTHERDIR/ssh"
+export GIT_SSH_COMMAND="$OTHERDIR/ssh"
+"$TARGET" clone "git@example.com:$TESTDIR/repo.git" foo >/dev/null 2>"$OTHERDIR/err" &
+CLONE=$!
+for _ in $(seq 50); do test -e foo/.git/objects/pack/tmp_pack_* && break; sleep 0.1; done
+test -e foo/.git/objects/pack/tmp_pack_*
+kill -INT "$CLONE"
+if wait "$CLONE"; then false; fi
+grep -q "interrupted" "$OTHERDIR/err"
+test -z "$(find foo -name 'tmp*' -o -name '*.lock')"
+unset GIT_SSH_COMMAND
+cleanup
+
+setup "git dedup-report (partial clone, object-info over ssh)"
+git init -q work && mkdir work/d && echo same > work/a && echo same > work/d/b
+echo old > work/c && git -C work add . && git -C work commit -q -m first
+echo new > work/c && git -C work commit -q -am second
+git clone -q --bare work repo.git && git -C repo.git config uploadpack.allowFilter true
+# A fake ssh, running the command locally, optionally hiding object-info.
+cat >"$OTHERDIR/ssh" <<'EOF'
+#!/bin/sh
+while [ "$1" = -o ] || [ "$1" = -p ]; do shift 2; done
+shift
+if [ -n "${HIDE_OBJECT_INFO:-}" ]; then
+    sh -c "$1" | python3 -c '
+import sys
+src, dst = sys.stdin.buffer, sys.stdout.buffer
+while len(head := src.read(4)) == 4:
+    data = src.read(max(int(head, 16) - 4, 0))
+    if data != b"object-info\n":
+        dst.write(head + data)
+        dst.flush()
+'
+else
+    exec sh -c "$1"
+fi
+EOF
+chmod +x "$OTHERDIR/ssh"
+export GIT_SSH_COMMAND="$OTHERDIR/ssh"
+"$TARGET" clone --filter=blob:none "git@example.com:$TESTDIR/repo.git" part >/dev/null
+OLD=$(git -C work rev-parse HEAD~:c)
+(cd work && "$TARGET" dedup-report) >"$OTHERDIR/ref"
+(cd part && "$TARGET" dedup-report) >"$OTHERDIR/mine"
+diff "$OTHERDIR/mine" "$OTHERDIR/ref"
+# the size was asked, not the blob
+git -C part rev-list --objects --missing=print --all | grep -q "^?$OLD"
+# without object-info, the missing blob can't be sized
+if (cd part && HIDE_OBJECT_INFO=1 "$TARGET" dedup-report) >/dev/null 2>"$OTHERDIR/err"; then false; fi
+grep -q "opening blob $OLD" "$OTHERDIR/err"
+unset GIT_SSH_COMMAND
+cleanup
+
+setup "git clone / fetch <url> (dumb HTTP)"
+git init -q work && echo a > work/a && git -C work add a && git -C work commit -q -m first
+git -C work tag -a -m tag v1 && git clone -q --bare work repo.git && git -C repo.git repack -qad
+echo b > work/b && git -C work add b && git -C work commit -q -m second
+git -C work push -q "$TESTDIR/repo.git" main && git -C repo.git update-server-info
+PORT=$((20000 + $$ % 10000))
+URL="http://127.0.0.1:$PORT/repo.git"
+python3 -m http.server --bind 127.0.0.1 --directory "$TESTDIR" "$PORT" >/dev/null 2>&1 &
+SERVER=$!
+for _ in $(seq 50); do git ls-remote "$URL" >/dev/null 2>&1 && break; sleep 0.1; done
+git ls-remote "$URL" >"$OTHERDIR/ref"
+"$TARGET" ls-remote "$URL" >"$OTHERDIR/mine"
+"$TARGET" clone "$URL" foo >/dev/null
+echo c > work/c && git -C work add c && git -C work commit -q -m third
+git -C work push -q "$TESTDIR/repo.git" main && git -C repo.git update-server-info
+(cd foo && "$TARGET" fetch >/dev/null)
+kill "$SERVER"
+diff "$OTHERDIR/mine" "$OTHERDIR/ref"
+test "$(git -C foo rev-parse origin/main)" = "$(git -C work rev-parse HEAD)"
+test "$(git -C foo rev-parse v1)" = "$(git -C work rev-parse v1)"
+git -C foo fsck
+cleanup
+
+setup "git push [--no-verify] <remote> [+]<src>:<dst>... (smart HTTP, pre-push hook)"
+git init -q work && echo a > work/a && git -C work add a && git -C work commit -q -m first
+git -C work branch side
+git clone -q --bare work mine.git && git -C mine.git config http.receivepack true
+git clone -q --bare work ref.git && git -C ref.git config http.receivepack true
+# git http-backend, run as a CGI script by python's http.server.
+mkdir cgi-bin && cat >cgi-bin/git <<EOF
+#!/bin/sh
+GIT_PROJECT_ROOT="$TESTDIR" GIT_HTTP_EXPORT_ALL=1 exec git http-backend
+EOF
+chmod +x cgi-bin/git
+PORT=$((20000 + $$ % 10000))
+URL="http://127.0.0.1:$PORT/cgi-bin/git"
+python3 -c 'import http.server as s, os, sys
+s.nobody_uid = os.getuid  # rather than running scripts as nobody, when root
+s.test(s.CGIHTTPRequestHandler, port=int(sys.argv[1]), bind="127.0.0.1")' "$PORT" >/dev/null 2>&1 &
+SERVER=$!
+for _ in $(seq 50); do git ls-remote "$URL/ref.git" >/dev/null 2>&1 && break; sleep 0.1; done
+# The same pushes, by us from mine to mine.git, and by git from ref to ref.git.
+for r in mine ref; do
+    git clone -q work $r && git -C $r branch -q side origin/side
+    git -C $r remote set-url origin "$URL/$r.git"
+    printf '#!/bin/sh\necho "$@" >.git/pre-push.args\ncat >>.git/pre-push.log\n' >$r/.git/hooks/pre-push
+    chmod +x $r/.git/hooks/pre-push
+    echo b > $r/b && git -C $r add b
+    GIT_AUTHOR_DATE="@0 +0000" GIT_COMMITTER_DATE="@0 +0000" git -C $r commit -q -m second
+done
+push() {
+    (cd mine && "$TARGET" push "$@") >"$OTHERDIR/mine" 2>&1 && MINE=0 || MINE=$?
+    (cd ref && git push -q "$@") >/dev/null 2>&1 && REF=0 || REF=$?
+    test "$MINE" = "$REF"
+}
+push origin main
+push origin main:topic
+push origin side:main
+grep -q "rejected.*non-fast-forward" "$OTHERDIR/mine"
+push origin +side:topic
+push origin :refs/heads/side
+push --no-verify origin main:refs/tags/v1
+# The hook may write more than a pipe holds before reading more than a pipe holds.
+for r in mine ref; do
+    sed -i '2i seq 20000' $r/.git/hooks/pre-push
+    seq -f "create refs/tags/t%g HEAD" 1000 | git -C $r update-ref --stdin
+done
+push origin $(seq -f refs/tags/t%g 1000)
+# A failing pre-push hook aborts the push.
+echo "exit 1" >>mine/.git/hooks/pre-push && echo "exit 1" >>ref/.git/hooks/pre-push
+push origin main:refs/heads/blocked
+grep -q "hook pre-push failed" "$OTHERDIR/mine"
+kill "$SERVER"
+diff <(git -C mine.git for-each-ref) <(git -C ref.git for-each-ref)
+diff <(git -C mine for-each-ref refs/remotes) <(git -C ref for-each-ref refs/remotes)
+diff mine/.git/pre-push.log ref/.git/pre-push.log
+diff <(sed "s/mine/ref/" mine/.git/pre-push.args) ref/.git/pre-push.args
+git -C mine.git fsck
+cleanup
+
+setup "core.remoteAlternate (objects fetched on demand over HTTP)"
+git init -q store && cd store
+echo a > a && git add a && git commit -q -m first
+FIRST=$(git rev-parse HEAD)
+echo b > b && git add b && git commit -q -m second
+SECOND=$(git rev-parse HEAD)
+# Store the blob of b under the name of that of a.
+A=$(git rev-parse HEAD:a) && B=$(git rev-parse HEAD:b)
+cp ".git/objects/${A:0:2}/${A:2}" ".git/objects/${B:0:2}/${B:2}"
+cd ..
+PORT=$((20000 + $$ % 10000))
+python3 -m http.server --bind 127.0.0.1 --directory "$TESTDIR" "$PORT" >/dev/null 2>&1 &
+SERVER=$!
+for _ in $(seq 50); do curl -sf "http://127.0.0.1:$PORT/" >/dev/null && break; sleep 0.1; done
+git init -q mine && cd mine
+git config core.remoteAlternate "http://127.0.0.1:$PORT/store/.git"
+# Only commits are here, their trees and blobs are fetched.
+for commit in "$FIRST" "$SECOND"; do
+    git -C ../store cat-file commit "$commit" | git hash-object -t commit -w --stdin >/dev/null
+done
+"$TARGET" checkout-empty "$FIRST"
+test "$(cat a)" = a
+test -f ".git/objects/${A:0:2}/${A:2}"
+if "$TARGET" checkout-empty "$SECOND" 2>"$OTHERDIR/err"; then false; fi
+# SHA-256 objects are fetched too.
+git init -q --object-format=sha256 ../store256
+git -C ../store256 commit -q --allow-empty -m first
+C=$(git -C ../store256 rev-parse HEAD)
+git init -q --object-format=sha256 ../mine256
+git -C ../mine256 config core.remoteAlternate "http://127.0.0.1:$PORT/store256/.git"
+(cd ../mine256 && echo "$C" | "$TARGET" cat-file --batch-check) >"$OTHERDIR/check256" || true
+kill "$SERVER"
+grep -q "wrong hash" "$OTHERDIR/err"
+test ! -e ".git/objects/${B:0:2}/${B:2}"
+test "$(cat "$OTHERDIR/check256")" = "$C commit $(git -C ../store256 cat-file -s "$C")"
+test -f "../mine256/.git/objects/${C:0:2}/${C:2}"
+cleanup
+
+setup "git fetch"
+REPO="https://github.com/mpg/ct"
+"$TARGET" clone "$REPO" foo >/dev/null
+cd foo
+git for-each-ref > "$OTHERDIR/before"
+find .git/refs/remotes/origin -type f -not -name HEAD -delete
+rm -r .git/refs/tags
+# --dry-run shows what would happen, and changes nothing
+BRANCH="$(git symbolic-ref --short HEAD)"
+git for-each-ref >"$OTHERDIR/deleted"
+"$TARGET" fetch --dry-run >"$OTHERDIR/out"
+grep -q "^ \* \[new branch\]      $BRANCH -> origin/$BRANCH$" "$OTHERDIR/out"
+git for-each-ref | diff - "$OTHERDIR/deleted"
+"$TARGET" fetch >/dev/null
+git for-each-ref | diff - "$OTHERDIR/before"
+cleanup
+
+setup "git remote [-v] / add / rename / remove / show -n / set-url"
+git init -q mine && git -C mine commit -q --allow-empty -m first && cp -r mine ref
+both() {
+    (cd ref && git "$@")
+    (cd mine && "$TARGET" "$@")
+}
+both remote add zeta https://example.com/zeta.git
+both remote add alpha https://example.com/alpha.git
+both remote set-url --push zeta https://example.com/push.git
+for repo in mine ref; do
+    git -C $repo update-ref refs/remotes/alpha/main HEAD
+    git -C $repo update-ref refs/remotes/alpha/dev HEAD
+    git -C $repo symbolic-ref refs/remotes/alpha/HEAD refs/remotes/alpha/main
+    git -C $repo config branch.main.remote alpha
+    git -C $repo config branch.main.merge refs/heads/dev
+done
+cd mine
+diff_cmd remote
+diff_cmd remote -v
+diff_cmd remote show -n alpha zeta
+if "$TARGET" remote add alpha https://example.com/again.git 2>/dev/null; then false; fi
+if "$TARGET" remote rename nope other 2>/dev/null; then false; fi
+cd ..
+both remote rename alpha beta
+(cd mine && diff_cmd remote show -n beta)
+diff mine/.git/config ref/.git/config
+diff <(git -C mine for-each-ref) <(git -C ref for-each-ref)
+test "$(git -C mine symbolic-ref refs/remotes/beta/HEAD)" = refs/remotes/beta/main
+both remote remove beta
+diff mine/.git/config ref/.git/config
+test "$(git -C mine for-each-ref refs/remotes)" = ""
+test ! -e mine/.git/refs/remotes/beta
+cleanup
+
+setup "git fetch <remote> (fetch refspecs)"
+git init -q -b main src && git -C src commit -q --allow-empty -m first
+git -C src branch side && git -C src commit -q --allow-empty -m second
+(cd src && "$TARGET" bundle create ../all.bundle --all)
+git init -q work && cd work
+"$TARGET" remote add src "$TESTDIR/all.bundle"
+git config remote.src.fetch +refs/heads/main:refs/remotes/src/only-main
+"$TARGET" fetch src >/dev/null
+test "$(git for-each-ref --format='%(refname)' refs/remotes)" = refs/remotes/src/only-main
+test "$(git rev-parse src/only-main)" = "$(git -C ../src rev-parse main)"
+# without +, only fast-forwards
+git config remote.src.fetch 'refs/heads/*:refs/remotes/src/*'
+git update-ref refs/remotes/src/side src/only-main
+# --dry-run shows what would happen, and changes nothing
+git for-each-ref >"$OTHERDIR/refs"
+find .git/objects -type f | sort >"$OTHERDIR/objects"
+if "$TARGET" fetch --dry-run src >"$OTHERDIR/out" 2>/dev/null; then false; fi
+grep -q "^ \* \[new branch\]      main -> src/main$" "$OTHERDIR/out"
+grep -q "^ ! \[rejected\]        side -> src/side  (non-fast-forward)$" "$OTHERDIR/out"
+git for-each-ref | diff - "$OTHERDIR/refs"
+find .git/objects -type f | sort | diff - "$OTHERDIR/objects"
+if "$TARGET" fetch src >/dev/null 2>&1; then false; fi
+test "$(git rev-parse src/side)" = "$(git rev-parse src/only-main)"
+test "$(git rev-parse src/main)" = "$(git -C ../src rev-parse main)"
+# fast-forwards show the old and new values
+git -C ../src commit -q --allow-empty -m third
+(cd ../src && "$TARGET" bundle create ../all.bundle --all)
+RANGE="$(git rev-parse --short src/main)..$(git -C ../src rev-parse --short main)"
+if "$TARGET" fetch --dry-run src >"$OTHERDIR/out" 2>/dev/null; then false; fi
+grep -q "^   $RANGE  main -> src/main$" "$OTHERDIR/out"
+cleanup
+
+setup "git --offline clone / GIT_OFFLINE=1 ls-remote"
+REPO="https://github.com/mpg/ct"
+"$TARGET" --offline clone "$REPO" foo 2>&1 | grep -q "network access disabled"
+test ! -e foo
+GIT_OFFLINE=1 "$TARGET" ls-remote "$REPO" 2>&1 | grep -q "network access disabled"
 cleanup
 
 setup "git clone <url>"
@@ -0,0 +1,106 @@
+//! Abbreviating object hashes to short but unambiguous prefixes,
+//! and expanding such prefixes back to full hashes.
+//!
+//! The minimum length comes from --abbrev=N if given, then core.abbrev,
+//! and defaults to 7 like git.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::sync::OnceLock;
+
+use crate::config;
+use crate::object_id::is_hash;
+use crate::pack_read;
+use crate::repository::Repository;
+
+/// Git never abbreviates to fewer hex digits than this.
+const MIN_LEN: usize = 4;
+
+/// Length used when neither --abbrev=N nor core.abbrev are set.
+const DEFAULT_LEN: usize = 7;
+
+/// Length set on the command line, if any.
+static LEN: OnceLock<usize> = OnceLock::new();
+
+/// Set the minimum length from the command line (--abbrev=N).
+pub fn set_len(len: usize) {
+    // Only called once, from main, maybe outside of a repository:
+    // this is clamped to the length of hashes in shorten_to().
+    _ = LEN.set(len.max(MIN_LEN));
+}
+
+/// The minimum length of abbreviated hashes.
+pub fn min_len(repo: &Repository) -> Result<usize> {
+    if let Some(&len) = LEN.get() {
+        return Ok(len);
+    }
+    match config::get(repo, "core.abbrev")?.as_deref() {
+        None | Some("auto") => Ok(DEFAULT_LEN),
+        Some("no") => Ok(repo.hash_algo()?.hex_len()),
+        Some(value) => match value.parse::<usize>() {
+            Ok(len) => Ok(len.clamp(MIN_LEN, repo.hash_algo()?.hex_len())),
+            Err(_) => bail!("invalid value for core.abbrev: {value}"),
+        },
+    }
+}
+
+/// List hashes of the objects (loose or packed) starting with a prefix
+/// of at least two hex digits.
+fn objects_with_prefix(repo: &Repository, prefix: &str) -> Result<Vec<String>> {
+    let mut found = Vec::new();
+    for objects in repo.object_dirs() {
+        let dir = objects.join(&prefix[..2]);
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries {
+            let entry = entry.with_context(|| format!("listing {}", dir.display()))?;
+            let hash = format!("{}{}", &prefix[..2], entry.file_name().to_string_lossy());
+            if is_hash(repo, &hash) && hash.starts_with(prefix) {
+                found.push(hash);
+            }
+        }
+    }
+    found.extend(pack_read::with_prefix(repo, prefix)?);
+    Ok(found)
+}
+
+/// Abbreviate a hash to the shortest prefix of at least min_len() hex digits
+/// that no other object in the repository starts with.
+pub fn shorten(repo: &Repository, hash: &str) -> Result<String> {
+    shorten_to(repo, hash, min_len(repo)?)
+}
+
+/// Abbreviate a hash to the shortest unambiguous prefix of at least `len` hex digits.
+pub fn shorten_to(repo: &Repository, hash: &str, len: usize) -> Result<String> {
+    let len = len.clamp(MIN_LEN, hash.len());
+    let mut unique = len;
+    for other in objects_with_prefix(repo, &hash[..len])? {
+        let common = hash
+            .bytes()
+            .zip(other.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if common < hash.len() {
+            unique = unique.max(common + 1);
+        }
+    }
+    Ok(hash[..unique].to_owned())
+}
+
+/// Find the object whose hash starts with the given prefix (at least 4 hex digits),
+/// failing if there are several.
+pub fn expand(repo: &Repository, prefix: &str) -> Result<Option<String>> {
+    let prefix = prefix.to_ascii_lowercase();
+    if prefix.len() < MIN_LEN || !prefix.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    let mut found = objects_with_prefix(repo, &prefix)?;
+    found.sort_unstable();
+    found.dedup();
+    match &found[..] {
+        [] => Ok(None),
+        [hash] => Ok(Some(hash.clone())),
+        _ => bail!("short object ID {prefix} is ambiguous"),
+    }
+}
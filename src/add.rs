@@ -0,0 +1,304 @@
+//! Staging some of the changes to tracked files, like git add -p.
+//!
+//! The changes of each modified file are shown hunk by hunk, asking whether
+//! to stage them. Answers are read from standard input, one per line, as git
+//! does when single-key input is not enabled. The staged content is the one
+//! in the index with the chosen changes applied. Like git, a hunk can be
+//! split at the unchanged lines between its changes.
+//!
+//! Editing hunks and going to a hunk by number or regex (e, g, /) are not
+//! supported, and only changes to the content of regular files are shown:
+//! not new or deleted files, mode changes, symbolic links or binary files.
+
+use anyhow::Result;
+use std::io;
+use std::io::prelude::*;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::abbrev::shorten;
+use crate::common::{is_under, repo_path};
+use crate::diff::{
+    content, diff_lines, diffs_as_binary, hunks, print_hunk, worktree_side, DiffColors, Op, Side,
+};
+use crate::index;
+use crate::lock::lock_worktree;
+use crate::obj_type::ObjType;
+use crate::obj_write::write_object;
+use crate::object_id::ObjectId;
+use crate::repository::Repository;
+use crate::tree_entry::Mode;
+
+/// The answers to the prompt, with their help, in the order git shows them.
+/// The first five are always available.
+const HELP: [(char, &str); 10] = [
+    ('y', "stage this hunk"),
+    ('n', "do not stage this hunk"),
+    (
+        'q',
+        "quit; do not stage this hunk or any of the remaining ones",
+    ),
+    ('a', "stage this hunk and all later hunks in the file"),
+    (
+        'd',
+        "do not stage this hunk or any of the later hunks in the file",
+    ),
+    ('j', "leave this hunk undecided, see next undecided hunk"),
+    ('J', "leave this hunk undecided, see next hunk"),
+    (
+        'k',
+        "leave this hunk undecided, see previous undecided hunk",
+    ),
+    ('K', "leave this hunk undecided, see previous hunk"),
+    ('s', "split the current hunk into smaller hunks"),
+];
+
+/// A hunk of a file's changes, as a range in its edit script (including
+/// unchanged lines around the changes).
+struct Hunk {
+    ops: Range<usize>,
+    /// Made by splitting a hunk: shown without the function line, like git
+    split: bool,
+    /// Whether to stage it, None until decided
+    stage: Option<bool>,
+}
+
+/// Split a hunk at the unchanged lines between its changes, like git: each
+/// part keeps all the unchanged lines between it and the parts around it,
+/// so consecutive parts share these lines. A single part means the hunk
+/// can't be split.
+fn split(ops: &[Op], hunk: &Range<usize>) -> Vec<Range<usize>> {
+    let mut changes: Vec<Range<usize>> = Vec::new();
+    for pos in hunk.clone() {
+        if matches!(ops[pos], Op::Equal(..)) {
+            continue;
+        }
+        match changes.last_mut() {
+            Some(last) if last.end == pos => last.end = pos + 1,
+            _ => changes.push(pos..pos + 1),
+        }
+    }
+    (0..changes.len())
+        .map(|n| {
+            let start = match n {
+                0 => hunk.start,
+                _ => changes[n - 1].end,
+            };
+            let end = changes.get(n + 1).map_or(hunk.end, |next| next.start);
+            start..end
+        })
+        .collect()
+}
+
+/// Show the hunks of a file and ask which ones to stage. Return the lines
+/// to stage, if any hunk was chosen, and whether to quit.
+fn choose(
+    old: &[&[u8]],
+    new: &[&[u8]],
+    ops: &[Op],
+    input: &mut impl Iterator<Item = io::Result<String>>,
+    out: &mut impl Write,
+) -> Result<(Option<Vec<u8>>, bool)> {
+    let mut hunks: Vec<Hunk> = hunks(ops)
+        .into_iter()
+        .map(|ops| Hunk {
+            ops,
+            split: false,
+            stage: None,
+        })
+        .collect();
+    let mut index = 0;
+    let mut quit = false;
+    loop {
+        if index >= hunks.len() {
+            index = 0;
+        }
+        let previous = hunks[..index].iter().rposition(|h| h.stage.is_none());
+        let next = hunks[index + 1..]
+            .iter()
+            .position(|h| h.stage.is_none())
+            .map(|n| index + 1 + n);
+        if previous.is_none() && next.is_none() && hunks[index].stage.is_some() {
+            break;
+        }
+        let hunk = &hunks[index];
+        let colors = DiffColors::default();
+        print_hunk(&ops[hunk.ops.clone()], old, new, !hunk.split, &colors, out)?;
+        let parts = split(ops, &hunk.ops);
+        let mut allowed = vec!['y', 'n', 'q', 'a', 'd'];
+        for (letter, available) in [
+            ('k', previous.is_some()),
+            ('K', index > 0),
+            ('j', next.is_some()),
+            ('J', index + 1 < hunks.len()),
+            ('s', parts.len() > 1),
+        ] {
+            if available {
+                allowed.push(letter);
+            }
+        }
+        let letters: Vec<String> = allowed.iter().map(char::to_string).collect();
+        write!(
+            out,
+            "({}/{}) Stage this hunk [{},?]? ",
+            index + 1,
+            hunks.len(),
+            letters.join(",")
+        )?;
+        out.flush()?;
+        let Some(answer) = input.next().transpose()? else {
+            break;
+        };
+        let Some(letter) = answer.trim().chars().next() else {
+            continue;
+        };
+        // Like git, these answers are case-insensitive, but not the others.
+        let lower = letter.to_ascii_lowercase();
+        match lower {
+            'y' | 'n' => {
+                hunks[index].stage = Some(lower == 'y');
+                index = next.unwrap_or(hunks.len());
+                continue;
+            }
+            'a' | 'd' => {
+                for hunk in &mut hunks[index..] {
+                    hunk.stage.get_or_insert(lower == 'a');
+                }
+                index = hunks.len();
+                continue;
+            }
+            'q' => {
+                quit = true;
+                break;
+            }
+            _ => (),
+        }
+        match letter {
+            'K' if index > 0 => index -= 1,
+            'J' if index + 1 < hunks.len() => index += 1,
+            'k' | 'j' => match if letter == 'k' { previous } else { next } {
+                Some(other) => index = other,
+                None if letter == 'k' => eprintln!("No previous hunk"),
+                None => eprintln!("No next hunk"),
+            },
+            'K' => eprintln!("No previous hunk"),
+            'J' => eprintln!("No next hunk"),
+            's' if parts.len() > 1 => {
+                writeln!(out, "Split into {} hunks.", parts.len())?;
+                let parts = parts.into_iter().map(|ops| Hunk {
+                    ops,
+                    split: true,
+                    stage: None,
+                });
+                hunks.splice(index..=index, parts);
+            }
+            's' => eprintln!("Sorry, cannot split this hunk"),
+            _ => {
+                for (letter, help) in HELP {
+                    if allowed.contains(&letter) {
+                        writeln!(out, "{letter} - {help}")?;
+                    }
+                }
+                writeln!(out, "? - print help")?;
+            }
+        }
+    }
+    writeln!(out)?;
+
+    if !hunks.iter().any(|hunk| hunk.stage == Some(true)) {
+        return Ok((None, quit));
+    }
+    // Keep the old lines, except for the changes of staged hunks.
+    let mut staged = vec![false; ops.len()];
+    for hunk in hunks.iter().filter(|hunk| hunk.stage == Some(true)) {
+        staged[hunk.ops.clone()].fill(true);
+    }
+    let mut data = Vec::new();
+    for (op, staged) in ops.iter().zip(staged) {
+        match *op {
+            Op::Equal(i, _) => data.extend_from_slice(old[i]),
+            Op::Delete(i) if !staged => data.extend_from_slice(old[i]),
+            Op::Insert(j) if staged => data.extend_from_slice(new[j]),
+            _ => (),
+        }
+    }
+    Ok((Some(data), quit))
+}
+
+/// The "git add -p" command: stage changes to tracked files (under the given
+/// paths, if any), hunk by hunk.
+pub fn add_patch(repo: &Repository, paths: &[PathBuf]) -> Result<()> {
+    let lock = lock_worktree(repo)?;
+    let root = repo.work_tree()?;
+    let prefixes = paths
+        .iter()
+        .map(|path| repo_path(repo, path))
+        .collect::<Result<Vec<_>>>()?;
+    let selected =
+        |path: &[u8]| prefixes.is_empty() || prefixes.iter().any(|prefix| is_under(path, prefix));
+    let mut entries = index::read(repo)?;
+    let mut input = io::stdin().lock().lines();
+    let mut out = io::stdout().lock();
+    let mut shown = false;
+    for entry in &mut entries {
+        if entry.stage != 0
+            || !matches!(entry.mode, Mode::File | Mode::Exe)
+            || !selected(&entry.path)
+        {
+            continue;
+        }
+        let indexed = Side {
+            mode: entry.mode.clone(),
+            hash: hex::encode(entry.hash),
+            file: None,
+        };
+        let Some(current) = worktree_side(repo, root, &entry.path, &indexed)? else {
+            continue;
+        };
+        if current.mode != indexed.mode || current.hash == indexed.hash {
+            continue;
+        }
+        let old_data = content(repo, &indexed)?;
+        let new_data = content(repo, &current)?;
+        if diffs_as_binary(repo, &entry.path, &old_data, &new_data)? {
+            continue;
+        }
+        let old: Vec<&[u8]> = old_data.split_inclusive(|&c| c == b'\n').collect();
+        let new: Vec<&[u8]> = new_data.split_inclusive(|&c| c == b'\n').collect();
+        let ops = diff_lines(&old, &new);
+        if hunks(&ops).is_empty() {
+            continue;
+        }
+        shown = true;
+
+        let path = &entry.path;
+        out.write_all(&[b"diff --git a/", &path[..], b" b/", path, b"\n"].concat())?;
+        let (old_hash, new_hash) = (shorten(repo, &indexed.hash)?, shorten(repo, &current.hash)?);
+        writeln!(
+            out,
+            "index {old_hash}..{new_hash} {}",
+            indexed.mode.to_str()
+        )?;
+        out.write_all(&[b"--- a/", &path[..], b"\n+++ b/", path, b"\n"].concat())?;
+        let (staged, quit) = choose(&old, &new, &ops, &mut input, &mut out)?;
+        if let Some(staged) = staged {
+            if staged == new_data {
+                // Everything is staged: the file matches the index again.
+                entry.hash = ObjectId::from_hex(&current.hash)?;
+                entry.stat = None;
+            } else {
+                let hash = write_object(repo, ObjType::Blob, &mut io::Cursor::new(staged), true)?;
+                entry.hash = ObjectId::from_hex(&hash)?;
+                // The file differs from the index: don't let its stat data match.
+                entry.stat = Some([0; 9]);
+            }
+        }
+        if quit {
+            break;
+        }
+    }
+    if !shown {
+        eprintln!("No changes.");
+    }
+    index::write(repo, lock, &entries)
+}
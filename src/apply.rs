@@ -0,0 +1,797 @@
+//! Applying patches in unified diff format to the worktree or the index,
+//! like git apply.
+//!
+//! Both git diffs, with their extended headers for new and deleted files,
+//! mode changes, renames and copies, and traditional diffs are understood,
+//! as well as git binary patches (see apply_binary()). Like git, a hunk
+//! applies where its context matches
+//! exactly, looking first at the position it gives then further and further
+//! away from it, and nothing is changed unless all patches apply. Context is
+//! only reduced (fuzz) when asked to, see apply().
+
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::bufread::ZlibDecoder;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::str;
+
+use crate::base85;
+use crate::common::{read_inputs, unquote_c};
+use crate::filter;
+use crate::index::{self, IndexEntry};
+use crate::lock::{lock_worktree, LockFile};
+use crate::obj_read::read_blob;
+use crate::obj_type::ObjType;
+use crate::obj_write::write_object;
+use crate::object_id::{is_hash, ObjectId};
+use crate::pack_index::apply_delta;
+use crate::platform;
+use crate::repository::Repository;
+use crate::tree_entry::{check_leading_dirs, verify_path, Mode};
+use crate::worktree::remove_path;
+
+/// A group of changed lines, with the lines around them.
+struct Hunk {
+    /// Line numbers given by the header, starting at 1
+    old_start: usize,
+    new_start: usize,
+    /// Lines before and after the change, with their line feed if any
+    pre: Vec<Vec<u8>>,
+    post: Vec<Vec<u8>>,
+    /// Number of context lines before and after the changed lines
+    leading: usize,
+    trailing: usize,
+}
+
+/// The changes to one file.
+struct FilePatch {
+    /// Paths before and after, None for created and deleted files
+    old_path: Option<Vec<u8>>,
+    new_path: Option<Vec<u8>>,
+    old_mode: Option<Mode>,
+    new_mode: Option<Mode>,
+    /// The old path is kept (copy rather than rename)
+    copy: bool,
+    hunks: Vec<Hunk>,
+    /// Names of the old and new objects, from the index line
+    old_hash: Option<String>,
+    new_hash: Option<String>,
+    /// A binary file: its new content comes from `binary_hunk` if the patch
+    /// has data, or from the object store
+    binary: bool,
+    binary_hunk: Option<BinaryHunk>,
+}
+
+/// The data of a git binary patch, to get the new content of a file.
+enum BinaryHunk {
+    /// The new content
+    Literal(Vec<u8>),
+    /// Delta instructions to apply to the old content
+    Delta(Vec<u8>),
+}
+
+/// Content of a path: its mode and the data of the blob.
+type Content = (Mode, Vec<u8>);
+
+/// Remove `strip` leading components from a path in a patch.
+fn strip_path(path: &[u8], strip: usize) -> Result<Vec<u8>> {
+    let mut rest = path;
+    for _ in 0..strip {
+        let Some(slash) = rest.iter().position(|&c| c == b'/') else {
+            bail!(
+                "cannot remove {strip} leading components from '{}'",
+                String::from_utf8_lossy(path)
+            );
+        };
+        rest = &rest[slash + 1..];
+    }
+    Ok(rest.to_vec())
+}
+
+/// Parse the name in a `---` or `+++` line, None for /dev/null.
+fn file_name(value: &[u8], strip: usize) -> Result<Option<Vec<u8>>> {
+    // Traditional diffs may add a timestamp, and git a tab after names with spaces.
+    let name = value.split(|&c| c == b'\t').next().unwrap_or(value);
+    let name = unquote_c(name.trim_ascii_end())?;
+    if name == b"/dev/null" {
+        return Ok(None);
+    }
+    strip_path(&name, strip).map(Some)
+}
+
+/// Find the name in a `diff --git a/<name> b/<name>` line, which is ambiguous
+/// when names contain spaces: both names are the same (or the patch gives
+/// them in other lines).
+fn git_header_name(value: &[u8], strip: usize) -> Result<Option<Vec<u8>>> {
+    if value.starts_with(b"\"") {
+        let mut end = 1;
+        while end < value.len() && value[end] != b'"' {
+            end += if value[end] == b'\\' { 2 } else { 1 };
+        }
+        let old = unquote_c(&value[..(end + 1).min(value.len())])?;
+        return Ok(strip_path(&old, strip).ok());
+    }
+    for (i, _) in value.iter().enumerate().filter(|&(_, &c)| c == b' ') {
+        let old = strip_path(&value[..i], strip);
+        let new = strip_path(&value[i + 1..], strip);
+        if let (Ok(old), Ok(new)) = (old, new) {
+            if old == new {
+                return Ok(Some(old));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Parse a mode in an extended header line.
+fn parse_mode(value: &[u8]) -> Result<Mode> {
+    let value = str::from_utf8(value.trim_ascii())?;
+    Mode::from_u32(u32::from_str_radix(value, 8).with_context(|| format!("bad mode {value}"))?)
+}
+
+/// Parse the line numbers of a hunk header, `@@ -<old>[,<len>] +<new>[,<len>] @@`.
+fn parse_hunk_header(line: &[u8]) -> Option<(usize, usize, usize, usize)> {
+    let line = str::from_utf8(line.strip_prefix(b"@@ -")?).ok()?;
+    let (ranges, _) = line.split_once(" @@")?;
+    let (old, new) = ranges.split_once(" +")?;
+    let range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_len) = range(old)?;
+    let (new_start, new_len) = range(new)?;
+    Some((old_start, old_len, new_start, new_len))
+}
+
+/// Reads patches line by line.
+struct Parser<'a> {
+    lines: Vec<&'a [u8]>,
+    pos: usize,
+    /// Number of leading components to remove from paths
+    strip: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a [u8]> {
+        self.lines.get(self.pos).copied()
+    }
+
+    /// Parse the hunks following a header.
+    fn parse_hunks(&mut self) -> Result<Vec<Hunk>> {
+        let mut hunks = Vec::new();
+        while let Some(line) = self.peek() {
+            let Some((old_start, mut old_left, new_start, mut new_left)) = parse_hunk_header(line)
+            else {
+                break;
+            };
+            self.pos += 1;
+            let mut hunk = Hunk {
+                old_start,
+                new_start,
+                pre: Vec::new(),
+                post: Vec::new(),
+                leading: 0,
+                trailing: 0,
+            };
+            let mut changed = false;
+            let mut last = b' ';
+            while old_left > 0 || new_left > 0 || self.peek().is_some_and(|l| l[0] == b'\\') {
+                let Some(line) = self.peek() else {
+                    bail!("corrupt patch at line {}", self.pos + 1);
+                };
+                // Some tools remove the space of empty context lines.
+                let (kind, text) = match line {
+                    b"\n" => (b' ', line),
+                    _ => (line[0], &line[1..]),
+                };
+                match kind {
+                    b' ' if old_left > 0 && new_left > 0 => {
+                        hunk.pre.push(text.to_vec());
+                        hunk.post.push(text.to_vec());
+                        old_left -= 1;
+                        new_left -= 1;
+                        match changed {
+                            true => hunk.trailing += 1,
+                            false => hunk.leading += 1,
+                        }
+                    }
+                    b'-' if old_left > 0 => {
+                        hunk.pre.push(text.to_vec());
+                        old_left -= 1;
+                        changed = true;
+                        hunk.trailing = 0;
+                    }
+                    b'+' if new_left > 0 => {
+                        hunk.post.push(text.to_vec());
+                        new_left -= 1;
+                        changed = true;
+                        hunk.trailing = 0;
+                    }
+                    // "\ No newline at end of file", about the previous line
+                    b'\\' => {
+                        for (side, kind) in [(&mut hunk.pre, b'-'), (&mut hunk.post, b'+')] {
+                            if last == b' ' || last == kind {
+                                if let Some(line) = side.last_mut() {
+                                    if line.ends_with(b"\n") {
+                                        line.pop();
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    _ => bail!("corrupt patch at line {}", self.pos + 1),
+                }
+                last = kind;
+                self.pos += 1;
+            }
+            hunks.push(hunk);
+        }
+        Ok(hunks)
+    }
+
+    /// Parse one hunk of a binary patch: "literal <size>" or "delta <size>",
+    /// then lines of deflated data in base85, each starting with the number
+    /// of bytes it has ('A' for 1 to 'z' for 52), up to an empty line.
+    fn parse_binary_hunk(&mut self) -> Result<BinaryHunk> {
+        let corrupt = |pos: usize| anyhow!("corrupt binary patch at line {}", pos + 1);
+        let line = self.peek().unwrap_or_default();
+        let (literal, size) = match line.strip_prefix(b"literal ") {
+            Some(size) => (true, size),
+            None => (
+                false,
+                line.strip_prefix(b"delta ").ok_or(corrupt(self.pos))?,
+            ),
+        };
+        let size: usize = str::from_utf8(size.trim_ascii())
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .ok_or(corrupt(self.pos))?;
+        self.pos += 1;
+        let mut deflated = Vec::new();
+        // The empty line after the data may be missing at the end of the input.
+        while let Some(line) = self.peek() {
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            if line.is_empty() {
+                self.pos += 1;
+                break;
+            }
+            let len = match line[0] {
+                c @ b'A'..=b'Z' => c - b'A' + 1,
+                c @ b'a'..=b'z' => c - b'a' + 27,
+                _ => return Err(corrupt(self.pos)),
+            };
+            let data = base85::decode(&line[1..], len.into()).map_err(|_| corrupt(self.pos))?;
+            deflated.extend_from_slice(&data);
+            self.pos += 1;
+        }
+        let mut data = Vec::with_capacity(size);
+        ZlibDecoder::new(&deflated[..])
+            .read_to_end(&mut data)
+            .map_err(|_| corrupt(self.pos - 1))?;
+        if data.len() != size {
+            return Err(corrupt(self.pos - 1));
+        }
+        Ok(match literal {
+            true => BinaryHunk::Literal(data),
+            false => BinaryHunk::Delta(data),
+        })
+    }
+
+    /// Parse a git diff, from the line after `diff --git `.
+    fn parse_git(&mut self, header: &[u8]) -> Result<FilePatch> {
+        let name = git_header_name(header.trim_ascii_end(), self.strip)?;
+        let mut patch = FilePatch {
+            old_path: name.clone(),
+            new_path: name,
+            old_mode: None,
+            new_mode: None,
+            copy: false,
+            hunks: Vec::new(),
+            old_hash: None,
+            new_hash: None,
+            binary: false,
+            binary_hunk: None,
+        };
+        let (mut created, mut deleted) = (false, false);
+        while let Some(line) = self.peek() {
+            let value = |prefix: &str| line.strip_prefix(prefix.as_bytes());
+            let raw_name = |value: &[u8]| unquote_c(value.trim_ascii_end()).map(Some);
+            if let Some(value) = value("old mode ") {
+                patch.old_mode = Some(parse_mode(value)?);
+            } else if let Some(value) = value("new mode ") {
+                patch.new_mode = Some(parse_mode(value)?);
+            } else if let Some(value) = value("new file mode ") {
+                patch.new_mode = Some(parse_mode(value)?);
+                created = true;
+            } else if let Some(value) = value("deleted file mode ") {
+                patch.old_mode = Some(parse_mode(value)?);
+                deleted = true;
+            } else if let Some(value) = value("rename from ") {
+                patch.old_path = raw_name(value)?;
+            } else if let Some(value) = value("rename to ") {
+                patch.new_path = raw_name(value)?;
+            } else if let Some(value) = value("copy from ") {
+                patch.old_path = raw_name(value)?;
+                patch.copy = true;
+            } else if let Some(value) = value("copy to ") {
+                patch.new_path = raw_name(value)?;
+            } else if let Some(value) = value("--- ") {
+                if let Some(name) = file_name(value, self.strip)? {
+                    patch.old_path = Some(name);
+                }
+            } else if let Some(value) = value("+++ ") {
+                if let Some(name) = file_name(value, self.strip)? {
+                    patch.new_path = Some(name);
+                }
+            } else if let Some(value) = value("index ") {
+                let hashes = str::from_utf8(value)?.split(' ').next().unwrap_or_default();
+                if let Some((old, new)) = hashes.trim_ascii_end().split_once("..") {
+                    patch.old_hash = Some(old.to_owned());
+                    patch.new_hash = Some(new.to_owned());
+                }
+            } else if line.starts_with(b"Binary files ") {
+                patch.binary = true;
+            } else if line.starts_with(b"GIT binary patch") {
+                self.pos += 1;
+                patch.binary = true;
+                patch.binary_hunk = Some(self.parse_binary_hunk()?);
+                // The reverse hunk, to undo the patch, is not needed.
+                let next = self.peek().unwrap_or_default();
+                if next.starts_with(b"literal ") || next.starts_with(b"delta ") {
+                    self.parse_binary_hunk()?;
+                }
+                break;
+            } else if !(line.starts_with(b"similarity index ")
+                || line.starts_with(b"dissimilarity index "))
+            {
+                break;
+            }
+            self.pos += 1;
+        }
+        if patch.old_path.is_none() || patch.new_path.is_none() {
+            bail!(
+                "git diff header lacks filename information (line {})",
+                self.pos
+            );
+        }
+        if created {
+            patch.old_path = None;
+        }
+        if deleted {
+            patch.new_path = None;
+        }
+        patch.hunks = self.parse_hunks()?;
+        Ok(patch)
+    }
+
+    /// Parse a traditional diff, from its `---` and `+++` lines.
+    fn parse_traditional(&mut self, old: &[u8], new: &[u8]) -> Result<FilePatch> {
+        let old = file_name(old, self.strip)?;
+        let new = file_name(new, self.strip)?;
+        // Like git, prefer the old name if the new one only adds to it
+        // (eg "file" and "file.new").
+        let path = match (&old, &new) {
+            (Some(old), Some(new)) if new.starts_with(old) => Some(old.clone()),
+            _ => new.clone().or(old.clone()),
+        };
+        Ok(FilePatch {
+            old_path: old.and(path.clone()),
+            new_path: new.and(path),
+            old_mode: None,
+            new_mode: None,
+            copy: false,
+            hunks: self.parse_hunks()?,
+            old_hash: None,
+            new_hash: None,
+            binary: false,
+            binary_hunk: None,
+        })
+    }
+
+    /// Parse all the patches, skipping lines that are not part of any
+    /// (such as the message of a commit).
+    fn parse(mut self) -> Result<Vec<FilePatch>> {
+        let mut patches = Vec::new();
+        while let Some(line) = self.peek() {
+            self.pos += 1;
+            if let Some(header) = line.strip_prefix(b"diff --git ") {
+                patches.push(self.parse_git(header)?);
+            } else if let (Some(old), Some(new)) = (
+                line.strip_prefix(b"--- "),
+                self.peek().and_then(|next| next.strip_prefix(b"+++ ")),
+            ) {
+                self.pos += 1;
+                patches.push(self.parse_traditional(old, new)?);
+            }
+        }
+        Ok(patches)
+    }
+}
+
+/// Tell if the lines of the image at a position are those given.
+fn matches_at(image: &[&[u8]], pos: usize, lines: &[Vec<u8>]) -> bool {
+    image[pos..pos + lines.len()]
+        .iter()
+        .zip(lines)
+        .all(|(a, b)| *a == &b[..])
+}
+
+/// Find where lines are in the image, closest to the given position first.
+/// They may have to be at the beginning or the end of the image.
+fn find_pos(
+    image: &[&[u8]],
+    lines: &[Vec<u8>],
+    hint: usize,
+    at_start: bool,
+    at_end: bool,
+) -> Option<usize> {
+    let last = image.len().checked_sub(lines.len())?;
+    let ok = |pos: usize| {
+        (!at_start || pos == 0) && (!at_end || pos == last) && matches_at(image, pos, lines)
+    };
+    let hint = match (at_start, at_end) {
+        (true, _) => 0,
+        (false, true) => last,
+        (false, false) => hint.min(last),
+    };
+    for distance in 0..=last {
+        let before = hint.checked_sub(distance);
+        let after = Some(hint + distance).filter(|&pos| pos <= last);
+        if before.is_none() && after.is_none() {
+            break;
+        }
+        if let Some(pos) = before.filter(|&pos| ok(pos)) {
+            return Some(pos);
+        }
+        if let Some(pos) = after.filter(|&pos| ok(pos)) {
+            return Some(pos);
+        }
+    }
+    None
+}
+
+/// Apply hunks to the content of a file. Like git, when a hunk doesn't
+/// apply, its context is reduced one line at a time as long as at least
+/// `min_context` lines remain on each side (if given).
+fn apply_hunks(
+    name: &str,
+    content: &[u8],
+    hunks: &[Hunk],
+    min_context: Option<usize>,
+) -> Result<Vec<u8>> {
+    let min_context = min_context.unwrap_or(usize::MAX);
+    let mut image: Vec<&[u8]> = content.split_inclusive(|&c| c == b'\n').collect();
+    for hunk in hunks {
+        let (mut pre, mut post) = (&hunk.pre[..], &hunk.post[..]);
+        let (mut leading, mut trailing) = (hunk.leading, hunk.trailing);
+        // A hunk starting at the first line, or without lines after the
+        // change, must apply there.
+        let mut at_start = hunk.old_start <= 1;
+        let mut at_end = trailing == 0;
+        let hint = hunk.new_start.saturating_sub(1);
+        let pos = loop {
+            if let Some(pos) = find_pos(&image, pre, hint, at_start, at_end) {
+                if leading != hunk.leading || trailing != hunk.trailing {
+                    eprintln!(
+                        "Context reduced to ({leading}/{trailing}) to apply fragment at {}",
+                        pos + 1
+                    );
+                }
+                break pos;
+            }
+            if leading <= min_context && trailing <= min_context {
+                eprintln!("error: patch failed: {name}:{}", hunk.old_start);
+                bail!("{name}: patch does not apply");
+            }
+            if at_start || at_end {
+                (at_start, at_end) = (false, false);
+                continue;
+            }
+            if leading >= trailing {
+                (pre, post) = (&pre[1..], &post[1..]);
+                leading -= 1;
+            }
+            if trailing > leading {
+                (pre, post) = (&pre[..pre.len() - 1], &post[..post.len() - 1]);
+                trailing -= 1;
+            }
+        };
+        image.splice(pos..pos + pre.len(), post.iter().map(|line| &line[..]));
+    }
+    Ok(image.concat())
+}
+
+/// Get the new content of a binary file. Like git, the patch must give the
+/// full names of the old and new objects: the old content is checked, and
+/// the new one must be the result, taken from the object store if the patch
+/// has no data.
+fn apply_binary(
+    repo: &Repository,
+    name: &str,
+    content: &[u8],
+    patch: &FilePatch,
+) -> Result<Vec<u8>> {
+    let (Some(old_hash), Some(new_hash)) = (&patch.old_hash, &patch.new_hash) else {
+        bail!("cannot apply binary patch to '{name}' without full index line");
+    };
+    if !is_hash(repo, old_hash) || !is_hash(repo, new_hash) {
+        bail!("cannot apply binary patch to '{name}' without full index line");
+    }
+    let hash = |data: &[u8]| write_object(repo, ObjType::Blob, &mut io::Cursor::new(data), false);
+    if patch.old_path.is_some() {
+        let hash = hash(content)?;
+        if hash != *old_hash {
+            bail!("the patch applies to '{name}' ({hash}), which does not match the current contents.");
+        }
+    } else if !content.is_empty() {
+        bail!("the patch applies to an empty '{name}' but it is not empty");
+    }
+    if *new_hash == repo.hash_algo()?.zero_hash() {
+        return Ok(Vec::new());
+    }
+    let data = match &patch.binary_hunk {
+        Some(BinaryHunk::Literal(data)) => data.clone(),
+        Some(BinaryHunk::Delta(delta)) => apply_delta(content, delta)
+            .with_context(|| format!("binary patch does not apply to '{name}'"))?,
+        None => read_blob(repo, new_hash)
+            .with_context(|| format!("missing binary patch data for '{name}'"))?,
+    };
+    let hash = hash(&data)?;
+    if hash != *new_hash {
+        bail!(
+            "binary patch to '{name}' creates incorrect result (expecting {new_hash}, got {hash})"
+        );
+    }
+    Ok(data)
+}
+
+/// Where patches apply: the worktree or the index, with the changes made so far
+/// (so that several patches can change the same path).
+struct Target {
+    /// The entries of the index, when applying to it
+    index: Option<Vec<IndexEntry>>,
+    /// New content of changed paths, None for deleted ones
+    changes: Vec<(Vec<u8>, Option<Content>)>,
+}
+
+impl Target {
+    fn changed(&self, path: &[u8]) -> Option<&Option<Content>> {
+        self.changes
+            .iter()
+            .find(|(changed, _)| changed == path)
+            .map(|(_, content)| content)
+    }
+
+    fn set(&mut self, path: &[u8], content: Option<Content>) {
+        match self.changes.iter_mut().find(|(changed, _)| changed == path) {
+            Some((_, old)) => *old = content,
+            None => self.changes.push((path.to_vec(), content)),
+        }
+    }
+
+    /// Read the content of a path, None if there is none.
+    fn read(&self, repo: &Repository, path: &[u8]) -> Result<Option<Content>> {
+        if let Some(content) = self.changed(path) {
+            return Ok(content.clone());
+        }
+        if let Some(index) = &self.index {
+            let Some(entry) = index.iter().find(|e| e.path == path && e.stage == 0) else {
+                return Ok(None);
+            };
+            let data = read_blob(repo, &hex::encode(entry.hash))?;
+            return Ok(Some((entry.mode.clone(), data)));
+        }
+        let file = platform::join(repo.work_tree()?, path);
+        let meta = match fs::symlink_metadata(&file) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("stat {}", file.display())),
+        };
+        let mode = Mode::from_worktree(repo, &meta, None)?;
+        let data = match mode {
+            Mode::SymLink => platform::read_symlink(&file)?,
+            Mode::File | Mode::Exe => {
+                let data =
+                    fs::read(&file).with_context(|| format!("reading {}", file.display()))?;
+                filter::clean(repo, &file, data)?
+            }
+            _ => bail!("{}: not a regular file", file.display()),
+        };
+        Ok(Some((mode, data)))
+    }
+
+    /// Name of the target for messages.
+    fn name(&self) -> &'static str {
+        match self.index {
+            Some(_) => "index",
+            None => "working directory",
+        }
+    }
+
+    /// Apply the changes to one file.
+    fn apply(
+        &mut self,
+        repo: &Repository,
+        patch: &FilePatch,
+        min_context: Option<usize>,
+    ) -> Result<()> {
+        let path = patch.new_path.as_ref().or(patch.old_path.as_ref());
+        let name = String::from_utf8_lossy(path.expect("patches have a path")).into_owned();
+        let old = match &patch.old_path {
+            Some(path) => match self.read(repo, path)? {
+                Some(content) => Some(content),
+                None => {
+                    let path = String::from_utf8_lossy(path);
+                    match self.index {
+                        Some(_) => bail!("{path}: does not exist in index"),
+                        None => bail!("{path}: No such file or directory"),
+                    }
+                }
+            },
+            None => None,
+        };
+        if let Some(path) = &patch.new_path {
+            verify_path(path)?;
+            if patch.old_path.as_ref() != Some(path) && self.read(repo, path)?.is_some() {
+                bail!("{name}: already exists in {}", self.name());
+            }
+        }
+        let data = old.as_ref().map_or(&[][..], |(_, data)| &data[..]);
+        let data = match patch.binary {
+            true => apply_binary(repo, &name, data, patch)?,
+            false => apply_hunks(&name, data, &patch.hunks, min_context)?,
+        };
+        let Some(new_path) = &patch.new_path else {
+            if !data.is_empty() {
+                bail!("{name}: removal patch leaves file contents");
+            }
+            self.set(patch.old_path.as_ref().expect("patches have a path"), None);
+            return Ok(());
+        };
+        let mode = patch
+            .new_mode
+            .clone()
+            .or(old.map(|(mode, _)| mode))
+            .or(patch.old_mode.clone())
+            .unwrap_or(Mode::File);
+        if let Some(old_path) = patch.old_path.as_ref().filter(|p| *p != new_path) {
+            if !patch.copy {
+                self.set(old_path, None);
+            }
+        }
+        self.set(new_path, Some((mode, data)));
+        Ok(())
+    }
+
+    /// Write the changes to the index.
+    fn write_index(self, repo: &Repository, lock: LockFile) -> Result<()> {
+        let mut entries = self.index.expect("applying to the index");
+        entries.retain(|e| !self.changes.iter().any(|(path, _)| *path == e.path));
+        for (path, content) in self.changes {
+            let Some((mode, data)) = content else {
+                continue;
+            };
+            let hash = write_object(repo, ObjType::Blob, &mut io::Cursor::new(data), true)?;
+            entries.push(IndexEntry {
+                path,
+                mode,
+                hash: ObjectId::from_hex(&hash)?,
+                stage: 0,
+                // The worktree doesn't have this content.
+                stat: Some([0; 9]),
+                skip_worktree: false,
+            });
+        }
+        entries.sort_by(|a, b| (&a.path, a.stage).cmp(&(&b.path, b.stage)));
+        index::write(repo, lock, &entries)
+    }
+
+    /// Write the changes to the worktree, deleted files first so that
+    /// files can replace directories.
+    fn write_worktree(self, repo: &Repository) -> Result<()> {
+        let root = repo.work_tree()?;
+        for (path, _) in self.changes.iter().filter(|(_, c)| c.is_none()) {
+            remove_path(root, path)?;
+        }
+        for (path, content) in &self.changes {
+            let Some((mode, data)) = content else {
+                continue;
+            };
+            check_leading_dirs(root, path)?;
+            let file = platform::join(root, path);
+            if let Some(dir) = file.parent() {
+                fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+            }
+            match fs::remove_file(&file) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("removing {}", file.display()))
+                }
+                _ => (),
+            }
+            if *mode == Mode::SymLink {
+                platform::symlink(repo, data, &file)?;
+                continue;
+            }
+            let mut out = platform::create_no_follow(&file)
+                .with_context(|| format!("creating file {}", file.display()))?;
+            let data = filter::smudge(repo, &file, data.clone())?;
+            io::Write::write_all(&mut out, &data)
+                .with_context(|| format!("writing file {}", file.display()))?;
+            if *mode == Mode::Exe {
+                platform::set_executable(&out, &file)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The "apply" command: apply patches read from files (or standard input
+/// if there are none, or for "-") to the worktree, or to the index only if
+/// `cached`. Only check that they apply if `check`.
+///
+/// `strip` leading components are removed from the paths in the patches
+/// (1 in git, for the `a/` and `b/` prefixes). By default, all the context
+/// of a hunk must match; with `min_context`, it is reduced down to that many
+/// lines on each side if needed (fuzz).
+pub fn apply(
+    repo: &Repository,
+    patches: &[PathBuf],
+    cached: bool,
+    check: bool,
+    strip: usize,
+    min_context: Option<usize>,
+) -> Result<()> {
+    apply_patches(
+        repo,
+        &read_inputs(patches)?,
+        cached,
+        check,
+        strip,
+        min_context,
+    )
+}
+
+/// Apply patches like apply(), from their text.
+pub fn apply_patches(
+    repo: &Repository,
+    input: &[u8],
+    cached: bool,
+    check: bool,
+    strip: usize,
+    min_context: Option<usize>,
+) -> Result<()> {
+    let parser = Parser {
+        lines: input.split_inclusive(|&c| c == b'\n').collect(),
+        pos: 0,
+        strip,
+    };
+    let patches = parser.parse()?;
+    if patches.is_empty() {
+        bail!("no valid patches in input");
+    }
+
+    let lock = match cached && !check {
+        true => Some(lock_worktree(repo)?),
+        false => None,
+    };
+    let mut target = Target {
+        index: if cached {
+            Some(index::read(repo)?)
+        } else {
+            None
+        },
+        changes: Vec::new(),
+    };
+    for patch in &patches {
+        target.apply(repo, patch, min_context)?;
+    }
+    match (check, lock) {
+        (true, _) => Ok(()),
+        (false, Some(lock)) => target.write_index(repo, lock),
+        (false, None) => target.write_worktree(repo),
+    }
+}
@@ -0,0 +1,670 @@
+//! Writing a tree as a tar archive, possibly gzipped, or a zip archive,
+//! like git archive.
+//!
+//! The tar output is the same as git's, byte for byte: ustar headers with
+//! owner root, mode bits after applying git's default tar.umask (002),
+//! the committer date as modification time, and pax headers for the commit
+//! hash (global header) and for names which don't fit in ustar headers.
+//! See archive-tar.c in git's sources, tar(5) and pax(1) for the format.
+//!
+//! The zip output has the same entries and attributes as git's (see
+//! archive-zip.c and PKWARE's APPNOTE.TXT), but the compressed data may
+//! differ, and dates are in UTC rather than local time.
+
+use anyhow::{bail, Context, Result};
+use flate2::{
+    write::{DeflateEncoder, GzEncoder},
+    Compression,
+};
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::attributes::{State, TreeAttributes};
+use crate::date;
+use crate::interrupt;
+use crate::obj_read::{read_blob, read_commit, read_tree};
+use crate::obj_type::ObjType;
+use crate::repository::Repository;
+use crate::revision::resolve;
+use crate::revwalk::peel;
+use crate::tree_entry::Mode;
+
+/// Size of tar blocks: headers and content are padded to a multiple of this.
+const BLOCK_SIZE: usize = 512;
+
+/// Size of the records git writes, the archive is padded to a multiple of this.
+const RECORD_SIZE: usize = 20 * BLOCK_SIZE;
+
+/// Permission bits git removes by default (tar.umask).
+const UMASK: u32 = 0o002;
+
+/// Archive formats.
+#[derive(Clone, Copy)]
+pub enum Format {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl Format {
+    /// Get a format from its name, as given to --format.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "tar" => Ok(Format::Tar),
+            "tgz" | "tar.gz" => Ok(Format::TarGz),
+            "zip" => Ok(Format::Zip),
+            _ => bail!("unknown archive format {name:?}"),
+        }
+    }
+
+    /// Guess the format from the name of the output file, like git.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        if name.ends_with(".tgz") || name.ends_with(".tar.gz") {
+            Some(Format::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else {
+            None
+        }
+    }
+
+    /// The name of the format, as accepted by from_name().
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Tar => "tar",
+            Format::TarGz => "tgz",
+            Format::Zip => "zip",
+        }
+    }
+}
+
+/// Tar entry types (typeflag field).
+const TYPE_FILE: u8 = b'0';
+const TYPE_SYMLINK: u8 = b'2';
+const TYPE_DIR: u8 = b'5';
+const TYPE_PAX_GLOBAL: u8 = b'g';
+const TYPE_PAX: u8 = b'x';
+
+/// Copy a string into a header field, truncating it if needed.
+fn set_field(field: &mut [u8], value: &[u8]) {
+    let len = value.len().min(field.len());
+    field[..len].copy_from_slice(&value[..len]);
+}
+
+/// Write a number in octal into a header field, with a final NUL like git.
+fn set_octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    set_field(field, format!("{value:0width$o}\0").as_bytes());
+}
+
+/// Append a pax extended header record, `<len> <keyword>=<value>\n`,
+/// the length including itself.
+fn pax_record(records: &mut Vec<u8>, keyword: &str, value: &[u8]) {
+    let rest = 1 + keyword.len() + 1 + value.len() + 1;
+    let mut len = rest + 1;
+    while len.to_string().len() + rest > len {
+        len += 1;
+    }
+    records.extend_from_slice(format!("{len} {keyword}=").as_bytes());
+    records.extend_from_slice(value);
+    records.push(b'\n');
+}
+
+/// Length of the part of a path to put in the prefix field of a ustar header:
+/// up to the last slash (excluding a final one) that fits, 0 if none does.
+fn path_prefix_len(path: &[u8], max_len: usize) -> usize {
+    let mut i = path.len();
+    if i > 1 && path[i - 1] == b'/' {
+        i -= 1;
+    }
+    i = i.min(max_len);
+    loop {
+        i = i.saturating_sub(1);
+        if i == 0 || path[i] == b'/' {
+            return i;
+        }
+    }
+}
+
+/// The writer of an archive format, adding entries one by one.
+/// Paths are full paths in the archive, and hashes those of the objects.
+trait EntryWriter {
+    /// Add a directory; the path ends with a slash.
+    fn add_dir(&mut self, path: &[u8], hash: &str) -> Result<()>;
+
+    /// Add a symbolic link to the given target.
+    fn add_symlink(&mut self, path: &[u8], hash: &str, target: &[u8]) -> Result<()>;
+
+    /// Add a file of the given size, with content read from a blob.
+    fn add_file(
+        &mut self,
+        path: &[u8],
+        hash: &str,
+        executable: bool,
+        blob: impl Read,
+        size: usize,
+    ) -> Result<()>;
+}
+
+/// Walks a tree to give its entries to an archive writer.
+struct TreeWalker {
+    /// Attributes of the entries, for export-ignore
+    attributes: TreeAttributes,
+    /// Length of the prefix of all paths, to get paths relative to the root of the tree
+    prefix_len: usize,
+}
+
+impl TreeWalker {
+    /// Add the entries of a tree, recursively, with paths starting with `path`.
+    /// Like git, entries with the export-ignore attribute are left out.
+    fn add_tree(
+        &mut self,
+        repo: &Repository,
+        tree: &str,
+        path: &mut Vec<u8>,
+        out: &mut impl EntryWriter,
+    ) -> Result<()> {
+        let entries = read_tree(repo, tree)?;
+        let mark = self
+            .attributes
+            .enter_tree(repo, &entries, &path[self.prefix_len..])?;
+        for entry in entries {
+            interrupt::check()?;
+            let hash = hex::encode(entry.hash);
+            let len = path.len();
+            path.extend_from_slice(&entry.name);
+            let rel = &path[self.prefix_len..];
+            if self.attributes.get(rel, "export-ignore") == Some(State::Set) {
+                path.truncate(len);
+                continue;
+            }
+            match entry.mode {
+                // Like git, submodules are archived as empty directories.
+                Mode::Dir | Mode::SubMod => {
+                    path.push(b'/');
+                    out.add_dir(path, &hash)?;
+                    if entry.mode == Mode::Dir {
+                        self.add_tree(repo, &hash, path, out)?;
+                    }
+                }
+                Mode::SymLink => {
+                    let target = read_blob(repo, &hash)?;
+                    out.add_symlink(path, &hash, &target)?;
+                }
+                Mode::File | Mode::Exe => {
+                    let blob = repo
+                        .read_object(&hash)
+                        .with_context(|| format!("opening blob {hash}"))?;
+                    let size = blob.size;
+                    out.add_file(path, &hash, entry.mode == Mode::Exe, blob, size)?;
+                }
+            }
+            path.truncate(len);
+        }
+        self.attributes.leave_tree(mark);
+        Ok(())
+    }
+
+    /// Add all the entries of an archive of a tree, with paths starting with
+    /// `prefix`, including a directory for the prefix if it ends with a slash.
+    fn add_all(
+        repo: &Repository,
+        tree: &str,
+        prefix: &str,
+        out: &mut impl EntryWriter,
+    ) -> Result<()> {
+        let mut walker = TreeWalker {
+            attributes: TreeAttributes::new(repo)?,
+            prefix_len: prefix.len(),
+        };
+        let mut path = prefix.as_bytes().to_vec();
+        if prefix.ends_with('/') {
+            out.add_dir(&path, tree)?;
+        }
+        walker.add_tree(repo, tree, &mut path, out)
+    }
+}
+
+/// Writes a tar archive entry by entry.
+struct TarWriter<W: Write> {
+    out: W,
+    /// Modification time of all entries, in seconds since the epoch
+    mtime: u64,
+    /// Number of bytes written so far
+    written: usize,
+}
+
+impl<W: Write> TarWriter<W> {
+    /// Account for `len` bytes of content just written,
+    /// and write zeroes up to the end of the block.
+    fn end_content(&mut self, len: usize) -> Result<()> {
+        let padding = (BLOCK_SIZE - len % BLOCK_SIZE) % BLOCK_SIZE;
+        self.out.write_all(&[0; BLOCK_SIZE][..padding])?;
+        self.written += len + padding;
+        Ok(())
+    }
+
+    /// Write some data, then zeroes up to the end of the block.
+    fn write_padded(&mut self, data: &[u8]) -> Result<()> {
+        self.out.write_all(data)?;
+        self.end_content(data.len())
+    }
+
+    /// Write a ustar header. Name and prefix must fit in their fields.
+    fn write_header(
+        &mut self,
+        (prefix, name): (&[u8], &[u8]),
+        typeflag: u8,
+        mode: u32,
+        size: u64,
+        linkname: &[u8],
+    ) -> Result<()> {
+        let mut header = [0; BLOCK_SIZE];
+        set_field(&mut header[0..100], name);
+        set_octal(&mut header[100..108], (mode & 0o7777).into());
+        set_octal(&mut header[108..116], 0);
+        set_octal(&mut header[116..124], 0);
+        set_octal(&mut header[124..136], size);
+        set_octal(&mut header[136..148], self.mtime);
+        header[156] = typeflag;
+        set_field(&mut header[157..257], linkname);
+        set_field(&mut header[257..263], b"ustar\0");
+        set_field(&mut header[263..265], b"00");
+        set_field(&mut header[265..297], b"root");
+        set_field(&mut header[297..329], b"root");
+        set_octal(&mut header[329..337], 0);
+        set_octal(&mut header[337..345], 0);
+        set_field(&mut header[345..500], prefix);
+        // The checksum is computed with spaces in its own field.
+        header[148..156].fill(b' ');
+        let checksum = header.iter().map(|&b| u64::from(b)).sum();
+        set_octal(&mut header[148..156], checksum);
+        self.write_padded(&header)
+    }
+
+    /// Write a pax header with the given records, followed by its content.
+    fn write_pax(&mut self, name: &[u8], typeflag: u8, records: &[u8]) -> Result<()> {
+        let size = records.len() as u64;
+        self.write_header((b"", name), typeflag, 0o666, size, b"")?;
+        self.write_padded(records)
+    }
+
+    /// Write the header of an entry, preceded by a pax header if its path
+    /// or link target is too long. The hash of the object is used to name
+    /// things in that case, like git.
+    fn write_entry_header(
+        &mut self,
+        path: &[u8],
+        hash: &str,
+        typeflag: u8,
+        mode: u32,
+        size: u64,
+        linkname: &[u8],
+    ) -> Result<()> {
+        let mut records = Vec::new();
+        let placeholder;
+        let (mut prefix, mut name) = (&b""[..], path);
+        if path.len() > 100 {
+            let prefix_len = path_prefix_len(path, 155);
+            if prefix_len > 0 && path.len() - prefix_len - 1 <= 100 {
+                (prefix, name) = (&path[..prefix_len], &path[prefix_len + 1..]);
+            } else {
+                placeholder = format!("{hash}.data");
+                name = placeholder.as_bytes();
+                pax_record(&mut records, "path", path);
+            }
+        }
+        let link_placeholder;
+        let mut linkname = linkname;
+        if linkname.len() > 100 {
+            pax_record(&mut records, "linkpath", linkname);
+            link_placeholder = format!("see {hash}.paxheader");
+            linkname = link_placeholder.as_bytes();
+        }
+        if !records.is_empty() {
+            let pax_name = format!("{hash}.paxheader");
+            self.write_pax(pax_name.as_bytes(), TYPE_PAX, &records)?;
+        }
+        self.write_header((prefix, name), typeflag, mode, size, linkname)
+    }
+
+    /// Pad the archive with zeroes like git: to the end of the current record,
+    /// plus a whole record if that's not enough for the two final empty blocks.
+    fn finish(mut self) -> Result<W> {
+        let padding = RECORD_SIZE - self.written % RECORD_SIZE;
+        self.out.write_all(&vec![0; padding])?;
+        if padding < 2 * BLOCK_SIZE {
+            self.out.write_all(&[0; RECORD_SIZE])?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> EntryWriter for TarWriter<W> {
+    fn add_dir(&mut self, path: &[u8], hash: &str) -> Result<()> {
+        self.write_entry_header(path, hash, TYPE_DIR, 0o777 & !UMASK, 0, b"")
+    }
+
+    fn add_symlink(&mut self, path: &[u8], hash: &str, target: &[u8]) -> Result<()> {
+        self.write_entry_header(path, hash, TYPE_SYMLINK, 0o777, 0, target)
+    }
+
+    fn add_file(
+        &mut self,
+        path: &[u8],
+        hash: &str,
+        executable: bool,
+        mut blob: impl Read,
+        size: usize,
+    ) -> Result<()> {
+        let mode = if executable { 0o777 } else { 0o666 };
+        self.write_entry_header(path, hash, TYPE_FILE, mode & !UMASK, size as u64, b"")?;
+        let copied =
+            io::copy(&mut blob, &mut self.out).with_context(|| format!("archiving blob {hash}"))?;
+        if copied != size as u64 {
+            bail!("blob {hash}: size mismatch: announced {size}, got {copied}");
+        }
+        self.end_content(size)
+    }
+}
+
+/// Write a tar archive of a tree, with paths starting with `prefix`.
+/// `mtime` is used for all entries, and the commit, if any, is recorded
+/// in a pax global header.
+fn write_tar<W: Write>(
+    repo: &Repository,
+    out: W,
+    tree: &str,
+    commit: Option<&str>,
+    mtime: i64,
+    prefix: &str,
+) -> Result<W> {
+    let mut tar = TarWriter {
+        out,
+        mtime: mtime.max(0) as u64,
+        written: 0,
+    };
+    if let Some(commit) = commit {
+        let mut records = Vec::new();
+        pax_record(&mut records, "comment", commit.as_bytes());
+        tar.write_pax(b"pax_global_header", TYPE_PAX_GLOBAL, &records)?;
+    }
+    TreeWalker::add_all(repo, tree, prefix, &mut tar)?;
+    tar.finish()
+}
+
+/// Zip record signatures.
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4b50;
+const ZIP_DIR_HEADER: u32 = 0x0201_4b50;
+const ZIP_END: u32 = 0x0605_4b50;
+
+/// Version needed to extract entries (1.0), as written by git.
+const ZIP_VERSION: u16 = 10;
+
+/// "Version made by" of entries with Unix permissions: Unix, version 2.3.
+const ZIP_CREATOR_UNIX: u16 = 0x0317;
+
+/// Compression methods.
+const ZIP_STORED: u16 = 0;
+const ZIP_DEFLATED: u16 = 8;
+
+/// Flag telling that the path of an entry is in UTF-8.
+const ZIP_UTF8: u16 = 0x0800;
+
+/// MS-DOS attribute of directories.
+const ZIP_DOS_DIR: u32 = 0x10;
+
+/// Internal attribute of text files.
+const ZIP_TEXT: u16 = 1;
+
+/// Number of bytes looked at to tell if a file is binary, like git.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// The attributes of a zip entry, which depend on its type.
+struct ZipAttributes {
+    /// "Version made by": tells how to read the external attributes
+    creator: u16,
+    /// Internal attributes (text or binary)
+    internal: u16,
+    /// External attributes: Unix mode in the high bits, or MS-DOS attributes
+    external: u32,
+}
+
+/// Writes a zip archive entry by entry: a local header followed by the
+/// content for each, then a central directory repeating all headers.
+struct ZipWriter<W: Write> {
+    out: W,
+    /// Modification time of all entries, in seconds since the epoch
+    mtime: u32,
+    /// The same time in MS-DOS format, as (date, time)
+    dos_mtime: (u16, u16),
+    /// Number of bytes written so far, the offset of the next entry
+    written: u64,
+    /// The central directory, written at the end
+    directory: Vec<u8>,
+    /// Number of entries written
+    entries: usize,
+}
+
+impl<W: Write> ZipWriter<W> {
+    /// Write an entry, compressing its content if asked and if that makes it smaller,
+    /// and record it in the central directory.
+    fn add_entry(
+        &mut self,
+        path: &[u8],
+        attributes: ZipAttributes,
+        content: &[u8],
+        compress: bool,
+    ) -> Result<()> {
+        let Ok(path_len) = u16::try_from(path.len()) else {
+            bail!("path too long for a zip archive: {}", path.escape_ascii());
+        };
+        let Ok(offset) = u32::try_from(self.written) else {
+            bail!("archive too large for the zip format");
+        };
+        let deflated;
+        let (method, data) = match compress && !content.is_empty() {
+            true => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(content)?;
+                deflated = encoder.finish()?;
+                match deflated.len() < content.len() {
+                    true => (ZIP_DEFLATED, &deflated[..]),
+                    false => (ZIP_STORED, content),
+                }
+            }
+            false => (ZIP_STORED, content),
+        };
+        let flags = match !path.is_ascii() && std::str::from_utf8(path).is_ok() {
+            true => ZIP_UTF8,
+            false => 0,
+        };
+        // Extended timestamp: modification time only.
+        let mut extra = vec![0x55, 0x54, 5, 0, 1];
+        extra.extend_from_slice(&self.mtime.to_le_bytes());
+
+        // Fields common to the local and central headers, after the versions.
+        let mut common = Vec::new();
+        common.extend_from_slice(&flags.to_le_bytes());
+        common.extend_from_slice(&method.to_le_bytes());
+        common.extend_from_slice(&self.dos_mtime.1.to_le_bytes());
+        common.extend_from_slice(&self.dos_mtime.0.to_le_bytes());
+        common.extend_from_slice(&crc32fast::hash(content).to_le_bytes());
+        common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        common.extend_from_slice(&path_len.to_le_bytes());
+        common.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&ZIP_LOCAL_HEADER.to_le_bytes());
+        header.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+        header.extend_from_slice(&common);
+        header.extend_from_slice(path);
+        header.extend_from_slice(&extra);
+        self.out.write_all(&header)?;
+        self.out.write_all(data)?;
+        self.written += (header.len() + data.len()) as u64;
+
+        let dir = &mut self.directory;
+        dir.extend_from_slice(&ZIP_DIR_HEADER.to_le_bytes());
+        dir.extend_from_slice(&attributes.creator.to_le_bytes());
+        dir.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+        dir.extend_from_slice(&common);
+        // No comment, on the first disk.
+        dir.extend_from_slice(&[0; 4]);
+        dir.extend_from_slice(&attributes.internal.to_le_bytes());
+        dir.extend_from_slice(&attributes.external.to_le_bytes());
+        dir.extend_from_slice(&offset.to_le_bytes());
+        dir.extend_from_slice(path);
+        dir.extend_from_slice(&extra);
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Write the central directory and its end record, with the commit,
+    /// if any, as comment like git.
+    fn finish(mut self, commit: Option<&str>) -> Result<W> {
+        let (Ok(entries), Ok(offset)) = (u16::try_from(self.entries), u32::try_from(self.written))
+        else {
+            bail!("archive too large for the zip format");
+        };
+        let comment = commit.unwrap_or("").as_bytes();
+        let mut end = Vec::new();
+        end.extend_from_slice(&ZIP_END.to_le_bytes());
+        // Everything is on the first disk.
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&(self.directory.len() as u32).to_le_bytes());
+        end.extend_from_slice(&offset.to_le_bytes());
+        end.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+        end.extend_from_slice(comment);
+        self.out.write_all(&self.directory)?;
+        self.out.write_all(&end)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Attributes of a file or symbolic link with the given Unix mode, like git:
+/// the mode is only recorded if it's not the default (executable, link),
+/// and the text flag is set unless the content has NUL bytes.
+fn zip_attributes(mode: Option<u32>, content: &[u8]) -> ZipAttributes {
+    let binary = content[..content.len().min(BINARY_CHECK_LEN)].contains(&0);
+    ZipAttributes {
+        creator: mode.map_or(0, |_| ZIP_CREATOR_UNIX),
+        internal: if binary { 0 } else { ZIP_TEXT },
+        external: mode.map_or(0, |mode| mode << 16),
+    }
+}
+
+impl<W: Write> EntryWriter for ZipWriter<W> {
+    fn add_dir(&mut self, path: &[u8], _hash: &str) -> Result<()> {
+        let attributes = ZipAttributes {
+            creator: 0,
+            internal: 0,
+            external: ZIP_DOS_DIR,
+        };
+        self.add_entry(path, attributes, b"", false)
+    }
+
+    fn add_symlink(&mut self, path: &[u8], _hash: &str, target: &[u8]) -> Result<()> {
+        let attributes = zip_attributes(Some(0o120777), target);
+        self.add_entry(path, attributes, target, false)
+    }
+
+    fn add_file(
+        &mut self,
+        path: &[u8],
+        hash: &str,
+        executable: bool,
+        mut blob: impl Read,
+        size: usize,
+    ) -> Result<()> {
+        let mut content = Vec::with_capacity(size);
+        blob.read_to_end(&mut content)
+            .with_context(|| format!("archiving blob {hash}"))?;
+        if content.len() != size {
+            bail!(
+                "blob {hash}: size mismatch: announced {size}, got {}",
+                content.len()
+            );
+        }
+        let attributes = zip_attributes(executable.then_some(0o100755), &content);
+        self.add_entry(path, attributes, &content, true)
+    }
+}
+
+/// Write a zip archive of a tree, with paths starting with `prefix`.
+/// `mtime` is used for all entries, and the commit, if any, is recorded
+/// as the comment of the archive.
+fn write_zip<W: Write>(
+    repo: &Repository,
+    out: W,
+    tree: &str,
+    commit: Option<&str>,
+    mtime: i64,
+    prefix: &str,
+) -> Result<W> {
+    let mut zip = ZipWriter {
+        out,
+        mtime: mtime.clamp(0, u32::MAX.into()) as u32,
+        dos_mtime: date::dos_date_time(mtime),
+        written: 0,
+        directory: Vec::new(),
+        entries: 0,
+    };
+    TreeWalker::add_all(repo, tree, prefix, &mut zip)?;
+    zip.finish(commit)
+}
+
+/// Find what to archive for a tree-ish: the tree, the commit if it is one,
+/// and the modification time of entries. Like git, that is the committer
+/// date for commits, and now for trees.
+pub fn resolve_tree_ish(
+    repo: &Repository,
+    tree_ish: &str,
+) -> Result<(String, Option<String>, i64)> {
+    let hash = resolve(repo, tree_ish)?;
+    match peel(repo, &hash)? {
+        Some((commit, ObjType::Commit)) => {
+            let parsed = read_commit(repo, &commit)?;
+            Ok((parsed.tree, Some(commit), parsed.time))
+        }
+        Some((tree, ObjType::Tree)) => Ok((tree, None, date::now())),
+        Some(_) => bail!("{tree_ish} is neither a commit nor a tree"),
+        None => bail!("{hash}: object not found"),
+    }
+}
+
+/// Write an archive of a tree in the given format, see write_tar() and write_zip().
+pub fn write_archive<W: Write>(
+    repo: &Repository,
+    out: W,
+    format: Format,
+    tree: &str,
+    commit: Option<&str>,
+    mtime: i64,
+    prefix: &str,
+) -> Result<()> {
+    match format {
+        Format::Tar => {
+            write_tar(repo, out, tree, commit, mtime, prefix)?;
+        }
+        Format::TarGz => {
+            let gz = GzEncoder::new(out, Compression::default());
+            write_tar(repo, gz, tree, commit, mtime, prefix)?
+                .finish()
+                .context("finishing gzip stream")?;
+        }
+        Format::Zip => {
+            write_zip(repo, out, tree, commit, mtime, prefix)?;
+        }
+    }
+    Ok(())
+}
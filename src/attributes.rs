@@ -0,0 +1,229 @@
+//! Attributes of paths, from .gitattributes files and .git/info/attributes.
+//!
+//! See gitattributes(5). Patterns are matched like in .gitignore files, but
+//! can't be negated and don't match directories. The only macro is the
+//! built-in `binary`; quoted patterns and `[attr]` definitions are not
+//! supported.
+//!
+//! Attributes of files in the worktree are looked up with get(), which reads
+//! rules once per directory: checking out a .gitattributes file should be
+//! followed by a call to invalidate(), so that files checked out after it use
+//! its rules. Attributes of entries of a tree (eg for archive) come from the
+//! .gitattributes files of the tree, see TreeAttributes.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::ignore::glob;
+use crate::obj_read::read_blob;
+use crate::platform;
+use crate::repository::Repository;
+use crate::tree_entry::{Entry, Mode};
+
+/// Rules already read, by directory relative to the root of the worktree
+/// with a trailing '/' (empty for the root, and None for .git/info/attributes),
+/// kept in the repository.
+pub(crate) type Cache = HashMap<Option<Vec<u8>>, Arc<Vec<Rule>>>;
+
+/// The state of an attribute for a path.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum State {
+    /// Set, eg `text`
+    Set,
+    /// Unset, eg `-text`
+    Unset,
+    /// Set to a value, eg `eol=crlf`
+    Value(String),
+}
+
+/// One line of an attributes file.
+pub(crate) struct Rule {
+    /// Directory of the file the rule comes from, as in Cache
+    base: Vec<u8>,
+    pattern: Vec<u8>,
+    /// Pattern containing a '/': matches the full path relative to base,
+    /// rather than just the name of the file
+    anchored: bool,
+    /// Attributes with their state, None for `!name` (unspecified)
+    attributes: Vec<(String, Option<State>)>,
+}
+
+impl Rule {
+    /// Parse a line from an attributes file, returning None for blank lines,
+    /// comments and patterns which can't match files.
+    fn parse(line: &[u8], base: &[u8]) -> Option<Self> {
+        let line = String::from_utf8_lossy(line);
+        let mut fields = line.split_ascii_whitespace();
+        let pattern = fields.next()?;
+        if pattern.starts_with('#') || pattern.starts_with('!') || pattern.ends_with('/') {
+            return None;
+        }
+        let mut attributes = Vec::new();
+        for field in fields {
+            let (name, state) = if let Some(name) = field.strip_prefix('-') {
+                (name, Some(State::Unset))
+            } else if let Some(name) = field.strip_prefix('!') {
+                (name, None)
+            } else if let Some((name, value)) = field.split_once('=') {
+                (name, Some(State::Value(value.to_owned())))
+            } else {
+                (field, Some(State::Set))
+            };
+            if name == "binary" && state == Some(State::Set) {
+                for name in ["diff", "merge", "text"] {
+                    attributes.push((name.to_owned(), Some(State::Unset)));
+                }
+            }
+            attributes.push((name.to_owned(), state));
+        }
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        Some(Rule {
+            base: base.to_vec(),
+            pattern: pattern.as_bytes().to_vec(),
+            anchored,
+            attributes,
+        })
+    }
+
+    /// Tell if the pattern matches a path relative to the root of the worktree.
+    fn matches(&self, path: &[u8]) -> bool {
+        let Some(path) = path.strip_prefix(&self.base[..]) else {
+            return false;
+        };
+        if self.anchored {
+            glob(&self.pattern, path)
+        } else {
+            let name = path.rsplit(|&c| c == b'/').next().unwrap_or(path);
+            glob(&self.pattern, name)
+        }
+    }
+}
+
+/// Parse the rules of an attributes file.
+fn parse(content: &[u8], base: &[u8]) -> Vec<Rule> {
+    content
+        .split(|&c| c == b'\n')
+        .filter_map(|line| Rule::parse(line, base))
+        .collect()
+}
+
+/// Read the rules of an attributes file if it exists.
+fn load(file: &Path, base: &[u8]) -> Result<Vec<Rule>> {
+    match fs::read(file) {
+        Ok(content) => Ok(parse(&content, base)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("reading {}", file.display())),
+    }
+}
+
+/// Find the state of an attribute for a path in rules by increasing
+/// precedence: the last matching rule setting it (or unspecifying it) decides.
+fn lookup<'a>(rules: impl Iterator<Item = &'a Rule>, path: &[u8], name: &str) -> Option<State> {
+    let mut state = None;
+    for rule in rules.filter(|rule| rule.matches(path)) {
+        for (attribute, value) in &rule.attributes {
+            if attribute == name {
+                state = value.clone();
+            }
+        }
+    }
+    state
+}
+
+/// Get the rules of a directory (see Cache), reading them if needed.
+fn rules(repo: &Repository, root: &Path, dir: Option<&[u8]>) -> Result<Arc<Vec<Rule>>> {
+    let key = dir.map(<[u8]>::to_vec);
+    if let Some(rules) = repo.cache().attributes.lock().unwrap().get(&key) {
+        return Ok(rules.clone());
+    }
+    let rules = Arc::new(match dir {
+        Some(dir) => load(&platform::join(root, dir).join(".gitattributes"), dir)?,
+        None => load(&repo.common_dir().join("info/attributes"), b"")?,
+    });
+    let mut cache = repo.cache().attributes.lock().unwrap();
+    cache.insert(key, rules.clone());
+    Ok(rules)
+}
+
+/// Forget the rules read so far, after .gitattributes files changed.
+pub fn invalidate(repo: &Repository) {
+    repo.cache().attributes.lock().unwrap().clear();
+}
+
+/// Get the state of an attribute for a path relative to the root of the
+/// worktree, None if it is unspecified. Rules in deeper directories take
+/// precedence, and .git/info/attributes over all of them; within a file,
+/// the last matching line does. There are no attributes in bare repositories.
+pub fn get(repo: &Repository, path: &[u8], name: &str) -> Result<Option<State>> {
+    if repo.is_bare() {
+        return Ok(None);
+    }
+    let root = repo.work_tree()?;
+    let mut dirs = vec![Some(&b""[..])];
+    let slashes = path.iter().enumerate().filter(|&(_, &c)| c == b'/');
+    dirs.extend(slashes.map(|(end, _)| Some(&path[..=end])));
+    dirs.push(None);
+    let rules = dirs
+        .into_iter()
+        .map(|dir| rules(repo, root, dir))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(lookup(
+        rules.iter().flat_map(|rules| rules.iter()),
+        path,
+        name,
+    ))
+}
+
+/// The attributes that apply at some point while walking a tree: rules from
+/// the .gitattributes files of the directories entered so far, and from
+/// .git/info/attributes (which take precedence).
+pub struct TreeAttributes {
+    rules: Vec<Rule>,
+    info: Vec<Rule>,
+}
+
+impl TreeAttributes {
+    /// Start with the rules from .git/info/attributes.
+    pub fn new(repo: &Repository) -> Result<Self> {
+        Ok(TreeAttributes {
+            rules: Vec::new(),
+            info: load(&repo.common_dir().join("info/attributes"), b"")?,
+        })
+    }
+
+    /// Add the rules from the .gitattributes file in the given tree, `rel`
+    /// being its path relative to the root with a trailing '/' (empty for
+    /// the root). Return a mark to pass to leave_tree() when done with it.
+    pub fn enter_tree(
+        &mut self,
+        repo: &Repository,
+        entries: &[Entry],
+        rel: &[u8],
+    ) -> Result<usize> {
+        let mark = self.rules.len();
+        let file = entries
+            .iter()
+            .find(|entry| entry.name == b".gitattributes" && entry.mode == Mode::File);
+        if let Some(file) = file {
+            let content = read_blob(repo, &hex::encode(file.hash))?;
+            self.rules.extend(parse(&content, rel));
+        }
+        Ok(mark)
+    }
+
+    /// Drop the rules added by the matching enter_tree().
+    pub fn leave_tree(&mut self, mark: usize) {
+        self.rules.truncate(mark);
+    }
+
+    /// Get the state of an attribute for a path relative to the root of the
+    /// tree, None if it is unspecified.
+    pub fn get(&self, path: &[u8], name: &str) -> Option<State> {
+        lookup(self.rules.iter().chain(&self.info), path, name)
+    }
+}
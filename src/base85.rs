@@ -0,0 +1,48 @@
+//! The base85 encoding of binary patches, like git's (see base85.c): each
+//! group of 4 bytes, big-endian, is written as 5 characters.
+
+use anyhow::{bail, Result};
+
+/// The characters for the digits, from 0 to 84.
+const DIGITS: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// Encode data, the last group being padded with zeros.
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len().div_ceil(4) * 5);
+    for group in data.chunks(4) {
+        let mut bytes = [0; 4];
+        bytes[..group.len()].copy_from_slice(group);
+        let mut value = u32::from_be_bytes(bytes);
+        let mut digits = [0; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = DIGITS[(value % 85) as usize];
+            value /= 85;
+        }
+        out.extend_from_slice(&digits);
+    }
+    out
+}
+
+/// Decode `len` bytes from encoded text, which must have just enough groups.
+pub fn decode(text: &[u8], len: usize) -> Result<Vec<u8>> {
+    if text.len() != len.div_ceil(4) * 5 {
+        bail!("invalid base85 length");
+    }
+    let mut out = Vec::with_capacity(len);
+    for group in text.chunks(5) {
+        let mut value: u64 = 0;
+        for &c in group {
+            let Some(digit) = DIGITS.iter().position(|&d| d == c) else {
+                bail!("invalid base85 alphabet {}", c as char);
+            };
+            value = value * 85 + digit as u64;
+        }
+        let Ok(value) = u32::try_from(value) else {
+            bail!("invalid base85 sequence");
+        };
+        let bytes = value.to_be_bytes();
+        out.extend_from_slice(&bytes[..(len - out.len()).min(4)]);
+    }
+    Ok(out)
+}
@@ -0,0 +1,417 @@
+//! Reachability bitmaps: for some commits of a pack, the set of objects
+//! reachable from them, so that the objects to send or keep can be found
+//! without walking all of history.
+//!
+//! See gitformat-pack(5) and git's Documentation/technical/bitmap-format.txt.
+//! Bit `i` stands for the `i`-th object of the pack in pack order (by offset),
+//! and bitmaps are compressed with EWAH. Only single-pack bitmaps of version 1
+//! are supported: the name-hash cache and lookup table extensions are ignored
+//! when reading, and not written. Written bitmaps don't use XOR compression.
+//!
+//! Walks stop at commits with a bitmap, while objects outside of the pack
+//! (eg loose objects created since the last gc) are walked as usual.
+
+use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::slice;
+
+use crate::commit::Commit;
+use crate::common::write_error;
+use crate::config;
+use crate::fetch::have_object;
+use crate::obj_read::read_tree;
+use crate::obj_type::ObjType;
+use crate::object_id::Hasher;
+use crate::pack_read::{self, read_index};
+use crate::repository::Repository;
+use crate::revwalk::{peel, reachable_commits, tag_target};
+use crate::tree_entry::Mode;
+
+const SIGNATURE: &[u8] = b"BITM";
+
+/// The bitmaps are for all objects reachable from their commit, a required option.
+const OPT_FULL_DAG: u16 = 1;
+
+/// Besides the tips, commits of the history which get a bitmap, see write().
+const COMMIT_INTERVAL: usize = 100;
+
+/// Decode an EWAH-compressed bitmap at the start of some data, returning its
+/// words and the number of bytes it takes.
+///
+/// The words are preceded by the number of bits, which bounds the number of
+/// words once decoded, and their count, and followed by the position of the
+/// last run-length word. Each run-length word has a bit for its run, the
+/// length of the run in words (32 bits), and the number of literal words that
+/// follow (31 bits).
+fn read_ewah(data: &[u8]) -> Result<(Vec<u64>, usize)> {
+    let u32_at = |pos: usize| {
+        data.get(pos..pos + 4)
+            .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+    };
+    let (Some(bits), Some(count)) = (u32_at(0), u32_at(4).map(|count| count as usize)) else {
+        bail!("truncated bitmap");
+    };
+    let max_words = (bits as usize).div_ceil(64);
+    let end = 8 + count * 8 + 4;
+    if data.len() < end {
+        bail!("truncated bitmap");
+    }
+    let word = |i: usize| u64::from_be_bytes(data[8 + i * 8..16 + i * 8].try_into().unwrap());
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < count {
+        let rlw = word(i);
+        let fill = if rlw & 1 == 1 { u64::MAX } else { 0 };
+        let run = ((rlw >> 1) & u64::from(u32::MAX)) as usize;
+        let literals = (rlw >> 33) as usize;
+        if i + 1 + literals > count {
+            bail!("corrupt bitmap");
+        }
+        if words.len() + run + literals > max_words {
+            bail!("corrupt bitmap: more words than its {bits} bits need");
+        }
+        words.resize(words.len() + run, fill);
+        words.extend((i + 1..i + 1 + literals).map(word));
+        i += 1 + literals;
+    }
+    Ok((words, end))
+}
+
+/// Encode a bitmap with EWAH, see read_ewah().
+fn write_ewah(words: &[u64], out: &mut Vec<u8>) {
+    let len = words.iter().rposition(|&w| w != 0).map_or(0, |i| i + 1);
+    let words = &words[..len];
+    let bits = match len {
+        0 => 0,
+        len => len * 64 - words[len - 1].leading_zeros() as usize,
+    };
+    let mut encoded = Vec::new();
+    let mut last_rlw = 0;
+    let mut i = 0;
+    let is_clean = |w: u64| w == 0 || w == u64::MAX;
+    while i < words.len() || encoded.is_empty() {
+        let fill = words.get(i).copied().filter(|&w| is_clean(w)).unwrap_or(0);
+        let mut run = 0;
+        while i < words.len() && words[i] == fill && run < u32::MAX as usize {
+            run += 1;
+            i += 1;
+        }
+        let start = i;
+        while i < words.len() && !is_clean(words[i]) && i - start < (u32::MAX >> 1) as usize {
+            i += 1;
+        }
+        last_rlw = encoded.len();
+        encoded.push((fill & 1) | (run as u64) << 1 | ((i - start) as u64) << 33);
+        encoded.extend_from_slice(&words[start..i]);
+    }
+    out.extend((bits as u32).to_be_bytes());
+    out.extend((encoded.len() as u32).to_be_bytes());
+    for word in encoded {
+        out.extend(word.to_be_bytes());
+    }
+    out.extend((last_rlw as u32).to_be_bytes());
+}
+
+/// Objects reachable from some tips: bits for those in the pack, and hashes
+/// for the others.
+struct Reach {
+    bits: Vec<u64>,
+    others: HashSet<String>,
+}
+
+/// The bitmaps of a pack.
+struct PackBitmap {
+    /// Hashes of the objects of the pack, in pack order
+    objects: Vec<String>,
+    /// Position of each object in pack order
+    positions: HashMap<String, usize>,
+    /// Objects reachable from the commits which have a bitmap
+    commits: HashMap<String, Vec<u64>>,
+}
+
+impl PackBitmap {
+    /// List the objects of a pack, with no bitmaps yet. Return them along
+    /// with the objects in index (hash) order, and the checksum of the pack.
+    fn new(repo: &Repository, pack: &Path) -> Result<(Self, Vec<String>, Vec<u8>)> {
+        let (entries, checksum) = read_index(repo, &pack.with_extension("idx"))?;
+        let mut by_offset: Vec<_> = entries
+            .iter()
+            .map(|e| (e.offset, e.hash.to_string()))
+            .collect();
+        by_offset.sort_unstable();
+        let objects: Vec<String> = by_offset.into_iter().map(|(_, hash)| hash).collect();
+        let positions = objects
+            .iter()
+            .enumerate()
+            .map(|(i, h)| (h.clone(), i))
+            .collect();
+        let bitmap = PackBitmap {
+            objects,
+            positions,
+            commits: HashMap::new(),
+        };
+        let by_hash = entries.iter().map(|e| e.hash.to_string()).collect();
+        Ok((bitmap, by_hash, checksum.as_ref().to_vec()))
+    }
+
+    /// Read the bitmap file of a pack, if it has one.
+    fn open(repo: &Repository, pack: &Path) -> Result<Option<Self>> {
+        let path = pack.with_extension("bitmap");
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+        };
+        let (mut bitmap, by_hash, checksum) = PackBitmap::new(repo, pack)?;
+        bitmap
+            .parse(&data, &by_hash, &checksum)
+            .with_context(|| format!("reading {}", path.display()))?;
+        Ok(Some(bitmap))
+    }
+
+    /// Parse the content of a bitmap file.
+    fn parse(&mut self, data: &[u8], by_hash: &[String], checksum: &[u8]) -> Result<()> {
+        let header_len = 12 + checksum.len();
+        if data.len() < header_len || &data[..4] != SIGNATURE {
+            bail!("not a bitmap file");
+        }
+        let version = u16::from_be_bytes([data[4], data[5]]);
+        let options = u16::from_be_bytes([data[6], data[7]]);
+        if version != 1 || options & OPT_FULL_DAG == 0 {
+            bail!("unsupported bitmap version {version} (options {options:#x})");
+        }
+        let count = u32::from_be_bytes(data[8..12].try_into().unwrap()) as usize;
+        if &data[12..header_len] != checksum {
+            bail!("bitmap does not match its pack");
+        }
+        let mut pos = header_len;
+        // Bitmaps of commits, trees, blobs and tags: not needed here.
+        for _ in 0..4 {
+            pos += read_ewah(&data[pos..])?.1;
+        }
+        // Commits in the order of the file, for XOR offsets
+        let mut order: Vec<&String> = Vec::with_capacity(count);
+        for _ in 0..count {
+            let Some(entry) = data.get(pos..pos + 6) else {
+                bail!("truncated bitmap");
+            };
+            let index_pos = u32::from_be_bytes(entry[..4].try_into().unwrap()) as usize;
+            let xor_offset = entry[4] as usize;
+            let (mut words, len) = read_ewah(&data[pos + 6..])?;
+            pos += 6 + len;
+            if xor_offset > 0 {
+                let Some(base) = order
+                    .len()
+                    .checked_sub(xor_offset)
+                    .map(|i| &self.commits[order[i]])
+                else {
+                    bail!("invalid XOR offset");
+                };
+                if words.len() < base.len() {
+                    words.resize(base.len(), 0);
+                }
+                for (word, base) in words.iter_mut().zip(base) {
+                    *word ^= base;
+                }
+            }
+            let Some(hash) = by_hash.get(index_pos) else {
+                bail!("invalid commit position {index_pos}");
+            };
+            self.commits.insert(hash.clone(), words);
+            order.push(hash);
+        }
+        Ok(())
+    }
+
+    /// Start an empty set of objects.
+    fn empty(&self) -> Reach {
+        Reach {
+            bits: vec![0; self.objects.len().div_ceil(64)],
+            others: HashSet::new(),
+        }
+    }
+
+    /// Add an object to a set, returning false if it was already there.
+    fn insert(&self, reach: &mut Reach, hash: &str) -> bool {
+        match self.positions.get(hash) {
+            Some(&pos) => {
+                let (word, bit) = (pos / 64, 1 << (pos % 64));
+                let new = reach.bits[word] & bit == 0;
+                reach.bits[word] |= bit;
+                new
+            }
+            None => reach.others.insert(hash.to_owned()),
+        }
+    }
+
+    /// Add the content of a tree to a set, skipping subtrees already there.
+    fn add_tree(&self, repo: &Repository, reach: &mut Reach, hash: &str) -> Result<()> {
+        for entry in read_tree(repo, hash)? {
+            let hash = hex::encode(entry.hash);
+            match entry.mode {
+                Mode::SubMod => (),
+                Mode::Dir => {
+                    if self.insert(reach, &hash) {
+                        self.add_tree(repo, reach, &hash)?;
+                    }
+                }
+                _ => _ = self.insert(reach, &hash),
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the objects reachable from the tips, using the bitmaps of the
+    /// commits met along the way. Objects we don't have are ignored.
+    fn reach(&self, repo: &Repository, tips: &[String]) -> Result<Reach> {
+        let mut reach = self.empty();
+        let mut queue = tips.to_vec();
+        while let Some(hash) = queue.pop() {
+            if let Some(bits) = self.commits.get(&hash) {
+                for (word, bits) in reach.bits.iter_mut().zip(bits) {
+                    *word |= bits;
+                }
+                continue;
+            }
+            if !have_object(repo, &hash)? || !self.insert(&mut reach, &hash) {
+                continue;
+            }
+            let mut object = repo.read_object(&hash)?;
+            match object.obj_type {
+                ObjType::Commit => {
+                    let commit = Commit::from_object(repo, object, &hash)?;
+                    queue.extend(commit.parents);
+                    if self.insert(&mut reach, &commit.tree) {
+                        self.add_tree(repo, &mut reach, &commit.tree)?;
+                    }
+                }
+                ObjType::Tag => queue.push(tag_target(&mut object)?.0),
+                ObjType::Tree => self.add_tree(repo, &mut reach, &hash)?,
+                ObjType::Blob => (),
+            }
+        }
+        Ok(reach)
+    }
+}
+
+/// Find the bitmap of a local pack, if there is one and pack.useBitmaps
+/// is not false. Like git, only one is used.
+fn find(repo: &Repository) -> Result<Option<PackBitmap>> {
+    if !config::get_bool(repo, "pack.usebitmaps")?.unwrap_or(true) {
+        return Ok(None);
+    }
+    for (pack, _) in pack_read::list(repo)? {
+        if let Some(bitmap) = PackBitmap::open(repo, &pack)? {
+            return Ok(Some(bitmap));
+        }
+    }
+    Ok(None)
+}
+
+/// Get the objects reachable from the tips but not from the excluded objects
+/// (unlike with a walk, this includes all trees and blobs of the excluded
+/// history), or None if there is no bitmap to help. Objects of the pack come
+/// first, in pack order.
+pub fn reachable(
+    repo: &Repository,
+    tips: &[String],
+    exclude: &[String],
+) -> Result<Option<Vec<String>>> {
+    let Some(bitmap) = find(repo)? else {
+        return Ok(None);
+    };
+    let wanted = bitmap.reach(repo, tips)?;
+    let excluded = bitmap.reach(repo, exclude)?;
+    let mut objects = Vec::new();
+    for (i, (wanted, excluded)) in wanted.bits.iter().zip(&excluded.bits).enumerate() {
+        let mut bits = wanted & !excluded;
+        while bits != 0 {
+            objects.push(bitmap.objects[i * 64 + bits.trailing_zeros() as usize].clone());
+            bits &= bits - 1;
+        }
+    }
+    let mut others: Vec<String> = wanted
+        .others
+        .difference(&excluded.others)
+        .cloned()
+        .collect();
+    others.sort_unstable();
+    objects.extend(others);
+    Ok(Some(objects))
+}
+
+/// Tell if gc should write a bitmap: repack.writeBitmaps, by default only
+/// in bare repositories, like git.
+pub fn enabled(repo: &Repository) -> Result<bool> {
+    match config::get_bool(repo, "repack.writebitmaps")? {
+        Some(enabled) => Ok(enabled),
+        None => Ok(repo.is_bare()),
+    }
+}
+
+/// Write the bitmap file of a pack, for the commits of the tips and one in
+/// every COMMIT_INTERVAL commits of their history. Return false, writing
+/// nothing, if some objects reachable from them are not in the pack.
+pub fn write(repo: &Repository, pack: &Path, tips: &[String]) -> Result<bool> {
+    let (mut bitmap, by_hash, checksum) = PackBitmap::new(repo, pack)?;
+    let mut selected = HashSet::new();
+    for tip in tips {
+        if let Some((hash, ObjType::Commit)) = peel(repo, tip)? {
+            selected.insert(hash);
+        }
+    }
+    // Oldest first, so that walks stop at the bitmaps of older commits.
+    let mut commits = reachable_commits(repo, tips, &[])?;
+    commits.reverse();
+    let commits: Vec<String> = commits
+        .into_iter()
+        .enumerate()
+        .filter(|(i, hash)| i % COMMIT_INTERVAL == COMMIT_INTERVAL - 1 || selected.contains(hash))
+        .map(|(_, hash)| hash)
+        .collect();
+    for commit in &commits {
+        let reach = bitmap.reach(repo, slice::from_ref(commit))?;
+        if !reach.others.is_empty() {
+            return Ok(false);
+        }
+        bitmap.commits.insert(commit.clone(), reach.bits);
+    }
+
+    // Bitmaps of commits, trees, blobs and tags
+    let mut types =
+        [ObjType::Commit, ObjType::Tree, ObjType::Blob, ObjType::Tag].map(|t| (t, bitmap.empty()));
+    for hash in &bitmap.objects {
+        let obj_type = repo.read_object(hash)?.obj_type;
+        if let Some((_, reach)) = types.iter_mut().find(|(t, _)| *t == obj_type) {
+            bitmap.insert(reach, hash);
+        }
+    }
+
+    let mut out = SIGNATURE.to_vec();
+    out.extend(1u16.to_be_bytes());
+    out.extend(OPT_FULL_DAG.to_be_bytes());
+    out.extend((commits.len() as u32).to_be_bytes());
+    out.extend(&checksum);
+    for (_, reach) in &types {
+        write_ewah(&reach.bits, &mut out);
+    }
+    let index_positions: HashMap<&String, usize> =
+        by_hash.iter().enumerate().map(|(i, h)| (h, i)).collect();
+    for commit in &commits {
+        out.extend((index_positions[commit] as u32).to_be_bytes());
+        // No XOR with a previous bitmap, no flags
+        out.extend([0, 0]);
+        write_ewah(&bitmap.commits[commit], &mut out);
+    }
+    let trailer = Hasher::digest(repo.hash_algo()?, &out);
+    out.extend(trailer.as_ref());
+
+    let path = pack.with_extension("bitmap");
+    let tmp_path = pack.with_extension("bitmap.tmp");
+    fs::write(&tmp_path, out).map_err(|e| write_error(e, &tmp_path))?;
+    fs::rename(&tmp_path, &path).map_err(|e| write_error(e, &path))?;
+    Ok(true)
+}
@@ -0,0 +1,151 @@
+//! Finding the commit that last changed each line of a file, like git blame.
+
+use anyhow::{bail, Result};
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::Write;
+use std::path::Path;
+
+use crate::abbrev::min_len;
+use crate::commit::Commit;
+use crate::date;
+use crate::diff::{diff_lines, Op};
+use crate::extract::find_entry;
+use crate::obj_read::{read_blob, read_commit};
+use crate::platform;
+use crate::repository::Repository;
+use crate::tree_entry::Mode;
+
+/// Get the hash of the file at a path in a commit, if there is one.
+fn file_in(repo: &Repository, commit: &Commit, path: &[u8]) -> Result<Option<String>> {
+    match find_entry(repo, &commit.tree, Path::new(&platform::os_str(path)))? {
+        Some(entry) if !matches!(entry.mode, Mode::Dir | Mode::SubMod) => {
+            Ok(Some(hex::encode(entry.hash)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Find the commit that introduced each line of the file at `path` (relative
+/// to the root of the tree) in commit `start`, and print them like git blame.
+///
+/// Starting from `start`, lines are passed to the parents of each commit when
+/// they are unchanged in them, according to a diff of the file, trying parents
+/// in order; the lines that are left are blamed on the commit. Like git,
+/// commits are visited by decreasing commit date, so that lines reaching the
+/// same commit through several children are handled at once. Renames are not
+/// followed: lines of a file which does not exist in a parent are not passed
+/// to that parent.
+pub fn blame(repo: &Repository, start: &str, path: &[u8], out: &mut impl Write) -> Result<()> {
+    let mut commits = HashMap::new();
+    let commit = read_commit(repo, start)?;
+    let Some(blob) = file_in(repo, &commit, path)? else {
+        bail!("no such path {} in {start}", String::from_utf8_lossy(path));
+    };
+    let data = read_blob(repo, &blob)?;
+    let lines: Vec<&[u8]> = data.split_inclusive(|&c| c == b'\n').collect();
+
+    // Lines waiting to be blamed, by commit: the hash of the file in the commit,
+    // and pairs of (line in that file, line in the blamed file).
+    let mut pending: HashMap<String, (String, Vec<(usize, usize)>)> = HashMap::new();
+    pending.insert(
+        start.to_owned(),
+        (blob, (0..lines.len()).map(|i| (i, i)).collect()),
+    );
+    let mut queue = BinaryHeap::from([(commit.time, start.to_owned())]);
+    commits.insert(start.to_owned(), commit);
+    let mut blamed = vec![String::new(); lines.len()];
+    while let Some((_, hash)) = queue.pop() {
+        let Some((blob, mut suspects)) = pending.remove(&hash) else {
+            continue;
+        };
+        let parents = commits[&hash].parents.clone();
+        let mut ours = None;
+        for parent in parents {
+            if suspects.is_empty() {
+                break;
+            }
+            if !commits.contains_key(&parent) {
+                commits.insert(parent.clone(), read_commit(repo, &parent)?);
+            }
+            let Some(parent_blob) = file_in(repo, &commits[&parent], path)? else {
+                continue;
+            };
+            let mut passed: Vec<(usize, usize)> = Vec::new();
+            if parent_blob == blob {
+                passed = std::mem::take(&mut suspects);
+            } else {
+                if ours.is_none() {
+                    ours = Some(read_blob(repo, &blob)?);
+                }
+                let ours = ours.as_deref().expect("content was just read");
+                let ours: Vec<&[u8]> = ours.split_inclusive(|&c| c == b'\n').collect();
+                let theirs = read_blob(repo, &parent_blob)?;
+                let theirs: Vec<&[u8]> = theirs.split_inclusive(|&c| c == b'\n').collect();
+                let mut in_parent = vec![None; ours.len()];
+                for op in diff_lines(&theirs, &ours) {
+                    if let Op::Equal(i, j) = op {
+                        in_parent[j] = Some(i);
+                    }
+                }
+                suspects.retain(|&(line, final_line)| match in_parent[line] {
+                    Some(i) => {
+                        passed.push((i, final_line));
+                        false
+                    }
+                    None => true,
+                });
+            }
+            if passed.is_empty() {
+                continue;
+            }
+            match pending.entry(parent.clone()) {
+                Entry::Occupied(mut entry) => entry.get_mut().1.extend(passed),
+                Entry::Vacant(entry) => {
+                    entry.insert((parent_blob, passed));
+                    queue.push((commits[&parent].time, parent));
+                }
+            }
+        }
+        for (_, final_line) in suspects {
+            blamed[final_line] = hash.clone();
+        }
+    }
+
+    // One more hex digit than usual, for the ^ marking root commits.
+    let len = (min_len(repo)? + 1).min(start.len());
+    let name = |commit: &Commit| -> String {
+        let author = &commit.author;
+        author
+            .split_once(" <")
+            .map_or(author.as_str(), |(name, _)| name)
+            .to_owned()
+    };
+    let name_width = blamed
+        .iter()
+        .map(|hash| name(&commits[hash]).chars().count())
+        .max()
+        .unwrap_or(0);
+    let number_width = lines.len().to_string().len();
+    for (n, (line, hash)) in lines.iter().zip(&blamed).enumerate() {
+        let commit = &commits[hash];
+        let hash = match commit.parents.is_empty() {
+            true => format!("^{}", &hash[..len - 1]),
+            false => hash[..len].to_owned(),
+        };
+        let name = name(commit);
+        let padding = name_width - name.chars().count();
+        let date = date::format_iso(commit.author_time, &commit.author_zone);
+        write!(
+            out,
+            "{hash} ({name}{:padding$} {date} {:number_width$}) ",
+            "",
+            n + 1
+        )?;
+        out.write_all(line)?;
+        if !line.ends_with(b"\n") {
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
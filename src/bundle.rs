@@ -0,0 +1,125 @@
+//! Bundles: references and a packfile in a single file, to transfer history
+//! without any network connection, like git bundle.
+//!
+//! See gitformat-bundle(5). Only version 2 is supported, so SHA-1 only.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::{self, prelude::*, BufReader};
+use std::path::Path;
+
+use crate::fetch::have_object;
+use crate::obj_read::read_commit;
+use crate::pack_write::write_pack;
+use crate::repository::Repository;
+use crate::revwalk::{boundary_commits, reachable_hashes};
+
+/// The first line of a version 2 bundle.
+const SIGNATURE: &[u8] = b"# v2 git bundle\n";
+
+/// What comes before the pack in a bundle.
+pub struct BundleHeader {
+    /// Commits the receiving repository must already have, with their subject
+    pub prerequisites: Vec<(String, String)>,
+    /// References, as full names and hashes, in the order of the bundle
+    pub refs: Vec<(String, String)>,
+}
+
+/// Tell if a path is a bundle file, from its signature.
+pub fn is_bundle(path: &Path) -> bool {
+    let mut start = [0; SIGNATURE.len()];
+    let read = fs::File::open(path).and_then(|mut file| file.read_exact(&mut start));
+    read.is_ok() && start == SIGNATURE
+}
+
+/// Open a bundle and read its header, returning it with a reader positioned
+/// at the start of the pack.
+pub fn open(path: &Path) -> Result<(BundleHeader, impl BufRead)> {
+    let file = fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line)?;
+    if line != SIGNATURE {
+        bail!(
+            "{} is not a bundle (or not a version 2 one)",
+            path.display()
+        );
+    }
+    let mut header = BundleHeader {
+        prerequisites: Vec::new(),
+        refs: Vec::new(),
+    };
+    loop {
+        line.clear();
+        reader.read_until(b'\n', &mut line)?;
+        let text = std::str::from_utf8(&line).context("bundle header is not UTF-8")?;
+        let Some(text) = text.strip_suffix('\n') else {
+            bail!("truncated bundle header");
+        };
+        if text.is_empty() {
+            return Ok((header, reader));
+        }
+        // -<hash> [<subject>] for prerequisites, <hash> <name> for references
+        match text.strip_prefix('-') {
+            Some(prerequisite) => {
+                let (hash, subject) = prerequisite.split_once(' ').unwrap_or((prerequisite, ""));
+                header.prerequisites.push((hash.into(), subject.into()));
+            }
+            None => match text.split_once(' ') {
+                Some((hash, name)) => header.refs.push((name.into(), hash.into())),
+                None => bail!("malformed bundle header line: {text}"),
+            },
+        }
+    }
+}
+
+/// Fail unless the repository has all the prerequisites of a bundle.
+pub fn check_prerequisites(repo: &Repository, header: &BundleHeader) -> Result<()> {
+    let mut missing = Vec::new();
+    for (hash, _) in &header.prerequisites {
+        if !have_object(repo, hash)? {
+            missing.push(hash.as_str());
+        }
+    }
+    if !missing.is_empty() {
+        bail!(
+            "repository lacks these prerequisite commits: {}",
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Write a bundle with the given references (full names and hashes) and the
+/// objects they need, except those reachable from `exclude`: the excluded
+/// commits at the boundary become prerequisites.
+pub fn write(
+    repo: &Repository,
+    out: &mut impl Write,
+    refs: &[(String, String)],
+    exclude: &[String],
+) -> Result<()> {
+    let tips: Vec<String> = refs.iter().map(|(_, hash)| hash.clone()).collect();
+    let mut header = SIGNATURE.to_vec();
+    for hash in boundary_commits(repo, &tips, exclude)? {
+        let message = read_commit(repo, &hash)?.message;
+        let subject = message.split(|&c| c == b'\n').next().unwrap_or_default();
+        writeln!(header, "-{hash} {}", String::from_utf8_lossy(subject))?;
+    }
+    for (name, hash) in refs {
+        writeln!(header, "{hash} {name}")?;
+    }
+    header.push(b'\n');
+    out.write_all(&header).context("writing bundle header")?;
+
+    write_pack(repo, &mut *out, &reachable_hashes(repo, &tips, exclude)?)?;
+    Ok(())
+}
+
+/// Print the references of a bundle like git bundle list-heads.
+pub fn list_heads(header: &BundleHeader, out: &mut impl Write) -> io::Result<()> {
+    for (name, hash) in &header.refs {
+        writeln!(out, "{hash} {name}")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,223 @@
+//! Removing untracked files from the worktree, like git clean.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::common::{is_under, relative, repo_path};
+use crate::config;
+use crate::ignore::Ignores;
+use crate::index;
+use crate::platform;
+use crate::repository::Repository;
+
+/// Which untracked files to remove, depending on .gitignore files.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum IgnoredFiles {
+    /// Only files which are not ignored (the default)
+    Keep,
+    /// Ignored files too (-x)
+    Remove,
+    /// Only ignored files (-X)
+    Only,
+}
+
+/// Options of the clean command.
+pub struct CleanOptions {
+    /// Only tell what would be removed
+    pub dry_run: bool,
+    /// Number of times --force was given: twice to remove other repositories
+    pub force: u8,
+    /// Also remove untracked directories
+    pub dirs: bool,
+    pub ignored: IgnoredFiles,
+    /// Don't print the paths removed
+    pub quiet: bool,
+}
+
+/// Tell if some of the paths given are inside a directory.
+fn contains_pathspec(dir: &[u8], pathspecs: &[Vec<u8>]) -> bool {
+    pathspecs.iter().any(|spec| {
+        spec.strip_prefix(dir)
+            .is_some_and(|p| p.first() == Some(&b'/'))
+    })
+}
+
+/// Collects what to remove, see walk().
+struct Walker<'a> {
+    options: &'a CleanOptions,
+    pathspecs: Vec<Vec<u8>>,
+    /// The paths given on the command line, if any: directories they name
+    /// are cleaned as if with -d, like git does
+    named: bool,
+    tracked: HashSet<&'a [u8]>,
+    /// Directories with tracked files in them, with a trailing '/'
+    tracked_dirs: HashSet<&'a [u8]>,
+    ignores: Ignores,
+}
+
+impl Walker<'_> {
+    /// Collect the paths to remove in a directory (`rel` being its path
+    /// relative to the root of the worktree, with a trailing '/'), with `dirs`
+    /// if untracked directories in it are to be removed too. Return
+    /// whether everything in it is to be removed: it is then removed as a
+    /// whole with -d, rather than file by file. Like git, untracked
+    /// directories are only looked into with -d, when a path given is in
+    /// them, or for ignored files with -X (unless everything in them is
+    /// ignored).
+    fn walk(
+        &mut self,
+        repo: &Repository,
+        dir: &Path,
+        rel: &[u8],
+        dir_ignored: bool,
+        dirs: bool,
+        out: &mut Vec<Vec<u8>>,
+    ) -> Result<bool> {
+        let mark = self.ignores.enter_dir(dir, rel)?;
+        let mut names = Vec::new();
+        for entry in fs::read_dir(dir).with_context(|| format!("listing {}", dir.display()))? {
+            let entry = entry.with_context(|| format!("listing {}", dir.display()))?;
+            names.push((
+                entry.file_name().into_encoded_bytes(),
+                entry.file_type()?.is_dir(),
+            ));
+        }
+        names.sort_unstable();
+
+        let removed_before = out.len();
+        let mut all = true;
+        for (name, is_dir) in names {
+            let path = [rel, &name].concat();
+            if name == b".git" || self.tracked.contains(&path[..]) {
+                all = false;
+                continue;
+            }
+            let in_pathspec = self.pathspecs.iter().any(|spec| is_under(&path, spec));
+            if !(in_pathspec || is_dir && contains_pathspec(&path, &self.pathspecs)) {
+                all = false;
+                continue;
+            }
+            let is_ignored = dir_ignored || self.ignores.is_ignored(&path, is_dir);
+            let candidate = match self.options.ignored {
+                IgnoredFiles::Keep => !is_ignored,
+                IgnoredFiles::Remove => true,
+                IgnoredFiles::Only => is_ignored,
+            };
+            if !is_dir {
+                match candidate {
+                    true => out.push(path),
+                    false => all = false,
+                }
+                continue;
+            }
+            let sub_rel = [&path[..], b"/"].concat();
+            let dirs = dirs || (self.named && self.pathspecs.contains(&path));
+            let full = platform::join(repo.work_tree()?, &path);
+            if full.join(".git").exists() {
+                // Another repository: only removed with -d and -f twice.
+                match candidate && in_pathspec && dirs && self.options.force > 1 {
+                    true => out.push(sub_rel),
+                    false => all = false,
+                }
+                continue;
+            }
+            let untracked = !self.tracked_dirs.contains(&sub_rel[..]);
+            if untracked
+                && !dirs
+                && self.options.ignored != IgnoredFiles::Only
+                && !contains_pathspec(&path, &self.pathspecs)
+            {
+                all = false;
+                continue;
+            }
+            let mut found = Vec::new();
+            let whole = self.walk(repo, &full, &sub_rel, is_ignored, dirs, &mut found)?;
+            if whole && untracked && in_pathspec {
+                match dirs {
+                    true => out.push(sub_rel),
+                    false => all = false,
+                }
+            } else {
+                all = false;
+                out.extend(found);
+            }
+        }
+        // With -X, empty directories are kept: they are not ignored.
+        if self.options.ignored == IgnoredFiles::Only && out.len() == removed_before && all {
+            all = false;
+        }
+        self.ignores.leave_dir(mark);
+        Ok(all)
+    }
+}
+
+/// The "git clean" command - partial implementation: no -i or -e. Remove
+/// untracked files under the given paths (by default the current directory),
+/// and with `dirs` untracked directories too. Without `force`,
+/// clean.requireForce must be false.
+pub fn clean(repo: &Repository, paths: &[PathBuf], options: &CleanOptions) -> Result<()> {
+    if !options.dry_run && options.force == 0 {
+        match config::get_bool(repo, "clean.requireforce")? {
+            Some(false) => (),
+            Some(true) => bail!(
+                "clean.requireForce set to true and neither -i, -n, nor -f given; refusing to clean"
+            ),
+            None => bail!(
+                "clean.requireForce defaults to true and neither -i, -n, nor -f given; refusing to clean"
+            ),
+        }
+    }
+    let root = repo.work_tree()?;
+    let prefix = platform::bytes(repo.prefix().as_os_str());
+    let pathspecs = match paths {
+        [] => vec![prefix.to_vec()],
+        _ => paths
+            .iter()
+            .map(|path| repo_path(repo, path))
+            .collect::<Result<_>>()?,
+    };
+    let entries = index::read(repo)?;
+    let mut tracked_dirs = HashSet::new();
+    for entry in &entries {
+        for (end, _) in entry.path.iter().enumerate().filter(|&(_, &c)| c == b'/') {
+            tracked_dirs.insert(&entry.path[..=end]);
+        }
+    }
+    let mut walker = Walker {
+        options,
+        named: !paths.is_empty(),
+        pathspecs,
+        tracked: entries.iter().map(|e| &e.path[..]).collect(),
+        tracked_dirs,
+        ignores: Ignores::new(repo)?,
+    };
+    let dirs = options.dirs || (walker.named && walker.pathspecs.contains(&Vec::new()));
+    let mut found = Vec::new();
+    walker.walk(repo, root, b"", false, dirs, &mut found)?;
+
+    let mut stdout = io::stdout().lock();
+    for path in found {
+        if !options.quiet {
+            stdout.write_all(match options.dry_run {
+                true => b"Would remove ",
+                false => b"Removing ",
+            })?;
+            stdout.write_all(&relative(&path, &prefix))?;
+            stdout.write_all(b"\n")?;
+        }
+        if options.dry_run {
+            continue;
+        }
+        let full = platform::join(root, path.strip_suffix(b"/").unwrap_or(&path));
+        let result = match path.ends_with(b"/") {
+            true => fs::remove_dir_all(&full),
+            false => fs::remove_file(&full),
+        };
+        result.with_context(|| format!("removing {}", full.display()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,749 @@
+//! The clone command, as a pipeline of stages customised by options.
+//!
+//! Repositories are cloned over the network (see network and dumb_http), or
+//! from a local path: then objects are hard-linked (or copied) and references
+//! read directly, like git clone --local. A bundle file can be cloned too.
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{self, Path, PathBuf};
+
+use crate::bundle::{self, is_bundle, BundleHeader};
+use crate::commands::{committer, git_init};
+use crate::config;
+use crate::diff::{flatten_tree, PathList};
+use crate::dumb_http;
+use crate::fetch::have_object;
+use crate::hooks;
+use crate::index::{self, IndexEntry};
+use crate::interrupt;
+use crate::lock::lock_worktree;
+use crate::network::{ensure_online, get_pack, is_dumb_http, ls_refs, FetchRequest, RemoteRef};
+use crate::obj_read::{read_commit, read_tree};
+use crate::object_id::{is_hash, HashAlgo};
+use crate::pack_index::store_pack;
+use crate::refs;
+use crate::repository::{common_dir_of, is_git_dir, read_git_file, Repository};
+use crate::revwalk::peel;
+use crate::shallow;
+use crate::tree_entry::Mode;
+use crate::unpack::unpack_from;
+
+/// Options for the clone command.
+#[derive(Default)]
+pub struct CloneOptions {
+    /// Create a shallow clone with history truncated to this many commits.
+    pub depth: Option<u32>,
+    /// Only check out files at the root of the tree (sparse-checkout, cone mode).
+    pub sparse: bool,
+    /// Create a partial clone, omitting objects according to this filter spec.
+    pub filter: Option<String>,
+    /// Also apply the filter to submodules (recorded, but submodules aren't supported).
+    pub also_filter_submodules: bool,
+    /// Directory to copy into .git before anything else.
+    pub template: Option<PathBuf>,
+    /// Unpack fetched objects to loose storage instead of keeping the pack.
+    pub unpack: bool,
+    /// Create a bare repository, mirroring the remote's branches without checkout.
+    pub bare: bool,
+    /// Variables to set in the new repository's config, as `name=value`,
+    /// before fetching and checking out (eg core.autocrlf).
+    pub config: Vec<String>,
+    /// Check out this branch instead of the one the remote HEAD points to.
+    pub branch: Option<String>,
+    /// Only fetch the branch to check out (and tags pointing into its history).
+    pub single_branch: bool,
+}
+
+/// What the remote told us about its references.
+struct RemoteState {
+    refs: Vec<RemoteRef>,
+    /// Hash of the branch to check out.
+    head: String,
+    /// Name of the branch to check out (without refs/heads/).
+    branch: String,
+    /// Name of the branch the remote HEAD points to.
+    default_branch: String,
+}
+
+// This seems to be roughly what git is doing based on experiments.
+fn dir_from_repo_url(url: &str) -> &Path {
+    let url = url.trim_end_matches("/");
+    let url = url.trim_end_matches(".git");
+    let url = url.trim_end_matches("/");
+    // Also split scp-like addresses, eg "host:repo.git".
+    let last = url
+        .rsplit(['/', ':'])
+        .next()
+        .expect("always at least one component");
+    Path::new(last)
+}
+
+/// Stage 1: pick the target directory and check it's empty (or non-existent).
+/// Like git, bare repositories are named after the remote with a .git suffix.
+fn target_directory(repo_url: &str, directory: Option<&Path>, bare: bool) -> Result<PathBuf> {
+    let directory = match directory {
+        Some(directory) => directory.to_owned(),
+        None if bare => {
+            let mut name = dir_from_repo_url(repo_url).as_os_str().to_owned();
+            name.push(".git");
+            PathBuf::from(name)
+        }
+        None => dir_from_repo_url(repo_url).to_owned(),
+    };
+    if directory.exists() {
+        if !directory.is_dir() {
+            bail!("destination exists and is not a directory");
+        }
+        let mut entries = directory
+            .read_dir()
+            .context("checking destination is empty")?;
+        if entries.next().is_some() {
+            bail!("destination exists and is not empty");
+        }
+    }
+    Ok(directory)
+}
+
+/// Recursively copy a template directory, without overwriting existing files.
+fn copy_template(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to).with_context(|| format!("creating {}", to.display()))?;
+    let iter = fs::read_dir(from).with_context(|| format!("reading {}", from.display()))?;
+    for entry in iter {
+        let entry = entry.with_context(|| format!("bad direntry in {}", from.display()))?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_template(&entry.path(), &dest)?;
+        } else if !dest.exists() {
+            fs::copy(entry.path(), &dest)
+                .with_context(|| format!("copying template file to {}", dest.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Stage 2: create the repository, and record configuration for the remote
+/// and options that affect later commands.
+fn init_repository(
+    directory: &Path,
+    repo_url: &str,
+    object_format: HashAlgo,
+    options: &CloneOptions,
+) -> Result<Repository> {
+    // Resolve the template before changing directory.
+    let template = match &options.template {
+        Some(t) => Some(fs::canonicalize(t).with_context(|| format!("template {}", t.display()))?),
+        None => None,
+    };
+
+    let repo =
+        git_init(directory, object_format, options.bare).context("initializing git directory")?;
+    env::set_current_dir(directory)
+        .with_context(|| format!("changing working directory to {}", directory.display()))?;
+    if let Some(template) = template {
+        copy_template(&template, repo.git_dir()).context("copying template")?;
+    }
+
+    for option in &options.config {
+        let (key, value) = option.split_once('=').unwrap_or((option, "true"));
+        config::add(&repo, key, value)?;
+    }
+    config::add(&repo, "remote.origin.url", repo_url)?;
+
+    if let Some(filter) = &options.filter {
+        config::add(&repo, "core.repositoryformatversion", "1")?;
+        config::add(&repo, "extensions.partialClone", "origin")?;
+        config::add(&repo, "remote.origin.promisor", "true")?;
+        config::add(&repo, "remote.origin.partialclonefilter", filter)?;
+        if options.also_filter_submodules {
+            config::add(&repo, "clone.filterSubmodules", "true")?;
+        }
+    }
+
+    if options.sparse {
+        config::add(&repo, "core.sparseCheckout", "true")?;
+        config::add(&repo, "core.sparseCheckoutCone", "true")?;
+        let info = repo.git_dir().join("info");
+        fs::create_dir_all(&info).context("creating .git/info")?;
+        // Cone mode patterns for "only files at the root".
+        fs::write(info.join("sparse-checkout"), "/*\n!/*/\n")
+            .context("writing .git/info/sparse-checkout")?;
+    }
+
+    Ok(repo)
+}
+
+/// Stage 3: list the remote's references and find its default branch.
+/// For a single branch given by name, the others aren't listed.
+fn discover(repo_url: &str, options: &CloneOptions) -> Result<RemoteState> {
+    let heads = match (&options.branch, options.single_branch) {
+        (Some(branch), true) => format!("refs/heads/{branch}"),
+        _ => "refs/heads/".to_owned(),
+    };
+    let prefixes = ["HEAD".to_owned(), heads, "refs/tags/".to_owned()];
+    let refs = ls_refs(repo_url, &prefixes).context("listing remote refs")?;
+
+    let Some(head) = refs.iter().find(|r| r.name == "HEAD") else {
+        bail!("remote did not list HEAD");
+    };
+    let Some(target) = &head.symref_target else {
+        bail!("remote HEAD is detached");
+    };
+    let Some(branch) = target.strip_prefix("refs/heads/") else {
+        bail!("remote HEAD does not point to a branch: {target}");
+    };
+    Ok(RemoteState {
+        head: head.hash.clone(),
+        branch: branch.into(),
+        default_branch: branch.into(),
+        refs,
+    })
+}
+
+/// List the references of a local repository given by its .git directory (see
+/// local_source()) like ls_refs(): HEAD, then the references under refs/, with
+/// annotated tags peeled. Only those starting with one of the prefixes are
+/// listed, if any are given.
+pub(crate) fn ls_refs_local(source: &Path, prefixes: &[String]) -> Result<Vec<RemoteRef>> {
+    let remote = Repository::open_read_only(source)?;
+    let mut found = Vec::new();
+    if let Some(hash) = refs::resolve(&remote, "HEAD")? {
+        found.push(("HEAD".to_owned(), hash));
+    }
+    found.extend(refs::list(&remote, "refs/")?);
+    let mut listed = Vec::new();
+    for (name, hash) in found {
+        if !prefixes.is_empty() && !prefixes.iter().any(|p| name.starts_with(p.as_str())) {
+            continue;
+        }
+        let symref_target = refs::read_symbolic(&remote, &name)?;
+        let peeled = peel(&remote, &hash)?
+            .map(|(peeled, _)| peeled)
+            .filter(|peeled| *peeled != hash);
+        listed.push(RemoteRef {
+            hash,
+            name,
+            symref_target,
+            peeled,
+        });
+    }
+    Ok(listed)
+}
+
+/// Stage 3 (local): read the references of a local repository, and find its
+/// default branch. Loose references take precedence over packed ones.
+fn discover_local(repo: &Repository, source: &Path) -> Result<RemoteState> {
+    let mut found = BTreeMap::new();
+    match fs::read_to_string(source.join("packed-refs")) {
+        Ok(text) => {
+            for line in text.lines().filter(|l| !l.starts_with(['#', '^'])) {
+                if let Some((hash, name)) = line.split_once(' ') {
+                    found.insert(name.to_owned(), hash.to_owned());
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e).context("reading packed-refs"),
+    }
+    for name in ["refs/heads", "refs/tags"] {
+        read_loose_refs(repo, source, name, &mut found)?;
+    }
+
+    let head = fs::read_to_string(source.join("HEAD")).context("reading remote HEAD")?;
+    let Some(target) = head.trim().strip_prefix("ref: ") else {
+        bail!("remote HEAD is detached");
+    };
+    let Some(branch) = target.strip_prefix("refs/heads/") else {
+        bail!("remote HEAD does not point to a branch: {target}");
+    };
+    let Some(head) = found.get(target) else {
+        bail!("remote HEAD points to {target}, which does not exist");
+    };
+    Ok(RemoteState {
+        head: head.clone(),
+        branch: branch.into(),
+        default_branch: branch.into(),
+        refs: found
+            .into_iter()
+            .map(|(name, hash)| RemoteRef {
+                hash,
+                name,
+                symref_target: None,
+                peeled: None,
+            })
+            .collect(),
+    })
+}
+
+/// Stage 3 (bundle): get the references of a bundle. Its HEAD is needed, and
+/// the default branch is the first one it points to.
+fn discover_bundle(header: &BundleHeader) -> Result<RemoteState> {
+    let Some((_, head)) = header.refs.iter().find(|(name, _)| name == "HEAD") else {
+        bail!("bundle does not contain HEAD");
+    };
+    let Some(branch) = header
+        .refs
+        .iter()
+        .find(|(name, hash)| hash == head && name.starts_with("refs/heads/"))
+        .and_then(|(name, _)| name.strip_prefix("refs/heads/"))
+    else {
+        bail!("bundle HEAD does not point to a branch");
+    };
+    Ok(RemoteState {
+        head: head.clone(),
+        branch: branch.into(),
+        default_branch: branch.into(),
+        refs: header
+            .refs
+            .iter()
+            .filter(|(name, _)| name != "HEAD")
+            .map(|(name, hash)| RemoteRef {
+                hash: hash.clone(),
+                name: name.clone(),
+                symref_target: None,
+                peeled: None,
+            })
+            .collect(),
+    })
+}
+
+/// Stage 3 (all sources): choose the branch given with --branch, and with
+/// --single-branch, forget about the other branches (tags are filtered after
+/// fetching, see follow_tags()).
+fn select_branch(remote: &mut RemoteState, options: &CloneOptions) -> Result<()> {
+    if let Some(branch) = &options.branch {
+        let name = format!("refs/heads/{branch}");
+        let Some(r) = remote.refs.iter().find(|r| r.name == name) else {
+            bail!("Remote branch {branch} not found in upstream origin");
+        };
+        remote.head = r.hash.clone();
+        remote.branch = branch.clone();
+    }
+    if options.single_branch {
+        let name = format!("refs/heads/{}", remote.branch);
+        remote
+            .refs
+            .retain(|r| r.name == name || r.name.starts_with("refs/tags/"));
+    }
+    Ok(())
+}
+
+/// Stage 4 (single branch): like git's tag following, keep tags whose
+/// objects we have (annotated tags pointing into the history of the branch
+/// are sent along with it, and local clones have all objects).
+fn follow_tags(repo: &Repository, remote: &mut RemoteState) -> Result<()> {
+    let mut kept = Vec::new();
+    for r in remote.refs.drain(..) {
+        if !r.name.starts_with("refs/tags/") || have_object(repo, &r.hash)? {
+            kept.push(r);
+        }
+    }
+    remote.refs = kept;
+    Ok(())
+}
+
+/// Read loose references under the given directory of a local repository,
+/// ignoring symbolic ones.
+fn read_loose_refs(
+    repo: &Repository,
+    source: &Path,
+    name: &str,
+    found: &mut BTreeMap<String, String>,
+) -> Result<()> {
+    let Ok(entries) = fs::read_dir(source.join(name)) else {
+        return Ok(());
+    };
+    for entry in entries {
+        let entry = entry.with_context(|| format!("listing remote {name}"))?;
+        let name = format!("{name}/{}", entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            read_loose_refs(repo, source, &name, found)?;
+            continue;
+        }
+        let content =
+            fs::read_to_string(entry.path()).with_context(|| format!("reading remote {name}"))?;
+        if is_hash(repo, content.trim()) {
+            found.insert(name, content.trim().to_owned());
+        }
+    }
+    Ok(())
+}
+
+/// Stage 4 (local): hard-link all files of an object directory into ours,
+/// or copy them if that fails (for example across file systems).
+pub(crate) fn link_objects(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to).with_context(|| format!("creating {}", to.display()))?;
+    let iter = fs::read_dir(from).with_context(|| format!("reading {}", from.display()))?;
+    for entry in iter {
+        interrupt::check()?;
+        let entry = entry.with_context(|| format!("bad direntry in {}", from.display()))?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            link_objects(&entry.path(), &dest)?;
+        } else if !entry.file_name().to_string_lossy().starts_with("tmp") && !dest.exists() {
+            fs::hard_link(entry.path(), &dest)
+                .or_else(|_| fs::copy(entry.path(), &dest).map(drop))
+                .with_context(|| format!("copying {}", dest.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Fetch the given objects with a single request,
+/// and store the pack (with its index) or unpack it.
+fn fetch_pack(
+    repo: &Repository,
+    repo_url: &str,
+    request: FetchRequest,
+    unpack: bool,
+) -> Result<()> {
+    let promisor = request.filter.is_some();
+    let (pack, shallow_info) = get_pack(repo, repo_url, request).context("fetching objects")?;
+    if unpack {
+        let nb_obj = unpack_from(repo, pack).context("unpacking objects")?;
+        println!("Unpacked {nb_obj} objects");
+    } else {
+        let (name, nb_obj) = store_pack(repo, pack).context("storing pack")?;
+        println!("Received {nb_obj} objects");
+        if promisor {
+            // Like git, mark packs from a filtered fetch, so that objects
+            // they refer to are known to be available from the remote.
+            let path = repo.object_dir().join(format!("pack/pack-{name}.promisor"));
+            fs::write(&path, "").with_context(|| format!("creating {}", path.display()))?;
+        }
+    }
+    shallow::update(repo, &shallow_info.shallow, &shallow_info.unshallow)
+}
+
+/// Stage 4: fetch objects for all references, or with --single-branch, for
+/// the branch and the annotated tags pointing into its history.
+fn fetch_objects(
+    repo: &Repository,
+    repo_url: &str,
+    remote: &RemoteState,
+    options: &CloneOptions,
+) -> Result<()> {
+    let mut wants: Vec<String> = match options.single_branch {
+        true => vec![remote.head.clone()],
+        false => remote.refs.iter().map(|r| r.hash.clone()).collect(),
+    };
+    wants.sort_unstable();
+    wants.dedup();
+    if is_dumb_http(repo_url)? {
+        if options.depth.is_some() || options.filter.is_some() {
+            bail!("--depth and --filter are not supported with the dumb HTTP protocol");
+        }
+        let nb_obj = dumb_http::fetch(repo, repo_url, &wants).context("fetching objects")?;
+        println!("Received {nb_obj} objects");
+        return Ok(());
+    }
+    let request = FetchRequest {
+        wants: &wants,
+        depth: options.depth,
+        filter: options.filter.as_deref(),
+        include_tag: options.single_branch,
+        ..Default::default()
+    };
+    fetch_pack(repo, repo_url, request, options.unpack)
+}
+
+/// Stage 5 (bare): copy the remote's branches and tags, and point HEAD
+/// to the default branch. Like git, there are no reflogs in bare repositories.
+fn write_bare_refs(repo: &Repository, remote: &RemoteState) -> Result<()> {
+    for r in &remote.refs {
+        if r.name.starts_with("refs/heads/") || r.name.starts_with("refs/tags/") {
+            refs::write(repo, &r.name, &r.hash)?;
+        }
+    }
+    refs::write_symbolic(repo, "HEAD", &format!("refs/heads/{}", remote.branch))
+}
+
+/// Stage 5: create remote-tracking branches, tags, and the local branch, and
+/// record where to fetch remote branches from (only that one with --single-branch).
+/// Like git, branches and HEAD get a first reflog entry, tags don't.
+fn write_refs(
+    repo: &Repository,
+    repo_url: &str,
+    remote: &RemoteState,
+    single_branch: bool,
+) -> Result<()> {
+    let branch = &remote.branch;
+    let spec = match single_branch {
+        true => format!("+refs/heads/{branch}:refs/remotes/origin/{branch}"),
+        false => "+refs/heads/*:refs/remotes/origin/*".to_owned(),
+    };
+    config::add(repo, "remote.origin.fetch", &spec)?;
+
+    let who = committer();
+    let message = format!("clone: from {repo_url}");
+    for r in &remote.refs {
+        if let Some(name) = r.name.strip_prefix("refs/heads/") {
+            let name = format!("refs/remotes/origin/{name}");
+            refs::update(repo, &name, None, &r.hash, &who, &message)?;
+        } else if r.name.starts_with("refs/tags/") {
+            refs::write(repo, &r.name, &r.hash)?;
+        }
+    }
+    // The remote HEAD is only known if its branch was fetched.
+    let default = &remote.default_branch;
+    if !single_branch || default == branch {
+        refs::write_symbolic(
+            repo,
+            "refs/remotes/origin/HEAD",
+            &format!("refs/remotes/origin/{default}"),
+        )?;
+    }
+    refs::write_symbolic(repo, "HEAD", &format!("refs/heads/{branch}"))?;
+    refs::update(repo, "HEAD", None, &remote.head, &who, &message)?;
+    // Like git, the local branch is set to pull from the remote one.
+    config::add(repo, &format!("branch.{branch}.remote"), "origin")?;
+    config::add(
+        repo,
+        &format!("branch.{branch}.merge"),
+        &format!("refs/heads/{branch}"),
+    )
+}
+
+/// Collect objects missing from the given tree (possibly the tree itself)
+/// that are needed to check it out; if root_only, only look at its direct entries.
+fn missing_objects(
+    repo: &Repository,
+    tree: &str,
+    root_only: bool,
+    out: &mut Vec<String>,
+) -> Result<()> {
+    if !have_object(repo, tree)? {
+        out.push(tree.to_owned());
+        return Ok(());
+    }
+    for entry in read_tree(repo, tree)? {
+        let hash = hex::encode(entry.hash);
+        match entry.mode {
+            Mode::Dir if !root_only => missing_objects(repo, &hash, false, out)?,
+            Mode::Dir | Mode::SubMod => (),
+            _ if !have_object(repo, &hash)? => out.push(hash),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Stage 6: check out the default branch, respecting sparse-checkout,
+/// first fetching the objects needed if this is a partial clone.
+fn checkout(
+    repo: &Repository,
+    repo_url: &str,
+    remote: &RemoteState,
+    options: &CloneOptions,
+) -> Result<()> {
+    let tree = read_commit(repo, &remote.head)?.tree;
+
+    if options.filter.is_some() {
+        // Fetching a missing tree gets all its content, so this only loops
+        // if the filter excluded the root tree (tree:0).
+        loop {
+            let mut missing = Vec::new();
+            missing_objects(repo, &tree, options.sparse, &mut missing)?;
+            if missing.is_empty() {
+                break;
+            }
+            let request = FetchRequest {
+                wants: &missing,
+                ..Default::default()
+            };
+            fetch_pack(repo, repo_url, request, options.unpack)
+                .context("fetching objects for checkout")?;
+        }
+    }
+
+    let lock = lock_worktree(repo)?;
+    let root = repo.work_tree()?;
+    let tree_reader = repo.read_tree(&tree)?;
+    if options.sparse {
+        for entry in tree_reader.into_entries()? {
+            if !matches!(entry.mode, Mode::Dir) {
+                entry.actualise(repo, root)?;
+            }
+        }
+    } else {
+        tree_reader.actualise_entries(repo, root)?;
+    }
+
+    // The index has all paths, those in directories being marked as not
+    // checked out with sparse-checkout.
+    let mut files = PathList::new();
+    flatten_tree(repo, &tree, b"", &mut files)?;
+    let mut entries = Vec::new();
+    for (path, side) in &files {
+        let mut entry = IndexEntry::new(path, side, 0)?;
+        entry.skip_worktree = options.sparse && path.contains(&b'/');
+        entries.push(entry);
+    }
+    index::write(repo, lock, &entries)?;
+
+    let zero_hash = repo.hash_algo()?.zero_hash();
+    hooks::run(repo, "post-checkout", &[zero_hash, &remote.head, "1"])
+}
+
+/// Run all stages after the target directory has been chosen.
+fn run_stages(directory: &Path, repo_url: &str, options: &CloneOptions) -> Result<()> {
+    let repo = &init_repository(directory, repo_url, HashAlgo::Sha1, options)?;
+    let mut remote = discover(repo_url, options)?;
+    select_branch(&mut remote, options)?;
+    fetch_objects(repo, repo_url, &remote, options)?;
+    if options.single_branch {
+        follow_tags(repo, &mut remote)?;
+    }
+    if options.bare {
+        return write_bare_refs(repo, &remote);
+    }
+    write_refs(repo, repo_url, &remote, options.single_branch)?;
+    checkout(repo, repo_url, &remote, options).context("checking out HEAD")
+}
+
+/// Run all stages for a local source, given as its .git directory and the
+/// absolute path recorded as the URL of the remote, like git does.
+fn run_local_stages(
+    directory: &Path,
+    (source, url): &(PathBuf, PathBuf),
+    options: &CloneOptions,
+) -> Result<()> {
+    let object_format = match config::get_in(source, "extensions.objectformat")? {
+        Some(name) => HashAlgo::from_name(&name)?,
+        None => HashAlgo::Sha1,
+    };
+    let url = &url.to_string_lossy();
+    let repo = &init_repository(directory, url, object_format, options)?;
+    let mut remote = discover_local(repo, source)?;
+    select_branch(&mut remote, options)?;
+    link_objects(&source.join("objects"), repo.object_dir()).context("copying objects")?;
+    if options.single_branch {
+        follow_tags(repo, &mut remote)?;
+    }
+    if options.bare {
+        return write_bare_refs(repo, &remote);
+    }
+    write_refs(repo, url, &remote, options.single_branch)?;
+    checkout(repo, url, &remote, options).context("checking out HEAD")
+}
+
+/// Run all stages for a bundle file, which must have no prerequisites.
+/// It is recorded as a remote with its absolute path, like git does.
+fn run_bundle_stages(directory: &Path, bundle: &Path, options: &CloneOptions) -> Result<()> {
+    let bundle =
+        fs::canonicalize(bundle).with_context(|| format!("resolving {}", bundle.display()))?;
+    let url = &bundle.to_string_lossy();
+    let (header, pack) = bundle::open(&bundle)?;
+    if !header.prerequisites.is_empty() {
+        bail!("cannot clone from a bundle with prerequisites (an incremental bundle)");
+    }
+    let mut remote = discover_bundle(&header)?;
+    select_branch(&mut remote, options)?;
+    let repo = &init_repository(directory, url, HashAlgo::Sha1, options)?;
+    if options.unpack {
+        let nb_obj = unpack_from(repo, pack).context("unpacking objects")?;
+        println!("Unpacked {nb_obj} objects");
+    } else {
+        let (_, nb_obj) = store_pack(repo, pack).context("storing pack")?;
+        println!("Received {nb_obj} objects");
+    }
+    if options.single_branch {
+        follow_tags(repo, &mut remote)?;
+    }
+    if options.bare {
+        return write_bare_refs(repo, &remote);
+    }
+    write_refs(repo, url, &remote, options.single_branch)?;
+    checkout(repo, url, &remote, options).context("checking out HEAD")
+}
+
+/// Find the .git directory of a local repository to clone, given as a path
+/// (to a worktree or a bare repository), along with the absolute path to use
+/// as its URL; None if the source is a URL.
+pub(crate) fn local_source(repo_url: &str) -> Result<Option<(PathBuf, PathBuf)>> {
+    let path = Path::new(repo_url);
+    if repo_url.contains("://") || !path.exists() {
+        return Ok(None);
+    }
+    let path = fs::canonicalize(path).with_context(|| format!("resolving {repo_url}"))?;
+    let dot_git = path.join(".git");
+    if dot_git.is_dir() {
+        return Ok(Some((dot_git, path)));
+    }
+    // A .git file, in a submodule or a linked worktree: clone what it shares.
+    if dot_git.is_file() {
+        return Ok(Some((common_dir_of(&read_git_file(&dot_git)?), path)));
+    }
+    if !is_git_dir(&path) {
+        bail!("repository '{repo_url}' does not exist");
+    }
+    let url = match path.ends_with(".git") {
+        true => path.parent().expect(".git has a parent").to_owned(),
+        false => path.clone(),
+    };
+    Ok(Some((path, url)))
+}
+
+/// After an interruption, offer to remove what was created by the clone
+/// (the directory itself, or its content if it already existed).
+fn offer_removal(directory: &Path, created: bool) -> Result<()> {
+    if !io::stdin().is_terminal() {
+        return Ok(());
+    }
+    eprint!("Remove partially cloned {}? [y/N] ", directory.display());
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        return Ok(());
+    }
+    if created {
+        return fs::remove_dir_all(directory)
+            .with_context(|| format!("removing {}", directory.display()));
+    }
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() && !path.is_symlink() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        }
+        .with_context(|| format!("removing {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Clone a repository: see CloneOptions for what can be customised.
+pub fn clone(repo_url: &str, directory: Option<&Path>, options: &CloneOptions) -> Result<()> {
+    let bundle = is_bundle(Path::new(repo_url));
+    let source = match bundle {
+        true => None,
+        false => local_source(repo_url)?,
+    };
+    if bundle {
+        if options.depth.is_some() || options.filter.is_some() {
+            bail!("--depth and --filter are not supported when cloning a bundle");
+        }
+    } else if source.is_none() {
+        // Don't create anything if we won't be able to fetch.
+        ensure_online(repo_url)?;
+    } else if options.depth.is_some() || options.filter.is_some() || options.unpack {
+        bail!("--depth, --filter and --unpack are not supported when cloning a local repository");
+    }
+    let directory = &target_directory(repo_url, directory, options.bare)?;
+    println!("Cloning to {}", directory.display());
+
+    let created = !directory.exists();
+    let absolute = path::absolute(directory).context("resolving destination")?;
+    let res = match &source {
+        Some(source) => run_local_stages(directory, source, options),
+        None if bundle => run_bundle_stages(directory, Path::new(repo_url), options),
+        None => run_stages(directory, repo_url, options),
+    };
+    if res.is_err() && interrupt::interrupted() {
+        offer_removal(&absolute, created).context("cleaning up")?;
+    }
+    res
+}
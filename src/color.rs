@@ -0,0 +1,187 @@
+//! Colored output: when to use it, like git's --color option and color.ui,
+//! and escape sequences for colors given like in git's config, eg "red bold"
+//! or "#ff8000 ul".
+
+use anyhow::{bail, Result};
+use std::env;
+use std::io;
+use std::io::IsTerminal;
+
+use crate::config;
+use crate::pager;
+use crate::repository::Repository;
+
+/// Resets colors and attributes.
+pub const RESET: &str = "\x1b[m";
+
+/// When to color output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum When {
+    Never,
+    Always,
+    /// Only when writing to a terminal that can show colors
+    Auto,
+}
+
+/// Parse the value of a --color option: never, always or auto.
+pub fn parse_when(value: &str) -> Result<When> {
+    match value.to_lowercase().as_str() {
+        "never" => Ok(When::Never),
+        "always" => Ok(When::Always),
+        "auto" => Ok(When::Auto),
+        _ => bail!("option `color' expects \"always\", \"auto\", or \"never\""),
+    }
+}
+
+/// Parse a color.* setting: never, always, auto, or a boolean, true
+/// meaning auto like in git.
+fn parse_setting(key: &str, value: &str) -> Result<When> {
+    match parse_when(value) {
+        Ok(when) => Ok(when),
+        Err(_) => match config::parse_bool(key, value)? {
+            true => Ok(When::Auto),
+            false => Ok(When::Never),
+        },
+    }
+}
+
+/// Tell if standard output is a terminal that can show colors, that is
+/// with TERM set to something else than "dumb", like git (possibly through
+/// a pager).
+pub fn stdout_has_colors() -> bool {
+    (io::stdout().is_terminal() || pager::in_use())
+        && env::var_os("TERM").is_some_and(|term| term != "dumb")
+}
+
+/// Tell if the output of a command should be colored, from its --color
+/// option if given, else from the `key` setting (eg "color.diff"), else
+/// from color.ui, which is auto by default.
+pub fn use_color(repo: &Repository, option: Option<When>, key: &str) -> Result<bool> {
+    let when = match option {
+        Some(when) => when,
+        None => match config::get(repo, key)? {
+            Some(value) => parse_setting(key, &value)?,
+            None => match config::get(repo, "color.ui")? {
+                Some(value) => parse_setting("color.ui", &value)?,
+                None => When::Auto,
+            },
+        },
+    };
+    Ok(match when {
+        When::Never => false,
+        When::Always => true,
+        When::Auto => stdout_has_colors(),
+    })
+}
+
+/// The attributes that can be given for a color, with their codes to set and
+/// unset them (with a "no" or "no-" prefix).
+const ATTRIBUTES: [(&str, u8, u8); 7] = [
+    ("bold", 1, 22),
+    ("dim", 2, 22),
+    ("italic", 3, 23),
+    ("ul", 4, 24),
+    ("blink", 5, 25),
+    ("reverse", 7, 27),
+    ("strike", 9, 29),
+];
+
+/// The names of the 8 basic colors, in order of their codes.
+const NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// Parse one color, returning the parameters of its escape sequence for the
+/// foreground (empty for "normal"), or None if this is not a color. Those
+/// for the background are the same, with a 4 in place of the first 3 (or 10
+/// in place of the 9 of bright colors).
+fn parse_one(word: &str) -> Option<String> {
+    let word = word.to_lowercase();
+    if word == "normal" {
+        return Some(String::new());
+    }
+    if word == "default" {
+        return Some("39".to_owned());
+    }
+    if let Some(hex) = word.strip_prefix('#') {
+        if hex.len() != 6 || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(format!(
+            "38;2;{};{};{}",
+            component(0)?,
+            component(2)?,
+            component(4)?
+        ));
+    }
+    let (name, base) = match word.strip_prefix("bright") {
+        Some(name) => (name, 90),
+        None => (&word[..], 30),
+    };
+    if let Some(n) = NAMES.iter().position(|&known| known == name) {
+        return Some((base + n).to_string());
+    }
+    match word.parse::<i32>().ok()? {
+        -1 => Some(String::new()),
+        n @ 0..=7 => Some((30 + n).to_string()),
+        n @ 8..=15 => Some((90 + n - 8).to_string()),
+        n @ 16..=255 => Some(format!("38;5;{n}")),
+        _ => None,
+    }
+}
+
+/// Parse a color setting like git: "reset", or a foreground and a background
+/// color and attributes, separated by spaces, in any order. Colors are names
+/// (possibly with a "bright" prefix), "normal", "default", numbers for the
+/// 256 colors of terminals (-1 for normal) or "#rrggbb". Attributes are bold,
+/// dim, italic, ul, blink, reverse and strike (lowercase only, unlike colors),
+/// with a "no" prefix to unset them. Returns the escape sequence, empty if
+/// there is nothing to set.
+pub fn parse(value: &str) -> Result<String> {
+    if value.trim().eq_ignore_ascii_case("reset") {
+        return Ok(RESET.to_owned());
+    }
+    let mut colors: Vec<String> = Vec::new();
+    let mut codes = Vec::new();
+    for word in value.split_ascii_whitespace() {
+        if let Some(color) = parse_one(word) {
+            if colors.len() == 2 {
+                bail!("invalid color value: {value}");
+            }
+            colors.push(color);
+            continue;
+        }
+        let (name, negated) = match word.strip_prefix("no") {
+            Some(name) => (name.strip_prefix('-').unwrap_or(name), true),
+            None => (word, false),
+        };
+        let Some(&(_, set, unset)) = ATTRIBUTES.iter().find(|(known, ..)| *known == name) else {
+            bail!("invalid color value: {value}");
+        };
+        codes.push(if negated { unset } else { set });
+    }
+    codes.sort_unstable();
+    codes.dedup();
+    let mut params: Vec<String> = codes.iter().map(u8::to_string).collect();
+    for (i, color) in colors.into_iter().enumerate() {
+        match (i, color.is_empty()) {
+            (_, true) => {}
+            (0, false) => params.push(color),
+            (_, false) if color.starts_with('9') => params.push(format!("10{}", &color[1..])),
+            (_, false) => params.push(format!("4{}", &color[1..])),
+        }
+    }
+    Ok(match params.is_empty() {
+        true => String::new(),
+        false => format!("\x1b[{}m", params.join(";")),
+    })
+}
+
+/// Get a color from the config, or the default escape sequence.
+pub fn get(repo: &Repository, key: &str, default: &str) -> Result<String> {
+    match config::get(repo, key)? {
+        Some(value) => parse(&value),
+        None => Ok(default.to_owned()),
+    }
+}
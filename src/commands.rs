@@ -1,47 +1,123 @@
 //! Functions implementing each subcommand from the CLI.
 
-use anyhow::{bail, ensure, Context, Result};
+use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::slice;
 use std::str;
 use std::time;
 
-use crate::common::git_dir;
-use crate::network::{get_pack, ls_remote_head};
-use crate::obj_read::ObjReader;
+use crate::abbrev::{min_len, shorten, shorten_to};
+use crate::apply::apply_patches;
+use crate::archive::{resolve_tree_ish, write_archive, Format};
+use crate::attributes;
+use crate::blame;
+use crate::bundle::{self, check_prerequisites, is_bundle, list_heads};
+use crate::clean::CleanOptions;
+use crate::clone::{link_objects, local_source, CloneOptions};
+use crate::color::When;
+use crate::commit::{add_signature, split_ident, split_signature};
+use crate::common::{read_inputs, repo_path, wildmatch, write_error};
+use crate::config;
+use crate::count;
+use crate::date;
+use crate::dedup::dedup_report;
+use crate::diff::{
+    detect_renames, diff_lists, diff_to_worktree, diff_tree_to_index, diff_trees, flatten_tree,
+    print_diff, print_stat, rename_score, worktree_side, Change, DiffFormat, PathList, Side,
+    WordDiff, STAT_WIDTH,
+};
+use crate::dumb_http;
+use crate::extract::extract;
+use crate::fast_export;
+use crate::fast_import;
+use crate::fetch::{have_object, negotiate, plan_updates, Quarantine, UpdateKind};
+use crate::fsck::check_all;
+use crate::gc::{prunable, remove_loose, repack};
+use crate::grep::{self, Source};
+use crate::hooks;
+use crate::ignore::{self, Ignores};
+use crate::index::{self, IndexEntry};
+use crate::interrupt;
+use crate::json;
+use crate::lock::{lock_gc, lock_worktree, LockFile};
+use crate::ls_files::{untracked, LsFilesOptions};
+use crate::mail;
+use crate::mailmap::Mailmap;
+use crate::merge::{merge_trees, MergeResult};
+use crate::network::{
+    is_dumb_http, ls_refs, receive_pack_refs, send_pack, upload_archive, RefUpdate, RemoteRef,
+};
+use crate::notes;
+use crate::obj_read::{read_commit, read_tree, MAX_METADATA_SIZE};
 use crate::obj_type::ObjType;
-use crate::obj_write::write_object;
+use crate::obj_write::{write_object, ObjWriter};
+use crate::object_id::HashAlgo;
+use crate::pack_index;
+use crate::pack_write::write_pack;
+use crate::pager;
+use crate::platform;
+use crate::push::{
+    describe, hook_input, remote_value, short_name, tracking_ref, PushSpec, ZERO_HASH,
+};
+use crate::refs;
+use crate::refs_verify::verify;
+use crate::remote;
+use crate::repository::Repository;
+use crate::revision::resolve;
+use crate::revwalk::{
+    filtered_commits, is_ancestor, merge_base, merge_bases, peel, peel_to_commit,
+    reachable_commits, reachable_hashes, reachable_objects, Filter,
+};
+use crate::serve;
+use crate::shallow;
+use crate::show::show_objects;
+use crate::sign;
+use crate::status::StatusOptions;
+use crate::tag::{self, Tag};
+use crate::tree_entry::{Entry, Mode};
 use crate::tree_read::TreeReader;
-use crate::tree_write::tree_from_workdir;
+use crate::tree_write::{hash_blob, hash_file, tree_from_list, tree_from_workdir};
 use crate::unpack::unpack_from;
+use crate::worktree;
+use crate::worktrees;
 
 /// The "git init" command - partial implementation: git populates .git more fully.
-pub fn git_init(path: &Path) -> Result<()> {
-    let obj_dir = path.join(".git/objects");
-    fs::create_dir_all(&obj_dir).with_context(|| format!("creating {}", obj_dir.display()))?;
-    fs::create_dir_all(path.join(".git/refs/heads")).context("creating .git/refs/heads")?;
-    fs::write(path.join(".git/HEAD"), b"ref: refs/heads/main\n").context("creating .git/HEAD")?;
-
+/// Repositories using SHA-256 get a config file saying so.
+/// The new repository is returned, for clone to fill.
+pub fn git_init(path: &Path, object_format: HashAlgo, bare: bool) -> Result<Repository> {
+    let repo = Repository::init(path, object_format, bare)?;
     println!(
-        "Initialized empty Git repository in {}/.git/",
-        fs::canonicalize(path)?.display()
+        "Initialized empty Git repository in {}/",
+        repo.git_dir().display()
     );
-    Ok(())
+    Ok(repo)
 }
 
 /// The "cat-file -p" command.
-pub fn cat_file_p(hash: &str) -> Result<()> {
-    let mut object =
-        ObjReader::from_hash(hash).with_context(|| format!("opening object {hash}"))?;
+pub fn cat_file_p(repo: &Repository, rev: &str) -> Result<()> {
+    let hash = &resolve(repo, rev)?;
+    let mut object = repo
+        .read_object(hash)
+        .with_context(|| format!("opening object {hash}"))?;
     match object.obj_type {
         ObjType::Tree => {
             let tree = TreeReader::from_object(object)?;
-            tree.print_entries(false)
+            tree.print_entries(repo, false, false)
                 .with_context(|| format!("reading & printing tree object {hash}"))?;
         }
+        // Tags are printed as they are, like git, once checked.
+        ObjType::Tag => {
+            let data = object
+                .read_to_vec(MAX_METADATA_SIZE)
+                .with_context(|| format!("reading tag {hash}"))?;
+            Tag::parse(repo, &data, false).with_context(|| format!("parsing tag {hash}"))?;
+            io::stdout().write_all(&data)?;
+        }
         _ => {
             io::copy(&mut object, &mut io::stdout())
                 .with_context(|| format!("reading object {hash} to stdout"))?;
@@ -50,28 +126,261 @@ pub fn cat_file_p(hash: &str) -> Result<()> {
     Ok(())
 }
 
+/// The "cat-file --batch-check" command - partial implementation: no format.
+/// For each object (or revision) read from stdin, one per line, print its hash,
+/// type and size, or that it is missing; with --format=json, as records with
+/// those fields, or the name given and "missing": true.
+pub fn cat_file_batch_check(repo: &Repository) -> Result<()> {
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    for line in io::stdin().lock().lines() {
+        let name = line.context("reading object names from stdin")?;
+        let object = resolve(repo, &name).and_then(|hash| Ok((repo.read_object(&hash)?, hash)));
+        match (object, json::enabled()) {
+            (Ok((object, hash)), false) => writeln!(
+                stdout,
+                "{hash} {} {}",
+                object.obj_type.to_str(),
+                object.size
+            )?,
+            (Ok((object, hash)), true) => {
+                let fields = [
+                    ("hash", hash.as_str().into()),
+                    ("type", object.obj_type.to_str().into()),
+                    ("size", json::Value::Number(object.size as u64)),
+                ];
+                json::write_record(&mut stdout, &fields)?
+            }
+            (Err(_), false) => writeln!(stdout, "{name} missing")?,
+            (Err(_), true) => {
+                let fields = [
+                    ("name", name.as_str().into()),
+                    ("missing", json::Value::Bool(true)),
+                ];
+                json::write_record(&mut stdout, &fields)?
+            }
+        }
+    }
+    Ok(stdout.flush()?)
+}
+
+/// Check the signature of a payload, printing what the signing program says
+/// about it to stderr. Return whether it is good.
+fn verify_signature(repo: &Repository, payload: &[u8], signature: &str) -> Result<bool> {
+    let verification = sign::verify(repo, payload, signature)?;
+    eprint!("{}", verification.output);
+    Ok(verification.good)
+}
+
+/// The "verify-commit" command: check the signature of commits, printing
+/// what gpg or ssh-keygen says about them to stderr.
+/// Return false if one of them is not signed or the signature is not good
+/// (the exit status should then be 1).
+pub fn verify_commit(repo: &Repository, revs: &[String]) -> Result<bool> {
+    let mut good = true;
+    for rev in revs {
+        let hash = peel_to_commit(repo, &resolve(repo, rev)?)?;
+        let content = repo
+            .read_object(&hash)?
+            .read_to_vec(MAX_METADATA_SIZE)
+            .with_context(|| format!("reading commit {hash}"))?;
+        good &= match split_signature(repo, &content)? {
+            Some((payload, signature)) => verify_signature(repo, &payload, &signature)
+                .with_context(|| format!("checking the signature of {hash}"))?,
+            None => false,
+        };
+    }
+    Ok(good)
+}
+
+/// The "verify-tag" command: check the signature of annotated tags, like
+/// verify_commit().
+pub fn verify_tag(repo: &Repository, revs: &[String]) -> Result<bool> {
+    let mut good = true;
+    for rev in revs {
+        let hash = resolve(repo, rev)?;
+        let object = repo.read_object(&hash)?;
+        if object.obj_type != ObjType::Tag {
+            bail!(
+                "{rev}: cannot verify a non-tag object of type {}",
+                object.obj_type.to_str()
+            );
+        }
+        let content = object
+            .read_to_vec(MAX_METADATA_SIZE)
+            .with_context(|| format!("reading tag {hash}"))?;
+        good &= match tag::split_signature(&content) {
+            Some((payload, signature)) => {
+                let signature = str::from_utf8(signature).context("signature is not UTF-8")?;
+                verify_signature(repo, payload, signature)
+                    .with_context(|| format!("checking the signature of {hash}"))?
+            }
+            None => false,
+        };
+    }
+    Ok(good)
+}
+
+/// The "mktag" command: write a tag object read from stdin, after checking
+/// its format strictly and that it points to an existing object of the
+/// given type, and print its hash.
+pub fn mktag(repo: &Repository) -> Result<()> {
+    let mut data = Vec::new();
+    io::stdin()
+        .lock()
+        .read_to_end(&mut data)
+        .context("reading standard input")?;
+    let tag =
+        Tag::parse(repo, &data, true).context("tag on stdin did not pass our strict check")?;
+    let object = repo
+        .read_object(&tag.object)
+        .with_context(|| format!("could not read tagged object '{}'", tag.object))?;
+    if object.obj_type != tag.obj_type {
+        bail!(
+            "object '{}' tagged as '{}', but is a '{}' type",
+            tag.object,
+            tag.obj_type.to_str(),
+            object.obj_type.to_str()
+        );
+    }
+    let hash = write_object(
+        repo,
+        ObjType::Tag,
+        &mut io::Cursor::new(tag.to_bytes()),
+        true,
+    )
+    .context("writing tag object")?;
+    println!("{hash}");
+    Ok(())
+}
+
 /// The "hash-object [-w]" command.
-pub fn hash_object(file: &Path, write: bool) -> Result<()> {
-    let mut source = fs::File::open(file)
-        .with_context(|| format!("could not open {} for reading", file.display()))?;
-    let hash_hex = write_object(ObjType::Blob, &mut source, write).context("hashing object")?;
+pub fn hash_object(repo: &Repository, file: &Path, write: bool) -> Result<()> {
+    let hash_hex = hash_file(repo, file, write).context("hashing object")?;
     println!("{}", hash_hex);
     Ok(())
 }
 
-/// The "ls-tree [--name-only]" command.
-pub fn ls_tree(tree_hash: &str, name_only: bool) -> Result<()> {
-    let tree = TreeReader::from_hash(tree_hash)
+/// The "hash-object [-w] --stdin" command: the size of the content is not
+/// known in advance, so it goes to a temporary file first.
+pub fn hash_object_stdin(repo: &Repository, write: bool) -> Result<()> {
+    let mut object =
+        ObjWriter::new_unsized(repo, ObjType::Blob, write).context("creating object")?;
+    io::copy(&mut io::stdin().lock(), &mut object).context("reading standard input")?;
+    println!("{}", object.finish().context("hashing object")?);
+    Ok(())
+}
+
+/// The "mktree" command - partial implementation: no -z, --missing or --batch.
+/// Build a tree from entries read from stdin in the format of ls-tree,
+/// in any order, and print its hash.
+pub fn mktree(repo: &Repository) -> Result<()> {
+    let mut input = Vec::new();
+    io::stdin()
+        .lock()
+        .read_to_end(&mut input)
+        .context("reading standard input")?;
+    let input = input.strip_suffix(b"\n").unwrap_or(&input);
+    let mut entries = Vec::new();
+    if !input.is_empty() {
+        for line in input.split(|&c| c == b'\n') {
+            if line.is_empty() {
+                bail!("input format error: (blank line only valid in batch mode)");
+            }
+            entries.push(Entry::parse_listed(repo, line)?);
+        }
+    }
+    entries.sort_by(Entry::cmp_in_tree);
+    let mut tree = Vec::new();
+    for entry in &entries {
+        entry.push_to_vec(&mut tree);
+    }
+    let hash = write_object(repo, ObjType::Tree, &mut io::Cursor::new(tree), true)
+        .context("writing tree object")?;
+    println!("{hash}");
+    Ok(())
+}
+
+/// Find the subtree at the given path, if there is one.
+fn subtree(repo: &Repository, tree_hash: &str, path: &Path) -> Result<Option<String>> {
+    let mut tree_hash = tree_hash.to_owned();
+    for name in path {
+        let entry = read_tree(repo, &tree_hash)?
+            .into_iter()
+            .find(|e| e.mode == Mode::Dir && e.name[..] == *platform::bytes(name));
+        match entry {
+            Some(entry) => tree_hash = hex::encode(entry.hash),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(tree_hash))
+}
+
+/// The "ls-tree [--name-only] [--full-tree]" command: like git, when run from
+/// a subdirectory, only list that part of the tree unless --full-tree is given.
+pub fn ls_tree(
+    repo: &Repository,
+    tree_ish: &str,
+    name_only: bool,
+    full_tree: bool,
+    abbrev: bool,
+) -> Result<()> {
+    let mut tree_hash = tree_from_tree_ish(repo, &resolve(repo, tree_ish)?)?;
+    if !full_tree {
+        let Some(hash) = subtree(repo, &tree_hash, repo.prefix())? else {
+            return Ok(());
+        };
+        tree_hash = hash;
+    }
+    let tree_hash = &tree_hash;
+    let tree = repo
+        .read_tree(tree_hash)
         .with_context(|| format!("opening tree object {tree_hash}"))?;
-    tree.print_entries(name_only)
+    tree.print_entries(repo, name_only, abbrev)
         .with_context(|| format!("reading & printing tree object {tree_hash}"))?;
     Ok(())
 }
 
+/// The "ls-files" command, see LsFilesOptions for what is listed.
+pub fn ls_files(repo: &Repository, options: &LsFilesOptions) -> Result<()> {
+    crate::ls_files::ls_files(repo, options)
+}
+
+/// The "add -p" command - partial implementation: only patch mode, see
+/// crate::add.
+pub fn add_patch(repo: &Repository, paths: &[PathBuf]) -> Result<()> {
+    crate::add::add_patch(repo, paths)
+}
+
+/// The "clean" command, see crate::clean.
+pub fn clean(repo: &Repository, paths: &[PathBuf], options: &CleanOptions) -> Result<()> {
+    crate::clean::clean(repo, paths, options)
+}
+
+/// The "status --porcelain=v2" command, see crate::status.
+pub fn status(repo: &Repository, options: &StatusOptions) -> Result<()> {
+    crate::status::status(repo, options)
+}
+
+/// The "rm [--cached] [-r] [-f]" command.
+pub fn rm(
+    repo: &Repository,
+    paths: &[PathBuf],
+    cached: bool,
+    recursive: bool,
+    force: bool,
+) -> Result<()> {
+    crate::rm_mv::rm(repo, paths, cached, recursive, force)
+}
+
+/// The "mv" command.
+pub fn mv(repo: &Repository, from: &Path, to: &Path) -> Result<()> {
+    crate::rm_mv::mv(repo, from, to)
+}
+
 /// The "write-tree" command, except it takes the tree directly from the filesystem,
 /// bypassing the index. Also, no support for .gitignore either.
-pub fn write_tree() -> Result<()> {
-    let hash = tree_from_workdir()?;
+pub fn write_tree(repo: &Repository) -> Result<()> {
+    let hash = tree_from_workdir(repo, false)?;
     println!("{hash}");
     Ok(())
 }
@@ -102,128 +411,3090 @@ fn get_env_date_or_current(var_name: &str) -> String {
     format!("{timestamp} +0000")
 }
 
-/// The "commit-tree" command, except no support for config: author and commiter details
-/// taken either from enviornment variables, or hardcoded defaults.
-/// Also, no support for time zones.
-pub fn commit_tree(tree: &str, parents: &[String], messages: &[String]) -> Result<()> {
-    let auth_name = get_env_or("GIT_AUTHOR_NAME", "Author Name");
-    let auth_mail = get_env_or("GIT_AUTHOR_EMAIL", "author@example.org");
-    let comm_name = get_env_or("GIT_COMMITTER_NAME", "Committer Name");
-    let comm_mail = get_env_or("GIT_COMMITTER_EMAIL", "committer@example.org");
+/// The committer identity and date, as used in commits and reflogs.
+pub fn committer() -> String {
+    let name = get_env_or("GIT_COMMITTER_NAME", "Committer Name");
+    let mail = get_env_or("GIT_COMMITTER_EMAIL", "committer@example.org");
+    let date = get_env_date_or_current("GIT_COMMITTER_DATE");
+    format!("{name} <{mail}> {date}")
+}
+
+/// The author identity and date, as used in new commits.
+fn author() -> String {
+    let name = get_env_or("GIT_AUTHOR_NAME", "Author Name");
+    let mail = get_env_or("GIT_AUTHOR_EMAIL", "author@example.org");
+    let date = get_env_date_or_current("GIT_AUTHOR_DATE");
+    format!("{name} <{mail}> {date}")
+}
+
+/// Create a commit object and return its hash. Each message is a paragraph.
+fn write_commit(
+    repo: &Repository,
+    tree: &str,
+    parents: &[String],
+    messages: &[String],
+) -> Result<String> {
+    let paragraphs: Vec<String> = messages.iter().map(|m| format!("{m}\n")).collect();
+    write_commit_message(
+        repo,
+        tree,
+        parents,
+        &author(),
+        paragraphs.join("\n").as_bytes(),
+    )
+}
 
-    let auth_date = get_env_date_or_current("GIT_AUTHOR_DATE");
-    let comm_date = get_env_date_or_current("GIT_COMMITTER_DATE");
+/// Create a commit object with the given author (identity and date) and the
+/// message exactly as given, and return its hash. Like git, stashes have no
+/// final newline, and cherry-picked commits keep the original message.
+fn write_commit_message(
+    repo: &Repository,
+    tree: &str,
+    parents: &[String],
+    author: &str,
+    message: &[u8],
+) -> Result<String> {
+    let content = commit_content(tree, parents, author, message)?;
+    write_object(repo, ObjType::Commit, &mut io::Cursor::new(content), true)
+        .context("writing out commit object")
+}
 
+/// Give the content of a commit object, see write_commit_message().
+fn commit_content(tree: &str, parents: &[String], author: &str, message: &[u8]) -> Result<Vec<u8>> {
     let mut content = Vec::new();
     writeln!(content, "tree {tree}").context("writing commit contents (tree)")?;
     for p in parents {
         writeln!(content, "parent {p}").context("writing commit contents (parent)")?;
     }
-    writeln!(content, "author {auth_name} <{auth_mail}> {auth_date}")
-        .context("writing commit contents (author)")?;
-    writeln!(content, "committer {comm_name} <{comm_mail}> {comm_date}")
+    writeln!(content, "author {author}").context("writing commit contents (author)")?;
+    writeln!(content, "committer {}", committer())
         .context("writing commit contents (committer)")?;
-    for m in messages {
-        writeln!(content, "\n{m}").context("writing commit contents (message)")?;
+    if !message.is_empty() {
+        content.push(b'\n');
+        content.extend_from_slice(message);
+    }
+    Ok(content)
+}
+
+/// What the "config" command does.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ConfigAction {
+    /// Show the value of a key, the last one if there are several
+    Get,
+    /// Show all the values of a key
+    GetAll,
+    /// Set the value of a key, which must not have several
+    Set,
+    /// Add a value to a key, keeping the others
+    Add,
+    /// Show all variables with their values
+    List,
+}
+
+/// Format a value from the config for the "config" command, as the given type.
+fn format_config_value(key: &str, value: Option<&str>, value_type: Option<&str>) -> Result<String> {
+    Ok(match (value_type, value) {
+        (None, value) => value.unwrap_or_default().to_owned(),
+        (Some("bool"), None) => "true".to_owned(),
+        (Some("bool"), Some(value)) => config::parse_bool(key, value)?.to_string(),
+        (Some("int"), None) => bail!("missing value for '{key}'"),
+        (Some("int"), Some(value)) => config::parse_int(key, value)?.to_string(),
+        (Some(other), _) => bail!("unrecognized --type argument, {other}"),
+    })
+}
+
+/// The "config" command - partial implementation: only .git/config (no
+/// --global, --system or --file), no value patterns, no --unset or
+/// --replace-all. Setting a value keeps comments and other lines as they are.
+/// With `value_type` ("bool" or "int"), values are checked, and shown in
+/// canonical form, eg "1k" as 1024. Returns false if the key to get is missing.
+pub fn config(
+    repo: &Repository,
+    action: ConfigAction,
+    key: Option<&str>,
+    value: Option<&str>,
+    value_type: Option<&str>,
+) -> Result<bool> {
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    match (action, key, value) {
+        (ConfigAction::List, None, None) => {
+            for (key, value) in config::list(repo)? {
+                match value {
+                    Some(value) => {
+                        let value = format_config_value(&key, Some(&value), value_type)?;
+                        writeln!(stdout, "{key}={value}")?
+                    }
+                    None => writeln!(stdout, "{key}")?,
+                }
+            }
+        }
+        (ConfigAction::Get | ConfigAction::GetAll, Some(key), None) => {
+            let mut values = config::get_values(repo, key)?;
+            if action == ConfigAction::Get {
+                values = values.pop().into_iter().collect();
+            }
+            if values.is_empty() {
+                return Ok(false);
+            }
+            for value in values {
+                let value = format_config_value(key, value.as_deref(), value_type)?;
+                writeln!(stdout, "{value}")?;
+            }
+        }
+        (ConfigAction::Set | ConfigAction::Add, Some(key), Some(value)) => {
+            // Check the value, but store it as given.
+            format_config_value(key, Some(value), value_type)?;
+            if action == ConfigAction::Set {
+                config::set(repo, key, value)?;
+            } else {
+                config::add(repo, key, value)?;
+            }
+        }
+        (ConfigAction::List, _, _) => bail!("--list takes no arguments"),
+        (_, None, _) => bail!("missing name of the configuration variable"),
+        (ConfigAction::Get | ConfigAction::GetAll, _, Some(_)) => {
+            bail!("value patterns are not supported")
+        }
+        (ConfigAction::Set | ConfigAction::Add, _, None) => bail!("missing value to set"),
     }
+    stdout.flush()?;
+    Ok(true)
+}
 
-    let hash = write_object(ObjType::Commit, &mut io::Cursor::new(content), true)
+/// The "commit-tree" command: author and commiter details are taken either from
+/// enviornment variables, or hardcoded defaults, not from the config.
+/// Also, no support for time zones.
+/// Without any -m, the message is read from stdin and used as is, like git.
+/// With `sign_key`, the commit is signed with that key, or the default one if
+/// empty, see sign::signing_key(). Without, it is signed if commit.gpgSign is
+/// set, unless `no_sign`.
+pub fn commit_tree(
+    repo: &Repository,
+    tree_ish: &str,
+    parents: &[String],
+    messages: &[String],
+    sign_key: Option<&str>,
+    no_sign: bool,
+) -> Result<()> {
+    let tree = tree_from_tree_ish(repo, &resolve(repo, tree_ish)?)?;
+    let parents = parents
+        .iter()
+        .map(|rev| peel_to_commit(repo, &resolve(repo, rev)?))
+        .collect::<Result<Vec<_>>>()?;
+    let message = match messages {
+        [] => {
+            let mut message = Vec::new();
+            io::stdin()
+                .lock()
+                .read_to_end(&mut message)
+                .context("reading commit message from standard input")?;
+            message
+        }
+        _ => {
+            let paragraphs: Vec<String> = messages.iter().map(|m| format!("{m}\n")).collect();
+            paragraphs.join("\n").into_bytes()
+        }
+    };
+    let content = commit_content(&tree, &parents, &author(), &message)?;
+    let content = sign_commit(repo, content, sign_key, no_sign)?;
+    let hash = write_object(repo, ObjType::Commit, &mut io::Cursor::new(content), true)
         .context("writing out commit object")?;
     println!("{hash}");
     Ok(())
 }
 
-fn tree_from_commit(commit_hash: &str) -> Result<String> {
-    let mut commit = ObjReader::from_hash(commit_hash)
-        .with_context(|| format!("opening object {commit_hash}"))?;
-    let line = commit
-        .read_up_to(b'\n')
-        .with_context(|| format!("reading from object {commit_hash}"))?;
-    let line = str::from_utf8(&line).with_context(|| format!("malformed commit {commit_hash}"))?;
-    let Some(("tree", tree_hash)) = line.split_once(' ') else {
-        bail!("malformed commit {commit_hash}: no tree in first line");
+/// Sign the content of a new commit if asked to with `sign_key` (with the
+/// default key if empty), or by commit.gpgSign unless `no_sign`.
+fn sign_commit(
+    repo: &Repository,
+    content: Vec<u8>,
+    sign_key: Option<&str>,
+    no_sign: bool,
+) -> Result<Vec<u8>> {
+    let sign_key = match sign_key {
+        Some(key) => key,
+        None if !no_sign && config::get_bool(repo, "commit.gpgSign")?.unwrap_or(false) => "",
+        None => return Ok(content),
     };
-    Ok(tree_hash.into())
+    let committer = committer();
+    let key = sign::signing_key(repo, Some(sign_key), split_ident(&committer).0)?;
+    let signature = sign::sign(repo, &content, &key).context("signing commit")?;
+    add_signature(repo, &content, &signature)
 }
 
-/// The "checkout-empty" (made up) command - a bit like "checkout" except:
-/// - assumes the working directory is empty, and will overwrite files otherwise;
-/// - always leaves us with a detached HEAD;
-/// - only accepts an unabbreviate commit hash (no branch name etc.).
-pub fn checkout_empty(commit_hash: &str) -> Result<()> {
-    let tree_hash = tree_from_commit(commit_hash)
-        .with_context(|| format!("getting tree hash from commit {commit_hash}"))?;
-    let tree = TreeReader::from_hash(&tree_hash)
-        .with_context(|| format!("opening tree object {tree_hash}"))?;
-    let root = git_dir()?.parent().expect(".git has a parent");
-    tree.actualise_entries(root)
-        .with_context(|| format!("checking out to {}", root.display()))?;
-
-    fs::write(git_dir()?.join("HEAD"), commit_hash).context("updating HEAD")?;
+/// Get the commit being merged, if a merge with conflicts is in progress.
+fn merge_head(repo: &Repository) -> Result<Option<String>> {
+    match fs::read_to_string(repo.git_dir().join("MERGE_HEAD")) {
+        Ok(hash) => Ok(Some(hash.trim_end().to_owned())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context("reading MERGE_HEAD"),
+    }
+}
 
+/// Forget about a merge, cherry-pick or revert in progress: remove MERGE_HEAD,
+/// CHERRY_PICK_HEAD, REVERT_HEAD and MERGE_MSG.
+fn remove_merge_state(repo: &Repository) -> Result<()> {
+    for name in ["MERGE_HEAD", "CHERRY_PICK_HEAD", "REVERT_HEAD", "MERGE_MSG"] {
+        let path = repo.git_dir().join(name);
+        fs::remove_file(&path)
+            .or_else(|e| match e.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            })
+            .with_context(|| format!("removing {}", path.display()))?;
+    }
     Ok(())
 }
 
-/// The "unpack-objects" command - does not support ofs-delta deltified objects.
-pub fn unpack_objects() -> Result<()> {
-    let nb_obj = unpack_from(io::stdin().lock()).context("unpacking from stdin")?;
-    println!("Unpacked {nb_obj} objects");
+/// Replace the content of the index with the result of a merge: merged paths
+/// at stage 0, and the three versions of conflicted paths.
+fn write_index_with_conflicts(
+    repo: &Repository,
+    lock: LockFile,
+    result: &MergeResult,
+) -> Result<()> {
+    let mut entries = Vec::new();
+    for (path, side) in &result.merged {
+        entries.push(IndexEntry::new(path, side, 0)?);
+    }
+    for conflict in &result.conflicts {
+        let sides = [&conflict.base, &conflict.ours, &conflict.theirs];
+        for (stage, side) in (1..).zip(sides) {
+            if let Some(side) = side {
+                entries.push(IndexEntry::new(&conflict.path, side, stage)?);
+            }
+        }
+    }
+    entries.sort_by(|a, b| (&a.path, a.stage).cmp(&(&b.path, b.stage)));
+    index::write(repo, lock, &entries)
+}
+
+/// Replace the content of the index, with all paths at stage 0, when files
+/// in the worktree may not match it. Those that don't keep no file system
+/// data from the worktree, so git looks at them. Return their paths.
+fn write_index_unrefreshed(
+    repo: &Repository,
+    lock: LockFile,
+    list: &PathList,
+) -> Result<Vec<Vec<u8>>> {
+    let root = repo.work_tree()?;
+    let mut entries = Vec::new();
+    let mut unrefreshed = Vec::new();
+    for (path, side) in list {
+        let mut entry = IndexEntry::new(path, side, 0)?;
+        let in_worktree = worktree_side(repo, root, path, side)?;
+        if !in_worktree.is_some_and(|w| w.mode == side.mode && w.hash == side.hash) {
+            entry.stat = Some([0; 9]);
+            unrefreshed.push(entry.path.clone());
+        }
+        entries.push(entry);
+    }
+    index::write(repo, lock, &entries)?;
+    Ok(unrefreshed)
+}
+
+/// Replace the content of the index, with all paths at stage 0.
+fn write_index_from_list(repo: &Repository, lock: LockFile, list: &PathList) -> Result<()> {
+    let entries = list
+        .iter()
+        .map(|(path, side)| IndexEntry::new(path, side, 0))
+        .collect::<Result<Vec<_>>>()?;
+    index::write(repo, lock, &entries)
+}
+
+/// Update the worktree and the index from a tree to a list of paths
+/// with their content (sorted in index order). Return the changes made.
+fn switch_worktree(
+    repo: &Repository,
+    lock: LockFile,
+    from_tree: &str,
+    to: PathList,
+) -> Result<Vec<Change>> {
+    let mut from = PathList::new();
+    flatten_tree(repo, from_tree, b"", &mut from)?;
+    let changes = diff_lists(from, to.clone());
+    worktree::apply(repo, &changes)?;
+    write_index_from_list(repo, lock, &to)?;
+    Ok(changes)
+}
+
+/// Print a summary of changes made to the worktree: the number of files
+/// changed and lines inserted or deleted, and which files were created
+/// or deleted.
+fn print_changes(repo: &Repository, changes: &[Change]) -> Result<()> {
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    print_stat(repo, changes, STAT_WIDTH, &mut stdout)?;
+    stdout.flush()?;
     Ok(())
 }
 
-/// The "ls-remote" command - can only list HEAD.
-pub fn ls_remote(repo_url: &str, pattern: &str) -> Result<()> {
-    ensure!(pattern == "HEAD", "ls-remote only implemented for HEAD");
-    let (hash, _) = ls_remote_head(repo_url).context("listing remote head")?;
-    println!("{hash}\tHEAD");
+/// The "snapshot" (made up) command - commit the whole working directory,
+/// except ignored files, on top of HEAD and move the current branch to it.
+/// Like write-tree followed by commit-tree, without going through the index.
+///
+/// If a merge with conflicts is in progress, this concludes it with a merge
+/// commit, and the index (where conflicts were recorded) is reset to match it.
+/// Same for a cherry-pick or revert with conflicts, with a regular commit.
+///
+/// Like git commit, the pre-commit hook runs first, then the commit-msg hook
+/// with the path of .git/COMMIT_EDITMSG, which holds the message and may be
+/// edited by the hook; either can abort by failing, unless `verify` is false.
+/// The post-commit hook runs once the branch is updated, and can't abort.
+///
+/// The commit is signed with the default key if commit.gpgSign is set.
+pub fn snapshot(repo: &Repository, messages: &[String], verify: bool) -> Result<()> {
+    if verify {
+        hooks::run(repo, "pre-commit", &[])?;
+    }
+    let merge_head = merge_head(repo)?;
+    let git_dir = repo.git_dir();
+    let picking = ["CHERRY_PICK_HEAD", "REVERT_HEAD"]
+        .iter()
+        .any(|name| git_dir.join(name).exists());
+    let lock = (merge_head.is_some() || picking)
+        .then(|| lock_worktree(repo))
+        .transpose()?;
+    let tree = tree_from_workdir(repo, true)?;
+    let parent = refs::resolve(repo, "HEAD")?;
+    if let (Some(parent), None) = (&parent, &merge_head) {
+        if tree_from_commit(repo, parent)? == tree {
+            println!("nothing to snapshot, working directory unchanged");
+            return Ok(());
+        }
+    }
+    let parents: Vec<String> = parent.iter().chain(&merge_head).cloned().collect();
+    let paragraphs: Vec<String> = messages.iter().map(|m| format!("{m}\n")).collect();
+    let mut message = paragraphs.join("\n").into_bytes();
+    let msg_path = git_dir.join("COMMIT_EDITMSG");
+    fs::write(&msg_path, &message).with_context(|| format!("writing {}", msg_path.display()))?;
+    if verify {
+        hooks::run(repo, "commit-msg", &[&msg_path.to_string_lossy()])?;
+        message = fs::read(&msg_path).with_context(|| format!("reading {}", msg_path.display()))?;
+        if message.iter().all(u8::is_ascii_whitespace) {
+            bail!("aborting commit due to empty commit message");
+        }
+    }
+    let content = commit_content(&tree, &parents, &author(), &message)?;
+    let content = sign_commit(repo, content, None, false)?;
+    let hash = write_object(repo, ObjType::Commit, &mut io::Cursor::new(content), true)
+        .context("writing out commit object")?;
+
+    let message = String::from_utf8_lossy(&message);
+    let subject = message.lines().next().unwrap_or_default();
+    let (action, root) = match (&parent, &merge_head) {
+        (None, _) => ("snapshot (initial)", " (root-commit)"),
+        (Some(_), Some(_)) => ("snapshot (merge)", ""),
+        (Some(_), None) => ("snapshot", ""),
+    };
+    let reflog_message = format!("{action}: {subject}");
+    refs::update(
+        repo,
+        "HEAD",
+        parent.as_deref(),
+        &hash,
+        &committer(),
+        &reflog_message,
+    )?;
+    if let Some(lock) = lock {
+        let mut list = PathList::new();
+        flatten_tree(repo, &tree, b"", &mut list)?;
+        write_index_from_list(repo, lock, &list)?;
+        remove_merge_state(repo)?;
+    }
+
+    let branch = refs::current_branch(repo)?;
+    let branch = branch.as_deref().map_or("detached HEAD", |b| {
+        b.strip_prefix("refs/heads/").unwrap_or(b)
+    });
+    println!("[{branch}{root} {}] {subject}", shorten(repo, &hash)?);
+    // The commit is done: a failing post-commit hook only gets a warning.
+    if let Err(e) = hooks::run(repo, "post-commit", &[]) {
+        eprintln!("warning: {e:#}");
+    }
     Ok(())
 }
 
-// This seems to be roughly what git is doing based on experiments.
-fn dir_from_repo_url(url: &str) -> &Path {
-    let url = url.trim_end_matches("/");
-    let url = url.trim_end_matches(".git");
-    let url = url.trim_end_matches("/");
-    let last = url
-        .rsplit("/")
+/// The "git merge" command - partial implementation: merge a single branch
+/// or commit, no options. The worktree must match HEAD, as checked by
+/// comparing it to HEAD's tree (so untracked files count as changes).
+///
+/// Conflicts are recorded in the index and in files with markers, like git;
+/// once they are resolved, use snapshot to conclude the merge.
+pub fn merge(repo: &Repository, name: &str) -> Result<()> {
+    if merge_head(repo)?.is_some() {
+        bail!("you have not concluded your merge (MERGE_HEAD exists): snapshot the result first");
+    }
+    let (full_name, theirs) = match refs::dwim(repo, name)? {
+        Some((full_name, hash)) => (Some(full_name), hash),
+        None => (None, resolve(repo, name)?),
+    };
+    let Some(ours) = refs::resolve(repo, "HEAD")? else {
+        bail!("nothing to merge into: HEAD has no commits yet");
+    };
+    let lock = lock_worktree(repo)?;
+    let ours_tree = tree_from_commit(repo, &ours)?;
+    if tree_from_workdir(repo, true)? != ours_tree {
+        bail!("the working directory has changes not in HEAD: snapshot them first");
+    }
+
+    let base = merge_base(repo, &ours, &theirs)?;
+    if base.as_deref() == Some(&theirs[..]) {
+        println!("Already up to date.");
+        return Ok(());
+    }
+    let theirs_tree = tree_from_commit(repo, &theirs)?;
+    if base.as_deref() == Some(&ours[..]) {
+        let mut to = PathList::new();
+        flatten_tree(repo, &theirs_tree, b"", &mut to)?;
+        let changes = switch_worktree(repo, lock, &ours_tree, to)?;
+        let reflog_message = format!("merge {name}: Fast-forward");
+        refs::update(
+            repo,
+            "HEAD",
+            Some(&ours),
+            &theirs,
+            &committer(),
+            &reflog_message,
+        )?;
+        println!(
+            "Updating {}..{}",
+            shorten(repo, &ours)?,
+            shorten(repo, &theirs)?
+        );
+        println!("Fast-forward");
+        return print_changes(repo, &changes);
+    }
+
+    let base_tree = base
+        .as_deref()
+        .map(|commit| tree_from_commit(repo, commit))
+        .transpose()?;
+    let result = merge_trees(
+        repo,
+        base_tree.as_deref(),
+        &ours_tree,
+        &theirs_tree,
+        "HEAD",
+        name,
+    )?;
+    let kind = full_name.as_deref().and_then(|n| n.strip_prefix("refs/"));
+    let message = match kind {
+        Some(n) if n.starts_with("heads/") => format!("Merge branch '{name}'"),
+        Some(n) if n.starts_with("remotes/") => format!("Merge remote-tracking branch '{name}'"),
+        Some(n) if n.starts_with("tags/") => format!("Merge tag '{name}'"),
+        _ => format!("Merge commit '{name}'"),
+    };
+
+    if result.conflicts.is_empty() {
+        let tree = tree_from_list(repo, &result.merged)?;
+        let commit = write_commit(repo, &tree, &[ours.clone(), theirs], &[message])?;
+        let changes = switch_worktree(repo, lock, &ours_tree, result.merged)?;
+        let reflog_message = format!("merge {name}: Merge made by the three-way strategy.");
+        refs::update(
+            repo,
+            "HEAD",
+            Some(&ours),
+            &commit,
+            &committer(),
+            &reflog_message,
+        )?;
+        println!("Merge made by the three-way strategy.");
+        return print_changes(repo, &changes);
+    }
+
+    // Update the worktree, and record the three versions of conflicted paths.
+    let mut from = PathList::new();
+    flatten_tree(repo, &ours_tree, b"", &mut from)?;
+    worktree::apply(repo, &diff_lists(from, result.worktree()))?;
+    write_index_with_conflicts(repo, lock, &result)?;
+    fs::write(repo.git_dir().join("MERGE_HEAD"), format!("{theirs}\n"))
+        .context("writing MERGE_HEAD")?;
+    fs::write(repo.git_dir().join("MERGE_MSG"), format!("{message}\n"))
+        .context("writing MERGE_MSG")?;
+    bail!("Automatic merge failed; fix conflicts and then snapshot the result.");
+}
+
+/// The "git cherry-pick" command - partial implementation: a single commit,
+/// no options. The changes the commit made to its parent are applied on top
+/// of HEAD with a three-way merge, and committed with the original author
+/// and message. The worktree must match HEAD, like for merge.
+///
+/// Conflicts are recorded in the index and in files with markers, like git;
+/// once they are resolved, use snapshot to commit the result.
+pub fn cherry_pick(repo: &Repository, rev: &str) -> Result<()> {
+    pick_commit(repo, rev, false)
+}
+
+/// The "git revert" command - partial implementation: a single commit,
+/// no options. Like cherry-pick, except the changes are undone, and
+/// committed as "Revert ..." by the current author.
+pub fn revert(repo: &Repository, rev: &str) -> Result<()> {
+    pick_commit(repo, rev, true)
+}
+
+/// Apply the changes made by a commit on top of HEAD, or undo them if
+/// `revert` is set, and commit the result: see cherry_pick() and revert().
+fn pick_commit(repo: &Repository, rev: &str, revert: bool) -> Result<()> {
+    let commit = peel_to_commit(repo, &resolve(repo, rev)?)?;
+    let action = if revert { "revert" } else { "cherry-pick" };
+    let Some(head) = refs::resolve(repo, "HEAD")? else {
+        bail!("cannot {action} onto nothing: HEAD has no commits yet");
+    };
+    let lock = lock_worktree(repo)?;
+    let head_tree = tree_from_commit(repo, &head)?;
+    if tree_from_workdir(repo, true)? != head_tree {
+        bail!("the working directory has changes not in HEAD: snapshot them first");
+    }
+    let picked = read_commit(repo, &commit)?;
+    if picked.parents.len() > 1 {
+        bail!("commit {commit} is a merge, {action} of merges is not supported");
+    }
+    let parent_tree = match picked.parents.first() {
+        Some(parent) => tree_from_commit(repo, parent)?,
+        None => tree_from_list(repo, &[])?,
+    };
+    let subject = picked
+        .message
+        .split(|&c| c == b'\n')
         .next()
-        .expect("always at least one component");
-    Path::new(last)
+        .unwrap_or_default();
+    let subject = String::from_utf8_lossy(subject).into_owned();
+    let short = shorten(repo, &commit)?;
+    // Reverting is applying the changes from the commit to its parent.
+    let (base, theirs, their_name) = match revert {
+        false => (parent_tree, picked.tree, format!("{short} ({subject})")),
+        true => (
+            picked.tree,
+            parent_tree,
+            format!("parent of {short} ({subject})"),
+        ),
+    };
+    let result = merge_trees(repo, Some(&base), &head_tree, &theirs, "HEAD", &their_name)?;
+    let (author, message) = match revert {
+        false => {
+            let author = format!(
+                "{} {} {}",
+                picked.author, picked.author_time, picked.author_zone
+            );
+            (author, picked.message)
+        }
+        true => {
+            let message = format!("Revert \"{subject}\"\n\nThis reverts commit {commit}.\n");
+            (author(), message.into_bytes())
+        }
+    };
+
+    if !result.conflicts.is_empty() {
+        let mut from = PathList::new();
+        flatten_tree(repo, &head_tree, b"", &mut from)?;
+        worktree::apply(repo, &diff_lists(from, result.worktree()))?;
+        write_index_with_conflicts(repo, lock, &result)?;
+        let state = if revert {
+            "REVERT_HEAD"
+        } else {
+            "CHERRY_PICK_HEAD"
+        };
+        fs::write(repo.git_dir().join(state), format!("{commit}\n"))
+            .with_context(|| format!("writing {state}"))?;
+        fs::write(repo.git_dir().join("MERGE_MSG"), &message).context("writing MERGE_MSG")?;
+        bail!(
+            "could not {action} {short}... {subject}: fix conflicts and then snapshot the result."
+        );
+    }
+
+    let tree = tree_from_list(repo, &result.merged)?;
+    if tree == head_tree {
+        bail!("the changes from {short} are already in HEAD: nothing to {action}");
+    }
+    let new = write_commit_message(repo, &tree, slice::from_ref(&head), &author, &message)?;
+    let changes = switch_worktree(repo, lock, &head_tree, result.merged)?;
+    let new_subject = message.split(|&c| c == b'\n').next().unwrap_or_default();
+    let new_subject = String::from_utf8_lossy(new_subject);
+    let reflog_message = format!("{action}: {new_subject}");
+    refs::update(
+        repo,
+        "HEAD",
+        Some(&head),
+        &new,
+        &committer(),
+        &reflog_message,
+    )?;
+
+    let branch = refs::current_branch(repo)?;
+    let branch = branch.as_deref().map_or("detached HEAD", short_name);
+    println!("[{branch} {}] {new_subject}", shorten(repo, &new)?);
+    print_changes(repo, &changes)
 }
 
-/// The "clone" command. Unlike the real one, it unpacks all object to loose storage.
-/// Also, only gets the default branch, not other refs.
-pub fn clone(repo_url: &str, directory: Option<impl AsRef<Path>>) -> Result<()> {
-    let directory = match &directory {
-        Some(d) => d.as_ref(),
-        None => dir_from_repo_url(repo_url),
+/// Where rebase saves what to restore if it stops, like git.
+const REBASE_DIR: &str = "rebase-merge";
+
+/// Stop a rebase: check out the branch (or commit) it started from, as saved
+/// in .git/rebase-merge, from the commit it got to, and forget about it.
+fn abort_rebase(repo: &Repository, current: &str, current_tree: &str) -> Result<()> {
+    let dir = repo.git_dir().join(REBASE_DIR);
+    let read = |name: &str| -> Result<String> {
+        let path = dir.join(name);
+        let value =
+            fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        Ok(value.trim_end().to_owned())
     };
-    println!("Cloning to {}", directory.display());
+    let head_name = read("head-name")?;
+    let orig_head = read("orig-head")?;
+    let mut list = PathList::new();
+    flatten_tree(repo, &tree_from_commit(repo, &orig_head)?, b"", &mut list)?;
+    switch_worktree(repo, lock_worktree(repo)?, current_tree, list)?;
+    let reflog_message = format!("rebase (abort): returning to {head_name}");
+    refs::update_no_deref(
+        repo,
+        "HEAD",
+        Some(current),
+        &orig_head,
+        &committer(),
+        &reflog_message,
+    )?;
+    if head_name.starts_with("refs/") {
+        refs::write_symbolic(repo, "HEAD", &head_name)?;
+    }
+    fs::remove_dir_all(&dir).with_context(|| format!("removing {}", dir.display()))
+}
 
-    if directory.exists() {
-        if !directory.is_dir() {
-            bail!("destination exists and is not a directory");
+/// The "git format-patch" command - partial implementation: write the commits
+/// in `since..HEAD`, or in a range like `a..b`, as emails in mbox format,
+/// oldest first and skipping merges. Each goes in its own file, in the
+/// current directory or `output_dir`, whose name is printed, or they all
+/// go to standard output if `to_stdout`.
+///
+/// Emails end with a signature: format.signature, or the name and version
+/// of this program; `signature` replaces it, and is empty to omit it.
+pub fn format_patch(
+    repo: &Repository,
+    range: &str,
+    output_dir: Option<&Path>,
+    to_stdout: bool,
+    signature: Option<&str>,
+) -> Result<()> {
+    let (tips, exclude) = match range.split_once("..") {
+        Some((from, to)) => {
+            let to = if to.is_empty() { "HEAD" } else { to };
+            (vec![resolve(repo, to)?], vec![resolve(repo, from)?])
         }
-        let mut entries = directory
-            .read_dir()
-            .context("checking destination is empty")?;
-        if entries.next().is_some() {
-            bail!("destination exists and is not empty");
+        None => (vec![resolve(repo, "HEAD")?], vec![resolve(repo, range)?]),
+    };
+    let mut commits = Vec::new();
+    for hash in reachable_commits(repo, &tips, &exclude)?.into_iter().rev() {
+        let commit = read_commit(repo, &hash)?;
+        if commit.parents.len() <= 1 {
+            commits.push((hash, commit));
         }
     }
+    let signature = match signature {
+        Some(signature) => signature.to_owned(),
+        None => match config::get(repo, "format.signature")? {
+            Some(signature) => signature,
+            None => format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        },
+    };
+    let signature = Some(signature.as_str()).filter(|s| !s.is_empty());
+    if let Some(dir) = output_dir {
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
 
-    git_init(directory).context("initializing git directory")?;
-    env::set_current_dir(directory)
-        .with_context(|| format!("changing working directory to {}", directory.display()))?;
+    let total = commits.len();
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    for (n, (hash, commit)) in (1..).zip(&commits) {
+        let parent = commit.parents.first().map(|p| tree_from_commit(repo, p));
+        let changes = diff_trees(repo, parent.transpose()?.as_deref(), &commit.tree)?;
+        let number = (total > 1).then_some((n, total));
+        if to_stdout {
+            // Like git, a blank line separates the emails.
+            if n > 1 {
+                writeln!(stdout)?;
+            }
+            mail::write_patch(repo, &mut stdout, hash, commit, number, &changes, signature)?;
+            continue;
+        }
+        let name = mail::file_name(n, &commit.message);
+        let path = output_dir.map_or_else(|| PathBuf::from(&name), |dir| dir.join(&name));
+        let mut out = Vec::new();
+        mail::write_patch(repo, &mut out, hash, commit, number, &changes, signature)?;
+        fs::write(&path, out).with_context(|| format!("writing {}", path.display()))?;
+        writeln!(stdout, "{}", path.display())?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
 
-    let (head, branch) = ls_remote_head(repo_url).context("listing remote head")?;
-    let pack = get_pack(repo_url, &head).context("fetching objects")?;
-    let nb_obj = unpack_from(pack).context("unpacking objects")?;
-    println!("Unpacked {nb_obj} objects");
+/// The "git am" command - partial implementation: apply the patches in
+/// mailboxes (as written by format-patch, standard input if none) one by
+/// one on top of HEAD, committing each with the author, date and message
+/// of its email.
+///
+/// Unlike git, there is no --continue, --skip or --abort: a patch which
+/// doesn't apply stops the command with nothing changed for it, and the
+/// patches before it stay committed.
+pub fn am(repo: &Repository, mailboxes: &[PathBuf]) -> Result<()> {
+    // Each file is a mailbox, with one or more emails.
+    let mut inputs = Vec::new();
+    if mailboxes.is_empty() {
+        inputs.push(read_inputs(&[])?);
+    }
+    for mailbox in mailboxes {
+        inputs.push(read_inputs(slice::from_ref(mailbox))?);
+    }
+    let mails: Vec<&[u8]> = inputs.iter().flat_map(|i| mail::split_mbox(i)).collect();
+    if mails.is_empty() {
+        bail!("no patches found in the input");
+    }
+    let lock = lock_worktree(repo)?;
+    let mut head = refs::resolve(repo, "HEAD")?;
+    let mut head_tree = match &head {
+        Some(head) => tree_from_commit(repo, head)?,
+        None => tree_from_list(repo, &[])?,
+    };
+    if tree_from_workdir(repo, true)? != head_tree {
+        bail!("the working directory has changes not in HEAD: snapshot them first");
+    }
+    let mut applied = || -> Result<()> {
+        for (n, mail) in (1..).zip(&mails) {
+            let mail = mail::parse(mail).with_context(|| format!("reading patch {n:04}"))?;
+            let subject = mail
+                .message
+                .split(|&c| c == b'\n')
+                .next()
+                .unwrap_or_default();
+            let subject = String::from_utf8_lossy(subject).into_owned();
+            println!("Applying: {subject}");
+            apply_patches(repo, &mail.patch, false, false, 1, None)
+                .with_context(|| format!("patch failed at {n:04} {subject}"))?;
+            let tree = tree_from_workdir(repo, true)?;
+            let author = format!("{} {} {}", mail.author, mail.time, mail.zone);
+            let parents: Vec<String> = head.iter().cloned().collect();
+            let new = write_commit_message(repo, &tree, &parents, &author, &mail.message)?;
+            let reflog_message = format!("am: {subject}");
+            refs::update(
+                repo,
+                "HEAD",
+                head.as_deref(),
+                &new,
+                &committer(),
+                &reflog_message,
+            )?;
+            (head, head_tree) = (Some(new), tree);
+        }
+        Ok(())
+    };
+    let result = applied();
+    // The index follows what was committed, even if a patch failed.
+    let mut list = PathList::new();
+    flatten_tree(repo, &head_tree, b"", &mut list)?;
+    write_index_from_list(repo, lock, &list)?;
+    result
+}
+
+/// The "git rebase" command - partial implementation: no options.
+/// Replay the commits of the current branch that are not in `upstream` on
+/// top of it, one by one like cherry-pick, then move the branch to the
+/// result. Like git, merge commits, and commits whose changes are already
+/// upstream, are dropped.
+///
+/// Unlike git, a conflict aborts the rebase: the branch is left alone, and
+/// HEAD and the worktree are restored from what was saved in .git/rebase-merge.
+pub fn rebase(repo: &Repository, upstream: &str) -> Result<()> {
+    let onto = peel_to_commit(repo, &resolve(repo, upstream)?)?;
+    let Some(orig_head) = refs::resolve(repo, "HEAD")? else {
+        bail!("nothing to rebase: HEAD has no commits yet");
+    };
+    let dir = repo.git_dir().join(REBASE_DIR);
+    if dir.exists() {
+        bail!(
+            "{} exists: is another rebase running? (if not, remove it to continue)",
+            dir.display()
+        );
+    }
+    let lock = lock_worktree(repo)?;
+    let orig_tree = tree_from_commit(repo, &orig_head)?;
+    if tree_from_workdir(repo, true)? != orig_tree {
+        bail!("the working directory has changes not in HEAD: snapshot them first");
+    }
+    let branch = refs::current_branch(repo)?;
+    let head_name = branch.as_deref().unwrap_or("detached HEAD");
+    if is_ancestor(repo, &onto, &orig_head)? {
+        println!("Current branch {} is up to date.", short_name(head_name));
+        return Ok(());
+    }
+    let mut commits = reachable_commits(repo, slice::from_ref(&orig_head), slice::from_ref(&onto))?;
+    commits.reverse();
+
+    fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    for (name, value) in [
+        ("head-name", head_name),
+        ("orig-head", &orig_head),
+        ("onto", &onto),
+    ] {
+        fs::write(dir.join(name), format!("{value}\n"))
+            .with_context(|| format!("writing {}", dir.join(name).display()))?;
+    }
+
+    // Check out upstream (detached), then pick commits on top of it.
+    let mut current = onto.clone();
+    let mut current_tree = tree_from_commit(repo, &onto)?;
+    let mut list = PathList::new();
+    flatten_tree(repo, &current_tree, b"", &mut list)?;
+    switch_worktree(repo, lock, &orig_tree, list)?;
+    let reflog_message = format!("rebase (start): checkout {upstream}");
+    refs::update_no_deref(
+        repo,
+        "HEAD",
+        Some(&orig_head),
+        &onto,
+        &committer(),
+        &reflog_message,
+    )?;
+    for commit in commits {
+        let picked = read_commit(repo, &commit)?;
+        if picked.parents.len() > 1 {
+            continue;
+        }
+        let subject = picked
+            .message
+            .split(|&c| c == b'\n')
+            .next()
+            .unwrap_or_default();
+        let subject = String::from_utf8_lossy(subject).into_owned();
+        let short = shorten(repo, &commit)?;
+        let base = picked
+            .parents
+            .first()
+            .map(|p| tree_from_commit(repo, p))
+            .transpose()?;
+        let their_name = format!("{short} ({subject})");
+        let result = merge_trees(
+            repo,
+            base.as_deref(),
+            &current_tree,
+            &picked.tree,
+            "HEAD",
+            &their_name,
+        )?;
+        if !result.conflicts.is_empty() {
+            abort_rebase(repo, &current, &current_tree)?;
+            bail!("could not apply {short}... {subject}: rebase aborted, nothing was changed");
+        }
+        let tree = tree_from_list(repo, &result.merged)?;
+        if tree == current_tree {
+            println!("dropping {commit} {subject} -- patch contents already upstream");
+            continue;
+        }
+        let author = format!(
+            "{} {} {}",
+            picked.author, picked.author_time, picked.author_zone
+        );
+        let new = write_commit_message(
+            repo,
+            &tree,
+            slice::from_ref(&current),
+            &author,
+            &picked.message,
+        )?;
+        switch_worktree(repo, lock_worktree(repo)?, &current_tree, result.merged)?;
+        let reflog_message = format!("rebase (pick): {subject}");
+        refs::update_no_deref(
+            repo,
+            "HEAD",
+            Some(&current),
+            &new,
+            &committer(),
+            &reflog_message,
+        )?;
+        (current, current_tree) = (new, tree);
+    }
+
+    // Move the branch to the result, and HEAD back to the branch.
+    if let Some(branch) = &branch {
+        let reflog_message = format!("rebase (finish): {branch} onto {onto}");
+        refs::update(
+            repo,
+            branch,
+            Some(&orig_head),
+            &current,
+            &committer(),
+            &reflog_message,
+        )?;
+        let reflog_message = format!("rebase (finish): returning to {branch}");
+        refs::update_no_deref(
+            repo,
+            "HEAD",
+            Some(&current),
+            &current,
+            &committer(),
+            &reflog_message,
+        )?;
+        refs::write_symbolic(repo, "HEAD", branch)?;
+    }
+    fs::remove_dir_all(&dir).with_context(|| format!("removing {}", dir.display()))?;
+    println!("Successfully rebased and updated {head_name}.");
+    Ok(())
+}
+
+fn tree_from_commit(repo: &Repository, commit_hash: &str) -> Result<String> {
+    Ok(read_commit(repo, commit_hash)?.tree)
+}
+
+/// Get the tree hash from either a commit or a tree hash.
+fn tree_from_tree_ish(repo: &Repository, hash: &str) -> Result<String> {
+    let object = repo
+        .read_object(hash)
+        .with_context(|| format!("opening object {hash}"))?;
+    match object.obj_type {
+        ObjType::Tree => Ok(hash.into()),
+        ObjType::Commit => tree_from_commit(repo, hash),
+        _ => bail!("{hash} is neither a commit nor a tree"),
+    }
+}
+
+/// The "extract" (made up) command - export the paths matching pathspecs
+/// from a commit or tree to a destination directory, without touching HEAD
+/// or the working directory.
+pub fn extract_paths(
+    repo: &Repository,
+    tree_ish: &str,
+    pathspecs: &[PathBuf],
+    dest: &Path,
+) -> Result<()> {
+    let tree_hash = tree_from_tree_ish(repo, &resolve(repo, tree_ish)?)?;
+    let nb_files = extract(repo, &tree_hash, pathspecs, dest)
+        .with_context(|| format!("extracting to {}", dest.display()))?;
+    println!("Extracted {nb_files} files");
+    Ok(())
+}
+
+/// Print the entries of the reflog of a reference, most recent first,
+/// like git reflog. `name` is how the user designated the reference.
+fn print_reflog(
+    repo: &Repository,
+    full_name: &str,
+    name: &str,
+    max_count: Option<usize>,
+) -> Result<()> {
+    let log = refs::read_reflog(repo, full_name)?;
+    let max_count = max_count.unwrap_or(usize::MAX);
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    for (i, entry) in log.iter().rev().take(max_count).enumerate() {
+        let hash = shorten(repo, &entry.new)?;
+        writeln!(stdout, "{hash} {name}@{{{i}}}: {}", entry.message)?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// The "git reflog [show]" command - partial implementation: no options except -n.
+pub fn reflog(repo: &Repository, name: &str, max_count: Option<usize>) -> Result<()> {
+    match refs::dwim(repo, name)? {
+        Some((full_name, _)) => print_reflog(repo, &full_name, name, max_count),
+        None => bail!("unknown reference {name}"),
+    }
+}
 
-    fs::write(".git/HEAD", format!("ref: refs/heads/{branch}\n")).context("updating HEAD")?;
-    fs::write(format!(".git/refs/heads/{branch}"), &head)
-        .with_context(|| format!("updating branch {branch}"))?;
+/// Number of positions shown by undo --list.
+const UNDO_LIST_LEN: usize = 10;
 
-    checkout_empty(&head).context("checking out HEAD")
+/// The "undo" (made up, experimental) command - move HEAD, and the branch it
+/// points to, back to a previous position from its reflog: `steps` positions
+/// back, so undoing again (with 1 step) redoes what was undone. With `worktree`,
+/// the working directory and index are restored too; they must match HEAD.
+///
+/// With `list`, only show the recent positions, like git reflog.
+pub fn undo(repo: &Repository, steps: usize, list: bool, worktree: bool) -> Result<()> {
+    if list {
+        return print_reflog(repo, "HEAD", "HEAD", Some(UNDO_LIST_LEN));
+    }
+    let log = refs::read_reflog(repo, "HEAD")?;
+    if merge_head(repo)?.is_some() {
+        bail!("a merge is in progress (MERGE_HEAD exists): snapshot the result first");
+    }
+    let Some(current) = refs::resolve(repo, "HEAD")? else {
+        bail!("nothing to undo: HEAD has no commits yet");
+    };
+    let Some(entry) = log.iter().rev().nth(steps) else {
+        bail!(
+            "cannot go back {steps} steps: the reflog of HEAD only has {} entries",
+            log.len()
+        );
+    };
+    let target = &entry.new;
+
+    let mut changes = None;
+    if worktree {
+        let lock = lock_worktree(repo)?;
+        let current_tree = tree_from_commit(repo, &current)?;
+        if tree_from_workdir(repo, true)? != current_tree {
+            bail!("the working directory has changes not in HEAD: snapshot them first");
+        }
+        let mut to = PathList::new();
+        flatten_tree(repo, &tree_from_commit(repo, target)?, b"", &mut to)?;
+        changes = Some(switch_worktree(repo, lock, &current_tree, to)?);
+    }
+    let reflog_message = format!("undo: moving to HEAD@{{{steps}}}");
+    refs::update(
+        repo,
+        "HEAD",
+        Some(&current),
+        target,
+        &committer(),
+        &reflog_message,
+    )?;
+
+    print_head_is_now_at(repo, target)?;
+    match changes {
+        Some(changes) => print_changes(repo, &changes),
+        None => Ok(()),
+    }
+}
+
+/// What reset updates besides the current branch, see git-reset(1).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ResetMode {
+    /// Only move the branch
+    Soft,
+    /// Also reset the index
+    Mixed,
+    /// Also reset the index and the working directory
+    Hard,
+}
+
+/// Print the subject of a commit after its short hash, like git does
+/// when it moves HEAD.
+fn print_head_is_now_at(repo: &Repository, hash: &str) -> Result<()> {
+    let message = read_commit(repo, hash)?.message;
+    let subject = message.split(|&c| c == b'\n').next().unwrap_or_default();
+    let subject = String::from_utf8_lossy(subject);
+    println!("HEAD is now at {} {subject}", shorten(repo, hash)?);
+    Ok(())
+}
+
+/// The "git reset [--soft | --mixed | --hard]" command - partial
+/// implementation: no paths. Move HEAD, and the branch it points to, to a
+/// commit; with --mixed (the default) or --hard, also reset the index to it,
+/// and with --hard, also files in the working directory.
+///
+/// Like git, --hard loses changes to tracked files but leaves untracked ones
+/// alone, and --mixed lists the files that differ from the new index.
+pub fn reset(repo: &Repository, rev: &str, mode: ResetMode) -> Result<()> {
+    let lock = match mode {
+        ResetMode::Soft => None,
+        _ => Some(lock_worktree(repo)?),
+    };
+    let target = peel_to_commit(repo, &resolve(repo, rev)?)?;
+    let current = refs::resolve(repo, "HEAD")?;
+    if mode == ResetMode::Soft && merge_head(repo)?.is_some() {
+        bail!("cannot do a soft reset in the middle of a merge");
+    }
+
+    if let Some(lock) = lock {
+        let root = repo.work_tree()?;
+        let mut to = PathList::new();
+        flatten_tree(repo, &tree_from_commit(repo, &target)?, b"", &mut to)?;
+        if mode == ResetMode::Hard {
+            // Compare with what is actually in the worktree, at the paths
+            // of the index and of the target.
+            let mut tracked: Vec<(Vec<u8>, Side)> = index::read(repo)?
+                .into_iter()
+                .map(|e| {
+                    let side = Side {
+                        mode: e.mode,
+                        hash: hex::encode(e.hash),
+                        file: None,
+                    };
+                    (e.path, side)
+                })
+                .chain(to.iter().cloned())
+                .collect();
+            tracked.sort_by(|a, b| a.0.cmp(&b.0));
+            tracked.dedup_by(|a, b| a.0 == b.0);
+            let mut from = PathList::new();
+            for (path, side) in tracked {
+                if let Some(side) = worktree_side(repo, root, &path, &side)? {
+                    from.push((path, side));
+                }
+            }
+            worktree::apply(repo, &diff_lists(from, to.clone()))?;
+            write_index_from_list(repo, lock, &to)?;
+        } else {
+            let unstaged = write_index_unrefreshed(repo, lock, &to)?;
+            if !unstaged.is_empty() {
+                println!("Unstaged changes after reset:");
+                for path in &unstaged {
+                    let status = match platform::join(root, path).exists() {
+                        true => 'M',
+                        false => 'D',
+                    };
+                    println!("{status}\t{}", String::from_utf8_lossy(path));
+                }
+            }
+        }
+        remove_merge_state(repo)?;
+    }
+
+    let reflog_message = format!("reset: moving to {rev}");
+    refs::update(
+        repo,
+        "HEAD",
+        current.as_deref(),
+        &target,
+        &committer(),
+        &reflog_message,
+    )?;
+    if mode == ResetMode::Hard {
+        print_head_is_now_at(repo, &target)?;
+    }
+    Ok(())
+}
+
+/// Where stashes are kept: the latest in the reference, the others in its reflog.
+const STASH_REF: &str = "refs/stash";
+
+/// Describe where HEAD is, for stash messages: "main: 1234abc Subject".
+fn describe_head(repo: &Repository, head: &str) -> Result<String> {
+    let branch = refs::current_branch(repo)?;
+    let branch = branch.as_deref().map_or("(no branch)", short_name);
+    let message = read_commit(repo, head)?.message;
+    let subject = message.split(|&c| c == b'\n').next().unwrap_or_default();
+    let subject = String::from_utf8_lossy(subject);
+    Ok(format!("{branch}: {} {subject}", shorten(repo, head)?))
+}
+
+/// The "git stash [push]" command - partial implementation: only tracked
+/// files, and no paths. Save the index and the changes to tracked files
+/// in the worktree, then reset both to HEAD. Untracked files are left alone.
+///
+/// Like git, a stash is a commit of the worktree whose parents are HEAD and
+/// a commit of the index, and the latest stash is refs/stash while the others
+/// are in its reflog.
+pub fn stash_push(repo: &Repository, message: Option<&str>) -> Result<()> {
+    let lock = lock_worktree(repo)?;
+    let root = repo.work_tree()?;
+    let Some(head) = refs::resolve(repo, "HEAD")? else {
+        bail!("you do not have the initial commit yet");
+    };
+    let mut head_list = PathList::new();
+    flatten_tree(repo, &tree_from_commit(repo, &head)?, b"", &mut head_list)?;
+    let mut index_list = PathList::new();
+    let mut worktree_list = PathList::new();
+    for entry in index::read(repo)? {
+        if entry.stage != 0 {
+            bail!("cannot save the current index state: there are conflicts");
+        }
+        let side = Side {
+            mode: entry.mode,
+            hash: hex::encode(entry.hash),
+            file: None,
+        };
+        if let Some(mut current) = worktree_side(repo, root, &entry.path, &side)? {
+            if let Some(file) = current.file.as_ref().filter(|_| current.hash != side.hash) {
+                current.hash = hash_blob(repo, file, &fs::symlink_metadata(file)?)?;
+            }
+            worktree_list.push((entry.path.clone(), current));
+        }
+        index_list.push((entry.path, side));
+    }
+    if diff_lists(head_list.clone(), index_list.clone()).is_empty()
+        && diff_lists(index_list.clone(), worktree_list.clone()).is_empty()
+    {
+        println!("No local changes to save");
+        return Ok(());
+    }
+
+    let description = describe_head(repo, &head)?;
+    let index_tree = tree_from_list(repo, &index_list)?;
+    let index_message = format!("index on {description}");
+    let index_commit = write_commit(repo, &index_tree, slice::from_ref(&head), &[index_message])?;
+    let message = match message {
+        Some(message) => {
+            let (branch, _) = description
+                .split_once(':')
+                .expect("described HEAD has a ':'");
+            format!("On {branch}: {message}")
+        }
+        None => format!("WIP on {description}"),
+    };
+    let worktree_tree = tree_from_list(repo, &worktree_list)?;
+    let parents = [head, index_commit];
+    let stash = write_commit_message(
+        repo,
+        &worktree_tree,
+        &parents,
+        &author(),
+        message.as_bytes(),
+    )?;
+    let old = refs::resolve(repo, STASH_REF)?;
+    refs::update(
+        repo,
+        STASH_REF,
+        old.as_deref(),
+        &stash,
+        &committer(),
+        &message,
+    )?;
+
+    worktree::apply(repo, &diff_lists(worktree_list, head_list.clone()))?;
+    write_index_from_list(repo, lock, &head_list)?;
+    println!("Saved working directory and index state {message}");
+    Ok(())
+}
+
+/// The "git stash list" command: stashes, most recent first.
+pub fn stash_list(repo: &Repository) -> Result<()> {
+    for (i, entry) in refs::read_reflog(repo, STASH_REF)?.iter().rev().enumerate() {
+        println!("stash@{{{i}}}: {}", entry.message);
+    }
+    Ok(())
+}
+
+/// Find a stash, given as stash@{N} or N (the latest if None), in the reflog
+/// of refs/stash. Return its position and its commit.
+fn find_stash(repo: &Repository, stash: Option<&str>) -> Result<(usize, String)> {
+    let log = refs::read_reflog(repo, STASH_REF)?;
+    if log.is_empty() {
+        bail!("no stash entries found");
+    }
+    let Some(stash) = stash else {
+        return Ok((0, log[log.len() - 1].new.clone()));
+    };
+    let n = stash
+        .strip_prefix("stash@{")
+        .and_then(|n| n.strip_suffix('}'))
+        .unwrap_or(stash);
+    let n: usize = n
+        .parse()
+        .with_context(|| format!("{stash} is not a stash reference"))?;
+    match log.iter().rev().nth(n) {
+        Some(entry) => Ok((n, entry.new.clone())),
+        None => bail!(
+            "stash@{{{n}}} does not exist: there are {} stashes",
+            log.len()
+        ),
+    }
+}
+
+/// The "git stash drop" command: forget a stash.
+pub fn stash_drop(repo: &Repository, stash: Option<&str>) -> Result<()> {
+    let (n, hash) = find_stash(repo, stash)?;
+    refs::drop_reflog_entry(repo, STASH_REF, n)?;
+    println!("Dropped refs/stash@{{{n}}} ({hash})");
+    Ok(())
+}
+
+/// The "worktree add" command: create a linked worktree at `path`, checking
+/// out a branch (`<commit-ish>` if it is one, a new one given with -b, or by
+/// default a branch named after the directory, created if needed), or else
+/// a detached HEAD.
+pub fn worktree_add(
+    repo: &Repository,
+    path: &Path,
+    commit_ish: Option<&str>,
+    new_branch: Option<&str>,
+    detach: bool,
+) -> Result<()> {
+    worktrees::check_path(path)?;
+    let Some(name) = path.file_name() else {
+        bail!("invalid worktree path {}", path.display());
+    };
+    // The branch to check out, and whether it must be created
+    let branch = match (new_branch, commit_ish) {
+        (Some(branch), _) => Some((branch.to_owned(), true)),
+        (None, _) if detach => None,
+        (None, Some(rev)) => {
+            refs::resolve(repo, &format!("refs/heads/{rev}"))?.map(|_| (rev.to_owned(), false))
+        }
+        (None, None) => {
+            let branch = name.to_string_lossy().into_owned();
+            let exists = refs::resolve(repo, &format!("refs/heads/{branch}"))?.is_some();
+            Some((branch, !exists))
+        }
+    };
+    let start = peel_to_commit(repo, &resolve(repo, commit_ish.unwrap_or("HEAD"))?)?;
+    let head = match branch {
+        Some((branch, create)) => {
+            let full_name = format!("refs/heads/{branch}");
+            if create {
+                eprintln!("Preparing worktree (new branch '{branch}')");
+                if !refs::is_valid_name(&full_name) {
+                    bail!("'{branch}' is not a valid branch name");
+                }
+                if refs::resolve(repo, &full_name)?.is_some() {
+                    bail!("a branch named '{branch}' already exists");
+                }
+                let message = format!("branch: Created from {}", commit_ish.unwrap_or("HEAD"));
+                refs::update(repo, &full_name, None, &start, &committer(), &message)?;
+            } else {
+                eprintln!("Preparing worktree (checking out '{branch}')");
+                if let Some(worktree) = worktrees::checked_out(repo, &full_name)? {
+                    bail!(
+                        "'{branch}' is already checked out at '{}'",
+                        worktree.path.display()
+                    );
+                }
+            }
+            format!("ref: {full_name}")
+        }
+        None => {
+            eprintln!(
+                "Preparing worktree (detached HEAD {})",
+                shorten(repo, &start)?
+            );
+            start
+        }
+    };
+    let output = worktrees::add(repo, path, &head)?;
+    io::stdout().write_all(&output).context("writing to stdout")
+}
+
+/// The "worktree list" command: show the worktrees with their HEAD and
+/// branch, or with --porcelain in a format for scripts.
+pub fn worktree_list(repo: &Repository, porcelain: bool) -> Result<()> {
+    let worktrees = worktrees::list(repo)?;
+    let width = worktrees
+        .iter()
+        .map(|worktree| worktree.path.as_os_str().len())
+        .max()
+        .unwrap_or_default();
+    for worktree in worktrees {
+        let path = worktree.path.display();
+        let hash = refs::resolve(repo, &worktree.head)?
+            .unwrap_or(repo.hash_algo()?.zero_hash().to_owned());
+        let branch = refs::read_symbolic(repo, &worktree.head)?;
+        if porcelain {
+            println!("worktree {path}");
+            match (&branch, worktree.bare) {
+                (_, true) => println!("bare"),
+                (Some(branch), _) => println!("HEAD {hash}\nbranch {branch}"),
+                (None, _) => println!("HEAD {hash}\ndetached"),
+            }
+            println!();
+            continue;
+        }
+        let path = format!("{path:width$}", width = width + 1);
+        match (branch, worktree.bare) {
+            (_, true) => println!("{path} (bare)"),
+            (Some(branch), _) => println!(
+                "{path} {} [{}]",
+                shorten(repo, &hash)?,
+                branch.strip_prefix("refs/heads/").unwrap_or(&branch)
+            ),
+            (None, _) => println!("{path} {} (detached HEAD)", shorten(repo, &hash)?),
+        }
+    }
+    Ok(())
+}
+
+/// The "worktree remove" command: delete a linked worktree, which must be
+/// clean unless --force is given.
+pub fn worktree_remove(repo: &Repository, path: &Path, force: bool) -> Result<()> {
+    worktrees::remove(repo, path, force)
+}
+
+/// Get the notes reference for a notes command: the one given, or the default.
+fn notes_ref(repo: &Repository, name: Option<&str>) -> Result<String> {
+    match name {
+        Some(name) => Ok(notes::expand_ref(name)),
+        None => notes::default_ref(repo),
+    }
+}
+
+/// The "git notes add" command - partial implementation: the note is given
+/// with -m, there is no editor. An empty note removes the existing one.
+pub fn notes_add(
+    repo: &Repository,
+    notes_ref_name: Option<&str>,
+    object: &str,
+    messages: &[String],
+    force: bool,
+) -> Result<()> {
+    let notes_ref = notes_ref(repo, notes_ref_name)?;
+    notes::check_ref(&notes_ref)?;
+    let object = resolve(repo, object)?;
+    let mut notes = notes::read(repo, &notes_ref)?;
+    if notes.contains_key(&object) && !force {
+        bail!(
+            "Cannot add notes. Found existing notes for object {object}. \
+             Use '-f' to overwrite existing notes"
+        );
+    }
+    let paragraphs: Vec<String> = messages
+        .iter()
+        .map(|m| m.trim_end())
+        .filter(|m| !m.is_empty())
+        .map(|m| format!("{m}\n"))
+        .collect();
+    let message = if paragraphs.is_empty() {
+        if notes.remove(&object).is_none() {
+            return Ok(());
+        }
+        eprintln!("Removing note for object {object}");
+        "Notes removed by 'git notes add'"
+    } else {
+        let blob = paragraphs.join("\n").into_bytes();
+        let blob = write_object(repo, ObjType::Blob, &mut io::Cursor::new(blob), true)?;
+        notes.insert(object, blob);
+        "Notes added by 'git notes add'"
+    };
+    let tree = notes::write_tree(repo, &notes)?;
+    let old = refs::resolve(repo, &notes_ref)?;
+    let parents: Vec<String> = old.iter().cloned().collect();
+    let commit = write_commit(repo, &tree, &parents, &[message.to_owned()])?;
+    refs::update(
+        repo,
+        &notes_ref,
+        old.as_deref(),
+        &commit,
+        &committer(),
+        &format!("notes: {message}"),
+    )
+}
+
+/// The "git notes show" command: print the note of an object.
+pub fn notes_show(repo: &Repository, notes_ref_name: Option<&str>, object: &str) -> Result<()> {
+    let object = resolve(repo, object)?;
+    let Some(blob) = notes::find(repo, &notes_ref(repo, notes_ref_name)?, &object)? else {
+        bail!("no note found for object {object}.");
+    };
+    cat_file_p(repo, &blob)
+}
+
+/// The "git notes list" command: print the hash of each note and of the object
+/// it annotates, or only the note of the given object.
+pub fn notes_list(
+    repo: &Repository,
+    notes_ref_name: Option<&str>,
+    object: Option<&str>,
+) -> Result<()> {
+    let notes = notes::read(repo, &notes_ref(repo, notes_ref_name)?)?;
+    let mut out = io::stdout().lock();
+    match object {
+        Some(object) => {
+            let object = resolve(repo, object)?;
+            let Some(blob) = notes.get(&object) else {
+                bail!("no note found for object {object}.");
+            };
+            writeln!(out, "{blob}")?;
+        }
+        None => {
+            for (object, blob) in notes {
+                writeln!(out, "{blob} {object}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The "git stash apply" and "git stash pop" commands - partial implementation:
+/// the index and tracked files in the worktree must match HEAD, and the index
+/// of the stash is not restored. Changes from the stash are merged into HEAD
+/// with a three-way merge, and left in the worktree; like git, files the stash
+/// added are added to the index too.
+///
+/// With `pop`, the stash is dropped afterwards, unless there were conflicts.
+pub fn stash_apply(repo: &Repository, stash: Option<&str>, pop: bool) -> Result<()> {
+    let lock = lock_worktree(repo)?;
+    let (n, hash) = find_stash(repo, stash)?;
+    let Some(head) = refs::resolve(repo, "HEAD")? else {
+        bail!("you do not have the initial commit yet");
+    };
+    let head_tree = tree_from_commit(repo, &head)?;
+    let unclean = !diff_tree_to_index(repo, Some(&head_tree))?.is_empty()
+        || !diff_to_worktree(repo, None)?.is_empty();
+    if unclean {
+        bail!("your local changes would be overwritten: stash them first");
+    }
+    let stash_commit = read_commit(repo, &hash)?;
+    let Some(base) = stash_commit.parents.first() else {
+        bail!("stash@{{{n}}} ({hash}) is not a stash: it has no parents");
+    };
+    let base_tree = tree_from_commit(repo, base)?;
+    let result = merge_trees(
+        repo,
+        Some(&base_tree),
+        &head_tree,
+        &stash_commit.tree,
+        "Updated upstream",
+        "Stashed changes",
+    )?;
+
+    let mut head_list = PathList::new();
+    flatten_tree(repo, &head_tree, b"", &mut head_list)?;
+    let to = result.worktree();
+    let changes = diff_lists(head_list.clone(), to);
+    let root = repo.work_tree()?;
+    for change in changes.iter().filter(|c| c.old.is_none()) {
+        if fs::symlink_metadata(platform::join(root, &change.path)).is_ok() {
+            bail!(
+                "{} already exists, no checkout",
+                String::from_utf8_lossy(&change.path)
+            );
+        }
+    }
+    worktree::apply(repo, &changes)?;
+    if !result.conflicts.is_empty() {
+        write_index_with_conflicts(repo, lock, &result)?;
+        match pop {
+            true => bail!("conflicts in index: the stash entry is kept in case you need it again"),
+            false => bail!("conflicts in index"),
+        }
+    }
+    // Only paths added by the stash are staged.
+    let mut index_list = head_list;
+    index_list.extend(
+        changes
+            .into_iter()
+            .filter(|c| c.old.is_none())
+            .filter_map(|c| Some((c.path, c.new?))),
+    );
+    index_list.sort_by(|a, b| a.0.cmp(&b.0));
+    write_index_unrefreshed(repo, lock, &index_list)?;
+    if pop {
+        stash_drop(repo, Some(&n.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Choose the format of an archive: the one given, or guessed from the name
+/// of the output file, or tar.
+fn archive_format(format: Option<&str>, output: Option<&Path>) -> Result<Format> {
+    match (format, output.and_then(Format::from_path)) {
+        (Some(name), _) => Format::from_name(name),
+        (None, Some(format)) => Ok(format),
+        (None, None) => Ok(Format::Tar),
+    }
+}
+
+/// Create the output file of an archive, or use stdout.
+fn archive_output(output: Option<&Path>) -> Result<Box<dyn Write>> {
+    Ok(match output {
+        Some(path) => {
+            let file =
+                fs::File::create(path).with_context(|| format!("creating {}", path.display()))?;
+            Box::new(io::BufWriter::new(file))
+        }
+        None => Box::new(io::BufWriter::new(io::stdout().lock())),
+    })
+}
+
+/// The "git archive" command - partial implementation: tar, tar.gz and zip
+/// formats, no paths. Written to stdout unless an output file is given,
+/// in the format given, or guessed from the file's name, or tar.
+pub fn archive(
+    repo: &Repository,
+    tree_ish: &str,
+    format: Option<&str>,
+    prefix: &str,
+    output: Option<&Path>,
+) -> Result<()> {
+    let format = archive_format(format, output)?;
+    let (tree, commit, mtime) = resolve_tree_ish(repo, tree_ish)?;
+    let mut out = archive_output(output)?;
+    write_archive(
+        repo,
+        &mut out,
+        format,
+        &tree,
+        commit.as_deref(),
+        mtime,
+        prefix,
+    )
+    .with_context(|| format!("archiving {tree_ish}"))?;
+    Ok(out.flush()?)
+}
+
+/// The "git archive --remote" command: get the archive from a remote
+/// repository with the upload-archive service, over git:// or SSH (running
+/// `exec` on the server, git-upload-archive by default). This works outside
+/// of a repository.
+pub fn archive_remote(
+    remote: &str,
+    exec: Option<&str>,
+    tree_ish: &str,
+    format: Option<&str>,
+    prefix: &str,
+    output: Option<&Path>,
+) -> Result<()> {
+    let format = archive_format(format, output)?;
+    let mut args = vec![format!("--format={}", format.name())];
+    if !prefix.is_empty() {
+        args.push(format!("--prefix={prefix}"));
+    }
+    args.push(tree_ish.to_owned());
+    let mut out = archive_output(output)?;
+    upload_archive(remote, exec, &args, &mut out)?;
+    Ok(out.flush()?)
+}
+
+/// The "git upload-archive" command, run by "git archive --remote" on the
+/// server, talking to it on stdin and stdout.
+pub fn serve_upload_archive(directory: &Path) -> Result<()> {
+    let repo = Repository::open_read_only(directory)?;
+    serve::upload_archive(&repo, io::stdin().lock(), io::stdout().lock())
+}
+
+/// The options of the "git diff" command.
+#[derive(Clone, Copy, Default)]
+pub struct DiffOptions {
+    /// Compare the index to a commit (HEAD by default) instead of the worktree
+    pub cached: bool,
+    /// Minimum similarity of renames, in percent
+    pub find_renames: Option<u32>,
+    pub no_renames: bool,
+    /// Show changes to binary files as binary patches
+    pub binary: bool,
+    pub color: Option<When>,
+    pub word_diff: WordDiff,
+}
+
+/// The "git diff" command - partial implementation: no options except --cached,
+/// --binary, --color, --word-diff and those about renames, and no paths. Renames
+/// are detected with at least `find_renames` similarity, or by default unless
+/// diff.renames is false (or with `no_renames`).
+pub fn diff(repo: &Repository, options: &DiffOptions, commits: &[String]) -> Result<()> {
+    let DiffOptions {
+        cached,
+        find_renames,
+        no_renames,
+        binary,
+        color,
+        word_diff,
+    } = *options;
+    let _pager = pager::start(repo, "diff")?;
+    let format = DiffFormat {
+        binary,
+        ..DiffFormat::new(repo, color, word_diff)?
+    };
+    let trees = commits
+        .iter()
+        .map(|rev| tree_from_tree_ish(repo, &resolve(repo, rev)?).with_context(|| rev.to_string()))
+        .collect::<Result<Vec<_>>>()?;
+    let changes = match (cached, &trees[..]) {
+        (false, []) => diff_to_worktree(repo, None)?,
+        (false, [tree]) => diff_to_worktree(repo, Some(tree))?,
+        (true, []) => {
+            let head = refs::resolve(repo, "HEAD")?;
+            let tree = head
+                .as_deref()
+                .map(|commit| tree_from_commit(repo, commit))
+                .transpose()?;
+            diff_tree_to_index(repo, tree.as_deref())?
+        }
+        (true, [tree]) => diff_tree_to_index(repo, Some(tree))?,
+        (false, [old, new]) => diff_trees(repo, Some(old), new)?,
+        _ => bail!("--cached takes at most one commit"),
+    };
+    let min_score = match no_renames {
+        true => None,
+        false => find_renames.map_or_else(|| rename_score(repo), |score| Ok(Some(score)))?,
+    };
+    let changes = match min_score {
+        Some(min_score) => detect_renames(repo, changes, min_score)?,
+        None => changes,
+    };
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    print_diff(repo, &changes, &format, &mut stdout)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// The "apply" command.
+pub fn apply(
+    repo: &Repository,
+    patches: &[PathBuf],
+    cached: bool,
+    check: bool,
+    strip: usize,
+    context: Option<usize>,
+) -> Result<()> {
+    crate::apply::apply(repo, patches, cached, check, strip, context)
+}
+
+/// The "symbolic-ref" command: print the reference a symbolic reference points
+/// to (shortened like a branch name if asked), or make it point to another one.
+/// Return false if it is not a symbolic reference and `quiet` is set.
+pub fn symbolic_ref(
+    repo: &Repository,
+    name: &str,
+    target: Option<&str>,
+    short: bool,
+    quiet: bool,
+) -> Result<bool> {
+    if let Some(target) = target {
+        if !target.starts_with("refs/") {
+            bail!("refusing to point {name} outside of refs/");
+        }
+        repo.ensure_writable(name)?;
+        refs::write_symbolic(repo, name, target)?;
+        return Ok(true);
+    }
+    match refs::read_symbolic(repo, name)? {
+        Some(target) if short => println!("{}", short_name(&target)),
+        Some(target) => println!("{target}"),
+        None if quiet => return Ok(false),
+        None => bail!("ref {name} is not a symbolic ref"),
+    }
+    Ok(true)
+}
+
+/// Parse the expected old value of a reference for update-ref:
+/// empty or zeros means it must not exist.
+fn expected_value(repo: &Repository, old: &str) -> Result<Option<String>> {
+    if old.is_empty() || old == repo.hash_algo()?.zero_hash() {
+        return Ok(None);
+    }
+    resolve(repo, old).map(Some)
+}
+
+/// The "update-ref" command: point a reference to a new object (or delete it),
+/// checking its current value if given, and logging the update in its reflog.
+/// HEAD is followed to the branch it points to, unless `no_deref` is set.
+pub fn update_ref(
+    repo: &Repository,
+    name: &str,
+    values: &[String],
+    delete: bool,
+    no_deref: bool,
+    message: &str,
+) -> Result<()> {
+    if name != "HEAD" && !name.starts_with("refs/") {
+        bail!("refusing to update reference with bad name {name}");
+    }
+    let target = match name {
+        "HEAD" if !no_deref => refs::current_branch(repo)?.unwrap_or_else(|| name.to_owned()),
+        _ => name.to_owned(),
+    };
+    if delete {
+        let old = match values {
+            [] => None,
+            [old] => expected_value(repo, old)?,
+            _ => bail!("usage: update-ref -d <ref> [<old-value>]"),
+        };
+        if target == "HEAD" {
+            bail!("refusing to delete HEAD");
+        }
+        return refs::delete(repo, &target, old.as_deref());
+    }
+
+    let (new, old) = match values {
+        [new] => (new, refs::resolve(repo, &target)?),
+        [new, old] => (new, expected_value(repo, old)?),
+        _ => bail!("usage: update-ref <ref> <new-value> [<old-value>]"),
+    };
+    let new = resolve(repo, new)?;
+    if !have_object(repo, &new)? {
+        bail!("{new}: object not found");
+    }
+    let obj_type = repo.read_object(&new)?.obj_type;
+    if target.starts_with("refs/heads/") && obj_type != ObjType::Commit {
+        bail!("trying to write non-commit object {new} to branch {target}");
+    }
+    match no_deref {
+        true => refs::update_no_deref(repo, name, old.as_deref(), &new, &committer(), message),
+        false => refs::update(repo, name, old.as_deref(), &new, &committer(), message),
+    }
+}
+
+/// The "show" command - partial implementation: no options but --color, and
+/// merges are shown without a diff.
+pub fn show(repo: &Repository, revs: &[String], color: Option<When>) -> Result<()> {
+    let _pager = pager::start(repo, "show")?;
+    let format = DiffFormat::new(repo, color, WordDiff::None)?;
+    let objects = revs
+        .iter()
+        .map(|rev| Ok((rev.clone(), resolve(repo, rev)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    show_objects(repo, &objects, &format, &mut stdout)?;
+    Ok(stdout.flush()?)
+}
+
+/// The "blame" command - partial implementation: no options, and only the
+/// file in HEAD can be blamed.
+pub fn blame(repo: &Repository, path: &Path) -> Result<()> {
+    let path = repo_path(repo, path)?;
+    let Some(head) = refs::resolve(repo, "HEAD")? else {
+        bail!("no commits yet");
+    };
+    let _pager = pager::start(repo, "blame")?;
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    blame::blame(repo, &head, &path, &mut stdout)?;
+    Ok(stdout.flush()?)
+}
+
+/// The "grep" command - partial implementation: the pattern is a fixed string
+/// (like -F) and line numbers are always shown (like -n). Only tracked files are
+/// searched in the worktree, unless `untracked` is set; either way, like git,
+/// only paths in the current directory are searched, and shown relative to it.
+/// Return false if no line matched (the exit status should then be 1).
+pub fn grep(
+    repo: &Repository,
+    pattern: &str,
+    rev: Option<&str>,
+    untracked_too: bool,
+    threads: usize,
+) -> Result<bool> {
+    let mut prefix = platform::bytes(repo.prefix().as_os_str()).into_owned();
+    if !prefix.is_empty() {
+        prefix.push(b'/');
+    }
+    let mut files = Vec::new();
+    match rev {
+        Some(rev) => {
+            let tree = tree_from_tree_ish(repo, &resolve(repo, rev)?)?;
+            let mut list = PathList::new();
+            flatten_tree(repo, &tree, b"", &mut list)?;
+            for (path, side) in list {
+                let Some(path) = path.strip_prefix(&prefix[..]) else {
+                    continue;
+                };
+                if side.mode != Mode::SubMod {
+                    let name = [rev.as_bytes(), b":", path].concat();
+                    files.push((name, Source::Blob(side.hash)));
+                }
+            }
+        }
+        None => {
+            let root = repo.work_tree()?;
+            let entries = index::read(repo)?;
+            let mut paths: Vec<Vec<u8>> = entries
+                .iter()
+                .filter(|e| e.mode != Mode::SubMod)
+                .map(|e| e.path.clone())
+                .collect();
+            // Conflicted paths have several entries.
+            paths.dedup();
+            if untracked_too {
+                let tracked = entries.iter().map(|e| &e.path[..]).collect();
+                let mut found = Vec::new();
+                let mut ignores = Ignores::new(repo)?;
+                untracked(root, b"", &tracked, Some(&mut ignores), false, &mut found)?;
+                paths.extend(
+                    found
+                        .into_iter()
+                        .filter(|(path, ignored)| !ignored && !path.ends_with(b"/"))
+                        .map(|(path, _)| path),
+                );
+                paths.sort_unstable();
+            }
+            for path in paths {
+                let file = platform::join(root, &path);
+                // Files deleted from the worktree are skipped.
+                if fs::symlink_metadata(&file).is_err() {
+                    continue;
+                }
+                if let Some(path) = path.strip_prefix(&prefix[..]) {
+                    files.push((path.to_vec(), Source::File(file)));
+                }
+            }
+        }
+    }
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    let found = grep::grep(repo, pattern.as_bytes(), &files, threads, &mut stdout)?;
+    stdout.flush()?;
+    Ok(found)
+}
+
+/// The "describe" command - partial implementation: only HEAD is described,
+/// and only --tags and --dirty are supported.
+pub fn git_describe(repo: &Repository, tags: bool, dirty: Option<&str>) -> Result<()> {
+    let Some(head) = refs::resolve(repo, "HEAD")? else {
+        bail!("cannot describe HEAD: no commits yet");
+    };
+    let mut name = crate::describe::describe(repo, &head, tags)?;
+    if let Some(mark) = dirty {
+        let tree = tree_from_commit(repo, &head)?;
+        if !diff_to_worktree(repo, Some(&tree))?.is_empty() {
+            name.push_str(mark);
+        }
+    }
+    println!("{name}");
+    Ok(())
+}
+
+/// The "show-ref" command - partial implementation: only --heads, --tags,
+/// --dereference and --hash, and patterns matching the end of reference names
+/// (whole components).
+/// Return false if no reference was shown (the exit status should then be 1).
+pub fn show_ref(
+    repo: &Repository,
+    patterns: &[String],
+    heads: bool,
+    tags: bool,
+    dereference: bool,
+    hash_only: bool,
+) -> Result<bool> {
+    let mut shown = false;
+    for (name, hash) in refs::list(repo, "refs/")? {
+        let kind_ok = (heads && name.starts_with("refs/heads/"))
+            || (tags && name.starts_with("refs/tags/"))
+            || (!heads && !tags);
+        let pattern_ok = patterns.is_empty()
+            || patterns
+                .iter()
+                .any(|p| name == *p || name.ends_with(&format!("/{p}")));
+        if !kind_ok || !pattern_ok {
+            continue;
+        }
+        match hash_only {
+            true => println!("{hash}"),
+            false => println!("{hash} {name}"),
+        }
+        if dereference {
+            // Like git, the name is shown even with --hash.
+            if let Some((peeled, _)) = peel(repo, &hash)?.filter(|(peeled, _)| *peeled != hash) {
+                println!("{peeled} {name}^{{}}");
+            }
+        }
+        shown = true;
+    }
+    Ok(shown)
+}
+
+/// The "check-ignore" command - partial implementation: only -v, and no
+/// --stdin or --no-index. Like git, tracked paths are never reported.
+/// Return false if no path was reported (the exit status should then be 1).
+pub fn check_ignore(repo: &Repository, paths: &[PathBuf], verbose: bool) -> Result<bool> {
+    let root = repo.work_tree()?;
+    let entries = index::read(repo)?;
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    let mut reported = false;
+    for arg in paths {
+        let path = repo_path(repo, arg)?;
+        if entries.iter().any(|e| e.path == path) {
+            continue;
+        }
+        let is_dir =
+            fs::symlink_metadata(platform::join(root, &path)).is_ok_and(|meta| meta.is_dir());
+        let Some(found) = ignore::check(repo, root, &path, is_dir)? else {
+            continue;
+        };
+        // Paths kept by a negated pattern are only shown with -v.
+        if found.negated && !verbose {
+            continue;
+        }
+        reported = true;
+        if verbose {
+            write!(stdout, "{}:{}:", found.source, found.line)?;
+            stdout.write_all(&found.pattern)?;
+            stdout.write_all(b"\t")?;
+        }
+        stdout.write_all(&platform::bytes(arg.as_os_str()))?;
+        writeln!(stdout)?;
+    }
+    stdout.flush()?;
+    Ok(reported)
+}
+
+/// The "check-attr" command - partial implementation: no --all, --stdin or
+/// --cached. Print the state of each attribute for each path: "set", "unset",
+/// "unspecified" or its value.
+pub fn check_attr(repo: &Repository, attributes: &[String], paths: &[PathBuf]) -> Result<()> {
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    for arg in paths {
+        let path = repo_path(repo, arg)?;
+        for name in attributes {
+            let state = match attributes::get(repo, &path, name)? {
+                Some(attributes::State::Set) => "set".to_owned(),
+                Some(attributes::State::Unset) => "unset".to_owned(),
+                Some(attributes::State::Value(value)) => value,
+                None => "unspecified".to_owned(),
+            };
+            stdout.write_all(&platform::bytes(arg.as_os_str()))?;
+            writeln!(stdout, ": {name}: {state}")?;
+        }
+    }
+    Ok(stdout.flush()?)
+}
+
+/// Map an identity for check-mailmap.
+fn map_contact(mailmap: &Mailmap, contact: &str) -> Result<String> {
+    let Some((name, email)) = contact
+        .split_once('<')
+        .and_then(|(name, rest)| Some((name, rest.split_once('>')?.0)))
+    else {
+        bail!("unable to parse contact: {contact}");
+    };
+    Ok(match mailmap.map(name.trim_end(), email) {
+        (name, email) if name.is_empty() => format!("<{email}>"),
+        (name, email) => format!("{name} <{email}>"),
+    })
+}
+
+/// The "check-mailmap" command: print the canonical form of identities
+/// (`Name <email>` or `<email>`), given as arguments or with `stdin` one
+/// per line.
+pub fn check_mailmap(repo: &Repository, contacts: &[String], stdin: bool) -> Result<()> {
+    let mailmap = Mailmap::read(repo)?;
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    for contact in contacts {
+        writeln!(stdout, "{}", map_contact(&mailmap, contact)?)?;
+    }
+    if stdin {
+        for line in io::stdin().lock().lines() {
+            writeln!(stdout, "{}", map_contact(&mailmap, &line?)?)?;
+        }
+    }
+    Ok(stdout.flush()?)
+}
+
+/// The "pack-refs" command: move loose references to .git/packed-refs,
+/// only tags unless `all` is set.
+pub fn pack_refs(repo: &Repository, all: bool) -> Result<()> {
+    refs::pack_refs(repo, all)?;
+    Ok(())
+}
+
+/// The options of the "rev-parse" command: how to check and print
+/// revisions, and what to print about the repository.
+#[derive(Clone, Copy, Default)]
+pub struct RevParseOptions {
+    pub verify: bool,
+    /// Abbreviate hashes, to at least the given length
+    pub short: Option<Option<usize>>,
+    pub show_git_dir: bool,
+    pub show_common_dir: bool,
+    pub show_prefix: bool,
+    pub show_bare: bool,
+}
+
+/// The "rev-parse" command - partial implementation: only --verify, --short,
+/// --git-dir and --git-common-dir, and the revision syntax supported by the
+/// revision module.
+pub fn rev_parse(repo: &Repository, revs: &[String], options: &RevParseOptions) -> Result<()> {
+    let RevParseOptions {
+        verify,
+        short,
+        show_git_dir,
+        show_common_dir,
+        show_prefix,
+        show_bare,
+    } = *options;
+    // Like git, relative when in the current directory, eg ".git" or ".".
+    let cwd = env::current_dir().context("getting current directory")?;
+    let show_dir = |dir: &Path| match dir.strip_prefix(&cwd) {
+        Ok(rel) if rel.as_os_str().is_empty() => println!("."),
+        Ok(rel) => println!("{}", rel.display()),
+        Err(_) => println!("{}", dir.display()),
+    };
+    if show_git_dir {
+        show_dir(repo.git_dir());
+    }
+    if show_common_dir {
+        show_dir(repo.common_dir());
+    }
+    if show_prefix {
+        match repo.prefix().as_os_str().is_empty() {
+            true => println!(),
+            false => println!("{}/", repo.prefix().display()),
+        }
+    }
+    if show_bare {
+        println!("{}", repo.is_bare());
+    }
+    // --short implies --verify
+    if (verify || short.is_some()) && revs.len() != 1 {
+        bail!("needed a single revision");
+    }
+    for rev in revs {
+        let hash = resolve(repo, rev)?;
+        if (verify || short.is_some()) && !have_object(repo, &hash)? {
+            bail!("needed a single revision: {hash} not found");
+        }
+        match short {
+            Some(len) => println!(
+                "{}",
+                shorten_to(repo, &hash, len.unwrap_or(min_len(repo)?))?
+            ),
+            None => println!("{hash}"),
+        }
+    }
+    Ok(())
+}
+
+/// The "merge-base" command - partial implementation: exactly two commits.
+/// Return false if there is no merge base, or for --is-ancestor if the first
+/// commit is not an ancestor of the second (the exit status should then be 1).
+pub fn show_merge_base(
+    repo: &Repository,
+    one: &str,
+    two: &str,
+    all: bool,
+    is_ancestor: bool,
+) -> Result<bool> {
+    let one = resolve(repo, one)?;
+    let two = resolve(repo, two)?;
+    let bases = merge_bases(repo, &one, &two)?;
+    if is_ancestor {
+        return Ok(bases == [peel_to_commit(repo, &one)?]);
+    }
+    let shown = if all {
+        &bases[..]
+    } else {
+        &bases[..bases.len().min(1)]
+    };
+    for base in shown {
+        println!("{base}");
+    }
+    Ok(!bases.is_empty())
+}
+
+/// The "rev-list" command - partial implementation: only --objects, --not
+/// (or a ^ prefix) for excluding commits, filters on commits (not with --objects),
+/// default ordering.
+pub fn rev_list(
+    repo: &Repository,
+    revs: &[String],
+    not: &[String],
+    objects: bool,
+    filter: &Filter,
+) -> Result<()> {
+    let mut tips = Vec::new();
+    let mut names = HashMap::new();
+    let mut exclude = Vec::new();
+    for rev in revs {
+        match rev.strip_prefix('^') {
+            Some(rev) => exclude.push(resolve(repo, rev)?),
+            None => {
+                let hash = resolve(repo, rev)?;
+                names.insert(hash.clone(), rev.as_bytes());
+                tips.push(hash);
+            }
+        }
+    }
+    for rev in not {
+        exclude.push(resolve(repo, rev)?);
+    }
+
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    if !objects {
+        for hash in filtered_commits(repo, &tips, &exclude, filter)? {
+            writeln!(stdout, "{hash}")?;
+        }
+        return Ok(stdout.flush()?);
+    }
+    for (hash, name) in reachable_objects(repo, &tips, &exclude)? {
+        let Some(name) = name else {
+            writeln!(stdout, "{hash}")?;
+            continue;
+        };
+        write!(stdout, "{hash} ")?;
+        stdout.write_all(names.get(&hash).copied().unwrap_or(&name))?;
+        writeln!(stdout)?;
+    }
+    Ok(stdout.flush()?)
+}
+
+/// The "git shortlog" command - partial implementation: commits are given like
+/// for rev-list (the default is HEAD, stdin is not read), and grouped by
+/// author only. Each author's commits are listed by subject, oldest first,
+/// or with `summary` just counted; authors are sorted by name, or with
+/// `numbered` by number of commits. Identities are mapped with the mailmap.
+pub fn shortlog(
+    repo: &Repository,
+    revs: &[String],
+    summary: bool,
+    numbered: bool,
+    email: bool,
+) -> Result<()> {
+    let mut tips = Vec::new();
+    let mut exclude = Vec::new();
+    for rev in revs {
+        match rev.strip_prefix('^') {
+            Some(rev) => exclude.push(resolve(repo, rev)?),
+            None => tips.push(resolve(repo, rev)?),
+        }
+    }
+    if tips.is_empty() {
+        tips.push(resolve(repo, "HEAD")?);
+    }
+
+    let mailmap = Mailmap::read(repo)?;
+    let mut authors: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+    for hash in reachable_commits(repo, &tips, &exclude)?.into_iter().rev() {
+        let commit = read_commit(repo, &hash)?;
+        let (name, address) = mailmap.map_ident(&commit.author);
+        let author = match email {
+            true => format!("{name} <{address}>"),
+            false => name,
+        };
+        let mut subject = mail::subject(&commit.message);
+        // Like git, drop "[PATCH ...]" from subjects of applied patches.
+        if subject.starts_with(b"[PATCH") {
+            if let Some(end) = subject.iter().position(|&c| c == b']') {
+                subject.drain(..=end);
+            }
+        }
+        let subject = subject.trim_ascii_start();
+        let subject = match subject.is_empty() {
+            true => b"<none>".to_vec(),
+            false => subject.to_vec(),
+        };
+        authors.entry(author).or_default().push(subject);
+    }
+    let mut authors: Vec<_> = authors.into_iter().collect();
+    authors.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    if numbered {
+        authors.sort_by_key(|(_, subjects)| std::cmp::Reverse(subjects.len()));
+    }
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for (author, subjects) in authors {
+        if summary {
+            writeln!(out, "{:6}\t{author}", subjects.len())?;
+            continue;
+        }
+        writeln!(out, "{author} ({}):", subjects.len())?;
+        for subject in subjects {
+            out.write_all(b"      ")?;
+            out.write_all(&subject)?;
+            writeln!(out)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(out.flush()?)
+}
+
+/// The "dedup-report" (made up) command - report blobs present at several paths
+/// and space used under each directory, across all history reachable from refs.
+pub fn dedup(repo: &Repository, depth: usize) -> Result<()> {
+    dedup_report(repo, depth)
+}
+
+/// The "git count-objects [-v]" command: count loose objects and the space
+/// they use, and with `verbose`, packs, packed objects and garbage files.
+pub fn count_objects(repo: &Repository, verbose: bool) -> Result<()> {
+    let counts = count::count_objects(repo)?;
+    let kib = |size: u64| size / 1024;
+    if !verbose {
+        println!("{} objects, {} kilobytes", counts.count, kib(counts.size));
+        return Ok(());
+    }
+    for path in &counts.garbage {
+        eprintln!("warning: garbage found: {}", path.display());
+    }
+    println!("count: {}", counts.count);
+    println!("size: {}", kib(counts.size));
+    println!("in-pack: {}", counts.in_pack);
+    println!("packs: {}", counts.packs);
+    println!("size-pack: {}", kib(counts.size_pack));
+    println!("prune-packable: {}", counts.prune_packable);
+    println!("garbage: {}", counts.garbage.len());
+    println!("size-garbage: {}", kib(counts.size_garbage));
+    Ok(())
+}
+
+/// The "refs verify" command: check references and report problems,
+/// optionally offering to fix them. Fails if problems are left.
+pub fn refs_verify(repo: &Repository, fix: bool) -> Result<()> {
+    let left = verify(repo, fix)?;
+    if left > 0 {
+        bail!("{left} broken reference(s) found");
+    }
+    Ok(())
+}
+
+/// The "git fsck" command - partial implementation: the only option is --unreachable.
+/// Fails if problems were found (dangling objects are not problems).
+pub fn fsck(repo: &Repository, unreachable: bool) -> Result<()> {
+    let problems = check_all(repo, unreachable)?;
+    if problems > 0 {
+        bail!("{problems} problem(s) found");
+    }
+    Ok(())
+}
+
+/// The "git gc" command - partial implementation: repack reachable objects
+/// into a single pack, and delete unreachable loose objects older than `prune`
+/// ("never" or `no_prune` to keep them all). Reflogs are not expired. Only one
+/// gc runs at a time, see lock_gc().
+pub fn gc(repo: &Repository, prune: &str, no_prune: bool) -> Result<()> {
+    let expire = match prune {
+        _ if no_prune => None,
+        "never" => None,
+        date => Some(date::parse(date).context("invalid --prune date")?),
+    };
+    let _lock = lock_gc(repo)?;
+    repack(repo, expire)
+}
+
+/// The "git prune" command: delete unreachable loose objects, only those
+/// older than `expire` if given. With `dry_run`, list them instead,
+/// as with `verbose` when deleting them.
+pub fn prune(repo: &Repository, dry_run: bool, verbose: bool, expire: Option<&str>) -> Result<()> {
+    let expire = match expire {
+        Some(date) => date::parse(date).context("invalid --expire date")?,
+        None => i64::MAX,
+    };
+    for hash in prunable(repo, expire)? {
+        if dry_run || verbose {
+            let obj_type = repo
+                .read_object(&hash)
+                .map_or("unknown", |o| o.obj_type.to_str());
+            println!("{hash} {obj_type}");
+        }
+        if !dry_run {
+            remove_loose(repo, &hash)?;
+        }
+    }
+    Ok(())
+}
+
+/// The "checkout-empty" (made up) command - a bit like "checkout" except:
+/// - assumes the working directory is empty, and will overwrite files otherwise;
+/// - always leaves us with a detached HEAD;
+/// - takes the commit as a revision, but does not attach HEAD to branches.
+pub fn checkout_empty(repo: &Repository, rev: &str) -> Result<()> {
+    repo.ensure_writable("HEAD")?;
+    let lock = lock_worktree(repo)?;
+    let commit_hash = &peel_to_commit(repo, &resolve(repo, rev)?)?;
+    let tree_hash = tree_from_commit(repo, commit_hash)
+        .with_context(|| format!("getting tree hash from commit {commit_hash}"))?;
+    let tree = repo
+        .read_tree(&tree_hash)
+        .with_context(|| format!("opening tree object {tree_hash}"))?;
+    let root = repo.work_tree()?;
+    tree.actualise_entries(repo, root)
+        .with_context(|| format!("checking out to {}", root.display()))?;
+    let mut files = PathList::new();
+    flatten_tree(repo, &tree_hash, b"", &mut files)?;
+    write_index_from_list(repo, lock, &files)?;
+
+    let old_head = refs::resolve(repo, "HEAD")?;
+    let from = match refs::current_branch(repo)? {
+        Some(branch) => short_name(&branch).to_owned(),
+        None => old_head.clone().unwrap_or_default(),
+    };
+    let reflog_message = format!("checkout: moving from {from} to {rev}");
+    refs::update_no_deref(
+        repo,
+        "HEAD",
+        old_head.as_deref(),
+        commit_hash,
+        &committer(),
+        &reflog_message,
+    )?;
+    print_changes(repo, &diff_lists(PathList::new(), files))?;
+
+    let old_head = old_head.as_deref().unwrap_or(repo.hash_algo()?.zero_hash());
+    hooks::run(repo, "post-checkout", &[old_head, commit_hash, "1"])
+}
+
+/// The "unpack-objects" command: write the objects of a pack read from stdin
+/// as loose objects. Deltas may be against objects we already have (thin packs).
+pub fn unpack_objects(repo: &Repository) -> Result<()> {
+    interrupt::install();
+    let nb_obj = unpack_from(repo, io::stdin().lock()).context("unpacking from stdin")?;
+    println!("Unpacked {nb_obj} objects");
+    Ok(())
+}
+
+/// The "pack-objects" command: read object hashes from stdin (one per line,
+/// anything after the hash is ignored, as in "rev-list --objects" output)
+/// and write a pack containing them to stdout. With --revs, read revisions
+/// instead, and pack what is reachable from them but not from the ones
+/// prefixed with '^', using a bitmap if there is one.
+pub fn pack_objects(repo: &Repository, revs: bool) -> Result<()> {
+    // Objects in the order they were given, without duplicates.
+    let mut hashes = Vec::new();
+    let mut seen = HashSet::new();
+    let mut tips = Vec::new();
+    let mut exclude = Vec::new();
+    for line in io::stdin().lines() {
+        let line = line.context("reading object list from stdin")?;
+        if revs {
+            match line.strip_prefix('^') {
+                Some(rev) => exclude.push(resolve(repo, rev)?),
+                None if !line.is_empty() => tips.push(resolve(repo, &line)?),
+                None => (),
+            }
+        } else if let Some(hash) = line.split_whitespace().next() {
+            if seen.insert(hash.to_owned()) {
+                hashes.push(hash.to_owned());
+            }
+        }
+    }
+    if revs {
+        hashes = reachable_hashes(repo, &tips, &exclude)?;
+    }
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    write_pack(repo, &mut out, &hashes)?;
+    out.flush().context("writing pack to stdout")?;
+    Ok(())
+}
+
+/// The "bundle create" command - partial implementation: references are given
+/// by name (or --all for all of them and HEAD), and only ^REV exclusions are
+/// supported.
+pub fn bundle_create(repo: &Repository, file: &Path, revs: &[String], all: bool) -> Result<()> {
+    ensure_sha1(repo, "create a bundle")?;
+    let mut included = Vec::new();
+    let mut exclude = Vec::new();
+    if all {
+        included.extend(refs::list(repo, "refs/")?);
+        if let Some(head) = refs::resolve(repo, "HEAD")? {
+            included.push(("HEAD".to_owned(), head));
+        }
+    }
+    for rev in revs {
+        match rev.strip_prefix('^') {
+            Some(rev) => exclude.push(resolve(repo, rev)?),
+            None => match refs::dwim(repo, rev)? {
+                Some(found) if !included.contains(&found) => included.push(found),
+                Some(_) => (),
+                None => bail!("{rev} is not a reference"),
+            },
+        }
+    }
+    if included.is_empty() {
+        bail!("refusing to create empty bundle");
+    }
+    let res = fs::File::create(file)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| {
+            let mut out = io::BufWriter::new(file);
+            bundle::write(repo, &mut out, &included, &exclude)?;
+            Ok(out.flush()?)
+        });
+    if res.is_err() {
+        _ = fs::remove_file(file);
+    }
+    res.with_context(|| format!("creating {}", file.display()))
+}
+
+/// The "bundle verify" command: check that the prerequisites of a bundle
+/// are in the repository, and describe it like git.
+pub fn bundle_verify(repo: &Repository, file: &Path) -> Result<()> {
+    let (header, _) = bundle::open(file)?;
+    check_prerequisites(repo, &header)?;
+    let count = |n: usize| match n {
+        1 => "this ref".to_owned(),
+        n => format!("these {n} refs"),
+    };
+    println!("The bundle contains {}:", count(header.refs.len()));
+    list_heads(&header, &mut io::stdout())?;
+    if header.prerequisites.is_empty() {
+        println!("The bundle records a complete history.");
+    } else {
+        println!("The bundle requires {}:", count(header.prerequisites.len()));
+        // Like git, the subjects of the commits are not shown.
+        for (hash, _) in &header.prerequisites {
+            println!("{hash} ");
+        }
+    }
+    println!("The bundle uses this hash algorithm: sha1");
+    eprintln!("{} is okay", file.display());
+    Ok(())
+}
+
+/// The "bundle list-heads" command.
+pub fn bundle_list_heads(file: &Path) -> Result<()> {
+    let (header, _) = bundle::open(file)?;
+    Ok(list_heads(&header, &mut io::stdout())?)
+}
+
+/// The "bundle unbundle" command: write the objects of a bundle to the object
+/// database, and print its references (which are not updated).
+pub fn bundle_unbundle(repo: &Repository, file: &Path) -> Result<()> {
+    ensure_sha1(repo, "unbundle")?;
+    let (header, pack) = bundle::open(file)?;
+    check_prerequisites(repo, &header)?;
+    // Unpacked rather than kept: the pack may be thin, with deltas against
+    // the prerequisites, which pack_index doesn't support.
+    unpack_from(repo, pack).context("unpacking objects")?;
+    Ok(list_heads(&header, &mut io::stdout())?)
+}
+
+/// The "fast-export" command: write the history of the given references as
+/// a stream for fast-import, on stdout.
+pub fn fast_export(repo: &Repository, revs: &[String], all: bool) -> Result<()> {
+    let mut included = Vec::new();
+    let mut exclude = Vec::new();
+    if all {
+        included.extend(refs::list(repo, "refs/")?);
+    }
+    for rev in revs {
+        match rev.strip_prefix('^') {
+            Some(rev) => exclude.push(resolve(repo, rev)?),
+            None => match refs::dwim(repo, rev)? {
+                Some(found) if !included.contains(&found) => included.push(found),
+                Some(_) => (),
+                None => bail!("{rev} is not a reference"),
+            },
+        }
+    }
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    fast_export::write(repo, &mut stdout, &included, &exclude)?;
+    Ok(stdout.flush()?)
+}
+
+/// The "fast-import" command: import a stream from stdin, then update the
+/// references it sets. Like git, branches are only updated if their new tip
+/// contains the current one, unless `force` is set; return false if some
+/// were not.
+pub fn fast_import(repo: &Repository, force: bool) -> Result<bool> {
+    let updates = fast_import::import(repo, io::stdin().lock())?;
+    let who = committer();
+    let mut all_updated = true;
+    for (name, new) in updates {
+        let old = refs::resolve(repo, &name)?;
+        if let Some(old) = &old {
+            let branch = !name.starts_with("refs/tags/");
+            if branch
+                && !force
+                && !is_ancestor(
+                    repo,
+                    &peel_to_commit(repo, old)?,
+                    &peel_to_commit(repo, &new)?,
+                )?
+            {
+                eprintln!("warning: Not updating {name} (new tip {new} does not contain {old})");
+                all_updated = false;
+                continue;
+            }
+        }
+        if old.as_ref() != Some(&new) {
+            refs::update(repo, &name, old.as_deref(), &new, &who, "fast-import")?;
+        }
+    }
+    Ok(all_updated)
+}
+
+/// The "index-pack" command: write the .idx file next to the given .pack file,
+/// or without one, store the pack read from stdin in the object database.
+pub fn index_pack(repo: &Repository, pack_file: Option<&Path>) -> Result<()> {
+    let Some(pack_file) = pack_file else {
+        let (name, _) = pack_index::store_pack(repo, io::stdin().lock())?;
+        println!("pack\t{name}");
+        return Ok(());
+    };
+    if pack_file.extension() != Some("pack".as_ref()) {
+        bail!("pack file name must end with .pack");
+    }
+    let pack = fs::read(pack_file).with_context(|| format!("reading {}", pack_file.display()))?;
+    let (entries, checksum) = pack_index::index_pack(repo, &pack).context("indexing pack")?;
+    println!("{}", hex::encode(checksum));
+
+    let idx_file = pack_file.with_extension("idx");
+    let idx = fs::File::create(&idx_file).map_err(|e| write_error(e, &idx_file))?;
+    pack_index::write_index(repo, io::BufWriter::new(idx), &entries, &checksum)
+        .with_context(|| format!("writing {}", idx_file.display()))
+}
+
+/// The "verify-pack" command: check packs against their index, given the
+/// path of either, or of both without extension. With `verbose`, list the
+/// objects in pack order and the statistics, which are all that is shown with
+/// `stat_only`.
+pub fn verify_pack(
+    repo: &Repository,
+    paths: &[PathBuf],
+    verbose: bool,
+    stat_only: bool,
+) -> Result<()> {
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    for path in paths {
+        let idx_file = match path.extension() {
+            Some(ext) if ext == "idx" || ext == "pack" => path.with_extension("idx"),
+            _ => {
+                let mut name = path.as_os_str().to_owned();
+                name.push(".idx");
+                PathBuf::from(name)
+            }
+        };
+        let pack_file = idx_file.with_extension("pack");
+        let objects = pack_index::verify_pack(repo, &idx_file)
+            .with_context(|| format!("{}: bad", pack_file.display()))?;
+        if !verbose && !stat_only {
+            continue;
+        }
+        let mut chains: Vec<usize> = Vec::new();
+        for object in &objects {
+            let depth = object.delta.map_or(0, |(depth, _)| depth);
+            if chains.len() <= depth {
+                chains.resize(depth + 1, 0);
+            }
+            chains[depth] += 1;
+            if stat_only {
+                continue;
+            }
+            write!(
+                stdout,
+                "{} {:<6} {} {} {}",
+                object.hash,
+                object.obj_type.to_str(),
+                object.size,
+                object.packed_size,
+                object.offset
+            )?;
+            match object.delta {
+                Some((depth, base)) => writeln!(stdout, " {depth} {base}")?,
+                None => writeln!(stdout)?,
+            }
+        }
+        let plural = |n: usize| if n == 1 { "object" } else { "objects" };
+        for (depth, &count) in chains.iter().enumerate() {
+            match (depth, count) {
+                (_, 0) => (),
+                (0, n) => writeln!(stdout, "non delta: {n} {}", plural(n))?,
+                (d, n) => writeln!(stdout, "chain length = {d}: {n} {}", plural(n))?,
+            }
+        }
+        if verbose {
+            writeln!(stdout, "{}: ok", pack_file.display())?;
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Fail in repositories using another hash function than SHA-1:
+/// we only speak to remotes with SHA-1 object ids.
+fn ensure_sha1(repo: &Repository, action: &str) -> Result<()> {
+    let algo = repo.hash_algo()?;
+    if algo != HashAlgo::Sha1 {
+        bail!(
+            "cannot {action}: not supported with object format {}",
+            algo.name()
+        );
+    }
+    Ok(())
+}
+
+/// The "ls-remote" command.
+///
+/// Like git, patterns are matched against the end of reference names,
+/// so "main" matches "refs/heads/main" and may contain '*' and '?' wildcards.
+/// Patterns starting with "refs/" are also used to restrict what the server lists.
+/// With --format=json, each reference is a record with its hash, name, and the
+/// hash of the object it peels to for annotated tags.
+/// The remote can be given by name, and defaults to the one of the current
+/// branch, or origin, see remote::list_url().
+pub fn ls_remote(
+    repo: Option<&Repository>,
+    remote: Option<&str>,
+    patterns: &[String],
+) -> Result<()> {
+    let repo_url = &remote::list_url(repo, remote)?;
+    let prefixes: Vec<String> = patterns
+        .iter()
+        .filter(|p| p.starts_with("refs/"))
+        .map(|p| {
+            p.split(['*', '?'])
+                .next()
+                .expect("at least one item")
+                .into()
+        })
+        .collect();
+    // A single pattern not starting with refs/ means we must not restrict at all.
+    let prefixes = if prefixes.len() == patterns.len() {
+        prefixes
+    } else {
+        Vec::new()
+    };
+
+    let refs = ls_refs(repo_url, &prefixes).context("listing remote refs")?;
+
+    let mut stdout = io::stdout().lock();
+    for r in refs {
+        let tail = format!("/{}", r.name);
+        let matches = |p: &String| wildmatch(format!("*/{p}").as_bytes(), tail.as_bytes());
+        if !patterns.is_empty() && !patterns.iter().any(matches) {
+            continue;
+        }
+        if json::enabled() {
+            let mut fields = vec![
+                ("hash", r.hash.as_str().into()),
+                ("name", r.name.as_str().into()),
+            ];
+            if let Some(peeled) = &r.peeled {
+                fields.push(("peeled", peeled.as_str().into()));
+            }
+            json::write_record(&mut stdout, &fields)?;
+            continue;
+        }
+        writeln!(stdout, "{}\t{}", r.hash, r.name)?;
+        if let Some(peeled) = r.peeled {
+            writeln!(stdout, "{peeled}\t{}^{{}}", r.name)?;
+        }
+    }
+    Ok(())
+}
+
+/// The "clone" command. Unlike the real one, it unpacks all object to loose storage.
+/// Fetches all branches (as remote-tracking branches) and tags,
+/// and creates a local branch for the remote's default branch.
+pub fn clone(repo_url: &str, directory: Option<&Path>, options: &CloneOptions) -> Result<()> {
+    interrupt::install();
+    crate::clone::clone(repo_url, directory, options)
+}
+
+/// The "remote" command without a subcommand - list the remotes, with
+/// their URLs if `verbose`.
+pub fn remote_list(repo: &Repository, verbose: bool) -> Result<()> {
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    for name in remote::names(repo)? {
+        if verbose {
+            let url = config::remote_url(repo, &name)?;
+            writeln!(stdout, "{name}\t{url} (fetch)")?;
+            writeln!(stdout, "{name}\t{} (push)", remote::push_url(repo, &name)?)?;
+        } else {
+            writeln!(stdout, "{name}")?;
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// The "remote add" command - partial implementation: no options, nothing is fetched.
+pub fn remote_add(repo: &Repository, name: &str, url: &str) -> Result<()> {
+    remote::add(repo, name, url)
+}
+
+/// The "remote remove" command.
+pub fn remote_remove(repo: &Repository, name: &str) -> Result<()> {
+    remote::remove(repo, name)
+}
+
+/// The "remote rename" command.
+pub fn remote_rename(repo: &Repository, old: &str, new: &str) -> Result<()> {
+    remote::rename(repo, old, new)
+}
+
+/// The "remote set-url" command - partial implementation: no --add or --delete.
+pub fn remote_set_url(repo: &Repository, name: &str, url: &str, push: bool) -> Result<()> {
+    remote::set_url(repo, name, url, push)
+}
+
+/// The "remote show" command - partial implementation: the remote is not
+/// queried, like with -n, so only local information is shown.
+pub fn remote_show(repo: &Repository, names: &[String]) -> Result<()> {
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    let plural = |n: usize, one: &'static str, many: &'static str| if n == 1 { one } else { many };
+    for name in names {
+        if !remote::names(repo)?.contains(name) {
+            bail!("No such remote: '{name}'");
+        }
+        writeln!(stdout, "* remote {name}")?;
+        writeln!(stdout, "  Fetch URL: {}", config::remote_url(repo, name)?)?;
+        writeln!(stdout, "  Push  URL: {}", remote::push_url(repo, name)?)?;
+        writeln!(stdout, "  HEAD branch: (not queried)")?;
+
+        let prefix = format!("refs/remotes/{name}/");
+        let tracking: Vec<String> = refs::list(repo, &prefix)?
+            .into_iter()
+            .map(|(tracking, _)| tracking[prefix.len()..].to_owned())
+            .filter(|branch| branch != "HEAD")
+            .collect();
+        if !tracking.is_empty() {
+            let title = plural(tracking.len(), "Remote branch", "Remote branches");
+            writeln!(stdout, "  {title}: (status not queried)")?;
+            for branch in tracking {
+                writeln!(stdout, "    {branch}")?;
+            }
+        }
+
+        let pulling = remote::tracking_branches(repo, name)?;
+        if !pulling.is_empty() {
+            let title = plural(pulling.len(), "Local branch", "Local branches");
+            writeln!(stdout, "  {title} configured for 'git pull':")?;
+            let width = pulling
+                .iter()
+                .map(|(branch, _)| branch.len())
+                .max()
+                .unwrap_or(0);
+            for (branch, merge) in pulling {
+                let merge = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+                writeln!(stdout, "    {branch:width$} merges with remote {merge}")?;
+            }
+        }
+
+        let mut pushing: Vec<(bool, String, String)> = remote::push_specs(repo, name)?
+            .into_iter()
+            .map(|spec| {
+                let (force, spec) = match spec.strip_prefix('+') {
+                    Some(spec) => (true, spec.to_owned()),
+                    None => (false, spec),
+                };
+                let (src, dst) = spec.split_once(':').unwrap_or((&spec, &spec));
+                (force, src.to_owned(), dst.to_owned())
+            })
+            .collect();
+        if pushing.is_empty() {
+            pushing.push((false, "(matching)".to_owned(), "(matching)".to_owned()));
+        }
+        pushing.sort_by(|a, b| a.1.cmp(&b.1));
+        let title = plural(pushing.len(), "Local ref", "Local refs");
+        writeln!(
+            stdout,
+            "  {title} configured for 'git push' (status not queried):"
+        )?;
+        let width = pushing
+            .iter()
+            .map(|(_, src, _)| src.len())
+            .max()
+            .unwrap_or(0);
+        for (force, src, dst) in pushing {
+            let verb = if force { "forces to" } else { "pushes to" };
+            writeln!(stdout, "    {src:width$} {verb} {dst}")?;
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// The "fetch" command - fetches the references matching the remote's fetch
+/// refspecs (by default, all branches to remote-tracking branches), and tags
+/// we don't have yet, from the given remote. Without a `+`, references that
+/// would not fast-forward are not updated (and the command fails).
+/// The remote may also be a bundle file, or a local repository whose objects
+/// are then all hard-linked (or copied) like clone does.
+///
+/// Updates are printed like git does. With `dry_run`, objects are received
+/// in a temporary directory, to tell how references would change, and
+/// neither objects nor references are written.
+pub fn fetch(repo: &Repository, remote: &str, dry_run: bool) -> Result<()> {
+    interrupt::install();
+    ensure_sha1(repo, "fetch")?;
+    let url = config::remote_url(repo, remote)?;
+    let specs = remote::fetch_specs(repo, remote)?;
+    let wanted = |name: &str| {
+        specs.iter().any(|spec| spec.map(name).is_some()) || name.starts_with("refs/tags/")
+    };
+
+    let bundle = match is_bundle(Path::new(&url)) {
+        true => Some(bundle::open(Path::new(&url))?),
+        false => None,
+    };
+    let local = match bundle {
+        Some(_) => None,
+        None => local_source(&url)?,
+    };
+    let remote_refs: Vec<RemoteRef> = match &bundle {
+        Some((header, _)) => header
+            .refs
+            .iter()
+            .filter(|(name, _)| wanted(name))
+            .map(|(name, hash)| RemoteRef {
+                hash: hash.clone(),
+                name: name.clone(),
+                symref_target: None,
+                peeled: None,
+            })
+            .collect(),
+        None => {
+            let mut prefixes: Vec<String> = specs.iter().map(|s| s.prefix().to_owned()).collect();
+            prefixes.push("refs/tags/".to_owned());
+            let remote_refs = ls_refs(&url, &prefixes).context("listing remote refs")?;
+            remote_refs
+                .into_iter()
+                .filter(|r| wanted(&r.name))
+                .collect()
+        }
+    };
+    let updates = plan_updates(repo, &specs, remote_refs)?;
+
+    let mut wants = Vec::new();
+    for update in &updates {
+        if !have_object(repo, &update.new)? && !wants.contains(&update.new) {
+            wants.push(update.new.clone());
+        }
+    }
+    let quarantine = match dry_run {
+        true => Some(Quarantine::new(repo)?),
+        false => None,
+    };
+    let store = quarantine.as_ref().map_or(repo, Quarantine::repo);
+    let received = |verb: &str, nb_obj: usize| match dry_run {
+        true => println!("Would receive {nb_obj} objects"),
+        false => println!("{verb} {nb_obj} objects"),
+    };
+    if let Some((header, pack)) = bundle {
+        if !wants.is_empty() {
+            check_prerequisites(repo, &header)?;
+            let nb_obj = unpack_from(store, pack).context("unpacking objects")?;
+            received("Unpacked", nb_obj as usize);
+        }
+    } else if let Some((source, _)) = local.filter(|_| !wants.is_empty()) {
+        link_objects(&source.join("objects"), store.object_dir()).context("copying objects")?;
+    } else if !wants.is_empty() && is_dumb_http(&url)? {
+        let nb_obj = dumb_http::fetch(store, &url, &wants).context("fetching objects")?;
+        received("Received", nb_obj);
+    } else if !wants.is_empty() {
+        let (pack, shallow_info) = negotiate(repo, &url, &wants).context("fetching objects")?;
+        let nb_obj = unpack_from(store, pack).context("unpacking objects")?;
+        received("Unpacked", nb_obj as usize);
+        if !dry_run {
+            shallow::update(repo, &shallow_info.shallow, &shallow_info.unshallow)?;
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut rejected = false;
+    for update in &updates {
+        let kind = update.kind(store)?;
+        lines.push(update.describe(store, kind)?);
+        if kind == UpdateKind::Rejected {
+            rejected = true;
+        } else if !dry_run {
+            let reflog_message = format!("fetch {remote}: storing {}", short_name(&update.name));
+            refs::update(
+                repo,
+                &update.name,
+                update.old.as_deref(),
+                &update.new,
+                &committer(),
+                &reflog_message,
+            )?;
+        }
+    }
+    if !lines.is_empty() {
+        println!("From {url}");
+        for line in lines {
+            println!("{line}");
+        }
+    }
+    if rejected {
+        bail!("some local references could not be updated");
+    }
+    Ok(())
+}
+
+/// The "push" command - update remote refs along with associated objects.
+///
+/// Refspecs are `[+]<src>[:<dst>]`, and default to the remote's push refspecs
+/// (`remote.<name>.push`), or the current branch. The remote's pushurl is used
+/// instead of its url if set. Unless `verify` is false, the pre-push hook is run before anything is sent.
+pub fn push(repo: &Repository, remote: &str, refspecs: &[String], verify: bool) -> Result<()> {
+    ensure_sha1(repo, "push")?;
+    let url = remote::push_url(repo, remote)?;
+
+    let configured = remote::push_specs(repo, remote)?;
+    let refspecs = if refspecs.is_empty() {
+        &configured
+    } else {
+        refspecs
+    };
+    let mut specs = Vec::new();
+    if refspecs.is_empty() {
+        let Some(branch) = refs::current_branch(repo)? else {
+            bail!("not on a branch, specify what to push");
+        };
+        specs.push(PushSpec::parse(repo, &branch)?);
+    }
+    for spec in refspecs {
+        specs.push(PushSpec::parse(repo, spec).with_context(|| format!("invalid refspec {spec}"))?);
+    }
+
+    let remote_refs = receive_pack_refs(&url).context("listing remote refs")?;
+
+    // Check which updates are needed and allowed.
+    println!("To {url}");
+    let mut updates = Vec::new();
+    let mut rejected = false;
+    for spec in specs {
+        let old = remote_value(&remote_refs, &spec.dst);
+        let src = short_name(spec.local.as_deref().unwrap_or_default()).to_owned();
+        let dst = short_name(&spec.dst).to_owned();
+        if old == spec.hash {
+            println!(" = [up to date]      {src} -> {dst}");
+        } else if let Some(reason) = spec.check(repo, &old)? {
+            println!(" ! [rejected]        {src} -> {dst} ({reason})");
+            rejected = true;
+        } else {
+            updates.push((spec, old));
+        }
+    }
+    if updates.is_empty() {
+        if rejected {
+            bail!("failed to push some refs to {url}");
+        }
+        return Ok(());
+    }
+
+    if verify {
+        let input = hook_input(&updates);
+        hooks::run_with_input(repo, "pre-push", &[remote, &url], input.as_bytes())?;
+    }
+
+    // Send objects reachable from what we push, but not from what the remote has.
+    let tips: Vec<String> = updates
+        .iter()
+        .map(|(spec, _)| spec.hash.clone())
+        .filter(|h| h != ZERO_HASH)
+        .collect();
+    let pack = if tips.is_empty() {
+        None
+    } else {
+        let mut exclude = Vec::new();
+        for r in &remote_refs {
+            if have_object(repo, &r.hash)? {
+                exclude.push(r.hash.clone());
+            }
+        }
+        let objects = reachable_hashes(repo, &tips, &exclude).context("listing objects to send")?;
+        let mut pack = Vec::new();
+        write_pack(repo, &mut pack, &objects).context("creating pack")?;
+        Some(pack)
+    };
+
+    let ref_updates: Vec<RefUpdate> = updates
+        .iter()
+        .map(|(spec, old)| RefUpdate {
+            old: old.clone(),
+            new: spec.hash.clone(),
+            name: spec.dst.clone(),
+        })
+        .collect();
+    let statuses = send_pack(&url, &ref_updates, pack)?;
+
+    for (spec, old) in &updates {
+        let src = short_name(spec.local.as_deref().unwrap_or_default());
+        let dst = short_name(&spec.dst);
+        match statuses.iter().find(|(name, _)| *name == spec.dst) {
+            Some((_, None)) => {
+                let (flag, summary) = describe(repo, spec, old)?;
+                match spec.local {
+                    Some(_) => println!(" {flag} {summary:<17} {src} -> {dst}"),
+                    None => println!(" {flag} {summary:<17} {dst}"),
+                }
+                if let Some(tracking) = tracking_ref(remote, &spec.dst) {
+                    if remote.contains("://") {
+                        continue;
+                    }
+                    let current = refs::resolve(repo, &tracking)?;
+                    if spec.hash == ZERO_HASH {
+                        if current.is_some() {
+                            refs::delete(repo, &tracking, None)?;
+                        }
+                    } else if current.as_ref() != Some(&spec.hash) {
+                        let reflog_message = "update by push";
+                        refs::update(
+                            repo,
+                            &tracking,
+                            current.as_deref(),
+                            &spec.hash,
+                            &committer(),
+                            reflog_message,
+                        )?;
+                    }
+                }
+            }
+            Some((_, Some(reason))) => {
+                println!(" ! [remote rejected] {src} -> {dst} ({reason})");
+                rejected = true;
+            }
+            None => {
+                println!(" ! [no status]       {src} -> {dst}");
+                rejected = true;
+            }
+        }
+    }
+
+    if rejected {
+        bail!("failed to push some refs to {url}");
+    }
+    Ok(())
 }
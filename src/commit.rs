@@ -0,0 +1,164 @@
+//! Parsing commit objects, and signing them.
+
+use anyhow::{bail, Context, Result};
+use std::str;
+
+use crate::obj_read::{ObjReader, MAX_METADATA_SIZE};
+use crate::obj_type::ObjType;
+use crate::object_id::HashAlgo;
+use crate::repository::Repository;
+use crate::shallow;
+
+/// The parsed content of a commit object.
+pub struct Commit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    /// Committer date, in seconds since the epoch (0 if missing or malformed)
+    pub time: i64,
+    /// Author name and email, as `Name <email>`
+    pub author: String,
+    /// Author date, in seconds since the epoch
+    pub author_time: i64,
+    /// Time zone of the author date, like "+0100"
+    pub author_zone: String,
+    /// Committer name and email, as `Name <email>`
+    pub committer: String,
+    /// Time zone of the committer date
+    pub committer_zone: String,
+    pub message: Vec<u8>,
+}
+
+/// Split an identity with a date, as found in commits and tags, into
+/// `Name <email>`, the time and the time zone (0 and +0000 if malformed).
+pub fn split_ident(ident: &str) -> (&str, i64, &str) {
+    let end = ident.rfind('>').map_or(0, |i| i + 1);
+    let mut fields = ident[end..].split_whitespace();
+    let time = fields.next().and_then(|t| t.parse().ok()).unwrap_or(0);
+    (&ident[..end], time, fields.next().unwrap_or("+0000"))
+}
+
+/// Name of the header holding the signature of a commit.
+fn signature_header(repo: &Repository) -> Result<&'static str> {
+    Ok(match repo.hash_algo()? {
+        HashAlgo::Sha1 => "gpgsig",
+        HashAlgo::Sha256 => "gpgsig-sha256",
+    })
+}
+
+/// Add a signature of a commit's content as a header, after the others.
+pub fn add_signature(repo: &Repository, content: &[u8], signature: &str) -> Result<Vec<u8>> {
+    let end = match content.windows(2).position(|w| w == b"\n\n") {
+        Some(end) => end + 1,
+        None => content.len(),
+    };
+    let mut out = content[..end].to_vec();
+    out.extend_from_slice(signature_header(repo)?.as_bytes());
+    // Continuation lines start with a space.
+    for line in signature.trim_end_matches('\n').split('\n') {
+        out.push(b' ');
+        out.extend_from_slice(line.as_bytes());
+        out.push(b'\n');
+    }
+    out.extend_from_slice(&content[end..]);
+    Ok(out)
+}
+
+/// Split a commit's content into what was signed, that is the content
+/// without the signature header, and the signature, if there is one.
+pub fn split_signature(repo: &Repository, content: &[u8]) -> Result<Option<(Vec<u8>, String)>> {
+    let header = signature_header(repo)?.as_bytes();
+    let end = match content.windows(2).position(|w| w == b"\n\n") {
+        Some(end) => end + 1,
+        None => content.len(),
+    };
+    let mut payload = Vec::new();
+    let mut signature = Vec::new();
+    let mut in_signature = false;
+    for line in content[..end].split_inclusive(|&c| c == b'\n') {
+        if let Some(rest) = line.strip_prefix(header).and_then(|l| l.strip_prefix(b" ")) {
+            signature.extend_from_slice(rest);
+            in_signature = true;
+        } else if let (true, Some(rest)) = (in_signature, line.strip_prefix(b" ")) {
+            signature.extend_from_slice(rest);
+        } else {
+            payload.extend_from_slice(line);
+            in_signature = false;
+        }
+    }
+    if signature.is_empty() {
+        return Ok(None);
+    }
+    payload.extend_from_slice(&content[end..]);
+    let signature = String::from_utf8(signature).context("signature is not UTF-8")?;
+    Ok(Some((payload, signature)))
+}
+
+impl Commit {
+    /// Parse a commit from an object reader; the hash is needed to tell
+    /// if the commit is a shallow boundary, in which case it has no parents.
+    ///
+    /// Only the headers we need are parsed, others are ignored.
+    pub fn from_object(repo: &Repository, object: ObjReader, hash: &str) -> Result<Self> {
+        if object.obj_type != ObjType::Commit {
+            bail!("not a commit");
+        }
+        let data = object
+            .read_to_vec(MAX_METADATA_SIZE)
+            .context("reading commit")?;
+        let (headers, message) = match data.windows(2).position(|w| w == b"\n\n") {
+            Some(end) => (&data[..end], &data[end + 2..]),
+            None => (&data[..], &[][..]),
+        };
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut time = 0;
+        let mut author = String::new();
+        let mut author_time = 0;
+        let mut author_zone = String::new();
+        let mut committer = String::new();
+        let mut committer_zone = String::new();
+        for line in headers.split(|&c| c == b'\n') {
+            // Continuation lines of multi-line headers (gpgsig, mergetag)
+            if line.first() == Some(&b' ') {
+                continue;
+            }
+            let line = str::from_utf8(line).context("header is not UTF-8")?;
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "tree" => tree = Some(value.to_owned()),
+                "parent" => parents.push(value.to_owned()),
+                // author <name> <<email>> <time> <tz>
+                "author" => {
+                    let (ident, time, zone) = split_ident(value);
+                    (author, author_time, author_zone) = (ident.to_owned(), time, zone.to_owned());
+                }
+                // committer <name> <<email>> <time> <tz>
+                "committer" => {
+                    let (ident, date, zone) = split_ident(value);
+                    (committer, time, committer_zone) = (ident.to_owned(), date, zone.to_owned());
+                }
+                _ => (),
+            }
+        }
+        let message = message.to_vec();
+
+        let Some(tree) = tree else {
+            bail!("no tree header");
+        };
+        if shallow::is_shallow(repo, hash)? {
+            parents.clear();
+        }
+        Ok(Commit {
+            tree,
+            parents,
+            time,
+            author,
+            author_time,
+            author_zone,
+            committer,
+            committer_zone,
+            message,
+        })
+    }
+}
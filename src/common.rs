@@ -1,29 +1,190 @@
 //! Basic functions used by several other modules.
 
 use anyhow::{anyhow, bail, Context, Result};
-use std::path::PathBuf;
-use std::sync::LazyLock;
-
-static GIT_DIR: LazyLock<Result<PathBuf>> = LazyLock::new(|| {
-    let cwd = std::env::current_dir().context("getting current directory (looking for .git)")?;
-    for dir in cwd.ancestors() {
-        if dir.join(".git").is_dir() {
-            return Ok(dir.join(".git"));
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::str;
+use std::thread;
+
+use crate::platform;
+use crate::repository::Repository;
+
+/// Make a path given on the command line relative to the root of the worktree,
+/// as a path in the index or in trees.
+pub fn repo_path(repo: &Repository, arg: &Path) -> Result<Vec<u8>> {
+    let root = repo.work_tree()?;
+    let full = match arg.strip_prefix(root) {
+        Ok(inside) => inside.to_owned(),
+        Err(_) if arg.is_absolute() => bail!("{} is outside the repository", arg.display()),
+        Err(_) => repo.prefix().join(arg),
+    };
+    let mut out: Vec<Cow<[u8]>> = Vec::new();
+    for comp in full.components() {
+        match comp {
+            Component::Normal(name) => out.push(platform::bytes(name)),
+            Component::CurDir => (),
+            Component::ParentDir if out.pop().is_some() => (),
+            _ => bail!("{} is outside the repository", arg.display()),
         }
     }
-    bail!("not a git repository (or any of the parent directories): .git");
-});
+    Ok(out.join(&b'/'))
+}
+
+/// Tell if a path from the index or a tree is the given path, or inside it if
+/// it is a directory (the empty path being the root).
+pub fn is_under(path: &[u8], dir: &[u8]) -> bool {
+    dir.is_empty() || path == dir || path.strip_prefix(dir).is_some_and(|p| p[0] == b'/')
+}
+
+/// Add context to an error that happened while writing to the repository,
+/// explaining the likely cause if it was due to missing permissions.
+pub fn write_error(err: io::Error, path: &Path) -> anyhow::Error {
+    if err.kind() == io::ErrorKind::PermissionDenied {
+        anyhow!(err).context(format!(
+            "could not create {}: the repository is not writable by the current user",
+            path.display()
+        ))
+    } else {
+        anyhow!(err).context(format!("could not create {}", path.display()))
+    }
+}
 
-/// Return the path to the .git directory, for example "/path/to/repo/.git".
-pub fn git_dir() -> Result<&'static PathBuf> {
-    GIT_DIR.as_ref().map_err(|e| anyhow!(e.to_string()))
+/// Read the content of files one after the other, "-" being standard input,
+/// which is also read if there are no files.
+pub fn read_inputs(paths: &[PathBuf]) -> Result<Vec<u8>> {
+    let mut input = Vec::new();
+    if paths.is_empty() {
+        io::stdin()
+            .read_to_end(&mut input)
+            .context("reading standard input")?;
+    }
+    for path in paths {
+        if path == Path::new("-") {
+            io::stdin()
+                .read_to_end(&mut input)
+                .context("reading standard input")?;
+        } else {
+            let data = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+            input.extend(data);
+        }
+    }
+    Ok(input)
+}
+
+/// Give a path relative to the root of the worktree relative to the current
+/// directory instead, like "../a" from "b".
+pub fn relative(path: &[u8], prefix: &[u8]) -> Vec<u8> {
+    let mut prefix: Vec<&[u8]> = prefix
+        .split(|&c| c == b'/')
+        .filter(|c| !c.is_empty())
+        .collect();
+    let mut path = path;
+    while let Some(first) = prefix.first() {
+        match path.strip_prefix(*first) {
+            Some(rest) if rest.first() == Some(&b'/') => {
+                path = &rest[1..];
+                prefix.remove(0);
+            }
+            _ => break,
+        }
+    }
+    [b"../".repeat(prefix.len()), path.to_vec()].concat()
+}
+
+/// Quote a path like git does, in C style, if it has control characters,
+/// quotes, backslashes or non-ASCII bytes (core.quotePath being true).
+pub fn quote_c(path: &[u8]) -> Option<String> {
+    let special = |c: u8| c < 0x20 || c == b'"' || c == b'\\' || c >= 0x7f;
+    if !path.iter().any(|&c| special(c)) {
+        return None;
+    }
+    let mut out = String::from("\"");
+    for &c in path {
+        match c {
+            7 => out.push_str("\\a"),
+            8 => out.push_str("\\b"),
+            b'\t' => out.push_str("\\t"),
+            b'\n' => out.push_str("\\n"),
+            11 => out.push_str("\\v"),
+            12 => out.push_str("\\f"),
+            b'\r' => out.push_str("\\r"),
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            c if special(c) => out.push_str(&format!("\\{c:03o}")),
+            c => out.push(char::from(c)),
+        }
+    }
+    out.push('"');
+    Some(out)
+}
+
+/// Unquote a path quoted like C strings, as git does for unusual names.
+pub fn unquote_c(name: &[u8]) -> Result<Vec<u8>> {
+    let Some(inner) = name.strip_prefix(b"\"").and_then(|n| n.strip_suffix(b"\"")) else {
+        return Ok(name.to_vec());
+    };
+    let mut out = Vec::new();
+    let mut bytes = inner.iter().copied();
+    while let Some(c) = bytes.next() {
+        if c != b'\\' {
+            out.push(c);
+            continue;
+        }
+        let c = bytes.next().context("bad quoted name")?;
+        out.push(match c {
+            b'a' => 7,
+            b'b' => 8,
+            b't' => b'\t',
+            b'n' => b'\n',
+            b'v' => 11,
+            b'f' => 12,
+            b'r' => b'\r',
+            b'0'..=b'7' => {
+                let digits = [c, bytes.next().unwrap_or(0), bytes.next().unwrap_or(0)];
+                let digits = str::from_utf8(&digits).unwrap_or("");
+                u8::from_str_radix(digits, 8).context("bad quoted name")?
+            }
+            c => c,
+        });
+    }
+    Ok(out)
+}
+
+/// Quote an argument for the shell.
+pub fn sq_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Match text against a shell-like pattern, where '*' matches any sequence of
+/// bytes (including '/') and '?' matches a single byte.
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|i| wildmatch(rest, &text[i..])),
+        Some((b'?', rest)) => !text.is_empty() && wildmatch(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && wildmatch(rest, &text[1..]),
+    }
 }
 
-/// Return the path for an object identified by its hash.
-/// For example, "/path/to/repo/.git/objects/01/2345...40".
-pub fn path_from_hash(hash: &str) -> Result<PathBuf> {
-    Ok(git_dir()?
-        .join("objects")
-        .join(&hash[0..2])
-        .join(&hash[2..]))
+/// Run a command with the given input, capturing its output.
+pub fn run(command: &mut Command, input: &[u8]) -> Result<Output> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("running {program}"))?;
+    let mut stdin = child.stdin.take().expect("stdin was piped");
+    // Feed the input from another thread, so that the output doesn't block.
+    thread::scope(|scope| {
+        // The program may exit without reading its input: errors show in its status.
+        scope.spawn(move || stdin.write_all(input));
+        child
+            .wait_with_output()
+            .with_context(|| format!("waiting for {program}"))
+    })
 }
@@ -0,0 +1,617 @@
+//! Reading and writing the repository configuration (.git/config).
+//!
+//! Only a subset of the syntax from git-config(1) is supported: no line
+//! continuations, and no global or system configuration files. Includes
+//! (include.path, and `includeIf.<condition>.path` with gitdir:, gitdir/i: and
+//! onbranch: conditions) are followed when reading.
+//!
+//! Besides strings, values can be read as booleans with get_bool() and
+//! integers with get_int(), like `git config --type=bool|int`.
+
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::common::wildmatch;
+use crate::lock::LockFile;
+use crate::repository::{common_dir_of, Repository};
+
+/// Includes nested deeper than this are an error, like in git (to catch loops).
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Values given on the command line with -c, which take precedence over the file.
+static OVERRIDES: OnceLock<Vec<Variable>> = OnceLock::new();
+
+/// A variable read from a configuration file, or from the command line.
+#[derive(Clone, Debug)]
+struct Variable {
+    /// Section, in the same form as split_key()
+    section: String,
+    /// Name, in lowercase
+    name: String,
+    /// None for a name without `=`, which means true for booleans
+    value: Option<String>,
+}
+
+impl Variable {
+    /// The full key, like "remote.origin.url".
+    fn key(&self) -> String {
+        let section = match self.section.split_once(" \"") {
+            Some((section, sub)) => format!("{section}.{}", &sub[..sub.len() - 1]),
+            None => self.section.clone(),
+        };
+        format!("{section}.{}", self.name)
+    }
+}
+
+/// Set values from `-c <name>=<value>` command-line options,
+/// which apply for the duration of this command only.
+pub fn set_overrides(options: &[String]) -> Result<()> {
+    let mut overrides = Vec::new();
+    for option in options {
+        let (key, value) = match option.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_owned())),
+            None => (option.as_str(), None),
+        };
+        let (section, name) = split_key(key)?;
+        overrides.push(Variable {
+            section,
+            name,
+            value,
+        });
+    }
+    OVERRIDES
+        .set(overrides)
+        .expect("overrides are only set once");
+    Ok(())
+}
+
+/// Split a key like "remote.origin.url" into a (section, name) pair like
+/// ("remote \"origin\"", "url"), with section and name normalised to lowercase
+/// (subsections are case-sensitive).
+fn split_key(key: &str) -> Result<(String, String)> {
+    let Some((section, name)) = key.rsplit_once('.') else {
+        bail!("key does not contain a section: {key}");
+    };
+    if section.is_empty() || name.is_empty() {
+        bail!("invalid key: {key}");
+    }
+    Ok((section_from_name(section), name.to_lowercase()))
+}
+
+/// Give the section for a name like "remote.origin", in the same form as split_key().
+fn section_from_name(name: &str) -> String {
+    match name.split_once('.') {
+        Some((section, sub)) => format!("{} \"{sub}\"", section.to_lowercase()),
+        None => name.to_lowercase(),
+    }
+}
+
+/// Parse the section header at the start of a line, if any, giving the
+/// section and where the header ends in the line.
+fn header_in(line: &str) -> Option<(String, usize)> {
+    let header = line.trim_start().strip_prefix('[')?;
+    let (header, _) = header.split_once(']')?;
+    Some((parse_section(header), line.find(']')? + 1))
+}
+
+/// Parse a section header line (without brackets) into the same form as split_key().
+fn parse_section(header: &str) -> String {
+    match header.split_once(' ') {
+        Some((section, sub)) => format!(
+            "{} \"{}\"",
+            section.to_lowercase(),
+            sub.trim().trim_matches('"')
+        ),
+        // Deprecated [section.subsection] syntax
+        None => match header.split_once('.') {
+            Some((section, sub)) => format!("{} \"{}\"", section.to_lowercase(), sub),
+            None => header.to_lowercase(),
+        },
+    }
+}
+
+/// Parse a value: strip comments, surrounding whitespace and quotes,
+/// and replace escape sequences.
+fn parse_value(value: &str) -> Result<String> {
+    let mut out = String::new();
+    let mut quoted = false;
+    // Whitespace outside quotes is only kept if more of the value follows.
+    let mut space = String::new();
+    let mut chars = value.trim_start().chars();
+    while let Some(c) = chars.next() {
+        if !quoted && c.is_whitespace() {
+            space.push(c);
+            continue;
+        }
+        if !quoted && (c == '#' || c == ';') {
+            break;
+        }
+        out.push_str(&space);
+        space.clear();
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => out.push(match chars.next() {
+                Some('\\') => '\\',
+                Some('"') => '"',
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('b') => '\u{8}',
+                _ => bail!("bad escape sequence in config value: {value}"),
+            }),
+            c => out.push(c),
+        }
+    }
+    if quoted {
+        bail!("unterminated quote in config value: {value}");
+    }
+    Ok(out)
+}
+
+/// Format a value so that parse_value() gives it back.
+fn quote_value(value: &str) -> String {
+    let mut out = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            c => out.push(c),
+        }
+    }
+    let needs_quotes =
+        value.starts_with(char::is_whitespace) || value.ends_with(char::is_whitespace);
+    if needs_quotes || value.contains(['#', ';']) {
+        out = format!("\"{out}\"");
+    }
+    out
+}
+
+/// Parse the variables in the text of a config file, with the index of the
+/// line each is on.
+fn parse(text: &str) -> Result<Vec<(usize, Variable)>> {
+    let mut variables = Vec::new();
+    let mut section = String::new();
+    for (i, line) in text.lines().enumerate() {
+        let mut line = line.trim();
+        if let Some(header) = line.strip_prefix('[') {
+            let Some((header, rest)) = header.split_once(']') else {
+                bail!("invalid section header in config, line {}: {line}", i + 1);
+            };
+            section = parse_section(header);
+            // A variable may follow on the same line.
+            line = rest.trim();
+        }
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        let (name, value) = match line.split_once('=') {
+            Some((name, value)) => (name, Some(parse_value(value)?)),
+            None => (line, None),
+        };
+        variables.push((
+            i,
+            Variable {
+                section: section.clone(),
+                name: name.trim().to_lowercase(),
+                value,
+            },
+        ));
+    }
+    Ok(variables)
+}
+
+/// Read a config file, returning an empty string if it doesn't exist.
+fn read_file(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+/// Expand a path from the configuration: `~/` is the home directory,
+/// and relative paths are relative to the given directory.
+fn expand_path(path: &str, dir: &Path) -> Result<PathBuf> {
+    match path.strip_prefix("~/") {
+        Some(rest) => {
+            let home = env::var_os("HOME").context("HOME is not set, for ~/ in config")?;
+            Ok(Path::new(&home).join(rest))
+        }
+        None => Ok(dir.join(path)),
+    }
+}
+
+/// Tell if the condition of an includeIf section holds. See
+/// "Conditional includes" in git-config(1), the patterns are simplified
+/// in that `*` also matches slashes.
+fn include_condition(condition: &str, dir: &Path, git_dir: &Path) -> Result<bool> {
+    let (pattern, text, fold_case) = if let Some(pattern) = condition.strip_prefix("gitdir:") {
+        (pattern, git_dir, false)
+    } else if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+        (pattern, git_dir, true)
+    } else if let Some(pattern) = condition.strip_prefix("onbranch:") {
+        let head = read_file(&git_dir.join("HEAD"))?;
+        let Some(branch) = head.trim_end().strip_prefix("ref: refs/heads/") else {
+            return Ok(false);
+        };
+        let mut pattern = pattern.to_owned();
+        if pattern.ends_with('/') {
+            pattern.push_str("**");
+        }
+        return Ok(wildmatch(pattern.as_bytes(), branch.as_bytes()));
+    } else {
+        return Ok(false);
+    };
+
+    let mut pattern = if pattern.starts_with("~/") {
+        expand_path(pattern, dir)?.to_string_lossy().into_owned()
+    } else if let Some(rest) = pattern.strip_prefix("./") {
+        dir.join(rest).to_string_lossy().into_owned()
+    } else if pattern.starts_with('/') {
+        pattern.to_owned()
+    } else {
+        format!("**/{pattern}")
+    };
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+    let text = fs::canonicalize(text).unwrap_or_else(|_| text.to_owned());
+    let mut text = text.to_string_lossy().into_owned();
+    if fold_case {
+        pattern = pattern.to_lowercase();
+        text = text.to_lowercase();
+    }
+    Ok(wildmatch(pattern.as_bytes(), text.as_bytes()))
+}
+
+/// Read the variables in a config file, replacing includes with the
+/// variables of the included files.
+fn read_variables(
+    path: &Path,
+    git_dir: &Path,
+    depth: usize,
+    out: &mut Vec<Variable>,
+) -> Result<()> {
+    if depth > MAX_INCLUDE_DEPTH {
+        bail!(
+            "exceeded maximum include depth ({MAX_INCLUDE_DEPTH}) including {}",
+            path.display()
+        );
+    }
+    let dir = path.parent().unwrap_or(Path::new("."));
+    for (_, variable) in
+        parse(&read_file(path)?).with_context(|| format!("in {}", path.display()))?
+    {
+        let include = match (
+            variable.section.as_str(),
+            variable.name.as_str(),
+            &variable.value,
+        ) {
+            ("include", "path", Some(value)) => Some(value.clone()),
+            (section, "path", Some(value)) => match section.strip_prefix("includeif \"") {
+                Some(condition)
+                    if include_condition(&condition[..condition.len() - 1], dir, git_dir)? =>
+                {
+                    Some(value.clone())
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        out.push(variable);
+        if let Some(include) = include {
+            read_variables(&expand_path(&include, dir)?, git_dir, depth + 1, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// All variables, from the repository configuration then the command line.
+fn variables(git_dir: &Path) -> Result<Vec<Variable>> {
+    let mut variables = Vec::new();
+    read_variables(
+        &common_dir_of(git_dir).join("config"),
+        git_dir,
+        0,
+        &mut variables,
+    )?;
+    variables.extend(OVERRIDES.get().into_iter().flatten().cloned());
+    Ok(variables)
+}
+
+/// List all variables in order, as (key, value) pairs, like `git config --list`.
+pub fn list(repo: &Repository) -> Result<Vec<(String, Option<String>)>> {
+    Ok(variables(repo.git_dir())?
+        .into_iter()
+        .map(|variable| (variable.key(), variable.value))
+        .collect())
+}
+
+/// Get all values for the given key, in order of appearance.
+/// A name without a value counts as "true".
+pub fn get_all(repo: &Repository, key: &str) -> Result<Vec<String>> {
+    get_all_in(repo.git_dir(), key)
+}
+
+/// Same as get_all(), in the repository with the given .git directory.
+fn get_all_in(git_dir: &Path, key: &str) -> Result<Vec<String>> {
+    Ok(values_in(git_dir, key)?
+        .into_iter()
+        .map(|value| value.unwrap_or_else(|| "true".to_owned()))
+        .collect())
+}
+
+/// Get all values for the given key, with None for names without a value.
+pub fn get_values(repo: &Repository, key: &str) -> Result<Vec<Option<String>>> {
+    values_in(repo.git_dir(), key)
+}
+
+/// Same as get_values(), in the repository with the given .git directory.
+fn values_in(git_dir: &Path, key: &str) -> Result<Vec<Option<String>>> {
+    let (section, name) = split_key(key)?;
+    Ok(variables(git_dir)?
+        .into_iter()
+        .filter(|variable| variable.section == section && variable.name == name)
+        .map(|variable| variable.value)
+        .collect())
+}
+
+/// Get the value for the given key (the last one if there are several).
+pub fn get(repo: &Repository, key: &str) -> Result<Option<String>> {
+    Ok(get_all(repo, key)?.pop())
+}
+
+/// Same as get(), in the repository with the given .git directory,
+/// for use before the repository is fully known.
+pub fn get_in(git_dir: &Path, key: &str) -> Result<Option<String>> {
+    Ok(get_all_in(git_dir, key)?.pop())
+}
+
+/// Parse a boolean value: true, yes, on or a non-zero number for true,
+/// false, no, off, 0 or the empty string for false (case-insensitive).
+pub fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" | "" => Ok(false),
+        _ => match parse_int(key, value) {
+            Ok(n) => Ok(n != 0),
+            Err(_) => bail!("bad boolean config value '{value}' for '{key}'"),
+        },
+    }
+}
+
+/// Parse an integer value, with an optional k, m or g suffix
+/// (case-insensitive) to multiply it by 1024, 1024² or 1024³.
+pub fn parse_int(key: &str, value: &str) -> Result<i64> {
+    let (digits, unit) = match value.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&value[..i], c.to_ascii_lowercase()),
+        _ => (value, ' '),
+    };
+    let factor: i64 = match unit {
+        ' ' => 1,
+        'k' => 1 << 10,
+        'm' => 1 << 20,
+        'g' => 1 << 30,
+        _ => bail!("bad numeric config value '{value}' for '{key}': invalid unit"),
+    };
+    let Ok(n) = digits.parse::<i64>() else {
+        bail!("bad numeric config value '{value}' for '{key}': invalid unit");
+    };
+    n.checked_mul(factor)
+        .with_context(|| format!("bad numeric config value '{value}' for '{key}': out of range"))
+}
+
+/// Get the value for the given key as a boolean, see parse_bool().
+pub fn get_bool(repo: &Repository, key: &str) -> Result<Option<bool>> {
+    get(repo, key)?
+        .map(|value| parse_bool(key, &value))
+        .transpose()
+}
+
+/// Same as get_bool(), in the repository with the given .git directory.
+pub fn get_bool_in(git_dir: &Path, key: &str) -> Result<Option<bool>> {
+    get_in(git_dir, key)?
+        .map(|value| parse_bool(key, &value))
+        .transpose()
+}
+
+/// Get the value for the given key as an integer, see parse_int().
+pub fn get_int(repo: &Repository, key: &str) -> Result<Option<i64>> {
+    get(repo, key)?
+        .map(|value| parse_int(key, &value))
+        .transpose()
+}
+
+/// Lock .git/config and read it, for a read-modify-write: the lock is
+/// held until the new lines are written with write_lines(), so that
+/// concurrent changes aren't lost.
+fn lock_and_read(repo: &Repository) -> Result<(LockFile, String)> {
+    let path = repo.common_dir().join("config");
+    let lock = LockFile::acquire(repo, &path)?;
+    Ok((lock, read_file(&path)?))
+}
+
+/// Write the lines of .git/config back, releasing the lock.
+fn write_lines(lock: LockFile, lines: &[&str]) -> Result<()> {
+    let mut text = lines.join("\n");
+    text.push('\n');
+    lock.commit(text.as_bytes())
+}
+
+/// Add a value for the given key, appending a new section if needed.
+pub fn add(repo: &Repository, key: &str, value: &str) -> Result<()> {
+    let (lock, text) = lock_and_read(repo)?;
+    add_to(lock, &text, key, value)
+}
+
+/// Add a value to the locked configuration with the given text.
+fn add_to(lock: LockFile, text: &str, key: &str, value: &str) -> Result<()> {
+    let (want_section, name) = split_key(key)?;
+
+    // Insert after the last line of the last matching section, if any.
+    let mut lines: Vec<&str> = text.lines().collect();
+    let mut insert_at = None;
+    let mut in_section = false;
+    for (i, line) in lines.iter().enumerate() {
+        if let Some((section, _)) = header_in(line) {
+            in_section = section == want_section;
+        }
+        if in_section && !line.trim().is_empty() {
+            insert_at = Some(i + 1);
+        }
+    }
+
+    let new_line = format!("\t{name} = {}", quote_value(value));
+    let section_line = format!("[{want_section}]");
+    match insert_at {
+        Some(i) => lines.insert(i, &new_line),
+        None => {
+            lines.push(&section_line);
+            lines.push(&new_line);
+        }
+    }
+    write_lines(lock, &lines)
+}
+
+/// Set the value for the given key in .git/config, replacing the line with
+/// its current value, if any: other lines, including comments, are kept.
+/// Fails if the key has several values.
+pub fn set(repo: &Repository, key: &str, value: &str) -> Result<()> {
+    let (section, name) = split_key(key)?;
+    let (lock, text) = lock_and_read(repo)?;
+    let found: Vec<usize> = parse(&text)?
+        .into_iter()
+        .filter(|(_, variable)| variable.section == section && variable.name == name)
+        .map(|(i, _)| i)
+        .collect();
+    match found[..] {
+        [] => add_to(lock, &text, key, value),
+        [i] => {
+            let mut lines: Vec<&str> = text.lines().collect();
+            let line = lines[i];
+            // Keep the section header if the variable is on the same line.
+            let header_end = header_in(line).map_or(0, |(_, end)| end);
+            let new_line = format!("{}\t{name} = {}", &line[..header_end], quote_value(value));
+            lines[i] = &new_line;
+            write_lines(lock, &lines)
+        }
+        _ => bail!(
+            "cannot overwrite multiple values with a single value: {key} has {} values",
+            found.len()
+        ),
+    }
+}
+
+/// Remove all values for the given key from .git/config.
+pub fn unset_all(repo: &Repository, key: &str) -> Result<()> {
+    let (section, name) = split_key(key)?;
+    let (lock, text) = lock_and_read(repo)?;
+    let found: Vec<usize> = parse(&text)?
+        .into_iter()
+        .filter(|(_, variable)| variable.section == section && variable.name == name)
+        .map(|(i, _)| i)
+        .collect();
+    if found.is_empty() {
+        return Ok(());
+    }
+    let lines: Vec<&str> = text
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| match header_in(line) {
+            _ if !found.contains(&i) => Some(line),
+            // Keep the section header if the variable is on the same line.
+            Some((_, end)) => Some(&line[..end]),
+            None => None,
+        })
+        .collect();
+    // Like git, remove the section if nothing is left in it.
+    let is_empty_header = |i: usize| {
+        let header_only = header_in(lines[i])
+            .is_some_and(|(s, end)| s == section && lines[i][end..].trim().is_empty());
+        header_only
+            && lines
+                .get(i + 1)
+                .map_or(true, |next| header_in(next).is_some())
+    };
+    let lines: Vec<&str> = (0..lines.len())
+        .filter(|&i| !is_empty_header(i))
+        .map(|i| lines[i])
+        .collect();
+    write_lines(lock, &lines)
+}
+
+/// List the subsections of a section, eg the names of the remotes for
+/// "remote", sorted and without duplicates.
+pub fn subsections(repo: &Repository, section: &str) -> Result<Vec<String>> {
+    let prefix = format!("{} \"", section.to_lowercase());
+    let mut names: Vec<String> = variables(repo.git_dir())?
+        .into_iter()
+        .filter_map(|variable| {
+            let sub = variable.section.strip_prefix(&prefix)?;
+            Some(sub[..sub.len() - 1].to_owned())
+        })
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    Ok(names)
+}
+
+/// Rename a section in .git/config, eg "remote.origin" to "remote.upstream".
+/// Returns false if there is no such section.
+pub fn rename_section(repo: &Repository, old: &str, new: &str) -> Result<bool> {
+    let (old, new) = (section_from_name(old), section_from_name(new));
+    let (lock, text) = lock_and_read(repo)?;
+    let mut found = false;
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| match header_in(line) {
+            Some((section, end)) if section == old => {
+                found = true;
+                format!("[{new}]{}", &line[end..])
+            }
+            _ => line.to_owned(),
+        })
+        .collect();
+    if found {
+        write_lines(lock, &lines.iter().map(String::as_str).collect::<Vec<_>>())?;
+    }
+    Ok(found)
+}
+
+/// Remove a section from .git/config with all its variables (and the
+/// comments in it), eg "remote.origin". Returns false if there is no such section.
+pub fn remove_section(repo: &Repository, name: &str) -> Result<bool> {
+    let removed = section_from_name(name);
+    let (lock, text) = lock_and_read(repo)?;
+    let mut found = false;
+    let mut in_section = false;
+    let lines: Vec<&str> = text
+        .lines()
+        .filter(|line| {
+            if let Some((section, _)) = header_in(line) {
+                in_section = section == removed;
+                found |= in_section;
+            }
+            !in_section
+        })
+        .collect();
+    if found {
+        write_lines(lock, &lines)?;
+    }
+    Ok(found)
+}
+
+/// Get the URL for a remote, allowing a URL to be given directly instead of a name.
+pub fn remote_url(repo: &Repository, remote: &str) -> Result<String> {
+    if remote.contains("://") {
+        return Ok(remote.to_owned());
+    }
+    get(repo, &format!("remote.{remote}.url"))?
+        .with_context(|| format!("no URL configured for remote {remote}"))
+}
@@ -0,0 +1,135 @@
+//! Counting objects and the disk space they use, like git count-objects.
+
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::object_id::is_hash;
+use crate::pack_read;
+use crate::repository::Repository;
+
+/// Files which may come with a pack, see gitrepository-layout(5).
+pub const PACK_EXTENSIONS: [&str; 6] = ["idx", "keep", "promisor", "bitmap", "rev", "mtimes"];
+
+/// Statistics about the object database; sizes are in bytes.
+#[derive(Default)]
+pub struct Counts {
+    /// Number of loose objects
+    pub count: usize,
+    /// Disk space used by loose objects
+    pub size: u64,
+    /// Number of objects in packs
+    pub in_pack: usize,
+    /// Number of packs
+    pub packs: usize,
+    /// Size of packs and their indexes
+    pub size_pack: u64,
+    /// Number of loose objects which are also in a pack
+    pub prune_packable: usize,
+    /// Files in the object database which are neither objects nor packs
+    pub garbage: Vec<PathBuf>,
+    /// Size of those files
+    pub size_garbage: u64,
+}
+
+/// Get the space used by a file on disk, rather than its size, like git
+/// (where the platform tells it).
+fn disk_usage(meta: &fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        meta.blocks() * 512
+    }
+    #[cfg(not(unix))]
+    meta.len()
+}
+
+impl Counts {
+    /// Record a file that shouldn't be there.
+    fn add_garbage(&mut self, path: &Path, meta: &fs::Metadata) {
+        self.garbage.push(path.to_owned());
+        self.size_garbage += meta.len();
+    }
+
+    /// Count loose objects, in .git/objects/xx directories.
+    fn add_loose(&mut self, repo: &Repository, dir: &Path, packed: &HashSet<String>) -> Result<()> {
+        for entry in fs::read_dir(dir).with_context(|| format!("listing {}", dir.display()))? {
+            let entry = entry.with_context(|| format!("listing {}", dir.display()))?;
+            let prefix = entry.file_name().to_string_lossy().into_owned();
+            if prefix.len() != 2 || !prefix.bytes().all(|c| c.is_ascii_hexdigit()) {
+                continue;
+            }
+            let subdir = entry.path();
+            for entry in
+                fs::read_dir(&subdir).with_context(|| format!("listing {}", subdir.display()))?
+            {
+                let entry = entry.with_context(|| format!("listing {}", subdir.display()))?;
+                let meta = entry.metadata()?;
+                let hash = format!("{prefix}{}", entry.file_name().to_string_lossy());
+                if !is_hash(repo, &hash) {
+                    self.add_garbage(&entry.path(), &meta);
+                    continue;
+                }
+                self.count += 1;
+                self.size += disk_usage(&meta);
+                if packed.contains(&hash) {
+                    self.prune_packable += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Find garbage in .git/objects/pack: files not belonging to a valid pack.
+    fn add_pack_garbage(&mut self, dir: &Path) -> Result<()> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Ok(());
+        };
+        for entry in entries {
+            let entry = entry.with_context(|| format!("listing {}", dir.display()))?;
+            let meta = entry.metadata()?;
+            if meta.is_dir() {
+                continue;
+            }
+            let path = entry.path();
+            let ext = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or_default();
+            let is_pack_file = match ext {
+                "pack" => path.with_extension("idx").exists(),
+                ext if PACK_EXTENSIONS.contains(&ext) => path.with_extension("pack").exists(),
+                _ => false,
+            };
+            if !is_pack_file {
+                self.add_garbage(&path, &meta);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Count objects in loose storage and packs.
+pub fn count_objects(repo: &Repository) -> Result<Counts> {
+    let mut counts = Counts::default();
+    let mut packed = HashSet::new();
+    for (pack, hashes) in pack_read::list(repo)? {
+        // An index without its pack is garbage, see add_pack_garbage().
+        if !pack.exists() {
+            continue;
+        }
+        counts.packs += 1;
+        counts.in_pack += hashes.len();
+        for path in [pack.clone(), pack.with_extension("idx")] {
+            let meta =
+                fs::metadata(&path).with_context(|| format!("reading {}", path.display()))?;
+            counts.size_pack += meta.len();
+        }
+        packed.extend(hashes);
+    }
+    let objects = repo.object_dir();
+    counts.add_loose(repo, objects, &packed)?;
+    counts.add_pack_garbage(&objects.join("pack"))?;
+    Ok(counts)
+}
@@ -0,0 +1,93 @@
+//! End-of-line conversion settings and primitives, see core.autocrlf and
+//! core.eol in git-config(1). The filter module decides what to convert.
+
+use anyhow::{bail, Result};
+
+use crate::config;
+use crate::repository::Repository;
+
+/// The values of core.autocrlf.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AutoCrlf {
+    False,
+    True,
+    Input,
+}
+
+/// Line endings of text files in the worktree.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Eol {
+    Lf,
+    Crlf,
+}
+
+/// Get the value of core.eol: the line endings of text files in the worktree
+/// when core.autocrlf is false, native (for the platform) by default.
+pub fn core_eol(repo: &Repository) -> Result<Eol> {
+    if let Some(&setting) = repo.cache().eol.get() {
+        return Ok(setting);
+    }
+    let setting = match config::get(repo, "core.eol")?.as_deref() {
+        Some("lf") => Eol::Lf,
+        Some("crlf") => Eol::Crlf,
+        Some("native") | None if cfg!(windows) => Eol::Crlf,
+        Some("native") | None => Eol::Lf,
+        Some(value) => bail!("bad core.eol value '{value}'"),
+    };
+    Ok(*repo.cache().eol.get_or_init(|| setting))
+}
+
+/// Get the value of core.autocrlf (false by default).
+pub fn autocrlf(repo: &Repository) -> Result<AutoCrlf> {
+    if let Some(&setting) = repo.cache().autocrlf.get() {
+        return Ok(setting);
+    }
+    let setting = match config::get(repo, "core.autocrlf")? {
+        Some(value) if value.eq_ignore_ascii_case("input") => AutoCrlf::Input,
+        Some(value) if config::parse_bool("core.autocrlf", &value)? => AutoCrlf::True,
+        _ => AutoCrlf::False,
+    };
+    Ok(*repo.cache().autocrlf.get_or_init(|| setting))
+}
+
+/// Tell if the content of a file looks binary, like git: it has NUL bytes or
+/// carriage returns not followed by a line feed, or it is not mostly printable.
+pub fn is_binary(data: &[u8]) -> bool {
+    let mut printable = 0;
+    let mut non_printable = 0;
+    for (i, &c) in data.iter().enumerate() {
+        match c {
+            0 => return true,
+            b'\r' if data.get(i + 1) != Some(&b'\n') => return true,
+            // Backspace, tab, line feed, form feed, carriage return, escape
+            8..=13 | 27 => printable += 1,
+            127 => non_printable += 1,
+            c if c < 32 => non_printable += 1,
+            _ => printable += 1,
+        }
+    }
+    (printable >> 7) < non_printable
+}
+
+/// Convert LF line endings to CRLF (leaving existing CRLF alone).
+pub fn lf_to_crlf(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 16);
+    for (i, &c) in data.iter().enumerate() {
+        if c == b'\n' && (i == 0 || data[i - 1] != b'\r') {
+            out.push(b'\r');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Convert CRLF line endings to LF.
+pub fn crlf_to_lf(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (i, &c) in data.iter().enumerate() {
+        if c != b'\r' || data.get(i + 1) != Some(&b'\n') {
+            out.push(c);
+        }
+    }
+    out
+}
@@ -0,0 +1,258 @@
+//! Parsing dates, for `--since`/`--until` and `<ref>@{<date>}`: a small subset
+//! of the formats git accepts; and formatting them like git log.
+//!
+//! Supported: `now`, `yesterday`, `<n> <unit>s ago` (also with dots, like
+//! `2.days.ago`, and with several units, like `1 hour 30 minutes ago`),
+//! `@<timestamp>`, and `YYYY-MM-DD[ HH:MM[:SS]]` optionally followed by a zone
+//! like `+0100` or `Z`. Dates without a zone are taken as UTC, like the dates
+//! this tool writes.
+
+use anyhow::{bail, Result};
+use std::time;
+
+/// The current time, in seconds since the epoch.
+pub fn now() -> i64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .expect("live in the present")
+        .as_secs() as i64
+}
+
+/// Parse a date into seconds since the epoch.
+pub fn parse(date: &str) -> Result<i64> {
+    let date = date.trim();
+    if let Some(timestamp) = date.strip_prefix('@') {
+        if let Ok(timestamp) = timestamp.parse() {
+            return Ok(timestamp);
+        }
+    }
+    if let Some(date) = parse_relative(date).or_else(|| parse_absolute(date)) {
+        return Ok(date);
+    }
+    bail!("invalid date {date:?}");
+}
+
+/// Length of a unit of time in seconds, months and years being approximate.
+fn unit_len(unit: &str) -> Option<i64> {
+    let len = match unit.strip_suffix('s').unwrap_or(unit) {
+        "second" | "sec" => 1,
+        "minute" | "min" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(len)
+}
+
+/// Parse `now`, `yesterday` and `<n> <unit> [<n> <unit>...] ago`.
+fn parse_relative(date: &str) -> Option<i64> {
+    let words: Vec<&str> = date
+        .split(|c: char| c == '.' || c == '_' || c.is_whitespace())
+        .filter(|w| !w.is_empty())
+        .collect();
+    match &words[..] {
+        ["now"] => return Some(now()),
+        ["yesterday"] => return Some(now() - 24 * 60 * 60),
+        _ => (),
+    }
+    let Some((&"ago", pairs)) = words.split_last() else {
+        return None;
+    };
+    if pairs.is_empty() || pairs.len() % 2 != 0 {
+        return None;
+    }
+    let mut ago = 0;
+    for pair in pairs.chunks(2) {
+        let n: i64 = pair[0].parse().ok()?;
+        ago += n * unit_len(pair[1])?;
+    }
+    Some(now() - ago)
+}
+
+/// Days since the epoch for a date in the (proleptic) Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Date in the (proleptic) Gregorian calendar for a number of days since the epoch,
+/// as (year, month, day).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Names of days of the week and months, in English whatever the locale.
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Offset of a time zone (like "+0100", as found in commits) in seconds,
+/// 0 if malformed.
+fn zone_offset(zone: &str) -> i64 {
+    match zone.as_bytes() {
+        [sign @ (b'+' | b'-'), digits @ ..] if digits.len() == 4 => {
+            let n: i64 = zone[1..].parse().unwrap_or(0);
+            let offset = (n / 100) * 60 * 60 + (n % 100) * 60;
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => 0,
+    }
+}
+
+/// Split a time into days since the epoch and seconds in the day,
+/// in the given time zone (like "+0100", as found in commits).
+fn local_day(time: i64, zone: &str) -> (i64, i64) {
+    let local = time + zone_offset(zone);
+    (
+        local.div_euclid(24 * 60 * 60),
+        local.rem_euclid(24 * 60 * 60),
+    )
+}
+
+/// Format a date in the given time zone (like "+0100", as found in commits),
+/// the way git log does by default, eg "Thu Oct 15 10:45:52 2026 +0100".
+pub fn format(time: i64, zone: &str) -> String {
+    let (days, seconds) = local_day(time, zone);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{} {} {day} {:02}:{:02}:{:02} {year} {zone}",
+        // The epoch was a Thursday.
+        DAYS[(days + 4).rem_euclid(7) as usize],
+        MONTHS[month as usize - 1],
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+    )
+}
+
+/// Format a date in the given time zone for email headers (RFC 2822),
+/// eg "Thu, 15 Oct 2026 10:45:52 +0100".
+pub fn format_rfc2822(time: i64, zone: &str) -> String {
+    let (days, seconds) = local_day(time, zone);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {day} {} {year} {:02}:{:02}:{:02} {zone}",
+        DAYS[(days + 4).rem_euclid(7) as usize],
+        MONTHS[month as usize - 1],
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+    )
+}
+
+/// Parse a date from an email header (RFC 2822), like
+/// "Thu, 15 Oct 2026 10:45:52 +0100", into seconds since the epoch
+/// and the time zone.
+pub fn parse_rfc2822(date: &str) -> Option<(i64, String)> {
+    let date = date.split_once(',').map_or(date, |(_, rest)| rest);
+    let [day, month, year, time, zone] = date.split_whitespace().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? as i64 + 1;
+    let (day, year): (i64, i64) = (day.parse().ok()?, year.parse().ok()?);
+    let mut seconds = 0;
+    let fields: Vec<_> = time.split(':').collect();
+    if !(2..=3).contains(&fields.len()) {
+        return None;
+    }
+    for (field, unit) in fields.iter().zip([60 * 60, 60, 1]) {
+        seconds += field.parse::<i64>().ok()? * unit;
+    }
+    if !(zone.len() == 5 && zone.starts_with(['+', '-'])) {
+        return None;
+    }
+    let time = days_from_civil(year, month, day) * 24 * 60 * 60 + seconds - zone_offset(zone);
+    Some((time, zone.to_owned()))
+}
+
+/// Format a date in the given time zone like git --date=iso,
+/// eg "2026-10-15 10:45:52 +0100".
+pub fn format_iso(time: i64, zone: &str) -> String {
+    let (days, seconds) = local_day(time, zone);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{year}-{month:02}-{day:02} {:02}:{:02}:{:02} {zone}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+    )
+}
+
+/// Convert a date to the MS-DOS format used in zip files, in UTC, as
+/// (date, time); times are rounded down to even seconds, and clamped to
+/// the years MS-DOS dates can represent, 1980 to 2107.
+pub fn dos_date_time(time: i64) -> (u16, u16) {
+    let (days, seconds) = local_day(time, "+0000");
+    let (year, month, day) = civil_from_days(days);
+    let (year, month, day, seconds) = match year {
+        ..=1979 => (1980, 1, 1, 0),
+        2108.. => (2107, 12, 31, 24 * 60 * 60 - 2),
+        _ => (year, month, day, seconds),
+    };
+    let date = ((year - 1980) << 9) | (month << 5) | day;
+    let time = ((seconds / 3600) << 11) | ((seconds / 60 % 60) << 5) | (seconds % 60 / 2);
+    (date as u16, time as u16)
+}
+
+/// Parse `YYYY-MM-DD[( |T)HH:MM[:SS]][ ][(+|-)HHMM|Z]`.
+fn parse_absolute(date: &str) -> Option<i64> {
+    let number = |s: &str| -> Option<i64> {
+        s.bytes()
+            .all(|c| c.is_ascii_digit())
+            .then(|| s.parse().ok())?
+    };
+    let (day, rest) = date.split_at(date.find([' ', 'T']).unwrap_or(date.len()));
+    let [year, month, day] = day.split('-').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let (year, month, day) = (number(year)?, number(month)?, number(day)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let rest = rest.get(1..).unwrap_or_default().trim();
+    let (time, zone) = rest.split_at(rest.find(['+', '-', 'Z', ' ']).unwrap_or(rest.len()));
+    let mut seconds = 0;
+    if !time.is_empty() {
+        let fields: Vec<_> = time.split(':').collect();
+        if !(2..=3).contains(&fields.len()) {
+            return None;
+        }
+        for (i, unit) in [60 * 60, 60, 1].iter().enumerate() {
+            seconds += number(fields.get(i).copied().unwrap_or("0"))? * unit;
+        }
+    }
+    let offset = match zone.trim().replace(':', "").as_str() {
+        "" | "Z" => 0,
+        zone if zone.len() == 5 => {
+            let sign = if zone.starts_with('-') { -1 } else { 1 };
+            let (hours, minutes) = (number(&zone[1..3])?, number(&zone[3..])?);
+            sign * (hours * 60 * 60 + minutes * 60)
+        }
+        _ => return None,
+    };
+    Some(days_from_civil(year, month, day) * 24 * 60 * 60 + seconds - offset)
+}
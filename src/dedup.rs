@@ -0,0 +1,198 @@
+//! Report on duplicated content across paths, and space used by each directory.
+//!
+//! Sizes are read from object headers. In partial clones (see clone --filter),
+//! those of missing blobs are asked to the promisor remote with the object-info
+//! command of protocol v2, so they aren't downloaded.
+
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io;
+use std::io::prelude::*;
+
+use crate::commit::Commit;
+use crate::config;
+use crate::fetch::have_object;
+use crate::network;
+use crate::obj_read::read_tree;
+use crate::obj_type::ObjType;
+use crate::refs;
+use crate::remote;
+use crate::repository::Repository;
+use crate::tree_entry::Mode;
+
+/// Information gathered while walking trees.
+#[derive(Default)]
+struct Walk {
+    /// Trees already visited, along with the path where they were seen.
+    seen: HashSet<(String, Vec<u8>)>,
+    /// Reverse lookup: for each blob, the set of paths where it appears.
+    paths: HashMap<String, HashSet<Vec<u8>>>,
+}
+
+impl Walk {
+    /// Record all blobs in the given tree, located at the given path
+    /// (empty for the root, otherwise ending with '/').
+    fn tree(&mut self, repo: &Repository, hash: &str, path: &[u8]) -> Result<()> {
+        if !self.seen.insert((hash.to_owned(), path.to_owned())) {
+            return Ok(());
+        }
+        let entries = read_tree(repo, hash)?;
+        for entry in entries {
+            let mut entry_path = path.to_owned();
+            entry_path.extend_from_slice(&entry.name);
+            let entry_hash = hex::encode(entry.hash);
+            match entry.mode {
+                Mode::Dir => {
+                    entry_path.push(b'/');
+                    self.tree(repo, &entry_hash, &entry_path)?;
+                }
+                Mode::File | Mode::Exe | Mode::SymLink => {
+                    self.paths.entry(entry_hash).or_default().insert(entry_path);
+                }
+                Mode::SubMod => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Get the size of a blob from its header.
+fn header_size(repo: &Repository, hash: &str) -> Result<usize> {
+    let object = repo
+        .read_object(hash)
+        .with_context(|| format!("opening blob {hash}"))?;
+    Ok(object.size)
+}
+
+/// Get the size of blobs missing from a partial clone from its promisor
+/// remote, or None if it isn't one or the remote doesn't support object-info.
+fn promisor_sizes(repo: &Repository, missing: &[String]) -> Result<Option<Vec<usize>>> {
+    let Some(remote) = config::get(repo, "extensions.partialclone")? else {
+        return Ok(None);
+    };
+    let url = remote::list_url(Some(repo), Some(&remote))?;
+    let Some(sizes) = network::object_info(&url, missing)
+        .with_context(|| format!("getting the size of missing blobs from {remote}"))?
+    else {
+        return Ok(None);
+    };
+    let sizes = missing.iter().map(|hash| match sizes.get(hash) {
+        Some(&size) => Ok(size),
+        None => bail!("blob {hash} is missing, and {remote} doesn't have it either"),
+    });
+    sizes.collect::<Result<_>>().map(Some)
+}
+
+/// Get the size of blobs. Those missing from a partial clone are asked to
+/// the promisor remote if it supports object-info; otherwise their headers
+/// are read like the others, which fails unless they can be fetched on demand
+/// (see http_store).
+fn blob_sizes<'a>(
+    repo: &Repository,
+    blobs: impl Iterator<Item = &'a String>,
+) -> Result<HashMap<String, usize>> {
+    let mut sizes = HashMap::new();
+    let mut missing = Vec::new();
+    for hash in blobs {
+        if have_object(repo, hash)? {
+            sizes.insert(hash.clone(), header_size(repo, hash)?);
+        } else {
+            missing.push(hash.clone());
+        }
+    }
+    if missing.is_empty() {
+        return Ok(sizes);
+    }
+    let missing_sizes = match promisor_sizes(repo, &missing)? {
+        Some(missing_sizes) => missing_sizes,
+        None => missing
+            .iter()
+            .map(|hash| header_size(repo, hash))
+            .collect::<Result<_>>()?,
+    };
+    sizes.extend(missing.into_iter().zip(missing_sizes));
+    Ok(sizes)
+}
+
+/// Get the root trees of all commits reachable from any ref.
+fn reachable_trees(repo: &Repository) -> Result<Vec<String>> {
+    let mut queue: VecDeque<String> = refs::list(repo, "refs/")?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect();
+    queue.extend(refs::resolve(repo, "HEAD")?);
+
+    let mut seen = HashSet::new();
+    let mut trees = Vec::new();
+    while let Some(hash) = queue.pop_front() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        let object = repo
+            .read_object(&hash)
+            .with_context(|| format!("opening {hash}"))?;
+        match object.obj_type {
+            ObjType::Commit => {
+                let commit = Commit::from_object(repo, object, &hash)
+                    .with_context(|| format!("parsing {hash}"))?;
+                queue.extend(commit.parents);
+                trees.push(commit.tree);
+            }
+            ObjType::Tree => trees.push(hash),
+            _ => (),
+        }
+    }
+    Ok(trees)
+}
+
+/// Print the report to stdout: blobs that appear under several paths,
+/// then the space used by unique blobs under each directory,
+/// for directories up to the given depth (0 is the root only).
+pub fn dedup_report(repo: &Repository, depth: usize) -> Result<()> {
+    let mut walk = Walk::default();
+    for tree in reachable_trees(repo)? {
+        walk.tree(repo, &tree, b"")?;
+    }
+    let sizes = blob_sizes(repo, walk.paths.keys())?;
+
+    // Duplicates, most wasted space first.
+    let mut dups: Vec<_> = walk.paths.iter().filter(|(_, p)| p.len() > 1).collect();
+    let wasted = |(hash, paths): &(&String, &HashSet<Vec<u8>>)| sizes[*hash] * (paths.len() - 1);
+    dups.sort_unstable_by(|a, b| wasted(b).cmp(&wasted(a)).then(a.0.cmp(b.0)));
+
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "Duplicate blobs:")?;
+    for (hash, paths) in dups {
+        let size = sizes[hash];
+        writeln!(stdout, "{hash} {size} bytes, {} paths", paths.len())?;
+        let mut paths: Vec<_> = paths.iter().collect();
+        paths.sort_unstable();
+        for path in paths {
+            stdout.write_all(b"\t")?;
+            stdout.write_all(path)?;
+            stdout.write_all(b"\n")?;
+        }
+    }
+
+    // Space by prefix: each blob counted once per directory containing it.
+    let mut prefixes: BTreeMap<&[u8], HashSet<&String>> = BTreeMap::new();
+    for (hash, paths) in &walk.paths {
+        for path in paths {
+            let dirs = path.iter().enumerate().filter(|(_, &c)| c == b'/');
+            let ends = std::iter::once(0).chain(dirs.map(|(i, _)| i + 1));
+            for end in ends.take(depth + 1) {
+                prefixes.entry(&path[..end]).or_default().insert(hash);
+            }
+        }
+    }
+
+    writeln!(stdout, "Space by path prefix:")?;
+    for (prefix, blobs) in prefixes {
+        let total: usize = blobs.iter().map(|h| sizes[*h]).sum();
+        write!(stdout, "{total}\t")?;
+        stdout.write_all(if prefix.is_empty() { b"/" } else { prefix })?;
+        stdout.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! Naming commits after the nearest tag, like git describe.
+
+use anyhow::{bail, Context, Result};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use crate::abbrev::shorten;
+use crate::commit::split_ident;
+use crate::fetch::have_object;
+use crate::obj_read::ObjReader;
+use crate::obj_type::ObjType;
+use crate::refs;
+use crate::repository::Repository;
+use crate::revwalk::{peel, reachable_commits};
+use crate::tag::Tag;
+
+/// Like git, stop looking for tags after finding this many.
+const MAX_CANDIDATES: usize = 10;
+
+/// A tag that can name a commit.
+struct Candidate {
+    name: String,
+    annotated: bool,
+    /// Date of the tagger, for annotated tags
+    date: i64,
+}
+
+impl Candidate {
+    /// Tell if this tag is better than another one for the same commit:
+    /// annotated tags first, then the most recent.
+    fn is_better(&self, other: &Candidate) -> bool {
+        match (self.annotated, other.annotated) {
+            (true, true) => self.date > other.date,
+            (annotated, other_annotated) => annotated && !other_annotated,
+        }
+    }
+}
+
+/// Get the date of the tagger of an annotated tag (0 if there is none).
+fn tagger_date(repo: &Repository, object: ObjReader) -> Result<i64> {
+    let tag = Tag::from_object(repo, object)?;
+    Ok(tag
+        .tagger
+        .as_deref()
+        .map_or(0, |ident| split_ident(ident).1))
+}
+
+/// Find the best tag for each tagged commit: only annotated tags unless
+/// `all_tags`. Also tell if lightweight tags were skipped.
+fn tagged_commits(repo: &Repository, all_tags: bool) -> Result<(HashMap<String, Candidate>, bool)> {
+    let mut tagged = HashMap::new();
+    let mut skipped = false;
+    for (name, hash) in refs::list(repo, "refs/tags/")? {
+        if !have_object(repo, &hash)? {
+            continue;
+        }
+        let object = repo.read_object(&hash)?;
+        let annotated = object.obj_type == ObjType::Tag;
+        if !annotated && !all_tags {
+            skipped = true;
+            continue;
+        }
+        let date = match annotated {
+            true => tagger_date(repo, object).with_context(|| format!("reading tag {hash}"))?,
+            false => 0,
+        };
+        let Some((commit, ObjType::Commit)) = peel(repo, &hash)? else {
+            continue;
+        };
+        let candidate = Candidate {
+            name: name["refs/tags/".len()..].to_owned(),
+            annotated,
+            date,
+        };
+        match tagged.entry(commit) {
+            Entry::Vacant(entry) => _ = entry.insert(candidate),
+            Entry::Occupied(mut entry) if candidate.is_better(entry.get()) => {
+                entry.insert(candidate);
+            }
+            Entry::Occupied(_) => (),
+        }
+    }
+    Ok((tagged, skipped))
+}
+
+/// Describe a commit: the name of the nearest tag it can reach (annotated
+/// only unless `all_tags`), followed by the number of commits on top of it
+/// and the abbreviated hash of the commit unless it is tagged itself,
+/// eg "v1.0-3-g1234abc".
+///
+/// Like git, tags are looked for by walking history most recent first;
+/// among the first few found, the one with the fewest commits on top wins.
+pub fn describe(repo: &Repository, commit: &str, all_tags: bool) -> Result<String> {
+    let (tagged, skipped) = tagged_commits(repo, all_tags)?;
+    if tagged.is_empty() {
+        match skipped {
+            true => bail!("No annotated tags can describe '{commit}'.\nHowever, there were unannotated tags: try --tags."),
+            false => bail!("No names found, cannot describe anything."),
+        }
+    }
+    let mut found = Vec::new();
+    for hash in reachable_commits(repo, &[commit.to_owned()], &[])? {
+        if let Some(candidate) = tagged.get(&hash) {
+            if hash == commit {
+                return Ok(candidate.name.clone());
+            }
+            found.push((hash, candidate));
+            if found.len() == MAX_CANDIDATES {
+                break;
+            }
+        }
+    }
+    let mut best: Option<(usize, &Candidate)> = None;
+    for (hash, candidate) in found {
+        let depth = reachable_commits(repo, &[commit.to_owned()], &[hash])?.len();
+        match best {
+            Some((best_depth, _)) if best_depth <= depth => (),
+            _ => best = Some((depth, candidate)),
+        }
+    }
+    let Some((depth, candidate)) = best else {
+        match skipped {
+            true => bail!("No annotated tags can describe '{commit}'.\nHowever, there were unannotated tags: try --tags."),
+            false => bail!("No tags can describe '{commit}'."),
+        }
+    };
+    Ok(format!(
+        "{}-{depth}-g{}",
+        candidate.name,
+        shorten(repo, commit)?
+    ))
+}
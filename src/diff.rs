@@ -0,0 +1,1675 @@
+//! Comparing trees, the index and the worktree, and printing unified diffs.
+//!
+//! Lines are compared with Myers' algorithm, then each group of changed lines
+//! is slid down as far as possible, which matches git's output in most cases.
+//! Renames can be detected like git does (but not copies), and paths are
+//! printed without quoting.
+//!
+//! Diffs can be colored like git's, with whitespace errors highlighted in
+//! added lines (only with the default core.whitespace rules), or show the
+//! words changed in lines, with words being separated by whitespace.
+
+use anyhow::{bail, Context, Result};
+use flate2::{write::ZlibEncoder, Compression};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::abbrev::{min_len, shorten};
+use crate::attributes::{self, State};
+use crate::base85;
+use crate::color::{self, RESET};
+use crate::config;
+use crate::filter;
+use crate::index;
+use crate::obj_read::{read_blob, read_tree};
+use crate::obj_type::ObjType;
+use crate::obj_write::write_object;
+use crate::pack_index::create_delta;
+use crate::platform;
+use crate::repository::Repository;
+use crate::tree_entry::{Entry, Mode};
+use crate::tree_write::hash_file;
+
+/// Number of unchanged lines shown around changes.
+const CONTEXT: usize = 3;
+
+/// Width of a diffstat, same as git when not printing to a terminal.
+pub const STAT_WIDTH: usize = 80;
+
+/// Like git, only look for NUL bytes at the start of files to detect binaries.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// Similarity scores are out of this, like git.
+pub const MAX_SCORE: u32 = 60000;
+
+/// The minimum similarity of a rename by default: 50%.
+pub const DEFAULT_RENAME_SCORE: u32 = MAX_SCORE / 2;
+
+/// Hashes of chunks of content are modulo this, like git.
+const HASHBASE: u32 = 107927;
+
+/// The number of best matches kept for each added path, like git.
+const CANDIDATES_PER_PATH: usize = 4;
+
+/// Paths with their content, sorted in index order.
+pub type PathList = Vec<(Vec<u8>, Side)>;
+
+/// One side of a change: what a path contains.
+#[derive(Clone)]
+pub struct Side {
+    pub mode: Mode,
+    pub hash: String,
+    /// Set when the content is in the worktree rather than in the object store
+    pub file: Option<PathBuf>,
+}
+
+/// A path whose content differs between the two sides.
+/// A missing side means the path was added or deleted.
+pub struct Change {
+    pub path: Vec<u8>,
+    pub old: Option<Side>,
+    pub new: Option<Side>,
+    /// The path has conflicts in the index, so there is nothing to compare
+    pub unmerged: bool,
+    /// Set when the old side is at another path, renamed to this one
+    pub renamed: Option<Rename>,
+}
+
+/// Where a renamed path comes from.
+pub struct Rename {
+    pub from: Vec<u8>,
+    /// How similar the old and new contents are, out of MAX_SCORE
+    pub score: u32,
+}
+
+/// Read the entries of a tree, or none if there is no tree.
+fn tree_entries(repo: &Repository, hash: Option<&str>) -> Result<Vec<Entry>> {
+    match hash {
+        Some(hash) => read_tree(repo, hash),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Key used to order entries in a tree: directories sort as if followed by '/'.
+fn sort_key(entry: &Entry) -> Vec<u8> {
+    let mut key = entry.name.clone();
+    if entry.mode == Mode::Dir {
+        key.push(b'/');
+    }
+    key
+}
+
+/// Split a tree entry into the hash of a subtree, or a side of a change.
+fn split_entry(entry: Option<Entry>) -> (Option<String>, Option<Side>) {
+    match entry {
+        Some(entry) if entry.mode == Mode::Dir => (Some(hex::encode(entry.hash)), None),
+        Some(entry) => (
+            None,
+            Some(Side {
+                mode: entry.mode,
+                hash: hex::encode(entry.hash),
+                file: None,
+            }),
+        ),
+        None => (None, None),
+    }
+}
+
+/// Compare two trees (None standing for an empty tree), appending changes for
+/// paths below `prefix`. Entries are paired by name, and subtrees with the
+/// same hash are skipped without being read.
+fn walk_trees(
+    repo: &Repository,
+    old: Option<&str>,
+    new: Option<&str>,
+    prefix: &[u8],
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    let mut old_entries = tree_entries(repo, old)?.into_iter().peekable();
+    let mut new_entries = tree_entries(repo, new)?.into_iter().peekable();
+    loop {
+        let order = match (old_entries.peek(), new_entries.peek()) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(o), Some(n)) => sort_key(o).cmp(&sort_key(n)),
+        };
+        let (o, n) = match order {
+            Ordering::Less => (old_entries.next(), None),
+            Ordering::Greater => (None, new_entries.next()),
+            Ordering::Equal => (old_entries.next(), new_entries.next()),
+        };
+        if let (Some(o), Some(n)) = (&o, &n) {
+            if o.hash == n.hash && o.mode == n.mode {
+                continue;
+            }
+        }
+        let name = o
+            .as_ref()
+            .or(n.as_ref())
+            .expect("one side exists")
+            .name
+            .clone();
+        let path = [prefix, &name].concat();
+        let (old_tree, old_side) = split_entry(o);
+        let (new_tree, new_side) = split_entry(n);
+        if old_tree.is_some() || new_tree.is_some() {
+            let sub_prefix = [&path[..], b"/"].concat();
+            walk_trees(
+                repo,
+                old_tree.as_deref(),
+                new_tree.as_deref(),
+                &sub_prefix,
+                changes,
+            )?;
+        }
+        if old_side.is_some() || new_side.is_some() {
+            changes.push(Change {
+                path,
+                old: old_side,
+                new: new_side,
+                unmerged: false,
+                renamed: None,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Compare two trees, everything being added if there is no old tree.
+pub fn diff_trees(repo: &Repository, old: Option<&str>, new: &str) -> Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    walk_trees(repo, old, Some(new), b"", &mut changes)?;
+    Ok(changes)
+}
+
+/// List all paths in a tree with their content, in index order.
+pub fn flatten_tree(
+    repo: &Repository,
+    hash: &str,
+    prefix: &[u8],
+    out: &mut PathList,
+) -> Result<()> {
+    for entry in tree_entries(repo, Some(hash))? {
+        let path = [prefix, &entry.name].concat();
+        match split_entry(Some(entry)) {
+            (Some(subtree), _) => flatten_tree(repo, &subtree, &[&path[..], b"/"].concat(), out)?,
+            (_, Some(side)) => out.push((path, side)),
+            (None, None) => unreachable!("entry is either a tree or not"),
+        }
+    }
+    Ok(())
+}
+
+/// Pair two lists of paths sorted in index order, keeping the ones that differ.
+pub fn diff_lists(old: PathList, new: PathList) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut old = old.into_iter().peekable();
+    let mut new = new.into_iter().peekable();
+    loop {
+        let order = match (old.peek(), new.peek()) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(o), Some(n)) => o.0.cmp(&n.0),
+        };
+        let (path, o, n) = match order {
+            Ordering::Less => {
+                let (path, o) = old.next().expect("peeked");
+                (path, Some(o), None)
+            }
+            Ordering::Greater => {
+                let (path, n) = new.next().expect("peeked");
+                (path, None, Some(n))
+            }
+            Ordering::Equal => {
+                let (path, o) = old.next().expect("peeked");
+                let (_, n) = new.next().expect("peeked");
+                if o.hash == n.hash && o.mode == n.mode {
+                    continue;
+                }
+                (path, Some(o), Some(n))
+            }
+        };
+        changes.push(Change {
+            path,
+            old: o,
+            new: n,
+            unmerged: false,
+            renamed: None,
+        });
+    }
+    changes
+}
+
+/// The content of the index, as compared to trees and the worktree.
+struct IndexSides {
+    /// Stage 0 entries
+    sides: PathList,
+    /// Paths with conflicts
+    unmerged: Vec<Vec<u8>>,
+    /// Paths not checked out (skip-worktree)
+    skipped: HashSet<Vec<u8>>,
+}
+
+/// Read the index, see IndexSides.
+fn index_sides(repo: &Repository) -> Result<IndexSides> {
+    let mut sides = Vec::new();
+    let mut unmerged: Vec<Vec<u8>> = Vec::new();
+    let mut skipped = HashSet::new();
+    for entry in index::read(repo)? {
+        if entry.stage != 0 {
+            if unmerged.last() != Some(&entry.path) {
+                unmerged.push(entry.path);
+            }
+            continue;
+        }
+        if entry.skip_worktree {
+            skipped.insert(entry.path.clone());
+        }
+        let side = Side {
+            mode: entry.mode,
+            hash: hex::encode(entry.hash),
+            file: None,
+        };
+        sides.push((entry.path, side));
+    }
+    Ok(IndexSides {
+        sides,
+        unmerged,
+        skipped,
+    })
+}
+
+/// Look at what the worktree contains at the path of an index entry.
+/// Return None if there is nothing there, or a directory.
+pub fn worktree_side(
+    repo: &Repository,
+    root: &Path,
+    path: &[u8],
+    indexed: &Side,
+) -> Result<Option<Side>> {
+    let file = platform::join(root, path);
+    let meta = match fs::symlink_metadata(&file) {
+        Ok(meta) => meta,
+        Err(e) if platform::is_not_found(&e) => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("stat {}", file.display())),
+    };
+    if indexed.mode == Mode::SubMod {
+        // We can't look inside submodules, assume they're unchanged.
+        return Ok(Some(Side {
+            mode: Mode::SubMod,
+            hash: indexed.hash.clone(),
+            file: None,
+        }));
+    }
+    let mode = Mode::from_worktree(repo, &meta, Some(&indexed.mode))
+        .with_context(|| format!("{}", file.display()))?;
+    let hash = match mode {
+        Mode::Dir => return Ok(None),
+        Mode::SymLink => {
+            let mut target = io::Cursor::new(platform::read_symlink(&file)?);
+            write_object(repo, ObjType::Blob, &mut target, false)?
+        }
+        _ => {
+            hash_file(repo, &file, false).with_context(|| format!("hashing {}", file.display()))?
+        }
+    };
+    Ok(Some(Side {
+        mode,
+        hash,
+        file: Some(file),
+    }))
+}
+
+/// Add unmerged paths to the list of changes, keeping it sorted.
+fn add_unmerged(mut changes: Vec<Change>, unmerged: Vec<Vec<u8>>) -> Vec<Change> {
+    changes.extend(unmerged.into_iter().map(|path| Change {
+        path,
+        old: None,
+        new: None,
+        unmerged: true,
+        renamed: None,
+    }));
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+/// Compare a tree (None for an empty tree) with the index.
+pub fn diff_tree_to_index(repo: &Repository, tree: Option<&str>) -> Result<Vec<Change>> {
+    let IndexSides {
+        sides: index,
+        unmerged,
+        ..
+    } = index_sides(repo)?;
+    let mut old = Vec::new();
+    if let Some(tree) = tree {
+        flatten_tree(repo, tree, b"", &mut old)?;
+    }
+    old.retain(|(path, _)| !unmerged.contains(path));
+    Ok(add_unmerged(diff_lists(old, index), unmerged))
+}
+
+/// Compare a tree, or the index if `tree` is None, with the worktree.
+/// Only paths that are in the index are looked at in the worktree.
+pub fn diff_to_worktree(repo: &Repository, tree: Option<&str>) -> Result<Vec<Change>> {
+    let root = repo.work_tree()?;
+    let IndexSides {
+        sides: index,
+        unmerged,
+        skipped,
+    } = index_sides(repo)?;
+    let mut worktree = Vec::new();
+    for (path, side) in &index {
+        // Paths not checked out are taken as unchanged, like git.
+        if skipped.contains(path) {
+            worktree.push((path.clone(), side.clone()));
+        } else if let Some(side) = worktree_side(repo, root, path, side)? {
+            worktree.push((path.clone(), side));
+        }
+    }
+    let old = match tree {
+        Some(tree) => {
+            let mut old = Vec::new();
+            flatten_tree(repo, tree, b"", &mut old)?;
+            old.retain(|(path, _)| !unmerged.contains(path));
+            old
+        }
+        None => index,
+    };
+    Ok(add_unmerged(diff_lists(old, worktree), unmerged))
+}
+
+/// Parse a minimum similarity like git's -M option: digits are read as a
+/// fraction ("5" or "50" is 50%), unless followed by '%'.
+pub fn parse_rename_score(value: &str) -> Result<u32> {
+    let (mut number, mut scale, mut dot) = (0u64, 1u64, false);
+    let mut chars = value.chars();
+    for c in chars.by_ref() {
+        match c {
+            '.' if !dot => (scale, dot) = (1, true),
+            '%' => {
+                scale = if dot { scale * 100 } else { 100 };
+                break;
+            }
+            '0'..='9' if scale < 100000 => {
+                scale *= 10;
+                number = number * 10 + u64::from(c as u8 - b'0');
+            }
+            '0'..='9' => (),
+            _ => bail!("invalid similarity: {value}"),
+        }
+    }
+    if chars.next().is_some() {
+        bail!("invalid similarity: {value}");
+    }
+    Ok(match number >= scale {
+        true => MAX_SCORE,
+        false => (u64::from(MAX_SCORE) * number / scale) as u32,
+    })
+}
+
+/// The minimum similarity of renames to detect by default: renames are
+/// detected unless diff.renames is false ("copies" counts as true).
+pub fn rename_score(repo: &Repository) -> Result<Option<u32>> {
+    rename_setting(repo, "diff.renames")
+}
+
+/// Same as rename_score(), for git status: from status.renames if set, else
+/// from diff.renames.
+pub fn status_rename_score(repo: &Repository) -> Result<Option<u32>> {
+    match config::get(repo, "status.renames")? {
+        Some(_) => rename_setting(repo, "status.renames"),
+        None => rename_score(repo),
+    }
+}
+
+/// The minimum similarity of renames according to a setting like
+/// diff.renames, true if not set.
+fn rename_setting(repo: &Repository, key: &str) -> Result<Option<u32>> {
+    let enabled = match config::get(repo, key)?.as_deref() {
+        None | Some("copies" | "copy") => true,
+        Some(value) => config::parse_bool(key, value)?,
+    };
+    Ok(enabled.then_some(DEFAULT_RENAME_SCORE))
+}
+
+/// The content of a path split into chunks like git does to compare files:
+/// chunks end at newlines or after 64 bytes, and their sizes are summed
+/// by hash. In text files, CRs before LFs are left out, and like git, an
+/// unfinished chunk at the end is too.
+struct Chunks {
+    size: usize,
+    sizes: HashMap<u32, usize>,
+}
+
+impl Chunks {
+    fn new(repo: &Repository, path: &[u8], side: &Side) -> Result<Chunks> {
+        let data = content(repo, side)?;
+        let text = !diffs_as_binary(repo, path, &data, b"")?;
+        let mut sizes = HashMap::new();
+        let (mut accum1, mut accum2, mut n) = (0u32, 0u32, 0);
+        for (i, &c) in data.iter().enumerate() {
+            if text && c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+                continue;
+            }
+            let old = accum1;
+            accum1 = ((accum1 << 7) ^ (accum2 >> 25)).wrapping_add(u32::from(c));
+            accum2 = (accum2 << 7) ^ (old >> 25);
+            n += 1;
+            if n < 64 && c != b'\n' {
+                continue;
+            }
+            let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE;
+            *sizes.entry(hash).or_default() += n;
+            (accum1, accum2, n) = (0, 0, 0);
+        }
+        Ok(Chunks {
+            size: data.len(),
+            sizes,
+        })
+    }
+
+    /// Estimate how much of the new content comes from the old one, out of
+    /// MAX_SCORE, like git. Return 0 if their sizes differ too much for the
+    /// similarity to reach `min_score`.
+    fn similarity(old: &Chunks, new: &Chunks, min_score: u32) -> u32 {
+        let max_size = old.size.max(new.size) as u64;
+        let delta = max_size - old.size.min(new.size) as u64;
+        if max_size * u64::from(MAX_SCORE - min_score) < delta * u64::from(MAX_SCORE)
+            || new.size == 0
+        {
+            return 0;
+        }
+        let copied: usize = new
+            .sizes
+            .iter()
+            .map(|(hash, &size)| size.min(old.sizes.get(hash).copied().unwrap_or(0)))
+            .sum();
+        (copied as u64 * u64::from(MAX_SCORE) / max_size) as u32
+    }
+}
+
+/// The file name of a path, without its directory.
+fn basename(path: &[u8]) -> &[u8] {
+    path.rsplit(|&c| c == b'/').next().unwrap_or(path)
+}
+
+/// A possible rename of a deleted path to an added one.
+#[derive(Clone, Copy)]
+struct Candidate {
+    score: u32,
+    same_name: bool,
+    src: usize,
+    dst: usize,
+}
+
+impl Candidate {
+    /// Tell if it is a better match than another one.
+    fn better(&self, other: &Candidate) -> bool {
+        (self.score, self.same_name) > (other.score, other.same_name)
+    }
+}
+
+/// Detect renames among changes, like git's -M: deleted paths are paired
+/// with added ones, first those with the same content (preferably with the
+/// same file name), then among files with a unique name on both sides those
+/// quite similar, then the most similar ones, if at least `min_score` similar
+/// (out of MAX_SCORE). Only regular files are renamed with changes.
+pub fn detect_renames(
+    repo: &Repository,
+    changes: Vec<Change>,
+    min_score: u32,
+) -> Result<Vec<Change>> {
+    let is_src = |c: &Change| !c.unmerged && c.old.is_some() && c.new.is_none();
+    let is_dst = |c: &Change| !c.unmerged && c.old.is_none() && c.new.is_some();
+    let srcs: Vec<usize> = (0..changes.len())
+        .filter(|&i| is_src(&changes[i]))
+        .collect();
+    let dsts: Vec<usize> = (0..changes.len())
+        .filter(|&i| is_dst(&changes[i]))
+        .collect();
+    let old = |src: usize| changes[srcs[src]].old.as_ref().expect("deleted");
+    let new = |dst: usize| changes[dsts[dst]].new.as_ref().expect("added");
+    let regular = |side: &Side| matches!(side.mode, Mode::File | Mode::Exe);
+    let mut used = vec![false; srcs.len()];
+    let mut paired: Vec<Option<(usize, u32)>> = vec![None; dsts.len()];
+
+    for dst in 0..dsts.len() {
+        let target = new(dst);
+        let same = |src: usize| {
+            let source = old(src);
+            !used[src]
+                && source.hash == target.hash
+                && (source.mode == target.mode || regular(source) && regular(target))
+        };
+        let same_name =
+            |src: usize| basename(&changes[srcs[src]].path) == basename(&changes[dsts[dst]].path);
+        let found = (0..srcs.len())
+            .filter(|&src| same(src))
+            .find(|&src| same_name(src))
+            .or_else(|| (0..srcs.len()).find(|&src| same(src)));
+        if let Some(src) = found {
+            used[src] = true;
+            paired[dst] = Some((src, MAX_SCORE));
+        }
+    }
+
+    let src_left: Vec<usize> = (0..srcs.len()).filter(|&src| !used[src]).collect();
+    let dst_left: Vec<usize> = (0..dsts.len())
+        .filter(|&dst| paired[dst].is_none())
+        .collect();
+    let mut chunks = HashMap::new();
+    for &src in src_left.iter().filter(|&&src| regular(old(src))) {
+        chunks.insert(
+            srcs[src],
+            Chunks::new(repo, &changes[srcs[src]].path, old(src))?,
+        );
+    }
+    for &dst in dst_left.iter().filter(|&&dst| regular(new(dst))) {
+        chunks.insert(
+            dsts[dst],
+            Chunks::new(repo, &changes[dsts[dst]].path, new(dst))?,
+        );
+    }
+    let score = |src: usize, dst: usize| match (chunks.get(&srcs[src]), chunks.get(&dsts[dst])) {
+        (Some(old), Some(new)) => Chunks::similarity(old, new, min_score),
+        _ => 0,
+    };
+
+    // Files whose name is found once on each side are paired if similar
+    // enough, halfway between the minimum score and a perfect match.
+    let mut src_names: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for &src in &src_left {
+        let name = basename(&changes[srcs[src]].path);
+        src_names.entry(name).or_default().push(src);
+    }
+    let mut dst_names: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for &dst in &dst_left {
+        let name = basename(&changes[dsts[dst]].path);
+        dst_names.entry(name).or_default().push(dst);
+    }
+    let name_score = min_score + (MAX_SCORE - min_score) / 2;
+    for (name, named_srcs) in &src_names {
+        let (&[src], Some(&[dst])) = (&named_srcs[..], dst_names.get(name).map(Vec::as_slice))
+        else {
+            continue;
+        };
+        let score = score(src, dst);
+        if score >= name_score {
+            used[src] = true;
+            paired[dst] = Some((src, score));
+        }
+    }
+
+    // Then the best matches, keeping a few for each added file.
+    let mut candidates = Vec::new();
+    for &dst in dst_left
+        .iter()
+        .filter(|&&dst| paired[dst].is_none() && regular(new(dst)))
+    {
+        let mut best: Vec<Candidate> = Vec::new();
+        for &src in src_left
+            .iter()
+            .filter(|&&src| !used[src] && regular(old(src)))
+        {
+            let candidate = Candidate {
+                score: score(src, dst),
+                same_name: basename(&changes[srcs[src]].path) == basename(&changes[dsts[dst]].path),
+                src,
+                dst,
+            };
+            if best.len() < CANDIDATES_PER_PATH {
+                best.push(candidate);
+                continue;
+            }
+            // Like git, replace the first of the worst matches.
+            let mut worst = 0;
+            for (i, other) in best.iter().enumerate() {
+                if best[worst].better(other) {
+                    worst = i;
+                }
+            }
+            if candidate.better(&best[worst]) {
+                best[worst] = candidate;
+            }
+        }
+        candidates.extend(best);
+    }
+    candidates.sort_by_key(|c| Reverse((c.score, c.same_name)));
+    for candidate in candidates {
+        if candidate.score < min_score {
+            break;
+        }
+        if paired[candidate.dst].is_some() || used[candidate.src] {
+            continue;
+        }
+        used[candidate.src] = true;
+        paired[candidate.dst] = Some((candidate.src, candidate.score));
+    }
+
+    // Replace the paired additions with renames, dropping their deletions.
+    let mut changes: Vec<Option<Change>> = changes.into_iter().map(Some).collect();
+    for (dst, pair) in paired.into_iter().enumerate() {
+        if let Some((src, score)) = pair {
+            let old = changes[srcs[src]].take().expect("paired once");
+            let change = changes[dsts[dst]].as_mut().expect("added");
+            change.old = old.old;
+            change.renamed = Some(Rename {
+                from: old.path,
+                score,
+            });
+        }
+    }
+    Ok(changes.into_iter().flatten().collect())
+}
+
+/// Get the content of one side of a change.
+pub fn content(repo: &Repository, side: &Side) -> Result<Vec<u8>> {
+    if side.mode == Mode::SubMod {
+        return Ok(format!("Subproject commit {}\n", side.hash).into_bytes());
+    }
+    if let Some(file) = &side.file {
+        if side.mode == Mode::SymLink {
+            return platform::read_symlink(file);
+        }
+        let data = fs::read(file).with_context(|| format!("reading {}", file.display()))?;
+        return filter::clean(repo, file, data);
+    }
+    read_blob(repo, &side.hash)
+}
+
+/// Broad type of a path: changing it is shown as a deletion and an addition.
+pub fn kind(mode: &Mode) -> u8 {
+    match mode {
+        Mode::File | Mode::Exe => 0,
+        Mode::SymLink => 1,
+        Mode::SubMod => 2,
+        Mode::Dir => 3,
+    }
+}
+
+/// The colors of the parts of a diff, as escape sequences: all empty for
+/// plain output.
+#[derive(Default)]
+pub struct DiffColors {
+    /// Headers, up to the lines with the paths
+    pub meta: String,
+    /// Hunk headers
+    pub frag: String,
+    /// Function lines after hunk headers
+    pub func: String,
+    pub context: String,
+    pub old: String,
+    pub new: String,
+    /// Commit lines in git show
+    pub commit: String,
+    /// Whitespace errors
+    pub whitespace: String,
+    pub reset: String,
+}
+
+impl DiffColors {
+    /// Get the colors from the `color.diff.<slot>` settings, with git's
+    /// defaults.
+    pub fn from_config(repo: &Repository) -> Result<DiffColors> {
+        let get =
+            |slot: &str, default: &str| color::get(repo, &format!("color.diff.{slot}"), default);
+        let context = match config::get(repo, "color.diff.context")? {
+            Some(value) => color::parse(&value)?,
+            None => get("plain", "")?,
+        };
+        Ok(DiffColors {
+            meta: get("meta", "\x1b[1m")?,
+            frag: get("frag", "\x1b[36m")?,
+            func: get("func", "")?,
+            context,
+            old: get("old", "\x1b[31m")?,
+            new: get("new", "\x1b[32m")?,
+            commit: get("commit", "\x1b[33m")?,
+            whitespace: get("whitespace", "\x1b[41m")?,
+            reset: RESET.to_owned(),
+        })
+    }
+}
+
+/// How to show changes in the lines of files, like git diff --word-diff.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum WordDiff {
+    /// Show changed lines
+    #[default]
+    None,
+    /// Show words removed as [-word-] and added as {+word+}
+    Plain,
+    /// Show words removed and added only by their colors
+    Color,
+    /// Show each part on its own line, starting with "-", "+" or " " for
+    /// removed, added or unchanged text, and a "~" line for each end of line
+    Porcelain,
+}
+
+/// Parse the mode of --word-diff: none, plain, color or porcelain.
+pub fn parse_word_diff(value: &str) -> Result<WordDiff> {
+    match value {
+        "none" => Ok(WordDiff::None),
+        "plain" => Ok(WordDiff::Plain),
+        "color" => Ok(WordDiff::Color),
+        "porcelain" => Ok(WordDiff::Porcelain),
+        _ => bail!("bad --word-diff argument: {value}"),
+    }
+}
+
+/// How to print a diff.
+#[derive(Default)]
+pub struct DiffFormat {
+    /// Show changes to binary files as binary patches, see print_patch()
+    pub binary: bool,
+    pub colors: DiffColors,
+    pub word_diff: WordDiff,
+}
+
+impl DiffFormat {
+    /// Get the format for the options of git diff or show: colored like
+    /// color.diff says if `color` is not given, and always for word diffs
+    /// in color.
+    pub fn new(
+        repo: &Repository,
+        color: Option<color::When>,
+        word_diff: WordDiff,
+    ) -> Result<DiffFormat> {
+        let color = match (color, word_diff) {
+            (None, WordDiff::Color) => Some(color::When::Always),
+            (color, _) => color,
+        };
+        let colors = match color::use_color(repo, color, "color.diff")? {
+            true => DiffColors::from_config(repo)?,
+            false => DiffColors::default(),
+        };
+        Ok(DiffFormat {
+            binary: false,
+            colors,
+            word_diff,
+        })
+    }
+}
+
+/// Print changes as a unified diff, like git diff.
+pub fn print_diff(
+    repo: &Repository,
+    changes: &[Change],
+    format: &DiffFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    for change in changes {
+        if change.unmerged {
+            out.write_all(b"* Unmerged path ")?;
+            out.write_all(&change.path)?;
+            out.write_all(b"\n")?;
+            continue;
+        }
+        match (&change.old, &change.new) {
+            (Some(old), Some(new)) if kind(&old.mode) != kind(&new.mode) => {
+                print_patch(repo, &change.path, None, Some(old), None, format, out)?;
+                print_patch(repo, &change.path, None, None, Some(new), format, out)?;
+            }
+            (old, new) => print_patch(
+                repo,
+                &change.path,
+                change.renamed.as_ref(),
+                old.as_ref(),
+                new.as_ref(),
+                format,
+                out,
+            )?,
+        }
+    }
+    Ok(())
+}
+
+/// Lines added and deleted in one file, for a diffstat.
+struct FileStat<'a> {
+    path: &'a [u8],
+    added: usize,
+    deleted: usize,
+    /// Sizes before and after, for binary files
+    binary: Option<(usize, usize)>,
+}
+
+/// Scale a number of changed lines to fit in the graph, like git.
+fn scale(n: usize, graph_width: usize, max_change: usize) -> usize {
+    match n {
+        0 => 0,
+        n => 1 + n * (graph_width - 1) / max_change,
+    }
+}
+
+/// Print a summary of changes like git diff --stat --summary: a line with
+/// a graph of lines added and deleted for each file, the totals, then the
+/// files created or deleted and mode changes. Lines fit in `width` columns
+/// if possible.
+pub fn print_stat(
+    repo: &Repository,
+    changes: &[Change],
+    width: usize,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut stats = Vec::new();
+    for change in changes.iter().filter(|change| !change.unmerged) {
+        let old_data = change
+            .old
+            .as_ref()
+            .map(|side| content(repo, side))
+            .transpose()?
+            .unwrap_or_default();
+        let new_data = change
+            .new
+            .as_ref()
+            .map(|side| content(repo, side))
+            .transpose()?
+            .unwrap_or_default();
+        let mut stat = FileStat {
+            path: &change.path,
+            added: 0,
+            deleted: 0,
+            binary: None,
+        };
+        if diffs_as_binary(repo, &change.path, &old_data, &new_data)? {
+            stat.binary = Some((old_data.len(), new_data.len()));
+        } else {
+            let old_lines: Vec<&[u8]> = old_data.split_inclusive(|&c| c == b'\n').collect();
+            let new_lines: Vec<&[u8]> = new_data.split_inclusive(|&c| c == b'\n').collect();
+            for op in diff_lines(&old_lines, &new_lines) {
+                match op {
+                    Op::Insert(_) => stat.added += 1,
+                    Op::Delete(_) => stat.deleted += 1,
+                    Op::Equal(..) => {}
+                }
+            }
+        }
+        stats.push(stat);
+    }
+    if stats.is_empty() {
+        return Ok(());
+    }
+
+    // Share the width between names, numbers and the graph, like git.
+    let changed = |s: &FileStat| {
+        if s.binary.is_some() {
+            0
+        } else {
+            s.added + s.deleted
+        }
+    };
+    let max_change = stats.iter().map(changed).max().unwrap_or(0);
+    let mut number_width = max_change.to_string().len();
+    if stats.iter().any(|s| s.binary.is_some()) {
+        number_width = number_width.max(3);
+    }
+    let mut name_width = stats.iter().map(|s| s.path.len()).max().unwrap_or(0);
+    let mut graph_width = max_change;
+    if name_width + number_width + 6 + graph_width > width {
+        let limit = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+        graph_width = graph_width.min(limit);
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+
+    for stat in &stats {
+        // Long names are shortened from the start, preferably at a '/'.
+        let mut name = stat.path.to_vec();
+        if name.len() > name_width {
+            let mut tail = &stat.path[stat.path.len() - (name_width - 3)..];
+            if let Some(slash) = tail.iter().position(|&c| c == b'/') {
+                tail = &tail[slash..];
+            }
+            name = [b"...", tail].concat();
+        }
+        let padding = " ".repeat(name_width.saturating_sub(name.len()));
+        out.write_all(b" ")?;
+        out.write_all(&name)?;
+        write!(out, "{padding} |")?;
+        if let Some((before, after)) = stat.binary {
+            writeln!(out, " {:>number_width$} {before} -> {after} bytes", "Bin")?;
+            continue;
+        }
+        let total = stat.added + stat.deleted;
+        write!(out, " {total:>number_width$}")?;
+        let (mut added, mut deleted) = (stat.added, stat.deleted);
+        if max_change > graph_width {
+            let total = scale(total, graph_width, max_change).max(match added > 0 && deleted > 0 {
+                true => 2,
+                false => 0,
+            });
+            if added < deleted {
+                added = scale(added, graph_width, max_change);
+                deleted = total - added;
+            } else {
+                deleted = scale(deleted, graph_width, max_change);
+                added = total - deleted;
+            }
+        }
+        if total > 0 {
+            write!(out, " {}{}", "+".repeat(added), "-".repeat(deleted))?;
+        }
+        writeln!(out)?;
+    }
+
+    let plural = |n: usize, word: &str| format!("{n} {word}{}", if n == 1 { "" } else { "s" });
+    let insertions: usize = stats.iter().map(|s| s.added).sum();
+    let deletions: usize = stats.iter().map(|s| s.deleted).sum();
+    write!(out, " {} changed", plural(stats.len(), "file"))?;
+    if insertions > 0 || deletions == 0 {
+        write!(out, ", {}(+)", plural(insertions, "insertion"))?;
+    }
+    if deletions > 0 || insertions == 0 {
+        write!(out, ", {}(-)", plural(deletions, "deletion"))?;
+    }
+    writeln!(out)?;
+
+    for change in changes.iter().filter(|change| !change.unmerged) {
+        let what = match (&change.old, &change.new) {
+            (None, Some(new)) => format!("create mode {}", new.mode.to_str()),
+            (Some(old), None) => format!("delete mode {}", old.mode.to_str()),
+            (Some(old), Some(new)) if old.mode != new.mode => {
+                format!("mode change {} => {}", old.mode.to_str(), new.mode.to_str())
+            }
+            _ => continue,
+        };
+        write!(out, " {what} ")?;
+        out.write_all(&change.path)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Encode the change from one content to another like in git binary
+/// patches: "literal <size>" with the new content, or "delta <size>" with
+/// delta instructions if smaller, then the data deflated and in base85,
+/// up to 52 bytes per line, each starting with its length ('A' for 1 to
+/// 'z' for 52), and an empty line.
+fn binary_hunk(old: &[u8], new: &[u8]) -> Result<Vec<u8>> {
+    let deflate = |data: &[u8]| -> io::Result<Vec<u8>> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(data)?;
+        encoder.finish()
+    };
+    let mut kind = "literal";
+    let mut size = new.len();
+    let mut data = deflate(new)?;
+    if !old.is_empty() && !new.is_empty() {
+        let delta = create_delta(old, new);
+        let deflated = deflate(&delta)?;
+        if deflated.len() < data.len() {
+            (kind, size, data) = ("delta", delta.len(), deflated);
+        }
+    }
+    let mut text = format!("{kind} {size}\n").into_bytes();
+    for chunk in data.chunks(52) {
+        text.push(match chunk.len() {
+            len @ 1..=26 => b'A' + len as u8 - 1,
+            len => b'a' + len as u8 - 27,
+        });
+        text.extend_from_slice(&base85::encode(chunk));
+        text.push(b'\n');
+    }
+    text.push(b'\n');
+    Ok(text)
+}
+
+/// Print a "diff --git" header, followed by the differences in content.
+/// With a rename, the old side is at the path it was renamed from. With
+/// `format.binary`, changes to binary files are shown as git binary patches
+/// rather than just said to differ.
+fn print_patch(
+    repo: &Repository,
+    path: &[u8],
+    rename: Option<&Rename>,
+    old: Option<&Side>,
+    new: Option<&Side>,
+    format: &DiffFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    let colors = &format.colors;
+    let mut line = |parts: &[&[u8]]| -> io::Result<()> {
+        out.write_all(colors.meta.as_bytes())?;
+        for part in parts {
+            out.write_all(part)?;
+        }
+        out.write_all(colors.reset.as_bytes())?;
+        out.write_all(b"\n")
+    };
+    let old_path = rename.map_or(path, |rename| &rename.from);
+    let a = [b"a/", old_path].concat();
+    let b = [b"b/", path].concat();
+    line(&[b"diff --git ", &a, b" ", &b])?;
+    match (old, new) {
+        (None, Some(new)) => line(&[b"new file mode ", new.mode.to_str().as_bytes()])?,
+        (Some(old), None) => line(&[b"deleted file mode ", old.mode.to_str().as_bytes()])?,
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            line(&[b"old mode ", old.mode.to_str().as_bytes()])?;
+            line(&[b"new mode ", new.mode.to_str().as_bytes()])?;
+        }
+        _ => {}
+    }
+    if let Some(rename) = rename {
+        let similarity = rename.score * 100 / MAX_SCORE;
+        line(&[format!("similarity index {similarity}%").as_bytes()])?;
+        line(&[b"rename from ", old_path])?;
+        line(&[b"rename to ", path])?;
+    }
+    if old.map(|side| &side.hash) == new.map(|side| &side.hash) {
+        // mode change or rename only
+        return Ok(());
+    }
+    let old_data = old
+        .map(|side| content(repo, side))
+        .transpose()?
+        .unwrap_or_default();
+    let new_data = new
+        .map(|side| content(repo, side))
+        .transpose()?
+        .unwrap_or_default();
+    let is_binary = diffs_as_binary(repo, path, &old_data, &new_data)?;
+    // Binary patches give full object names, to check what they apply to.
+    let full = format.binary && is_binary;
+    let abbrev = |side: Option<&Side>| match side {
+        Some(side) if full => Ok(side.hash.clone()),
+        Some(side) => shorten(repo, &side.hash),
+        None if full => Ok(repo.hash_algo()?.zero_hash().to_owned()),
+        None => Ok("0".repeat(min_len(repo)?)),
+    };
+    let (old_hash, new_hash) = (abbrev(old)?, abbrev(new)?);
+    let index = format!("index {old_hash}..{new_hash}");
+    match (old, new) {
+        (Some(old), Some(new)) if old.mode == new.mode => {
+            line(&[index.as_bytes(), b" ", old.mode.to_str().as_bytes()])?
+        }
+        _ => line(&[index.as_bytes()])?,
+    }
+
+    let a = if old.is_some() { &a[..] } else { b"/dev/null" };
+    let b = if new.is_some() { &b[..] } else { b"/dev/null" };
+    if full {
+        out.write_all(b"GIT binary patch\n")?;
+        out.write_all(&binary_hunk(&old_data, &new_data)?)?;
+        out.write_all(&binary_hunk(&new_data, &old_data)?)?;
+        return Ok(());
+    }
+    if is_binary {
+        out.write_all(&[b"Binary files ", a, b" and ", b, b" differ\n"].concat())?;
+        return Ok(());
+    }
+    let old_lines: Vec<&[u8]> = old_data.split_inclusive(|&c| c == b'\n').collect();
+    let new_lines: Vec<&[u8]> = new_data.split_inclusive(|&c| c == b'\n').collect();
+    let ops = diff_lines(&old_lines, &new_lines);
+    let hunks = hunks(&ops);
+    if hunks.is_empty() {
+        return Ok(());
+    }
+    // Like git, end names containing spaces with a tab (after the color),
+    // for patch(1).
+    for (sign, name) in [(b"---", a), (b"+++", b)] {
+        out.write_all(colors.meta.as_bytes())?;
+        out.write_all(&[sign, &b" "[..], name].concat())?;
+        out.write_all(colors.reset.as_bytes())?;
+        if name.contains(&b' ') {
+            out.write_all(b"\t")?;
+        }
+        out.write_all(b"\n")?;
+    }
+    for hunk in hunks {
+        let ops = &ops[hunk];
+        match format.word_diff {
+            WordDiff::None => print_hunk(ops, &old_lines, &new_lines, true, colors, out)?,
+            mode => print_word_hunk(ops, &old_lines, &new_lines, mode, colors, out)?,
+        }
+    }
+    Ok(())
+}
+
+/// Tell if some content should be treated as binary.
+fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+/// Tell if a file should be diffed as binary: like git, this is decided by
+/// its diff attribute (unset, or a driver with `diff.<driver>.binary`) if
+/// any, and otherwise by its content before or after the change.
+pub fn diffs_as_binary(repo: &Repository, path: &[u8], old: &[u8], new: &[u8]) -> Result<bool> {
+    match attributes::get(repo, path, "diff")? {
+        Some(State::Unset) => return Ok(true),
+        Some(State::Set) => return Ok(false),
+        Some(State::Value(driver)) => {
+            if let Some(binary) = config::get_bool(repo, &format!("diff.{driver}.binary"))? {
+                return Ok(binary);
+            }
+        }
+        None => (),
+    }
+    Ok(is_binary(old) || is_binary(new))
+}
+
+/// One step of an edit script: each consumes one line of the old content,
+/// the new content, or both.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Op {
+    /// Line old\[.0\] is the same as line new\[.1\]
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Compute an edit script turning the old lines into the new lines.
+pub fn diff_lines(old: &[&[u8]], new: &[&[u8]]) -> Vec<Op> {
+    // Compare numbers rather than lines.
+    let mut ids = HashMap::new();
+    let mut intern = |line: &[u8]| -> usize {
+        let next = ids.len();
+        *ids.entry(line.to_vec()).or_insert(next)
+    };
+    let a: Vec<usize> = old.iter().map(|line| intern(line)).collect();
+    let b: Vec<usize> = new.iter().map(|line| intern(line)).collect();
+
+    let mut deleted = vec![false; a.len()];
+    let mut inserted = vec![false; b.len()];
+    // The common prefix and suffix are easy, and cheap to skip.
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    myers(
+        &a[prefix..a.len() - suffix],
+        &b[prefix..b.len() - suffix],
+        &mut deleted[prefix..a.len() - suffix],
+        &mut inserted[prefix..b.len() - suffix],
+    );
+    slide_down(&a, &mut deleted);
+    slide_down(&b, &mut inserted);
+
+    // Rebuild the script, with deletions before insertions in each group.
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && deleted[i] {
+            ops.push(Op::Delete(i));
+            i += 1;
+        } else if j < b.len() && inserted[j] {
+            ops.push(Op::Insert(j));
+            j += 1;
+        } else {
+            ops.push(Op::Equal(i, j));
+            i += 1;
+            j += 1;
+        }
+    }
+    ops
+}
+
+/// Myers' O(ND) algorithm: mark lines of `a` that are deleted
+/// and lines of `b` that are inserted in a shortest edit script.
+fn myers(a: &[usize], b: &[usize], deleted: &mut [bool], inserted: &mut [bool]) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    // For each step d, the furthest x reached on each diagonal k = x - y,
+    // for k in -d..=d, stored at index k + d.
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut v = vec![0isize; 1];
+    'search: for d in 0..=(n + m) {
+        let prev = v;
+        v = vec![0; 2 * d as usize + 1];
+        let get = |k: isize| prev[(k + d - 1) as usize];
+        for k in (-d..=d).step_by(2) {
+            let mut x = if d == 0 {
+                0
+            } else if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+                get(k + 1)
+            } else {
+                get(k - 1) + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(k + d) as usize] = x;
+            if x >= n && y >= m {
+                trace.push(v);
+                break 'search;
+            }
+        }
+        trace.push(v.clone());
+    }
+
+    // Walk back from the end to find which step was taken at each d.
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len() as isize).rev() {
+        let prev = &trace[d as usize - 1];
+        let get = |k: isize| prev[(k + d - 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+        // The step is a single edit followed by equal lines (the diagonal).
+        if prev_k == k + 1 {
+            inserted[prev_y as usize] = true;
+        } else {
+            deleted[prev_x as usize] = true;
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+}
+
+/// Move each group of changed lines as far down as possible, when the line
+/// after the group is the same as its first line.
+fn slide_down(lines: &[usize], changed: &mut [bool]) {
+    let mut start = 0;
+    while start < lines.len() {
+        if !changed[start] {
+            start += 1;
+            continue;
+        }
+        let mut end = start;
+        while end < lines.len() && changed[end] {
+            end += 1;
+        }
+        while end < lines.len() && lines[start] == lines[end] {
+            changed[start] = false;
+            changed[end] = true;
+            start += 1;
+            end += 1;
+            // merge with the next group if we reached it
+            while end < lines.len() && changed[end] {
+                end += 1;
+            }
+        }
+        start = end;
+    }
+}
+
+/// Group changes into hunks with context, returned as ranges in the edit script.
+pub fn hunks(ops: &[Op]) -> Vec<std::ops::Range<usize>> {
+    let mut hunks: Vec<std::ops::Range<usize>> = Vec::new();
+    let mut last_change_end = 0;
+    for (pos, op) in ops.iter().enumerate() {
+        if matches!(op, Op::Equal(..)) {
+            continue;
+        }
+        match hunks.last_mut() {
+            Some(hunk) if pos - last_change_end <= 2 * CONTEXT => {
+                hunk.end = (pos + 1 + CONTEXT).min(ops.len());
+            }
+            _ => hunks.push(pos.saturating_sub(CONTEXT)..(pos + 1 + CONTEXT).min(ops.len())),
+        }
+        last_change_end = pos + 1;
+    }
+    hunks
+}
+
+/// Format a line range for a hunk header: "start,count", or "start" if count is 1.
+fn range(first: Option<usize>, count: usize) -> String {
+    match (first, count) {
+        (Some(first), 1) => format!("{}", first + 1),
+        (Some(first), _) => format!("{},{count}", first + 1),
+        // only possible for an empty file
+        (None, _) => "0,0".to_string(),
+    }
+}
+
+/// Find the line to show after a hunk header, like git's default:
+/// the last line before the hunk starting with a letter, '_' or '$'.
+fn function_line<'a>(lines: &[&'a [u8]], before: usize) -> Option<&'a [u8]> {
+    let line = lines[..before]
+        .iter()
+        .rev()
+        .find(|line| matches!(line.first(), Some(c) if c.is_ascii_alphabetic() || *c == b'_' || *c == b'$'))?;
+    let line = &line[..line.len().min(80)];
+    Some(line.trim_ascii_end())
+}
+
+/// Where a hunk starts in the old and in the new content, if it has lines
+/// there.
+fn hunk_start(ops: &[Op]) -> (Option<usize>, Option<usize>) {
+    let old_first = ops.iter().find_map(|op| match op {
+        Op::Equal(i, _) | Op::Delete(i) => Some(*i),
+        Op::Insert(_) => None,
+    });
+    let new_first = ops.iter().find_map(|op| match op {
+        Op::Equal(_, j) | Op::Insert(j) => Some(*j),
+        Op::Delete(_) => None,
+    });
+    (old_first, new_first)
+}
+
+/// Print the header of a hunk, with the function line after it if
+/// `with_function` is set.
+fn print_hunk_header(
+    ops: &[Op],
+    old: &[&[u8]],
+    with_function: bool,
+    colors: &DiffColors,
+    out: &mut impl Write,
+) -> Result<()> {
+    let (old_first, new_first) = hunk_start(ops);
+    let old_count = ops.iter().filter(|op| !matches!(op, Op::Insert(_))).count();
+    let new_count = ops.iter().filter(|op| !matches!(op, Op::Delete(_))).count();
+    write!(
+        out,
+        "{}@@ -{} +{} @@{}",
+        colors.frag,
+        range(old_first, old_count),
+        range(new_first, new_count),
+        colors.reset
+    )?;
+    if let Some(function) = function_line(old, old_first.unwrap_or(0)).filter(|_| with_function) {
+        write!(out, "{} {}{}", colors.context, colors.reset, colors.func)?;
+        out.write_all(function)?;
+        out.write_all(colors.reset.as_bytes())?;
+    }
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Tell if a byte is whitespace for git: a space, tab, CR or LF.
+fn is_space(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\r' | b'\n')
+}
+
+/// Write a line like git: in `color`, the sign if any then the text
+/// without its end of line, with a reset after if anything was written,
+/// then the end of line (a CR being kept out of the color).
+fn write_line(
+    color: &str,
+    sign: Option<u8>,
+    text: &[u8],
+    reset: &str,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let text = text.strip_suffix(b"\n").unwrap_or(text);
+    let (text, cr) = match text.strip_suffix(b"\r") {
+        Some(text) => (text, true),
+        None => (text, false),
+    };
+    if sign.is_some() || !text.is_empty() {
+        out.write_all(color.as_bytes())?;
+        if let Some(sign) = sign {
+            out.write_all(&[sign])?;
+        }
+        out.write_all(text)?;
+        out.write_all(reset.as_bytes())?;
+    }
+    if cr {
+        out.write_all(b"\r")?;
+    }
+    out.write_all(b"\n")
+}
+
+/// Count the blank lines at the end of some content, like git (which does
+/// not count those at the very start).
+fn trailing_blank_lines(lines: &[&[u8]]) -> usize {
+    let mut start: usize = lines.iter().map(|line| line.len()).sum();
+    let mut count = 0;
+    for line in lines.iter().rev() {
+        start -= line.len();
+        let text = line.strip_suffix(b"\n").unwrap_or(line);
+        if start + text.len() <= 1 || !text.iter().all(|&c| is_space(c)) {
+            break;
+        }
+        count += 1;
+    }
+    count
+}
+
+/// Write an added line, with the whitespace errors of git's default rules
+/// highlighted: whitespace at the end of the line, spaces before a tab in
+/// the indent, and the whole line if it is one of the blank lines added at
+/// the end of the file (`at_eof`). Like git, the indent before the last tab
+/// is not colored.
+fn write_added(line: &[u8], at_eof: bool, colors: &DiffColors, out: &mut impl Write) -> Result<()> {
+    let (reset, ws) = (colors.reset.as_bytes(), colors.whitespace.as_bytes());
+    if ws.is_empty() || at_eof {
+        let color = if at_eof {
+            &colors.whitespace
+        } else {
+            &colors.new
+        };
+        return Ok(write_line(color, Some(b'+'), line, &colors.reset, out)?);
+    }
+    write!(out, "{}+{}", colors.new, colors.reset)?;
+    let text = line.strip_suffix(b"\n").unwrap_or(line);
+    let end = text.len() - text.iter().rev().take_while(|&&c| is_space(c)).count();
+    let mut written = 0;
+    for i in 0..end {
+        match text[i] {
+            b' ' => continue,
+            b'\t' => {}
+            _ => break,
+        }
+        if written < i {
+            out.write_all(&[ws, &text[written..i], reset].concat())?;
+            written = i;
+        }
+        out.write_all(&text[written..=i])?;
+        written = i + 1;
+    }
+    if written < end {
+        out.write_all(&[colors.new.as_bytes(), &text[written..end], reset].concat())?;
+    }
+    if end < text.len() {
+        out.write_all(&[ws, &text[end..], reset].concat())?;
+    }
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Print one hunk of a unified diff, with the function line after the
+/// header if `with_function` is set.
+pub fn print_hunk(
+    ops: &[Op],
+    old: &[&[u8]],
+    new: &[&[u8]],
+    with_function: bool,
+    colors: &DiffColors,
+    out: &mut impl Write,
+) -> Result<()> {
+    print_hunk_header(ops, old, with_function, colors, out)?;
+    // Where blank lines added at the end start in both contents, if any, as
+    // line numbers.
+    let (old_blank, new_blank) = (trailing_blank_lines(old), trailing_blank_lines(new));
+    let blank_at_eof =
+        (new_blank > old_blank).then(|| (old.len() - old_blank + 1, new.len() - new_blank + 1));
+    // The number of the next line of the old content, as in the header.
+    let mut old_number = hunk_start(ops).0.map_or(0, |i| i + 1);
+    for op in ops {
+        let line = match *op {
+            Op::Equal(i, _) => {
+                write_line(&colors.context, Some(b' '), old[i], &colors.reset, out)?;
+                old_number += 1;
+                old[i]
+            }
+            Op::Delete(i) => {
+                write_line(&colors.old, Some(b'-'), old[i], &colors.reset, out)?;
+                old_number += 1;
+                old[i]
+            }
+            Op::Insert(j) => {
+                let at_eof = blank_at_eof.is_some_and(|(old_start, new_start)| {
+                    old_start <= old_number && new_start <= j + 1
+                }) && new[j].iter().all(|&c| is_space(c));
+                write_added(new[j], at_eof, colors, out)?;
+                new[j]
+            }
+        };
+        if !line.ends_with(b"\n") {
+            let text = b"\\ No newline at end of file";
+            write_line(&colors.context, None, text, &colors.reset, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Split text into words, separated by whitespace, as ranges.
+fn words(text: &[u8]) -> Vec<std::ops::Range<usize>> {
+    let mut words = Vec::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        if is_space(text[pos]) {
+            pos += 1;
+            continue;
+        }
+        let start = pos;
+        while pos < text.len() && !is_space(text[pos]) {
+            pos += 1;
+        }
+        words.push(start..pos);
+    }
+    words
+}
+
+/// Write text of a word diff like git: in its color, each line between the
+/// given prefix and suffix, with `newline` in place of ends of lines.
+fn write_words(
+    (color, prefix, suffix): (&str, &str, &str),
+    newline: &str,
+    text: &[u8],
+    reset: &str,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let reset = if color.is_empty() { "" } else { reset };
+    for (i, part) in text.split(|&c| c == b'\n').enumerate() {
+        if i > 0 {
+            out.write_all(newline.as_bytes())?;
+        }
+        if !part.is_empty() {
+            out.write_all(&[color, prefix].concat().into_bytes())?;
+            out.write_all(part)?;
+            out.write_all(&[suffix, reset].concat().into_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Print the words changed from lines removed to lines added, like git: the
+/// unchanged text is the one of the added lines.
+fn print_words(
+    minus: &[u8],
+    plus: &[u8],
+    mode: WordDiff,
+    colors: &DiffColors,
+    out: &mut impl Write,
+) -> Result<()> {
+    let (old, new, context, newline) = match mode {
+        WordDiff::Porcelain => (("-", "\n"), ("+", "\n"), (" ", "\n"), "~\n"),
+        WordDiff::Plain => (("[-", "-]"), ("{+", "+}"), ("", ""), "\n"),
+        _ => (("", ""), ("", ""), ("", ""), "\n"),
+    };
+    let old = (&colors.old[..], old.0, old.1);
+    let new = (&colors.new[..], new.0, new.1);
+    let context = (&colors.context[..], context.0, context.1);
+    let reset = &colors.reset;
+    if plus.is_empty() {
+        return Ok(write_words(old, newline, minus, reset, out)?);
+    }
+    let (old_words, new_words) = (words(minus), words(plus));
+    let old_slices: Vec<&[u8]> = old_words.iter().map(|w| &minus[w.clone()]).collect();
+    let new_slices: Vec<&[u8]> = new_words.iter().map(|w| &plus[w.clone()]).collect();
+    let ops = diff_lines(&old_slices, &new_slices);
+    // Go through groups of changes, with the next words on both sides.
+    let (mut i, mut j, mut pos): (usize, usize, usize) = (0, 0, 0);
+    let mut rest = &ops[..];
+    while !rest.is_empty() {
+        if let Op::Equal(..) = rest[0] {
+            (i, j) = (i + 1, j + 1);
+            rest = &rest[1..];
+            continue;
+        }
+        let len = rest.iter().position(|op| matches!(op, Op::Equal(..)));
+        let (group, after) = rest.split_at(len.unwrap_or(rest.len()));
+        let removed = group
+            .iter()
+            .filter(|op| matches!(op, Op::Delete(_)))
+            .count();
+        let added = group.len() - removed;
+        // Like git, text unchanged before removed words goes before them.
+        let (start, end) = match added {
+            0 => {
+                let end = j.checked_sub(1).map_or(0, |j| new_words[j].end);
+                (end, end)
+            }
+            _ => (new_words[j].start, new_words[j + added - 1].end),
+        };
+        if pos < start {
+            write_words(context, newline, &plus[pos..start], reset, out)?;
+        }
+        if removed > 0 {
+            let text = &minus[old_words[i].start..old_words[i + removed - 1].end];
+            write_words(old, newline, text, reset, out)?;
+        }
+        if added > 0 {
+            write_words(new, newline, &plus[start..end], reset, out)?;
+        }
+        (i, j, pos) = (i + removed, j + added, end);
+        rest = after;
+    }
+    if pos < plus.len() {
+        write_words(context, newline, &plus[pos..], reset, out)?;
+    }
+    Ok(())
+}
+
+/// Print one hunk as a word diff: each group of removed and added lines
+/// as the words changed, see print_words().
+fn print_word_hunk(
+    ops: &[Op],
+    old: &[&[u8]],
+    new: &[&[u8]],
+    mode: WordDiff,
+    colors: &DiffColors,
+    out: &mut impl Write,
+) -> Result<()> {
+    print_hunk_header(ops, old, true, colors, out)?;
+    let (mut minus, mut plus) = (Vec::new(), Vec::new());
+    // Lines are compared as if they all ended with an end of line.
+    let append = |text: &mut Vec<u8>, line: &[u8]| {
+        text.extend_from_slice(line);
+        if !line.ends_with(b"\n") {
+            text.push(b'\n');
+        }
+    };
+    for op in ops {
+        match *op {
+            Op::Delete(i) => append(&mut minus, old[i]),
+            Op::Insert(j) => append(&mut plus, new[j]),
+            Op::Equal(i, _) => {
+                if !minus.is_empty() || !plus.is_empty() {
+                    print_words(&minus, &plus, mode, colors, out)?;
+                    (minus, plus) = (Vec::new(), Vec::new());
+                }
+                if mode == WordDiff::Porcelain {
+                    let line = [b" ", old[i]].concat();
+                    write_line(&colors.context, None, &line, &colors.reset, out)?;
+                    out.write_all(b"~\n")?;
+                } else {
+                    write_line(&colors.context, None, old[i], &colors.reset, out)?;
+                }
+            }
+        }
+    }
+    if !minus.is_empty() || !plus.is_empty() {
+        print_words(&minus, &plus, mode, colors, out)?;
+    }
+    Ok(())
+}
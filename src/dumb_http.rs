@@ -0,0 +1,192 @@
+//! Fetching from HTTP servers which only implement the dumb protocol, that is
+//! serve the files of a repository as they are, see gitprotocol-http(5)
+//! "Dumb Clients". The server needs info/refs and objects/info/packs, as
+//! written by git update-server-info.
+//!
+//! Without negotiation, objects are found by walking from the wanted ones:
+//! each one is downloaded as a loose object, or else the pack containing it
+//! is downloaded whole. Shallow and partial fetches are not supported.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+
+use crate::commit::Commit;
+use crate::fetch::have_object;
+use crate::http_store;
+use crate::network::{get_file, RemoteRef};
+use crate::obj_read::read_tree;
+use crate::obj_type::ObjType;
+use crate::pack_index::store_pack;
+use crate::pack_read::index_hashes;
+use crate::repository::Repository;
+use crate::revwalk::tag_target;
+use crate::tree_entry::Mode;
+
+/// Download a text file from the server, failing if it doesn't exist.
+fn get_text(repo_url: &str, path: &str) -> Result<String> {
+    let Some(response) = get_file(repo_url, path)? else {
+        bail!("{path} not found on {repo_url}: is this a git repository?");
+    };
+    response
+        .text()
+        .with_context(|| format!("reading {path} from {repo_url}"))
+}
+
+/// Tell if a string is a SHA-1 in hexadecimal.
+fn is_sha1(s: &str) -> bool {
+    s.len() == 40 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// List references from info/refs, with HEAD first, like ls-refs would.
+///
+/// If prefixes are given, only list references starting with one of them.
+pub fn ls_refs(repo_url: &str, prefixes: &[String]) -> Result<Vec<RemoteRef>> {
+    // <hash> TAB <refname>, followed by <hash> TAB <refname>^{} for tags.
+    let mut refs: Vec<RemoteRef> = Vec::new();
+    for line in get_text(repo_url, "info/refs")?.lines() {
+        let Some((hash, name)) = line.split_once('\t').filter(|(h, _)| is_sha1(h)) else {
+            bail!("invalid line in info/refs: {line:?}");
+        };
+        if let Some(name) = name.strip_suffix("^{}") {
+            if let Some(last) = refs.last_mut().filter(|r| r.name == name) {
+                last.peeled = Some(hash.to_owned());
+            }
+            continue;
+        }
+        refs.push(RemoteRef {
+            hash: hash.to_owned(),
+            name: name.to_owned(),
+            symref_target: None,
+            peeled: None,
+        });
+    }
+
+    // HEAD is not in info/refs: it is either symbolic or a hash.
+    let head = get_text(repo_url, "HEAD")?;
+    let head = head.trim_end();
+    let head = match head.strip_prefix("ref: ") {
+        Some(target) => refs.iter().find(|r| r.name == target).map(|r| RemoteRef {
+            hash: r.hash.clone(),
+            name: "HEAD".to_owned(),
+            symref_target: Some(target.to_owned()),
+            peeled: None,
+        }),
+        None if is_sha1(head) => Some(RemoteRef {
+            hash: head.to_owned(),
+            name: "HEAD".to_owned(),
+            symref_target: None,
+            peeled: None,
+        }),
+        None => bail!("invalid HEAD on {repo_url}: {head:?}"),
+    };
+    refs.splice(0..0, head);
+
+    refs.retain(|r| prefixes.is_empty() || prefixes.iter().any(|p| r.name.starts_with(p)));
+    Ok(refs)
+}
+
+/// Objects available on the server, and how to get them.
+struct Remote<'a> {
+    url: &'a str,
+    /// Packs not downloaded yet, with their objects (listed on first need)
+    packs: Option<Vec<(String, HashSet<String>)>>,
+}
+
+impl Remote<'_> {
+    /// List the packs on the server, from objects/info/packs, downloading
+    /// their indexes to know which objects they contain.
+    fn packs(&mut self, repo: &Repository) -> Result<&mut Vec<(String, HashSet<String>)>> {
+        if self.packs.is_none() {
+            let mut packs = Vec::new();
+            // P <name>, other lines are ignored
+            for line in get_text(self.url, "objects/info/packs")?.lines() {
+                let Some(name) = line
+                    .strip_prefix("P ")
+                    .and_then(|n| n.strip_suffix(".pack"))
+                else {
+                    continue;
+                };
+                let path = format!("objects/pack/{name}.idx");
+                let Some(response) = get_file(self.url, &path)? else {
+                    bail!("{path} not found on {}", self.url);
+                };
+                let idx = response
+                    .bytes()
+                    .with_context(|| format!("reading {path} from {}", self.url))?;
+                let hashes = index_hashes(repo, idx.to_vec(), &path)?;
+                packs.push((name.to_owned(), hashes.into_iter().collect()));
+            }
+            self.packs = Some(packs);
+        }
+        Ok(self.packs.as_mut().expect("just listed"))
+    }
+
+    /// Download an object, loose or with the pack containing it.
+    /// Return the hashes of all objects received.
+    fn download(&mut self, repo: &Repository, hash: &str) -> Result<Vec<String>> {
+        if http_store::fetch_from(repo, self.url, hash)?.is_some() {
+            return Ok(vec![hash.to_owned()]);
+        }
+        let url = self.url;
+        let packs = self.packs(repo)?;
+        let Some(i) = packs.iter().position(|(_, hashes)| hashes.contains(hash)) else {
+            bail!("object {hash} not found on {url}");
+        };
+        let (name, hashes) = packs.swap_remove(i);
+        let path = format!("objects/pack/{name}.pack");
+        let Some(response) = get_file(url, &path)? else {
+            bail!("{path} not found on {url}");
+        };
+        store_pack(repo, response).with_context(|| format!("storing {path}"))?;
+        Ok(hashes.into_iter().collect())
+    }
+}
+
+/// Download the given objects and all objects they refer to, except those
+/// we already had (which are complete). Return the number of objects received.
+pub fn fetch(repo: &Repository, repo_url: &str, wants: &[String]) -> Result<usize> {
+    let mut remote = Remote {
+        url: repo_url,
+        packs: None,
+    };
+    let mut received = HashSet::new();
+    let mut seen = HashSet::new();
+    let mut queue = wants.to_vec();
+    while let Some(hash) = queue.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        // Objects from a pack may refer to objects in other packs.
+        if !received.contains(&hash) {
+            if have_object(repo, &hash)? {
+                continue;
+            }
+            received.extend(remote.download(repo, &hash)?);
+        }
+        let mut object = repo
+            .read_object(&hash)
+            .with_context(|| format!("opening {hash}"))?;
+        match object.obj_type {
+            ObjType::Commit => {
+                let commit = Commit::from_object(repo, object, &hash)
+                    .with_context(|| format!("parsing commit {hash}"))?;
+                queue.push(commit.tree);
+                queue.extend(commit.parents);
+            }
+            ObjType::Tree => {
+                for entry in read_tree(repo, &hash)? {
+                    if !matches!(entry.mode, Mode::SubMod) {
+                        queue.push(hex::encode(entry.hash));
+                    }
+                }
+            }
+            ObjType::Tag => {
+                let (target, _) =
+                    tag_target(&mut object).with_context(|| format!("reading tag {hash}"))?;
+                queue.push(target);
+            }
+            ObjType::Blob => (),
+        }
+    }
+    Ok(received.len())
+}
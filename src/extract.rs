@@ -0,0 +1,210 @@
+//! Exporting parts of a tree to an arbitrary directory.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+
+use crate::common::{is_under, wildmatch};
+use crate::obj_read::read_tree;
+use crate::platform;
+use crate::repository::Repository;
+use crate::tree_entry::{check_leading_dirs, verify_name, verify_path, Entry, Mode};
+
+/// Find the entry for the given path (relative to the root of the tree).
+///
+/// Return None if the path designates the root itself.
+pub fn find_entry(repo: &Repository, tree_hash: &str, path: &Path) -> Result<Option<Entry>> {
+    let mut tree_hash = tree_hash.to_owned();
+    let mut found = None;
+    for comp in path.components() {
+        let name = match comp {
+            Component::Normal(name) => platform::bytes(name),
+            Component::CurDir => continue,
+            _ => bail!("path must be relative and may not contain '..'"),
+        };
+        if let Some(Entry { mode, .. }) = &found {
+            if !matches!(mode, Mode::Dir) {
+                bail!("{} is not a directory in tree", path.display());
+            }
+        }
+        let entries = read_tree(repo, &tree_hash)?;
+        let Some(entry) = entries.into_iter().find(|e| e.name == *name) else {
+            bail!("path {} does not exist in tree", path.display());
+        };
+        tree_hash = hex::encode(entry.hash);
+        found = Some(entry);
+    }
+    Ok(found)
+}
+
+/// Tell if a pathspec has wildcards, see wildmatch().
+fn has_wildcards(spec: &[u8]) -> bool {
+    spec.iter().any(|c| matches!(c, b'*' | b'?'))
+}
+
+/// Tell if a path from the tree matches a pathspec: like git, if it is the
+/// path or inside it, or if the pathspec has wildcards and matches it (where
+/// `*` also matches slashes). The empty pathspec matches everything.
+fn matches(path: &[u8], spec: &[u8]) -> bool {
+    is_under(path, spec) || has_wildcards(spec) && wildmatch(spec, path)
+}
+
+/// Tell if a directory may contain paths matching a pathspec.
+fn may_contain(dir: &[u8], spec: &[u8]) -> bool {
+    let end = spec
+        .iter()
+        .position(|c| matches!(c, b'*' | b'?'))
+        .unwrap_or(spec.len());
+    let literal = &spec[..end];
+    let dir = [dir, b"/"].concat();
+    match end == spec.len() {
+        true => literal.starts_with(&dir),
+        false => literal.starts_with(&dir) || dir.starts_with(literal),
+    }
+}
+
+/// Check a pathspec, relative to the root of the tree, and normalize it:
+/// without leading `./` or trailing `/`, and empty for the whole tree. Like
+/// the paths of trees, it may not contain `..` or `.git`, or be absolute.
+fn check_pathspec(spec: &Path) -> Result<Vec<u8>> {
+    let mut normalized = &platform::bytes(spec.as_os_str())[..];
+    while let Some(rest) = normalized.strip_prefix(b"./") {
+        normalized = rest;
+    }
+    while let Some(rest) = normalized.strip_suffix(b"/").filter(|r| !r.is_empty()) {
+        normalized = rest;
+    }
+    if normalized == b"." {
+        normalized = b"";
+    }
+    if !normalized.is_empty() {
+        verify_path(normalized)
+            .with_context(|| format!("invalid pathspec '{}'", spec.display()))?;
+    }
+    Ok(normalized.to_vec())
+}
+
+/// Plans an extraction: creates directories, and collects the files to write.
+struct Planner<'a> {
+    dest: &'a Path,
+    pathspecs: Vec<Vec<u8>>,
+    /// Which pathspecs matched something
+    matched: Vec<bool>,
+    /// Files to write, with the directory to write them in
+    jobs: Vec<(Entry, PathBuf)>,
+}
+
+impl Planner<'_> {
+    /// Create the directory for a path from the tree below the destination,
+    /// refusing to write through symlinks.
+    fn create_dir(&self, path: &[u8]) -> Result<PathBuf> {
+        check_leading_dirs(self.dest, &[path, b"/"].concat())?;
+        let dir = platform::join(self.dest, path);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("creating directory {}", dir.display()))?;
+        Ok(dir)
+    }
+
+    /// Plan the extraction of entries of a tree at the given path, all of
+    /// them if `selected`, else those matching pathspecs.
+    fn plan_tree(
+        &mut self,
+        repo: &Repository,
+        tree_hash: &str,
+        path: &[u8],
+        selected: bool,
+    ) -> Result<()> {
+        for entry in read_tree(repo, tree_hash)? {
+            verify_name(&entry.name)?;
+            let entry_path = match path {
+                b"" => entry.name.clone(),
+                _ => [path, b"/", &entry.name].concat(),
+            };
+            let mut selected = selected;
+            for (spec, matched) in self.pathspecs.iter().zip(&mut self.matched) {
+                if matches(&entry_path, spec) {
+                    *matched = true;
+                    selected = true;
+                }
+            }
+            match entry.mode {
+                Mode::Dir => {
+                    if selected {
+                        self.create_dir(&entry_path)?;
+                    }
+                    if selected || self.pathspecs.iter().any(|s| may_contain(&entry_path, s)) {
+                        self.plan_tree(repo, &hex::encode(entry.hash), &entry_path, selected)?;
+                    }
+                }
+                // Like git archive, represent submodules as empty directories.
+                Mode::SubMod if selected => _ = self.create_dir(&entry_path)?,
+                Mode::SubMod => (),
+                _ if selected => {
+                    let dir = self.create_dir(path)?;
+                    self.jobs.push((entry, dir));
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Export the paths matching the given pathspecs (relative to the root of
+/// the tree) from a tree to the destination directory, preserving their path
+/// from the root of the tree, and return the number of files (including
+/// symlinks) written.
+///
+/// Directories are created first, then blobs are written in parallel.
+pub fn extract(
+    repo: &Repository,
+    tree_hash: &str,
+    pathspecs: &[PathBuf],
+    dest: &Path,
+) -> Result<usize> {
+    let mut planner = Planner {
+        dest,
+        pathspecs: pathspecs
+            .iter()
+            .map(|spec| check_pathspec(spec))
+            .collect::<Result<_>>()?,
+        matched: vec![false; pathspecs.len()],
+        jobs: Vec::new(),
+    };
+    planner.create_dir(b"")?;
+    planner.plan_tree(repo, tree_hash, b"", false)?;
+    if let Some(i) = planner.matched.iter().position(|matched| !matched) {
+        bail!(
+            "pathspec '{}' did not match any files",
+            pathspecs[i].display()
+        );
+    }
+    let jobs = planner.jobs;
+
+    let nb_threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = jobs.len().div_ceil(nb_threads).max(1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = jobs
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || -> Result<()> {
+                    for (entry, dir) in chunk {
+                        entry.actualise(repo, dir).with_context(|| {
+                            format!("writing {}", platform::join(dir, &entry.name).display())
+                        })?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker
+                .join()
+                .map_err(|_| anyhow!("extraction thread panicked"))??;
+        }
+        Ok::<_, anyhow::Error>(())
+    })?;
+
+    Ok(jobs.len())
+}
@@ -0,0 +1,230 @@
+//! Exporting history as a stream of commands for fast-import, like git
+//! fast-export.
+//!
+//! See git-fast-import(1) for the format. Commits are written oldest first,
+//! each preceded by the blobs it adds, under the name of the first reference
+//! they are reachable from; other references are then set with a reset
+//! command. Excluded parents are referred to by their hash, like git does with
+//! --reference-excluded-parents. Annotated tags of commits and blobs are
+//! exported as they are, with any signature in their message, while commit
+//! signatures and encoding headers are dropped.
+
+use anyhow::{bail, Result};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use crate::commit::Commit;
+use crate::common::quote_c;
+use crate::diff::{diff_trees, Change};
+use crate::obj_read::{read_blob, read_commit};
+use crate::obj_type::ObjType;
+use crate::repository::Repository;
+use crate::revwalk::{peel, reachable_commits};
+use crate::tag::Tag;
+use crate::tree_entry::Mode;
+
+/// Write a path in a file command: quoted if it has unusual characters or
+/// spaces, which would be ambiguous.
+fn path(path: &[u8]) -> String {
+    match quote_c(path) {
+        Some(quoted) => quoted,
+        None if path.contains(&b' ') => format!("\"{}\"", String::from_utf8_lossy(path)),
+        None => String::from_utf8_lossy(path).into_owned(),
+    }
+}
+
+/// Order changes like git: by path, with the content of a directory before
+/// a file replacing it, but a deleted file before a directory replacing it.
+fn depth_first(a: &Change, b: &Change) -> Ordering {
+    let len = a.path.len().min(b.path.len());
+    match a.path[..len].cmp(&b.path[..len]) {
+        Ordering::Equal if a.path.len() < b.path.len() && a.new.is_none() => Ordering::Less,
+        Ordering::Equal if b.path.len() < a.path.len() && b.new.is_none() => Ordering::Greater,
+        Ordering::Equal => b.path.len().cmp(&a.path.len()),
+        order => order,
+    }
+}
+
+/// State of an export: marks given to the objects written so far.
+struct Exporter<'a, W: Write> {
+    out: &'a mut W,
+    marks: HashMap<String, usize>,
+}
+
+impl<W: Write> Exporter<'_, W> {
+    /// Give the next mark to an object.
+    fn mark(&mut self, hash: &str) -> usize {
+        let mark = self.marks.len() + 1;
+        self.marks.insert(hash.to_owned(), mark);
+        mark
+    }
+
+    /// Refer to an object: by its mark if it was exported, else by its hash.
+    fn reference(&self, hash: &str) -> String {
+        match self.marks.get(hash) {
+            Some(mark) => format!(":{mark}"),
+            None => hash.to_owned(),
+        }
+    }
+
+    /// Write a data command with its content.
+    fn data(&mut self, data: &[u8]) -> Result<()> {
+        writeln!(self.out, "data {}", data.len())?;
+        Ok(self.out.write_all(data)?)
+    }
+
+    /// Write a blob, unless already done.
+    fn blob(&mut self, repo: &Repository, hash: &str) -> Result<()> {
+        if self.marks.contains_key(hash) {
+            return Ok(());
+        }
+        let data = read_blob(repo, hash)?;
+        let mark = self.mark(hash);
+        writeln!(self.out, "blob\nmark :{mark}")?;
+        self.data(&data)?;
+        Ok(writeln!(self.out)?)
+    }
+
+    /// Write a commit with its new blobs, as changes from its first parent.
+    fn commit(&mut self, repo: &Repository, name: &str, hash: &str, commit: &Commit) -> Result<()> {
+        let parent_tree = match commit.parents.first() {
+            Some(parent) => Some(read_commit(repo, parent)?.tree),
+            None => None,
+        };
+        let mut changes = diff_trees(repo, parent_tree.as_deref(), &commit.tree)?;
+        changes.sort_by(depth_first);
+        for side in changes.iter().filter_map(|change| change.new.as_ref()) {
+            if side.mode != Mode::SubMod {
+                self.blob(repo, &side.hash)?;
+            }
+        }
+        if commit.parents.is_empty() {
+            writeln!(self.out, "reset {name}")?;
+        }
+        let mark = self.mark(hash);
+        writeln!(self.out, "commit {name}\nmark :{mark}")?;
+        writeln!(
+            self.out,
+            "author {} {} {}",
+            commit.author, commit.author_time, commit.author_zone
+        )?;
+        writeln!(
+            self.out,
+            "committer {} {} {}",
+            commit.committer, commit.time, commit.committer_zone
+        )?;
+        self.data(&commit.message)?;
+        for (i, parent) in commit.parents.iter().enumerate() {
+            let command = if i == 0 { "from" } else { "merge" };
+            writeln!(self.out, "{command} {}", self.reference(parent))?;
+        }
+        for change in &changes {
+            match &change.new {
+                Some(side) => writeln!(
+                    self.out,
+                    "M {} {} {}",
+                    side.mode.to_str(),
+                    self.reference(&side.hash),
+                    path(&change.path)
+                )?,
+                None => writeln!(self.out, "D {}", path(&change.path))?,
+            }
+        }
+        Ok(writeln!(self.out)?)
+    }
+
+    /// Write the commits leading to a tip which are part of the export and
+    /// were not written yet, parents first.
+    fn history(
+        &mut self,
+        repo: &Repository,
+        name: &str,
+        tip: &str,
+        commits: &HashSet<String>,
+    ) -> Result<()> {
+        let mut stack = vec![(tip.to_owned(), false)];
+        while let Some((hash, parents_done)) = stack.pop() {
+            if self.marks.contains_key(&hash) {
+                continue;
+            }
+            let commit = read_commit(repo, &hash)?;
+            if parents_done {
+                self.commit(repo, name, &hash, &commit)?;
+                continue;
+            }
+            stack.push((hash, true));
+            for parent in commit.parents.iter().rev() {
+                if commits.contains(parent) && !self.marks.contains_key(parent) {
+                    stack.push((parent.clone(), false));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write an annotated tag, the object it tags being already written
+    /// (or excluded) unless it is a blob.
+    fn tag(&mut self, repo: &Repository, name: &str, hash: &str) -> Result<()> {
+        let tag = Tag::from_object(repo, repo.read_object(hash)?)?;
+        let Some(short) = name.strip_prefix("refs/tags/") else {
+            bail!("{name} is an annotated tag outside of refs/tags/");
+        };
+        match tag.obj_type {
+            ObjType::Commit => (),
+            ObjType::Blob => self.blob(repo, &tag.object)?,
+            obj_type => bail!(
+                "{name} tags a {}, which can't be exported",
+                obj_type.to_str()
+            ),
+        }
+        writeln!(
+            self.out,
+            "tag {short}\nfrom {}",
+            self.reference(&tag.object)
+        )?;
+        if let Some(tagger) = &tag.tagger {
+            writeln!(self.out, "tagger {tagger}")?;
+        }
+        self.data(tag.message.as_deref().unwrap_or_default())?;
+        Ok(writeln!(self.out)?)
+    }
+}
+
+/// Write a stream for fast-import with references, given as full names and
+/// hashes, and their history, except for what is reachable from the excluded
+/// commits. References to objects other than commits and tags are left out.
+pub fn write(
+    repo: &Repository,
+    out: &mut impl Write,
+    refs: &[(String, String)],
+    exclude: &[String],
+) -> Result<()> {
+    let tips: Vec<String> = refs.iter().map(|(_, hash)| hash.clone()).collect();
+    let commits: HashSet<String> = reachable_commits(repo, &tips, exclude)?
+        .into_iter()
+        .collect();
+    let mut exporter = Exporter {
+        out,
+        marks: HashMap::new(),
+    };
+    for (name, hash) in refs {
+        let is_tag = repo.read_object(hash)?.obj_type == ObjType::Tag;
+        match peel(repo, hash)? {
+            Some((commit, ObjType::Commit)) if commits.contains(&commit) => {
+                let exported = exporter.marks.contains_key(&commit);
+                exporter.history(repo, name, &commit, &commits)?;
+                if exported && !is_tag {
+                    let from = exporter.reference(&commit);
+                    writeln!(exporter.out, "reset {name}\nfrom {from}\n")?;
+                }
+            }
+            Some((_, ObjType::Blob)) => (),
+            _ => continue,
+        }
+        if is_tag {
+            exporter.tag(repo, name, hash)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,490 @@
+//! Importing a stream of commands, as written by fast-export, like git
+//! fast-import.
+//!
+//! See git-fast-import(1) for the format. The blob, commit, tag, reset,
+//! checkpoint, progress and done commands are supported, with marks, inline
+//! data and the M, D, C, R and deleteall file commands. Dates must be in the
+//! raw format. Marks files and the ls, cat-blob, get-mark and note commands
+//! are not supported, and options are ignored.
+//!
+//! Objects are written as the stream is read, while references are only
+//! updated at the end, by the caller.
+
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Read};
+use std::str;
+
+use crate::common::unquote_c;
+use crate::diff::{flatten_tree, Side};
+use crate::obj_read::read_commit;
+use crate::obj_type::ObjType;
+use crate::obj_write::write_object;
+use crate::object_id::is_hash;
+use crate::refs;
+use crate::repository::Repository;
+use crate::revision::resolve;
+use crate::revwalk::peel_to_commit;
+use crate::tree_entry::Mode;
+use crate::tree_write::tree_from_list;
+
+/// Content of a commit being built, by path in index order.
+type Files = BTreeMap<Vec<u8>, Side>;
+
+/// State of an import.
+struct Importer<R: BufRead> {
+    input: R,
+    /// A line read ahead, which turned out to start the next command
+    pending: Option<Vec<u8>>,
+    marks: HashMap<u64, String>,
+    /// Tips of the references set so far by the stream, None after a reset
+    /// without a commit
+    refs: BTreeMap<String, Option<String>>,
+    /// Content of the last commit of each branch, to avoid reading its
+    /// tree again for the next one
+    files: HashMap<String, (String, Files)>,
+}
+
+/// Parse the mode of a file command: git also accepts short forms.
+fn parse_mode(mode: &[u8]) -> Result<Mode> {
+    let mode = str::from_utf8(mode).ok();
+    let Some(mode) = mode.and_then(|mode| u32::from_str_radix(mode, 8).ok()) else {
+        bail!("invalid mode {mode:?}");
+    };
+    match mode {
+        0o644 => Ok(Mode::File),
+        0o755 => Ok(Mode::Exe),
+        mode => Mode::from_u32(mode),
+    }
+}
+
+/// Split the path at the start of a file command from what follows: a path
+/// is either quoted, or ends at the first space if there is something after it.
+fn split_path(line: &[u8], last: bool) -> Result<(Vec<u8>, &[u8])> {
+    if line.starts_with(b"\"") {
+        let mut escaped = false;
+        for (i, &c) in line.iter().enumerate().skip(1) {
+            match c {
+                b'"' if !escaped => {
+                    let rest = &line[i + 1..];
+                    return Ok((
+                        unquote_c(&line[..=i])?,
+                        rest.strip_prefix(b" ").unwrap_or(rest),
+                    ));
+                }
+                b'\\' => escaped = !escaped,
+                _ => escaped = false,
+            }
+        }
+        bail!("unterminated quoted path");
+    }
+    match last {
+        true => Ok((line.to_vec(), b"")),
+        false => match line.iter().position(|&c| c == b' ') {
+            Some(space) => Ok((line[..space].to_vec(), &line[space + 1..])),
+            None => bail!("missing destination path"),
+        },
+    }
+}
+
+/// Tell if a path is in a directory.
+fn is_below(path: &[u8], dir: &[u8]) -> bool {
+    path.len() > dir.len() && path.starts_with(dir) && path[dir.len()] == b'/'
+}
+
+/// Remove a path, or everything below it if it is a directory.
+fn remove(files: &mut Files, path: &[u8]) {
+    files.remove(path);
+    let below: Vec<Vec<u8>> = files
+        .range(path.to_vec()..)
+        .map(|(name, _)| name)
+        .take_while(|name| name.starts_with(path))
+        .filter(|name| is_below(name, path))
+        .cloned()
+        .collect();
+    for name in below {
+        files.remove(&name);
+    }
+}
+
+/// Add a path, replacing what was there and any file in the way.
+fn insert(files: &mut Files, path: Vec<u8>, side: Side) {
+    remove(files, &path);
+    for (i, _) in path.iter().enumerate().filter(|&(_, &c)| c == b'/') {
+        files.remove(&path[..i]);
+    }
+    files.insert(path, side);
+}
+
+/// Find the part of a line after a command name and a space.
+fn argument<'a>(line: &'a [u8], command: &str) -> Option<&'a [u8]> {
+    line.strip_prefix(command.as_bytes())?.strip_prefix(b" ")
+}
+
+/// Give the text of an argument, which must be UTF-8.
+fn text(arg: &[u8]) -> Result<&str> {
+    str::from_utf8(arg).context("invalid UTF-8 in command")
+}
+
+impl<R: BufRead> Importer<R> {
+    /// Read the next line without its end, skipping comments.
+    fn line(&mut self) -> Result<Option<Vec<u8>>> {
+        if let Some(line) = self.pending.take() {
+            return Ok(Some(line));
+        }
+        loop {
+            let mut line = Vec::new();
+            if self.input.read_until(b'\n', &mut line)? == 0 {
+                return Ok(None);
+            }
+            if line.pop() != Some(b'\n') {
+                bail!("unterminated line at the end of the stream");
+            }
+            if !line.starts_with(b"#") {
+                return Ok(Some(line));
+            }
+        }
+    }
+
+    /// Read the next line if it is the given command, returning its argument.
+    fn optional(&mut self, command: &str) -> Result<Option<Vec<u8>>> {
+        let Some(line) = self.line()? else {
+            return Ok(None);
+        };
+        match argument(&line, command) {
+            Some(arg) => Ok(Some(arg.to_vec())),
+            None => {
+                self.pending = Some(line);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Read the content of a data command, in either of its forms.
+    fn data(&mut self) -> Result<Vec<u8>> {
+        let line = self.line()?.unwrap_or_default();
+        let Some(arg) = argument(&line, "data") else {
+            bail!(
+                "expected data command, got {:?}",
+                String::from_utf8_lossy(&line)
+            );
+        };
+        let mut data = Vec::new();
+        if let Some(delimiter) = arg.strip_prefix(b"<<") {
+            loop {
+                let mut line = Vec::new();
+                self.input.read_until(b'\n', &mut line)?;
+                if line.pop() != Some(b'\n') {
+                    bail!("end of stream in delimited data");
+                }
+                if line == delimiter {
+                    break;
+                }
+                data.extend(line);
+                data.push(b'\n');
+            }
+        } else {
+            let size: u64 = text(arg)?.parse().context("invalid data size")?;
+            (&mut self.input).take(size).read_to_end(&mut data)?;
+            if (data.len() as u64) < size {
+                bail!("end of stream in data");
+            }
+        }
+        // An optional line feed may follow.
+        if self.input.fill_buf()?.first() == Some(&b'\n') {
+            self.input.consume(1);
+        }
+        Ok(data)
+    }
+
+    /// Read an optional mark command, and an original-oid command, ignored.
+    fn mark(&mut self) -> Result<Option<u64>> {
+        let mark = match self.optional("mark")? {
+            Some(mark) => {
+                let mark = mark.strip_prefix(b":").unwrap_or_default();
+                Some(text(mark)?.parse().context("invalid mark")?)
+            }
+            None => None,
+        };
+        self.optional("original-oid")?;
+        Ok(mark)
+    }
+
+    /// Find the object a mark, a hash, a reference set by the stream or a
+    /// revision refers to.
+    fn object(&self, repo: &Repository, name: &[u8]) -> Result<String> {
+        let name = text(name)?;
+        if let Some(mark) = name.strip_prefix(':') {
+            let mark: u64 = mark.parse().context("invalid mark")?;
+            return self.marks.get(&mark).cloned().context("unknown mark");
+        }
+        if is_hash(repo, name) {
+            return Ok(name.to_owned());
+        }
+        match self.refs.get(name) {
+            Some(Some(hash)) => Ok(hash.clone()),
+            Some(None) => bail!("{name} has no commits yet"),
+            None => resolve(repo, name),
+        }
+    }
+
+    /// Get the content of a commit, None standing for no commit.
+    fn commit_files(
+        &mut self,
+        repo: &Repository,
+        name: &str,
+        commit: Option<&str>,
+    ) -> Result<Files> {
+        let Some(commit) = commit else {
+            return Ok(Files::new());
+        };
+        if let Some((last, files)) = self.files.remove(name) {
+            if last == commit {
+                return Ok(files);
+            }
+        }
+        let mut list = Vec::new();
+        flatten_tree(repo, &read_commit(repo, commit)?.tree, b"", &mut list)?;
+        Ok(list.into_iter().collect())
+    }
+
+    /// Apply a file command of a commit.
+    fn file_command(&mut self, repo: &Repository, files: &mut Files, line: &[u8]) -> Result<()> {
+        if line == b"deleteall" {
+            files.clear();
+        } else if let Some(arg) = argument(line, "M") {
+            let mut fields = arg.splitn(3, |&c| c == b' ');
+            let (Some(mode), Some(dataref), Some(rest)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                bail!("invalid file command {:?}", String::from_utf8_lossy(line));
+            };
+            let mode = parse_mode(mode)?;
+            let (path, _) = split_path(rest, true)?;
+            let hash = match dataref {
+                b"inline" => {
+                    let data = self.data()?;
+                    write_object(repo, ObjType::Blob, &mut io::Cursor::new(data), true)?
+                }
+                dataref => self.object(repo, dataref)?,
+            };
+            if mode == Mode::Dir {
+                // A whole tree, the root one if the path is empty
+                let prefix = match path.is_empty() {
+                    true => Vec::new(),
+                    false => [&path[..], b"/"].concat(),
+                };
+                match path.is_empty() {
+                    true => files.clear(),
+                    false => remove(files, &path),
+                }
+                // The empty tree needs not exist.
+                if hash != repo.hash_algo()?.empty_tree() {
+                    let mut list = Vec::new();
+                    flatten_tree(repo, &hash, &prefix, &mut list)?;
+                    files.extend(list);
+                }
+            } else {
+                insert(
+                    files,
+                    path,
+                    Side {
+                        mode,
+                        hash,
+                        file: None,
+                    },
+                );
+            }
+        } else if let Some(arg) = argument(line, "D") {
+            remove(files, &split_path(arg, true)?.0);
+        } else if let Some(arg) = argument(line, "C").or(argument(line, "R")) {
+            let (source, rest) = split_path(arg, false)?;
+            let (target, _) = split_path(rest, true)?;
+            let moved: Vec<(Vec<u8>, Side)> = files
+                .iter()
+                .filter(|(path, _)| **path == source || is_below(path, &source))
+                .map(|(path, side)| ([&target[..], &path[source.len()..]].concat(), side.clone()))
+                .collect();
+            if moved.is_empty() {
+                bail!("path {} not in branch", String::from_utf8_lossy(&source));
+            }
+            if line.starts_with(b"R") {
+                remove(files, &source);
+            }
+            remove(files, &target);
+            for (path, side) in moved {
+                insert(files, path, side);
+            }
+        } else {
+            bail!(
+                "unsupported file command {:?}",
+                String::from_utf8_lossy(line)
+            );
+        }
+        Ok(())
+    }
+
+    /// Import a blob.
+    fn blob(&mut self, repo: &Repository) -> Result<()> {
+        let mark = self.mark()?;
+        let data = self.data()?;
+        let hash = write_object(repo, ObjType::Blob, &mut io::Cursor::new(data), true)?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, hash);
+        }
+        Ok(())
+    }
+
+    /// Import a commit on a branch, by default following its current tip.
+    fn commit(&mut self, repo: &Repository, name: &str) -> Result<()> {
+        let mark = self.mark()?;
+        let author = self.optional("author")?;
+        let Some(committer) = self.optional("committer")? else {
+            bail!("missing committer in commit on {name}");
+        };
+        let encoding = self.optional("encoding")?;
+        let message = self.data()?;
+        let mut parents = Vec::new();
+        match self.optional("from")? {
+            Some(from) => parents.push(peel_to_commit(repo, &self.object(repo, &from)?)?),
+            None => match self.refs.get(name) {
+                Some(tip) => parents.extend(tip.clone()),
+                None => parents.extend(refs::resolve(repo, name)?),
+            },
+        }
+        while let Some(merge) = self.optional("merge")? {
+            parents.push(peel_to_commit(repo, &self.object(repo, &merge)?)?);
+        }
+
+        let mut files = self.commit_files(repo, name, parents.first().map(String::as_str))?;
+        while let Some(line) = self.line()? {
+            if !line.starts_with(b"M ")
+                && !line.starts_with(b"D ")
+                && !line.starts_with(b"C ")
+                && !line.starts_with(b"R ")
+                && line != b"deleteall"
+            {
+                self.pending = Some(line);
+                break;
+            }
+            self.file_command(repo, &mut files, &line)?;
+        }
+        let list: Vec<(Vec<u8>, Side)> =
+            files.iter().map(|(p, s)| (p.clone(), s.clone())).collect();
+        let tree = tree_from_list(repo, &list)?;
+
+        let mut content = format!("tree {tree}\n").into_bytes();
+        for parent in &parents {
+            content.extend(format!("parent {parent}\n").bytes());
+        }
+        content.extend(b"author ");
+        content.extend(author.as_ref().unwrap_or(&committer));
+        content.extend(b"\ncommitter ");
+        content.extend(&committer);
+        if let Some(encoding) = encoding {
+            content.extend(b"\nencoding ");
+            content.extend(encoding);
+        }
+        content.extend(b"\n\n");
+        content.extend(message);
+        let hash = write_object(repo, ObjType::Commit, &mut io::Cursor::new(content), true)?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, hash.clone());
+        }
+        self.refs.insert(name.to_owned(), Some(hash.clone()));
+        self.files.insert(name.to_owned(), (hash, files));
+        Ok(())
+    }
+
+    /// Import an annotated tag.
+    fn tag(&mut self, repo: &Repository, name: &str) -> Result<()> {
+        let mark = self.mark()?;
+        let Some(from) = self.optional("from")? else {
+            bail!("missing from in tag {name}");
+        };
+        let object = self.object(repo, &from)?;
+        self.optional("original-oid")?;
+        let tagger = self.optional("tagger")?;
+        let message = self.data()?;
+        let obj_type = repo.read_object(&object)?.obj_type;
+
+        let mut content =
+            format!("object {object}\ntype {}\ntag {name}\n", obj_type.to_str()).into_bytes();
+        if let Some(tagger) = tagger {
+            content.extend(b"tagger ");
+            content.extend(tagger);
+            content.push(b'\n');
+        }
+        content.push(b'\n');
+        content.extend(message);
+        let hash = write_object(repo, ObjType::Tag, &mut io::Cursor::new(content), true)?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, hash.clone());
+        }
+        self.refs.insert(format!("refs/tags/{name}"), Some(hash));
+        Ok(())
+    }
+
+    /// Set a branch to a commit, or to nothing so that the next commit on
+    /// it has no parent.
+    fn reset(&mut self, repo: &Repository, name: &str) -> Result<()> {
+        let tip = match self.optional("from")? {
+            Some(from) => Some(peel_to_commit(repo, &self.object(repo, &from)?)?),
+            None => None,
+        };
+        self.refs.insert(name.to_owned(), tip);
+        Ok(())
+    }
+
+    /// Run the commands of the stream until its end or a done command.
+    fn run(&mut self, repo: &Repository) -> Result<()> {
+        let mut require_done = false;
+        while let Some(line) = self.line()? {
+            let (command, arg) = match line.iter().position(|&c| c == b' ') {
+                Some(space) => (&line[..space], text(&line[space + 1..])?),
+                None => (&line[..], ""),
+            };
+            let check_ref = |name: &str| match refs::is_valid_name(name) {
+                true => Ok(name.to_owned()),
+                false => Err(anyhow::anyhow!("invalid reference name {name:?}")),
+            };
+            match command {
+                b"" => (),
+                b"blob" => self.blob(repo)?,
+                b"commit" => self.commit(repo, &check_ref(arg)?)?,
+                b"tag" => self.tag(repo, &check_ref(arg)?)?,
+                b"reset" => self.reset(repo, &check_ref(arg)?)?,
+                b"checkpoint" => (),
+                b"progress" => println!("{}", String::from_utf8_lossy(&line)),
+                b"done" => return Ok(()),
+                b"feature" => match arg {
+                    "done" => require_done = true,
+                    "date-format=raw" => (),
+                    _ => bail!("this version of fast-import does not support feature {arg}"),
+                },
+                b"option" => (),
+                _ => bail!("unsupported command: {}", String::from_utf8_lossy(&line)),
+            }
+        }
+        if require_done {
+            bail!("stream ends early");
+        }
+        Ok(())
+    }
+}
+
+/// Import a stream, writing its objects, and return the references it sets
+/// as full names and hashes, in name order. Commits on a branch which the
+/// stream doesn't set yet follow its current tip in the repository.
+pub fn import(repo: &Repository, input: impl BufRead) -> Result<Vec<(String, String)>> {
+    let mut importer = Importer {
+        input,
+        pending: None,
+        marks: HashMap::new(),
+        refs: BTreeMap::new(),
+        files: HashMap::new(),
+    };
+    importer.run(repo)?;
+    let refs = importer.refs.into_iter();
+    Ok(refs.filter_map(|(name, tip)| Some((name, tip?))).collect())
+}
@@ -0,0 +1,244 @@
+//! Fetching from a remote: planning which references to update, and
+//! negotiating which objects to send.
+
+use anyhow::{bail, Context, Result};
+use rand::Rng;
+use std::collections::{HashSet, VecDeque};
+use std::env;
+use std::fs;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+use crate::abbrev::shorten;
+use crate::commit::Commit;
+use crate::network::{fetch_round, FetchRequest, Negotiation, RemoteRef, ShallowInfo};
+use crate::obj_type::ObjType;
+use crate::pack_read;
+use crate::push::short_name;
+use crate::refs;
+use crate::remote::FetchSpec;
+use crate::repository::Repository;
+use crate::revwalk::is_ancestor;
+
+/// Number of haves sent in each round of negotiation.
+const HAVES_PER_ROUND: usize = 32;
+
+/// Maximum number of haves sent in total before giving up on negotiation.
+const MAX_HAVES: usize = 256;
+
+/// Tell if an object exists in the local object database (loose or packed),
+/// including alternates.
+pub fn have_object(repo: &Repository, hash: &str) -> Result<bool> {
+    Ok(repo.find_loose(hash).is_some() || pack_read::contains(repo, hash)?)
+}
+
+/// A planned update of a local reference to the value of a remote one.
+pub struct FetchUpdate {
+    /// The reference on the remote, eg "refs/heads/main"
+    pub remote_name: String,
+    /// The local reference, eg "refs/remotes/origin/main"
+    pub name: String,
+    /// The current value of the local reference, None if it doesn't exist
+    pub old: Option<String>,
+    pub new: String,
+    /// Allow non-fast-forward updates (`+` in the refspec)
+    pub force: bool,
+}
+
+/// How a fetch changes a local reference.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum UpdateKind {
+    New,
+    FastForward,
+    Forced,
+    /// Not a fast-forward, and not forced: the reference is left alone
+    Rejected,
+}
+
+impl FetchUpdate {
+    /// Tell how the reference changes. The objects of both values must be
+    /// available from the repository.
+    pub fn kind(&self, repo: &Repository) -> Result<UpdateKind> {
+        Ok(match &self.old {
+            None => UpdateKind::New,
+            Some(old) if is_ancestor(repo, old, &self.new)? => UpdateKind::FastForward,
+            Some(_) if self.force => UpdateKind::Forced,
+            Some(_) => UpdateKind::Rejected,
+        })
+    }
+
+    /// Describe the update for display, like git fetch:
+    /// `<flag> <summary> <remote> -> <local>[  (<note>)]`.
+    pub fn describe(&self, repo: &Repository, kind: UpdateKind) -> Result<String> {
+        let old = self.old.as_deref().unwrap_or_default();
+        let (flag, summary, note) = match kind {
+            UpdateKind::New if self.name.starts_with("refs/tags/") => {
+                ('*', "[new tag]".to_owned(), "")
+            }
+            UpdateKind::New if self.remote_name.starts_with("refs/heads/") => {
+                ('*', "[new branch]".to_owned(), "")
+            }
+            UpdateKind::New => ('*', "[new ref]".to_owned(), ""),
+            UpdateKind::FastForward => (
+                ' ',
+                format!("{}..{}", shorten(repo, old)?, shorten(repo, &self.new)?),
+                "",
+            ),
+            UpdateKind::Forced => (
+                '+',
+                format!("{}...{}", shorten(repo, old)?, shorten(repo, &self.new)?),
+                "  (forced update)",
+            ),
+            UpdateKind::Rejected => ('!', "[rejected]".to_owned(), "  (non-fast-forward)"),
+        };
+        let src = short_name(&self.remote_name);
+        let dst = self.name.strip_prefix("refs/remotes/");
+        let dst = dst.unwrap_or(short_name(&self.name));
+        Ok(format!(" {flag} {summary:<17} {src} -> {dst}{note}"))
+    }
+}
+
+/// Plan the updates of local references from remote ones: those mapped by
+/// a fetch refspec, and tags we don't have yet. References that are already
+/// up to date are left out.
+pub fn plan_updates(
+    repo: &Repository,
+    specs: &[FetchSpec],
+    remote_refs: Vec<RemoteRef>,
+) -> Result<Vec<FetchUpdate>> {
+    let mut updates = Vec::new();
+    for r in remote_refs {
+        let mapped = specs
+            .iter()
+            .find_map(|spec| Some((spec.map(&r.name)?, spec.force)));
+        let (name, force) = if let Some(mapped) = mapped {
+            mapped
+        } else if r.name.starts_with("refs/tags/") && refs::resolve(repo, &r.name)?.is_none() {
+            // Like git, don't update tags that already exist.
+            (r.name.clone(), false)
+        } else {
+            continue;
+        };
+        let old = refs::resolve(repo, &name)?;
+        if old.as_ref() != Some(&r.hash) {
+            updates.push(FetchUpdate {
+                remote_name: r.name,
+                name,
+                old,
+                new: r.hash,
+                force,
+            });
+        }
+    }
+    Ok(updates)
+}
+
+/// Objects kept aside in a temporary directory, removed on drop: fetch
+/// --dry-run receives objects there, to tell how references would change
+/// without adding anything to the repository.
+pub struct Quarantine {
+    dir: PathBuf,
+    repo: Repository,
+}
+
+impl Quarantine {
+    /// Create an empty temporary object directory for the repository.
+    pub fn new(repo: &Repository) -> Result<Self> {
+        let mut tmp_rand = [0u8; 20];
+        rand::rng().fill(&mut tmp_rand);
+        let dir = env::temp_dir().join(format!("tmpobjects{}", hex::encode(tmp_rand)));
+        fs::create_dir(&dir).with_context(|| format!("creating {}", dir.display()))?;
+        let repo = repo.quarantine(&dir);
+        Ok(Quarantine { dir, repo })
+    }
+
+    /// The repository, with new objects stored in the temporary directory.
+    pub fn repo(&self) -> &Repository {
+        &self.repo
+    }
+}
+
+impl Drop for Quarantine {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Collect commits reachable from local refs, in breadth-first order from the tips,
+/// so that the most recent commits come first. Stop after MAX_HAVES commits.
+fn local_commits(repo: &Repository) -> Result<Vec<String>> {
+    let mut queue: VecDeque<String> = refs::list(repo, "refs/")?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect();
+    if let Some(head) = refs::resolve(repo, "HEAD")? {
+        queue.push_front(head);
+    }
+
+    let mut seen = HashSet::new();
+    let mut commits = Vec::new();
+    while let Some(hash) = queue.pop_front() {
+        if commits.len() >= MAX_HAVES {
+            break;
+        }
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        // Refs may point to other types (tags), skip them.
+        let object = repo
+            .read_object(&hash)
+            .with_context(|| format!("opening {hash}"))?;
+        if object.obj_type != ObjType::Commit {
+            continue;
+        }
+        let commit =
+            Commit::from_object(repo, object, &hash).with_context(|| format!("parsing {hash}"))?;
+        queue.extend(commit.parents);
+        commits.push(hash);
+    }
+    Ok(commits)
+}
+
+/// Negotiate with the server to get a pack containing the wanted objects,
+/// avoiding objects we already have as much as possible.
+///
+/// Send our local commits as haves in batches, until the server says it's
+/// ready to send a pack, or we run out of haves and tell the server we're done.
+///
+/// Return a reader for the pack, and updates to the list of shallow commits.
+pub fn negotiate(
+    repo: &Repository,
+    repo_url: &str,
+    wants: &[String],
+) -> Result<(impl BufRead, ShallowInfo)> {
+    let mut common: Vec<String> = Vec::new();
+    for batch in local_commits(repo)?.chunks(HAVES_PER_ROUND) {
+        let haves: Vec<String> = common.iter().chain(batch).cloned().collect();
+        let request = FetchRequest {
+            wants,
+            haves: &haves,
+            ..Default::default()
+        };
+        match fetch_round(repo, repo_url, &request)? {
+            Negotiation::Pack(reader, shallow_info) => return Ok((reader, shallow_info)),
+            Negotiation::Continue(acked) => {
+                for hash in acked {
+                    if !common.contains(&hash) {
+                        common.push(hash);
+                    }
+                }
+            }
+        }
+    }
+
+    let request = FetchRequest {
+        wants,
+        haves: &common,
+        done: true,
+        ..Default::default()
+    };
+    match fetch_round(repo, repo_url, &request)? {
+        Negotiation::Pack(reader, shallow_info) => Ok((reader, shallow_info)),
+        Negotiation::Continue(_) => bail!("server did not send a pack despite done"),
+    }
+}
@@ -0,0 +1,189 @@
+//! Filters applied to the content of files between the worktree and the
+//! repository: "clean" when hashing them, "smudge" when checking them out.
+//!
+//! See gitattributes(5). End-of-line conversion is decided by the text and
+//! eol attributes of the file, and by core.autocrlf for files without them.
+//! The filter attribute names a driver, whose filter.`<name>`.clean and
+//! filter.`<name>`.smudge commands get the content on their standard input
+//! and give the converted content on their standard output (eg for Git LFS);
+//! `%f` in them is replaced by the path of the file. The long-running
+//! filter.`<name>`.process protocol is not supported. Like git, the driver
+//! runs before end-of-line conversion when cleaning, and after it when
+//! smudging. Files outside the worktree (eg written by extract) have no
+//! attributes.
+
+use anyhow::{bail, Result};
+use std::io::{self, Write};
+use std::path::{self, Path};
+use std::process::Command;
+
+use crate::attributes::{self, State};
+use crate::common::{run, sq_quote};
+use crate::config;
+use crate::crlf::{self, AutoCrlf, Eol};
+use crate::platform;
+use crate::repository::Repository;
+
+/// What to do with the line endings of a file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Conversion {
+    /// Leave the content alone
+    None,
+    /// If the content looks like text, LF in the repository, and these in the worktree
+    Auto(Eol),
+    /// The content is text: LF in the repository, and these in the worktree
+    Text(Eol),
+}
+
+/// Line endings of text files in the worktree, from the configuration.
+fn worktree_eol(repo: &Repository) -> Result<Eol> {
+    match crlf::autocrlf(repo)? {
+        AutoCrlf::True => Ok(Eol::Crlf),
+        AutoCrlf::Input => Ok(Eol::Lf),
+        AutoCrlf::False => crlf::core_eol(repo),
+    }
+}
+
+/// Give the path of a file relative to the root of the worktree, if inside.
+fn repo_path(repo: &Repository, file: &Path) -> Result<Option<Vec<u8>>> {
+    if repo.is_bare() {
+        return Ok(None);
+    }
+    let file = path::absolute(file)?;
+    let inside = file.strip_prefix(repo.work_tree()?).ok();
+    Ok(inside.map(|path| platform::bytes(path.as_os_str()).into_owned()))
+}
+
+/// Decide what to do with the line endings of a file.
+fn conversion(repo: &Repository, path: Option<&[u8]>) -> Result<Conversion> {
+    let (text, eol) = match path {
+        Some(path) => (
+            attributes::get(repo, path, "text")?,
+            attributes::get(repo, path, "eol")?,
+        ),
+        None => (None, None),
+    };
+    let eol = match eol.as_ref() {
+        Some(State::Value(eol)) if eol == "lf" => Some(Eol::Lf),
+        Some(State::Value(eol)) if eol == "crlf" => Some(Eol::Crlf),
+        _ => None,
+    };
+    let text = match text {
+        Some(State::Value(value)) if value != "auto" => None,
+        text => text,
+    };
+    Ok(match (text, eol) {
+        (Some(State::Unset), _) => Conversion::None,
+        (Some(State::Value(_)), Some(eol)) => Conversion::Auto(eol),
+        (Some(State::Value(_)), None) => Conversion::Auto(worktree_eol(repo)?),
+        // Setting eol implies text.
+        (Some(State::Set) | None, Some(eol)) => Conversion::Text(eol),
+        (Some(State::Set), None) => Conversion::Text(worktree_eol(repo)?),
+        (None, None) => match crlf::autocrlf(repo)? {
+            AutoCrlf::True => Conversion::Auto(Eol::Crlf),
+            AutoCrlf::Input => Conversion::Auto(Eol::Lf),
+            AutoCrlf::False => Conversion::None,
+        },
+    })
+}
+
+/// Find the name of the filter driver of a file, if any.
+fn driver(repo: &Repository, path: Option<&[u8]>) -> Result<Option<String>> {
+    match path {
+        Some(path) => match attributes::get(repo, path, "filter")? {
+            Some(State::Value(name)) => Ok(Some(name)),
+            _ => Ok(None),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Run the clean or smudge command of a filter driver on the content of a
+/// file. If the command is not configured or fails, the content is left
+/// alone, unless filter.`<name>`.required is set.
+fn run_driver(
+    repo: &Repository,
+    name: &str,
+    which: &str,
+    path: &[u8],
+    data: Vec<u8>,
+) -> Result<Vec<u8>> {
+    let display = String::from_utf8_lossy(path);
+    let required = config::get_bool(repo, &format!("filter.{name}.required"))?.unwrap_or(false);
+    let Some(command) = config::get(repo, &format!("filter.{name}.{which}"))? else {
+        if required {
+            bail!("{display}: {which} filter '{name}' is required but not configured");
+        }
+        return Ok(data);
+    };
+    let command = command.replace("%f", &sq_quote(&display));
+    let output = run(
+        Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .current_dir(repo.work_tree()?),
+        &data,
+    );
+    if let Ok(output) = &output {
+        let _ = io::stderr().write_all(&output.stderr);
+    }
+    match output {
+        Ok(output) if output.status.success() => return Ok(output.stdout),
+        Ok(output) => eprintln!(
+            "error: external filter '{command}' failed {}",
+            output.status
+        ),
+        Err(err) => eprintln!("error: external filter '{command}' failed: {err:#}"),
+    }
+    if required {
+        bail!("{display}: {which} filter '{name}' failed");
+    }
+    Ok(data)
+}
+
+/// Tell if clean() or smudge() may change the content of a file: if not,
+/// it can be read or written without having it all in memory.
+pub fn converts(repo: &Repository, file: &Path) -> Result<bool> {
+    let path = repo_path(repo, file)?;
+    Ok(conversion(repo, path.as_deref())? != Conversion::None
+        || driver(repo, path.as_deref())?.is_some())
+}
+
+/// Convert the content of a file from the worktree for the repository.
+/// Like git, files that look binary are left alone unless marked as text.
+pub fn clean(repo: &Repository, file: &Path, data: Vec<u8>) -> Result<Vec<u8>> {
+    let path = repo_path(repo, file)?;
+    let data = match (driver(repo, path.as_deref())?, &path) {
+        (Some(name), Some(path)) => run_driver(repo, &name, "clean", path, data)?,
+        _ => data,
+    };
+    let convert = match conversion(repo, path.as_deref())? {
+        Conversion::None => false,
+        Conversion::Auto(_) => !crlf::is_binary(&data),
+        Conversion::Text(_) => true,
+    };
+    match convert && data.windows(2).any(|w| w == b"\r\n") {
+        true => Ok(crlf::crlf_to_lf(&data)),
+        false => Ok(data),
+    }
+}
+
+/// Convert the content of a file from the repository for the worktree.
+/// Like git, files that look binary, or already have carriage returns,
+/// are left alone unless marked as text.
+pub fn smudge(repo: &Repository, file: &Path, data: Vec<u8>) -> Result<Vec<u8>> {
+    let path = repo_path(repo, file)?;
+    let convert = match conversion(repo, path.as_deref())? {
+        Conversion::Auto(Eol::Crlf) => !data.contains(&b'\r') && !crlf::is_binary(&data),
+        Conversion::Text(Eol::Crlf) => true,
+        _ => false,
+    };
+    let data = match convert && data.contains(&b'\n') {
+        true => crlf::lf_to_crlf(&data),
+        false => data,
+    };
+    match (driver(repo, path.as_deref())?, &path) {
+        (Some(name), Some(path)) => run_driver(repo, &name, "smudge", path, data),
+        _ => Ok(data),
+    }
+}
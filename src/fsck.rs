@@ -0,0 +1,272 @@
+//! Checking the object database, like git fsck: the integrity of each object
+//! (loose or packed), links between objects, and which objects are not
+//! reachable from references, their reflogs or the index. Like git, objects
+//! in alternates are checked too.
+//!
+//! Links from objects in promisor packs (from clone --filter) may point to
+//! missing objects, as those can be fetched later; so may the parents of
+//! shallow commits.
+
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::index;
+use crate::obj_read::{read_commit, read_tree};
+use crate::obj_type::ObjType;
+use crate::object_id::is_hash;
+use crate::pack_read;
+use crate::refs;
+use crate::repository::Repository;
+use crate::revwalk::tag_target;
+use crate::shallow::is_shallow;
+use crate::tree_entry::Mode;
+use crate::worktrees;
+
+/// What we know about an object in the database.
+struct Object {
+    /// Only known once the object has been read successfully.
+    obj_type: Option<ObjType>,
+    /// Is it in a promisor pack?
+    promisor: bool,
+    /// Objects it points to, with the type they should have.
+    links: Vec<(String, ObjType)>,
+}
+
+/// List loose objects in the repository (not in alternates).
+pub fn loose_objects(repo: &Repository) -> Result<Vec<String>> {
+    loose_objects_in(repo, repo.object_dir())
+}
+
+/// List loose objects in the given object directory.
+fn loose_objects_in(repo: &Repository, dir: &Path) -> Result<Vec<String>> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("listing {}", dir.display()))? {
+        let entry = entry.with_context(|| format!("listing {}", dir.display()))?;
+        let prefix = entry.file_name().to_string_lossy().into_owned();
+        if prefix.len() != 2 || !prefix.bytes().all(|c| c.is_ascii_hexdigit()) {
+            continue;
+        }
+        let subdir = entry.path();
+        for entry in
+            fs::read_dir(&subdir).with_context(|| format!("listing {}", subdir.display()))?
+        {
+            let entry = entry.with_context(|| format!("listing {}", subdir.display()))?;
+            let hash = format!("{prefix}{}", entry.file_name().to_string_lossy());
+            if is_hash(repo, &hash) {
+                found.push(hash);
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Check the checksum at the end of a pack.
+fn check_pack(repo: &Repository, path: &Path) -> Result<()> {
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let hash_len = repo.hash_algo()?.raw_len();
+    if len < hash_len as u64 {
+        bail!("truncated pack");
+    }
+    let mut hasher = repo.hash_algo()?.hasher();
+    io::copy(&mut (&mut file).take(len - hash_len as u64), &mut hasher)?;
+    let mut checksum = vec![0; hash_len];
+    file.read_exact(&mut checksum)?;
+    if hasher.finalize().as_ref() != checksum {
+        bail!("pack checksum mismatch");
+    }
+    Ok(())
+}
+
+/// Read an object, checking that its content matches its hash, and return
+/// its type and the objects it points to.
+fn check_object(repo: &Repository, hash: &str) -> Result<(ObjType, Vec<(String, ObjType)>)> {
+    let mut object = repo.read_object(hash)?;
+    let obj_type = object.obj_type.clone();
+    let mut hasher = repo.hash_algo()?.hasher();
+    hasher.update(format!("{} {}\0", obj_type.to_str(), object.size));
+    io::copy(&mut object, &mut hasher).context("reading content")?;
+    let actual = hasher.finalize().to_string();
+    if actual != hash {
+        bail!("hash mismatch: content hashes to {actual}");
+    }
+
+    let mut links = Vec::new();
+    match obj_type {
+        ObjType::Blob => (),
+        ObjType::Tree => {
+            for entry in read_tree(repo, hash)? {
+                let target_type = match entry.mode {
+                    Mode::Dir => ObjType::Tree,
+                    Mode::File | Mode::Exe | Mode::SymLink => ObjType::Blob,
+                    Mode::SubMod => continue,
+                };
+                links.push((hex::encode(entry.hash), target_type));
+            }
+        }
+        ObjType::Commit => {
+            let commit = read_commit(repo, hash)?;
+            links.push((commit.tree, ObjType::Tree));
+            if !is_shallow(repo, hash)? {
+                links.extend(commit.parents.into_iter().map(|p| (p, ObjType::Commit)));
+            }
+        }
+        ObjType::Tag => {
+            let mut object = repo.read_object(hash)?;
+            links.push(tag_target(&mut object)?);
+        }
+    }
+    Ok((obj_type, links))
+}
+
+/// Collect the objects reachability starts from: what references point to
+/// (including their previous values), and blobs in the index, those of
+/// other worktrees included. Each comes with a description of where it was
+/// found.
+pub fn roots(repo: &Repository) -> Result<Vec<(String, String)>> {
+    let mut names = vec!["HEAD".to_owned()];
+    let mut indexes = vec![repo.git_dir().join("index")];
+    for worktree in worktrees::list(repo)? {
+        if worktree.git_dir != *repo.git_dir() {
+            names.push(worktree.head);
+            indexes.push(worktree.git_dir.join("index"));
+        }
+    }
+    names.extend(refs::list(repo, "refs/")?.into_iter().map(|(name, _)| name));
+    let mut roots = Vec::new();
+    for name in names {
+        if let Some(hash) = refs::resolve(repo, &name)? {
+            roots.push((name.clone(), hash));
+        }
+        for entry in refs::read_reflog(repo, &name)? {
+            for hash in [entry.old, entry.new] {
+                if hash != repo.hash_algo()?.zero_hash() {
+                    roots.push((format!("reflog of {name}"), hash));
+                }
+            }
+        }
+    }
+    for path in indexes {
+        for entry in index::read_from(repo, &path)? {
+            if entry.mode != Mode::SubMod {
+                roots.push(("index".to_owned(), hex::encode(entry.hash)));
+            }
+        }
+    }
+    Ok(roots)
+}
+
+/// Check all objects, printing problems (to stderr), broken links, and
+/// dangling objects: unreachable ones that no other object points to, or,
+/// if `unreachable` is set, all unreachable objects.
+/// Return the number of problems found.
+pub fn check_all(repo: &Repository, unreachable: bool) -> Result<usize> {
+    let mut problems = 0;
+    let mut objects: BTreeMap<String, Object> = BTreeMap::new();
+    for dir in repo.object_dirs() {
+        for hash in loose_objects_in(repo, dir)? {
+            objects.insert(
+                hash,
+                Object {
+                    obj_type: None,
+                    promisor: false,
+                    links: Vec::new(),
+                },
+            );
+        }
+    }
+    for (pack, hashes) in pack_read::list_all(repo)? {
+        if let Err(e) = check_pack(repo, &pack) {
+            eprintln!("error: {}: {e:#}", pack.display());
+            problems += 1;
+        }
+        let promisor = pack.with_extension("promisor").exists();
+        for hash in hashes {
+            let object = objects.entry(hash).or_insert(Object {
+                obj_type: None,
+                promisor,
+                links: Vec::new(),
+            });
+            object.promisor |= promisor;
+        }
+    }
+
+    for (hash, object) in objects.iter_mut() {
+        match check_object(repo, hash) {
+            Ok((obj_type, links)) => {
+                object.obj_type = Some(obj_type);
+                object.links = links;
+            }
+            Err(e) => {
+                eprintln!("error: {hash}: {e:#}");
+                problems += 1;
+            }
+        }
+    }
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let mut referenced = HashSet::new();
+    for (hash, object) in &objects {
+        let Some(obj_type) = &object.obj_type else {
+            continue;
+        };
+        for (target, target_type) in &object.links {
+            referenced.insert(target.as_str());
+            let actual_type = match objects.get(target) {
+                Some(target) => target.obj_type.as_ref(),
+                None if object.promisor => continue,
+                None => {
+                    writeln!(out, "broken link from {:>7} {hash}", obj_type.to_str())?;
+                    writeln!(out, "              to {:>7} {target}", target_type.to_str())?;
+                    problems += 1;
+                    continue;
+                }
+            };
+            if let Some(actual_type) = actual_type.filter(|&t| t != target_type) {
+                eprintln!(
+                    "error: {hash}: points to {target} as a {}, but it is a {}",
+                    target_type.to_str(),
+                    actual_type.to_str()
+                );
+                problems += 1;
+            }
+        }
+    }
+
+    let mut reachable = HashSet::new();
+    let mut queue = Vec::new();
+    for (source, hash) in roots(repo)? {
+        if objects.contains_key(&hash) {
+            queue.push(hash);
+        } else {
+            eprintln!("error: {source}: points to missing object {hash}");
+            problems += 1;
+        }
+    }
+    while let Some(hash) = queue.pop() {
+        if let Some(object) = objects.get(&hash) {
+            if reachable.insert(hash) {
+                queue.extend(object.links.iter().map(|(target, _)| target.clone()));
+            }
+        }
+    }
+    for (hash, object) in &objects {
+        let Some(obj_type) = &object.obj_type else {
+            continue;
+        };
+        if reachable.contains(hash) {
+            continue;
+        }
+        if unreachable {
+            writeln!(out, "unreachable {} {hash}", obj_type.to_str())?;
+        } else if !referenced.contains(hash.as_str()) {
+            writeln!(out, "dangling {} {hash}", obj_type.to_str())?;
+        }
+    }
+    out.flush()?;
+    Ok(problems)
+}
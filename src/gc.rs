@@ -0,0 +1,180 @@
+//! Repacking the object database, like git gc: all reachable objects are
+//! written to a single new pack, then loose objects and old packs are removed.
+//!
+//! Like git, unreachable objects from old packs are kept as loose objects
+//! (with the modification time of their pack), and unreachable loose objects
+//! are only deleted once older than a grace period, so that objects just
+//! created by another command are not removed before being referenced.
+//! Packs with a .keep file are left alone, and so are objects in alternates.
+//! In bare repositories, or with repack.writeBitmaps, a reachability bitmap
+//! is written for the new pack. Partial clones are not supported: objects in
+//! promisor packs may point to objects we don't have.
+//!
+//! Prune only does the last part: finding unreachable loose objects.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::bitmap;
+use crate::count::PACK_EXTENSIONS;
+use crate::fsck::{loose_objects, roots};
+use crate::interrupt;
+use crate::obj_write::ObjWriter;
+use crate::pack_index::store_pack;
+use crate::pack_read;
+use crate::pack_write::write_pack;
+use crate::refs;
+use crate::repository::Repository;
+use crate::revwalk::reachable_hashes;
+
+/// Modification time of a file, in seconds since the epoch.
+fn mtime(path: &Path) -> Result<i64> {
+    let modified = fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .with_context(|| format!("reading {}", path.display()))?;
+    Ok(match modified.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(_) => 0,
+    })
+}
+
+/// Is something last modified at `time` old enough to be deleted?
+fn is_expired(time: i64, expire: Option<i64>) -> bool {
+    expire.is_some_and(|expire| time <= expire)
+}
+
+/// Write a packed object as a loose object, with the given modification time.
+fn loosen(repo: &Repository, hash: &str, time: i64) -> Result<()> {
+    let mut object = repo.read_object(hash)?;
+    let mut writer = ObjWriter::new(repo, object.obj_type.clone(), object.size, true)?;
+    io::copy(&mut object, &mut writer).context("copying object")?;
+    writer.finish_loose()?;
+    let path = repo.loose_path(hash);
+    let time = UNIX_EPOCH + Duration::from_secs(time.max(0) as u64);
+    fs::File::open(&path)
+        .and_then(|file| file.set_modified(time))
+        .with_context(|| format!("setting modification time of {}", path.display()))
+}
+
+/// Remove a pack and the files that come with it, the index first so that
+/// readers never see an index without its pack.
+fn remove_pack(repo: &Repository, pack: &Path) -> Result<()> {
+    pack_read::forget(repo, pack);
+    for path in [pack.with_extension("idx"), pack.to_owned()] {
+        fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))?;
+    }
+    for ext in PACK_EXTENSIONS {
+        let _ = fs::remove_file(pack.with_extension(ext));
+    }
+    Ok(())
+}
+
+/// Remove a loose object, and its directory if that leaves it empty.
+pub fn remove_loose(repo: &Repository, hash: &str) -> Result<()> {
+    let path = repo.loose_path(hash);
+    fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))?;
+    let _ = fs::remove_dir(path.parent().expect("object path has a parent"));
+    Ok(())
+}
+
+/// Get the objects reachable from references, their reflogs and the index.
+fn reachable(repo: &Repository) -> Result<Vec<String>> {
+    let mut tips: Vec<String> = roots(repo)?.into_iter().map(|(_, hash)| hash).collect();
+    tips.sort_unstable();
+    tips.dedup();
+    reachable_hashes(repo, &tips, &[])
+}
+
+/// List unreachable loose objects last modified at or before `expire`
+/// (seconds since the epoch).
+pub fn prunable(repo: &Repository, expire: i64) -> Result<Vec<String>> {
+    let reachable: HashSet<String> = reachable(repo)?.into_iter().collect();
+    let mut found = Vec::new();
+    for hash in loose_objects(repo)? {
+        if !reachable.contains(&hash) && mtime(&repo.loose_path(&hash))? <= expire {
+            found.push(hash);
+        }
+    }
+    found.sort_unstable();
+    Ok(found)
+}
+
+/// Repack reachable objects and remove redundant ones. Unreachable loose
+/// objects last modified at or before `expire` (seconds since the epoch)
+/// are deleted; with None, they are all kept.
+pub fn repack(repo: &Repository, expire: Option<i64>) -> Result<()> {
+    let packs = pack_read::list(repo)?;
+    let mut kept = HashSet::new();
+    let mut old_packs = Vec::new();
+    // Like git, objects from alternates are not copied to the repository.
+    let mut local: HashSet<String> = loose_objects(repo)?.into_iter().collect();
+    for (pack, hashes) in packs {
+        if pack.with_extension("promisor").exists() {
+            bail!("gc is not supported in partial clones");
+        }
+        if pack.with_extension("keep").exists() {
+            kept.extend(hashes);
+        } else if pack.exists() {
+            local.extend(hashes.iter().cloned());
+            old_packs.push((pack, hashes));
+        }
+    }
+
+    let hashes: Vec<String> = reachable(repo)?
+        .into_iter()
+        .filter(|hash| !kept.contains(hash) && local.contains(hash))
+        .collect();
+
+    let mut new_pack = None;
+    if !hashes.is_empty() {
+        let mut pack = Vec::new();
+        write_pack(repo, &mut pack, &hashes)?;
+        let (name, _) = store_pack(repo, &pack[..]).context("storing new pack")?;
+        new_pack = Some(repo.object_dir().join(format!("pack/pack-{name}.pack")));
+    }
+    let packed: HashSet<String> = hashes.into_iter().chain(kept).collect();
+
+    for (pack, hashes) in old_packs {
+        if Some(&pack) == new_pack.as_ref() {
+            continue;
+        }
+        let time = mtime(&pack)?;
+        if !is_expired(time, expire) {
+            for hash in hashes {
+                interrupt::check()?;
+                if !packed.contains(&hash) && !repo.loose_path(&hash).exists() {
+                    loosen(repo, &hash, time).with_context(|| format!("unpacking {hash}"))?;
+                }
+            }
+        }
+        remove_pack(repo, &pack)?;
+    }
+
+    for hash in loose_objects(repo)? {
+        interrupt::check()?;
+        if packed.contains(&hash) || is_expired(mtime(&repo.loose_path(&hash))?, expire) {
+            remove_loose(repo, &hash)?;
+        }
+    }
+
+    match new_pack {
+        Some(pack) if bitmap::enabled(repo)? => {
+            let mut tips: Vec<String> = refs::list(repo, "refs/")?
+                .into_iter()
+                .map(|(_, hash)| hash)
+                .collect();
+            tips.extend(refs::resolve(repo, "HEAD")?);
+            if !bitmap::write(repo, &pack, &tips).context("writing bitmap")? {
+                eprintln!(
+                    "warning: disabling bitmap writing, as some objects are not being packed"
+                );
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! Searching file contents for a fixed string, like git grep -F -n.
+
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::io::{self, prelude::*, BufReader};
+use std::path::PathBuf;
+use std::thread;
+
+use crate::platform;
+use crate::repository::Repository;
+
+/// Like git, files with a NUL byte in their beginning are treated as binary.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// Where the content of a searched file is.
+pub enum Source {
+    /// A file (or symlink) in the worktree
+    File(PathBuf),
+    /// A blob in the object store
+    Blob(String),
+}
+
+/// Tell if a line contains the pattern (the empty pattern matches all lines).
+fn contains(line: &[u8], pattern: &[u8]) -> bool {
+    pattern.is_empty() || line.windows(pattern.len()).any(|w| w == pattern)
+}
+
+/// Search one file, returning what to print for it.
+fn grep_one(repo: &Repository, pattern: &[u8], name: &[u8], source: &Source) -> Result<Vec<u8>> {
+    let reader: Box<dyn Read> = match source {
+        Source::File(path) => {
+            let meta = fs::symlink_metadata(path)?;
+            if meta.is_symlink() {
+                let target = fs::read_link(path)?;
+                Box::new(io::Cursor::new(
+                    platform::bytes(target.as_os_str()).into_owned(),
+                ))
+            } else {
+                Box::new(fs::File::open(path)?)
+            }
+        }
+        Source::Blob(hash) => Box::new(repo.read_object(hash)?),
+    };
+    let mut reader = BufReader::new(reader);
+    let mut out = Vec::new();
+    let (mut number, mut offset, mut binary, mut matched) = (0, 0, false, false);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        number += 1;
+        if offset < BINARY_CHECK_LEN {
+            let checked = line.len().min(BINARY_CHECK_LEN - offset);
+            binary |= line[..checked].contains(&0);
+            offset += checked;
+        }
+        if !contains(&line, pattern) {
+            continue;
+        }
+        matched = true;
+        if binary {
+            // Binary files are reported as a whole: no need to read further.
+            break;
+        }
+        out.extend_from_slice(name);
+        write!(out, ":{number}:")?;
+        out.extend_from_slice(&line);
+        if !line.ends_with(b"\n") {
+            out.push(b'\n');
+        }
+    }
+    if binary && matched {
+        out.clear();
+        out.extend_from_slice(b"Binary file ");
+        out.extend_from_slice(name);
+        out.extend_from_slice(b" matches\n");
+    }
+    Ok(out)
+}
+
+/// Search files for lines containing `pattern`, printing them as `name:line:text`
+/// in the order of the files. Return whether some line matched.
+///
+/// Files are searched by `threads` threads, using all processors if 0.
+pub fn grep(
+    repo: &Repository,
+    pattern: &[u8],
+    files: &[(Vec<u8>, Source)],
+    threads: usize,
+    out: &mut impl Write,
+) -> Result<bool> {
+    let threads = match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let chunk_size = files.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = files
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || -> Result<Vec<Vec<u8>>> {
+                    chunk
+                        .iter()
+                        .map(|(name, source)| {
+                            grep_one(repo, pattern, name, source).with_context(|| {
+                                format!("searching {}", String::from_utf8_lossy(name))
+                            })
+                        })
+                        .collect()
+                })
+            })
+            .collect();
+        let mut found = false;
+        for worker in workers {
+            let results = worker
+                .join()
+                .map_err(|_| anyhow!("search thread panicked"))??;
+            for result in results {
+                found |= !result.is_empty();
+                out.write_all(&result)?;
+            }
+        }
+        Ok(found)
+    })
+}
@@ -0,0 +1,67 @@
+//! Running hooks, see githooks(5).
+//!
+//! Hooks are looked up in core.hooksPath if set, otherwise .git/hooks.
+//! Setting GIT_HOOKS_DISABLE in the environment disables all hooks.
+
+use anyhow::{bail, Result};
+use std::env;
+use std::io;
+use std::io::prelude::*;
+use std::process::Command;
+
+use crate::common;
+use crate::config;
+use crate::platform;
+use crate::repository::Repository;
+
+/// Run the given hook, if it exists and is executable, with the given arguments.
+///
+/// The hook's output is captured, then forwarded to stderr if it succeeds,
+/// or included in the error otherwise, so that failures are actionable.
+pub fn run(repo: &Repository, name: &str, args: &[&str]) -> Result<()> {
+    run_with_input(repo, name, args, b"")
+}
+
+/// Same as run(), but also feed the given data to the hook's standard input.
+pub fn run_with_input(repo: &Repository, name: &str, args: &[&str], input: &[u8]) -> Result<()> {
+    if env::var_os("GIT_HOOKS_DISABLE").is_some() {
+        return Ok(());
+    }
+
+    let git_dir = repo.git_dir();
+    // Hooks run from the root of the worktree, or the repository if bare.
+    let root = if repo.is_bare() {
+        git_dir.as_path()
+    } else {
+        repo.work_tree()?
+    };
+    let hooks_dir = match config::get(repo, "core.hooksPath")? {
+        Some(dir) => root.join(dir),
+        None => repo.common_dir().join("hooks"),
+    };
+    let path = hooks_dir.join(name);
+    let Ok(meta) = path.metadata() else {
+        return Ok(());
+    };
+    // Elsewhere than on unix, any file is run, like git for Windows does.
+    if !meta.is_file() || cfg!(unix) && !platform::is_executable(&meta) {
+        return Ok(());
+    }
+
+    let output = common::run(Command::new(&path).args(args).current_dir(root), input)?;
+
+    if !output.status.success() {
+        let mut msg = format!("hook {name} failed ({})", output.status);
+        for (label, out) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            if !out.is_empty() {
+                msg += &format!("\n--- {label} ---\n{}", String::from_utf8_lossy(out));
+            }
+        }
+        bail!(msg);
+    }
+
+    let mut stderr = io::stderr().lock();
+    stderr.write_all(&output.stdout)?;
+    stderr.write_all(&output.stderr)?;
+    Ok(())
+}
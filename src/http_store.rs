@@ -0,0 +1,73 @@
+//! Experimental: fetching missing objects on demand from an HTTP object store.
+//!
+//! If core.remoteAlternate is set to a base URL, objects that are not found
+//! locally are downloaded from BASE/objects/xx/yyyy... (the layout used by the
+//! dumb HTTP protocol), checked, and only then cached as loose objects.
+//! Commands that only read cache them too, unless the repository was opened
+//! read-only, which they only do when it is not writable (see
+//! Repository::discover_for_reading()).
+//! Only loose objects are looked up on the server, not packs.
+
+use anyhow::{bail, Context, Result};
+use flate2::read::ZlibDecoder;
+use std::io::prelude::*;
+
+use crate::network::get_loose_object;
+use crate::obj_type::ObjType;
+use crate::object_id::Hasher;
+use crate::repository::Repository;
+
+/// Try to fetch an object from the configured store, caching it locally if possible.
+/// Return its type and content, or None if no store is configured or it doesn't have it.
+pub fn fetch(repo: &Repository, hash: &str) -> Result<Option<(ObjType, Vec<u8>)>> {
+    if hash.len() != repo.hash_algo()?.hex_len() || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    let Some(base_url) = repo.config("core.remoteAlternate")? else {
+        return Ok(None);
+    };
+    fetch_from(repo, &base_url, hash)
+}
+
+/// Same as fetch(), from the given base URL, eg that of a dumb HTTP server.
+pub fn fetch_from(
+    repo: &Repository,
+    base_url: &str,
+    hash: &str,
+) -> Result<Option<(ObjType, Vec<u8>)>> {
+    let Some(data) = get_loose_object(base_url, hash)? else {
+        return Ok(None);
+    };
+
+    // Object format: <type> <size>\0<content>, all zlib-compressed
+    let mut raw = Vec::new();
+    ZlibDecoder::new(&data[..])
+        .read_to_end(&mut raw)
+        .with_context(|| format!("decompressing object {hash} from {base_url}"))?;
+    let Some(nul) = raw.iter().position(|&b| b == 0) else {
+        bail!("invalid object {hash} from {base_url}: no header");
+    };
+    let header = std::str::from_utf8(&raw[..nul]).context("non-UTF-8 object header")?;
+    let Some((obj_type, size)) = header.split_once(' ') else {
+        bail!("invalid object {hash} from {base_url}: bad header");
+    };
+    let obj_type = ObjType::from_bytes(obj_type.as_bytes())?;
+    if size != (raw.len() - nul - 1).to_string() {
+        bail!("invalid object {hash} from {base_url}: size mismatch");
+    }
+
+    // Never trust the server's data blindly: check it before caching it.
+    let got = Hasher::digest(repo.hash_algo()?, &raw).to_string();
+    if got != hash {
+        bail!("object from {base_url} has the wrong hash: expected {hash}, got {got}");
+    }
+    let content = raw.split_off(nul + 1);
+    if !repo.is_read_only() {
+        let mut writer = repo
+            .write_object(obj_type.clone(), content.len())
+            .context("caching object")?;
+        writer.write_all(&content).context("caching object")?;
+        writer.finish().context("caching object")?;
+    }
+    Ok(Some((obj_type, content)))
+}
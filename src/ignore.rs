@@ -0,0 +1,247 @@
+//! Ignore rules from .gitignore files and .git/info/exclude.
+//!
+//! See gitignore(5). Patterns from core.excludesFile are not supported.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::platform;
+use crate::repository::Repository;
+
+/// One line of an ignore file.
+struct Pattern {
+    /// The file the pattern comes from, as shown by check-ignore
+    source: String,
+    /// Line number of the pattern in that file, starting at 1
+    line: usize,
+    /// The line as written, without trailing whitespace
+    text: Vec<u8>,
+    /// Directory of the file the pattern comes from, relative to the root
+    /// of the worktree and with a trailing '/' (empty for the root)
+    base: Vec<u8>,
+    pattern: Vec<u8>,
+    /// Pattern starting with '!': matching paths are not ignored after all
+    negated: bool,
+    /// Pattern ending with '/': only matches directories
+    dir_only: bool,
+    /// Pattern containing a '/': matches the full path relative to base,
+    /// rather than just the name of the file
+    anchored: bool,
+}
+
+impl Pattern {
+    /// Parse a line from an ignore file, returning None for blank lines and comments.
+    fn parse(line: &[u8], base: &[u8], source: &str, number: usize) -> Option<Self> {
+        let text = line.trim_ascii_end();
+        let mut line = text;
+        if line.is_empty() || line[0] == b'#' {
+            return None;
+        }
+        let negated = line[0] == b'!';
+        // skip the '!', or the '\' in "\#" or "\!"
+        if negated || line.starts_with(b"\\#") || line.starts_with(b"\\!") {
+            line = &line[1..];
+        }
+        let dir_only = line.ends_with(b"/");
+        if dir_only {
+            line = &line[..line.len() - 1];
+        }
+        let anchored = line.contains(&b'/');
+        let line = line.strip_prefix(b"/").unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+        Some(Pattern {
+            source: source.to_owned(),
+            line: number,
+            text: text.to_vec(),
+            base: base.to_vec(),
+            pattern: line.to_vec(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    /// Describe the pattern for check-ignore.
+    fn to_match(&self) -> Match {
+        Match {
+            source: self.source.clone(),
+            line: self.line,
+            pattern: self.text.clone(),
+            negated: self.negated,
+        }
+    }
+
+    /// Tell if the pattern matches a path relative to the root of the worktree.
+    fn matches(&self, path: &[u8], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Some(path) = path.strip_prefix(&self.base[..]) else {
+            return false;
+        };
+        if self.anchored {
+            glob(&self.pattern, path)
+        } else {
+            let name = path.rsplit(|&c| c == b'/').next().unwrap_or(path);
+            glob(&self.pattern, name)
+        }
+    }
+}
+
+/// Match text against a gitignore-style pattern: '*' and '?' don't match '/',
+/// "**" does, and [...] matches a set of bytes.
+pub fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            // zero or more directories
+            glob(rest, text)
+                || (0..text.len()).any(|i| text[i] == b'/' && glob(rest, &text[i + 1..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| glob(rest, &text[i..])),
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != b'/')
+            .any(|i| glob(rest, &text[i..])),
+        [b'?', rest @ ..] => {
+            matches!(text.first(), Some(&c) if c != b'/') && glob(rest, &text[1..])
+        }
+        [b'[', class @ ..] => match match_class(class, text.first().copied()) {
+            Some((matched, rest)) => matched && glob(rest, &text[1..]),
+            // no closing bracket: literal '['
+            None => text.first() == Some(&b'[') && glob(class, &text[1..]),
+        },
+        [b'\\', c, rest @ ..] | [c, rest @ ..] => text.first() == Some(c) && glob(rest, &text[1..]),
+    }
+}
+
+/// Match a byte against a bracket expression (after the opening '['),
+/// returning whether it matched and the rest of the pattern,
+/// or None if the expression is not terminated.
+fn match_class(class: &[u8], byte: Option<u8>) -> Option<(bool, &[u8])> {
+    let (negated, class) = match class {
+        [b'!' | b'^', rest @ ..] => (true, rest),
+        _ => (false, class),
+    };
+    // A ']' right at the start is part of the set.
+    let end = 1 + class.get(1..)?.iter().position(|&c| c == b']')?;
+    let (set, rest) = (&class[..end], &class[end + 1..]);
+    let Some(byte) = byte.filter(|&c| c != b'/') else {
+        return Some((false, rest));
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == b'-' {
+            found |= (set[i]..=set[i + 2]).contains(&byte);
+            i += 3;
+        } else {
+            found |= set[i] == byte;
+            i += 1;
+        }
+    }
+    Some((found != negated, rest))
+}
+
+/// The pattern deciding whether a path is ignored.
+pub struct Match {
+    /// The file the pattern comes from: a .gitignore file relative to the
+    /// root of the worktree, or the path of .git/info/exclude
+    pub source: String,
+    /// Line number of the pattern in that file, starting at 1
+    pub line: usize,
+    /// The pattern as written in the file
+    pub pattern: Vec<u8>,
+    /// The pattern starts with '!': the path is not ignored after all
+    pub negated: bool,
+}
+
+/// The ignore rules that apply at some point while walking the worktree.
+pub struct Ignores {
+    patterns: Vec<Pattern>,
+}
+
+impl Ignores {
+    /// Start with the rules from .git/info/exclude.
+    pub fn new(repo: &Repository) -> Result<Self> {
+        let mut ignores = Ignores {
+            patterns: Vec::new(),
+        };
+        let exclude = repo.common_dir().join("info/exclude");
+        // Shown relative to the root of the worktree when inside, like git.
+        let source = match repo.work_tree() {
+            Ok(root) => exclude.strip_prefix(root).unwrap_or(&exclude),
+            Err(_) => &exclude,
+        };
+        ignores.load(&exclude, b"", &source.to_string_lossy())?;
+        Ok(ignores)
+    }
+
+    /// Add patterns from a file if it exists; base and source are as in Pattern.
+    fn load(&mut self, file: &Path, base: &[u8], source: &str) -> Result<()> {
+        let content = match fs::read(file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", file.display())),
+        };
+        self.patterns.extend(
+            content
+                .split(|&c| c == b'\n')
+                .enumerate()
+                .filter_map(|(i, line)| Pattern::parse(line, base, source, i + 1)),
+        );
+        Ok(())
+    }
+
+    /// Add the rules from the .gitignore file in a directory, `rel` being
+    /// its path relative to the root with a trailing '/' (empty for the root).
+    /// Return a mark to pass to leave_dir() when done with this directory.
+    pub fn enter_dir(&mut self, dir: &Path, rel: &[u8]) -> Result<usize> {
+        let mark = self.patterns.len();
+        let source = format!("{}.gitignore", String::from_utf8_lossy(rel));
+        self.load(&dir.join(".gitignore"), rel, &source)?;
+        Ok(mark)
+    }
+
+    /// Drop the rules added by the matching enter_dir().
+    pub fn leave_dir(&mut self, mark: usize) {
+        self.patterns.truncate(mark);
+    }
+
+    /// Tell if a path relative to the root of the worktree is ignored:
+    /// the last matching pattern decides.
+    pub fn is_ignored(&self, path: &[u8], is_dir: bool) -> bool {
+        self.last_match(path, is_dir)
+            .is_some_and(|pattern| !pattern.negated)
+    }
+
+    /// Find the last pattern matching a path relative to the root of the worktree.
+    fn last_match(&self, path: &[u8], is_dir: bool) -> Option<&Pattern> {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_dir))
+    }
+}
+
+/// Find the pattern deciding whether a path relative to the root of the
+/// worktree is ignored, like git check-ignore: the .gitignore files of all
+/// its leading directories apply, and the path is ignored by the pattern
+/// ignoring one of these directories if there is one.
+pub fn check(repo: &Repository, root: &Path, path: &[u8], is_dir: bool) -> Result<Option<Match>> {
+    let mut ignores = Ignores::new(repo)?;
+    ignores.enter_dir(root, b"")?;
+    let slashes = path.iter().enumerate().filter(|&(_, &c)| c == b'/');
+    for (end, _) in slashes {
+        let dir = &path[..end];
+        if ignores.is_ignored(dir, true) {
+            return Ok(ignores.last_match(dir, true).map(Pattern::to_match));
+        }
+        let rel = [dir, b"/"].concat();
+        ignores.enter_dir(&platform::join(root, dir), &rel)?;
+    }
+    Ok(ignores.last_match(path, is_dir).map(Pattern::to_match))
+}
@@ -0,0 +1,197 @@
+//! Reading and writing the index (staging area), compatible with git.
+//!
+//! See gitformat-index(5). Only versions 2 and 3 are supported,
+//! and extensions are ignored. We write version 2 without extensions,
+//! or version 3 if some entries have the skip-worktree flag.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::diff::Side;
+use crate::lock::LockFile;
+use crate::object_id::{Hasher, ObjectId};
+use crate::platform;
+use crate::repository::Repository;
+use crate::tree_entry::Mode;
+
+/// An entry in the index.
+pub struct IndexEntry {
+    /// Path relative to the root of the worktree
+    pub path: Vec<u8>,
+    pub mode: Mode,
+    pub hash: ObjectId,
+    /// 0 normally, 1-3 for the base, ours and theirs versions of a conflicted path
+    pub stage: u8,
+    /// File system data as read from the index: ctime, mtime, dev, ino, uid,
+    /// gid, size. None for new entries, see write().
+    pub stat: Option<[u32; 9]>,
+    /// The path is not checked out (sparse-checkout): it is assumed to match
+    /// the index whatever the worktree has
+    pub skip_worktree: bool,
+}
+
+/// Flag of entries with extended flags (version 3).
+const FLAG_EXTENDED: u16 = 0x4000;
+
+/// Extended flag of entries not checked out.
+const FLAG_SKIP_WORKTREE: u16 = 0x4000;
+
+impl IndexEntry {
+    /// Create an entry for a path with the given content.
+    pub fn new(path: &[u8], side: &Side, stage: u8) -> Result<Self> {
+        Ok(IndexEntry {
+            path: path.to_vec(),
+            mode: side.mode.clone(),
+            hash: ObjectId::from_hex(&side.hash)?,
+            stage,
+            stat: None,
+            skip_worktree: false,
+        })
+    }
+}
+
+/// Read a 32-bit big-endian number.
+fn be32(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(data[pos..pos + 4].try_into().expect("slice size is 4"))
+}
+
+/// Read the index, returning its entries sorted by path then stage.
+/// A missing index is the same as an empty one.
+pub fn read(repo: &Repository) -> Result<Vec<IndexEntry>> {
+    read_from(repo, &repo.git_dir().join("index"))
+}
+
+/// Read an index file, eg the one of another worktree.
+pub fn read_from(repo: &Repository, path: &Path) -> Result<Vec<IndexEntry>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    parse(repo, &data).with_context(|| format!("parsing {}", path.display()))
+}
+
+/// Parse the content of an index file.
+fn parse(repo: &Repository, data: &[u8]) -> Result<Vec<IndexEntry>> {
+    // 12-byte header: "DIRC", version, number of entries;
+    // entries, extensions, then the hash of everything before.
+    let algo = repo.hash_algo()?;
+    let hash_len = algo.raw_len();
+    if data.len() < 12 + hash_len || &data[..4] != b"DIRC" {
+        bail!("not an index file");
+    }
+    let (body, checksum) = data.split_at(data.len() - hash_len);
+    if Hasher::digest(algo, body).as_ref() != checksum {
+        bail!("index checksum mismatch");
+    }
+    let version = be32(body, 4);
+    if version != 2 && version != 3 {
+        bail!("unsupported index version {version}");
+    }
+    let nb_entries = be32(body, 8);
+
+    let mut entries = Vec::new();
+    let mut pos = 12;
+    for _ in 0..nb_entries {
+        // ctime, mtime, dev, ino (4 bytes each except times: 8),
+        // mode, uid, gid, size, hash, flags
+        let start = pos;
+        let flags_pos = pos + 40 + hash_len;
+        if body.len() < flags_pos + 2 {
+            bail!("truncated index entry");
+        }
+        let mode = be32(body, pos + 24);
+        let mut stat = [0; 9];
+        for (i, value) in stat.iter_mut().enumerate() {
+            // skip mode
+            *value = be32(body, pos + 4 * (i + usize::from(i >= 6)));
+        }
+        let hash = ObjectId::from_bytes(&body[pos + 40..flags_pos])?;
+        let flags = u16::from_be_bytes([body[flags_pos], body[flags_pos + 1]]);
+        pos = flags_pos + 2;
+        let mut extended = 0;
+        if flags & FLAG_EXTENDED != 0 {
+            // extended flags (version 3)
+            if body.len() < pos + 2 {
+                bail!("truncated index entry");
+            }
+            extended = u16::from_be_bytes([body[pos], body[pos + 1]]);
+            pos += 2;
+        }
+        let Some(len) = body[pos.min(body.len())..].iter().position(|&b| b == 0) else {
+            bail!("unterminated path in index entry");
+        };
+        let path = body[pos..pos + len].to_vec();
+        pos += len;
+        // 1-8 NUL bytes so that the entry size is a multiple of 8
+        pos = start + (pos - start + 8) / 8 * 8;
+
+        let mode = Mode::from_u32(mode)
+            .with_context(|| format!("entry {}", String::from_utf8_lossy(&path)))?;
+        entries.push(IndexEntry {
+            path,
+            mode,
+            hash,
+            stage: ((flags >> 12) & 3) as u8,
+            stat: Some(stat),
+            skip_worktree: extended & FLAG_SKIP_WORKTREE != 0,
+        });
+    }
+    Ok(entries)
+}
+
+/// Write the index with the given entries, sorted by path then stage,
+/// using the lock from lock_worktree().
+///
+/// For new stage 0 entries, file system data (times, size...) is taken from
+/// the worktree, so git knows those files match the index without reading them.
+/// Entries read from the index keep theirs, as their files may have changed.
+pub fn write(repo: &Repository, lock: LockFile, entries: &[IndexEntry]) -> Result<()> {
+    let algo = repo.hash_algo()?;
+    let root = repo.work_tree()?;
+    let version: u32 = match entries.iter().any(|e| e.skip_worktree) {
+        true => 3,
+        false => 2,
+    };
+    let mut out = Vec::new();
+    out.extend_from_slice(b"DIRC");
+    out.extend_from_slice(&version.to_be_bytes());
+    out.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    for entry in entries {
+        let stat = entry.stat.unwrap_or_else(|| {
+            let meta = match entry.stage {
+                0 => fs::symlink_metadata(platform::join(root, &entry.path)).ok(),
+                _ => None,
+            };
+            meta.map_or([0; 9], |meta| platform::index_stat(&meta))
+        });
+        // ctime, mtime, dev, ino, then mode, uid, gid, size
+        for value in &stat[..6] {
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+        out.extend_from_slice(&entry.mode.to_u32().to_be_bytes());
+        for value in &stat[6..] {
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+        out.extend_from_slice(entry.hash.as_ref());
+        let mut flags = (u16::from(entry.stage) << 12) | entry.path.len().min(0xfff) as u16;
+        let mut flags_len = 2;
+        if entry.skip_worktree {
+            flags |= FLAG_EXTENDED;
+            flags_len += 2;
+        }
+        out.extend_from_slice(&flags.to_be_bytes());
+        if entry.skip_worktree {
+            out.extend_from_slice(&FLAG_SKIP_WORKTREE.to_be_bytes());
+        }
+        out.extend_from_slice(&entry.path);
+        // 1-8 NUL bytes so that the entry size is a multiple of 8
+        let padding = 8 - (40 + algo.raw_len() + flags_len + entry.path.len()) % 8;
+        out.extend_from_slice(&[0; 8][..padding]);
+    }
+    let checksum = Hasher::digest(algo, &out);
+    out.extend_from_slice(checksum.as_ref());
+    lock.commit(&out).context("writing .git/index")
+}
@@ -0,0 +1,53 @@
+//! Handling of SIGINT (Ctrl-C).
+//!
+//! The signal handler only records the interruption; long-running operations
+//! call check() at points where stopping is safe, and return an error which
+//! unwinds normally, so in-flight writers can remove their temporary files.
+//! A second SIGINT exits immediately, in case we're stuck waiting for the network.
+//!
+//! The handler is only installed by commands which download or unpack many
+//! objects (clone, fetch and unpack-objects): others are killed by SIGINT as
+//! usual.
+
+use anyhow::{bail, Result};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigint(_: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        // SAFETY: _exit() is async-signal-safe.
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Install the SIGINT handler, see the module documentation for which commands.
+pub fn install() {
+    let handler = on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only uses async-signal-safe operations.
+    unsafe { libc::signal(libc::SIGINT, handler) };
+}
+
+/// Has SIGINT been received?
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Fail if SIGINT has been received.
+pub fn check() -> Result<()> {
+    if interrupted() {
+        bail!("interrupted");
+    }
+    Ok(())
+}
+
+/// Same as check(), for use in io::Read and io::Write implementations.
+///
+/// Note: ErrorKind::Interrupted can't be used as read_exact() etc. retry on it.
+pub fn check_io() -> io::Result<()> {
+    if interrupted() {
+        return Err(io::Error::other("interrupted"));
+    }
+    Ok(())
+}
@@ -0,0 +1,80 @@
+//! Output as JSON records, for --format=json: each record is an object on a
+//! line of its own (JSON Lines), so that output can be read as it comes.
+//! Strings that are not valid UTF-8, like some paths, are written with the
+//! replacement character in place of invalid bytes.
+//!
+//! This is supported by ls-tree, ls-remote, cat-file --batch-check and
+//! status --porcelain=v2 (whose fields have the names of git's documentation),
+//! with the fields of their text output, and rejected by other commands. There
+//! is no log command to support it (rev-list lists commits, as text only).
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by --format=json.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable JSON output.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Tell if commands should output JSON records rather than text.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// The value of a field of a record.
+pub enum Value<'a> {
+    String(&'a [u8]),
+    Number(u64),
+    Bool(bool),
+}
+
+impl<'a> From<&'a str> for Value<'a> {
+    fn from(s: &'a str) -> Self {
+        Value::String(s.as_bytes())
+    }
+}
+
+impl<'a> From<&'a [u8]> for Value<'a> {
+    fn from(s: &'a [u8]) -> Self {
+        Value::String(s)
+    }
+}
+
+/// Write a string, quoted and escaped.
+fn write_string(out: &mut impl Write, s: &[u8]) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in String::from_utf8_lossy(s).chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{c}")?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+/// Write a record: an object with the given fields, in order, then an end of
+/// line.
+pub fn write_record(out: &mut impl Write, fields: &[(&str, Value)]) -> io::Result<()> {
+    out.write_all(b"{")?;
+    for (i, (name, value)) in fields.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        write_string(out, name.as_bytes())?;
+        out.write_all(b":")?;
+        match value {
+            Value::String(s) => write_string(out, s)?,
+            Value::Number(n) => write!(out, "{n}")?,
+            Value::Bool(b) => write!(out, "{b}")?,
+        }
+    }
+    out.write_all(b"}\n")
+}
@@ -0,0 +1,104 @@
+//! A toy implementation of a small subset of git, as a library.
+//!
+//! The command-line tool is a thin layer over [commands], which has one
+//! function per git sub-command. Other programs can also work with a
+//! repository directly through [repository::Repository]:
+//!
+//! ```no_run
+//! use codecrafters_git::obj_type::ObjType;
+//! use codecrafters_git::repository::Repository;
+//! use std::io::{Read, Write};
+//! use std::path::Path;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let repo = Repository::open(Path::new("path/to/repo"))?;
+//! let mut readme = String::new();
+//! repo.read_object("0123abcd")?.read_to_string(&mut readme)?;
+//!
+//! // Copy it to another repository.
+//! let other = Repository::open(Path::new("path/to/other"))?;
+//! let mut object = other.write_object(ObjType::Blob, readme.len())?;
+//! object.write_all(readme.as_bytes())?;
+//! object.finish()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Several repositories can be open at the same time: commands take the one
+//! they operate on as their first argument, see [repository].
+
+// Use a flat structure
+pub mod abbrev;
+pub mod add;
+pub mod apply;
+pub mod archive;
+pub mod attributes;
+pub mod base85;
+pub mod bitmap;
+pub mod blame;
+pub mod bundle;
+pub mod clean;
+pub mod clone;
+pub mod color;
+pub mod commands;
+pub mod commit;
+pub mod common;
+pub mod config;
+pub mod count;
+pub mod crlf;
+pub mod date;
+pub mod dedup;
+pub mod describe;
+pub mod diff;
+pub mod dumb_http;
+pub mod extract;
+pub mod fast_export;
+pub mod fast_import;
+pub mod fetch;
+pub mod filter;
+pub mod fsck;
+pub mod gc;
+pub mod grep;
+pub mod hooks;
+pub mod http_store;
+pub mod ignore;
+pub mod index;
+pub mod interrupt;
+pub mod json;
+pub mod lock;
+pub mod ls_files;
+pub mod mail;
+pub mod mailmap;
+pub mod merge;
+pub mod network;
+pub mod notes;
+pub mod obj_read;
+pub mod obj_type;
+pub mod obj_write;
+pub mod object_id;
+pub mod pack_index;
+pub mod pack_read;
+pub mod pack_write;
+pub mod pager;
+pub mod platform;
+pub mod push;
+pub mod refs;
+pub mod refs_verify;
+pub mod remote;
+pub mod repository;
+pub mod revision;
+pub mod revwalk;
+pub mod rm_mv;
+pub mod serve;
+pub mod sha256;
+pub mod shallow;
+pub mod show;
+pub mod sign;
+pub mod status;
+pub mod tag;
+pub mod tree_entry;
+pub mod tree_read;
+pub mod tree_write;
+pub mod unpack;
+pub mod worktree;
+pub mod worktrees;
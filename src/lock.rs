@@ -0,0 +1,169 @@
+//! Lock files, to detect concurrent invocations of commands that modify
+//! the same things, like git does.
+//!
+//! Writing FILE is done by creating FILE.lock exclusively, writing to it,
+//! then renaming it to FILE. If FILE.lock already exists, another process
+//! is (or was, if it crashed) working on FILE, so we fail instead of waiting.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::common::write_error;
+use crate::repository::Repository;
+
+/// A held lock: the .lock file is removed on drop unless committed.
+pub struct LockFile {
+    /// The file being protected (which may not exist).
+    target: PathBuf,
+    /// Path to the .lock file, None once committed.
+    lock: Option<PathBuf>,
+    file: Option<fs::File>,
+}
+
+impl LockFile {
+    /// Take the lock for the given file of the repository, failing
+    /// immediately if it's held, or if the repository is read-only.
+    pub fn acquire(repo: &Repository, target: &Path) -> Result<Self> {
+        match Self::try_acquire(repo, target)? {
+            Ok(lock) => Ok(lock),
+            Err(lock) => bail!(
+                "unable to create {}: another process is running \
+                 (if not, it may have crashed: remove the file to continue)",
+                lock.display()
+            ),
+        }
+    }
+
+    /// Take the lock for the given file, or get the path of the .lock file
+    /// if it's held.
+    fn try_acquire(repo: &Repository, target: &Path) -> Result<Result<Self, PathBuf>> {
+        repo.ensure_writable(&target.display().to_string())?;
+        let mut name = target
+            .file_name()
+            .expect("locked path has a name")
+            .to_owned();
+        name.push(".lock");
+        let lock = target.with_file_name(name);
+        let file = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(Err(lock)),
+            Err(e) => return Err(write_error(e, &lock)),
+        };
+        Ok(Ok(Self {
+            target: target.to_owned(),
+            lock: Some(lock),
+            file: Some(file),
+        }))
+    }
+
+    /// Write content into the lock file, replacing anything written so far
+    /// (like the pid from lock_worktree), then rename it over the target.
+    /// The content is flushed to disk first, so that the target is never
+    /// left empty or truncated by a crash.
+    pub fn commit(mut self, content: &[u8]) -> Result<()> {
+        let lock = self.lock.clone().expect("not committed yet");
+        let mut file = self.file.take().expect("not committed yet");
+        file.set_len(0)
+            .and_then(|_| file.rewind())
+            .and_then(|_| file.write_all(content))
+            .and_then(|_| file.sync_all())
+            .with_context(|| format!("writing {}", lock.display()))?;
+        drop(file);
+        fs::rename(&lock, &self.target)
+            .with_context(|| format!("renaming {} into place", lock.display()))?;
+        self.lock = None;
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    /// Release the lock if it wasn't committed.
+    fn drop(&mut self) {
+        drop(self.file.take());
+        if let Some(lock) = self.lock.take() {
+            let _ = fs::remove_file(lock);
+        }
+    }
+}
+
+/// Lock the worktree for an operation that modifies it (like checkout),
+/// using .git/index.lock as git does. Released when the result is dropped.
+pub fn lock_worktree(repo: &Repository) -> Result<LockFile> {
+    let mut lock = LockFile::acquire(repo, &repo.git_dir().join("index"))?;
+    let file = lock.file.as_mut().expect("just acquired");
+    writeln!(file, "{}", process::id()).context("writing index.lock")?;
+    Ok(lock)
+}
+
+/// Get the name of this machine.
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its length, and one byte is left for
+    // the final NUL in case the name is truncated.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len() - 1) } != 0 {
+        return "unknown".to_owned();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Get the name of this machine, as Windows tells it in the environment.
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "unknown".to_owned())
+}
+
+/// Tell if a process is running on this machine.
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks that the process exists.
+    let found = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    found || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+/// Tell if a process is running on this machine: without a way to check
+/// here, assume it is, so a lock is never taken over by mistake.
+#[cfg(not(unix))]
+fn is_running(_: u32) -> bool {
+    true
+}
+
+/// Lock the repository for gc, using .git/gc.pid.lock, held for the whole
+/// run. Like git's gc.pid, the lock holds our pid and hostname, so that a
+/// lock left by a process which is no longer running on this machine is
+/// taken over rather than blocking gc forever.
+pub fn lock_gc(repo: &Repository) -> Result<LockFile> {
+    let target = repo.git_dir().join("gc.pid");
+    let host = hostname();
+    let mut lock = match LockFile::try_acquire(repo, &target)? {
+        Ok(lock) => lock,
+        Err(path) => {
+            let owner = fs::read_to_string(&path).unwrap_or_default();
+            let (pid, owner_host) = owner.trim_end().split_once(' ').unwrap_or_default();
+            match pid.parse() {
+                Ok(pid) if owner_host == host && !is_running(pid) => {
+                    fs::remove_file(&path)
+                        .with_context(|| format!("removing stale {}", path.display()))?;
+                    LockFile::acquire(repo, &target)?
+                }
+                _ => bail!(
+                    "gc is already running on machine '{owner_host}' pid {pid} \
+                     (if not, remove {} to continue)",
+                    path.display()
+                ),
+            }
+        }
+    };
+    let file = lock.file.as_mut().expect("just acquired");
+    writeln!(file, "{} {host}", process::id()).context("writing gc.pid.lock")?;
+    Ok(lock)
+}
@@ -0,0 +1,149 @@
+//! Listing files in the index and the worktree, like git ls-files.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::abbrev::shorten;
+use crate::ignore::Ignores;
+use crate::index;
+use crate::platform;
+use crate::repository::Repository;
+
+/// What to list, see git-ls-files(1).
+#[derive(Default)]
+pub struct LsFilesOptions {
+    /// List files in the index (the default unless others is set)
+    pub cached: bool,
+    /// Show mode, hash and stage of index entries
+    pub stage: bool,
+    /// List untracked files in the worktree
+    pub others: bool,
+    /// Only list untracked files that are ignored
+    pub ignored: bool,
+    /// Use .gitignore files and .git/info/exclude
+    pub exclude_standard: bool,
+    /// Abbreviate hashes
+    pub abbrev: bool,
+}
+
+/// Collect untracked files under a directory, with whether they are ignored.
+/// `rel` is the path of the directory relative to the root of the worktree,
+/// with a trailing '/' (empty for the root). Everything in an ignored
+/// directory is ignored, and other repositories are listed as directories.
+pub fn untracked(
+    dir: &Path,
+    rel: &[u8],
+    tracked: &HashSet<&[u8]>,
+    mut ignores: Option<&mut Ignores>,
+    dir_ignored: bool,
+    out: &mut Vec<(Vec<u8>, bool)>,
+) -> Result<()> {
+    let mark = match ignores.as_deref_mut() {
+        Some(ignores) => ignores.enter_dir(dir, rel)?,
+        None => 0,
+    };
+    let entries = fs::read_dir(dir).with_context(|| format!("listing {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("listing {}", dir.display()))?;
+        let name = entry.file_name().into_encoded_bytes();
+        if name == b".git" {
+            continue;
+        }
+        let path = [rel, &name].concat();
+        if tracked.contains(&path[..]) {
+            continue;
+        }
+        let is_dir = entry.file_type()?.is_dir();
+        let is_ignored = dir_ignored
+            || ignores
+                .as_deref()
+                .is_some_and(|ignores| ignores.is_ignored(&path, is_dir));
+        if !is_dir {
+            out.push((path, is_ignored));
+        } else if entry.path().join(".git").exists() {
+            out.push(([&path[..], b"/"].concat(), is_ignored));
+        } else {
+            let sub_rel = [&path[..], b"/"].concat();
+            untracked(
+                &entry.path(),
+                &sub_rel,
+                tracked,
+                ignores.as_deref_mut(),
+                is_ignored,
+                out,
+            )?;
+        }
+    }
+    if let Some(ignores) = ignores {
+        ignores.leave_dir(mark);
+    }
+    Ok(())
+}
+
+/// The "ls-files [-s] [-o [-i]] [--exclude-standard]" command.
+///
+/// Like git, untracked files are listed before index entries, and only paths
+/// in the current directory are listed, relative to it.
+pub fn ls_files(repo: &Repository, options: &LsFilesOptions) -> Result<()> {
+    if options.ignored && !options.exclude_standard {
+        bail!("--ignored needs some exclude pattern, eg --exclude-standard");
+    }
+    if options.ignored && !options.others {
+        bail!("--ignored is only supported with --others");
+    }
+    let entries = index::read(repo)?;
+    let mut prefix = platform::bytes(repo.prefix().as_os_str()).into_owned();
+    if !prefix.is_empty() {
+        prefix.push(b'/');
+    }
+    let mut stdout = io::stdout().lock();
+
+    if options.others {
+        let tracked = entries.iter().map(|e| &e.path[..]).collect();
+        let mut ignores = match options.exclude_standard {
+            true => Some(Ignores::new(repo)?),
+            false => None,
+        };
+        let mut found = Vec::new();
+        untracked(
+            repo.work_tree()?,
+            b"",
+            &tracked,
+            ignores.as_mut(),
+            false,
+            &mut found,
+        )?;
+        found.sort_unstable();
+        for (path, is_ignored) in found {
+            if is_ignored != options.ignored {
+                continue;
+            }
+            if let Some(path) = path.strip_prefix(&prefix[..]) {
+                stdout.write_all(path)?;
+                stdout.write_all(b"\n")?;
+            }
+        }
+    }
+
+    if options.cached || !options.others {
+        for entry in &entries {
+            let Some(path) = entry.path.strip_prefix(&prefix[..]) else {
+                continue;
+            };
+            if options.stage {
+                let mut hash = entry.hash.to_string();
+                if options.abbrev {
+                    hash = shorten(repo, &hash)?;
+                }
+                let mode = entry.mode.to_str();
+                write!(stdout, "{mode:0>6} {hash} {}\t", entry.stage)?;
+            }
+            stdout.write_all(path)?;
+            stdout.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}
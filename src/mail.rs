@@ -0,0 +1,485 @@
+//! Patches as emails, as git format-patch writes them and git am reads them.
+//!
+//! Emails are in mbox format: the author and date of the commit go in the
+//! headers, its first paragraph in the subject, and the rest of its message
+//! in the body, followed by a diffstat and the diff. Like git, non-ASCII
+//! names and subjects are encoded as RFC 2047 "Q" encoded words, in UTF-8.
+//!
+//! Reading supports folded headers and "Q" encoded words, but not other MIME
+//! features: "B" encoded words, base64 or quoted-printable bodies, and
+//! attachments.
+
+use anyhow::{bail, Context, Result};
+use std::io::prelude::*;
+
+use crate::commit::Commit;
+use crate::date;
+use crate::diff::{print_diff, print_stat, Change, DiffFormat};
+use crate::repository::Repository;
+
+/// Width of lines in headers, and of the diffstat.
+const MAX_LENGTH: usize = 78;
+const STAT_WIDTH: usize = 72;
+
+/// Encoded words must fit in this width (RFC 2047).
+const MAX_ENCODED_LENGTH: usize = 76;
+
+/// Longest name of a patch file, with its number and ".patch".
+const NAME_MAX: usize = 64;
+
+/// Where RFC 2047 encoded words are used.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Address,
+    Subject,
+}
+
+/// An email read from a mailbox.
+pub struct Mail {
+    /// Author, as `Name <email>`
+    pub author: String,
+    /// Date, in seconds since the epoch
+    pub time: i64,
+    /// Time zone of the date, like "+0100"
+    pub zone: String,
+    /// Commit message: the subject and the body before the patch
+    pub message: Vec<u8>,
+    /// What follows the message: the diffstat and the diff
+    pub patch: Vec<u8>,
+}
+
+/// Length of the last line of some text.
+fn last_line_len(text: &[u8]) -> usize {
+    text.len() - text.iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1)
+}
+
+/// Add words to a header, breaking lines before words which would end past
+/// MAX_LENGTH, continuation lines starting with a space.
+fn add_wrapped(out: &mut Vec<u8>, text: &[u8]) {
+    let mut column = last_line_len(out);
+    for (i, word) in text.split(|&c| c == b' ').enumerate() {
+        let separator = usize::from(i > 0);
+        if column + separator + word.len() > MAX_LENGTH && column > 1 {
+            out.extend_from_slice(b"\n ");
+            column = 1;
+        } else if separator > 0 {
+            out.push(b' ');
+            column += 1;
+        }
+        out.extend_from_slice(word);
+        column += word.len();
+    }
+}
+
+/// Tell if a byte must be encoded in an encoded word.
+fn is_special(c: u8, field: Field) -> bool {
+    if !c.is_ascii() || !(c.is_ascii_graphic() || c == b' ') {
+        return true;
+    }
+    match field {
+        Field::Address => !(c.is_ascii_alphanumeric() || b"!*+-/".contains(&c)),
+        Field::Subject => b" =?_".contains(&c),
+    }
+}
+
+/// Add text to a header as RFC 2047 "Q" encoded words, keeping multi-byte
+/// characters within a word.
+fn add_rfc2047(out: &mut Vec<u8>, text: &[u8], field: Field) {
+    const START: &[u8] = b"=?UTF-8?q?";
+    out.extend_from_slice(START);
+    let mut column = last_line_len(out);
+    let mut rest = text;
+    while let Some(&c) = rest.first() {
+        let len = match c {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        }
+        .min(rest.len());
+        let (char, tail) = rest.split_at(len);
+        rest = tail;
+        let special = len > 1 || is_special(c, field);
+        let encoded_len = if special { 3 * len } else { 1 };
+        if column + encoded_len + 2 > MAX_ENCODED_LENGTH {
+            out.extend_from_slice(b"?=\n ");
+            out.extend_from_slice(START);
+            column = 1 + START.len();
+        }
+        for &c in char {
+            match special {
+                true => out.extend_from_slice(format!("={c:02X}").as_bytes()),
+                false => out.push(c),
+            }
+        }
+        column += encoded_len;
+    }
+    out.extend_from_slice(b"?=");
+}
+
+/// Tell if a header value must be encoded.
+fn needs_rfc2047(text: &[u8]) -> bool {
+    !text.is_ascii() || text.windows(2).any(|w| w == b"=?")
+}
+
+/// Add the name of the author to a From header: encoded if needed,
+/// or quoted if it has characters special in addresses.
+fn add_name(out: &mut Vec<u8>, name: &[u8]) {
+    if needs_rfc2047(name) {
+        add_rfc2047(out, name, Field::Address);
+    } else if name.iter().any(|c| b"()<>@,;:\\\".[]".contains(c)) {
+        out.push(b'"');
+        for &c in name {
+            if c == b'"' || c == b'\\' {
+                out.push(b'\\');
+            }
+            out.push(c);
+        }
+        out.push(b'"');
+    } else {
+        add_wrapped(out, name);
+    }
+}
+
+/// The subject of a commit: its first paragraph, on one line.
+pub fn subject(message: &[u8]) -> Vec<u8> {
+    let lines = message.split(|&c| c == b'\n');
+    let lines: Vec<&[u8]> = lines
+        .take_while(|line| !line.trim_ascii().is_empty())
+        .collect();
+    lines
+        .iter()
+        .map(|line| line.trim_ascii())
+        .collect::<Vec<_>>()
+        .join(&b' ')
+}
+
+/// The body of a commit message: what follows its first paragraph,
+/// without blank lines at the start and the end.
+fn body(message: &[u8]) -> Vec<&[u8]> {
+    let mut lines = message.split(|&c| c == b'\n');
+    for line in lines.by_ref() {
+        if line.trim_ascii().is_empty() {
+            break;
+        }
+    }
+    let mut body: Vec<&[u8]> = lines
+        .skip_while(|line| line.trim_ascii().is_empty())
+        .collect();
+    while body.last().is_some_and(|line| line.trim_ascii().is_empty()) {
+        body.pop();
+    }
+    body
+}
+
+/// Name of the file for a patch, from its number and the commit message,
+/// like "0001-Fix-the-thing.patch".
+pub fn file_name(number: usize, message: &[u8]) -> String {
+    // Keep letters, digits, '.' and '_', with a single '-' instead of
+    // anything else, and a single '.' instead of several.
+    let mut name = format!("{number:04}-").into_bytes();
+    let start = name.len();
+    let mut separate = false;
+    for &c in &subject(message) {
+        if c.is_ascii_alphanumeric() || c == b'.' || c == b'_' {
+            if separate && name.len() > start {
+                name.push(b'-');
+            }
+            separate = false;
+            if !(c == b'.' && name.last() == Some(&b'.')) {
+                name.push(c);
+            }
+        } else {
+            separate = true;
+        }
+    }
+    while name.len() > start && matches!(name.last(), Some(b'.' | b'-')) {
+        name.pop();
+    }
+    name.truncate(NAME_MAX - ".patch".len() - 1);
+    format!("{}.patch", String::from_utf8_lossy(&name))
+}
+
+/// Write a commit as an email: headers, message, diffstat and diff of its
+/// changes, then the signature if any. `number` is the number of the patch
+/// and how many there are, if several.
+pub fn write_patch(
+    repo: &Repository,
+    out: &mut impl Write,
+    hash: &str,
+    commit: &Commit,
+    number: Option<(usize, usize)>,
+    changes: &[Change],
+    signature: Option<&str>,
+) -> Result<()> {
+    let mut headers = format!("From {hash} Mon Sep 17 00:00:00 2001\n").into_bytes();
+    let (name, email) = match commit.author.rsplit_once(" <") {
+        Some((name, email)) => (name, email.trim_end_matches('>')),
+        None => ("", commit.author.as_str()),
+    };
+    headers.extend_from_slice(b"From: ");
+    add_name(&mut headers, name.as_bytes());
+    let max_length = match needs_rfc2047(name.as_bytes()) {
+        true => MAX_ENCODED_LENGTH,
+        false => MAX_LENGTH,
+    };
+    if last_line_len(&headers) + " <>".len() + email.len() > max_length {
+        headers.push(b'\n');
+    }
+    headers.extend_from_slice(format!(" <{email}>\n").as_bytes());
+    let date = date::format_rfc2822(commit.author_time, &commit.author_zone);
+    headers.extend_from_slice(format!("Date: {date}\n").as_bytes());
+    let prefix = match number {
+        Some((n, total)) => format!("[PATCH {n}/{total}]"),
+        None => "[PATCH]".to_owned(),
+    };
+    headers.extend_from_slice(format!("Subject: {prefix} ").as_bytes());
+    let subject = subject(&commit.message);
+    if needs_rfc2047(&subject) {
+        add_rfc2047(&mut headers, &subject, Field::Subject);
+    } else {
+        add_wrapped(&mut headers, &subject);
+    }
+    headers.push(b'\n');
+    if !commit.message.is_ascii() {
+        headers.extend_from_slice(
+            b"MIME-Version: 1.0\n\
+              Content-Type: text/plain; charset=UTF-8\n\
+              Content-Transfer-Encoding: 8bit\n",
+        );
+    }
+    out.write_all(&headers)?;
+    writeln!(out)?;
+    for line in body(&commit.message) {
+        out.write_all(line)?;
+        writeln!(out)?;
+    }
+    writeln!(out, "---")?;
+    print_stat(repo, changes, STAT_WIDTH, out)?;
+    writeln!(out)?;
+    // Like git, with binary patches, so that they can be applied.
+    let format = DiffFormat {
+        binary: true,
+        ..Default::default()
+    };
+    print_diff(repo, changes, &format, out)?;
+    if let Some(signature) = signature {
+        writeln!(out, "-- \n{signature}\n")?;
+    }
+    Ok(())
+}
+
+/// Tell if a line starts an email in a mailbox: "From <sender> <date>",
+/// the date having a time and ending with the year.
+fn is_from_line(line: &[u8]) -> bool {
+    let Some(rest) = line.strip_prefix(b"From ") else {
+        return false;
+    };
+    let fields: Vec<&[u8]> = rest.split(u8::is_ascii_whitespace).collect();
+    let fields: Vec<&[u8]> = fields.into_iter().filter(|f| !f.is_empty()).collect();
+    fields.len() >= 3
+        && fields.iter().any(|field| field.contains(&b':'))
+        && fields
+            .last()
+            .is_some_and(|year| year.len() == 4 && year.iter().all(u8::is_ascii_digit))
+}
+
+/// Split a mailbox into emails, each starting with a "From " line.
+pub fn split_mbox(data: &[u8]) -> Vec<&[u8]> {
+    let mut mails = Vec::new();
+    let mut start = 0;
+    let mut pos = 0;
+    for line in data.split_inclusive(|&c| c == b'\n') {
+        if is_from_line(line) && pos > start {
+            mails.push(&data[start..pos]);
+            start = pos;
+        }
+        pos += line.len();
+    }
+    if data[start..].iter().any(|c| !c.is_ascii_whitespace()) {
+        mails.push(&data[start..]);
+    }
+    mails
+}
+
+/// Decode the RFC 2047 "Q" encoded words in a header value. Whitespace
+/// between encoded words is dropped.
+fn decode_rfc2047(value: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut rest = value;
+    let mut pending_space: &[u8] = b"";
+    let mut after_word = false;
+    while !rest.is_empty() {
+        let word = rest.strip_prefix(b"=?").and_then(|word| {
+            let mut parts = word.splitn(3, |&c| c == b'?');
+            let (_charset, encoding, text) = (parts.next()?, parts.next()?, parts.next()?);
+            let end = text.windows(2).position(|w| w == b"?=")?;
+            encoding
+                .eq_ignore_ascii_case(b"q")
+                .then_some((&text[..end], &text[end + 2..]))
+        });
+        if let Some((text, tail)) = word {
+            if !after_word {
+                out.extend_from_slice(pending_space);
+            }
+            let mut bytes = text.iter();
+            while let Some(&c) = bytes.next() {
+                match c {
+                    b'_' => out.push(b' '),
+                    b'=' => {
+                        let hex = [
+                            *bytes.next().unwrap_or(&b'0'),
+                            *bytes.next().unwrap_or(&b'0'),
+                        ];
+                        let hex = std::str::from_utf8(&hex).unwrap_or("3F");
+                        out.push(u8::from_str_radix(hex, 16).unwrap_or(b'?'));
+                    }
+                    c => out.push(c),
+                }
+            }
+            rest = tail;
+            pending_space = b"";
+            after_word = true;
+            continue;
+        }
+        let space_len = rest.iter().take_while(|c| c.is_ascii_whitespace()).count();
+        if space_len > 0 {
+            pending_space = &rest[..space_len];
+            rest = &rest[space_len..];
+            continue;
+        }
+        out.extend_from_slice(pending_space);
+        pending_space = b"";
+        after_word = false;
+        let len = rest[1..]
+            .iter()
+            .position(|c| c.is_ascii_whitespace() || *c == b'=')
+            .map_or(rest.len(), |i| i + 1);
+        out.extend_from_slice(&rest[..len]);
+        rest = &rest[len..];
+    }
+    out.extend_from_slice(pending_space);
+    out
+}
+
+/// Remove the "Re:" and "[PATCH ...]" prefixes of a subject.
+fn clean_subject(mut subject: &[u8]) -> &[u8] {
+    loop {
+        subject = subject.trim_ascii();
+        if subject.len() >= 3 && subject[..3].eq_ignore_ascii_case(b"re:") {
+            subject = &subject[3..];
+        } else if let Some(end) = subject
+            .starts_with(b"[")
+            .then(|| subject.iter().position(|&c| c == b']'))
+            .flatten()
+        {
+            subject = &subject[end + 1..];
+        } else {
+            return subject;
+        }
+    }
+}
+
+/// Parse a From header into `Name <email>`.
+fn parse_from(value: &[u8]) -> Result<String> {
+    let value = String::from_utf8_lossy(value);
+    let value = value.trim();
+    let (name, email) = match value.rsplit_once('<') {
+        Some((name, email)) => (name.trim(), email.trim_end_matches('>').trim()),
+        // "email (Name)", or just the email
+        None => match value.split_once(" (") {
+            Some((email, name)) => (name.trim_end_matches(')'), email),
+            None => ("", value),
+        },
+    };
+    if email.is_empty() {
+        bail!("missing email address in From: {value}");
+    }
+    let name = match name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) {
+        Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => name.to_owned(),
+    };
+    let name = if name.is_empty() {
+        email.split('@').next().unwrap_or(email)
+    } else {
+        &name
+    };
+    Ok(format!("{name} <{email}>"))
+}
+
+/// Parse an email, as written by write_patch(). Like git am, the message
+/// ends at the first "---" line, or the first line of a diff.
+pub fn parse(mail: &[u8]) -> Result<Mail> {
+    let mut lines = mail.split_inclusive(|&c| c == b'\n').peekable();
+    if lines.peek().is_some_and(|line| line.starts_with(b"From ")) {
+        lines.next();
+    }
+    // Headers, unfolded
+    let mut headers: Vec<(String, Vec<u8>)> = Vec::new();
+    for line in lines.by_ref() {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with(b" ") || line.starts_with(b"\t") {
+            if let Some((_, value)) = headers.last_mut() {
+                value.extend_from_slice(line);
+            }
+            continue;
+        }
+        let Some(colon) = line.iter().position(|&c| c == b':') else {
+            bail!("not an email: bad header line");
+        };
+        let name = String::from_utf8_lossy(&line[..colon]).to_ascii_lowercase();
+        headers.push((name, line[colon + 1..].trim_ascii().to_vec()));
+    }
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| decode_rfc2047(value))
+    };
+    let author = parse_from(&header("from").context("missing From header")?)?;
+    let date = header("date").context("missing Date header")?;
+    let date = String::from_utf8_lossy(&date);
+    let Some((time, zone)) = date::parse_rfc2822(&date) else {
+        bail!("invalid date {date:?}");
+    };
+    let subject = header("subject").unwrap_or_default();
+
+    let mut body = Vec::new();
+    let mut patch = Vec::new();
+    for line in lines {
+        let starts_patch = line.trim_ascii_end() == b"---"
+            || line.starts_with(b"diff -")
+            || line.starts_with(b"Index: ");
+        if starts_patch || !patch.is_empty() {
+            patch.extend_from_slice(line);
+        } else {
+            body.push(line.trim_ascii_end());
+        }
+    }
+    while body.first().is_some_and(|line| line.is_empty()) {
+        body.remove(0);
+    }
+    while body.last().is_some_and(|line| line.is_empty()) {
+        body.pop();
+    }
+    let mut message = clean_subject(&subject).to_vec();
+    message.push(b'\n');
+    if !body.is_empty() {
+        message.push(b'\n');
+        for line in body {
+            message.extend_from_slice(line);
+            message.push(b'\n');
+        }
+    }
+    Ok(Mail {
+        author,
+        time,
+        zone,
+        message,
+        patch,
+    })
+}
@@ -0,0 +1,146 @@
+//! Mapping author and committer identities to canonical ones, from the
+//! .mailmap file at the top of the worktree and the file named by
+//! mailmap.file, see gitmailmap(5). mailmap.blob is not supported.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::config;
+use crate::repository::Repository;
+
+/// What an identity is replaced with: either part may be kept as is.
+#[derive(Clone, Default)]
+struct Replacement {
+    name: Option<String>,
+    email: Option<String>,
+}
+
+/// The replacements for one email address.
+#[derive(Default)]
+struct ForEmail {
+    /// For any name
+    any: Option<Replacement>,
+    /// For given names, lowercased
+    names: HashMap<String, Replacement>,
+}
+
+/// Identity mappings, by lowercased email address (matching is case
+/// insensitive, like git).
+#[derive(Default)]
+pub struct Mailmap {
+    emails: HashMap<String, ForEmail>,
+}
+
+/// Split "Name <email>" at the start of a mailmap line into the name
+/// (None if empty) and the email, returning the rest of the line too.
+fn parse_ident(line: &str) -> Option<(Option<&str>, &str, &str)> {
+    let (name, rest) = line.split_once('<')?;
+    let (email, rest) = rest.split_once('>')?;
+    let name = name.trim();
+    Some(((!name.is_empty()).then_some(name), email.trim(), rest))
+}
+
+impl Mailmap {
+    /// Read the mappings of the repository: none if there is no mailmap.
+    pub fn read(repo: &Repository) -> Result<Mailmap> {
+        let mut mailmap = Mailmap::default();
+        let mut files = Vec::new();
+        if !repo.is_bare() {
+            files.push(repo.work_tree()?.join(".mailmap"));
+        }
+        if let Some(file) = config::get(repo, "mailmap.file")? {
+            files.push(file.into());
+        }
+        for file in files {
+            mailmap.read_file(&file)?;
+        }
+        Ok(mailmap)
+    }
+
+    /// Add the mappings of a file, if it exists.
+    fn read_file(&mut self, file: &Path) -> Result<()> {
+        match fs::read(file) {
+            Ok(content) => self.parse(&String::from_utf8_lossy(&content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", file.display())),
+        }
+        Ok(())
+    }
+
+    /// Add mappings, one per line in one of these forms:
+    ///
+    /// ```text
+    /// Proper Name <commit@email>
+    /// <proper@email> <commit@email>
+    /// Proper Name <proper@email> <commit@email>
+    /// Proper Name <proper@email> Commit Name <commit@email>
+    /// ```
+    ///
+    /// Like git, lines for an email address with no commit name add to each
+    /// other (a name from one, an email from another), while later lines
+    /// for the same commit name and email win. Lines starting with '#' are
+    /// comments, and so is anything after the last email address.
+    pub fn parse(&mut self, text: &str) {
+        for line in text.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((name, email, rest)) = parse_ident(line) else {
+                continue;
+            };
+            let (replacement, old_name, old_email) = match parse_ident(rest) {
+                Some((old_name, old_email, _)) => {
+                    let replacement = Replacement {
+                        name: name.map(str::to_owned),
+                        email: (!email.is_empty()).then(|| email.to_owned()),
+                    };
+                    (replacement, old_name, old_email)
+                }
+                None => {
+                    let replacement = Replacement {
+                        name: name.map(str::to_owned),
+                        email: None,
+                    };
+                    (replacement, None, email)
+                }
+            };
+            let entry = self.emails.entry(old_email.to_lowercase()).or_default();
+            match old_name {
+                Some(old_name) => {
+                    entry.names.insert(old_name.to_lowercase(), replacement);
+                }
+                None => {
+                    let any = entry.any.get_or_insert_with(Replacement::default);
+                    any.name = replacement.name.or(any.name.take());
+                    any.email = replacement.email.or(any.email.take());
+                }
+            }
+        }
+    }
+
+    /// Map a name and email address to the canonical ones.
+    pub fn map(&self, name: &str, email: &str) -> (String, String) {
+        let replacement = self
+            .emails
+            .get(&email.to_lowercase())
+            .and_then(|entry| entry.names.get(&name.to_lowercase()).or(entry.any.as_ref()));
+        let replacement = replacement.cloned().unwrap_or_default();
+        (
+            replacement.name.unwrap_or_else(|| name.to_owned()),
+            replacement.email.unwrap_or_else(|| email.to_owned()),
+        )
+    }
+
+    /// Map an identity as found in commits, `Name <email>` (possibly followed
+    /// by a date), to the canonical name and email.
+    pub fn map_ident(&self, ident: &str) -> (String, String) {
+        let (name, email) = match ident.split_once('<') {
+            Some((name, rest)) => (name.trim(), rest.split('>').next().unwrap_or(rest)),
+            None => (ident.trim(), ""),
+        };
+        self.map(name, email)
+    }
+}
@@ -3,32 +3,51 @@
 //! See [Commands] for the list of git sub-commands (partially) implemented.
 //!
 //! Major restrictions (within the subset of commands implemented):
-//! - Only works with loose objects (ie will not work after git gc).
-//! - No index (stating area), no support for .gitignore.
-//! - No support for git config (only environment variables for author etc.).
+//! - Reads loose objects and packs, but mostly writes loose objects (clone keeps packs).
+//! - The index (staging area) is read in versions 2 and 3 only, its extensions are ignored.
+//! - Ignore rules come from .gitignore and .git/info/exclude, not core.excludesFile.
+//! - Only the repository's config (.git/config, and includes), author etc. only from the environment.
 //! - The checkout-empty command will happily overwrite files if the directory's not empty.
-//! - Hashes may not be abbreviated; using references (eg branch names) is not supported.
+//! - Revisions are limited to hashes and reference names with `@{N}`, `@{date}`, `~N`, `^N`,
+//!   `^{type}` and `:path` suffixes.
 
+use anyhow::bail;
 use clap::{Parser, Subcommand};
+use std::env;
 use std::path::PathBuf;
+use std::process;
 
-// Use a flat structure
-mod commands;
-mod common;
-mod network;
-mod obj_read;
-mod obj_type;
-mod obj_write;
-mod tree_entry;
-mod tree_read;
-mod tree_write;
-mod unpack;
-
-use commands::*;
+use codecrafters_git::clean::{CleanOptions, IgnoredFiles};
+use codecrafters_git::clone::CloneOptions;
+use codecrafters_git::color::When;
+use codecrafters_git::commands::*;
+use codecrafters_git::diff::WordDiff;
+use codecrafters_git::ls_files::LsFilesOptions;
+use codecrafters_git::object_id::HashAlgo;
+use codecrafters_git::repository::Repository;
+use codecrafters_git::revwalk::Filter;
+use codecrafters_git::status::{StatusOptions, UntrackedFiles};
+use codecrafters_git::{abbrev, color, config, date, diff, json, network, pager, status};
 
 #[derive(Parser)]
 /// A toy implementation of a small subset of git
 struct Cli {
+    /// Set a configuration value for this command only, eg -c core.hooksPath=hooks
+    #[arg(short = 'c', value_name = "NAME=VALUE")]
+    config: Vec<String>,
+    /// Fail instead of accessing the network (also enabled by GIT_OFFLINE=1)
+    #[arg(long)]
+    offline: bool,
+    /// Don't send the output of diff, show or blame to a pager
+    #[arg(long)]
+    no_pager: bool,
+    /// Output of ls-tree, ls-remote, cat-file --batch-check and status: text
+    /// (the default) or json (one object per line)
+    #[arg(long, value_name = "FORMAT", value_parser = ["text", "json"], default_value = "text")]
+    format: String,
+    /// Show abbreviated hashes (at least N hex digits) where full ones are shown by default
+    #[arg(long, global = true, value_name = "N", num_args = 0..=1, require_equals = true)]
+    abbrev: Option<Option<usize>>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -37,58 +56,679 @@ struct Cli {
 enum Commands {
     /// Create an empty Git repository
     Init {
+        /// Hash function for object ids: sha1 or sha256
+        #[arg(long, value_name = "FORMAT", default_value = "sha1")]
+        object_format: String,
+        /// Create a bare repository, without a worktree
+        #[arg(long)]
+        bare: bool,
         /// Directory where the repository should be created
         #[arg(default_value = ".")]
         directory: PathBuf,
     },
     /// Provide contents of repository objects
     CatFile {
-        /// Pretty-print the contents of OBJECT based on its type
-        #[arg(short = 'p')]
-        object: String,
+        /// Pretty-print the contents of OBJECT (eg HEAD~2:path/to/file) based on its type
+        #[arg(short = 'p', required_unless_present = "batch_check")]
+        object: Option<String>,
+        /// Show the hash, type and size of objects read from stdin, one per line
+        #[arg(long, conflicts_with = "object")]
+        batch_check: bool,
     },
     /// Compute object hash and optionally create an object from a file
     HashObject {
         /// Actually write the object into the object database
         #[arg(short)]
         write: bool,
+        /// Read the object from standard input instead of a file
+        #[arg(long, conflicts_with = "file")]
+        stdin: bool,
         /// File to read
-        file: PathBuf,
+        #[arg(required_unless_present = "stdin")]
+        file: Option<PathBuf>,
     },
+    /// Create a tag object from its content read from stdin, after checking it
+    Mktag,
+    /// Create a tree object from entries in the format of ls-tree, read from stdin
+    Mktree,
     /// List the contents of a tree object
     LsTree {
         /// List only filenames, one per line
         #[arg(long)]
         name_only: bool,
-        /// The tree object to list
+        /// List the whole tree, even when run from a subdirectory
+        #[arg(long)]
+        full_tree: bool,
+        /// The tree (or commit) to list, eg main:subdir
         tree: String,
     },
-    /// Create a tree object from the current directory (not index)
+    /// List files in the index and untracked files in the working directory
+    LsFiles {
+        /// List files in the index (the default unless --others is given)
+        #[arg(short, long)]
+        cached: bool,
+        /// Show the mode, hash and stage of files in the index
+        #[arg(short, long)]
+        stage: bool,
+        /// List untracked files
+        #[arg(short, long)]
+        others: bool,
+        /// Only list ignored files (with --others and --exclude-standard)
+        #[arg(short, long)]
+        ignored: bool,
+        /// Ignore files according to .gitignore and .git/info/exclude
+        #[arg(long)]
+        exclude_standard: bool,
+    },
+    /// Stage changes to tracked files, choosing them hunk by hunk (only -p
+    /// is supported)
+    Add {
+        /// Choose the hunks to stage, answering prompts on standard input
+        #[arg(short, long, required = true)]
+        patch: bool,
+        /// Only look at changes to these files or directories
+        paths: Vec<PathBuf>,
+    },
+    /// Remove files from the index and the working directory
+    Rm {
+        /// Only remove from the index, keep files in the working directory
+        #[arg(long)]
+        cached: bool,
+        /// Allow removing directories recursively
+        #[arg(short)]
+        recursive: bool,
+        /// Remove even files with changes that would be lost
+        #[arg(short, long)]
+        force: bool,
+        /// Files or directories to remove
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Remove untracked files from the working directory
+    Clean {
+        /// Only show what would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Needed to remove files, unless clean.requireForce is false; twice to
+        /// also remove other repositories
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        force: u8,
+        /// Also remove untracked directories
+        #[arg(short)]
+        dirs: bool,
+        /// Also remove ignored files
+        #[arg(short = 'x', conflicts_with = "only_ignored")]
+        ignored: bool,
+        /// Only remove ignored files
+        #[arg(short = 'X')]
+        only_ignored: bool,
+        /// Don't list the files removed
+        #[arg(short, long)]
+        quiet: bool,
+        /// Only remove files under these paths (default: the current directory)
+        paths: Vec<PathBuf>,
+    },
+    /// Show changes in the index and the working directory, and untracked files
+    Status {
+        /// Output format for scripts: only v2 is supported
+        #[arg(long, value_name = "VERSION", required = true, value_parser = ["v2"])]
+        porcelain: String,
+        /// End entries with NUL rather than newlines, and don't quote paths
+        #[arg(short = 'z')]
+        nul: bool,
+        /// Untracked files to show: no, normal (directories with no tracked files
+        /// rather than their contents) or all (default: status.showUntrackedFiles)
+        #[arg(
+            short = 'u',
+            long,
+            value_name = "MODE",
+            num_args = 0..=1,
+            default_missing_value = "all",
+            value_parser = status::parse_untracked_files
+        )]
+        untracked_files: Option<UntrackedFiles>,
+    },
+    /// Move or rename a file or directory, in the index and the working directory
+    Mv {
+        /// File or directory to move
+        from: PathBuf,
+        /// New name, or existing directory to move into
+        to: PathBuf,
+    },
+    /// Get, set or list configuration variables (only in .git/config)
+    Config {
+        /// Get the value for the key (the last one if there are several)
+        #[arg(long, group = "action")]
+        get: bool,
+        /// Get all values for the key
+        #[arg(long, group = "action")]
+        get_all: bool,
+        /// Add a value for the key, keeping the others
+        #[arg(long, group = "action")]
+        add: bool,
+        /// List all variables and their values
+        #[arg(short, long, group = "action")]
+        list: bool,
+        /// Check values and show them in canonical form: bool or int
+        #[arg(long = "type", value_name = "TYPE")]
+        value_type: Option<String>,
+        /// The name of the variable, eg core.autocrlf (without a value, get it)
+        key: Option<String>,
+        /// The value to set
+        value: Option<String>,
+    },
+    /// Create a tree object from the whole working directory (not index), even from a subdirectory
     WriteTree,
     /// Create a new commit object
     CommitTree {
-        /// Each -p indicates the id of a parent commit object
+        /// Each -p indicates a parent commit
         #[arg(short)]
         parent: Vec<String>,
+        /// A paragraph in the commit log message (default: read the message from stdin)
+        #[arg(short)]
+        message: Vec<String>,
+        /// Sign the commit, with KEYID or the default key (user.signingKey or the committer)
+        #[arg(
+            short = 'S',
+            long,
+            value_name = "KEYID",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = ""
+        )]
+        gpg_sign: Option<String>,
+        /// Do not sign the commit, even if commit.gpgSign is set
+        #[arg(long, conflicts_with = "gpg_sign")]
+        no_gpg_sign: bool,
+        /// An existing tree (or commit, for its tree)
+        tree: String,
+    },
+    /// Check the signature of commits
+    VerifyCommit {
+        #[arg(required = true)]
+        commits: Vec<String>,
+    },
+    /// Check the signature of annotated tags
+    VerifyTag {
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Commit the whole working directory (except ignored files) on top of HEAD
+    Snapshot {
         /// A paragraph in the commit log message
         #[arg(short, required = true)]
         message: Vec<String>,
-        /// An existing tree object
-        tree: String,
+        /// Do not run the pre-commit and commit-msg hooks
+        #[arg(short, long)]
+        no_verify: bool,
+    },
+    /// Merge a branch (or commit) into the current branch
+    Merge {
+        /// The branch or commit to merge
+        branch: String,
+    },
+    /// Apply the changes made by a commit on top of HEAD, and commit them
+    CherryPick {
+        /// The commit to pick
+        commit: String,
+    },
+    /// Undo the changes made by a commit on top of HEAD, and commit that
+    Revert {
+        /// The commit to revert
+        commit: String,
+    },
+    /// Replay the commits of the current branch on top of another one
+    Rebase {
+        /// The branch (or commit) to replay commits on top of
+        upstream: String,
+    },
+    /// Write commits as patches in emails, one file per commit
+    FormatPatch {
+        /// Write the files in this directory rather than the current one
+        #[arg(short, long = "output-directory", value_name = "DIR")]
+        output_directory: Option<PathBuf>,
+        /// Write all the emails to standard output instead of files
+        #[arg(long)]
+        stdout: bool,
+        /// Signature at the end of emails (default: format.signature, or
+        /// the name and version of this program)
+        #[arg(long, value_name = "SIGNATURE")]
+        signature: Option<String>,
+        /// Don't add a signature at the end of emails
+        #[arg(long, conflicts_with = "signature")]
+        no_signature: bool,
+        /// Commits since this one (up to HEAD), or a range like `a..b`
+        #[arg(value_name = "SINCE|RANGE")]
+        range: String,
+    },
+    /// Commit patches from emails, as written by format-patch
+    Am {
+        /// Mailboxes with the emails, standard input if none
+        mailboxes: Vec<PathBuf>,
+    },
+    /// Show commits (with their diff), tags, trees or blobs
+    Show {
+        /// Color the output: always, never or auto (only for a terminal, the default)
+        #[arg(
+            long,
+            value_name = "WHEN",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "always",
+            value_parser = color::parse_when
+        )]
+        color: Option<When>,
+        /// Don't color the output, even if color.diff or color.ui says so
+        #[arg(long, overrides_with = "color")]
+        no_color: bool,
+        /// The objects to show, eg HEAD~1 or v1.0:README
+        #[arg(value_name = "OBJECT", default_value = "HEAD")]
+        revs: Vec<String>,
+    },
+    /// Show the commit that last changed each line of a file
+    Blame {
+        /// The file, in HEAD
+        path: PathBuf,
+    },
+    /// Print lines containing a fixed string, in tracked files or in a tree
+    Grep {
+        /// Also search untracked files which are not ignored
+        #[arg(long, conflicts_with = "rev")]
+        untracked: bool,
+        /// Number of threads searching files (0 to use all processors)
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        threads: usize,
+        /// The string to search for
+        pattern: String,
+        /// Search in this commit or tree instead of the worktree
+        rev: Option<String>,
+    },
+    /// Show the state of attributes for paths, from .gitattributes files
+    CheckAttr {
+        /// The attributes to check, then the paths (or all paths after "--")
+        #[arg(required = true)]
+        attributes: Vec<String>,
+        #[arg(last = true, hide = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Show the canonical name and email for identities, from the mailmap
+    CheckMailmap {
+        /// Also read identities from standard input, one per line
+        #[arg(long)]
+        stdin: bool,
+        /// Identities like "Name <email>" or "<email>"
+        #[arg(required_unless_present = "stdin")]
+        contacts: Vec<String>,
+    },
+    /// Tell which paths are ignored, and by which pattern with -v
+    CheckIgnore {
+        /// Also show the matching pattern, where it comes from, and paths kept by a '!' pattern
+        #[arg(short, long)]
+        verbose: bool,
+        /// The paths to check
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Name HEAD after the nearest tag, eg v1.0-3-g1234abc
+    Describe {
+        /// Also use lightweight tags, not only annotated ones
+        #[arg(long)]
+        tags: bool,
+        /// Append MARK (default: -dirty) if the worktree has changes
+        #[arg(
+            long,
+            value_name = "MARK",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "-dirty"
+        )]
+        dirty: Option<String>,
+    },
+    /// Get the hash of objects from revisions, eg HEAD~2 (and other repository information)
+    RevParse {
+        /// Check that exactly one revision is given and that it exists
+        #[arg(long)]
+        verify: bool,
+        /// Like --verify, but show the shortest unique abbreviation (at least N digits)
+        #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true)]
+        short: Option<Option<usize>>,
+        /// Show the path to the .git directory
+        #[arg(long)]
+        git_dir: bool,
+        /// Show the path to the directory shared by all worktrees
+        #[arg(long)]
+        git_common_dir: bool,
+        /// Show the path of the current directory relative to the top of the worktree
+        #[arg(long)]
+        show_prefix: bool,
+        /// Show whether the repository is bare: true or false
+        #[arg(long)]
+        is_bare_repository: bool,
+        /// Revisions: full or abbreviated hashes or ref names, with ~N, ^N, ^{type} or :path suffixes
+        revs: Vec<String>,
+    },
+    /// Find the best common ancestor of two commits
+    MergeBase {
+        /// Output all merge bases instead of just one
+        #[arg(long, conflicts_with = "is_ancestor")]
+        all: bool,
+        /// Check if the first commit is an ancestor of the second (exit status 0 or 1)
+        #[arg(long)]
+        is_ancestor: bool,
+        #[arg(value_name = "COMMIT")]
+        one: String,
+        #[arg(value_name = "COMMIT")]
+        two: String,
+    },
+    /// Summarize commits by author, with their subjects or counts
+    Shortlog {
+        /// Only count the commits of each author
+        #[arg(short, long)]
+        summary: bool,
+        /// Sort authors by number of commits, instead of by name
+        #[arg(short, long)]
+        numbered: bool,
+        /// Show the email address of authors
+        #[arg(short, long)]
+        email: bool,
+        /// Commits to start from; with a ^ prefix, exclude commits reachable from it
+        /// (default: HEAD)
+        #[arg(value_name = "COMMIT")]
+        revs: Vec<String>,
+    },
+    /// List commits (and with --objects, other objects) reachable from some commits
+    RevList {
+        /// Also list trees and blobs, with their path
+        #[arg(long, conflicts_with_all = ["since", "until", "author", "grep"])]
+        objects: bool,
+        /// Commits to start from; with a ^ prefix, exclude commits reachable from it
+        #[arg(required = true, value_name = "COMMIT")]
+        revs: Vec<String>,
+        /// Exclude commits reachable from these
+        #[arg(long, value_name = "COMMIT", num_args = 1..)]
+        not: Vec<String>,
+        /// Only list commits more recent than this date (eg "2.weeks.ago")
+        #[arg(long, alias = "after", value_name = "DATE", value_parser = date::parse)]
+        since: Option<i64>,
+        /// Only list commits older than this date
+        #[arg(long, alias = "before", value_name = "DATE", value_parser = date::parse)]
+        until: Option<i64>,
+        /// Only list commits whose author name or email contains this
+        #[arg(long, value_name = "PATTERN")]
+        author: Option<String>,
+        /// Only list commits whose message contains this
+        #[arg(long, value_name = "PATTERN")]
+        grep: Option<String>,
+    },
+    /// Read which reference a symbolic reference (like HEAD) points to, or change it
+    SymbolicRef {
+        /// Shorten the name of the target (eg "main" rather than "refs/heads/main")
+        #[arg(long)]
+        short: bool,
+        /// Don't complain if the reference is not symbolic, only exit with status 1
+        #[arg(short, long)]
+        quiet: bool,
+        /// The symbolic reference, eg HEAD
+        name: String,
+        /// The reference it should point to, eg refs/heads/main
+        target: Option<String>,
+    },
+    /// Safely update a reference (or delete it), logging the update in its reflog
+    UpdateRef {
+        /// Delete the reference instead
+        #[arg(short)]
+        delete: bool,
+        /// Update HEAD itself rather than the branch it points to
+        #[arg(long)]
+        no_deref: bool,
+        /// Reason for the update, for the reflog
+        #[arg(short, value_name = "REASON", default_value = "")]
+        message: String,
+        /// The reference to update, eg refs/heads/main
+        name: String,
+        /// New value (unless deleting), then the value the reference must have before
+        /// the update (empty or zeros meaning it must not exist); defaults to its
+        /// current value, which is still checked after locking the reference
+        #[arg(value_name = "VALUE", num_args = 0..=2)]
+        values: Vec<String>,
+    },
+    /// Count loose objects and the disk space they use
+    CountObjects {
+        /// Also report packs, packed objects and garbage files
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Check the integrity of objects and links between them, and list dangling objects
+    Fsck {
+        /// List all unreachable objects, not only dangling ones
+        #[arg(long)]
+        unreachable: bool,
+    },
+    /// Pack reachable objects and delete unreachable ones
+    Gc {
+        /// Delete unreachable loose objects older than this date
+        #[arg(long, value_name = "DATE", default_value = "2.weeks.ago")]
+        prune: String,
+        /// Keep all unreachable objects
+        #[arg(long, conflicts_with = "prune")]
+        no_prune: bool,
+    },
+    /// Delete unreachable loose objects
+    Prune {
+        /// Only list the objects that would be deleted
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// List deleted objects
+        #[arg(short, long)]
+        verbose: bool,
+        /// Only delete objects older than this date
+        #[arg(long, value_name = "DATE")]
+        expire: Option<String>,
     },
     /// Write out working tree files from a commit (assumes an empty workdir)
     CheckoutEmpty {
         /// The commit for check out
         commit: String,
     },
+    /// Export a path from a commit or tree to a directory (without touching HEAD)
+    Extract {
+        /// The commit or tree to extract from
+        tree_ish: String,
+        /// The files or directories to extract (relative to the root of the
+        /// tree), or patterns where * and ? also match slashes
+        #[arg(required = true)]
+        pathspecs: Vec<PathBuf>,
+        /// The destination directory (will be created if needed)
+        dest: PathBuf,
+    },
+    /// Show the previous values of a reference, as used by name@{N} revisions
+    Reflog {
+        /// Only show this many entries
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+        /// The reference, eg main
+        #[arg(default_value = "HEAD")]
+        name: String,
+    },
+    /// Move HEAD and the current branch to a commit, resetting the index too by default
+    Reset {
+        /// Only move the branch, leave the index and working directory alone
+        #[arg(long, conflicts_with_all = ["mixed", "hard"])]
+        soft: bool,
+        /// Also reset the index, but not the working directory (the default)
+        #[arg(long, conflicts_with = "hard")]
+        mixed: bool,
+        /// Also reset the working directory: changes to tracked files are lost
+        #[arg(long)]
+        hard: bool,
+        /// The commit to move to
+        #[arg(default_value = "HEAD")]
+        commit: String,
+    },
+    /// Save changes to tracked files away, and reset them to HEAD
+    Stash {
+        #[command(subcommand)]
+        command: Option<StashCommands>,
+    },
+    /// Manage linked worktrees: more worktrees sharing this repository
+    Worktree {
+        #[command(subcommand)]
+        command: WorktreeCommands,
+    },
+    /// Add, show or list notes: messages attached to commits without changing them
+    Notes {
+        /// The notes reference to use, eg "review" for refs/notes/review
+        /// (default: core.notesRef, or refs/notes/commits)
+        #[arg(long = "ref", global = true)]
+        notes_ref: Option<String>,
+        #[command(subcommand)]
+        command: Option<NotesCommands>,
+    },
+    /// Move HEAD back to a previous position from its reflog (experimental)
+    Undo {
+        /// Show the recent positions of HEAD instead, most recent first
+        #[arg(long, conflicts_with_all = ["worktree", "steps"])]
+        list: bool,
+        /// Also restore the working directory and index (which must match HEAD)
+        #[arg(long)]
+        worktree: bool,
+        /// How many positions to go back (see --list); undo again to redo
+        #[arg(default_value_t = 1)]
+        steps: usize,
+    },
+    /// Create a tar (or tar.gz) archive of a commit or tree
+    Archive {
+        /// Archive format: tar, tgz, tar.gz or zip (default: guessed from the output file name, or tar)
+        #[arg(long)]
+        format: Option<String>,
+        /// Prepend this to paths in the archive (add a final / for a directory)
+        #[arg(long, default_value = "")]
+        prefix: String,
+        /// Write the archive to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Get the archive from a remote repository, over git:// or SSH
+        #[arg(long, value_name = "URL")]
+        remote: Option<String>,
+        /// With --remote over SSH, the command to run on the server
+        #[arg(long, requires = "remote", value_name = "COMMAND")]
+        exec: Option<String>,
+        /// The commit or tree to archive
+        tree_ish: String,
+    },
+    /// Report duplicated blobs and space used per directory in all history
+    DedupReport {
+        /// Report space for directories up to this depth (0 is the root only)
+        #[arg(long, default_value_t = 1)]
+        depth: usize,
+    },
+    /// Show changes between commits, the index and the working tree
+    Diff {
+        /// Compare with the index rather than the working tree (default: from HEAD)
+        #[arg(long, alias = "staged")]
+        cached: bool,
+        /// Detect renames of files at least N similar, like -M=90% or -M=9 (default: 50%)
+        #[arg(
+            short = 'M',
+            long,
+            value_name = "N",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "50%",
+            value_parser = diff::parse_rename_score
+        )]
+        find_renames: Option<u32>,
+        /// Don't detect renames, even if diff.renames is set
+        #[arg(long, conflicts_with = "find_renames")]
+        no_renames: bool,
+        /// Show changes to binary files as patches that can be applied
+        #[arg(long)]
+        binary: bool,
+        /// Color the output: always, never or auto (only for a terminal, the default)
+        #[arg(
+            long,
+            value_name = "WHEN",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "always",
+            value_parser = color::parse_when
+        )]
+        color: Option<When>,
+        /// Don't color the output, even if color.diff or color.ui says so
+        #[arg(long, overrides_with = "color")]
+        no_color: bool,
+        /// Show changed words rather than lines, as [-removed-]{+added+} (plain,
+        /// the default), only in colors (color), or on lines of their own (porcelain)
+        #[arg(
+            long,
+            value_name = "MODE",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "plain",
+            value_parser = diff::parse_word_diff
+        )]
+        word_diff: Option<WordDiff>,
+        /// Same as --word-diff=color
+        #[arg(long, conflicts_with = "word_diff")]
+        color_words: bool,
+        /// Compare from this commit, or between those two commits (or trees)
+        #[arg(num_args = 0..=2)]
+        commits: Vec<String>,
+    },
+    /// Apply patches (unified diffs) to the working tree, or to the index
+    Apply {
+        /// Apply the patches to the index only, leaving the working tree alone
+        #[arg(long)]
+        cached: bool,
+        /// Only check that the patches apply, without changing anything
+        #[arg(long)]
+        check: bool,
+        /// Remove this many leading components from paths in the patches
+        #[arg(short = 'p', value_name = "N", default_value_t = 1)]
+        strip: usize,
+        /// Only require this many lines of context around changes to match
+        #[arg(short = 'C', value_name = "N")]
+        context: Option<usize>,
+        /// Patch files, standard input if none
+        patches: Vec<PathBuf>,
+    },
     /// Unpack objects from a packed archive
     UnpackObjects,
-    /// List references in a remote repository (only HEAD supported)
+    /// Create a packed archive of objects listed on stdin, written to stdout
+    PackObjects {
+        /// Read revisions instead (`<rev>` or `^<rev>`), and pack the objects
+        /// reachable from them
+        #[arg(long)]
+        revs: bool,
+    },
+    /// Build the pack index file for an existing packed archive
+    IndexPack {
+        /// The pack file (.idx is written next to it)
+        #[arg(required_unless_present = "stdin")]
+        pack_file: Option<PathBuf>,
+        /// Read the pack from stdin and store it in the object database
+        #[arg(long, conflicts_with = "pack_file")]
+        stdin: bool,
+    },
+    /// Check packed archives against their index
+    VerifyPack {
+        /// List the objects and statistics about delta chains
+        #[arg(short, long)]
+        verbose: bool,
+        /// Only show the statistics about delta chains
+        #[arg(short = 's', long)]
+        stat_only: bool,
+        /// The pack index files (or the pack files, or both without extension)
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// List references in a remote repository
     LsRemote {
-        /// The remote repository URL (must be HTTP)
-        repo: String,
-        /// The reference to list (must be HEAD)
-        pattern: String,
+        /// The remote repository: a URL or the name of a remote (default: the
+        /// remote of the current branch, or origin)
+        repo: Option<String>,
+        /// Only list references matching one of these patterns (matched from the end)
+        patterns: Vec<String>,
     },
     /// Clone a repository into a new directory
     Clone {
@@ -96,27 +736,877 @@ enum Commands {
         repo: String,
         /// The target directory (will be created if needed)
         directory: Option<PathBuf>,
+        /// Create a shallow clone with history truncated to this many commits
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        depth: Option<u32>,
+        /// Only check out files at the root (sparse-checkout in cone mode)
+        #[arg(long)]
+        sparse: bool,
+        /// Create a partial clone, eg --filter=blob:none
+        #[arg(long, value_name = "FILTER_SPEC")]
+        filter: Option<String>,
+        /// Also apply the filter to submodules
+        #[arg(long, requires = "filter")]
+        also_filter_submodules: bool,
+        /// Directory from which files are copied into .git
+        #[arg(long, value_name = "DIR")]
+        template: Option<PathBuf>,
+        /// Unpack all objects to loose storage instead of keeping the pack
+        #[arg(long)]
+        unpack: bool,
+        /// Make a bare repository, with the remote's branches and no checkout
+        #[arg(long, conflicts_with = "sparse")]
+        bare: bool,
+        /// Set a configuration value in the new repository, eg -c core.autocrlf=true
+        #[arg(short, long, value_name = "NAME=VALUE")]
+        config: Vec<String>,
+        /// Check out this branch instead of the one the remote HEAD points to
+        #[arg(short, long, value_name = "NAME")]
+        branch: Option<String>,
+        /// Only fetch the branch to check out, and tags pointing into its history
+        #[arg(long)]
+        single_branch: bool,
+    },
+    /// Update remote refs along with associated objects
+    Push {
+        /// Do not run the pre-push hook
+        #[arg(long)]
+        no_verify: bool,
+        /// The name of the remote (or a URL) to push to
+        #[arg(default_value = "origin")]
+        remote: String,
+        /// What to push: SRC or SRC:DST, + prefix to force (default: the current branch)
+        refspecs: Vec<String>,
+    },
+    /// Manage the remotes: repositories to fetch from and push to
+    Remote {
+        /// With no subcommand, also show the URLs
+        #[arg(short, long)]
+        verbose: bool,
+        #[command(subcommand)]
+        command: Option<RemoteCommands>,
+    },
+    /// Create, check or unpack bundles: history in a file, for offline transfer
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommands,
+    },
+    /// Write the history of references as a stream of commands for fast-import
+    FastExport {
+        /// Include all references
+        #[arg(long)]
+        all: bool,
+        /// References to include (eg main), or ^REV to exclude history
+        #[arg(required_unless_present = "all")]
+        revs: Vec<String>,
+    },
+    /// Import a stream of commands from fast-export, read from stdin
+    FastImport {
+        /// Update branches even if their history is lost
+        #[arg(long)]
+        force: bool,
+    },
+    /// List references (branches, tags...) with the hash they point to
+    ShowRef {
+        /// Only show branches (and tags if --tags is given)
+        #[arg(long)]
+        heads: bool,
+        /// Only show tags (and branches if --heads is given)
+        #[arg(long)]
+        tags: bool,
+        /// Also show what annotated tags point to, as TAG^{}
+        #[arg(short, long)]
+        dereference: bool,
+        /// Only show hashes
+        #[arg(short = 's', long)]
+        hash: bool,
+        /// Only show references whose name ends with one of these (whole components)
+        patterns: Vec<String>,
+    },
+    /// Move loose references to .git/packed-refs
+    PackRefs {
+        /// Pack all references, not only tags
+        #[arg(long)]
+        all: bool,
+    },
+    /// Low-level access to references
+    Refs {
+        #[command(subcommand)]
+        command: RefsCommands,
+    },
+    /// Download objects and refs from another repository
+    Fetch {
+        /// Show which references would be updated, without changing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// The name of the remote to fetch from
+        #[arg(default_value = "origin")]
+        remote: String,
+    },
+    /// Serve a repository to a client running on another machine,
+    /// talking to it on stdin and stdout
+    Serve {
+        #[command(subcommand)]
+        command: ServeCommands,
     },
 }
 use Commands::*;
 
+#[derive(Subcommand)]
+enum RefsCommands {
+    /// Check that all references point to valid objects
+    Verify {
+        /// Offer to repair broken references (from the reflog) or delete them
+        #[arg(long)]
+        fix: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ServeCommands {
+    /// Send archives to git archive --remote (run by it over SSH)
+    UploadArchive {
+        /// The repository to archive from
+        directory: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum RemoteCommands {
+    /// Add a remote, fetching all its branches to remote-tracking branches
+    Add {
+        /// The name of the remote
+        name: String,
+        /// Its URL
+        url: String,
+    },
+    /// Remove a remote, with its remote-tracking branches
+    #[command(alias = "rm")]
+    Remove {
+        /// The name of the remote
+        name: String,
+    },
+    /// Rename a remote, and its remote-tracking branches
+    Rename {
+        /// The current name of the remote
+        old: String,
+        /// The new name
+        new: String,
+    },
+    /// Show information about remotes (without querying them)
+    Show {
+        /// Do not query the remote (always the case)
+        #[arg(short = 'n')]
+        no_query: bool,
+        /// The names of the remotes
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+    /// Change the URL of a remote
+    SetUrl {
+        /// Change the URL to push to instead (remote.<name>.pushurl)
+        #[arg(long)]
+        push: bool,
+        /// The name of the remote
+        name: String,
+        /// The new URL
+        url: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum BundleCommands {
+    /// Write a bundle with the given references and the objects they need
+    Create {
+        /// Include all references, and HEAD
+        #[arg(long)]
+        all: bool,
+        /// The bundle file to write
+        file: PathBuf,
+        /// References to include (eg main), or ^REV to exclude history
+        #[arg(required_unless_present = "all")]
+        revs: Vec<String>,
+    },
+    /// Check that a bundle can be applied to this repository, and describe it
+    Verify {
+        /// The bundle file
+        file: PathBuf,
+    },
+    /// List the references in a bundle
+    ListHeads {
+        /// The bundle file
+        file: PathBuf,
+    },
+    /// Store the objects of a bundle, and list its references
+    Unbundle {
+        /// The bundle file
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum WorktreeCommands {
+    /// Create a worktree, and check out a branch (by default a new one named
+    /// after its directory) or a commit there
+    Add {
+        /// Create a new branch with this name
+        #[arg(short = 'b')]
+        branch: Option<String>,
+        /// Check out a detached HEAD, even if the commit is a branch
+        #[arg(long, conflicts_with = "branch")]
+        detach: bool,
+        /// Where to create the worktree
+        path: PathBuf,
+        /// The branch or commit to check out (default: HEAD)
+        commit_ish: Option<String>,
+    },
+    /// List the worktrees, with their HEAD and branch
+    List {
+        /// Use a format for scripts
+        #[arg(long)]
+        porcelain: bool,
+    },
+    /// Delete a worktree (not the main one)
+    Remove {
+        /// Remove it even with changes or untracked files
+        #[arg(short, long)]
+        force: bool,
+        /// The path of the worktree
+        worktree: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum NotesCommands {
+    /// Add a note to an object; an empty note removes it
+    Add {
+        /// Replace the existing note
+        #[arg(short, long)]
+        force: bool,
+        /// The note; several are separate paragraphs
+        #[arg(short, long, required = true)]
+        message: Vec<String>,
+        /// The object to annotate
+        #[arg(default_value = "HEAD")]
+        object: String,
+    },
+    /// Show the note of an object
+    Show {
+        #[arg(default_value = "HEAD")]
+        object: String,
+    },
+    /// List the notes and the objects they annotate (the default), or the
+    /// note of an object
+    List { object: Option<String> },
+}
+
+#[derive(Subcommand)]
+enum StashCommands {
+    /// Save changes to the index and tracked files, and reset them to HEAD (the default)
+    Push {
+        /// Describe the stash with this message
+        #[arg(short, long)]
+        message: Option<String>,
+    },
+    /// List stashes, most recent first
+    List,
+    /// Apply the changes from a stash on top of HEAD
+    Apply {
+        /// The stash to apply, eg stash@{1} (default: the latest)
+        stash: Option<String>,
+    },
+    /// Apply the changes from a stash, then drop it
+    Pop {
+        /// The stash to apply, eg stash@{1} (default: the latest)
+        stash: Option<String>,
+    },
+    /// Forget a stash
+    Drop {
+        /// The stash to drop, eg stash@{1} (default: the latest)
+        stash: Option<String>,
+    },
+}
+
+/// Tell if a command only reads from the repository, so it works on
+/// repositories the user can't write to, see discover_for_reading().
+fn only_reads(command: &Commands) -> bool {
+    matches!(
+        command,
+        CatFile { .. }
+            | LsTree { .. }
+            | LsFiles { .. }
+            | Status { .. }
+            | VerifyCommit { .. }
+            | VerifyTag { .. }
+            | FormatPatch { .. }
+            | Show { .. }
+            | Blame { .. }
+            | CheckAttr { .. }
+            | CheckMailmap { .. }
+            | CheckIgnore { .. }
+            | Describe { .. }
+            | Grep { .. }
+            | RevParse { .. }
+            | MergeBase { .. }
+            | Shortlog { .. }
+            | RevList { .. }
+            | SymbolicRef { target: None, .. }
+            | CountObjects { .. }
+            | Fsck { .. }
+            | Prune { dry_run: true, .. }
+            | Extract { .. }
+            | Reflog { .. }
+            | Stash {
+                command: Some(StashCommands::List)
+            }
+            | Undo { list: true, .. }
+            | Archive { .. }
+            | DedupReport { .. }
+            | Diff { .. }
+            | Refs {
+                command: RefsCommands::Verify { fix: false }
+            }
+            | ShowRef { .. }
+            | FastExport { .. }
+            | Remote {
+                command: None | Some(RemoteCommands::Show { .. }),
+                ..
+            }
+            | Bundle {
+                command: BundleCommands::Create { .. } | BundleCommands::Verify { .. }
+            }
+    )
+}
+
+/// Tell if a command has a JSON output, see --format.
+fn supports_json(command: &Commands) -> bool {
+    matches!(
+        command,
+        CatFile {
+            batch_check: true,
+            ..
+        } | LsTree { .. }
+            | LsRemote { .. }
+            | Status { .. }
+    )
+}
+
 fn main() -> anyhow::Result<()> {
     let args = Cli::parse();
-    match args.command {
-        Init { directory } => git_init(&directory)?,
-        CatFile { object } => cat_file_p(&object)?,
-        HashObject { write, file } => hash_object(&file, write)?,
-        LsTree { name_only, tree } => ls_tree(&tree, name_only)?,
-        WriteTree => write_tree()?,
+    if args.format == "json" && !supports_json(&args.command) {
+        bail!("--format=json is only supported by ls-tree, ls-remote, cat-file --batch-check and status");
+    }
+    config::set_overrides(&args.config)?;
+    pager::set_disabled(args.no_pager);
+    json::set_enabled(args.format == "json");
+    network::set_offline(args.offline || env::var_os("GIT_OFFLINE").is_some_and(|v| v == "1"));
+    if let Some(Some(len)) = args.abbrev {
+        abbrev::set_len(len);
+    }
+    // Commands that work outside of a repository.
+    let command = match args.command {
+        Init {
+            object_format,
+            bare,
+            directory,
+        } => {
+            git_init(&directory, HashAlgo::from_name(&object_format)?, bare)?;
+            return Ok(());
+        }
+        LsRemote { repo, patterns } => {
+            let found = Repository::discover().ok();
+            return ls_remote(found.as_ref(), repo.as_deref(), &patterns);
+        }
+        Clone {
+            repo,
+            directory,
+            depth,
+            sparse,
+            filter,
+            also_filter_submodules,
+            template,
+            unpack,
+            bare,
+            config,
+            branch,
+            single_branch,
+        } => {
+            let options = CloneOptions {
+                depth,
+                sparse,
+                filter,
+                also_filter_submodules,
+                template,
+                unpack,
+                bare,
+                config,
+                branch,
+                single_branch,
+            };
+            return clone(&repo, directory.as_deref(), &options);
+        }
+        Bundle {
+            command: BundleCommands::ListHeads { file },
+        } => return bundle_list_heads(&file),
+        Archive {
+            format,
+            prefix,
+            output,
+            remote: Some(remote),
+            exec,
+            tree_ish,
+        } => {
+            return archive_remote(
+                &remote,
+                exec.as_deref(),
+                &tree_ish,
+                format.as_deref(),
+                &prefix,
+                output.as_deref(),
+            )
+        }
+        Serve {
+            command: ServeCommands::UploadArchive { directory },
+        } => return serve_upload_archive(&directory),
+        command => command,
+    };
+    let repo = &match only_reads(&command) {
+        true => Repository::discover_for_reading()?,
+        false => Repository::discover()?,
+    };
+    match command {
+        Init { .. }
+        | LsRemote { .. }
+        | Clone { .. }
+        | Archive {
+            remote: Some(_), ..
+        }
+        | Serve { .. } => unreachable!("handled above"),
+        // Without an object, --batch-check is required by clap.
+        CatFile { object, .. } => match object {
+            Some(object) => cat_file_p(repo, &object)?,
+            None => cat_file_batch_check(repo)?,
+        },
+        HashObject { write, stdin, file } => match stdin {
+            true => hash_object_stdin(repo, write)?,
+            false => hash_object(repo, &file.expect("required by clap"), write)?,
+        },
+        Mktag => mktag(repo)?,
+        Mktree => mktree(repo)?,
+        LsTree {
+            name_only,
+            full_tree,
+            tree,
+        } => ls_tree(repo, &tree, name_only, full_tree, args.abbrev.is_some())?,
+        LsFiles {
+            cached,
+            stage,
+            others,
+            ignored,
+            exclude_standard,
+        } => ls_files(
+            repo,
+            &LsFilesOptions {
+                cached,
+                stage,
+                others,
+                ignored,
+                exclude_standard,
+                abbrev: args.abbrev.is_some(),
+            },
+        )?,
+        Add { patch: _, paths } => add_patch(repo, &paths)?,
+        Rm {
+            cached,
+            recursive,
+            force,
+            paths,
+        } => rm(repo, &paths, cached, recursive, force)?,
+        Mv { from, to } => mv(repo, &from, &to)?,
+        Clean {
+            dry_run,
+            force,
+            dirs,
+            ignored,
+            only_ignored,
+            quiet,
+            paths,
+        } => {
+            let ignored = match (ignored, only_ignored) {
+                (true, _) => IgnoredFiles::Remove,
+                (_, true) => IgnoredFiles::Only,
+                _ => IgnoredFiles::Keep,
+            };
+            let options = CleanOptions {
+                dry_run,
+                force,
+                dirs,
+                ignored,
+                quiet,
+            };
+            clean(repo, &paths, &options)?
+        }
+        Status {
+            porcelain: _,
+            nul,
+            untracked_files,
+        } => status(
+            repo,
+            &StatusOptions {
+                nul,
+                untracked: untracked_files,
+            },
+        )?,
+        Config {
+            get,
+            get_all,
+            add,
+            list,
+            value_type,
+            key,
+            value,
+        } => {
+            let action = match (get, get_all, add, list, &value) {
+                (true, _, _, _, _) => ConfigAction::Get,
+                (_, true, _, _, _) => ConfigAction::GetAll,
+                (_, _, true, _, _) => ConfigAction::Add,
+                (_, _, _, true, _) => ConfigAction::List,
+                (_, _, _, _, None) => ConfigAction::Get,
+                (_, _, _, _, Some(_)) => ConfigAction::Set,
+            };
+            if !config(
+                repo,
+                action,
+                key.as_deref(),
+                value.as_deref(),
+                value_type.as_deref(),
+            )? {
+                process::exit(1);
+            }
+        }
+        WriteTree => write_tree(repo)?,
         CommitTree {
             parent,
             message,
+            gpg_sign,
+            no_gpg_sign,
             tree,
-        } => commit_tree(&tree, &parent, &message)?,
-        CheckoutEmpty { commit } => checkout_empty(&commit)?,
-        UnpackObjects => unpack_objects()?,
-        LsRemote { repo, pattern } => ls_remote(&repo, &pattern)?,
-        Clone { repo, directory } => clone(&repo, directory.as_ref())?,
+        } => commit_tree(
+            repo,
+            &tree,
+            &parent,
+            &message,
+            gpg_sign.as_deref(),
+            no_gpg_sign,
+        )?,
+        VerifyCommit { commits } => {
+            if !verify_commit(repo, &commits)? {
+                process::exit(1);
+            }
+        }
+        VerifyTag { tags } => {
+            if !verify_tag(repo, &tags)? {
+                process::exit(1);
+            }
+        }
+        Snapshot { message, no_verify } => snapshot(repo, &message, !no_verify)?,
+        Merge { branch } => merge(repo, &branch)?,
+        CherryPick { commit } => cherry_pick(repo, &commit)?,
+        Revert { commit } => revert(repo, &commit)?,
+        Rebase { upstream } => rebase(repo, &upstream)?,
+        FormatPatch {
+            output_directory,
+            stdout,
+            signature,
+            no_signature,
+            range,
+        } => {
+            let signature = if no_signature {
+                Some("")
+            } else {
+                signature.as_deref()
+            };
+            format_patch(repo, &range, output_directory.as_deref(), stdout, signature)?
+        }
+        Am { mailboxes } => am(repo, &mailboxes)?,
+        Show {
+            color,
+            no_color,
+            revs,
+        } => show(
+            repo,
+            &revs,
+            if no_color { Some(When::Never) } else { color },
+        )?,
+        Blame { path } => blame(repo, &path)?,
+        CheckAttr {
+            mut attributes,
+            mut paths,
+        } => {
+            // Without "--", only the first argument is an attribute.
+            if paths.is_empty() {
+                paths = attributes.drain(1..).map(PathBuf::from).collect();
+            }
+            check_attr(repo, &attributes, &paths)?
+        }
+        CheckMailmap { stdin, contacts } => check_mailmap(repo, &contacts, stdin)?,
+        CheckIgnore { verbose, paths } => {
+            if !check_ignore(repo, &paths, verbose)? {
+                process::exit(1);
+            }
+        }
+        Describe { tags, dirty } => git_describe(repo, tags, dirty.as_deref())?,
+        Grep {
+            untracked,
+            threads,
+            pattern,
+            rev,
+        } => {
+            if !grep(repo, &pattern, rev.as_deref(), untracked, threads)? {
+                process::exit(1);
+            }
+        }
+        RevParse {
+            verify,
+            short,
+            git_dir,
+            git_common_dir,
+            show_prefix,
+            is_bare_repository,
+            revs,
+        } => {
+            let options = RevParseOptions {
+                verify,
+                short,
+                show_git_dir: git_dir,
+                show_common_dir: git_common_dir,
+                show_prefix,
+                show_bare: is_bare_repository,
+            };
+            rev_parse(repo, &revs, &options)?
+        }
+        MergeBase {
+            all,
+            is_ancestor,
+            one,
+            two,
+        } => {
+            if !show_merge_base(repo, &one, &two, all, is_ancestor)? {
+                process::exit(1);
+            }
+        }
+        Shortlog {
+            summary,
+            numbered,
+            email,
+            revs,
+        } => shortlog(repo, &revs, summary, numbered, email)?,
+        RevList {
+            objects,
+            revs,
+            not,
+            since,
+            until,
+            author,
+            grep,
+        } => {
+            let filter = Filter {
+                since,
+                until,
+                author,
+                grep,
+            };
+            rev_list(repo, &revs, &not, objects, &filter)?
+        }
+        SymbolicRef {
+            short,
+            quiet,
+            name,
+            target,
+        } => {
+            if !symbolic_ref(repo, &name, target.as_deref(), short, quiet)? {
+                process::exit(1);
+            }
+        }
+        UpdateRef {
+            delete,
+            no_deref,
+            message,
+            name,
+            values,
+        } => update_ref(repo, &name, &values, delete, no_deref, &message)?,
+        CountObjects { verbose } => count_objects(repo, verbose)?,
+        Fsck { unreachable } => fsck(repo, unreachable)?,
+        Gc { prune, no_prune } => gc(repo, &prune, no_prune)?,
+        Prune {
+            dry_run,
+            verbose,
+            expire,
+        } => prune(repo, dry_run, verbose, expire.as_deref())?,
+        CheckoutEmpty { commit } => checkout_empty(repo, &commit)?,
+        Extract {
+            tree_ish,
+            pathspecs,
+            dest,
+        } => extract_paths(repo, &tree_ish, &pathspecs, &dest)?,
+        Reflog { max_count, name } => reflog(repo, &name, max_count)?,
+        Reset {
+            soft,
+            mixed: _,
+            hard,
+            commit,
+        } => {
+            let mode = match (soft, hard) {
+                (true, _) => ResetMode::Soft,
+                (_, true) => ResetMode::Hard,
+                _ => ResetMode::Mixed,
+            };
+            reset(repo, &commit, mode)?
+        }
+        Stash { command } => match command {
+            None => stash_push(repo, None)?,
+            Some(StashCommands::Push { message }) => stash_push(repo, message.as_deref())?,
+            Some(StashCommands::List) => stash_list(repo)?,
+            Some(StashCommands::Apply { stash }) => stash_apply(repo, stash.as_deref(), false)?,
+            Some(StashCommands::Pop { stash }) => stash_apply(repo, stash.as_deref(), true)?,
+            Some(StashCommands::Drop { stash }) => stash_drop(repo, stash.as_deref())?,
+        },
+        Worktree { command } => match command {
+            WorktreeCommands::Add {
+                branch,
+                detach,
+                path,
+                commit_ish,
+            } => worktree_add(
+                repo,
+                &path,
+                commit_ish.as_deref(),
+                branch.as_deref(),
+                detach,
+            )?,
+            WorktreeCommands::List { porcelain } => worktree_list(repo, porcelain)?,
+            WorktreeCommands::Remove { force, worktree } => {
+                worktree_remove(repo, &worktree, force)?
+            }
+        },
+        Notes { notes_ref, command } => {
+            let notes_ref = notes_ref.as_deref();
+            match command {
+                Some(NotesCommands::Add {
+                    force,
+                    message,
+                    object,
+                }) => notes_add(repo, notes_ref, &object, &message, force)?,
+                Some(NotesCommands::Show { object }) => notes_show(repo, notes_ref, &object)?,
+                Some(NotesCommands::List { object }) => {
+                    notes_list(repo, notes_ref, object.as_deref())?
+                }
+                None => notes_list(repo, notes_ref, None)?,
+            }
+        }
+        Undo {
+            list,
+            worktree,
+            steps,
+        } => undo(repo, steps, list, worktree)?,
+        Archive {
+            format,
+            prefix,
+            output,
+            tree_ish,
+            ..
+        } => archive(
+            repo,
+            &tree_ish,
+            format.as_deref(),
+            &prefix,
+            output.as_deref(),
+        )?,
+        DedupReport { depth } => dedup(repo, depth)?,
+        Diff {
+            cached,
+            find_renames,
+            no_renames,
+            binary,
+            color,
+            no_color,
+            word_diff,
+            color_words,
+            commits,
+        } => {
+            let color = if no_color { Some(When::Never) } else { color };
+            let word_diff = match color_words {
+                true => WordDiff::Color,
+                false => word_diff.unwrap_or_default(),
+            };
+            let options = DiffOptions {
+                cached,
+                find_renames,
+                no_renames,
+                binary,
+                color,
+                word_diff,
+            };
+            diff(repo, &options, &commits)?
+        }
+        Apply {
+            cached,
+            check,
+            strip,
+            context,
+            patches,
+        } => apply(repo, &patches, cached, check, strip, context)?,
+        UnpackObjects => unpack_objects(repo)?,
+        PackObjects { revs } => pack_objects(repo, revs)?,
+        IndexPack { pack_file, .. } => index_pack(repo, pack_file.as_deref())?,
+        VerifyPack {
+            verbose,
+            stat_only,
+            paths,
+        } => verify_pack(repo, &paths, verbose, stat_only)?,
+        Refs {
+            command: RefsCommands::Verify { fix },
+        } => refs_verify(repo, fix)?,
+        ShowRef {
+            heads,
+            tags,
+            dereference,
+            hash,
+            patterns,
+        } => {
+            if !show_ref(repo, &patterns, heads, tags, dereference, hash)? {
+                process::exit(1);
+            }
+        }
+        FastExport { all, revs } => fast_export(repo, &revs, all)?,
+        FastImport { force } => {
+            if !fast_import(repo, force)? {
+                process::exit(1);
+            }
+        }
+        PackRefs { all } => pack_refs(repo, all)?,
+        Remote { verbose, command } => match command {
+            None => remote_list(repo, verbose)?,
+            Some(RemoteCommands::Add { name, url }) => remote_add(repo, &name, &url)?,
+            Some(RemoteCommands::Remove { name }) => remote_remove(repo, &name)?,
+            Some(RemoteCommands::Rename { old, new }) => remote_rename(repo, &old, &new)?,
+            Some(RemoteCommands::Show { no_query: _, names }) => remote_show(repo, &names)?,
+            Some(RemoteCommands::SetUrl { push, name, url }) => {
+                remote_set_url(repo, &name, &url, push)?
+            }
+        },
+        Bundle { command } => match command {
+            BundleCommands::Create { all, file, revs } => bundle_create(repo, &file, &revs, all)?,
+            BundleCommands::Verify { file } => bundle_verify(repo, &file)?,
+            BundleCommands::ListHeads { .. } => unreachable!("handled above"),
+            BundleCommands::Unbundle { file } => bundle_unbundle(repo, &file)?,
+        },
+        Fetch { dry_run, remote } => fetch(repo, &remote, dry_run)?,
+        Push {
+            no_verify,
+            remote,
+            refspecs,
+        } => push(repo, &remote, &refspecs, !no_verify)?,
     }
 
     Ok(())
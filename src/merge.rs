@@ -0,0 +1,382 @@
+//! Three-way merge of trees, and of file contents.
+//!
+//! Paths are merged one by one: if only one side changed a path, that side
+//! wins. If both did, text files are merged line by line, with conflict
+//! markers where both sides changed the same lines. Renames are not detected,
+//! and directory/file conflicts are not supported.
+
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::diff::{diff_lines, flatten_tree, Op, PathList, Side};
+use crate::obj_read::read_blob;
+use crate::obj_type::ObjType;
+use crate::obj_write::write_object;
+use crate::repository::Repository;
+use crate::tree_entry::Mode;
+
+/// Width of conflict markers, same as git.
+const MARKER_LEN: usize = 7;
+
+/// A path that could not be merged automatically.
+pub struct Conflict {
+    pub path: Vec<u8>,
+    pub base: Option<Side>,
+    pub ours: Option<Side>,
+    pub theirs: Option<Side>,
+    /// What to leave in the worktree: ours or theirs, or a file with conflict markers
+    pub worktree: Side,
+}
+
+/// The result of merging trees.
+pub struct MergeResult {
+    /// Paths merged cleanly, sorted in index order
+    pub merged: PathList,
+    /// Paths with conflicts, sorted in index order
+    pub conflicts: Vec<Conflict>,
+}
+
+impl MergeResult {
+    /// What to put in the worktree: merged paths and conflicted files,
+    /// sorted in index order.
+    pub fn worktree(&self) -> PathList {
+        let mut list = self.merged.clone();
+        list.extend(
+            self.conflicts
+                .iter()
+                .map(|c| (c.path.clone(), c.worktree.clone())),
+        );
+        list.sort_by(|a, b| a.0.cmp(&b.0));
+        list
+    }
+}
+
+/// Tell if two sides have the same content (both missing counts as the same).
+fn same(a: Option<&Side>, b: Option<&Side>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.hash == b.hash && a.mode == b.mode,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Merge trees: the changes from `base` to `theirs` are applied on top of
+/// `ours`. Without a base (unrelated histories), paths present on both sides
+/// with different contents are merged as if added on both sides.
+///
+/// Prints what is happening, like git, using `our_name` and `their_name`
+/// to refer to each side (eg "HEAD" and the name of the merged branch).
+pub fn merge_trees(
+    repo: &Repository,
+    base: Option<&str>,
+    ours: &str,
+    theirs: &str,
+    our_name: &str,
+    their_name: &str,
+) -> Result<MergeResult> {
+    let mut paths: BTreeMap<Vec<u8>, [Option<Side>; 3]> = BTreeMap::new();
+    for (i, tree) in [base, Some(ours), Some(theirs)].into_iter().enumerate() {
+        let Some(tree) = tree else { continue };
+        let mut list = PathList::new();
+        flatten_tree(repo, tree, b"", &mut list)?;
+        for (path, side) in list {
+            paths.entry(path).or_default()[i] = Some(side);
+        }
+    }
+
+    let mut result = MergeResult {
+        merged: PathList::new(),
+        conflicts: Vec::new(),
+    };
+    for (path, [b, o, t]) in paths {
+        let name = String::from_utf8_lossy(&path).into_owned();
+        let side = if same(o.as_ref(), t.as_ref()) || same(b.as_ref(), t.as_ref()) {
+            o
+        } else if same(b.as_ref(), o.as_ref()) {
+            t
+        } else {
+            match (b, o, t) {
+                (b, Some(o), Some(t)) => {
+                    let kind = if b.is_some() { "content" } else { "add/add" };
+                    match merge_files(repo, b.as_ref(), &o, &t, [our_name, their_name], &name)? {
+                        Ok(merged) => Some(merged),
+                        Err(worktree) => {
+                            println!("CONFLICT ({kind}): Merge conflict in {name}");
+                            result.conflicts.push(Conflict {
+                                path: path.clone(),
+                                base: b,
+                                ours: Some(o),
+                                theirs: Some(t),
+                                worktree,
+                            });
+                            None
+                        }
+                    }
+                }
+                (b, o, t) => {
+                    // One side deleted the file, the other modified it: keep that one.
+                    let (deleted_in, modified_in, kept) = match (&o, &t) {
+                        (Some(o), None) => (their_name, our_name, o.clone()),
+                        (None, Some(t)) => (our_name, their_name, t.clone()),
+                        _ => unreachable!("deleted on both sides is not a conflict"),
+                    };
+                    println!(
+                        "CONFLICT (modify/delete): {name} deleted in {deleted_in} and modified in \
+                         {modified_in}. Version {modified_in} of {name} left in tree."
+                    );
+                    result.conflicts.push(Conflict {
+                        path: path.clone(),
+                        base: b,
+                        ours: o,
+                        theirs: t,
+                        worktree: kept,
+                    });
+                    None
+                }
+            }
+        };
+        if let Some(side) = side {
+            result.merged.push((path, side));
+        }
+    }
+
+    // A path can't be both a file and a directory.
+    let list = result.worktree();
+    for pair in list.windows(2) {
+        let (file, other) = (&pair[0].0, &pair[1].0);
+        if other.starts_with(file) && other.get(file.len()) == Some(&b'/') {
+            bail!(
+                "directory/file conflict at {}: not supported",
+                String::from_utf8_lossy(file)
+            );
+        }
+    }
+    Ok(result)
+}
+
+/// Merge two versions of a file which both changed.
+/// Return the merged version, or what to leave in the worktree if there are conflicts.
+fn merge_files(
+    repo: &Repository,
+    base: Option<&Side>,
+    ours: &Side,
+    theirs: &Side,
+    names: [&str; 2],
+    name: &str,
+) -> Result<Result<Side, Side>> {
+    // Only the mode changed on one side, or both changed it in the same way.
+    let mode = match base {
+        Some(base) if base.mode == ours.mode => theirs.mode.clone(),
+        _ => ours.mode.clone(),
+    };
+    if ours.hash == theirs.hash || base.is_some_and(|b| b.hash == theirs.hash) {
+        return Ok(Ok(Side {
+            mode,
+            ..ours.clone()
+        }));
+    }
+    if base.is_some_and(|b| b.hash == ours.hash) {
+        return Ok(Ok(Side {
+            mode,
+            ..theirs.clone()
+        }));
+    }
+    let regular = |side: &Side| matches!(side.mode, Mode::File | Mode::Exe);
+    if !regular(ours) || !regular(theirs) || base.is_some_and(|b| !regular(b)) {
+        // Symbolic links and submodules can't be merged.
+        return Ok(Err(ours.clone()));
+    }
+
+    println!("Auto-merging {name}");
+    let base_data = base
+        .map(|b| read_blob(repo, &b.hash))
+        .transpose()?
+        .unwrap_or_default();
+    let ours_data = read_blob(repo, &ours.hash)?;
+    let theirs_data = read_blob(repo, &theirs.hash)?;
+    if [&base_data, &ours_data, &theirs_data]
+        .iter()
+        .any(|d| d.contains(&0))
+    {
+        let [our_name, their_name] = names;
+        println!("warning: Cannot merge binary files: {name} ({our_name} vs. {their_name})");
+        return Ok(Err(ours.clone()));
+    }
+    let (merged, clean) = merge_lines(&base_data, &ours_data, &theirs_data, names);
+    let hash = write_object(repo, ObjType::Blob, &mut io::Cursor::new(merged), true)?;
+    let side = Side {
+        mode,
+        hash,
+        file: None,
+    };
+    Ok(if clean { Ok(side) } else { Err(side) })
+}
+
+/// A change from the base: lines base\[start..end\] replaced by `lines`.
+struct Edit<'a> {
+    start: usize,
+    end: usize,
+    lines: Vec<&'a [u8]>,
+}
+
+/// Turn the differences between base and another version into a list of edits.
+fn edits<'a>(base: &[&[u8]], other: &[&'a [u8]]) -> Vec<Edit<'a>> {
+    let mut edits: Vec<Edit> = Vec::new();
+    let mut pos = 0;
+    let mut in_edit = false;
+    for op in diff_lines(base, other) {
+        match op {
+            Op::Equal(i, _) => {
+                pos = i + 1;
+                in_edit = false;
+                continue;
+            }
+            _ if !in_edit => {
+                edits.push(Edit {
+                    start: pos,
+                    end: pos,
+                    lines: Vec::new(),
+                });
+                in_edit = true;
+            }
+            _ => {}
+        }
+        let edit = edits.last_mut().expect("just pushed");
+        match op {
+            Op::Delete(i) => {
+                edit.end = i + 1;
+                pos = i + 1;
+            }
+            Op::Insert(j) => edit.lines.push(other[j]),
+            Op::Equal(..) => unreachable!(),
+        }
+    }
+    edits
+}
+
+/// Apply some edits, all within base\[start..end\], to that part of the base.
+fn apply<'a>(base: &[&'a [u8]], edits: &[Edit<'a>], start: usize, end: usize) -> Vec<&'a [u8]> {
+    let mut out = Vec::new();
+    let mut pos = start;
+    for edit in edits {
+        out.extend_from_slice(&base[pos..edit.start]);
+        out.extend_from_slice(&edit.lines);
+        pos = edit.end;
+    }
+    out.extend_from_slice(&base[pos..end]);
+    out
+}
+
+/// Merge the content of text files, line by line. Changes from both sides
+/// that touch or overlap are conflicts, unless they are identical.
+/// Return the result, with conflict markers if any, and whether it's clean.
+/// Markers are labelled with the names of our and their side.
+fn merge_lines(base: &[u8], ours: &[u8], theirs: &[u8], names: [&str; 2]) -> (Vec<u8>, bool) {
+    let split = |data| -> Vec<&[u8]> { <[u8]>::split_inclusive(data, |&c| c == b'\n').collect() };
+    let (base, ours, theirs) = (split(base), split(ours), split(theirs));
+    let mut ours_edits = edits(&base, &ours).into_iter().peekable();
+    let mut theirs_edits = edits(&base, &theirs).into_iter().peekable();
+
+    let mut out: Vec<u8> = Vec::new();
+    let mut clean = true;
+    let mut pos = 0;
+    loop {
+        // Start a group of overlapping edits with the first one from either side.
+        let first_is_ours = match (ours_edits.peek(), theirs_edits.peek()) {
+            (None, None) => break,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (Some(o), Some(t)) => o.start <= t.start,
+        };
+        let first = match first_is_ours {
+            true => ours_edits.next(),
+            false => theirs_edits.next(),
+        };
+        let first = first.expect("peeked");
+        let (start, mut end) = (first.start, first.end);
+        let (mut group_ours, mut group_theirs) = (Vec::new(), Vec::new());
+        if first_is_ours {
+            group_ours.push(first);
+        } else {
+            group_theirs.push(first);
+        }
+        // Edits from the same side never touch, as there are unchanged lines
+        // between them, so this only adds edits touching one from the other side.
+        loop {
+            if let Some(edit) = ours_edits.next_if(|e| e.start <= end) {
+                end = end.max(edit.end);
+                group_ours.push(edit);
+            } else if let Some(edit) = theirs_edits.next_if(|e| e.start <= end) {
+                end = end.max(edit.end);
+                group_theirs.push(edit);
+            } else {
+                break;
+            }
+        }
+
+        for line in &base[pos..start] {
+            out.extend_from_slice(line);
+        }
+        pos = end;
+        let ours_part = apply(&base, &group_ours, start, end);
+        let theirs_part = apply(&base, &group_theirs, start, end);
+        if group_theirs.is_empty() || ours_part == theirs_part {
+            ours_part
+                .iter()
+                .for_each(|line| out.extend_from_slice(line));
+            continue;
+        }
+        if group_ours.is_empty() {
+            theirs_part
+                .iter()
+                .for_each(|line| out.extend_from_slice(line));
+            continue;
+        }
+
+        // Conflict: lines common to both sides at the start and end are not part of it.
+        clean = false;
+        let prefix = ours_part
+            .iter()
+            .zip(&theirs_part)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = ours_part[prefix..]
+            .iter()
+            .rev()
+            .zip(theirs_part[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let marker = |out: &mut Vec<u8>, c: u8, label: &str| {
+            out.extend_from_slice(&[c; MARKER_LEN]);
+            if !label.is_empty() {
+                out.push(b' ');
+                out.extend_from_slice(label.as_bytes());
+            }
+            out.push(b'\n');
+        };
+        let lines = |out: &mut Vec<u8>, lines: &[&[u8]]| {
+            for line in lines {
+                out.extend_from_slice(line);
+                if !line.ends_with(b"\n") {
+                    out.push(b'\n');
+                }
+            }
+        };
+        ours_part[..prefix]
+            .iter()
+            .for_each(|line| out.extend_from_slice(line));
+        marker(&mut out, b'<', names[0]);
+        lines(&mut out, &ours_part[prefix..ours_part.len() - suffix]);
+        marker(&mut out, b'=', "");
+        lines(&mut out, &theirs_part[prefix..theirs_part.len() - suffix]);
+        marker(&mut out, b'>', names[1]);
+        ours_part[ours_part.len() - suffix..]
+            .iter()
+            .for_each(|line| out.extend_from_slice(line));
+    }
+    for line in &base[pos..] {
+        out.extend_from_slice(line);
+    }
+    (out, clean)
+}
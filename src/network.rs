@@ -1,30 +1,59 @@
-//! The subset of the Git v2 protocol (over HTTP) that is used to minimally clone.
+//! The subset of the Git v2 protocol (over HTTP, git:// or SSH) that is used
+//! to minimally clone and fetch.
 //!
 //! References:
 //! - gitprotocol-common(5) <https://git-scm.com/docs/gitprotocol-common>
 //! - gitprotocol-v2(5) <https://git-scm.com/docs/gitprotocol-v2>
 //!
-//! Note: compared to the documentation, we skip the discovery phase,
-//! and just assume the server implements the smart HTTP protocol v2.
+//! Note: compared to the documentation, the discovery phase over HTTP only
+//! tells if the server implements the smart HTTP protocol (assumed to be v2),
+//! falling back to the dumb protocol otherwise, see dumb_http.
+//! Each request uses a new connection, even with stateful transports.
+//!
+//! Pushing is not part of protocol v2, so it uses the original protocol,
+//! see gitprotocol-pack(5) "Pushing Data To a Server" and gitprotocol-http(5);
+//! it is only supported over HTTP.
+//!
+//! Getting archives uses the upload-archive service, which is not part of
+//! protocol v2 either, see upload_archive().
 
 use anyhow::{bail, Context, Result};
 use reqwest::blocking::{Client, Response};
 use reqwest::header::{HeaderMap, HeaderValue};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 use std::io;
 use std::io::prelude::*;
+use std::net::TcpStream;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
 use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::clone;
+use crate::common::sq_quote;
+use crate::dumb_http;
+use crate::interrupt;
+use crate::repository::Repository;
+use crate::shallow;
 
 fn io_err_invalid(msg: &str) -> io::Error {
     io::Error::new(io::ErrorKind::InvalidData, msg)
 }
 
-/// Read the length of a packet line, see gitprotocol-common(5) "pkt-line Format".
-/// Return the length of the following data (excluding the length bytes).
-///
-/// Note: there are more than one special packet (for example 0001 is delimiter),
-/// so in principle with should use a dedicated enum. But since we only need one,
-/// we use a simple Option with None representing flush-pkt.
-fn read_pkt_line_len(src: &mut impl Read) -> io::Result<Option<usize>> {
+/// The length field of a pkt-line, see gitprotocol-common(5) "pkt-line Format",
+/// and gitprotocol-v2(5) "Packet-Line Framing" for special packets.
+enum PktLen {
+    /// flush-pkt (0000): end of message
+    Flush,
+    /// delim-pkt (0001): separates sections of a message
+    Delim,
+    /// Normal packet, with the length of the data (excluding the length bytes)
+    Data(usize),
+}
+
+/// Read the length of a packet line.
+fn read_pkt_len(src: &mut impl Read) -> io::Result<PktLen> {
     let mut buf = [0; 4];
     src.read_exact(&mut buf)?;
     let Ok(len) = str::from_utf8(&buf) else {
@@ -34,19 +63,25 @@ fn read_pkt_line_len(src: &mut impl Read) -> io::Result<Option<usize>> {
         return Err(io_err_invalid("invalid pkt-line length: not hex"));
     };
 
-    if len == 0 {
-        return Ok(None);
+    match len {
+        0 => Ok(PktLen::Flush),
+        1 => Ok(PktLen::Delim),
+        len if len < 4 => Err(io_err_invalid(&format!("invalid pkt-line length: {len}"))),
+        len => Ok(PktLen::Data(len - 4)),
     }
+}
 
-    if len < 4 {
-        return Err(io_err_invalid(&format!("invalid pkt-line length: {}", len)));
+/// Read the length of a packet line, where the only special packet allowed
+/// is flush-pkt, represented as None.
+fn read_pkt_line_len(src: &mut impl Read) -> io::Result<Option<usize>> {
+    match read_pkt_len(src)? {
+        PktLen::Flush => Ok(None),
+        PktLen::Delim => Err(io_err_invalid("unexpected delim-pkt")),
+        PktLen::Data(len) => Ok(Some(len)),
     }
-    let len = len - 4;
-
-    Ok(Some(len))
 }
 
-/// Filter wrapping a Response to a fetch request and returning the bytes of the packfile.
+/// Filter wrapping the response to a fetch request and returning the bytes of the packfile.
 ///
 /// The response to the fetch request is in pkt-line format, with the first line
 /// indicating a packfile, and the following lines divided into multiple streams:
@@ -57,11 +92,11 @@ fn read_pkt_line_len(src: &mut impl Read) -> io::Result<Option<usize>> {
 /// Assume no-progress has been used in the request, so we only read from channel #1
 /// and treat everything else as a fatal error.
 ///
-/// This reader checks the first line, and returns the content from channel #1,
+/// This reader returns the content from channel #1,
 /// until the first flush-pkt, signaling EOF.
 ///
 /// It implements BufRead for the benefit of the zlib decompressor in the unpack module.
-struct PackFileReader {
+pub struct PackFileReader {
     /// Internal buffer
     buf: Vec<u8>,
     /// Position of the next byte to return in the buffer
@@ -71,37 +106,20 @@ struct PackFileReader {
     /// Remaining bytes in the current pkt-line
     rem: usize,
     /// Internal reader
-    src: Response,
+    src: Box<dyn Read>,
 }
 
 impl PackFileReader {
-    /// Create a packfile reader from a Response to a fetch request (with no-progress).
-    fn new(mut resp: Response) -> Result<Self> {
-        let mut buf = vec![0u8; 8192];
-
-        let len = read_pkt_line_len(&mut resp)
-            .context("reading first pkt-line")?
-            .context("first pkt-line was a flush")?;
-
-        // Check the first line: we expect "packfile" or "packfile\n".
-        if len > buf.len() {
-            bail!("overly long first pkt-line, exp. 9 got {}", len);
-        }
-
-        resp.read_exact(&mut buf[..len])?;
-
-        let len = if buf[len - 1] == b'\n' { len - 1 } else { len };
-        if &buf[..len] != b"packfile" {
-            bail!("expected 'packfile', got '{:?}'", &buf[..len]);
-        }
-
-        Ok(Self {
-            buf,
+    /// Create a packfile reader from the response to a fetch request (with no-progress),
+    /// positioned just after the "packfile" section header.
+    fn new(resp: Box<dyn Read>) -> Self {
+        Self {
+            buf: vec![0u8; 8192],
             pos: 0,
             cap: 0,
             rem: 0,
             src: resp,
-        })
+        }
     }
 }
 
@@ -126,6 +144,7 @@ impl BufRead for PackFileReader {
             }
 
             // Start a new pkt-line
+            interrupt::check_io()?;
             let line_len = read_pkt_line_len(&mut self.src)?;
             let Some(line_len) = line_len else {
                 // Flush means EOF, which we signal with empty slice
@@ -175,14 +194,109 @@ impl Read for PackFileReader {
     }
 }
 
-/// Make a request to the git-upload-pack service of protocol v2.
-pub fn request_upload_pack_v2(repo_url: &str, body: &str) -> Result<Response> {
+/// Network access policy: when set, all transports fail before connecting.
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable offline mode.
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+/// Fail if network access is disabled.
+/// Every code path that touches the network must go through this.
+pub fn ensure_online(url: &str) -> Result<()> {
+    if OFFLINE.load(Ordering::Relaxed) {
+        bail!("network access disabled (--offline or GIT_OFFLINE=1), not contacting {url}");
+    }
+    Ok(())
+}
+
+/// Get an HTTP client for a request to the given URL, if allowed.
+fn http_client(url: &str) -> Result<Client> {
+    ensure_online(url)?;
+    Ok(Client::new())
+}
+
+/// Download a file from a server exposing the layout of a .git directory
+/// over HTTP, as used by the dumb protocol, eg "objects/info/packs".
+/// Return None if the server doesn't have it.
+pub fn get_file(base_url: &str, path: &str) -> Result<Option<Response>> {
+    let request_url = format!("{}/{path}", base_url.trim_end_matches('/'));
+    let response = http_client(&request_url)?
+        .get(&request_url)
+        .send()
+        .with_context(|| format!("requesting {request_url}"))?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let response = response
+        .error_for_status()
+        .with_context(|| format!("requesting {request_url}"))?;
+    Ok(Some(response))
+}
+
+/// Download a loose object (still compressed), see get_file().
+pub fn get_loose_object(base_url: &str, hash: &str) -> Result<Option<Vec<u8>>> {
+    let path = format!("objects/{}/{}", &hash[..2], &hash[2..]);
+    let Some(response) = get_file(base_url, &path)? else {
+        return Ok(None);
+    };
+    let body = response
+        .bytes()
+        .with_context(|| format!("reading {path} from {base_url}"))?;
+    Ok(Some(body.to_vec()))
+}
+
+/// Tell if a URL uses HTTP(S).
+fn is_http(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// What we found out about HTTP servers, see is_dumb_http().
+static DUMB_HTTP: Mutex<BTreeMap<String, bool>> = Mutex::new(BTreeMap::new());
+
+/// Tell if a URL is that of an HTTP server only implementing the dumb protocol.
+///
+/// See gitprotocol-http(5) "Smart Clients": smart servers answer the discovery
+/// request with an advertisement of their own type, while dumb servers just
+/// send the info/refs file. The answer is remembered for later requests.
+pub fn is_dumb_http(repo_url: &str) -> Result<bool> {
+    if !is_http(repo_url) {
+        return Ok(false);
+    }
+    let mut known = DUMB_HTTP.lock().expect("no panic while holding the lock");
+    if let Some(&dumb) = known.get(repo_url) {
+        return Ok(dumb);
+    }
+    let request_url = format!(
+        "{}/info/refs?service=git-upload-pack",
+        repo_url.trim_end_matches('/')
+    );
+    let response = http_client(&request_url)?
+        .get(&request_url)
+        .header("git-protocol", "version=2")
+        .send()
+        .and_then(Response::error_for_status)
+        .with_context(|| format!("requesting {request_url}"))?;
+    let content_type = response.headers().get("content-type");
+    let dumb = !content_type
+        .is_some_and(|t| t.as_bytes() == b"application/x-git-upload-pack-advertisement");
+    known.insert(repo_url.to_owned(), dumb);
+    Ok(dumb)
+}
+
+/// Make a request to the git-upload-pack service of protocol v2 over HTTP.
+fn request_upload_pack_v2(repo_url: &str, body: &str) -> Result<Response> {
     let request_url = format!("{}/git-upload-pack", repo_url.trim_end_matches('/'));
 
     let mut headers = HeaderMap::new();
     headers.insert("git-protocol", HeaderValue::from_static("version=2"));
+    headers.insert(
+        "content-type",
+        HeaderValue::from_static("application/x-git-upload-pack-request"),
+    );
 
-    let response = Client::new()
+    let response = http_client(&request_url)?
         .post(request_url)
         .headers(headers)
         .body(body.to_owned())
@@ -191,56 +305,786 @@ pub fn request_upload_pack_v2(repo_url: &str, body: &str) -> Result<Response> {
     Ok(response)
 }
 
-/// Make a ls-refs request and return:
-/// - the hash of the remote HEAD;
-/// - the name of the default branch.
-pub fn ls_remote_head(repo_url: &str) -> Result<(String, String)> {
+/// A way to reach the git-upload-pack service of a remote repository.
+pub trait Transport {
+    /// Get the capabilities advertised by the server, eg "fetch=shallow"
+    /// or "object-info", see gitprotocol-v2(5) "Capability Advertisement".
+    fn capabilities(&self) -> Result<Vec<String>>;
+
+    /// Send a protocol v2 command request, and return a reader for the response.
+    fn request(&self, body: &str) -> Result<Box<dyn Read>>;
+}
+
+/// The smart HTTP transport, see gitprotocol-http(5).
+struct Http {
+    url: String,
+}
+
+impl Transport for Http {
+    fn capabilities(&self) -> Result<Vec<String>> {
+        let request_url = format!(
+            "{}/info/refs?service=git-upload-pack",
+            self.url.trim_end_matches('/')
+        );
+        let mut response = http_client(&request_url)?
+            .get(&request_url)
+            .header("git-protocol", "version=2")
+            .send()
+            .and_then(Response::error_for_status)
+            .with_context(|| format!("requesting {request_url}"))?;
+        // Some servers start with the service line of protocol v0.
+        let mut first = read_text_line(&mut response).context("reading capabilities")?;
+        if first.as_deref() == Some("# service=git-upload-pack") {
+            read_text_line(&mut response).context("reading capabilities")?;
+            first = read_text_line(&mut response).context("reading capabilities")?;
+        }
+        read_capabilities(first, &mut response)
+    }
+
+    fn request(&self, body: &str) -> Result<Box<dyn Read>> {
+        Ok(Box::new(request_upload_pack_v2(&self.url, body)?))
+    }
+}
+
+/// Default port of git daemon.
+const DAEMON_PORT: u16 = 9418;
+
+/// The git:// transport, talking to git daemon over TCP,
+/// see gitprotocol-pack(5) "Git Transport".
+struct Daemon {
+    host: String,
+    port: u16,
+    /// Path of the repository on the server, starting with a slash
+    path: String,
+}
+
+impl Daemon {
+    /// Parse a `git://<host>[:<port>]/<path>` URL.
+    fn parse(url: &str) -> Result<Self> {
+        let rest = url.strip_prefix("git://").expect("called for git:// URLs");
+        let Some((authority, path)) = rest.split_once('/') else {
+            bail!("no path in URL {url}");
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .with_context(|| format!("invalid port in {url}"))?;
+                (host, port)
+            }
+            None => (authority, DAEMON_PORT),
+        };
+        Ok(Daemon {
+            host: host.to_owned(),
+            port,
+            path: format!("/{path}"),
+        })
+    }
+}
+
+impl Daemon {
+    /// Connect to a service of the server, eg git-upload-pack, sending
+    /// extra parameters after the host.
+    fn open(&self, service: &str, extra: &str) -> Result<(TcpStream, io::BufReader<TcpStream>)> {
+        let addr = format!("{}:{}", self.host, self.port);
+        ensure_online(&addr)?;
+        let mut stream =
+            TcpStream::connect(&addr).with_context(|| format!("connecting to {addr}"))?;
+
+        // <service> <path>\0host=<host>\0<extra>
+        let host = match self.port {
+            DAEMON_PORT => self.host.clone(),
+            port => format!("{}:{port}", self.host),
+        };
+        let request = format!("{service} {}\0host={host}\0{extra}", self.path);
+        stream
+            .write_all(pkt_line(&request).as_bytes())
+            .context("sending request to server")?;
+        let reader = io::BufReader::new(stream.try_clone()?);
+        Ok((stream, reader))
+    }
+
+    /// Connect to git-upload-pack on the server and read its capabilities.
+    fn connect(&self) -> Result<(TcpStream, io::BufReader<TcpStream>, Vec<String>)> {
+        let (stream, mut reader) = self.open("git-upload-pack", "\0version=2\0")?;
+        let first = read_text_line(&mut reader).context("reading capabilities")?;
+        let capabilities = read_capabilities(first, &mut reader)?;
+        Ok((stream, reader, capabilities))
+    }
+}
+
+impl Transport for Daemon {
+    fn capabilities(&self) -> Result<Vec<String>> {
+        Ok(self.connect()?.2)
+    }
+
+    fn request(&self, body: &str) -> Result<Box<dyn Read>> {
+        let (mut stream, reader, _) = self.connect()?;
+        stream
+            .write_all(body.as_bytes())
+            .context("sending request to server")?;
+        Ok(Box::new(reader))
+    }
+}
+
+/// The SSH transport, running git-upload-pack on the server through ssh
+/// (or GIT_SSH_COMMAND, or GIT_SSH), see gitprotocol-pack(5) "SSH Transport".
+struct Ssh {
+    /// Host name, possibly with a user name, eg "git@github.com"
+    host: String,
+    port: Option<String>,
+    /// Path of the repository on the server, absolute or relative to the home directory
+    path: String,
+}
+
+/// Tell if a URL is an scp-like SSH address, eg "git@github.com:user/repo.git".
+fn is_scp_like(url: &str) -> bool {
+    !url.contains("://") && url.find(':').is_some_and(|i| !url[..i].contains('/'))
+}
+
+impl Ssh {
+    /// Parse a `ssh://[<user>@]<host>[:<port>]/<path>` URL,
+    /// or an scp-like address `[<user>@]<host>:<path>`.
+    fn parse(url: &str) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("ssh://") else {
+            let (host, path) = url.split_once(':').expect("called for scp-like URLs");
+            return Ok(Ssh {
+                host: host.to_owned(),
+                port: None,
+                path: path.to_owned(),
+            });
+        };
+        let Some((authority, path)) = rest.split_once('/') else {
+            bail!("no path in URL {url}");
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port.to_owned())),
+            None => (authority, None),
+        };
+        // Like git, ssh://host/~user/repo is relative to a home directory.
+        let path = match path.starts_with('~') {
+            true => path.to_owned(),
+            false => format!("/{path}"),
+        };
+        Ok(Ssh {
+            host: host.to_owned(),
+            port,
+            path,
+        })
+    }
+
+    /// Prepare the ssh command running a service on the server, eg
+    /// git-upload-pack, with the ssh program chosen by the user if any.
+    fn command(&self, service: &str) -> Command {
+        let mut cmd = match env::var("GIT_SSH_COMMAND") {
+            Ok(ssh) => {
+                let mut cmd = Command::new("sh");
+                cmd.arg("-c").arg(format!("{ssh} \"$@\"")).arg(ssh);
+                cmd
+            }
+            Err(_) => Command::new(env::var_os("GIT_SSH").unwrap_or("ssh".into())),
+        };
+        cmd.args(["-o", "SendEnv=GIT_PROTOCOL"]);
+        if let Some(port) = &self.port {
+            cmd.args(["-p", port]);
+        }
+        cmd.arg(&self.host)
+            .arg(format!("{service} {}", sq_quote(&self.path)))
+            .env("GIT_PROTOCOL", "version=2");
+        cmd
+    }
+}
+
+/// The output of a child process, which is killed once no longer needed.
+struct ChildReader {
+    child: Child,
+    stdout: io::BufReader<ChildStdout>,
+}
+
+impl Read for ChildReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Drop for ChildReader {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Ssh {
+    /// Start a service on the server, eg git-upload-pack.
+    fn open(&self, service: &str) -> Result<(ChildStdin, ChildReader)> {
+        ensure_online(&self.host)?;
+        let mut child = self
+            .command(service)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("running ssh")?;
+        let stdin = child.stdin.take().expect("stdin was piped");
+        let stdout = child.stdout.take().expect("stdout was piped");
+        let reader = ChildReader {
+            child,
+            stdout: io::BufReader::new(stdout),
+        };
+        Ok((stdin, reader))
+    }
+
+    /// Start git-upload-pack on the server and read its capabilities.
+    fn spawn(&self) -> Result<(ChildStdin, ChildReader, Vec<String>)> {
+        let (stdin, mut reader) = self.open("git-upload-pack")?;
+        let first = read_text_line(&mut reader).context("reading capabilities")?;
+        let capabilities = read_capabilities(first, &mut reader)?;
+        Ok((stdin, reader, capabilities))
+    }
+}
+
+impl Transport for Ssh {
+    fn capabilities(&self) -> Result<Vec<String>> {
+        Ok(self.spawn()?.2)
+    }
+
+    fn request(&self, body: &str) -> Result<Box<dyn Read>> {
+        let (mut stdin, reader, _) = self.spawn()?;
+        // The server reads the whole request before answering,
+        // then stops when it sees the end of its input.
+        stdin
+            .write_all(body.as_bytes())
+            .context("sending request to server")?;
+        drop(stdin);
+        Ok(Box::new(reader))
+    }
+}
+
+/// Read the capability advertisement, given its first line, see
+/// gitprotocol-v2(5) "Capability Advertisement": version 2, then capabilities,
+/// until a flush-pkt. Errors are reported as ERR <msg>.
+fn read_capabilities(first: Option<String>, src: &mut impl Read) -> Result<Vec<String>> {
+    match first {
+        Some(line) if line == "version 2" => (),
+        Some(line) if line.starts_with("ERR ") => bail!("server error: {}", &line[4..]),
+        line => bail!("server does not support protocol v2: {line:?}"),
+    }
+    let mut capabilities = Vec::new();
+    while let Some(line) = read_text_line(src).context("reading capabilities")? {
+        capabilities.push(line);
+    }
+    Ok(capabilities)
+}
+
+/// Get the transport for a URL, depending on its scheme.
+pub fn transport(url: &str) -> Result<Box<dyn Transport>> {
+    if is_http(url) {
+        Ok(Box::new(Http {
+            url: url.to_owned(),
+        }))
+    } else if url.starts_with("git://") {
+        Ok(Box::new(Daemon::parse(url)?))
+    } else if url.starts_with("ssh://") || is_scp_like(url) {
+        Ok(Box::new(Ssh::parse(url)?))
+    } else {
+        bail!("unsupported URL {url}: only http(s)://, git:// and SSH are supported")
+    }
+}
+
+/// Format a string as a pkt-line, see gitprotocol-common(5) "pkt-line Format".
+pub(crate) fn pkt_line(data: &str) -> String {
+    format!("{:04x}{data}", data.len() + 4)
+}
+
+/// Read a full pkt-line, returning None for a flush-pkt.
+pub(crate) fn read_pkt_line(src: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let Some(len) = read_pkt_line_len(src)? else {
+        return Ok(None);
+    };
+    let mut line = vec![0; len];
+    src.read_exact(&mut line)?;
+    Ok(Some(line))
+}
+
+/// A reference as advertised by the remote in response to ls-refs.
+pub struct RemoteRef {
+    /// The hash of the object the reference points to.
+    pub hash: String,
+    /// The full name of the reference, eg "refs/heads/main" or "HEAD".
+    pub name: String,
+    /// For symbolic references, the name of the target.
+    pub symref_target: Option<String>,
+    /// For annotated tags, the hash of the object the tag points to.
+    pub peeled: Option<String>,
+}
+
+impl RemoteRef {
+    /// Parse a line of the ls-refs response, see gitprotocol-v2(5) "ls-refs":
+    /// `<hash> <refname> [symref-target:<target>] [peeled:<hash>]`
+    fn parse(line: &str) -> Result<Self> {
+        let mut fields = line.split(' ');
+        let hash = fields.next().expect("split yields at least one item");
+        let Some(name) = fields.next() else {
+            bail!("no reference name in {line:?}");
+        };
+        if hash.len() != 40 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("invalid hash for {name}: {hash:?}");
+        }
+
+        let mut remote_ref = RemoteRef {
+            hash: hash.to_owned(),
+            name: name.to_owned(),
+            symref_target: None,
+            peeled: None,
+        };
+        for attr in fields {
+            match attr.split_once(':') {
+                Some(("symref-target", target)) => remote_ref.symref_target = Some(target.into()),
+                Some(("peeled", hash)) => remote_ref.peeled = Some(hash.into()),
+                _ => bail!("unsupported ref attribute for {name}: {attr:?}"),
+            }
+        }
+
+        Ok(remote_ref)
+    }
+}
+
+/// Make a ls-refs request and return the references advertised by the remote.
+///
+/// If prefixes are given, the server is asked to only list references starting
+/// with one of them; otherwise all references are listed. A local repository
+/// (see clone::local_source()) has its references read directly.
+pub fn ls_refs(repo_url: &str, prefixes: &[String]) -> Result<Vec<RemoteRef>> {
+    if let Some((source, _)) = clone::local_source(repo_url)? {
+        return clone::ls_refs_local(&source, prefixes);
+    }
+    if is_dumb_http(repo_url)? {
+        return dumb_http::ls_refs(repo_url, prefixes);
+    }
+
     // gitprotocol-v2(5) "ls-refs" for the content;
     // gitprotocol-common(5) for pkt-line format.
     //
     // 0013command=ls-refs - list references
     // 0001 - delim-pkt
     // 000bsymrefs - to get the name of the branch pointing to HEAD
-    // 0013ref-prefix HEAD - to only get info about HEAD
+    // 0008peel - to get the object annotated tags point to
+    // ref-prefix <prefix> - (multiple) to only get info about some references
     // 0000 - flush-pkt
-    let body = "0013command=ls-refs0001000bsymrefs0013ref-prefix HEAD0000";
-    let mut response = request_upload_pack_v2(repo_url, body).context("making ls-refs request")?;
+    let mut body = String::from("0013command=ls-refs0001000bsymrefs0008peel");
+    for prefix in prefixes {
+        body.push_str(&pkt_line(&format!("ref-prefix {prefix}")));
+    }
+    body.push_str("0000");
+    let mut response = transport(repo_url)?
+        .request(&body)
+        .context("making ls-refs request")?;
 
-    let len = read_pkt_line_len(&mut response)
-        .context("reading first pkt-line length")?
-        .context("unexpected flush at start of response")?;
-    let mut line = vec![0; len];
-    response
-        .read_exact(&mut line)
-        .context("reading first pkt-line content")?;
-    let line = str::from_utf8(&line).context("response is not ASCII")?;
-    let line = line.trim_end_matches('\n');
+    let mut refs = Vec::new();
+    while let Some(line) = read_pkt_line(&mut response).context("reading ls-refs response")? {
+        let line = str::from_utf8(&line).context("response is not ASCII")?;
+        let line = line.trim_end_matches('\n');
+        refs.push(RemoteRef::parse(line).context("parsing ls-refs response")?);
+    }
+
+    Ok(refs)
+}
+
+/// Get the sizes of objects from the server without downloading them, with
+/// the object-info command of gitprotocol-v2(5). Return None if the server
+/// doesn't advertise it (git servers only do with transfer.advertiseObjectInfo).
+/// Objects the server doesn't have are left out.
+pub fn object_info(repo_url: &str, hashes: &[String]) -> Result<Option<HashMap<String, usize>>> {
+    if is_dumb_http(repo_url)? {
+        return Ok(None);
+    }
+    let transport = transport(repo_url)?;
+    let capabilities = transport.capabilities()?;
+    if !capabilities
+        .iter()
+        .any(|c| c == "object-info" || c.starts_with("object-info="))
+    {
+        return Ok(None);
+    }
 
-    // <40-char hash> HEAD symref-target:refs/heads/<name>
-    // let's be lazy and directly index into the line
-    let hash = line[..40].to_owned();
-    let middle = &line[40..71];
-    let name = line[71..].to_owned();
+    // 0017command=object-info
+    // 0001 - delim-pkt
+    // 0008size - the only attribute there is
+    // oid <hash> - (multiple) the objects to describe
+    // 0000 - flush-pkt
+    let mut body = String::from("0017command=object-info00010008size");
+    for hash in hashes {
+        body.push_str(&pkt_line(&format!("oid {hash}")));
+    }
+    body.push_str("0000");
+    let mut response = transport
+        .request(&body)
+        .context("making object-info request")?;
 
-    if middle != " HEAD symref-target:refs/heads/" {
-        bail!("unsupported response format: {middle}");
+    // The attributes, then <hash> SP <size> for each object.
+    match read_text_line(&mut response).context("reading object-info response")? {
+        Some(attrs) if attrs == "size" => (),
+        attrs => bail!("unexpected object-info attributes: {attrs:?}"),
     }
+    let mut sizes = HashMap::new();
+    while let Some(line) = read_text_line(&mut response).context("reading object-info response")? {
+        let Some((hash, size)) = line.split_once(' ') else {
+            bail!("invalid object-info line: {line:?}");
+        };
+        // The size is empty for objects the server doesn't have.
+        if !size.is_empty() {
+            let size = size
+                .parse()
+                .with_context(|| format!("invalid size for {hash}: {size:?}"))?;
+            sizes.insert(hash.to_owned(), size);
+        }
+    }
+    Ok(Some(sizes))
+}
+
+/// Read a pkt-line expected to contain text, and return it without the final newline.
+/// Return None for flush-pkt or delim-pkt.
+pub(crate) fn read_text_line(src: &mut impl Read) -> Result<Option<String>> {
+    let PktLen::Data(len) = read_pkt_len(src)? else {
+        return Ok(None);
+    };
+    let mut line = vec![0; len];
+    src.read_exact(&mut line)?;
+    let line = String::from_utf8(line).context("pkt-line is not UTF-8")?;
+    Ok(Some(line.trim_end_matches('\n').to_owned()))
+}
+
+/// Changes to the list of shallow commits sent by the server with a pack.
+#[derive(Default)]
+pub struct ShallowInfo {
+    /// Commits that are now shallow boundaries.
+    pub shallow: Vec<String>,
+    /// Commits that are no longer shallow boundaries.
+    pub unshallow: Vec<String>,
+}
 
-    Ok((hash, name))
+/// Outcome of a round of fetch negotiation.
+pub enum Negotiation {
+    /// The server is not ready to send a pack yet; contains the haves it acknowledged.
+    Continue(Vec<String>),
+    /// The server is sending a pack.
+    Pack(Box<PackFileReader>, ShallowInfo),
 }
 
-/// Make a fetch request and return a BufRead for the packfile data.
-pub fn get_pack(repo_url: &str, head: &str) -> Result<impl BufRead> {
+/// Arguments for a fetch request.
+#[derive(Default)]
+pub struct FetchRequest<'a> {
+    /// Objects we want.
+    pub wants: &'a [String],
+    /// Objects we already have.
+    pub haves: &'a [String],
+    /// If true, we're done negotiating, and the server must send a pack.
+    pub done: bool,
+    /// Limit history to that many commits.
+    pub depth: Option<u32>,
+    /// Filter the objects to send, see git-rev-list(1) "--filter".
+    pub filter: Option<&'a str>,
+    /// Also send annotated tags pointing to objects in the pack.
+    pub include_tag: bool,
+}
+
+/// Make a fetch request, sending the given wants and haves.
+///
+/// Unless done is true, the server may not send a pack but acknowledge
+/// which of our haves it has in common with us, and expect another round.
+/// Since HTTP is stateless, each round must repeat the wants and common haves.
+///
+/// Our own shallow commits are always sent, so the server knows about them.
+pub fn fetch_round(
+    repo: &Repository,
+    repo_url: &str,
+    request: &FetchRequest,
+) -> Result<Negotiation> {
     // gitprotocol-v2(5) "fetch" for the content;
     // gitprotocol-common(5) for pkt-line format.
     //
     // 0011command=fetch
     // 0001 - delim-pkt
     // 000fno-progress - to only receive on side-band channel #1
-    // 0031want <hash> - the commit(s) we want
+    // 000dofs-delta - to get deltas against a base at an offset in the pack
+    // 0031want <hash> - (multiple) the objects we want
+    // 0034shallow <hash> - (multiple) our shallow commits
+    // deepen <depth> - if we want a shallow history
+    // filter <filter-spec> - if we want a partial clone
+    // include-tag - to also get annotated tags pointing to objects sent
+    // 0031have <hash> - (multiple) objects we already have
+    // 0008done - if we're done negotiating
     // 0000 - flush-pkt
-    let body = format!("0011command=fetch0001000fno-progress0031want {head}0000");
-    let response = request_upload_pack_v2(repo_url, &body).context("making fetch request")?;
-    let reader = PackFileReader::new(response).context("parsing fetch response")?;
-    Ok(reader)
+    let mut body = String::from("0011command=fetch0001000fno-progress000dofs-delta");
+    for want in request.wants {
+        body.push_str(&pkt_line(&format!("want {want}")));
+    }
+    for commit in shallow::list(repo)? {
+        body.push_str(&pkt_line(&format!("shallow {commit}")));
+    }
+    if let Some(depth) = request.depth {
+        body.push_str(&pkt_line(&format!("deepen {depth}")));
+    }
+    if let Some(filter) = request.filter {
+        body.push_str(&pkt_line(&format!("filter {filter}")));
+    }
+    if request.include_tag {
+        body.push_str(&pkt_line("include-tag"));
+    }
+    for have in request.haves {
+        body.push_str(&pkt_line(&format!("have {have}")));
+    }
+    if request.done {
+        body.push_str("0008done");
+    }
+    body.push_str("0000");
+    let mut resp = transport(repo_url)?
+        .request(&body)
+        .context("making fetch request")?;
+
+    // gitprotocol-v2(5) "fetch" output: sections, each starting with its name.
+    let mut header = read_text_line(&mut resp).context("reading section header")?;
+    if header.as_deref() == Some("acknowledgments") {
+        // NAK, or ACK <hash> lines, optionally followed by ready.
+        let mut common = Vec::new();
+        let mut ready = false;
+        while let Some(line) = read_text_line(&mut resp).context("reading acknowledgments")? {
+            if let Some(hash) = line.strip_prefix("ACK ") {
+                common.push(hash.to_owned());
+            } else if line == "ready" {
+                ready = true;
+            } else if line != "NAK" {
+                bail!("unexpected line in acknowledgments: {line:?}");
+            }
+        }
+        if !ready {
+            // The section ended with a flush-pkt: end of response.
+            return Ok(Negotiation::Continue(common));
+        }
+        header = read_text_line(&mut resp).context("reading section header")?;
+    }
+
+    let mut shallow_info = ShallowInfo::default();
+    if header.as_deref() == Some("shallow-info") {
+        // shallow <hash> or unshallow <hash> lines.
+        while let Some(line) = read_text_line(&mut resp).context("reading shallow-info")? {
+            match line.split_once(' ') {
+                Some(("shallow", hash)) => shallow_info.shallow.push(hash.into()),
+                Some(("unshallow", hash)) => shallow_info.unshallow.push(hash.into()),
+                _ => bail!("unexpected line in shallow-info: {line:?}"),
+            }
+        }
+        header = read_text_line(&mut resp).context("reading section header")?;
+    }
+
+    match header {
+        Some(h) if h == "packfile" => Ok(Negotiation::Pack(
+            Box::new(PackFileReader::new(resp)),
+            shallow_info,
+        )),
+        h => bail!("expected 'packfile' section, got {h:?}"),
+    }
+}
+
+/// Make a fetch request without negotiation (no haves),
+/// and return a BufRead for the packfile data, and updates to shallow commits.
+pub fn get_pack(
+    repo: &Repository,
+    repo_url: &str,
+    request: FetchRequest,
+) -> Result<(impl BufRead, ShallowInfo)> {
+    let request = FetchRequest {
+        done: true,
+        ..request
+    };
+    match fetch_round(repo, repo_url, &request)? {
+        Negotiation::Pack(reader, shallow_info) => Ok((reader, shallow_info)),
+        Negotiation::Continue(_) => bail!("server did not send a pack despite done"),
+    }
+}
+
+/// List references on the remote, as advertised by the git-receive-pack service.
+///
+/// The advertisement uses the original protocol (v0), see gitprotocol-http(5)
+/// "Smart Clients" and gitprotocol-pack(5) "Reference Discovery".
+pub fn receive_pack_refs(repo_url: &str) -> Result<Vec<RemoteRef>> {
+    let request_url = format!(
+        "{}/info/refs?service=git-receive-pack",
+        repo_url.trim_end_matches('/')
+    );
+    let mut response = http_client(&request_url)?
+        .get(request_url)
+        .send()
+        .context("sending request to server")?
+        .error_for_status()
+        .context("requesting ref advertisement")?;
+
+    // 001f# service=git-receive-pack\n
+    // 0000
+    let header = read_text_line(&mut response).context("reading service header")?;
+    if header.as_deref() != Some("# service=git-receive-pack") {
+        bail!("unexpected service header: {header:?}");
+    }
+    if read_text_line(&mut response)?.is_some() {
+        bail!("expected flush-pkt after service header");
+    }
+
+    // <hash> <refname>\0<capabilities> on the first line, <hash> <refname> on the others,
+    // with a fake capabilities^{} ref if the repository is empty.
+    let mut refs: Vec<RemoteRef> = Vec::new();
+    while let Some(line) = read_text_line(&mut response).context("reading advertisement")? {
+        let line = line
+            .split('\0')
+            .next()
+            .expect("split yields at least one item");
+        let remote_ref = RemoteRef::parse(line).context("parsing ref advertisement")?;
+        if remote_ref.name == "capabilities^{}" {
+            continue;
+        }
+        match remote_ref.name.strip_suffix("^{}") {
+            Some(name) => {
+                if let Some(tag) = refs.iter_mut().find(|r| r.name == name) {
+                    tag.peeled = Some(remote_ref.hash);
+                }
+            }
+            None => refs.push(remote_ref),
+        }
+    }
+    Ok(refs)
+}
+
+/// An update to a reference on the remote, as requested by a push.
+pub struct RefUpdate {
+    /// The current value on the remote, or zeros if it should be created.
+    pub old: String,
+    /// The new value, or zeros if it should be deleted.
+    pub new: String,
+    /// The full name of the reference on the remote.
+    pub name: String,
+}
+
+/// Send reference updates and a packfile to the git-receive-pack service,
+/// and return the status of each update as reported by the server:
+/// None for success, or the reason for failure.
+pub fn send_pack(
+    repo_url: &str,
+    updates: &[RefUpdate],
+    pack: Option<Vec<u8>>,
+) -> Result<Vec<(String, Option<String>)>> {
+    // gitprotocol-pack(5) "Reference Update Request and Packfile Transfer"
+    // <old> <new> <name>\0report-status - first command, with capabilities
+    // <old> <new> <name> - (multiple) other commands
+    // 0000 - flush-pkt
+    // packfile, unless all commands are deletions
+    let mut body = Vec::new();
+    for (i, u) in updates.iter().enumerate() {
+        let caps = if i == 0 {
+            "\0report-status delete-refs"
+        } else {
+            ""
+        };
+        let line = format!("{} {} {}{caps}\n", u.old, u.new, u.name);
+        body.extend_from_slice(pkt_line(&line).as_bytes());
+    }
+    body.extend_from_slice(b"0000");
+    body.extend(pack.into_iter().flatten());
+
+    let request_url = format!("{}/git-receive-pack", repo_url.trim_end_matches('/'));
+    let mut response = http_client(&request_url)?
+        .post(request_url)
+        .header("content-type", "application/x-git-receive-pack-request")
+        .body(body)
+        .send()
+        .context("sending request to server")?
+        .error_for_status()
+        .context("pushing to server")?;
+
+    // gitprotocol-pack(5) "Report Status"
+    // unpack ok / unpack <error>
+    // ok <refname> / ng <refname> <reason> - (multiple)
+    // 0000 - flush-pkt
+    let unpack = read_text_line(&mut response).context("reading unpack status")?;
+    match unpack.as_deref().and_then(|l| l.strip_prefix("unpack ")) {
+        Some("ok") => (),
+        Some(err) => bail!("remote failed to unpack: {err}"),
+        None => bail!("unexpected unpack status: {unpack:?}"),
+    }
+    let mut statuses = Vec::new();
+    while let Some(line) = read_text_line(&mut response).context("reading ref status")? {
+        if let Some(name) = line.strip_prefix("ok ") {
+            statuses.push((name.to_owned(), None));
+        } else if let Some(rest) = line.strip_prefix("ng ") {
+            let (name, reason) = rest.split_once(' ').unwrap_or((rest, "unknown reason"));
+            statuses.push((name.to_owned(), Some(reason.to_owned())));
+        } else {
+            bail!("unexpected line in status report: {line:?}");
+        }
+    }
+    Ok(statuses)
+}
+
+/// Sideband channels of multiplexed responses, see gitprotocol-pack(5).
+pub(crate) const BAND_DATA: u8 = 1;
+pub(crate) const BAND_PROGRESS: u8 = 2;
+pub(crate) const BAND_ERROR: u8 = 3;
+
+/// Get an archive from a remote repository with the upload-archive service
+/// (running `exec` on the server over SSH), see git-upload-archive(1):
+/// the arguments of git archive are sent as `argument <arg>` pkt-lines,
+/// then the server accepts them (ACK) or not (`NACK <reason>`), and sends
+/// the archive multiplexed with messages like a packfile.
+///
+/// The service is not offered over HTTP.
+pub fn upload_archive(
+    repo_url: &str,
+    exec: Option<&str>,
+    args: &[String],
+    out: &mut impl Write,
+) -> Result<()> {
+    let (mut input, mut output): (Box<dyn Write>, Box<dyn Read>) = if repo_url.starts_with("git://")
+    {
+        // git daemon always runs its own service.
+        let (stream, reader) = Daemon::parse(repo_url)?.open("git-upload-archive", "")?;
+        (Box::new(stream), Box::new(reader))
+    } else if repo_url.starts_with("ssh://") || is_scp_like(repo_url) {
+        let exec = exec.unwrap_or("git-upload-archive");
+        let (stdin, reader) = Ssh::parse(repo_url)?.open(exec)?;
+        (Box::new(stdin), Box::new(reader))
+    } else {
+        bail!("cannot archive from {repo_url}: operation not supported by protocol");
+    };
+    let mut request = String::new();
+    for arg in args {
+        request.push_str(&pkt_line(&format!("argument {arg}\n")));
+    }
+    request.push_str("0000");
+    input
+        .write_all(request.as_bytes())
+        .and_then(|()| input.flush())
+        .context("sending request to server")?;
+
+    let status = read_text_line(&mut output).context("reading archive status")?;
+    match status.as_deref() {
+        Some("ACK") => (),
+        Some(line) if line.starts_with("NACK ") => bail!("remote refused: {}", &line[5..]),
+        Some(line) if line.starts_with("ERR ") => bail!("server error: {}", &line[4..]),
+        _ => bail!("unexpected archive status: {status:?}"),
+    }
+    if read_text_line(&mut output)?.is_some() {
+        bail!("expected a flush-pkt after the archive status");
+    }
+    while let Some(line) = read_pkt_line(&mut output).context("reading archive")? {
+        interrupt::check()?;
+        match line.split_first() {
+            Some((&BAND_DATA, data)) => out.write_all(data)?,
+            Some((&BAND_PROGRESS, message)) => {
+                eprint!("remote: {}", String::from_utf8_lossy(message));
+            }
+            Some((&BAND_ERROR, message)) => {
+                let message = String::from_utf8_lossy(message);
+                bail!("remote error: {}", message.trim_end());
+            }
+            _ => bail!("invalid sideband channel in archive"),
+        }
+    }
+    Ok(())
 }
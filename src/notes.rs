@@ -0,0 +1,174 @@
+//! Notes, like git notes: messages attached to objects (usually commits)
+//! without changing them.
+//!
+//! The notes of a notes reference (refs/notes/commits by default) are blobs
+//! in the tree of the commit it points to, named after the hash of the object
+//! they annotate. Once there are many notes, they are spread in fan-out
+//! directories named after the first digits of the hash, eg "ab/cdef...":
+//! any depth is read, and new trees use the same layout as git.
+
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::env;
+use std::io::Write;
+use std::ops::Bound;
+
+use crate::config;
+use crate::diff::Side;
+use crate::obj_read::{read_blob, read_commit, read_tree};
+use crate::refs;
+use crate::repository::Repository;
+use crate::tree_entry::Mode;
+use crate::tree_write::tree_from_list;
+
+/// The notes reference used unless configured otherwise.
+pub const DEFAULT_REF: &str = "refs/notes/commits";
+
+/// Get the full name of a notes reference given on the command line, eg
+/// "refs/notes/review" for "review" or "notes/review".
+pub fn expand_ref(name: &str) -> String {
+    if name.starts_with("refs/") {
+        name.to_owned()
+    } else if name.starts_with("notes/") {
+        format!("refs/{name}")
+    } else {
+        format!("refs/notes/{name}")
+    }
+}
+
+/// Get the notes reference to use: GIT_NOTES_REF, core.notesRef, or
+/// refs/notes/commits.
+pub fn default_ref(repo: &Repository) -> Result<String> {
+    if let Some(name) = env::var_os("GIT_NOTES_REF").filter(|name| !name.is_empty()) {
+        return Ok(expand_ref(&name.to_string_lossy()));
+    }
+    Ok(match config::get(repo, "core.notesref")? {
+        Some(name) => expand_ref(&name),
+        None => DEFAULT_REF.to_owned(),
+    })
+}
+
+/// Collect the notes in a tree (and its fan-out directories), with the hash
+/// of the object they annotate. Entries which are not notes are ignored.
+fn read_notes(
+    repo: &Repository,
+    tree: &str,
+    prefix: &str,
+    out: &mut BTreeMap<String, String>,
+) -> Result<()> {
+    for entry in read_tree(repo, tree)? {
+        let name = format!("{prefix}{}", String::from_utf8_lossy(&entry.name));
+        let hash = hex::encode(entry.hash);
+        if !name.bytes().all(|c| c.is_ascii_hexdigit()) || name.len() > hash.len() {
+            continue;
+        }
+        match entry.mode {
+            Mode::Dir if name.len() < hash.len() && name.len() % 2 == 0 => {
+                read_notes(repo, &hash, &name, out)?
+            }
+            Mode::File | Mode::Exe if name.len() == hash.len() => {
+                out.insert(name.to_ascii_lowercase(), hash);
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Read the notes of a notes reference, as the hashes of the note blobs by
+/// the hash of the object they annotate (empty if there are none yet).
+pub fn read(repo: &Repository, notes_ref: &str) -> Result<BTreeMap<String, String>> {
+    let mut notes = BTreeMap::new();
+    if let Some(commit) = refs::resolve(repo, notes_ref)? {
+        read_notes(repo, &read_commit(repo, &commit)?.tree, "", &mut notes)?;
+    }
+    Ok(notes)
+}
+
+/// Find the note of an object, as the hash of its blob.
+pub fn find(repo: &Repository, notes_ref: &str, object: &str) -> Result<Option<String>> {
+    Ok(read(repo, notes_ref)?.remove(object))
+}
+
+/// Give the number of fan-out directories for a note, like git does (see
+/// determine_fanout() in its notes.c): it keeps notes in a tree with one
+/// level per hex digit, where a node holding more than one note has 16
+/// children. Every second level, if all 16 children of the node on the path
+/// to the note hold several notes, one more fan-out directory is used.
+fn fanout(object: &str, notes: &BTreeMap<String, String>) -> usize {
+    let count = |prefix: &str| {
+        notes
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(hash, _)| hash.starts_with(prefix))
+            .count()
+    };
+    let mut fanout = 0;
+    for n in 0..object.len() {
+        let prefix = &object[..n];
+        if n > 0 && count(prefix) < 2 {
+            break;
+        }
+        if n % 2 == 0
+            && n <= 2 * fanout
+            && "0123456789abcdef"
+                .chars()
+                .all(|digit| count(&format!("{prefix}{digit}")) >= 2)
+        {
+            fanout += 1;
+        }
+    }
+    fanout
+}
+
+/// Write the tree for a set of notes, see read(), and return its hash.
+pub fn write_tree(repo: &Repository, notes: &BTreeMap<String, String>) -> Result<String> {
+    let mut list = Vec::new();
+    for (object, blob) in notes {
+        let fanout = fanout(object, notes);
+        let mut path = String::new();
+        for i in 0..fanout {
+            path.push_str(&object[2 * i..2 * i + 2]);
+            path.push('/');
+        }
+        path.push_str(&object[2 * fanout..]);
+        let side = Side {
+            mode: Mode::File,
+            hash: blob.clone(),
+            file: None,
+        };
+        list.push((path.into_bytes(), side));
+    }
+    list.sort_by(|a, b| a.0.cmp(&b.0));
+    tree_from_list(repo, &list)
+}
+
+/// Print the note of an object as git show does, if there is one: after a
+/// blank line, a "Notes:" header (naming the reference unless it is the
+/// default one), then the note indented.
+pub fn show(repo: &Repository, object: &str, out: &mut impl Write) -> Result<()> {
+    let notes_ref = default_ref(repo)?;
+    let Some(blob) = find(repo, &notes_ref, object)? else {
+        return Ok(());
+    };
+    match notes_ref.strip_prefix("refs/notes/") {
+        _ if notes_ref == DEFAULT_REF => writeln!(out, "\nNotes:")?,
+        Some(name) => writeln!(out, "\nNotes ({name}):")?,
+        None => writeln!(out, "\nNotes ({notes_ref}):")?,
+    }
+    let note = read_blob(repo, &blob)?;
+    let note = note.strip_suffix(b"\n").unwrap_or(&note);
+    for line in note.split(|&c| c == b'\n') {
+        out.write_all(b"    ")?;
+        out.write_all(line)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Check that notes can be stored under a reference.
+pub fn check_ref(notes_ref: &str) -> Result<()> {
+    if !notes_ref.starts_with("refs/notes/") {
+        bail!("refusing to change notes in {notes_ref} (outside of refs/notes/)");
+    }
+    Ok(())
+}
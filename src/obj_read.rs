@@ -1,13 +1,25 @@
-//! Reading from objects in loose storage.
+//! Reading from objects in loose storage or packs.
 
-use anyhow::{anyhow, ensure, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use flate2::bufread::ZlibDecoder;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
 
-use crate::common::*;
+use crate::commit::Commit;
+use crate::http_store;
 use crate::obj_type::ObjType;
+use crate::object_id::HashAlgo;
+use crate::pack_read;
+use crate::repository::Repository;
+use crate::tag::Tag;
+use crate::tree_entry::Entry;
+
+/// Largest blob loaded in memory by read_blob(), larger ones should be streamed.
+pub const MAX_BLOB_SIZE: usize = 1 << 30;
+
+/// Largest commit or tree loaded in memory: anything bigger is surely bogus.
+pub const MAX_METADATA_SIZE: usize = 64 << 20;
 
 /// Read from stream until the given delimiter is found.
 /// Return content excluding the delimiter.
@@ -40,24 +52,72 @@ fn read_obj_type(s: &mut impl Read) -> Result<ObjType> {
     ObjType::from_bytes(&label)
 }
 
+/// Where an object's content is read from.
+pub enum Source {
+    /// A zlib stream in a file: loose object, or undeltified object in a pack
+    Stream(ZlibDecoder<io::BufReader<fs::File>>),
+    /// Content already in memory: deltified object in a pack, or fetched object
+    Memory(io::Cursor<Vec<u8>>),
+}
+
+impl Read for Source {
+    /// Pull some bytes from this source into the specified buffer,
+    /// returning how many bytes were read.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::Stream(zdec) => zdec.read(buf),
+            Source::Memory(cursor) => cursor.read(buf),
+        }
+    }
+}
+
 /// Acces to object data: type and size via members, content via the Read trait.
 pub struct ObjReader {
     pub obj_type: ObjType,
     pub size: usize,
     used: usize,
-    zdec: ZlibDecoder<io::BufReader<fs::File>>,
+    zdec: Source,
+    /// Hash function of the repository, for hashes in the content
+    hash_algo: HashAlgo,
 }
 
 impl ObjReader {
-    /// Create an object reader from a hash.
+    /// Create an object reader from a hash, looking in loose storage then packs
+    /// (of the repository then its alternates), then the remote object store
+    /// if configured (see http_store).
     ///
-    /// Note: no validation of the "hash" other than the fact that the file exists.
-    pub fn from_hash(hash: &str) -> Result<ObjReader> {
+    /// Note: no validation of the "hash" other than the fact that the object exists.
+    pub fn open(repo: &Repository, hash: &str) -> Result<ObjReader> {
         ensure!(hash.len() >= 4, "not a valid object name {}", hash);
-        let obj_path = path_from_hash(hash)?;
+        let obj_path = repo
+            .find_loose(hash)
+            .unwrap_or_else(|| repo.loose_path(hash));
+        let hash_algo = repo.hash_algo()?;
 
-        let file = fs::File::open(obj_path)
-            .with_context(|| format!("not a valid object name {}", hash))?;
+        let file = match fs::File::open(&obj_path) {
+            Ok(file) => file,
+            Err(e) => {
+                if let Some((obj_type, size, zdec)) = pack_read::open(repo, hash)? {
+                    return Ok(ObjReader {
+                        obj_type,
+                        size,
+                        used: 0,
+                        zdec,
+                        hash_algo,
+                    });
+                }
+                if let Some((obj_type, content)) = http_store::fetch(repo, hash)? {
+                    return Ok(ObjReader {
+                        obj_type,
+                        size: content.len(),
+                        used: 0,
+                        zdec: Source::Memory(io::Cursor::new(content)),
+                        hash_algo,
+                    });
+                }
+                return Err(e).with_context(|| format!("not a valid object name {}", hash));
+            }
+        };
         let bufreader = io::BufReader::new(file);
 
         // Object format: <type> <size>\0<content>, all zlib-compressed
@@ -71,10 +131,29 @@ impl ObjReader {
             obj_type,
             size,
             used: 0,
-            zdec,
+            zdec: Source::Stream(zdec),
+            hash_algo,
         })
     }
 
+    /// Get the hash function of the repository the object is from.
+    pub fn hash_algo(&self) -> HashAlgo {
+        self.hash_algo
+    }
+
+    /// Read the whole content of the object, failing if it is bigger than `limit`
+    /// (checked before allocating anything).
+    pub fn read_to_vec(mut self, limit: usize) -> Result<Vec<u8>> {
+        ensure!(
+            self.size <= limit,
+            "object too big to load in memory: {} bytes (limit {limit})",
+            self.size
+        );
+        let mut data = Vec::with_capacity(self.size);
+        self.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
     /// Read data from object up to the given delimiter (excluded).
     /// The delimiter is consumed, but not returned as part of the output.
     pub fn read_up_to(&mut self, delim: u8) -> Result<Vec<u8>> {
@@ -136,3 +215,49 @@ impl Read for ObjReader {
         }
     }
 }
+
+/// Open an object, failing if it doesn't have the expected type.
+fn open_typed(repo: &Repository, hash: &str, obj_type: ObjType) -> Result<ObjReader> {
+    let object = repo
+        .read_object(hash)
+        .with_context(|| format!("opening object {hash}"))?;
+    if object.obj_type != obj_type {
+        bail!(
+            "object {hash} is a {}, not a {}",
+            object.obj_type.to_str(),
+            obj_type.to_str()
+        );
+    }
+    Ok(object)
+}
+
+/// Read the whole content of a blob (up to MAX_BLOB_SIZE).
+pub fn read_blob(repo: &Repository, hash: &str) -> Result<Vec<u8>> {
+    open_typed(repo, hash, ObjType::Blob)?
+        .read_to_vec(MAX_BLOB_SIZE)
+        .with_context(|| format!("reading blob {hash}"))
+}
+
+/// Read and parse a commit (up to MAX_METADATA_SIZE).
+pub fn read_commit(repo: &Repository, hash: &str) -> Result<Commit> {
+    let object = repo
+        .read_object(hash)
+        .with_context(|| format!("opening object {hash}"))?;
+    Commit::from_object(repo, object, hash).with_context(|| format!("parsing commit {hash}"))
+}
+
+/// Read and parse an annotated tag (up to MAX_METADATA_SIZE).
+pub fn read_tag(repo: &Repository, hash: &str) -> Result<Tag> {
+    let object = repo
+        .read_object(hash)
+        .with_context(|| format!("opening object {hash}"))?;
+    Tag::from_object(repo, object).with_context(|| format!("parsing tag {hash}"))
+}
+
+/// Read and parse all entries of a tree (up to MAX_METADATA_SIZE).
+pub fn read_tree(repo: &Repository, hash: &str) -> Result<Vec<Entry>> {
+    let data = open_typed(repo, hash, ObjType::Tree)?
+        .read_to_vec(MAX_METADATA_SIZE)
+        .with_context(|| format!("reading tree {hash}"))?;
+    Entry::parse_all(&data, repo.hash_algo()?).with_context(|| format!("parsing tree {hash}"))
+}
@@ -3,7 +3,7 @@
 use anyhow::{anyhow, Result};
 
 /// Possible types for a git object.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ObjType {
     Commit,
     Tree,
@@ -3,71 +3,116 @@
 use anyhow::{bail, Context, Result};
 use flate2::{write::ZlibEncoder, Compression};
 use rand::Rng;
-use sha1::{Digest, Sha1};
+use std::env;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
 use std::io::{Read, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use crate::common::*;
 use crate::obj_type::ObjType;
+use crate::object_id::Hasher;
+use crate::pack_read;
+use crate::repository::Repository;
+
+/// Objects up to this size are compressed in memory, then written at once.
+const SMALL_OBJECT_SIZE: usize = 64 * 1024;
+
+/// Where compressed data goes.
+enum Sink {
+    /// A temporary file, renamed once the hash is known
+    File(ZlibEncoder<fs::File>),
+    /// A buffer, for small objects: this saves system calls
+    Memory(ZlibEncoder<Vec<u8>>),
+}
+
+/// Content of an object whose size is not known upfront,
+/// see ObjWriter::new_unsized().
+struct Spill {
+    obj_type: ObjType,
+    write: bool,
+    file: io::BufWriter<fs::File>,
+    path: PathBuf,
+}
 
 /// Generic object writer/hasher: data can be provided in a streaming way
-/// using the Write trait, but total size needs to be known upfront.
+/// using the Write trait, but total size needs to be known upfront,
+/// unless content is first spilled to a temporary file.
 ///
 /// Can either just compute the object hash, or also write it to the filesystem.
-pub struct ObjWriter {
-    hasher: Sha1,
-    zenc: Option<ZlibEncoder<fs::File>>,
+pub struct ObjWriter<'r> {
+    repo: &'r Repository,
+    hasher: Hasher,
+    zenc: Option<Sink>,
+    spill: Option<Spill>,
     size: usize,
     seen: usize,
     past_header: bool,
     tmp_rand: [u8; 20],
 }
 
-impl ObjWriter {
+impl<'r> ObjWriter<'r> {
     /// Pick a name for the temporary file.
     ///
     /// We can't write directly to the final location, as it is determined by
     /// the hash of the header+content which won't be known until the end.
-    fn tmp_path(tmp_rand: &[u8]) -> Result<PathBuf> {
+    fn tmp_path(repo: &Repository, tmp_rand: &[u8]) -> PathBuf {
         let tmp_name = format!("tmpobj{}", hex::encode(tmp_rand));
-        Ok(git_dir()?.join(tmp_name))
+        repo.object_dir().join(tmp_name)
+    }
+
+    /// Create a temporary file for an object.
+    /// Like git, it is read-only from the start, which saves system calls.
+    fn create_tmp(tmp_path: &Path) -> Result<fs::File> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o444);
+        }
+        options.open(tmp_path).map_err(|e| write_error(e, tmp_path))
     }
 
-    /// Create an object writer.
+    /// Create an object writer for the given repository.
     ///
     /// Immediately handle the header, and get ready to receive content.
-    pub fn new(obj_type: ObjType, size: usize, write: bool) -> Result<ObjWriter> {
-        let hasher = Sha1::new();
+    pub fn new(
+        repo: &'r Repository,
+        obj_type: ObjType,
+        size: usize,
+        write: bool,
+    ) -> Result<ObjWriter<'r>> {
+        let hasher = repo.hash_algo()?.hasher();
 
         let mut tmp_rand = [0u8; 20];
         if write {
             rand::rng().fill(&mut tmp_rand);
         }
-        let zenc = if write {
-            // We don't know the name (hash) yet, so use a temporary file
-            let tmp_path = Self::tmp_path(&tmp_rand)?;
-            let file = fs::File::create(&tmp_path)
-                .with_context(|| format!("could not create {}", tmp_path.display()))?;
-
-            // Mimick git and set the file read-only.
-            let mut perms = file.metadata()?.permissions();
-            perms.set_readonly(true);
-            fs::set_permissions(tmp_path, perms).context("making temporary file read-only")?;
-
-            Some(ZlibEncoder::new(file, Compression::default()))
-        } else {
+        let zenc = if !write {
             None
+        } else if size <= SMALL_OBJECT_SIZE {
+            repo.ensure_writable("object")?;
+            let buf = Vec::with_capacity(size / 2 + 64);
+            Some(Sink::Memory(ZlibEncoder::new(buf, Compression::default())))
+        } else {
+            repo.ensure_writable("object")?;
+            // We don't know the name (hash) yet, so use a temporary file
+            let tmp_path = Self::tmp_path(repo, &tmp_rand);
+            let file = Self::create_tmp(&tmp_path)?;
+            Some(Sink::File(ZlibEncoder::new(file, Compression::default())))
         };
 
         // Object format: <type> <size>\0<content>, all zlib-compressed
         // The hash is over all of the above (header+content) before compression.
         // The size is that the contents (excluding the header).
         let mut writer = ObjWriter {
+            repo,
             hasher,
             zenc,
+            spill: None,
             size,
             seen: 0,
             past_header: false,
@@ -79,39 +124,177 @@ impl ObjWriter {
         Ok(writer)
     }
 
+    /// Create an object writer for content of unknown size, eg from a pipe.
+    ///
+    /// Content is written to a temporary file first; once it is complete,
+    /// the size is known, and finish() reads it back to hash (and write) it.
+    pub fn new_unsized(
+        repo: &'r Repository,
+        obj_type: ObjType,
+        write: bool,
+    ) -> Result<ObjWriter<'r>> {
+        if write {
+            repo.ensure_writable("object")?;
+        }
+        let mut tmp_rand = [0u8; 20];
+        rand::rng().fill(&mut tmp_rand);
+        // Spill next to the objects, unless only hashing: then the repository
+        // may not be writable, and the file is made private to the user.
+        let dir = match write {
+            true => repo.object_dir().to_owned(),
+            false => env::temp_dir(),
+        };
+        let path = dir.join(format!("tmpspill{}", hex::encode(tmp_rand)));
+        let mut options = fs::OpenOptions::new();
+        options.read(true).write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(&path).map_err(|e| write_error(e, &path))?;
+        Ok(ObjWriter {
+            repo,
+            hasher: repo.hash_algo()?.hasher(),
+            zenc: None,
+            spill: Some(Spill {
+                obj_type,
+                write,
+                file: io::BufWriter::new(file),
+                path,
+            }),
+            size: 0,
+            seen: 0,
+            past_header: true,
+            tmp_rand,
+        })
+    }
+
+    /// Hash (and write) an object whose content was spilled to a temporary file.
+    fn finish_spilled(repo: &Repository, spill: Spill, packed: bool) -> Result<(String, bool)> {
+        let mut file = spill.file.into_inner().map_err(|e| e.into_error())?;
+        let size = file.stream_position()?;
+        let size = usize::try_from(size).context("object size does no fit in usize")?;
+        file.rewind()?;
+        let mut object = ObjWriter::new(repo, spill.obj_type, size, spill.write)?;
+        io::copy(&mut file, &mut object).context("copying to object")?;
+        object.finish_inner(packed)
+    }
+
     /// Finalize object creation. Call this when all data has been written,
     /// to get the object's hash (and write it to permanent storage if selected).
     ///
     /// Checks that the size of the data written matches the announced size.
+    /// Objects already in the database (loose or packed) are not written again.
     pub fn finish(self) -> Result<String> {
+        Ok(self.finish_checked()?.0)
+    }
+
+    /// Same as finish(), also telling if the object was already in the
+    /// database, eg for statistics (only checked when writing).
+    pub fn finish_checked(self) -> Result<(String, bool)> {
+        self.finish_inner(true)
+    }
+
+    /// Same as finish(), but write the object even if it is in a pack,
+    /// eg one that is about to be removed.
+    pub fn finish_loose(self) -> Result<String> {
+        Ok(self.finish_inner(false)?.0)
+    }
+
+    /// Tell if an object is already in the database, only looking at the
+    /// repository's loose objects unless `packed` is set. Like git, refresh
+    /// the modification time of loose objects, so that gc doesn't prune them.
+    fn exists(repo: &Repository, hash_hex: &str, packed: bool) -> Result<bool> {
+        let local = repo.loose_path(hash_hex);
+        if local.exists() {
+            let _ = fs::File::open(&local).and_then(|f| f.set_modified(SystemTime::now()));
+            return Ok(true);
+        }
+        Ok(packed && (repo.find_loose(hash_hex).is_some() || pack_read::contains(repo, hash_hex)?))
+    }
+
+    /// Finalize object creation, see finish().
+    fn finish_inner(mut self, packed: bool) -> Result<(String, bool)> {
+        if let Some(spill) = self.spill.take() {
+            let path = spill.path.clone();
+            let res = Self::finish_spilled(self.repo, spill, packed);
+            let _ = fs::remove_file(path);
+            return res;
+        }
         if self.seen != self.size {
             bail!("size mismatch: expected {}, got {}", self.size, self.seen);
         }
 
-        let hash_bin = self.hasher.finalize();
-        let hash_hex = format!("{:x}", hash_bin);
+        let hash_hex = self.hasher.finalize_reset().to_string();
 
-        if let Some(zenc) = self.zenc {
-            zenc.finish().context("closing zlib stream")?;
-            let from = Self::tmp_path(&self.tmp_rand)?;
-            let to = path_from_hash(&hash_hex)?;
-            fs::create_dir_all(to.parent().expect("object path has a parent"))
-                .with_context(|| format!("creating {}", to.parent().unwrap().display()))?;
-            fs::rename(from, &to)
-                .with_context(|| format!("renaming temporary file to {}", to.display()))?;
+        let Some(zenc) = self.zenc.take() else {
+            return Ok((hash_hex, false));
+        };
+        // Drop won't see the temporary file any more, so remove it when done.
+        let from = Self::tmp_path(self.repo, &self.tmp_rand);
+        let existed = Self::exists(self.repo, &hash_hex, packed);
+        if !matches!(existed, Ok(false)) {
+            if let Sink::File(_) = zenc {
+                let _ = fs::remove_file(&from);
+            }
+            return Ok((hash_hex, existed?));
+        }
+        if let Err(e) = Self::store(self.repo, zenc, &from, &hash_hex) {
+            let _ = fs::remove_file(&from);
+            return Err(e);
+        }
+
+        Ok((hash_hex, false))
+    }
+
+    /// Finish writing the temporary file and move it to its final location.
+    fn store(repo: &Repository, zenc: Sink, from: &Path, hash_hex: &str) -> Result<()> {
+        match zenc {
+            Sink::File(zenc) => {
+                zenc.finish().context("closing zlib stream")?;
+            }
+            Sink::Memory(zenc) => {
+                let data = zenc.finish().context("closing zlib stream")?;
+                Self::create_tmp(from)?
+                    .write_all(&data)
+                    .map_err(|e| write_error(e, from))?;
+            }
         }
+        let to = repo.loose_path(hash_hex);
+        let dir = to.parent().expect("object path has a parent");
+        fs::create_dir_all(dir).map_err(|e| write_error(e, dir))?;
+        fs::rename(from, &to)
+            .with_context(|| format!("renaming temporary file to {}", to.display()))?;
+        Ok(())
+    }
+}
 
-        Ok(hash_hex)
+impl Drop for ObjWriter<'_> {
+    /// Remove the temporary file if the object was not finished,
+    /// for example because of an error or an interruption.
+    fn drop(&mut self) {
+        if let Some(spill) = self.spill.take() {
+            let _ = fs::remove_file(spill.path);
+        }
+        if let Some(Sink::File(_)) = self.zenc.take() {
+            let _ = fs::remove_file(Self::tmp_path(self.repo, &self.tmp_rand));
+        }
     }
 }
 
-impl Write for ObjWriter {
+impl Write for ObjWriter<'_> {
     /// Writes a buffer into this object, returning how many bytes were written.
     ///
     /// Ensure we don't write more than the announced size.
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(spill) = &mut self.spill {
+            return spill.file.write(buf);
+        }
+        // Only hash what was actually compressed, in case of a short write.
         let n = match &mut self.zenc {
-            Some(zenc) => zenc.write(buf)?,
+            Some(Sink::File(zenc)) => zenc.write(buf)?,
+            Some(Sink::Memory(zenc)) => zenc.write(buf)?,
             None => buf.len(),
         };
 
@@ -133,8 +316,12 @@ impl Write for ObjWriter {
     /// Flushes this output stream,
     /// ensuring that all intermediately buffered contents reach their destination.
     fn flush(&mut self) -> io::Result<()> {
+        if let Some(spill) = &mut self.spill {
+            return spill.file.flush();
+        }
         match &mut self.zenc {
-            Some(z) => z.flush(),
+            Some(Sink::File(z)) => z.flush(),
+            Some(Sink::Memory(z)) => z.flush(),
             None => Ok(()),
         }
     }
@@ -144,7 +331,12 @@ impl Write for ObjWriter {
 ///
 /// We require the source to implement Seek as we need to know the size in advance.
 /// In practice the two sources we'll use are File and Cursor.
-pub fn write_object<R>(obj_type: ObjType, source: &mut R, write: bool) -> Result<String>
+pub fn write_object<R>(
+    repo: &Repository,
+    obj_type: ObjType,
+    source: &mut R,
+    write: bool,
+) -> Result<String>
 where
     R: Read + Seek,
 {
@@ -154,7 +346,7 @@ where
 
     let size = usize::try_from(size).context("object size does no fit in usize")?;
 
-    let mut object = ObjWriter::new(obj_type, size, write).context("creating object")?;
+    let mut object = ObjWriter::new(repo, obj_type, size, write).context("creating object")?;
     io::copy(source, &mut object).context("copying to object")?;
     object.finish()
 }
@@ -0,0 +1,194 @@
+//! Object ids and the hash function computing them: SHA-1 by default,
+//! or SHA-256 in repositories created with `init --object-format=sha256`
+//! (which sets extensions.objectFormat), see gitformat-index(5) and
+//! git's hash-function-transition document.
+//!
+//! All objects in a repository use the same function, which also computes
+//! the checksums of packs, pack indexes and the index. Interoperability
+//! between the two (for fetching from or pushing to a SHA-1 repository)
+//! is not supported.
+
+use anyhow::{anyhow, bail, Result};
+use sha1::{Digest, Sha1};
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+
+use crate::repository::Repository;
+use crate::sha256::Sha256;
+
+/// Hash functions for object ids.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HashAlgo {
+    Sha1,
+    Sha256,
+}
+
+/// More zeros than the longest hash, see HashAlgo::zero_hash().
+const ZEROS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+impl HashAlgo {
+    /// Get a hash function from its name, as in extensions.objectFormat.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha1" => Ok(HashAlgo::Sha1),
+            "sha256" => Ok(HashAlgo::Sha256),
+            _ => bail!("unknown object format {name:?}"),
+        }
+    }
+
+    /// Get the name of this hash function.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgo::Sha1 => "sha1",
+            HashAlgo::Sha256 => "sha256",
+        }
+    }
+
+    /// Size of a hash in bytes.
+    pub fn raw_len(self) -> usize {
+        match self {
+            HashAlgo::Sha1 => 20,
+            HashAlgo::Sha256 => 32,
+        }
+    }
+
+    /// Size of a hash in hexadecimal.
+    pub fn hex_len(self) -> usize {
+        2 * self.raw_len()
+    }
+
+    /// The all-zero hash, meaning "no object" in reflogs and ref updates.
+    pub fn zero_hash(self) -> &'static str {
+        &ZEROS[..self.hex_len()]
+    }
+
+    /// The hash of the empty tree.
+    pub fn empty_tree(self) -> &'static str {
+        match self {
+            HashAlgo::Sha1 => "4b825dc642cb6eb9a060e54bf8d69288fbee4904",
+            HashAlgo::Sha256 => "6ef19b41225c5369f1c104d45d8d85efa9b057b53b14b4b9b939dd74decc5321",
+        }
+    }
+
+    /// Start computing a hash.
+    pub fn hasher(self) -> Hasher {
+        match self {
+            HashAlgo::Sha1 => Hasher::Sha1(Sha1::new()),
+            HashAlgo::Sha256 => Hasher::Sha256(Sha256::default()),
+        }
+    }
+}
+
+/// Tell if a string is a full hash in hexadecimal for a repository.
+pub fn is_hash(repo: &Repository, s: &str) -> bool {
+    repo.hash_algo().is_ok_and(|algo| s.len() == algo.hex_len())
+        && s.bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+/// An object id in binary form.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct ObjectId {
+    /// The hash, followed by zeros for SHA-1
+    bytes: [u8; 32],
+    len: u8,
+}
+
+impl ObjectId {
+    /// Make an id from a hash in binary form.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != 20 && bytes.len() != 32 {
+            bail!("invalid hash length {}", bytes.len());
+        }
+        let mut id = ObjectId {
+            bytes: [0; 32],
+            len: bytes.len() as u8,
+        };
+        id.bytes[..bytes.len()].copy_from_slice(bytes);
+        Ok(id)
+    }
+
+    /// Make an id from a hash in hexadecimal.
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let bytes = hex::decode(hex).map_err(|e| anyhow!("invalid hash {hex:?}: {e}"))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Read an id in binary form, with the size of hashes of the given function.
+    pub fn read_from(reader: &mut impl Read, algo: HashAlgo) -> Result<Self> {
+        let mut bytes = [0; 32];
+        let len = algo.raw_len();
+        reader.read_exact(&mut bytes[..len])?;
+        Self::from_bytes(&bytes[..len])
+    }
+}
+
+impl AsRef<[u8]> for ObjectId {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&hex::encode(self))
+    }
+}
+
+/// Computes a hash with the function of the repository.
+#[derive(Clone)]
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    /// Add data to the hash.
+    pub fn update(&mut self, data: impl AsRef<[u8]>) {
+        match self {
+            Hasher::Sha1(h) => h.update(data),
+            Hasher::Sha256(h) => h.update(data.as_ref()),
+        }
+    }
+
+    /// Get the hash of all data added so far.
+    pub fn finalize(self) -> ObjectId {
+        let id = match self {
+            Hasher::Sha1(h) => ObjectId::from_bytes(&h.finalize()),
+            Hasher::Sha256(h) => ObjectId::from_bytes(&h.finalize()),
+        };
+        id.expect("valid hash length")
+    }
+
+    /// Get the hash function used.
+    pub fn algo(&self) -> HashAlgo {
+        match self {
+            Hasher::Sha1(_) => HashAlgo::Sha1,
+            Hasher::Sha256(_) => HashAlgo::Sha256,
+        }
+    }
+
+    /// Get the hash of all data added so far, and start over.
+    pub fn finalize_reset(&mut self) -> ObjectId {
+        let algo = self.algo();
+        std::mem::replace(self, algo.hasher()).finalize()
+    }
+
+    /// Compute the hash of some data.
+    pub fn digest(algo: HashAlgo, data: impl AsRef<[u8]>) -> ObjectId {
+        let mut hasher = algo.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,580 @@
+//! Indexing packfiles: finding the hash, offset and CRC32 of each object,
+//! and writing the corresponding pack index (.idx) file, or checking it.
+//!
+//! See gitformat-pack(5) "pack-*.pack files have the following format"
+//! and "Version 2 pack-*.idx files support packs larger than 4 GiB".
+
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::bufread::ZlibDecoder;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::common::write_error;
+use crate::obj_type::ObjType;
+use crate::object_id::{Hasher, ObjectId};
+use crate::pack_read::read_index;
+use crate::repository::Repository;
+
+/// How an entry's content is stored in the pack.
+enum EntryKind {
+    Base(ObjType),
+    /// Delta against the entry at this offset in the pack
+    OfsDelta(u64),
+    /// Delta against the object with this hash
+    RefDelta(ObjectId),
+}
+
+/// An entry as read from the pack, before deltas are resolved.
+struct RawEntry {
+    offset: u64,
+    crc32: u32,
+    kind: EntryKind,
+    /// Uncompressed data: object content, or delta instructions
+    data: Vec<u8>,
+}
+
+/// An entry once its delta chain is resolved.
+struct Resolved {
+    hash: ObjectId,
+    obj_type: ObjType,
+    /// Number of deltas to apply to get the content (0 for base entries)
+    depth: usize,
+    /// Index of the base entry, for deltas
+    base: Option<usize>,
+}
+
+/// An object found in a pack.
+pub struct IndexEntry {
+    pub hash: ObjectId,
+    /// Offset of the entry from the start of the pack
+    pub offset: u64,
+    /// CRC32 of the entry's raw (compressed) bytes in the pack
+    pub crc32: u32,
+}
+
+/// Read a size in the variable-length format used in packfiles,
+/// see unpack::read_size_and_opt_type() for details.
+pub fn read_size(data: &[u8], pos: &mut usize, type_bits: u8) -> Result<(u8, usize)> {
+    let mut next = || -> Result<u8> {
+        let Some(&byte) = data.get(*pos) else {
+            bail!("truncated size");
+        };
+        *pos += 1;
+        Ok(byte)
+    };
+    let mut byte = next()?;
+    let mut size_bits = 7 - type_bits;
+    let size_mask = (1 << size_bits) - 1;
+    let type_id = (byte & 0x7f & !size_mask) >> size_bits;
+    let mut size = (byte & size_mask) as usize;
+    while byte & 0x80 != 0 {
+        if size_bits >= usize::BITS as u8 {
+            bail!("size too large");
+        }
+        byte = next()?;
+        size += ((byte & 0x7f) as usize) << size_bits;
+        size_bits += 7;
+    }
+    Ok((type_id, size))
+}
+
+/// Read the negative offset of an ofs_delta's base,
+/// see gitformat-pack(5) "offset encoding".
+pub fn read_ofs(data: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut next = || -> Result<u8> {
+        let Some(&byte) = data.get(*pos) else {
+            bail!("truncated base offset");
+        };
+        *pos += 1;
+        Ok(byte)
+    };
+    let mut byte = next()?;
+    let mut ofs = (byte & 0x7f) as u64;
+    while byte & 0x80 != 0 {
+        byte = next()?;
+        let Some(shifted) = ofs.checked_add(1).and_then(|ofs| ofs.checked_mul(1 << 7)) else {
+            bail!("base offset too large");
+        };
+        ofs = shifted | (byte & 0x7f) as u64;
+    }
+    Ok(ofs)
+}
+
+/// How much bigger than its compressed form data can get with deflate.
+const MAX_DEFLATE_RATIO: usize = 1032;
+
+/// Decompress a zlib stream starting at pos, advancing pos past its end.
+///
+/// The size comes from the pack and can't be trusted before decompressing:
+/// space is reserved for no more than the rest of the data could expand to.
+fn inflate(data: &[u8], pos: &mut usize, size: usize) -> Result<Vec<u8>> {
+    let mut zdec = ZlibDecoder::new(&data[*pos..]);
+    let max_size = (data.len() - *pos).saturating_mul(MAX_DEFLATE_RATIO);
+    let mut out = Vec::with_capacity(size.min(max_size));
+    zdec.read_to_end(&mut out).context("decompressing entry")?;
+    if out.len() != size {
+        bail!("size mismatch: expected {size}, got {}", out.len());
+    }
+    *pos += zdec.total_in() as usize;
+    Ok(out)
+}
+
+/// Read one entry starting at pos, advancing pos past its end.
+fn read_entry(repo: &Repository, data: &[u8], pos: &mut usize) -> Result<RawEntry> {
+    let start = *pos;
+    let (type_id, size) = read_size(data, pos, 3).context("reading type and size")?;
+    let kind = match type_id {
+        1 => EntryKind::Base(ObjType::Commit),
+        2 => EntryKind::Base(ObjType::Tree),
+        3 => EntryKind::Base(ObjType::Blob),
+        4 => EntryKind::Base(ObjType::Tag),
+        6 => {
+            let ofs = read_ofs(data, pos)?;
+            let Some(base) = (start as u64).checked_sub(ofs) else {
+                bail!("base offset out of range");
+            };
+            EntryKind::OfsDelta(base)
+        }
+        7 => {
+            let hash_len = repo.hash_algo()?.raw_len();
+            let Some(hash) = data.get(*pos..*pos + hash_len) else {
+                bail!("truncated base hash");
+            };
+            *pos += hash_len;
+            EntryKind::RefDelta(ObjectId::from_bytes(hash)?)
+        }
+        t => bail!("unknown pack object type: {t}"),
+    };
+    let data_out = inflate(data, pos, size)?;
+    Ok(RawEntry {
+        offset: start as u64,
+        crc32: crc32fast::hash(&data[start..*pos]),
+        kind,
+        data: data_out,
+    })
+}
+
+/// Apply delta instructions to a base object's content.
+/// See gitformat-pack(5) "Deltified representation".
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut pos = 0;
+    let (_, base_size) = read_size(delta, &mut pos, 0).context("reading base size")?;
+    let (_, size) = read_size(delta, &mut pos, 0).context("reading object size")?;
+    if base_size != base.len() {
+        bail!("delta base size mismatch");
+    }
+
+    // Don't trust the size before checking it against the result: usually,
+    // the object is not much bigger than its base and the delta together.
+    let mut out = Vec::with_capacity(size.min(base.len() + delta.len()));
+    while let Some(&first) = delta.get(pos) {
+        pos += 1;
+        if first & 0x80 != 0 {
+            // copy instruction: up to 4 bytes of offset, 3 bytes of size
+            let mut field = |bits: std::ops::Range<u8>| -> Result<usize> {
+                let mut value = 0;
+                for (i, b) in bits.enumerate() {
+                    if first & (1 << b) != 0 {
+                        let Some(&byte) = delta.get(pos) else {
+                            bail!("truncated copy instruction");
+                        };
+                        pos += 1;
+                        value |= (byte as usize) << (8 * i);
+                    }
+                }
+                Ok(value)
+            };
+            let offset = field(0..4)?;
+            let len = match field(4..7)? {
+                0 => 0x10000,
+                n => n,
+            };
+            let Some(chunk) = base.get(offset..offset + len) else {
+                bail!("copy instruction out of base object bounds");
+            };
+            out.extend_from_slice(chunk);
+        } else if first != 0 {
+            // add instruction
+            let len = first as usize;
+            let Some(chunk) = delta.get(pos..pos + len) else {
+                bail!("truncated add instruction");
+            };
+            pos += len;
+            out.extend_from_slice(chunk);
+        } else {
+            bail!("reserved delta instruction");
+        }
+    }
+    if out.len() != size {
+        bail!("delta result size mismatch");
+    }
+    Ok(out)
+}
+
+/// Length of the blocks of the base looked for in the target by create_delta().
+const DELTA_BLOCK: usize = 16;
+
+/// Write a size in delta headers: 7 bits per byte, least significant first.
+fn write_delta_size(out: &mut Vec<u8>, mut size: usize) {
+    while size >= 0x80 {
+        out.push(size as u8 | 0x80);
+        size >>= 7;
+    }
+    out.push(size as u8);
+}
+
+/// Compute delta instructions turning a base into a target, for
+/// apply_delta(). This is simpler than git's algorithm: parts of the target
+/// are copied from the base wherever one of its blocks is found, extended as
+/// far as possible, and the rest is added as is.
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_delta_size(&mut out, base.len());
+    write_delta_size(&mut out, target.len());
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for offset in 0..(base.len() + 1).saturating_sub(DELTA_BLOCK) {
+        blocks
+            .entry(&base[offset..offset + DELTA_BLOCK])
+            .or_insert(offset);
+    }
+    let add = |out: &mut Vec<u8>, data: &[u8]| {
+        for chunk in data.chunks(0x7f) {
+            out.push(chunk.len() as u8);
+            out.extend_from_slice(chunk);
+        }
+    };
+    let (mut pos, mut added) = (0, 0);
+    while pos + DELTA_BLOCK <= target.len() {
+        let Some(&offset) = blocks.get(&target[pos..pos + DELTA_BLOCK]) else {
+            pos += 1;
+            continue;
+        };
+        let len = base[offset..]
+            .iter()
+            .zip(&target[pos..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        add(&mut out, &target[added..pos]);
+        // Copy instructions have the non-zero bytes of the offset and size,
+        // a size of 0x10000 being written as 0.
+        for start in (0..len).step_by(0x10000) {
+            let (offset, size) = (offset + start, (len - start).min(0x10000));
+            let mut command = 0x80;
+            let mut args = Vec::new();
+            for (bit, byte) in offset.to_le_bytes()[..4].iter().enumerate() {
+                if *byte != 0 {
+                    command |= 1 << bit;
+                    args.push(*byte);
+                }
+            }
+            for (bit, byte) in (size & 0xffff).to_le_bytes()[..3].iter().enumerate() {
+                if *byte != 0 {
+                    command |= 0x10 << bit;
+                    args.push(*byte);
+                }
+            }
+            out.push(command);
+            out.extend_from_slice(&args);
+        }
+        pos += len;
+        added = pos;
+    }
+    add(&mut out, &target[added..]);
+    out
+}
+
+/// Compute the hash of an object from its type and content.
+fn hash_object(repo: &Repository, obj_type: &ObjType, content: &[u8]) -> Result<ObjectId> {
+    let mut hasher = repo.hash_algo()?.hasher();
+    hasher.update(format!("{} {}\0", obj_type.to_str(), content.len()));
+    hasher.update(content);
+    Ok(hasher.finalize())
+}
+
+/// Resolve entries' types and contents, following delta chains within the pack.
+fn resolve(repo: &Repository, entries: &[RawEntry]) -> Result<Vec<Resolved>> {
+    let mut contents: Vec<Option<Vec<u8>>> = entries.iter().map(|_| None).collect();
+    let mut resolved: Vec<Option<Resolved>> = entries.iter().map(|_| None).collect();
+    // Resolved entries by hash, to find the bases of ref_deltas.
+    let mut by_hash: HashMap<ObjectId, usize> = HashMap::new();
+
+    // Keep going over unresolved entries until no progress is made:
+    // bases can appear after deltas that use them (ref_delta).
+    loop {
+        let mut progress = false;
+        for (i, entry) in entries.iter().enumerate() {
+            if resolved[i].is_some() {
+                continue;
+            }
+            let base =
+                match &entry.kind {
+                    EntryKind::Base(obj_type) => {
+                        let hash = hash_object(repo, obj_type, &entry.data)?;
+                        by_hash.entry(hash).or_insert(i);
+                        resolved[i] = Some(Resolved {
+                            hash,
+                            obj_type: obj_type.clone(),
+                            depth: 0,
+                            base: None,
+                        });
+                        contents[i] = Some(entry.data.clone());
+                        progress = true;
+                        continue;
+                    }
+                    EntryKind::OfsDelta(offset) => entries
+                        .binary_search_by_key(offset, |e| e.offset)
+                        .map_err(|_| anyhow!("no entry at base offset {offset}"))?,
+                    EntryKind::RefDelta(hash) => match by_hash.get(hash) {
+                        Some(&base) => base,
+                        None => continue,
+                    },
+                };
+            let (Some(base_entry), Some(base_data)) = (&resolved[base], &contents[base]) else {
+                continue;
+            };
+            let content = apply_delta(base_data, &entry.data)
+                .with_context(|| format!("resolving delta at offset {}", entry.offset))?;
+            let obj_type = base_entry.obj_type.clone();
+            let hash = hash_object(repo, &obj_type, &content)?;
+            by_hash.entry(hash).or_insert(i);
+            resolved[i] = Some(Resolved {
+                hash,
+                obj_type,
+                depth: base_entry.depth + 1,
+                base: Some(base),
+            });
+            contents[i] = Some(content);
+            progress = true;
+        }
+        if !progress {
+            break;
+        }
+    }
+
+    resolved
+        .into_iter()
+        .zip(entries)
+        .map(|(resolved, entry)| match (resolved, &entry.kind) {
+            (Some(resolved), _) => Ok(resolved),
+            (None, EntryKind::RefDelta(base)) => bail!(
+                "base object {} not in pack (thin packs are not supported)",
+                hex::encode(base)
+            ),
+            (None, _) => bail!("unresolved delta at offset {}", entry.offset),
+        })
+        .collect()
+}
+
+/// Read all the entries of a pack held in memory after checking its header
+/// and checksum, returning them in pack order with the checksum.
+fn read_entries(repo: &Repository, pack: &[u8]) -> Result<(Vec<RawEntry>, ObjectId)> {
+    let algo = repo.hash_algo()?;
+    if pack.len() < 12 + algo.raw_len() || &pack[..8] != b"PACK\x00\x00\x00\x02" {
+        bail!("invalid packfile header");
+    }
+    let (body, checksum) = pack.split_at(pack.len() - algo.raw_len());
+    let checksum = ObjectId::from_bytes(checksum)?;
+    if Hasher::digest(algo, body) != checksum {
+        bail!("pack checksum mismatch");
+    }
+    let nb_obj = u32::from_be_bytes(body[8..12].try_into().expect("slice size is 4"));
+
+    let mut pos = 12;
+    let mut entries = Vec::new();
+    for i in 0..nb_obj {
+        let entry = read_entry(repo, body, &mut pos)
+            .with_context(|| format!("reading object {}/{nb_obj}", i + 1))?;
+        entries.push(entry);
+    }
+    if pos != body.len() {
+        bail!("trailing data after last object");
+    }
+    Ok((entries, checksum))
+}
+
+/// Index a pack held in memory: check its header and checksum, and return
+/// its objects (sorted by hash) and checksum.
+pub fn index_pack(repo: &Repository, pack: &[u8]) -> Result<(Vec<IndexEntry>, ObjectId)> {
+    let (entries, checksum) = read_entries(repo, pack)?;
+    let resolved = resolve(repo, &entries)?;
+    let mut index: Vec<IndexEntry> = entries
+        .iter()
+        .zip(resolved)
+        .map(|(e, resolved)| IndexEntry {
+            hash: resolved.hash,
+            offset: e.offset,
+            crc32: e.crc32,
+        })
+        .collect();
+    index.sort_unstable_by_key(|e| e.hash);
+    Ok((index, checksum))
+}
+
+/// An object of a pack, as described by verify-pack -v.
+pub struct PackObject {
+    pub hash: ObjectId,
+    pub obj_type: ObjType,
+    /// Size of the content, or of the delta instructions for deltified objects
+    pub size: usize,
+    /// Size of the entry in the pack, header included
+    pub packed_size: u64,
+    pub offset: u64,
+    /// Length of the delta chain and hash of the base, for deltified objects
+    pub delta: Option<(usize, ObjectId)>,
+}
+
+/// Check a pack against its index, like git verify-pack: the checksums of
+/// both files, the number of objects, and the offset and CRC32 of each of
+/// them, all objects being read and hashed. Return the objects in pack order.
+pub fn verify_pack(repo: &Repository, idx_path: &Path) -> Result<Vec<PackObject>> {
+    let (index, pack_checksum) = read_index(repo, idx_path)?;
+    let pack_path = idx_path.with_extension("pack");
+    let pack = fs::read(&pack_path).with_context(|| format!("reading {}", pack_path.display()))?;
+    let (entries, checksum) = read_entries(repo, &pack)?;
+    if checksum != pack_checksum {
+        bail!("pack checksum does not match the one in the index");
+    }
+    if entries.len() != index.len() {
+        bail!(
+            "pack has {} objects, index has {}",
+            entries.len(),
+            index.len()
+        );
+    }
+    let resolved = resolve(repo, &entries)?;
+    let by_hash: HashMap<ObjectId, usize> = resolved
+        .iter()
+        .enumerate()
+        .map(|(i, r)| (r.hash, i))
+        .collect();
+    for expected in &index {
+        let Some(&i) = by_hash.get(&expected.hash) else {
+            bail!(
+                "object {} is in the index but not in the pack",
+                expected.hash
+            );
+        };
+        if entries[i].offset != expected.offset {
+            bail!(
+                "object {}: offset {} in the index, {} in the pack",
+                expected.hash,
+                expected.offset,
+                entries[i].offset
+            );
+        }
+        if entries[i].crc32 != expected.crc32 {
+            bail!("object {}: CRC32 mismatch", expected.hash);
+        }
+    }
+
+    let pack_end = (pack.len() - repo.hash_algo()?.raw_len()) as u64;
+    let ends = entries.iter().skip(1).map(|e| e.offset).chain([pack_end]);
+    Ok(entries
+        .iter()
+        .zip(ends)
+        .zip(&resolved)
+        .map(|((entry, end), r)| PackObject {
+            hash: r.hash,
+            obj_type: r.obj_type.clone(),
+            size: entry.data.len(),
+            packed_size: end - entry.offset,
+            offset: entry.offset,
+            delta: r.base.map(|base| (r.depth, resolved[base].hash)),
+        })
+        .collect())
+}
+
+/// Write a version 2 pack index for the given entries (which must be sorted).
+pub fn write_index(
+    repo: &Repository,
+    mut out: impl Write,
+    entries: &[IndexEntry],
+    pack_checksum: &ObjectId,
+) -> Result<()> {
+    let mut idx = Vec::new();
+
+    // magic number and version
+    idx.extend_from_slice(b"\xfftOc\x00\x00\x00\x02");
+
+    // fan-out table: number of objects whose hash starts with a byte <= i
+    for i in 0..=255u8 {
+        let count = entries.partition_point(|e| e.hash.as_ref()[0] <= i) as u32;
+        idx.extend_from_slice(&count.to_be_bytes());
+    }
+
+    for entry in entries {
+        idx.extend_from_slice(entry.hash.as_ref());
+    }
+    for entry in entries {
+        idx.extend_from_slice(&entry.crc32.to_be_bytes());
+    }
+
+    // 31-bit offsets, or an index into the table of 64-bit offsets with the MSB set
+    let mut large = Vec::new();
+    for entry in entries {
+        let offset = match u32::try_from(entry.offset) {
+            Ok(o) if o < 0x8000_0000 => o,
+            _ => {
+                large.push(entry.offset);
+                0x8000_0000 | u32::try_from(large.len() - 1).context("too many large offsets")?
+            }
+        };
+        idx.extend_from_slice(&offset.to_be_bytes());
+    }
+    for offset in large {
+        idx.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    idx.extend_from_slice(pack_checksum.as_ref());
+    let idx_checksum = Hasher::digest(repo.hash_algo()?, &idx);
+    idx.extend_from_slice(idx_checksum.as_ref());
+
+    out.write_all(&idx).context("writing pack index")?;
+    Ok(())
+}
+
+/// Receive a pack into a temporary file, index it, and move it in place.
+/// The index is written last, as that makes the pack visible to readers.
+fn receive_pack(
+    repo: &Repository,
+    reader: &mut impl Read,
+    dir: &Path,
+    tmp_path: &Path,
+) -> Result<(String, usize)> {
+    let mut tmp = fs::File::create(tmp_path).map_err(|e| write_error(e, tmp_path))?;
+    io::copy(reader, &mut tmp).context("receiving pack")?;
+    drop(tmp);
+    let pack = fs::read(tmp_path).context("reading received pack")?;
+    let (entries, checksum) = index_pack(repo, &pack).context("indexing pack")?;
+
+    let name = hex::encode(checksum);
+    let pack_path = dir.join(format!("pack-{name}.pack"));
+    let idx_path = pack_path.with_extension("idx");
+    fs::rename(tmp_path, &pack_path)
+        .with_context(|| format!("renaming temporary file to {}", pack_path.display()))?;
+    let idx = fs::File::create(&idx_path).map_err(|e| write_error(e, &idx_path))?;
+    write_index(repo, io::BufWriter::new(idx), &entries, &checksum)
+        .with_context(|| format!("writing {}", idx_path.display()))?;
+    Ok((name, entries.len()))
+}
+
+/// Store a pack read from the given reader in the object database,
+/// along with its index. Return the pack's name (checksum) and number of objects.
+pub fn store_pack(repo: &Repository, mut reader: impl Read) -> Result<(String, usize)> {
+    repo.ensure_writable("pack")?;
+    let dir = repo.object_dir().join("pack");
+    fs::create_dir_all(&dir).map_err(|e| write_error(e, &dir))?;
+    let mut tmp_rand = [0u8; 20];
+    rand::rng().fill(&mut tmp_rand);
+    let tmp_path = dir.join(format!("tmp_pack_{}", hex::encode(tmp_rand)));
+
+    let res = receive_pack(repo, &mut reader, &dir, &tmp_path);
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    res
+}
@@ -0,0 +1,387 @@
+//! Reading objects from packfiles, using their index.
+//!
+//! See gitformat-pack(5) "Version 2 pack-*.idx files support packs larger than 4 GiB".
+
+use anyhow::{bail, Context, Result};
+use flate2::bufread::ZlibDecoder;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::MutexGuard;
+
+use crate::obj_read::{Source, MAX_BLOB_SIZE};
+use crate::obj_type::ObjType;
+use crate::object_id::{Hasher, ObjectId};
+use crate::pack_index::{apply_delta, read_ofs, read_size, IndexEntry};
+use crate::repository::Repository;
+
+/// Size of the idx header: magic number, version, fan-out table.
+const IDX_HEADER: usize = 8 + 256 * 4;
+
+/// Maximum length of a chain of deltas, to stop on corrupt packs. git itself
+/// writes chains of at most 50 (pack.depth), and allows up to 4095.
+const MAX_DELTA_DEPTH: usize = 10_000;
+
+/// A pack index loaded in memory, with the path of the corresponding pack.
+pub(crate) struct PackIndex {
+    pack_path: PathBuf,
+    /// Whether the pack is in the repository's object directory rather than an alternate
+    local: bool,
+    idx: Vec<u8>,
+    nb_obj: usize,
+    /// Size of hashes in bytes
+    hash_len: usize,
+}
+
+impl PackIndex {
+    /// Load an index file and check its format, for hashes of `hash_len` bytes.
+    fn open(idx_path: &Path, local: bool, hash_len: usize) -> Result<Self> {
+        let idx = fs::read(idx_path).with_context(|| format!("reading {}", idx_path.display()))?;
+        Self::parse(idx, idx_path, local, hash_len)
+    }
+
+    /// Check the format of an index loaded from the given path.
+    fn parse(idx: Vec<u8>, idx_path: &Path, local: bool, hash_len: usize) -> Result<Self> {
+        if idx.len() < IDX_HEADER || &idx[..8] != b"\xfftOc\x00\x00\x00\x02" {
+            bail!("{}: not a version 2 pack index", idx_path.display());
+        }
+        let mut index = Self {
+            pack_path: idx_path.with_extension("pack"),
+            local,
+            idx,
+            nb_obj: 0,
+            hash_len,
+        };
+        index.nb_obj = index.fanout(255);
+        // hashes, CRC32s, offsets, two checksums (large offsets are optional)
+        if index.idx.len() < IDX_HEADER + index.nb_obj * (hash_len + 8) + 2 * hash_len {
+            bail!("{}: truncated pack index", idx_path.display());
+        }
+        Ok(index)
+    }
+
+    /// Read a 32-bit big-endian number at the given position.
+    fn u32_at(&self, pos: usize) -> u32 {
+        u32::from_be_bytes(self.idx[pos..pos + 4].try_into().expect("slice size is 4"))
+    }
+
+    /// Number of objects whose hash starts with a byte <= first.
+    fn fanout(&self, first: u8) -> usize {
+        self.u32_at(8 + first as usize * 4) as usize
+    }
+
+    /// Find the offset of an object in the pack.
+    fn find(&self, hash: &[u8]) -> Option<u64> {
+        let lo = match hash[0] {
+            0 => 0,
+            first => self.fanout(first - 1),
+        };
+        let hi = self.fanout(hash[0]);
+        let (mut lo, mut hi) = (lo, hi);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.hash(mid).cmp(hash) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(self.offset(mid)),
+            }
+        }
+        None
+    }
+
+    /// List hashes starting with a prefix of at least two hex digits.
+    fn with_prefix(&self, prefix: &str, out: &mut Vec<String>) {
+        let Ok(first) = u8::from_str_radix(&prefix[..2], 16) else {
+            return;
+        };
+        let lo = match first {
+            0 => 0,
+            first => self.fanout(first - 1),
+        };
+        for i in lo..self.fanout(first) {
+            let hash = hex::encode(self.hash(i));
+            if hash.starts_with(prefix) {
+                out.push(hash);
+            }
+        }
+    }
+
+    /// Get the hash of the i-th object (in hash order).
+    fn hash(&self, i: usize) -> &[u8] {
+        let start = IDX_HEADER + i * self.hash_len;
+        &self.idx[start..start + self.hash_len]
+    }
+
+    /// List the hashes of all objects in the pack, in order.
+    fn hashes(&self) -> Vec<String> {
+        (0..self.nb_obj)
+            .map(|i| hex::encode(self.hash(i)))
+            .collect()
+    }
+
+    /// Get the CRC32 of the i-th object (in hash order).
+    fn crc32(&self, i: usize) -> u32 {
+        self.u32_at(IDX_HEADER + self.nb_obj * self.hash_len + i * 4)
+    }
+
+    /// Get the offset of the i-th object (in hash order).
+    fn offset(&self, i: usize) -> u64 {
+        // after hashes and CRC32s
+        let offsets = IDX_HEADER + self.nb_obj * (self.hash_len + 4);
+        let offset = self.u32_at(offsets + i * 4);
+        if offset & 0x8000_0000 == 0 {
+            return offset as u64;
+        }
+        let large = offsets + self.nb_obj * 4 + (offset & 0x7fff_ffff) as usize * 8;
+        u64::from_be_bytes(
+            self.idx[large..large + 8]
+                .try_into()
+                .expect("slice size is 8"),
+        )
+    }
+}
+
+/// Get the indexes of the packs of a repository found so far.
+fn lock_packs(repo: &Repository) -> MutexGuard<'_, Vec<PackIndex>> {
+    repo.cache()
+        .packs
+        .lock()
+        .expect("no panic while holding the lock")
+}
+
+/// Load indexes of packs that were not known yet (eg, just fetched),
+/// from the object directory then alternates.
+fn load_new_packs(repo: &Repository, packs: &mut Vec<PackIndex>) -> Result<()> {
+    let hash_len = repo.hash_algo()?.raw_len();
+    for (i, objects) in repo.object_dirs().enumerate() {
+        let dir = objects.join("pack");
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry
+                .with_context(|| format!("listing {}", dir.display()))?
+                .path();
+            if path.extension() != Some("idx".as_ref()) {
+                continue;
+            }
+            if !packs
+                .iter()
+                .any(|p| p.pack_path == path.with_extension("pack"))
+            {
+                packs.push(PackIndex::open(&path, i == 0, hash_len)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Find which pack contains an object, and at what offset.
+fn find(repo: &Repository, hash: &str) -> Result<Option<(PathBuf, u64)>> {
+    let Ok(bin) = hex::decode(hash) else {
+        return Ok(None);
+    };
+    if bin.len() != repo.hash_algo()?.raw_len() {
+        return Ok(None);
+    }
+    let mut packs = lock_packs(repo);
+    let search = |packs: &[PackIndex]| {
+        packs
+            .iter()
+            .find_map(|p| p.find(&bin).map(|offset| (p.pack_path.clone(), offset)))
+    };
+    if let Some(found) = search(&packs) {
+        return Ok(Some(found));
+    }
+    let known = packs.len();
+    load_new_packs(repo, &mut packs)?;
+    Ok(search(&packs[known..]))
+}
+
+/// Forget about a pack that was removed, so that its objects are looked
+/// up elsewhere.
+pub fn forget(repo: &Repository, pack: &Path) {
+    let mut packs = lock_packs(repo);
+    packs.retain(|p| p.pack_path != pack);
+}
+
+/// Does a pack contain this object?
+pub fn contains(repo: &Repository, hash: &str) -> Result<bool> {
+    Ok(find(repo, hash)?.is_some())
+}
+
+/// List hashes of packed objects starting with a prefix of at least two hex digits.
+pub fn with_prefix(repo: &Repository, prefix: &str) -> Result<Vec<String>> {
+    let mut packs = lock_packs(repo);
+    load_new_packs(repo, &mut packs)?;
+    let mut found = Vec::new();
+    for pack in packs.iter() {
+        pack.with_prefix(prefix, &mut found);
+    }
+    Ok(found)
+}
+
+/// List the packs in the repository (not in alternates), along with the
+/// hashes of their objects.
+pub fn list(repo: &Repository) -> Result<Vec<(PathBuf, Vec<String>)>> {
+    list_packs(repo, false)
+}
+
+/// Same as list(), also including packs in alternates.
+pub fn list_all(repo: &Repository) -> Result<Vec<(PathBuf, Vec<String>)>> {
+    list_packs(repo, true)
+}
+
+/// List packs and their objects, only local ones unless `alternates` is set.
+fn list_packs(repo: &Repository, alternates: bool) -> Result<Vec<(PathBuf, Vec<String>)>> {
+    let mut packs = lock_packs(repo);
+    load_new_packs(repo, &mut packs)?;
+    Ok(packs
+        .iter()
+        .filter(|p| alternates || p.local)
+        .map(|p| (p.pack_path.clone(), p.hashes()))
+        .collect())
+}
+
+/// List the hashes of the objects in a pack index that is not part of the
+/// repository, eg downloaded from a server; the name is used for errors.
+pub fn index_hashes(repo: &Repository, idx: Vec<u8>, name: &str) -> Result<Vec<String>> {
+    let hash_len = repo.hash_algo()?.raw_len();
+    Ok(PackIndex::parse(idx, Path::new(name), false, hash_len)?.hashes())
+}
+
+/// Read a pack index file after checking its own checksum, returning its
+/// entries (in hash order) and the checksum of the pack it is for.
+pub fn read_index(repo: &Repository, idx_path: &Path) -> Result<(Vec<IndexEntry>, ObjectId)> {
+    let algo = repo.hash_algo()?;
+    let index = PackIndex::open(idx_path, false, algo.raw_len())?;
+    let hash_len = index.hash_len;
+    let (content, checksum) = index.idx.split_at(index.idx.len() - hash_len);
+    if Hasher::digest(algo, content).as_ref() != checksum {
+        bail!("{}: index checksum mismatch", idx_path.display());
+    }
+    let pack_checksum = ObjectId::from_bytes(&content[content.len() - hash_len..])?;
+    let entries = (0..index.nb_obj)
+        .map(|i| {
+            Ok(IndexEntry {
+                hash: ObjectId::from_bytes(index.hash(i))?,
+                offset: index.offset(i),
+                crc32: index.crc32(i),
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok((entries, pack_checksum))
+}
+
+/// What the header of a pack entry says about how to get its content.
+enum EntryKind {
+    Base(ObjType, usize),
+    OfsDelta(u64),
+    RefDelta(String),
+}
+
+/// Read the header of the entry at the given offset,
+/// leaving the file positioned at the start of the compressed data.
+fn read_header(
+    file: &mut io::BufReader<fs::File>,
+    offset: u64,
+    hash_len: usize,
+) -> Result<EntryKind> {
+    file.seek(SeekFrom::Start(offset))?;
+    // Headers are at most 10 bytes of type and size, plus the base hash.
+    let mut head = Vec::new();
+    file.by_ref()
+        .take(10 + hash_len as u64)
+        .read_to_end(&mut head)?;
+    let mut pos = 0;
+    let (type_id, size) = read_size(&head, &mut pos, 3)?;
+    let kind = match type_id {
+        1 => EntryKind::Base(ObjType::Commit, size),
+        2 => EntryKind::Base(ObjType::Tree, size),
+        3 => EntryKind::Base(ObjType::Blob, size),
+        4 => EntryKind::Base(ObjType::Tag, size),
+        6 => {
+            let ofs = read_ofs(&head, &mut pos)?;
+            let Some(base) = offset.checked_sub(ofs) else {
+                bail!("base offset out of range");
+            };
+            EntryKind::OfsDelta(base)
+        }
+        7 => {
+            let Some(hash) = head.get(pos..pos + hash_len) else {
+                bail!("truncated base hash");
+            };
+            pos += hash_len;
+            EntryKind::RefDelta(hex::encode(hash))
+        }
+        t => bail!("unknown pack object type: {t}"),
+    };
+    file.seek(SeekFrom::Start(offset + pos as u64))?;
+    Ok(kind)
+}
+
+/// Read the whole content of the entry at the given offset, resolving deltas.
+/// `depth` is the number of deltas already on the way to this entry.
+fn read_full(
+    repo: &Repository,
+    file: &mut io::BufReader<fs::File>,
+    offset: u64,
+    depth: usize,
+) -> Result<(ObjType, Vec<u8>)> {
+    if depth > MAX_DELTA_DEPTH {
+        bail!("delta chain longer than {MAX_DELTA_DEPTH} at offset {offset}");
+    }
+    let kind = read_header(file, offset, repo.hash_algo()?.raw_len())?;
+    let mut data = Vec::new();
+    ZlibDecoder::new(&mut *file)
+        .read_to_end(&mut data)
+        .with_context(|| format!("decompressing entry at offset {offset}"))?;
+    let (obj_type, base) = match kind {
+        EntryKind::Base(obj_type, _) => return Ok((obj_type, data)),
+        // Bases come first in the pack, which also rules out cycles.
+        EntryKind::OfsDelta(base_offset) if base_offset >= offset => {
+            bail!("delta at offset {offset} has its base at {base_offset}, not before it")
+        }
+        EntryKind::OfsDelta(base_offset) => read_full(repo, file, base_offset, depth + 1)?,
+        EntryKind::RefDelta(hash) => {
+            let base_obj = repo
+                .read_object(&hash)
+                .with_context(|| format!("opening base {hash}"))?;
+            let obj_type = base_obj.obj_type.clone();
+            (obj_type, base_obj.read_to_vec(MAX_BLOB_SIZE)?)
+        }
+    };
+    let content =
+        apply_delta(&base, &data).with_context(|| format!("resolving delta at offset {offset}"))?;
+    Ok((obj_type, content))
+}
+
+/// Open an object from a pack if it's there, returning its type, size and content.
+///
+/// Undeltified objects are streamed, deltified ones are resolved in memory.
+pub fn open(repo: &Repository, hash: &str) -> Result<Option<(ObjType, usize, Source)>> {
+    let Some((pack_path, offset)) = find(repo, hash)? else {
+        return Ok(None);
+    };
+    let file =
+        fs::File::open(&pack_path).with_context(|| format!("opening {}", pack_path.display()))?;
+    let mut file = io::BufReader::new(file);
+    let context = || format!("reading object {hash} from {}", pack_path.display());
+    let hash_len = repo.hash_algo()?.raw_len();
+    let kind = read_header(&mut file, offset, hash_len).with_context(context)?;
+    if let EntryKind::Base(obj_type, size) = kind {
+        return Ok(Some((
+            obj_type,
+            size,
+            Source::Stream(ZlibDecoder::new(file)),
+        )));
+    }
+    let (obj_type, content) = read_full(repo, &mut file, offset, 0).with_context(context)?;
+    Ok(Some((
+        obj_type,
+        content.len(),
+        Source::Memory(io::Cursor::new(content)),
+    )))
+}
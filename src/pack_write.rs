@@ -0,0 +1,153 @@
+//! Writing packfiles (without deltas).
+//!
+//! See gitformat-pack(5) "pack-*.pack files have the following format".
+
+use anyhow::{bail, Context, Result};
+use flate2::{write::ZlibEncoder, Compression};
+use std::io;
+use std::io::prelude::*;
+
+use crate::interrupt;
+use crate::obj_type::ObjType;
+use crate::object_id::{Hasher, ObjectId};
+use crate::repository::Repository;
+
+/// This wraps an existing Write into a new Write
+/// that also hashes the content as it's being written.
+struct HashingWriter<W> {
+    hasher: Hasher,
+    writer: W,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    /// Write a buffer into this writer, returning how many bytes were written.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.writer.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    /// Flush this output stream.
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Get the numeric code for an object type, see gitformat-pack(5) "Object types".
+fn type_code(obj_type: &ObjType) -> u8 {
+    match obj_type {
+        ObjType::Commit => 1,
+        ObjType::Tree => 2,
+        ObjType::Blob => 3,
+        ObjType::Tag => 4,
+    }
+}
+
+/// Write the type and size of an object in the variable-length format used
+/// in packfiles: 3 bits of type and 4 bits of size in the first byte,
+/// then 7 bits of size in each continuation byte.
+fn write_type_and_size(out: &mut impl Write, obj_type: &ObjType, size: usize) -> Result<()> {
+    let mut byte = (type_code(obj_type) << 4) | (size & 0x0f) as u8;
+    let mut rest = size >> 4;
+    while rest != 0 {
+        out.write_all(&[byte | 0x80])?;
+        byte = (rest & 0x7f) as u8;
+        rest >>= 7;
+    }
+    out.write_all(&[byte])?;
+    Ok(())
+}
+
+/// Writes a packfile object by object.
+///
+/// The number of objects must be known in advance as it's in the header;
+/// finish() checks that the right number was added.
+pub struct PackWriter<W: Write> {
+    out: HashingWriter<W>,
+    /// Number of objects announced in the header
+    nb_obj: u32,
+    /// Number of objects written so far
+    written: u32,
+}
+
+impl<W: Write> PackWriter<W> {
+    /// Start a packfile that will contain nb_obj objects, writing its header.
+    pub fn new(repo: &Repository, out: W, nb_obj: u32) -> Result<Self> {
+        let mut out = HashingWriter {
+            hasher: repo.hash_algo()?.hasher(),
+            writer: out,
+        };
+
+        // 4-byte signature "PACK" + 4-byte version number 2
+        // 4-byte number of objects
+        out.write_all(b"PACK\x00\x00\x00\x02")
+            .context("writing pack header")?;
+        out.write_all(&nb_obj.to_be_bytes())
+            .context("writing pack header")?;
+
+        Ok(Self {
+            out,
+            nb_obj,
+            written: 0,
+        })
+    }
+
+    /// Add an (undeltified) object entry: type and size, then zlib-compressed content.
+    pub fn add(&mut self, obj_type: &ObjType, size: usize, content: &mut impl Read) -> Result<()> {
+        interrupt::check()?;
+        if self.written == self.nb_obj {
+            bail!(
+                "more objects than announced in pack header ({})",
+                self.nb_obj
+            );
+        }
+        write_type_and_size(&mut self.out, obj_type, size).context("writing object header")?;
+        let mut zenc = ZlibEncoder::new(&mut self.out, Compression::default());
+        let copied = io::copy(content, &mut zenc).context("compressing object")?;
+        zenc.finish().context("closing zlib stream")?;
+        if copied != size as u64 {
+            bail!("object size mismatch: announced {size}, got {copied}");
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Add an object from the object database.
+    pub fn add_hash(&mut self, repo: &Repository, hash: &str) -> Result<()> {
+        let mut object = repo
+            .read_object(hash)
+            .with_context(|| format!("opening object {hash}"))?;
+        let (obj_type, size) = (object.obj_type.clone(), object.size);
+        self.add(&obj_type, size, &mut object)
+            .with_context(|| format!("packing object {hash}"))
+    }
+
+    /// Write the pack checksum, which is also returned.
+    pub fn finish(mut self) -> Result<ObjectId> {
+        if self.written != self.nb_obj {
+            bail!(
+                "fewer objects than announced in pack header: {} < {}",
+                self.written,
+                self.nb_obj
+            );
+        }
+        let checksum = self.out.hasher.finalize();
+        self.out
+            .writer
+            .write_all(checksum.as_ref())
+            .context("writing pack checksum")?;
+        self.out.writer.flush().context("flushing pack")?;
+        Ok(checksum)
+    }
+}
+
+/// Write a packfile containing the given objects (read from the object database).
+/// Return the pack's checksum (which is also written at the end of the pack).
+pub fn write_pack(repo: &Repository, out: impl Write, hashes: &[String]) -> Result<ObjectId> {
+    let nb_obj = u32::try_from(hashes.len()).context("too many objects for a pack")?;
+    let mut pack = PackWriter::new(repo, out, nb_obj)?;
+    for hash in hashes {
+        pack.add_hash(repo, hash)?;
+    }
+    pack.finish()
+}
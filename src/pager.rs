@@ -0,0 +1,138 @@
+//! Paging of the output of commands like diff, show and blame, like git: when
+//! standard output is a terminal, it is sent through the pager from GIT_PAGER,
+//! core.pager or PAGER (less by default, with LESS=FRX unless set), which is
+//! given the terminal back once the command is done. Standard output can
+//! only be redirected to the pager on unix: elsewhere, output is not paged.
+
+use anyhow::Result;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config;
+use crate::repository::Repository;
+
+/// Set by --no-pager.
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Set once the output goes to a pager.
+static IN_USE: AtomicBool = AtomicBool::new(false);
+
+/// Disable or enable the pager.
+pub fn set_disabled(disabled: bool) {
+    DISABLED.store(disabled, Ordering::Relaxed);
+}
+
+/// Tell if the output goes to a pager, which can then show colors.
+pub fn in_use() -> bool {
+    IN_USE.load(Ordering::Relaxed)
+}
+
+/// A pager running with our standard output as its input. Dropping it ends
+/// the output and waits for the pager to exit.
+pub struct Pager {
+    child: Child,
+    /// The standard output we had before
+    #[cfg(unix)]
+    stdout: std::os::fd::OwnedFd,
+}
+
+/// Get the pager for a command: from GIT_PAGER, else pager.`<command>` if it
+/// is not a boolean, else core.pager or PAGER, or less. None if
+/// pager.`<command>` is false, or the pager is empty or "cat".
+fn command(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let key = format!("pager.{name}");
+    let pager = match config::get(repo, &key)? {
+        Some(value) => match config::parse_bool(&key, &value) {
+            Ok(false) => return Ok(None),
+            Ok(true) => None,
+            Err(_) => Some(value),
+        },
+        None => None,
+    };
+    let pager = match (env::var("GIT_PAGER"), pager) {
+        (Ok(pager), _) | (Err(_), Some(pager)) => pager,
+        (Err(_), None) => match config::get(repo, "core.pager")? {
+            Some(pager) => pager,
+            None => env::var("PAGER").unwrap_or_else(|_| "less".to_owned()),
+        },
+    };
+    Ok(match pager.trim() {
+        "" | "cat" => None,
+        _ => Some(pager),
+    })
+}
+
+/// Start the pager for the given command (eg "diff"), if standard output is
+/// a terminal and there is one, see command(). The pager is run by the shell,
+/// so it can be given with arguments.
+pub fn start(repo: &Repository, name: &str) -> Result<Option<Pager>> {
+    if DISABLED.load(Ordering::Relaxed) || !io::stdout().is_terminal() {
+        return Ok(None);
+    }
+    let Some(pager) = command(repo, name)? else {
+        return Ok(None);
+    };
+    spawn(&pager)
+}
+
+/// Run the pager, and redirect standard output to it.
+#[cfg(unix)]
+fn spawn(pager: &str) -> Result<Option<Pager>> {
+    use anyhow::Context;
+    use std::os::fd::{AsFd, AsRawFd};
+    use std::process::{Command, Stdio};
+
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", pager]).stdin(Stdio::piped());
+    if env::var_os("LESS").is_none() {
+        cmd.env("LESS", "FRX");
+    }
+    if env::var_os("LV").is_none() {
+        cmd.env("LV", "-c");
+    }
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("running pager {pager}"))?;
+    let pipe = child.stdin.take().expect("stdin was piped");
+    let mut stdout = io::stdout().lock();
+    stdout.flush()?;
+    let saved = stdout.as_fd().try_clone_to_owned()?;
+    // SAFETY: both are open file descriptors, and the pipe is closed when
+    // dropped after being duplicated.
+    if unsafe { libc::dup2(pipe.as_raw_fd(), libc::STDOUT_FILENO) } < 0 {
+        return Err(io::Error::last_os_error()).context("redirecting output to the pager");
+    }
+    drop(pipe);
+    drop(stdout);
+    IN_USE.store(true, Ordering::Relaxed);
+    // Like git, stop quietly when the pager exits before the end of the output.
+    // SAFETY: restoring the default action of a signal is always safe.
+    unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+    Ok(Some(Pager {
+        child,
+        stdout: saved,
+    }))
+}
+
+/// Standard output can't be redirected to the pager on this platform.
+#[cfg(not(unix))]
+fn spawn(_: &str) -> Result<Option<Pager>> {
+    Ok(None)
+}
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        // Giving the terminal back closes our end of the pipe, which tells
+        // the pager that the output is complete.
+        #[cfg(unix)]
+        {
+            use std::os::fd::AsRawFd;
+            // SAFETY: both are open file descriptors.
+            unsafe { libc::dup2(self.stdout.as_raw_fd(), libc::STDOUT_FILENO) };
+        }
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,230 @@
+//! Differences between platforms, for files in the worktree.
+//!
+//! Git stores names as bytes: on unix they are used as they are, elsewhere
+//! (Windows) they must be UTF-8, and invalid sequences are replaced.
+//!
+//! Like git, core.filemode and core.symlinks tell whether the executable bit
+//! and symlinks can be trusted: they default to true on unix, false elsewhere.
+//! Without symlinks, a symlink is checked out as a file containing its target.
+
+use anyhow::{Context, Result};
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::config;
+use crate::repository::Repository;
+
+/// Get a boolean setting, with a default depending on the platform, read
+/// once per repository: checkouts and hashing may happen in several threads.
+fn setting(repo: &Repository, cell: &OnceLock<bool>, key: &str) -> Result<bool> {
+    if let Some(&value) = cell.get() {
+        return Ok(value);
+    }
+    let value = config::get_bool(repo, key)?.unwrap_or(cfg!(unix));
+    Ok(*cell.get_or_init(|| value))
+}
+
+/// Tell if the executable bit of files can be trusted (core.filemode).
+pub fn filemode(repo: &Repository) -> Result<bool> {
+    setting(repo, &repo.cache().filemode, "core.filemode")
+}
+
+/// Tell if symlinks can be created in the worktree (core.symlinks).
+pub fn symlinks(repo: &Repository) -> Result<bool> {
+    setting(repo, &repo.cache().symlinks, "core.symlinks")
+}
+
+/// Convert a name (or path) from git objects to one for the filesystem.
+pub fn os_str(name: &[u8]) -> Cow<'_, OsStr> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Borrowed(OsStr::from_bytes(name))
+    }
+    #[cfg(not(unix))]
+    {
+        match String::from_utf8_lossy(name) {
+            Cow::Borrowed(name) => Cow::Borrowed(OsStr::new(name)),
+            Cow::Owned(name) => Cow::Owned(name.into()),
+        }
+    }
+}
+
+/// Convert a name (or path) from the filesystem to one for git objects.
+pub fn bytes(name: &OsStr) -> Cow<'_, [u8]> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Borrowed(name.as_bytes())
+    }
+    #[cfg(not(unix))]
+    {
+        match name.to_string_lossy() {
+            Cow::Borrowed(name) => Cow::Borrowed(name.as_bytes()),
+            Cow::Owned(name) => Cow::Owned(name.into_bytes()),
+        }
+    }
+}
+
+/// Join a name (or path) from git objects to a directory.
+pub fn join(dir: &Path, name: &[u8]) -> PathBuf {
+    dir.join(os_str(name))
+}
+
+/// Tell if a file is executable, according to its metadata.
+pub fn is_executable(meta: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        meta.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = meta;
+        false
+    }
+}
+
+/// Make a file executable, where the platform supports it.
+pub fn set_executable(file: &fs::File, path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let meta = file
+            .metadata()
+            .with_context(|| format!("stat {}", path.display()))?;
+        let mut perms = meta.permissions();
+        perms.set_mode(perms.mode() | 0o111);
+        fs::set_permissions(path, perms)
+            .with_context(|| format!("making {} executable", path.display()))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (file, path);
+    Ok(())
+}
+
+/// Tell if the current user may create files in a directory, which is not
+/// the case on read-only file systems either.
+pub fn is_writable(dir: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        let Ok(path) = CString::new(dir.as_os_str().as_bytes()) else {
+            return false;
+        };
+        unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+    }
+    #[cfg(not(unix))]
+    fs::metadata(dir).is_ok_and(|meta| !meta.permissions().readonly())
+}
+
+/// Tell if an error from opening or stat-ing a path means it doesn't exist,
+/// including when one of its parent directories was replaced by a file.
+pub fn is_not_found(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    if err.raw_os_error() == Some(libc::ENOTDIR) {
+        return true;
+    }
+    err.kind() == io::ErrorKind::NotFound
+}
+
+/// Get the file system data recorded in the index for a file: ctime, mtime
+/// (seconds and nanoseconds), dev, ino, uid, gid and size, truncated to 32
+/// bits. Only times and size are available on platforms other than unix.
+pub fn index_stat(meta: &fs::Metadata) -> [u32; 9] {
+    #[cfg(unix)]
+    let stat = {
+        use std::os::unix::fs::MetadataExt;
+        [
+            meta.ctime(),
+            meta.ctime_nsec(),
+            meta.mtime(),
+            meta.mtime_nsec(),
+            meta.dev() as i64,
+            meta.ino() as i64,
+            meta.uid().into(),
+            meta.gid().into(),
+            meta.size() as i64,
+        ]
+    };
+    #[cfg(not(unix))]
+    let stat = {
+        let time = |time: io::Result<std::time::SystemTime>| {
+            let since_epoch = time
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .unwrap_or_default();
+            (
+                since_epoch.as_secs() as i64,
+                i64::from(since_epoch.subsec_nanos()),
+            )
+        };
+        let (ctime, ctime_nsec) = time(meta.created());
+        let (mtime, mtime_nsec) = time(meta.modified());
+        [
+            ctime,
+            ctime_nsec,
+            mtime,
+            mtime_nsec,
+            0,
+            0,
+            0,
+            0,
+            meta.len() as i64,
+        ]
+    };
+    stat.map(|value| value as u32)
+}
+
+/// Create a file for writing, truncating it if it exists, but failing if
+/// it is a symlink (which could point anywhere) where the platform allows.
+pub fn create_no_follow(path: &Path) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NOFOLLOW);
+    }
+    options.open(path)
+}
+
+/// Create a symlink, or a file containing its target without core.symlinks.
+pub fn symlink(repo: &Repository, target: &[u8], path: &Path) -> Result<()> {
+    let display = || {
+        let target = String::from_utf8_lossy(target);
+        format!("creating symlink {} -> {target}", path.display())
+    };
+    if !symlinks(repo)? {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .with_context(display)?;
+        return io::Write::write_all(&mut file, target).with_context(display);
+    }
+    #[cfg(unix)]
+    let created = std::os::unix::fs::symlink(os_str(target), path);
+    #[cfg(windows)]
+    let created = std::os::windows::fs::symlink_file(os_str(target), path);
+    #[cfg(not(any(unix, windows)))]
+    let created = Err(io::Error::from(io::ErrorKind::Unsupported));
+    created.with_context(display)
+}
+
+/// Read the target of a symlink, or the content of the file standing for
+/// it if the path is not an actual symlink (without core.symlinks).
+pub fn read_symlink(path: &Path) -> Result<Vec<u8>> {
+    let meta = fs::symlink_metadata(path).with_context(|| format!("stat {}", path.display()))?;
+    if !meta.is_symlink() {
+        return fs::read(path).with_context(|| format!("reading {}", path.display()));
+    }
+    let target =
+        fs::read_link(path).with_context(|| format!("reading symlink {}", path.display()))?;
+    Ok(bytes(target.as_os_str()).into_owned())
+}
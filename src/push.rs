@@ -0,0 +1,152 @@
+//! Planning a push: which references to update on the remote.
+
+use anyhow::{bail, Result};
+
+use crate::abbrev::shorten;
+use crate::fetch::have_object;
+use crate::network::RemoteRef;
+use crate::refs;
+use crate::repository::Repository;
+use crate::revwalk::is_ancestor;
+
+/// The hash used to represent a missing reference (to create or delete).
+pub const ZERO_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// A push refspec, `[+]<src>:<dst>` (see git-push(1)), resolved locally.
+pub struct PushSpec {
+    /// Full local reference name (or hash if given as such),
+    /// or None for deleting the destination.
+    pub local: Option<String>,
+    /// Hash to push, or ZERO_HASH for deletion.
+    pub hash: String,
+    /// Full reference name on the remote.
+    pub dst: String,
+    /// Allow non-fast-forward updates.
+    pub force: bool,
+}
+
+impl PushSpec {
+    /// Parse and resolve a refspec.
+    pub fn parse(repo: &Repository, spec: &str) -> Result<Self> {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, spec),
+        };
+        let (src, dst) = match spec.split_once(':') {
+            Some((src, dst)) => (src, Some(dst)),
+            None => (spec, None),
+        };
+
+        if src.is_empty() {
+            let Some(dst) = dst.filter(|d| d.starts_with("refs/")) else {
+                bail!("deleting requires a full destination name: {spec}");
+            };
+            return Ok(PushSpec {
+                local: None,
+                hash: ZERO_HASH.into(),
+                dst: dst.into(),
+                force,
+            });
+        }
+
+        let (full_src, hash) = match refs::dwim(repo, src)? {
+            Some((full, hash)) if full == "HEAD" => {
+                (refs::current_branch(repo)?.unwrap_or(full), hash)
+            }
+            Some(found) => found,
+            None if src.len() == 40 && have_object(repo, src)? => (src.to_owned(), src.to_owned()),
+            None => bail!("src refspec {src} does not match any reference"),
+        };
+
+        let dst = match dst {
+            Some(dst) if dst.starts_with("refs/") => dst.to_owned(),
+            Some(dst) => match full_src.split('/').take(2).collect::<Vec<_>>()[..] {
+                ["refs", kind @ ("heads" | "tags")] => format!("refs/{kind}/{dst}"),
+                _ => bail!("cannot infer full destination name for {dst}"),
+            },
+            None if full_src.starts_with("refs/") => full_src.clone(),
+            None => bail!("destination required when pushing {src}"),
+        };
+
+        Ok(PushSpec {
+            local: Some(full_src),
+            hash,
+            dst,
+            force,
+        })
+    }
+
+    /// Check if this update is allowed given the current value on the remote,
+    /// returning the reason if not.
+    pub fn check(&self, repo: &Repository, old: &str) -> Result<Option<&'static str>> {
+        if self.force || old == ZERO_HASH || self.hash == ZERO_HASH {
+            return Ok(None);
+        }
+        if !have_object(repo, old)? {
+            return Ok(Some("fetch first"));
+        }
+        if !is_ancestor(repo, old, &self.hash)? {
+            return Ok(Some("non-fast-forward"));
+        }
+        Ok(None)
+    }
+}
+
+/// Get the current value of a reference on the remote, or ZERO_HASH.
+pub fn remote_value(remote_refs: &[RemoteRef], name: &str) -> String {
+    remote_refs
+        .iter()
+        .find(|r| r.name == name)
+        .map_or(ZERO_HASH.into(), |r| r.hash.clone())
+}
+
+/// Shorten a reference name for display.
+pub fn short_name(name: &str) -> &str {
+    name.strip_prefix("refs/heads/")
+        .or_else(|| name.strip_prefix("refs/tags/"))
+        .unwrap_or(name)
+}
+
+/// Format the input for the pre-push hook, one line per update:
+/// `<local ref> <local hash> <remote ref> <remote hash>`.
+pub fn hook_input(updates: &[(PushSpec, String)]) -> String {
+    updates
+        .iter()
+        .map(|(spec, old)| {
+            let local = spec.local.as_deref().unwrap_or("(delete)");
+            format!("{local} {} {} {old}\n", spec.hash, spec.dst)
+        })
+        .collect()
+}
+
+/// Get the remote-tracking reference corresponding to a remote branch, if any.
+pub fn tracking_ref(remote: &str, dst: &str) -> Option<String> {
+    dst.strip_prefix("refs/heads/")
+        .map(|branch| format!("refs/remotes/{remote}/{branch}"))
+}
+
+/// Describe the update of a reference for display: flag and summary, like git push.
+pub fn describe(repo: &Repository, spec: &PushSpec, old: &str) -> Result<(char, String)> {
+    let kind = if spec.dst.starts_with("refs/tags/") {
+        "tag"
+    } else if spec.dst.starts_with("refs/heads/") {
+        "branch"
+    } else {
+        "reference"
+    };
+    Ok(if spec.hash == ZERO_HASH {
+        ('-', "[deleted]".into())
+    } else if old == ZERO_HASH {
+        ('*', format!("[new {kind}]"))
+    } else if spec.force {
+        (
+            '+',
+            format!("{}...{}", shorten(repo, old)?, shorten(repo, &spec.hash)?),
+        )
+    } else {
+        (
+            ' ',
+            format!("{}..{}", shorten(repo, old)?, shorten(repo, &spec.hash)?),
+        )
+    })
+}
@@ -0,0 +1,551 @@
+//! Reading and writing references, and their reflogs.
+//!
+//! References are written as loose files, which take precedence over those
+//! in .git/packed-refs: see pack_refs() for moving them there.
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::common::write_error;
+use crate::lock::LockFile;
+use crate::object_id::is_hash;
+use crate::repository::Repository;
+use crate::revwalk::peel;
+
+/// Maximum depth when following symbolic references, same as git.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// The first line of .git/packed-refs written by pack_refs(), like git:
+/// annotated tags are followed by the object they peel to, and refs are sorted.
+const PACKED_HEADER: &str = "# pack-refs with: peeled fully-peeled sorted \n";
+
+/// An entry of .git/packed-refs.
+pub struct PackedRef {
+    pub name: String,
+    pub hash: String,
+    /// For annotated tags, the object they peel to
+    pub peeled: Option<String>,
+}
+
+/// Read the entries of .git/packed-refs (empty if there is none).
+pub fn read_packed(repo: &Repository) -> Result<Vec<PackedRef>> {
+    let path = repo.git_path("packed-refs");
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("reading packed-refs"),
+    };
+    let mut packed: Vec<PackedRef> = Vec::new();
+    // Lines are "<hash> <name>", or "^<peeled hash>" after annotated tags,
+    // after a header starting with '#'.
+    for line in content.lines().filter(|line| !line.starts_with('#')) {
+        if let Some(peeled) = line.strip_prefix('^') {
+            match packed.last_mut() {
+                Some(last) if is_hash(repo, peeled) => last.peeled = Some(peeled.to_owned()),
+                _ => bail!("invalid line in packed-refs: {line}"),
+            }
+            continue;
+        }
+        match line.split_once(' ') {
+            Some((hash, name)) if is_hash(repo, hash) => packed.push(PackedRef {
+                name: name.to_owned(),
+                hash: hash.to_owned(),
+                peeled: None,
+            }),
+            _ => bail!("invalid line in packed-refs: {line}"),
+        }
+    }
+    Ok(packed)
+}
+
+/// Get the hash of a reference from .git/packed-refs, if it is there.
+fn resolve_packed(repo: &Repository, name: &str) -> Result<Option<String>> {
+    if !name.starts_with("refs/") {
+        return Ok(None);
+    }
+    let packed = read_packed(repo)?;
+    Ok(packed.into_iter().find(|r| r.name == name).map(|r| r.hash))
+}
+
+/// Resolve a reference (name relative to .git, eg "HEAD" or "refs/heads/main"),
+/// following symbolic references, and return the hash it points to,
+/// or None if it (or the end of the symref chain) doesn't exist.
+pub fn resolve(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let mut name = name.to_owned();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        let path = repo.git_path(&name);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return resolve_packed(repo, &name),
+            // A directory in refs/ can't be a loose reference.
+            Err(_) if path.is_dir() => return resolve_packed(repo, &name),
+            Err(e) => return Err(e).with_context(|| format!("reading {name}")),
+        };
+        let content = content.trim_end();
+        match content.strip_prefix("ref: ") {
+            Some(target) => name = target.into(),
+            None => return Ok(Some(content.into())),
+        }
+    }
+    bail!("symbolic reference chain too long, ending at {name}");
+}
+
+/// Tell if a reference name follows the rules of git-check-ref-format(1),
+/// also allowing names with a single component, like tag names.
+pub fn is_valid_name(name: &str) -> bool {
+    let bad_char = |c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c);
+    !name.is_empty()
+        && name != "@"
+        && !name.contains(bad_char)
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.ends_with('.')
+        && name
+            .split('/')
+            .all(|part| !part.is_empty() && !part.starts_with('.') && !part.ends_with(".lock"))
+}
+
+/// Expand a short name like "main" or "v1.0" to a full reference name,
+/// using the same rules as git, see gitrevisions(7) `<refname>`.
+/// Return the full name and the hash it resolves to, if found.
+pub fn dwim(repo: &Repository, name: &str) -> Result<Option<(String, String)>> {
+    let candidates = [
+        name.to_owned(),
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ];
+    for full_name in candidates {
+        // Only HEAD-like names are valid outside of refs/.
+        if !full_name.starts_with("refs/") && !full_name.ends_with("HEAD") {
+            continue;
+        }
+        if let Some(hash) = resolve(repo, &full_name)? {
+            return Ok(Some((full_name, hash)));
+        }
+    }
+    Ok(None)
+}
+
+/// Get the name of the branch HEAD points to, or None if HEAD is detached.
+pub fn current_branch(repo: &Repository) -> Result<Option<String>> {
+    read_symbolic(repo, "HEAD")
+}
+
+/// Get the reference a symbolic reference points to (without following it
+/// further), or None if it is a regular reference. Fails if it doesn't exist.
+pub fn read_symbolic(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let content = match fs::read_to_string(repo.git_path(name)) {
+        Ok(content) => content,
+        // Packed references are never symbolic.
+        Err(e) if e.kind() == io::ErrorKind::NotFound && resolve_packed(repo, name)?.is_some() => {
+            return Ok(None)
+        }
+        Err(e) => return Err(e).with_context(|| format!("reading {name}")),
+    };
+    Ok(content
+        .trim_end()
+        .strip_prefix("ref: ")
+        .map(|target| target.to_owned()))
+}
+
+/// Recursively collect loose references in the given directory,
+/// with their content: a hash, or "ref: " and a reference name.
+fn list_loose(dir: &Path, name: &str, out: &mut Vec<(String, String)>) -> Result<()> {
+    let iter = match fs::read_dir(dir) {
+        Ok(iter) => iter,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("listing {}", dir.display())),
+    };
+    for entry in iter {
+        let entry = entry.with_context(|| format!("bad direntry in {}", dir.display()))?;
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            bail!("invalid reference name in {}", dir.display());
+        };
+        let full_name = format!("{name}{file_name}");
+        if entry.file_type()?.is_dir() {
+            list_loose(&entry.path(), &format!("{full_name}/"), out)?;
+        } else if !file_name.ends_with(".lock") {
+            let content =
+                fs::read_to_string(entry.path()).with_context(|| format!("reading {full_name}"))?;
+            out.push((full_name, content.trim_end().to_owned()));
+        }
+    }
+    Ok(())
+}
+
+/// Collect the loose references whose name start with the given prefix:
+/// from the common directory and, in a linked worktree, from its own git
+/// directory for those that belong to it, like refs/worktree/.
+fn list_loose_refs(repo: &Repository, prefix: &str, out: &mut Vec<(String, String)>) -> Result<()> {
+    let mut dirs = vec![repo.common_dir()];
+    if repo.git_dir() != repo.common_dir() {
+        dirs.push(repo.git_dir());
+    }
+    for dir in dirs {
+        let mut found = Vec::new();
+        list_loose(&dir.join(prefix), prefix, &mut found)?;
+        for (name, content) in found {
+            if repo.git_path(&name) == dir.join(&name) {
+                out.push((name, content));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Get the directory containing a loose reference (or the logs directory
+/// containing a reflog): the git directory of the worktree, or the common one.
+fn base_dir<'r>(repo: &'r Repository, path: &Path) -> Result<&'r Path> {
+    let git_dir = repo.git_dir();
+    Ok(match path.starts_with(git_dir) {
+        true => git_dir,
+        false => repo.common_dir(),
+    })
+}
+
+/// List references whose name start with the given prefix, which must end
+/// with a '/' (eg "refs/heads/"), along with the hash they resolve to,
+/// sorted by name. Dangling symbolic references are skipped.
+///
+/// Both loose and packed references are listed, loose ones taking precedence.
+pub fn list(repo: &Repository, prefix: &str) -> Result<Vec<(String, String)>> {
+    let mut loose = Vec::new();
+    list_loose_refs(repo, prefix, &mut loose)?;
+    let mut refs = Vec::new();
+    for (name, _) in &loose {
+        if let Some(hash) = resolve(repo, name)? {
+            refs.push((name.clone(), hash));
+        }
+    }
+    for packed in read_packed(repo)? {
+        if packed.name.starts_with(prefix) && !loose.iter().any(|(name, _)| *name == packed.name) {
+            refs.push((packed.name, packed.hash));
+        }
+    }
+    refs.sort_unstable();
+    Ok(refs)
+}
+
+/// Rewrite .git/packed-refs (under its lock) with the given references,
+/// sorted by name, peeling annotated tags.
+fn write_packed(repo: &Repository, lock: LockFile, refs: &BTreeMap<String, String>) -> Result<()> {
+    let mut content = PACKED_HEADER.to_owned();
+    for (name, hash) in refs {
+        content.push_str(&format!("{hash} {name}\n"));
+        if let Some((peeled, _)) = peel(repo, hash)?.filter(|(peeled, _)| peeled != hash) {
+            content.push_str(&format!("^{peeled}\n"));
+        }
+    }
+    lock.commit(content.as_bytes())
+        .context("updating packed-refs")
+}
+
+/// Remove a reference from .git/packed-refs, if it is there.
+fn remove_packed(repo: &Repository, name: &str) -> Result<()> {
+    let lock = LockFile::acquire(repo, &repo.git_path("packed-refs"))?;
+    let packed = read_packed(repo)?;
+    if packed.iter().all(|r| r.name != name) {
+        return Ok(());
+    }
+    let kept = packed
+        .into_iter()
+        .filter(|r| r.name != name)
+        .map(|r| (r.name, r.hash))
+        .collect();
+    write_packed(repo, lock, &kept)
+}
+
+/// Move loose references to .git/packed-refs, like git pack-refs: only tags
+/// (and references already packed) unless `all` is set. Symbolic references
+/// stay loose.
+///
+/// The loose files are then removed, unless they changed in the meantime,
+/// along with the directories left empty (but not refs/heads and the like).
+pub fn pack_refs(repo: &Repository, all: bool) -> Result<usize> {
+    let common_dir = repo.common_dir();
+    let lock = LockFile::acquire(repo, &common_dir.join("packed-refs"))?;
+    let mut packed: BTreeMap<String, String> = read_packed(repo)?
+        .into_iter()
+        .map(|r| (r.name, r.hash))
+        .collect();
+    let mut loose = Vec::new();
+    list_loose(&common_dir.join("refs"), "refs/", &mut loose)?;
+    loose.retain(|(name, content)| {
+        is_hash(repo, content)
+            && (all || name.starts_with("refs/tags/") || packed.contains_key(name))
+    });
+    for (name, hash) in &loose {
+        packed.insert(name.clone(), hash.clone());
+    }
+    write_packed(repo, lock, &packed)?;
+
+    for (name, hash) in &loose {
+        let path = common_dir.join(name);
+        let lock = LockFile::acquire(repo, &path)?;
+        match fs::read_to_string(&path) {
+            Ok(content) if content.trim_end() == hash => {
+                fs::remove_file(&path).with_context(|| format!("removing {name}"))?
+            }
+            _ => continue,
+        }
+        drop(lock);
+        remove_empty_parents(common_dir, &path);
+    }
+    Ok(loose.len())
+}
+
+/// Remove the directories containing a removed reference (or reflog) as long
+/// as they are empty, but not refs/ and the directories right under it, like
+/// refs/heads. `base` is .git (or .git/logs for reflogs).
+fn remove_empty_parents(base: &Path, path: &Path) {
+    for dir in path.ancestors().skip(1) {
+        let depth = dir.strip_prefix(base).map_or(0, |d| d.components().count());
+        if depth <= 2 || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+/// Write a reference (relative to .git) pointing to the given hash,
+/// creating intermediate directories if needed.
+pub fn write(repo: &Repository, name: &str, hash: &str) -> Result<()> {
+    write_raw(repo, name, &format!("{hash}\n"))
+}
+
+/// Write a symbolic reference (relative to .git) pointing to another reference.
+pub fn write_symbolic(repo: &Repository, name: &str, target: &str) -> Result<()> {
+    write_raw(repo, name, &format!("ref: {target}\n"))
+}
+
+/// Delete a reference (relative to .git) and its reflog, failing if `old`
+/// is given and the reference doesn't point to it.
+/// Both the loose reference and the packed one, if any, are deleted.
+pub fn delete(repo: &Repository, name: &str, old: Option<&str>) -> Result<()> {
+    let path = repo.git_path(name);
+    let lock = LockFile::acquire(repo, &path)?;
+    if let Some(old) = old {
+        if resolve(repo, name)?.as_deref() != Some(old) {
+            bail!("cannot delete {name}: it does not point to {old}");
+        }
+    }
+    let packed = resolve_packed(repo, name)?.is_some();
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound && packed => (),
+        res => res.with_context(|| format!("deleting {name}"))?,
+    }
+    if packed {
+        remove_packed(repo, name)?;
+    }
+    let log = repo.git_path(&format!("logs/{name}"));
+    match fs::remove_file(&log) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("deleting the reflog of {name}"))
+        }
+        _ => (),
+    }
+    drop(lock);
+    remove_empty_parents(base_dir(repo, &path)?, &path);
+    remove_empty_parents(&base_dir(repo, &log)?.join("logs"), &log);
+    Ok(())
+}
+
+/// Delete the n-th most recent entry from the reflog of a reference, like
+/// git reflog delete --updateref --rewrite: the reference is set to the most
+/// recent remaining entry, or deleted if none remains.
+pub fn drop_reflog_entry(repo: &Repository, name: &str, n: usize) -> Result<()> {
+    let path = repo.git_path(name);
+    let log_path = repo.git_path(&format!("logs/{name}"));
+    let lock = LockFile::acquire(repo, &path)?;
+    let log =
+        fs::read_to_string(&log_path).with_context(|| format!("reading {}", log_path.display()))?;
+    let mut lines: Vec<&str> = log.lines().collect();
+    let Some(i) = lines.len().checked_sub(n + 1) else {
+        bail!("log for {name} only has {} entries", lines.len());
+    };
+    let removed = lines.remove(i);
+    if lines.is_empty() {
+        drop(lock);
+        return delete(repo, name, None);
+    }
+    // The next entry now follows the one before the removed entry.
+    let mut log: Vec<String> = lines.iter().map(|&line| line.to_owned()).collect();
+    if let Some(next) = log.get_mut(i) {
+        let old = removed.split(' ').next().unwrap_or_default();
+        let (_, rest) = next.split_once(' ').unwrap_or_default();
+        *next = format!("{old} {rest}");
+    }
+    let content = log
+        .iter()
+        .map(|line| format!("{line}\n"))
+        .collect::<String>();
+    LockFile::acquire(repo, &log_path)?
+        .commit(content.as_bytes())
+        .with_context(|| format!("updating the reflog of {name}"))?;
+    if i == log.len() {
+        let new = log[i - 1].split(' ').nth(1).unwrap_or_default();
+        lock.commit(format!("{new}\n").as_bytes())
+            .with_context(|| format!("updating {name}"))?;
+    }
+    Ok(())
+}
+
+/// Update a reference to a new hash, following HEAD if it points to a branch,
+/// failing if its current value is not `old` (None meaning it doesn't exist).
+///
+/// An entry is added to the reflog of the reference, and to the reflog of HEAD
+/// if it was updated through it, like git does. `who` is the identity and date
+/// of the committer, and `message` describes the update.
+pub fn update(
+    repo: &Repository,
+    name: &str,
+    old: Option<&str>,
+    new: &str,
+    who: &str,
+    message: &str,
+) -> Result<()> {
+    let target = match name {
+        "HEAD" => current_branch(repo)?.unwrap_or_else(|| name.to_owned()),
+        _ => name.to_owned(),
+    };
+    update_raw(repo, name, &target, old, new, who, message)
+}
+
+/// Like update(), but without following HEAD: if it points to a branch,
+/// it is replaced by the new hash (detached) and the branch is left alone.
+pub fn update_no_deref(
+    repo: &Repository,
+    name: &str,
+    old: Option<&str>,
+    new: &str,
+    who: &str,
+    message: &str,
+) -> Result<()> {
+    update_raw(repo, name, name, old, new, who, message)
+}
+
+/// Write `new` to `target` after checking its value under a lock, logging
+/// the update for `target`, and `name` if it is the symbolic reference that
+/// led to it.
+fn update_raw(
+    repo: &Repository,
+    name: &str,
+    target: &str,
+    old: Option<&str>,
+    new: &str,
+    who: &str,
+    message: &str,
+) -> Result<()> {
+    let path = repo.git_path(target);
+    let dir = path.parent().expect("ref path has a parent");
+    fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    let lock = LockFile::acquire(repo, &path)?;
+    // Now that no one else can change it, check it's still what the caller saw.
+    let current = resolve(repo, target)?;
+    if current.as_deref() != old {
+        bail!(
+            "cannot update {target}: it is at {} but expected {} (changed by another process?)",
+            current.as_deref().unwrap_or("nothing"),
+            old.unwrap_or("nothing")
+        );
+    }
+    // Like git, when HEAD is updated to the commit its branch already points
+    // to, only HEAD gets a reflog entry.
+    if target == name || old != Some(new) {
+        append_reflog(repo, target, old, new, who, message)?;
+    }
+    if target != name {
+        append_reflog(repo, name, old, new, who, message)?;
+    }
+    lock.commit(format!("{new}\n").as_bytes())
+        .with_context(|| format!("updating {target}"))
+}
+
+/// Add an entry to the reflog of a reference, in `.git/logs/<name>`.
+/// Like git, bare repositories only get entries in existing reflogs.
+fn append_reflog(
+    repo: &Repository,
+    name: &str,
+    old: Option<&str>,
+    new: &str,
+    who: &str,
+    message: &str,
+) -> Result<()> {
+    let path = repo.git_path(&format!("logs/{name}"));
+    if repo.is_bare() && !path.exists() {
+        return Ok(());
+    }
+    let dir = path.parent().expect("log path has a parent");
+    fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    let old = old.unwrap_or(repo.hash_algo()?.zero_hash());
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| write_error(e, &path))?;
+    // Like git, no tab without a message.
+    let tab = if message.is_empty() { "" } else { "\t" };
+    writeln!(log, "{old} {new} {who}{tab}{message}")
+        .with_context(|| format!("writing to {}", path.display()))
+}
+
+/// An entry of a reflog.
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    /// Time of the update, in seconds since the epoch
+    pub time: i64,
+    /// What the update was, like "commit: Fix typo"
+    pub message: String,
+}
+
+/// Read the reflog of a reference, oldest entry first (empty if there is none).
+pub fn read_reflog(repo: &Repository, name: &str) -> Result<Vec<ReflogEntry>> {
+    let path = repo.git_path(&format!("logs/{name}"));
+    let log = match fs::read_to_string(&path) {
+        Ok(log) => log,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    let mut entries = Vec::new();
+    // Lines are "<old> <new> <name> <<email>> <time> <zone>\t<message>".
+    for line in log.lines() {
+        let (who, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut fields = who.splitn(3, ' ');
+        let (Some(old), Some(new), Some(rest)) = (fields.next(), fields.next(), fields.next())
+        else {
+            bail!("invalid reflog entry in {}: {line}", path.display());
+        };
+        let time = rest
+            .rsplit(' ')
+            .nth(1)
+            .and_then(|time| time.parse().ok())
+            .with_context(|| format!("invalid reflog entry in {}: {line}", path.display()))?;
+        entries.push(ReflogEntry {
+            old: old.to_owned(),
+            new: new.to_owned(),
+            time,
+            message: message.to_owned(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Write a reference file atomically under a lock: see LockFile.
+/// This means an interruption never leaves a half-written reference,
+/// and concurrent updates of the same reference are detected.
+fn write_raw(repo: &Repository, name: &str, content: &str) -> Result<()> {
+    let path = repo.git_path(name);
+    let dir = path.parent().expect("ref path has a parent");
+    fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    LockFile::acquire(repo, &path)?
+        .commit(content.as_bytes())
+        .with_context(|| format!("updating {name}"))
+}
@@ -0,0 +1,326 @@
+//! Checking that references are valid, and repairing broken ones.
+//!
+//! Both loose references and those in .git/packed-refs are checked. Packed
+//! references are read here without the refs module, so that malformed lines
+//! can be reported and removed.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::fetch::have_object;
+use crate::lock::LockFile;
+use crate::obj_type::ObjType;
+use crate::object_id::is_hash;
+use crate::refs;
+use crate::repository::Repository;
+
+/// Maximum depth when following symbolic references, same as git.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// What a reference contains.
+enum Value {
+    Direct(String),
+    Symbolic(String),
+    Invalid(String),
+}
+
+impl Value {
+    /// Parse the content of a loose reference.
+    fn parse(repo: &Repository, content: &str) -> Self {
+        let content = content.trim_end();
+        if let Some(target) = content.strip_prefix("ref: ") {
+            Value::Symbolic(target.to_owned())
+        } else if is_hash(repo, content) {
+            Value::Direct(content.to_owned())
+        } else {
+            Value::Invalid(content.to_owned())
+        }
+    }
+}
+
+/// A reference to check.
+struct Ref {
+    name: String,
+    /// Found in packed-refs rather than as a loose file
+    packed: bool,
+    value: Value,
+}
+
+/// Recursively collect loose references in the given directory, unresolved,
+/// and the names of lock files found along the way.
+fn list_loose(
+    repo: &Repository,
+    dir: &Path,
+    name: &str,
+    out: &mut Vec<Ref>,
+    locks: &mut Vec<String>,
+) -> Result<()> {
+    let iter = match fs::read_dir(dir) {
+        Ok(iter) => iter,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("listing {}", dir.display())),
+    };
+    for entry in iter {
+        let entry = entry.with_context(|| format!("bad direntry in {}", dir.display()))?;
+        let full_name = format!("{name}{}", entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            list_loose(repo, &entry.path(), &format!("{full_name}/"), out, locks)?;
+        } else if full_name.ends_with(".lock") {
+            locks.push(full_name);
+        } else {
+            let content =
+                fs::read_to_string(entry.path()).with_context(|| format!("reading {full_name}"))?;
+            out.push(Ref {
+                name: full_name,
+                packed: false,
+                value: Value::parse(repo, &content),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Read the entries of .git/packed-refs (empty if there is none).
+fn list_packed(repo: &Repository) -> Result<Vec<Ref>> {
+    let path = repo.git_path("packed-refs");
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("reading packed-refs"),
+    };
+    let mut out = Vec::new();
+    // Lines are "<hash> <name>", except the header and "^<peeled hash>" lines.
+    for line in content.lines() {
+        if line.starts_with('#') || line.starts_with('^') {
+            continue;
+        }
+        let (hash, name) = line.split_once(' ').unwrap_or(("", line));
+        out.push(Ref {
+            name: name.to_owned(),
+            packed: true,
+            value: match is_hash(repo, hash) {
+                true => Value::Direct(hash.to_owned()),
+                false => Value::Invalid(line.to_owned()),
+            },
+        });
+    }
+    Ok(out)
+}
+
+/// Get the content of a loose reference, or failing that a packed one.
+fn read(repo: &Repository, name: &str, packed: &[Ref]) -> Result<Option<Value>> {
+    match fs::read_to_string(repo.git_path(name)) {
+        Ok(content) => return Ok(Some(Value::parse(repo, &content))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e).with_context(|| format!("reading {name}")),
+    }
+    Ok(packed
+        .iter()
+        .find(|r| r.name == name)
+        .map(|r| match &r.value {
+            Value::Direct(hash) => Value::Direct(hash.clone()),
+            _ => Value::Invalid(String::new()),
+        }))
+}
+
+/// Check that an object exists locally and has a type suitable for the reference:
+/// commits for branches, commits or tags for tags; anything for other references.
+/// Return a description of the problem if not.
+fn check_target(repo: &Repository, name: &str, hash: &str) -> Result<Option<String>> {
+    if !have_object(repo, hash)? {
+        return Ok(Some(format!("points to missing object {hash}")));
+    }
+    let obj_type = repo.read_object(hash)?.obj_type;
+    let allowed: &[ObjType] = if name.starts_with("refs/tags/") {
+        &[ObjType::Commit, ObjType::Tag]
+    } else if name == "HEAD" || name.starts_with("refs/heads/") || name.starts_with("refs/remotes/")
+    {
+        &[ObjType::Commit]
+    } else {
+        return Ok(None);
+    };
+    if allowed.contains(&obj_type) {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "points to a {} ({hash}), expected a {}",
+        obj_type.to_str(),
+        allowed
+            .iter()
+            .map(ObjType::to_str)
+            .collect::<Vec<_>>()
+            .join(" or ")
+    )))
+}
+
+/// Follow a symbolic reference, returning a description of the problem
+/// if the chain is broken. An unborn HEAD is not a problem.
+fn check_symref(
+    repo: &Repository,
+    name: &str,
+    target: &str,
+    packed: &[Ref],
+) -> Result<Option<String>> {
+    let mut target = target.to_owned();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        if !target.starts_with("refs/") {
+            return Ok(Some(format!("symbolic reference to invalid name {target}")));
+        }
+        match read(repo, &target, packed)? {
+            None if name == "HEAD" => return Ok(None),
+            None => return Ok(Some(format!("dangling symbolic reference to {target}"))),
+            Some(Value::Symbolic(next)) => target = next,
+            // Problems with the target are reported for the target itself.
+            Some(_) => return Ok(None),
+        }
+    }
+    Ok(Some(format!(
+        "symbolic reference chain too long, ending at {target}"
+    )))
+}
+
+/// Find the most recent value from the reflog of a reference which is a valid
+/// target for it, to repair the reference by pointing it back there.
+fn last_good_value(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let path = repo.git_path(&format!("logs/{name}"));
+    let log = match fs::read_to_string(&path) {
+        Ok(log) => log,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    // Lines are "<old> <new> <who>\t<message>".
+    for line in log.lines().rev() {
+        let mut fields = line.split(' ');
+        let (Some(old), Some(new)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        for hash in [new, old] {
+            if is_hash(repo, hash) && check_target(repo, name, hash)?.is_none() {
+                return Ok(Some(hash.to_owned()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Ask the user a yes/no question on the terminal, defaulting to no.
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{question} [y/N] ");
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .context("reading answer")?;
+    Ok(answer.trim().eq_ignore_ascii_case("y"))
+}
+
+/// Remove a reference from .git/packed-refs, along with its peeled value.
+fn remove_packed(repo: &Repository, name: &str) -> Result<()> {
+    let path = repo.git_path("packed-refs");
+    let lock = LockFile::acquire(repo, &path)?;
+    let content = fs::read_to_string(&path).context("reading packed-refs")?;
+    let mut kept = String::new();
+    let mut removing = false;
+    for line in content.lines() {
+        if !line.starts_with('^') {
+            removing = line.split_once(' ').map_or(line, |(_, n)| n) == name;
+        }
+        if !removing {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    lock.commit(kept.as_bytes()).context("updating packed-refs")
+}
+
+/// Offer to repair a broken reference: point it back to its last good value
+/// from the reflog, or delete it (except HEAD). Return true if repaired.
+fn fix(repo: &Repository, r: &Ref) -> Result<bool> {
+    let from_reflog = match r.value {
+        Value::Symbolic(_) => None,
+        _ => last_good_value(repo, &r.name)?,
+    };
+    if let Some(hash) = from_reflog {
+        if !confirm(&format!(
+            "Point {} back to {hash} (from its reflog)?",
+            r.name
+        ))? {
+            return Ok(false);
+        }
+        refs::write(repo, &r.name, &hash)?;
+        if r.packed {
+            // The loose reference takes precedence, but don't leave a broken one behind.
+            remove_packed(repo, &r.name)?;
+        }
+        return Ok(true);
+    }
+    if r.name == "HEAD" {
+        eprintln!("HEAD cannot be repaired automatically: use checkout-empty");
+        return Ok(false);
+    }
+    if !confirm(&format!("Delete {}?", r.name))? {
+        return Ok(false);
+    }
+    if r.packed {
+        remove_packed(repo, &r.name)?;
+    } else {
+        refs::delete(repo, &r.name, None)?;
+    }
+    Ok(true)
+}
+
+/// Check all references, printing problems to stderr and offering to fix them
+/// if asked. Return the number of problems left.
+pub fn verify(repo: &Repository, fix_problems: bool) -> Result<usize> {
+    let packed = list_packed(repo)?;
+    let head = fs::read_to_string(repo.git_path("HEAD")).context("reading HEAD")?;
+    let mut loose = vec![Ref {
+        name: "HEAD".to_owned(),
+        packed: false,
+        value: Value::parse(repo, &head),
+    }];
+    let mut locks = Vec::new();
+    list_loose(
+        repo,
+        &repo.git_path("refs"),
+        "refs/",
+        &mut loose,
+        &mut locks,
+    )?;
+    loose.sort_by(|a, b| a.name.cmp(&b.name));
+    for name in ["HEAD.lock", "packed-refs.lock"] {
+        if repo.git_path(name).exists() {
+            locks.push(name.to_owned());
+        }
+    }
+    locks.sort();
+
+    // Lock files left by a crashed process prevent updating the reference
+    // (which is frozen until they are removed), but nothing is broken.
+    for name in locks {
+        eprintln!("warning: {name}: reference is locked by another process, or a crashed one");
+        if fix_problems && confirm(&format!("Remove {name}?"))? {
+            let path = repo.git_path(&name);
+            fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))?;
+        }
+    }
+
+    let mut left = 0;
+    for r in loose.iter().chain(&packed) {
+        let problem = match &r.value {
+            Value::Invalid(content) => Some(format!("invalid content {content:?}")),
+            Value::Direct(hash) => check_target(repo, &r.name, hash)?,
+            Value::Symbolic(target) => check_symref(repo, &r.name, target, &packed)?,
+        };
+        let Some(problem) = problem else {
+            continue;
+        };
+        let packed_note = if r.packed { " (packed)" } else { "" };
+        eprintln!("error: {}{packed_note}: {problem}", r.name);
+        if !(fix_problems && fix(repo, r)?) {
+            left += 1;
+        }
+    }
+    Ok(left)
+}
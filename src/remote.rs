@@ -0,0 +1,256 @@
+//! Remotes: named repositories to fetch from and push to, configured with
+//! `remote.<name>.*` variables (url, pushurl, fetch and push), see
+//! git-remote(1) and "REMOTES" in git-fetch(1).
+//!
+//! Wherever a remote name is expected, a URL can be given instead; it then
+//! gets the default fetch refspec as if it were a remote with that name.
+
+use anyhow::{bail, Result};
+
+use crate::commands::committer;
+use crate::config;
+use crate::refs;
+use crate::repository::Repository;
+
+/// A fetch refspec, `[+]<src>:<dst>`, where both sides may have a `*`
+/// matching any part of a reference name.
+pub struct FetchSpec {
+    /// Allow non-fast-forward updates
+    pub force: bool,
+    /// The references on the remote, eg "refs/heads/*"
+    pub src: String,
+    /// Where to store them locally, eg "refs/remotes/origin/*"
+    pub dst: String,
+}
+
+impl FetchSpec {
+    /// Parse a fetch refspec. A destination is required.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (force, rest) = match spec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, spec),
+        };
+        let Some((src, dst)) = rest.split_once(':').filter(|(_, dst)| !dst.is_empty()) else {
+            bail!("fetch refspecs without a destination are not supported: {spec}");
+        };
+        let stars = src.matches('*').count();
+        if stars > 1 || dst.matches('*').count() != stars {
+            bail!("invalid refspec {spec}");
+        }
+        Ok(FetchSpec {
+            force,
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+        })
+    }
+
+    /// Give the local reference for a remote one, if this refspec matches it.
+    pub fn map(&self, name: &str) -> Option<String> {
+        match self.src.split_once('*') {
+            Some((prefix, suffix)) => {
+                let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+                Some(self.dst.replacen('*', matched, 1))
+            }
+            None => (name == self.src).then(|| self.dst.clone()),
+        }
+    }
+
+    /// The part of the source before any `*`, to list remote references.
+    pub fn prefix(&self) -> &str {
+        self.src.split('*').next().unwrap_or_default()
+    }
+}
+
+/// The fetch refspec of a new remote: all branches, to remote-tracking branches.
+fn default_fetch_spec(name: &str) -> String {
+    format!("+refs/heads/*:refs/remotes/{name}/*")
+}
+
+/// Tell if the remote name is a URL rather than a name, see config::remote_url().
+fn is_url(remote: &str) -> bool {
+    remote.contains("://")
+}
+
+/// List the names of the configured remotes, sorted.
+pub fn names(repo: &Repository) -> Result<Vec<String>> {
+    config::subsections(repo, "remote")
+}
+
+/// Fail unless a remote with this name is configured.
+fn check_exists(repo: &Repository, name: &str) -> Result<()> {
+    if !names(repo)?.iter().any(|n| n == name) {
+        bail!("No such remote: '{name}'");
+    }
+    Ok(())
+}
+
+/// Fail if the name can't be used for a new remote.
+fn check_new(repo: &Repository, name: &str) -> Result<()> {
+    // Like git, the name must be usable in remote-tracking branch names.
+    if !refs::is_valid_name(&format!("refs/remotes/{name}/test")) {
+        bail!("'{name}' is not a valid remote name");
+    }
+    if names(repo)?.iter().any(|n| n == name) {
+        bail!("remote {name} already exists.");
+    }
+    Ok(())
+}
+
+/// Get the fetch refspecs of a remote, the default one if none is configured.
+pub fn fetch_specs(repo: &Repository, remote: &str) -> Result<Vec<FetchSpec>> {
+    let mut specs = match is_url(remote) {
+        true => Vec::new(),
+        false => config::get_all(repo, &format!("remote.{remote}.fetch"))?,
+    };
+    if specs.is_empty() {
+        specs.push(default_fetch_spec(remote));
+    }
+    specs.iter().map(|spec| FetchSpec::parse(spec)).collect()
+}
+
+/// Get the remote of the current branch (`branch.<name>.remote`), or origin.
+pub fn default_remote(repo: &Repository) -> Result<String> {
+    let branch = refs::current_branch(repo)?;
+    if let Some(branch) = branch
+        .as_deref()
+        .and_then(|b| b.strip_prefix("refs/heads/"))
+    {
+        if let Some(remote) = config::get(repo, &format!("branch.{branch}.remote"))? {
+            return Ok(remote);
+        }
+    }
+    Ok("origin".to_owned())
+}
+
+/// Get the URL to list references from, like git ls-remote: the remote may
+/// be a configured name, or else is taken as a URL (which can be a path or
+/// `<host>:<path>`). By default, it is the remote of the current branch, see
+/// default_remote(). Outside a repository (None), there are no remote names.
+pub fn list_url(repo: Option<&Repository>, remote: Option<&str>) -> Result<String> {
+    let name = match (repo, remote) {
+        (_, Some(remote)) => remote.to_owned(),
+        (Some(repo), None) => default_remote(repo)?,
+        (None, None) => bail!("No remote configured to list refs from."),
+    };
+    let Some(repo) = repo.filter(|_| !is_url(&name)) else {
+        return Ok(name);
+    };
+    match config::get(repo, &format!("remote.{name}.url"))? {
+        Some(url) => Ok(url),
+        None if remote.is_none() => bail!("No remote configured to list refs from."),
+        None => Ok(name),
+    }
+}
+
+/// Get the URL to push to: `remote.<name>.pushurl` if set, otherwise the URL.
+pub fn push_url(repo: &Repository, remote: &str) -> Result<String> {
+    if is_url(remote) {
+        return Ok(remote.to_owned());
+    }
+    match config::get(repo, &format!("remote.{remote}.pushurl"))? {
+        Some(url) => Ok(url),
+        None => config::remote_url(repo, remote),
+    }
+}
+
+/// Get the push refspecs of a remote (none for a URL, or if not configured).
+pub fn push_specs(repo: &Repository, remote: &str) -> Result<Vec<String>> {
+    match is_url(remote) {
+        true => Ok(Vec::new()),
+        false => config::get_all(repo, &format!("remote.{remote}.push")),
+    }
+}
+
+/// List the branches set to pull from a remote, with the remote branch each
+/// merges, sorted by name.
+pub fn tracking_branches(repo: &Repository, name: &str) -> Result<Vec<(String, String)>> {
+    let mut branches = Vec::new();
+    for branch in config::subsections(repo, "branch")? {
+        if config::get(repo, &format!("branch.{branch}.remote"))?.as_deref() != Some(name) {
+            continue;
+        }
+        if let Some(merge) = config::get(repo, &format!("branch.{branch}.merge"))? {
+            branches.push((branch, merge));
+        }
+    }
+    Ok(branches)
+}
+
+/// Add a remote, with the default fetch refspec.
+pub fn add(repo: &Repository, name: &str, url: &str) -> Result<()> {
+    check_new(repo, name)?;
+    config::add(repo, &format!("remote.{name}.url"), url)?;
+    config::add(
+        repo,
+        &format!("remote.{name}.fetch"),
+        &default_fetch_spec(name),
+    )
+}
+
+/// Set the URL of a remote, or its push URL.
+pub fn set_url(repo: &Repository, name: &str, url: &str, push: bool) -> Result<()> {
+    if !names(repo)?.iter().any(|n| n == name) {
+        bail!("No such remote '{name}'");
+    }
+    let key = if push { "pushurl" } else { "url" };
+    config::set(repo, &format!("remote.{name}.{key}"), url)
+}
+
+/// Remove a remote: its configuration, its remote-tracking branches, and
+/// the configuration of branches set to pull from it.
+pub fn remove(repo: &Repository, name: &str) -> Result<()> {
+    check_exists(repo, name)?;
+    for (branch, _) in tracking_branches(repo, name)? {
+        config::unset_all(repo, &format!("branch.{branch}.remote"))?;
+        config::unset_all(repo, &format!("branch.{branch}.merge"))?;
+    }
+    for (tracking, _) in refs::list(repo, &format!("refs/remotes/{name}/"))? {
+        refs::delete(repo, &tracking, None)?;
+    }
+    config::remove_section(repo, &format!("remote.{name}"))?;
+    Ok(())
+}
+
+/// Rename a remote, along with its remote-tracking branches if the fetch
+/// refspecs use the default place, and the branches set to pull from it.
+pub fn rename(repo: &Repository, old: &str, new: &str) -> Result<()> {
+    check_exists(repo, old)?;
+    check_new(repo, new)?;
+    config::rename_section(repo, &format!("remote.{old}"), &format!("remote.{new}"))?;
+
+    let (old_prefix, new_prefix) = (
+        format!("refs/remotes/{old}/"),
+        format!("refs/remotes/{new}/"),
+    );
+    let key = format!("remote.{new}.fetch");
+    let specs = config::get_all(repo, &key)?;
+    if specs.iter().any(|spec| spec.contains(&old_prefix)) {
+        config::unset_all(repo, &key)?;
+        for spec in specs {
+            config::add(repo, &key, &spec.replace(&old_prefix, &new_prefix))?;
+        }
+    }
+    for (branch, _) in tracking_branches(repo, old)? {
+        config::set(repo, &format!("branch.{branch}.remote"), new)?;
+    }
+
+    let who = committer();
+    for (tracking, hash) in refs::list(repo, &old_prefix)? {
+        let renamed = format!("{new_prefix}{}", &tracking[old_prefix.len()..]);
+        match refs::read_symbolic(repo, &tracking)? {
+            Some(target) => {
+                let target = match target.strip_prefix(&old_prefix) {
+                    Some(rest) => format!("{new_prefix}{rest}"),
+                    None => target,
+                };
+                refs::write_symbolic(repo, &renamed, &target)?;
+            }
+            None => {
+                let message = format!("remote: renamed {tracking} to {renamed}");
+                refs::update(repo, &renamed, None, &hash, &who, &message)?;
+            }
+        }
+        refs::delete(repo, &tracking, None)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,497 @@
+//! The repository commands operate on: where its files are, and where the
+//! current command runs from in it.
+//!
+//! Commands get the repository they operate on, found from the current
+//! directory by main, and pass it on to the rest of the implementation:
+//! nothing is global, so a program can open several repositories.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use crate::attributes;
+use crate::config;
+use crate::crlf::{AutoCrlf, Eol};
+use crate::obj_read::ObjReader;
+use crate::obj_type::ObjType;
+use crate::obj_write::ObjWriter;
+use crate::object_id::HashAlgo;
+use crate::pack_read::PackIndex;
+use crate::platform;
+use crate::tree_read::TreeReader;
+
+/// A repository, and where the current command runs from in it.
+pub struct Repository {
+    git_dir: PathBuf,
+    /// Where what all worktrees share is stored, the same as git_dir except
+    /// in linked worktrees (see worktrees.rs)
+    common_dir: PathBuf,
+    /// Root of the worktree, None for bare repositories (see init --bare)
+    work_tree: Option<PathBuf>,
+    /// Where objects are stored, usually .git/objects
+    object_dir: PathBuf,
+    /// Other object directories to read objects from, see read_alternates()
+    alternates: Vec<PathBuf>,
+    /// Path of the current directory relative to the root of the worktree
+    prefix: PathBuf,
+    /// Refuse to write anything, see open_read_only()
+    read_only: bool,
+    cache: Cache,
+}
+
+/// What is read from a repository once, see the modules using it: checkouts
+/// and hashing may happen in several threads.
+#[derive(Default)]
+pub(crate) struct Cache {
+    /// Hash function of objects, from extensions.objectFormat
+    pub hash_algo: OnceLock<HashAlgo>,
+    /// Indexes of the packs found so far, see pack_read
+    pub packs: Mutex<Vec<PackIndex>>,
+    /// Shallow commits, see shallow
+    pub shallow: OnceLock<HashSet<String>>,
+    /// core.filemode and core.symlinks, see platform
+    pub filemode: OnceLock<bool>,
+    pub symlinks: OnceLock<bool>,
+    /// core.autocrlf and core.eol, see crlf
+    pub autocrlf: OnceLock<AutoCrlf>,
+    pub eol: OnceLock<Eol>,
+    /// Rules of the attributes files read so far, see attributes
+    pub attributes: Mutex<attributes::Cache>,
+}
+
+/// Tell if a directory looks like a git directory: the top level of a bare
+/// repository, the .git directory of a non-bare one, or the directory of
+/// a linked worktree in .git/worktrees.
+pub fn is_git_dir(dir: &Path) -> bool {
+    let common_dir = common_dir_of(dir);
+    dir.join("HEAD").is_file()
+        && common_dir.join("objects").is_dir()
+        && common_dir.join("refs").is_dir()
+}
+
+/// Get the directory shared by all worktrees from a git directory: the one
+/// named in its commondir file (relative to it) for linked worktrees, else
+/// the git directory itself.
+pub fn common_dir_of(git_dir: &Path) -> PathBuf {
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(dir) => {
+            let dir = git_dir.join(dir.trim_end());
+            fs::canonicalize(&dir).unwrap_or(dir)
+        }
+        Err(_) => git_dir.to_owned(),
+    }
+}
+
+/// Read a .git file, "gitdir: `<path>`" (relative to the directory of the
+/// file), as found in linked worktrees and submodules, and return the git
+/// directory it points to.
+pub fn read_git_file(path: &Path) -> Result<PathBuf> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let Some(git_dir) = content.trim_end().strip_prefix("gitdir: ") else {
+        bail!("invalid gitfile format: {}", path.display());
+    };
+    let git_dir = path.parent().unwrap_or(Path::new("/")).join(git_dir);
+    let git_dir = fs::canonicalize(&git_dir).unwrap_or(git_dir);
+    if !is_git_dir(&git_dir) {
+        bail!("not a git repository: {}", git_dir.display());
+    }
+    Ok(git_dir)
+}
+
+/// Files and directories of a git directory that all worktrees share, see
+/// gitrepository-layout(5); anything else, like HEAD or the index, belongs
+/// to a worktree.
+const SHARED: [&str; 12] = [
+    "branches",
+    "config",
+    "description",
+    "hooks",
+    "info",
+    "logs",
+    "objects",
+    "packed-refs",
+    "refs",
+    "remotes",
+    "shallow",
+    "worktrees",
+];
+
+/// References under these directories (of refs/ and logs/refs/) belong to a
+/// worktree, unlike other references.
+const PER_WORKTREE_REFS: [&str; 3] = ["bisect/", "rewritten/", "worktree/"];
+
+/// Maximum depth of alternates of alternates, same as git.
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+/// Add an object directory to the list of alternates, if it exists and is not
+/// already known, then its own alternates.
+fn add_alternate(dir: PathBuf, depth: usize, known: &mut Vec<PathBuf>) {
+    // Like git, ignore directories that don't exist.
+    let Ok(dir) = fs::canonicalize(dir) else {
+        return;
+    };
+    if known.contains(&dir) {
+        return;
+    }
+    known.push(dir.clone());
+    if depth < MAX_ALTERNATE_DEPTH {
+        read_alternates(&dir, depth + 1, known);
+    }
+}
+
+/// Add object directories listed in objects/info/alternates, one per line,
+/// absolute or relative to the object directory, see gitrepository-layout(5).
+fn read_alternates(object_dir: &Path, depth: usize, known: &mut Vec<PathBuf>) {
+    let Ok(text) = fs::read_to_string(object_dir.join("info/alternates")) else {
+        return;
+    };
+    for line in text.lines().map(str::trim) {
+        if !line.is_empty() && !line.starts_with('#') {
+            add_alternate(object_dir.join(line), depth, known);
+        }
+    }
+}
+
+/// Get an absolute path from an environment variable, if it is set.
+fn path_from_env(name: &str, cwd: &Path) -> Option<PathBuf> {
+    let value = env::var_os(name).filter(|v| !v.is_empty())?;
+    let path = cwd.join(value);
+    // Resolve symbolic links, so that the prefix can be computed.
+    Some(fs::canonicalize(&path).unwrap_or(path))
+}
+
+impl Repository {
+    /// Make a repository with the given .git directory and worktree (None if bare).
+    fn new(git_dir: PathBuf, work_tree: Option<PathBuf>) -> Self {
+        let common_dir = common_dir_of(&git_dir);
+        Repository {
+            object_dir: common_dir.join("objects"),
+            alternates: Vec::new(),
+            git_dir,
+            common_dir,
+            work_tree,
+            prefix: PathBuf::new(),
+            read_only: false,
+            cache: Cache::default(),
+        }
+    }
+
+    /// Find the other object directories to read objects from: the ones listed
+    /// in GIT_ALTERNATE_OBJECT_DIRECTORIES (separated by colons), and those in
+    /// objects/info/alternates, recursively.
+    fn load_alternates(&mut self, cwd: &Path) {
+        let mut known = vec![fs::canonicalize(&self.object_dir).unwrap_or(self.object_dir.clone())];
+        if let Some(dirs) = env::var_os("GIT_ALTERNATE_OBJECT_DIRECTORIES") {
+            for dir in env::split_paths(&dirs).filter(|d| !d.as_os_str().is_empty()) {
+                add_alternate(cwd.join(dir), 0, &mut known);
+            }
+        }
+        read_alternates(&self.object_dir, 0, &mut known);
+        self.alternates = known.split_off(1);
+    }
+
+    /// Set the prefix for commands running from the given directory.
+    /// Outside of the worktree, paths are relative to its root.
+    fn run_from(&mut self, dir: &Path) {
+        self.prefix = match self.work_tree.as_ref().map(|w| dir.strip_prefix(w)) {
+            Some(Ok(prefix)) => prefix.to_owned(),
+            _ => PathBuf::new(),
+        };
+    }
+
+    /// Look for a repository in the given directory and its ancestors.
+    fn search(start: &Path) -> Result<Self> {
+        for dir in start.ancestors() {
+            let dot_git = dir.join(".git");
+            if dot_git.is_dir() {
+                return Ok(Repository::new(dot_git, Some(dir.to_owned())));
+            }
+            if dot_git.is_file() {
+                let git_dir = read_git_file(&dot_git)?;
+                return Ok(Repository::new(git_dir, Some(dir.to_owned())));
+            }
+            // Inside the .git directory of a non-bare repository, keep looking
+            // for its worktree.
+            if is_git_dir(dir) && dir.file_name().is_some_and(|name| name != ".git") {
+                return Ok(Repository::new(dir.to_owned(), None));
+            }
+        }
+        bail!("not a git repository (or any of the parent directories): .git");
+    }
+
+    /// Find the repository from the current directory, like git.
+    ///
+    /// GIT_DIR overrides the search for a .git directory; the worktree is then
+    /// the current directory unless core.bare is set. GIT_WORK_TREE overrides
+    /// the worktree, and GIT_OBJECT_DIRECTORY where objects are stored.
+    pub fn discover() -> Result<Self> {
+        let cwd = env::current_dir().context("getting current directory (looking for .git)")?;
+        let mut repo = match path_from_env("GIT_DIR", &cwd) {
+            Some(mut git_dir) => {
+                if git_dir.is_file() {
+                    git_dir = read_git_file(&git_dir)?;
+                }
+                if !is_git_dir(&git_dir) {
+                    bail!("not a git repository: '{}'", git_dir.display());
+                }
+                let bare = config::get_bool_in(&git_dir, "core.bare")?.unwrap_or(false);
+                let work_tree = (!bare).then(|| cwd.clone());
+                Repository::new(git_dir, work_tree)
+            }
+            None => Self::search(&cwd)?,
+        };
+        if let Some(work_tree) = path_from_env("GIT_WORK_TREE", &cwd) {
+            repo.work_tree = Some(work_tree);
+        }
+        if let Some(object_dir) = path_from_env("GIT_OBJECT_DIRECTORY", &cwd) {
+            repo.object_dir = object_dir;
+        }
+        repo.run_from(&cwd);
+        repo.load_alternates(&cwd);
+        Ok(repo)
+    }
+
+    /// Open the repository containing the given path (a worktree, a directory
+    /// in it, or a bare repository).
+    pub fn open(path: &Path) -> Result<Self> {
+        let path = fs::canonicalize(path).with_context(|| format!("opening {}", path.display()))?;
+        let mut repo = Self::search(&path)?;
+        repo.run_from(&path);
+        repo.load_alternates(&path);
+        Ok(repo)
+    }
+
+    /// Open a repository like open(), in read-only mode: any attempt to write
+    /// to it (objects, or anything under a lock file like refs and the index)
+    /// then fails upfront with a clear message, rather than when trying to
+    /// create a temporary file. Suitable for commands that only read, so they
+    /// work on repositories the user can't write to.
+    pub fn open_read_only(path: &Path) -> Result<Self> {
+        let mut repo = Self::open(path)?;
+        repo.read_only = true;
+        Ok(repo)
+    }
+
+    /// Find the repository from the current directory like discover(),
+    /// in read-only mode, see open_read_only().
+    pub fn discover_read_only() -> Result<Self> {
+        let mut repo = Self::discover()?;
+        repo.read_only = true;
+        Ok(repo)
+    }
+
+    /// Find the repository from the current directory like discover(), for
+    /// a command that only reads: in read-only mode (see open_read_only())
+    /// if the current user can't write to it, so that the command works on
+    /// it, and fails clearly if it has to write after all. Otherwise, the
+    /// command may still write, for example to cache objects (see http_store).
+    pub fn discover_for_reading() -> Result<Self> {
+        let mut repo = Self::discover()?;
+        let dirs = [&repo.git_dir, &repo.common_dir, &repo.object_dir];
+        repo.read_only = !dirs.into_iter().all(|dir| platform::is_writable(dir));
+        Ok(repo)
+    }
+
+    /// Create an empty repository in the given directory (created if needed).
+    /// A bare repository has the content of .git at the top level, and no
+    /// worktree.
+    pub fn init(path: &Path, object_format: HashAlgo, bare: bool) -> Result<Self> {
+        let git_dir = match bare {
+            true => path.to_owned(),
+            false => path.join(".git"),
+        };
+        let obj_dir = git_dir.join("objects");
+        fs::create_dir_all(&obj_dir).with_context(|| format!("creating {}", obj_dir.display()))?;
+        fs::create_dir_all(git_dir.join("refs/heads")).context("creating refs/heads")?;
+        fs::write(git_dir.join("HEAD"), b"ref: refs/heads/main\n").context("creating HEAD")?;
+        let mut core = Vec::new();
+        if object_format != HashAlgo::Sha1 {
+            core.push("\trepositoryformatversion = 1\n");
+        }
+        if bare {
+            core.push("\tbare = true\n");
+        }
+        let mut config = String::new();
+        if !core.is_empty() {
+            config = format!("[core]\n{}", core.concat());
+        }
+        if object_format != HashAlgo::Sha1 {
+            config += &format!("[extensions]\n\tobjectformat = {}\n", object_format.name());
+        }
+        if !config.is_empty() {
+            fs::write(git_dir.join("config"), config).context("creating config")?;
+        }
+
+        let path = fs::canonicalize(path)?;
+        Ok(match bare {
+            true => Repository::new(path, None),
+            false => Repository::new(path.join(".git"), Some(path)),
+        })
+    }
+
+    /// Return the path to the .git directory, for example "/path/to/repo/.git".
+    pub fn git_dir(&self) -> &PathBuf {
+        &self.git_dir
+    }
+
+    /// Return the path to the directory shared by all worktrees, for example
+    /// "/path/to/repo/.git": the same as git_dir() except in linked worktrees.
+    pub fn common_dir(&self) -> &PathBuf {
+        &self.common_dir
+    }
+
+    /// Return the path to a file of the git directory, for example
+    /// "refs/heads/main": in the common directory if it is shared by all
+    /// worktrees, else in the git directory of this one.
+    ///
+    /// Like git, "main-worktree/`<name>`" is a file of the main worktree, and
+    /// "worktrees/`<id>`/`<name>`" one of a linked worktree, eg
+    /// "worktrees/`<id>`/HEAD"; their reflogs are found the same way.
+    pub fn git_path(&self, name: &str) -> PathBuf {
+        let (logs, rest) = match name.strip_prefix("logs/") {
+            Some(rest) => ("logs/", rest),
+            None => ("", name),
+        };
+        if let Some(rest) = rest.strip_prefix("main-worktree/") {
+            return self.common_dir.join(logs).join(rest);
+        }
+        if let Some((id, rest)) = rest
+            .strip_prefix("worktrees/")
+            .and_then(|rest| rest.split_once('/'))
+            .filter(|_| !logs.is_empty())
+        {
+            return self
+                .common_dir
+                .join("worktrees")
+                .join(id)
+                .join(logs)
+                .join(rest);
+        }
+        let top = name.split('/').next().unwrap_or_default();
+        let shared = match rest.strip_prefix("refs/") {
+            Some(rest) if name != "logs/HEAD" => {
+                !PER_WORKTREE_REFS.iter().any(|dir| rest.starts_with(dir))
+            }
+            _ => SHARED.contains(&top) && name != "logs/HEAD",
+        };
+        match shared {
+            true => self.common_dir.join(name),
+            false => self.git_dir.join(name),
+        }
+    }
+
+    /// Return the path to the root of the worktree, for example "/path/to/repo".
+    /// Fails in bare repositories, which have none.
+    pub fn work_tree(&self) -> Result<&Path> {
+        match &self.work_tree {
+            Some(work_tree) => Ok(work_tree),
+            None => bail!("this operation must be run in a work tree"),
+        }
+    }
+
+    /// Tell if the repository is bare, that is has no worktree.
+    pub fn is_bare(&self) -> bool {
+        self.work_tree.is_none()
+    }
+
+    /// Get a view of the repository that stores new objects in another
+    /// directory, reading existing ones from its own first, like an alternate.
+    /// This keeps received objects aside until they're known to be wanted,
+    /// see fetch --dry-run.
+    pub fn quarantine(&self, object_dir: &Path) -> Repository {
+        let mut repo = Repository::new(self.git_dir.clone(), self.work_tree.clone());
+        repo.object_dir = object_dir.to_owned();
+        repo.alternates = self.object_dirs().map(Path::to_owned).collect();
+        repo.prefix = self.prefix.clone();
+        repo.read_only = self.read_only;
+        repo
+    }
+
+    /// Tell if the repository has been opened in read-only mode.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fail if the repository has been opened in read-only mode.
+    /// The argument describes what we were about to write, for the error message.
+    pub fn ensure_writable(&self, what: &str) -> Result<()> {
+        if self.read_only {
+            bail!("refusing to write {what}: repository opened read-only");
+        }
+        Ok(())
+    }
+
+    /// Return the path to the object directory, for example "/path/to/repo/.git/objects".
+    pub fn object_dir(&self) -> &Path {
+        &self.object_dir
+    }
+
+    /// Return the paths of all directories to read objects from: the object
+    /// directory first, then alternates in order of priority.
+    pub fn object_dirs(&self) -> impl Iterator<Item = &Path> {
+        [&self.object_dir]
+            .into_iter()
+            .chain(&self.alternates)
+            .map(PathBuf::as_path)
+    }
+
+    /// Return the path of the current directory relative to the root of the worktree.
+    pub fn prefix(&self) -> &Path {
+        &self.prefix
+    }
+
+    /// Get the hash function of objects, from extensions.objectFormat.
+    pub fn hash_algo(&self) -> Result<HashAlgo> {
+        if let Some(&algo) = self.cache.hash_algo.get() {
+            return Ok(algo);
+        }
+        let algo = match self.config("extensions.objectformat")? {
+            Some(name) => HashAlgo::from_name(&name)?,
+            None => HashAlgo::Sha1,
+        };
+        Ok(*self.cache.hash_algo.get_or_init(|| algo))
+    }
+
+    /// Return the path of a loose object in the object directory, for example
+    /// "/path/to/repo/.git/objects/01/2345...".
+    pub fn loose_path(&self, hash: &str) -> PathBuf {
+        self.object_dir.join(&hash[0..2]).join(&hash[2..])
+    }
+
+    /// Find a loose object in the object directory or its alternates.
+    pub fn find_loose(&self, hash: &str) -> Option<PathBuf> {
+        self.object_dirs()
+            .map(|dir| dir.join(&hash[0..2]).join(&hash[2..]))
+            .find(|path| path.exists())
+    }
+
+    /// Get what was read from the repository so far.
+    pub(crate) fn cache(&self) -> &Cache {
+        &self.cache
+    }
+
+    /// Get the value of a configuration variable, eg "remote.origin.url".
+    pub fn config(&self, key: &str) -> Result<Option<String>> {
+        config::get_in(&self.git_dir, key)
+    }
+
+    /// Open an object for reading, from loose storage or a pack.
+    pub fn read_object(&self, hash: &str) -> Result<ObjReader> {
+        ObjReader::open(self, hash)
+    }
+
+    /// Open a tree object for reading its entries.
+    pub fn read_tree(&self, hash: &str) -> Result<TreeReader> {
+        TreeReader::from_object(self.read_object(hash)?)
+    }
+
+    /// Start writing an object of the given type and size to loose storage;
+    /// its hash is returned by ObjWriter::finish().
+    pub fn write_object(&self, obj_type: ObjType, size: usize) -> Result<ObjWriter<'_>> {
+        ObjWriter::new(self, obj_type, size, true)
+    }
+}
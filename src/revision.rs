@@ -0,0 +1,195 @@
+//! Resolving revisions to object hashes, see gitrevisions(7).
+//!
+//! Supported: full or abbreviated hashes, reference names (expanded like
+//! refs::dwim), "@" for HEAD, optionally followed by `@{<n>}` or `@{<date>}`
+//! to select a previous value from the reflog, then any number of `~<n>`,
+//! `^<n>` and `^{<type>}` suffixes, and finally `:<path>` to get an object
+//! from the tree of the resulting commit or tree (with a path relative to
+//! the root, or to the current directory if it starts with ./ or ../).
+
+use anyhow::{bail, Context, Result};
+use std::path::{Component, PathBuf};
+
+use crate::abbrev;
+use crate::date;
+use crate::extract::find_entry;
+use crate::obj_read::read_commit;
+use crate::obj_type::ObjType;
+use crate::object_id::is_hash;
+use crate::refs;
+use crate::repository::Repository;
+use crate::revwalk::{peel, peel_to_commit};
+
+/// Resolve the part of a revision before any suffix: a hash or reference name.
+fn resolve_base(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let name = if name == "@" { "HEAD" } else { name };
+    if is_hash(repo, name) {
+        return Ok(Some(name.to_ascii_lowercase()));
+    }
+    // Like git, reference names take precedence over abbreviated hashes.
+    if let Some((_, hash)) = refs::dwim(repo, name)? {
+        return Ok(Some(hash));
+    }
+    abbrev::expand(repo, name)
+}
+
+/// Resolve `<name>@{<n>}` (the n-th previous value of a reference)
+/// or `<name>@{<date>}` (its value at that date) using its reflog.
+/// An empty name means the current branch, like git.
+fn resolve_reflog(repo: &Repository, name: &str, selector: &str) -> Result<String> {
+    let full_name = match name {
+        "" => refs::current_branch(repo)?.unwrap_or_else(|| "HEAD".to_owned()),
+        _ => match refs::dwim(repo, name)? {
+            Some((full_name, _)) => full_name,
+            None => bail!("unknown revision {name}"),
+        },
+    };
+    let log = refs::read_reflog(repo, &full_name)?;
+    let Some(first) = log.first() else {
+        bail!("no reflog for {full_name}");
+    };
+    if selector.bytes().all(|c| c.is_ascii_digit()) {
+        let n: usize = selector.parse().context("invalid reflog index")?;
+        return match log.iter().rev().nth(n) {
+            Some(entry) => Ok(entry.new.clone()),
+            None => bail!("log for {full_name} only has {} entries", log.len()),
+        };
+    }
+    let time = date::parse(selector)?;
+    if let Some(entry) = log.iter().rev().find(|entry| entry.time <= time) {
+        return Ok(entry.new.clone());
+    }
+    // Before the whole log: the best guess is the value before the first entry.
+    eprintln!(
+        "warning: log for {full_name} only goes back to @{}",
+        first.time
+    );
+    Ok(if first.old == repo.hash_algo()?.zero_hash() {
+        first.new.clone()
+    } else {
+        first.old.clone()
+    })
+}
+
+/// Get the n-th parent of a commit (or of the commit a tag points to),
+/// the commit itself for n = 0.
+fn nth_parent(repo: &Repository, hash: &str, n: usize) -> Result<String> {
+    let commit = peel_to_commit(repo, hash)?;
+    if n == 0 {
+        return Ok(commit);
+    }
+    match read_commit(repo, &commit)?.parents.into_iter().nth(n - 1) {
+        Some(parent) => Ok(parent),
+        None => bail!("commit {commit} has no parent number {n}"),
+    }
+}
+
+/// Peel an object to the given type, as in `<rev>^{<type>}`:
+/// an empty type means peeling tags until reaching another type.
+fn peel_to(repo: &Repository, hash: &str, obj_type: &str) -> Result<String> {
+    let Some((peeled, peeled_type)) = peel(repo, hash)? else {
+        bail!("object {hash} not found");
+    };
+    match (obj_type, peeled_type) {
+        ("" | "object", _) => Ok(peeled),
+        ("tag", _) if peeled != hash => Ok(hash.to_owned()),
+        ("commit", ObjType::Commit) | ("tree", ObjType::Tree) | ("blob", ObjType::Blob) => {
+            Ok(peeled)
+        }
+        ("tree", ObjType::Commit) => Ok(read_commit(repo, &peeled)?.tree),
+        ("tag" | "commit" | "tree" | "blob", _) => {
+            bail!("{hash} cannot be peeled to a {obj_type}")
+        }
+        _ => bail!("unknown object type {obj_type:?}"),
+    }
+}
+
+/// Split `<rev>:<path>` at the first colon which is not inside braces
+/// (dates in `@{<date>}` may contain colons).
+fn split_path(rev: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    for (i, c) in rev.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            ':' if depth == 0 => return Some((&rev[..i], &rev[i + 1..])),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Make the path of `<rev>:<path>` relative to the root of the tree:
+/// like git, paths starting with ./ or ../ are relative to the current directory.
+fn tree_path(repo: &Repository, path: &str) -> Result<PathBuf> {
+    if !path.starts_with("./") && !path.starts_with("../") {
+        return Ok(PathBuf::from(path));
+    }
+    let mut out = PathBuf::new();
+    for comp in repo.prefix().join(path).components() {
+        match comp {
+            Component::Normal(name) => out.push(name),
+            Component::CurDir => (),
+            Component::ParentDir if out.pop() => (),
+            _ => bail!("{path} is outside the repository"),
+        }
+    }
+    Ok(out)
+}
+
+/// Get the hash of the object a revision refers to.
+pub fn resolve(repo: &Repository, rev: &str) -> Result<String> {
+    let Some((base, path)) = split_path(rev) else {
+        return resolve_rev(repo, rev);
+    };
+    if base.is_empty() {
+        bail!("invalid revision {rev}: paths in the index are not supported");
+    }
+    let tree = peel_to(repo, &resolve_rev(repo, base)?, "tree")
+        .with_context(|| format!("resolving {rev}"))?;
+    let path = tree_path(repo, path)?;
+    match find_entry(repo, &tree, &path).with_context(|| format!("resolving {rev}"))? {
+        Some(entry) => Ok(hex::encode(entry.hash)),
+        None => Ok(tree),
+    }
+}
+
+/// Get the hash of the object a revision without a path refers to.
+fn resolve_rev(repo: &Repository, rev: &str) -> Result<String> {
+    let (base, mut suffixes) = rev.split_at(rev.find(['~', '^']).unwrap_or(rev.len()));
+    let mut hash = match base.strip_suffix('}').and_then(|b| b.split_once("@{")) {
+        Some((name, selector)) => {
+            resolve_reflog(repo, name, selector).with_context(|| format!("resolving {rev}"))?
+        }
+        None => match resolve_base(repo, base)? {
+            Some(hash) => hash,
+            None => bail!("unknown revision {rev}"),
+        },
+    };
+    while !suffixes.is_empty() {
+        if let Some(rest) = suffixes.strip_prefix("^{") {
+            let Some(end) = rest.find('}') else {
+                bail!("invalid revision {rev}: missing '}}'");
+            };
+            hash =
+                peel_to(repo, &hash, &rest[..end]).with_context(|| format!("resolving {rev}"))?;
+            suffixes = &rest[end + 1..];
+            continue;
+        }
+        let (op, rest) = suffixes.split_at(1);
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let n = match digits {
+            0 => 1,
+            _ => rest[..digits]
+                .parse()
+                .with_context(|| format!("invalid revision {rev}"))?,
+        };
+        suffixes = &rest[digits..];
+        hash = match op {
+            "~" => (0..n).try_fold(hash, |hash, _| nth_parent(repo, &hash, 1)),
+            _ => nth_parent(repo, &hash, n),
+        }
+        .with_context(|| format!("resolving {rev}"))?;
+    }
+    Ok(hash)
+}
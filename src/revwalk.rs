@@ -0,0 +1,426 @@
+//! Walking history: finding commits and objects reachable from some tips.
+
+use anyhow::{bail, Context, Result};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::str;
+
+use crate::bitmap;
+use crate::commit::Commit;
+use crate::fetch::have_object;
+use crate::obj_read::{read_commit, read_tree, ObjReader};
+use crate::obj_type::ObjType;
+use crate::repository::Repository;
+use crate::tree_entry::Mode;
+
+/// Get the object an annotated tag points to, along with its type.
+pub fn tag_target(object: &mut ObjReader) -> Result<(String, ObjType)> {
+    // object <hash>
+    // type <type>
+    let line = object.read_up_to(b'\n').context("reading object line")?;
+    let Some(hash) = line.strip_prefix(b"object ") else {
+        bail!("malformed tag: no object in first line");
+    };
+    let hash = str::from_utf8(hash).context("malformed tag: invalid object hash")?;
+    let line = object.read_up_to(b'\n').context("reading type line")?;
+    let Some(obj_type) = line.strip_prefix(b"type ") else {
+        bail!("malformed tag: no type in second line");
+    };
+    Ok((hash.to_owned(), ObjType::from_bytes(obj_type)?))
+}
+
+/// Peel tags until reaching an object which is not a tag.
+/// Return None if the object (or one along the way) doesn't exist locally.
+pub fn peel(repo: &Repository, hash: &str) -> Result<Option<(String, ObjType)>> {
+    let mut hash = hash.to_owned();
+    loop {
+        if !have_object(repo, &hash)? {
+            return Ok(None);
+        }
+        let mut object = repo
+            .read_object(&hash)
+            .with_context(|| format!("opening {hash}"))?;
+        if object.obj_type != ObjType::Tag {
+            return Ok(Some((hash, object.obj_type)));
+        }
+        (hash, _) = tag_target(&mut object).with_context(|| format!("reading tag {hash}"))?;
+    }
+}
+
+/// Peel tags until reaching a commit, failing if the object is not one.
+pub fn peel_to_commit(repo: &Repository, hash: &str) -> Result<String> {
+    match peel(repo, hash)? {
+        Some((hash, ObjType::Commit)) => Ok(hash),
+        Some((_, obj_type)) => bail!("{hash} is a {}, not a commit", obj_type.to_str()),
+        None => bail!("{hash}: object not found"),
+    }
+}
+
+/// Conditions for commits to be listed, like the options of git rev-list:
+/// commits which don't match are still walked through.
+#[derive(Default)]
+pub struct Filter {
+    /// Committed at or after this date (--since)
+    pub since: Option<i64>,
+    /// Committed at or before this date (--until)
+    pub until: Option<i64>,
+    /// Author name or email containing this (--author), case-sensitive;
+    /// unlike git, this is a plain string rather than a regular expression
+    pub author: Option<String>,
+    /// Message containing this (--grep), with the same limitations
+    pub grep: Option<String>,
+}
+
+impl Filter {
+    /// Tell if a commit meets all conditions.
+    fn matches(&self, commit: &Commit) -> bool {
+        let contains = |haystack: &[u8], needle: &str| {
+            haystack
+                .windows(needle.len().max(1))
+                .any(|w| w == needle.as_bytes())
+        };
+        self.since.map_or(true, |since| commit.time >= since)
+            && self.until.map_or(true, |until| commit.time <= until)
+            && self
+                .author
+                .as_ref()
+                .map_or(true, |author| contains(commit.author.as_bytes(), author))
+            && self
+                .grep
+                .as_ref()
+                .map_or(true, |grep| contains(&commit.message, grep))
+    }
+}
+
+/// Walk commits reachable from the tips, not walking past commits in `hidden`,
+/// and return those matching the filter most recent first (by committer date,
+/// like git rev-list), along with the hidden parents of the commits walked
+/// (boundary).
+fn walk_commits(
+    repo: &Repository,
+    tips: &[String],
+    hidden: &HashSet<String>,
+    filter: &Filter,
+) -> Result<(Vec<String>, HashSet<String>)> {
+    let mut walk = Walk {
+        queue: BinaryHeap::new(),
+        seen: HashSet::new(),
+        boundary: HashSet::new(),
+        hidden,
+        filter,
+    };
+    for tip in tips {
+        match peel(repo, tip)? {
+            // Hidden tips are not part of the boundary, only hidden parents are.
+            Some((hash, ObjType::Commit)) if !hidden.contains(&hash) => walk.push(repo, hash)?,
+            _ => (),
+        }
+    }
+    let mut commits = Vec::new();
+    while let Some(pending) = walk.queue.pop() {
+        for parent in pending.parents {
+            walk.push(repo, parent)?;
+        }
+        if pending.shown {
+            commits.push(pending.hash);
+        }
+    }
+    Ok((commits, walk.boundary))
+}
+
+/// A commit queued by walk_commits(): most recent first, then in
+/// insertion order (hence the reversed sequence number).
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Pending {
+    time: i64,
+    seq: Reverse<usize>,
+    hash: String,
+    parents: Vec<String>,
+    /// Matches the filter
+    shown: bool,
+}
+
+/// State of walk_commits().
+struct Walk<'a> {
+    queue: BinaryHeap<Pending>,
+    seen: HashSet<String>,
+    boundary: HashSet<String>,
+    hidden: &'a HashSet<String>,
+    filter: &'a Filter,
+}
+
+impl Walk<'_> {
+    /// Queue a commit unless already seen or hidden.
+    fn push(&mut self, repo: &Repository, hash: String) -> Result<()> {
+        if self.hidden.contains(&hash) {
+            self.boundary.insert(hash);
+            return Ok(());
+        }
+        if !have_object(repo, &hash)? {
+            // Missing commit: must be from a part of history we don't have.
+            return Ok(());
+        }
+        if self.seen.insert(hash.clone()) {
+            let commit = read_commit(repo, &hash)?;
+            self.queue.push(Pending {
+                time: commit.time,
+                seq: Reverse(self.seen.len()),
+                shown: self.filter.matches(&commit),
+                hash,
+                parents: commit.parents,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Get commits reachable from the tips but not from any of the excluded commits,
+/// most recent first.
+///
+/// Tips and exclusions may also be tags (peeled to commits) or other objects (ignored).
+/// Objects we don't have locally are ignored too.
+pub fn reachable_commits(
+    repo: &Repository,
+    tips: &[String],
+    exclude: &[String],
+) -> Result<Vec<String>> {
+    filtered_commits(repo, tips, exclude, &Filter::default())
+}
+
+/// Like reachable_commits(), keeping only commits which match the filter.
+pub fn filtered_commits(
+    repo: &Repository,
+    tips: &[String],
+    exclude: &[String],
+    filter: &Filter,
+) -> Result<Vec<String>> {
+    let (hidden, _) = walk_commits(repo, exclude, &HashSet::new(), &Filter::default())?;
+    let (commits, _) = walk_commits(repo, tips, &hidden.into_iter().collect(), filter)?;
+    Ok(commits)
+}
+
+/// Get the excluded commits which are parents of commits reachable from the
+/// tips but not from the excluded commits, sorted: the history of those commits
+/// is complete given these.
+pub fn boundary_commits(
+    repo: &Repository,
+    tips: &[String],
+    exclude: &[String],
+) -> Result<Vec<String>> {
+    let (hidden, _) = walk_commits(repo, exclude, &HashSet::new(), &Filter::default())?;
+    let (_, boundary) = walk_commits(
+        repo,
+        tips,
+        &hidden.into_iter().collect(),
+        &Filter::default(),
+    )?;
+    let mut boundary: Vec<String> = boundary.into_iter().collect();
+    boundary.sort_unstable();
+    Ok(boundary)
+}
+
+/// Tell if a commit is an ancestor of (or the same as) another commit.
+pub fn is_ancestor(repo: &Repository, ancestor: &str, descendant: &str) -> Result<bool> {
+    let commits = reachable_commits(repo, &[descendant.to_owned()], &[])?;
+    Ok(commits.iter().any(|c| c == ancestor))
+}
+
+// Flags used when looking for merge bases.
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+/// A commit in the merge base queue, ordered by committer date (then hash,
+/// so that the order does not depend on the order of insertion).
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Queued {
+    time: i64,
+    hash: String,
+}
+
+/// Find common ancestors of two commits which are not ancestors of another
+/// common ancestor, most recent first.
+///
+/// Like git, commits are walked in committer date order from both sides,
+/// painting them with the side(s) they are reachable from; commits reachable
+/// from both are candidates, and their ancestors are marked stale. Since
+/// dates may be wrong, candidates reachable from another candidate are
+/// then filtered out.
+pub fn merge_bases(repo: &Repository, a: &str, b: &str) -> Result<Vec<String>> {
+    let a = peel_to_commit(repo, a)?;
+    let b = peel_to_commit(repo, b)?;
+    if a == b {
+        return Ok(vec![a]);
+    }
+
+    let mut flags: HashMap<String, u8> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    let mut candidates = Vec::new();
+    for (hash, flag) in [(a, PARENT1), (b, PARENT2)] {
+        let time = read_commit(repo, &hash)?.time;
+        flags.insert(hash.clone(), flag);
+        queue.push(Queued { time, hash });
+    }
+    // Stop once everything left is stale: it can't lead to a better candidate.
+    while queue.iter().any(|q| flags[&q.hash] & STALE == 0) {
+        let Queued { time, hash } = queue.pop().expect("queue is not empty");
+        let mut paint = flags[&hash] & (PARENT1 | PARENT2 | STALE);
+        if paint & (PARENT1 | PARENT2) == PARENT1 | PARENT2 {
+            if flags[&hash] & RESULT == 0 {
+                *flags.get_mut(&hash).expect("flagged") |= RESULT;
+                candidates.push(Queued {
+                    time,
+                    hash: hash.clone(),
+                });
+            }
+            paint |= STALE;
+        }
+        for parent in read_commit(repo, &hash)?.parents {
+            let parent_flags = flags.entry(parent.clone()).or_insert(0);
+            if *parent_flags & paint == paint {
+                continue;
+            }
+            *parent_flags |= paint;
+            // Missing commit: must be from a part of history we don't have.
+            if have_object(repo, &parent)? {
+                let time = read_commit(repo, &parent)?.time;
+                queue.push(Queued { time, hash: parent });
+            }
+        }
+    }
+
+    // Clock skew may let a candidate be found before one of its descendants.
+    let mut parents = Vec::new();
+    for candidate in &candidates {
+        parents.extend(read_commit(repo, &candidate.hash)?.parents);
+    }
+    let below: HashSet<String> = reachable_commits(repo, &parents, &[])?
+        .into_iter()
+        .collect();
+    candidates.retain(|c| !below.contains(&c.hash));
+    candidates.sort_by(|x, y| y.cmp(x));
+    Ok(candidates.into_iter().map(|c| c.hash).collect())
+}
+
+/// Find the best common ancestor of two commits, to use as the base of a merge.
+/// Return None if the commits have no common history.
+pub fn merge_base(repo: &Repository, a: &str, b: &str) -> Result<Option<String>> {
+    Ok(merge_bases(repo, a, b)?.into_iter().next())
+}
+
+/// Add all trees and blobs reachable from a tree to the output, skipping
+/// those already in the seen set, with their path (`prefix` is the tree's).
+fn walk_tree(
+    repo: &Repository,
+    hash: &str,
+    prefix: &[u8],
+    seen: &mut HashSet<String>,
+    out: &mut Vec<(String, Option<Vec<u8>>)>,
+) -> Result<()> {
+    if !seen.insert(hash.to_owned()) {
+        return Ok(());
+    }
+    out.push((hash.to_owned(), Some(prefix.to_vec())));
+    let entries = read_tree(repo, hash)?;
+    for entry in entries {
+        let entry_hash = hex::encode(entry.hash);
+        let path = match prefix {
+            [] => entry.name,
+            _ => [prefix, b"/", &entry.name].concat(),
+        };
+        match entry.mode {
+            Mode::Dir => walk_tree(repo, &entry_hash, &path, seen, out)?,
+            Mode::SubMod => (),
+            _ => {
+                if seen.insert(entry_hash.clone()) {
+                    out.push((entry_hash, Some(path)));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Get all objects (tags, commits, trees, blobs) reachable from the tips
+/// but not from the excluded objects, in the same order as git rev-list --objects:
+/// commits, then objects given as tips, then the trees and blobs of each commit.
+/// Objects other than commits come with a name: their path for trees and blobs
+/// reached from a commit or tree, empty otherwise.
+///
+/// Like git, for trees and blobs this only considers the trees of excluded
+/// commits at the boundary, rather than all excluded history.
+pub fn reachable_objects(
+    repo: &Repository,
+    tips: &[String],
+    exclude: &[String],
+) -> Result<Vec<(String, Option<Vec<u8>>)>> {
+    let (hidden, _) = walk_commits(repo, exclude, &HashSet::new(), &Filter::default())?;
+    let hidden: HashSet<String> = hidden.into_iter().collect();
+    let (commits, boundary) = walk_commits(repo, tips, &hidden, &Filter::default())?;
+
+    // Mark objects from the boundary as seen, so they're not included.
+    // Excluded commits only count if they are parents of included ones.
+    let mut seen = HashSet::new();
+    for tip in exclude {
+        seen.insert(tip.clone());
+        match peel(repo, tip)? {
+            Some((_, ObjType::Commit)) => (),
+            Some((hash, ObjType::Tree)) => walk_tree(repo, &hash, b"", &mut seen, &mut Vec::new())?,
+            Some((hash, _)) => _ = seen.insert(hash),
+            None => (),
+        }
+    }
+    for hash in &boundary {
+        let tree = read_commit(repo, hash)?.tree;
+        walk_tree(repo, &tree, b"", &mut seen, &mut Vec::new())?;
+    }
+
+    let mut objects: Vec<(String, Option<Vec<u8>>)> =
+        commits.iter().map(|hash| (hash.clone(), None)).collect();
+    // Tags and non-commit objects given as tips.
+    for tip in tips {
+        let mut hash = tip.clone();
+        while have_object(repo, &hash)? && !seen.contains(&hash) {
+            let mut object = repo.read_object(&hash)?;
+            match object.obj_type {
+                ObjType::Tag => {
+                    seen.insert(hash.clone());
+                    objects.push((hash.clone(), Some(Vec::new())));
+                    (hash, _) = tag_target(&mut object)?;
+                }
+                ObjType::Tree => {
+                    walk_tree(repo, &hash, b"", &mut seen, &mut objects)?;
+                    break;
+                }
+                ObjType::Blob => {
+                    seen.insert(hash.clone());
+                    objects.push((hash, Some(Vec::new())));
+                    break;
+                }
+                ObjType::Commit => break,
+            }
+        }
+    }
+    for hash in commits {
+        let tree = read_commit(repo, &hash)?.tree;
+        walk_tree(repo, &tree, b"", &mut seen, &mut objects)?;
+    }
+    Ok(objects)
+}
+
+/// Like reachable_objects(), but only the hashes, in no particular order.
+/// A reachability bitmap is used if there is one, see bitmap::reachable().
+pub fn reachable_hashes(
+    repo: &Repository,
+    tips: &[String],
+    exclude: &[String],
+) -> Result<Vec<String>> {
+    if let Some(objects) = bitmap::reachable(repo, tips, exclude)? {
+        return Ok(objects);
+    }
+    Ok(reachable_objects(repo, tips, exclude)?
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .collect())
+}
@@ -0,0 +1,190 @@
+//! Removing and renaming tracked files, in the index and the worktree,
+//! like git rm and git mv.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::common::{is_under, repo_path};
+use crate::diff::{flatten_tree, worktree_side, PathList, Side};
+use crate::index::{self, IndexEntry};
+use crate::lock::lock_worktree;
+use crate::obj_read::read_commit;
+use crate::platform;
+use crate::refs;
+use crate::repository::Repository;
+
+/// Tell if two sides have the same content.
+fn same(a: Option<&Side>, b: Option<&Side>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.mode == b.mode && a.hash == b.hash,
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
+
+/// Remove a file from the worktree, then its parent directories if empty.
+fn remove_file(root: &Path, path: &[u8]) -> Result<()> {
+    let file = platform::join(root, path);
+    match fs::remove_file(&file) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e).with_context(|| format!("removing {}", file.display())),
+    }
+    for dir in file.ancestors().skip(1) {
+        if dir == root || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The "rm [--cached] [-r] [-f]" command: remove files from the index,
+/// and from the worktree unless `cached` is set.
+///
+/// Like git, refuse to lose changes unless `force` is set: files whose content
+/// in the index differs from HEAD, or (unless `cached`) whose content in the
+/// worktree differs from the index.
+pub fn rm(
+    repo: &Repository,
+    paths: &[impl AsRef<Path>],
+    cached: bool,
+    recursive: bool,
+    force: bool,
+) -> Result<()> {
+    let lock = lock_worktree(repo)?;
+    let root = repo.work_tree()?;
+    let mut entries = index::read(repo)?;
+
+    let mut remove = vec![false; entries.len()];
+    for arg in paths {
+        let arg = arg.as_ref();
+        let path = repo_path(repo, arg)?;
+        let mut found = false;
+        for (entry, remove) in entries.iter().zip(&mut remove) {
+            if !is_under(&entry.path, &path) {
+                continue;
+            }
+            if entry.path != path && !recursive {
+                bail!("not removing '{}' recursively without -r", arg.display());
+            }
+            *remove = true;
+            found = true;
+        }
+        if !found {
+            bail!("pathspec '{}' did not match any files", arg.display());
+        }
+    }
+
+    if !force {
+        let mut head = PathList::new();
+        if let Some(commit) = refs::resolve(repo, "HEAD")? {
+            flatten_tree(repo, &read_commit(repo, &commit)?.tree, b"", &mut head)?;
+        }
+        let head: HashMap<_, _> = head.into_iter().collect();
+        for (entry, _) in entries
+            .iter()
+            .zip(&remove)
+            .filter(|(e, r)| **r && e.stage == 0)
+        {
+            let indexed = Side {
+                mode: entry.mode.clone(),
+                hash: hex::encode(entry.hash),
+                file: None,
+            };
+            let in_worktree = worktree_side(repo, root, &entry.path, &indexed)?;
+            let staged = !same(head.get(&entry.path), Some(&indexed));
+            let modified = in_worktree.is_some() && !same(in_worktree.as_ref(), Some(&indexed));
+            let path = String::from_utf8_lossy(&entry.path);
+            if staged && modified {
+                bail!("'{path}' has staged content different from both the file and HEAD (use -f to force removal)");
+            }
+            if staged && !cached {
+                bail!("'{path}' has changes staged in the index (use --cached to keep the file, or -f to force removal)");
+            }
+            if modified && !cached {
+                bail!("'{path}' has local modifications (use --cached to keep the file, or -f to force removal)");
+            }
+        }
+    }
+
+    let mut kept = Vec::new();
+    for (entry, remove) in entries.drain(..).zip(remove) {
+        if !remove {
+            kept.push(entry);
+            continue;
+        }
+        // Conflicted paths have several entries: only report them once.
+        if entry.stage <= 1 {
+            println!("rm '{}'", String::from_utf8_lossy(&entry.path));
+        }
+        if !cached && entry.stage <= 1 {
+            remove_file(root, &entry.path)?;
+        }
+    }
+    index::write(repo, lock, &kept)
+}
+
+/// The "mv" command: rename a tracked file or directory,
+/// in the index and the worktree. If `to` is a directory, move into it.
+pub fn mv(repo: &Repository, from: &Path, to: &Path) -> Result<()> {
+    let lock = lock_worktree(repo)?;
+    let root = repo.work_tree()?;
+    let mut entries: Vec<IndexEntry> = index::read(repo)?;
+
+    let src = repo_path(repo, from)?;
+    let mut dst = repo_path(repo, to)?;
+    if src.is_empty() {
+        bail!("can not move the root of the worktree");
+    }
+    let path_of = |path: &[u8]| platform::join(root, path);
+    if path_of(&dst).is_dir() {
+        let name = src
+            .rsplit(|&c| c == b'/')
+            .next()
+            .expect("split has an item");
+        dst = [&dst[..], if dst.is_empty() { b"" } else { b"/" }, name].concat();
+    }
+    let error = |msg: &str| {
+        anyhow!(
+            "{msg}, source={}, destination={}",
+            String::from_utf8_lossy(&src),
+            String::from_utf8_lossy(&dst)
+        )
+    };
+
+    if fs::symlink_metadata(path_of(&src)).is_err() {
+        return Err(error("bad source"));
+    }
+    if !entries.iter().any(|e| is_under(&e.path, &src)) {
+        return Err(error("not under version control"));
+    }
+    if entries
+        .iter()
+        .any(|e| is_under(&e.path, &src) && e.stage != 0)
+    {
+        return Err(error("conflicted"));
+    }
+    if is_under(&dst, &src) {
+        return Err(error("can not move directory into itself"));
+    }
+    if fs::symlink_metadata(path_of(&dst)).is_ok() || entries.iter().any(|e| e.path == dst) {
+        return Err(error("destination exists"));
+    }
+    let parent = path_of(&dst);
+    let parent = parent.parent().expect("joined path has a parent");
+    if !parent.is_dir() {
+        return Err(error("destination directory does not exist"));
+    }
+
+    fs::rename(path_of(&src), path_of(&dst))
+        .with_context(|| format!("renaming {}", path_of(&src).display()))?;
+    for entry in &mut entries {
+        if is_under(&entry.path, &src) {
+            entry.path = [&dst[..], &entry.path[src.len()..]].concat();
+        }
+    }
+    entries.sort_by(|a, b| (&a.path, a.stage).cmp(&(&b.path, b.stage)));
+    index::write(repo, lock, &entries)
+}
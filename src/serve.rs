@@ -0,0 +1,144 @@
+//! The server side of services, run on behalf of clients over SSH (or by
+//! git with --exec), talking to them on stdin and stdout.
+//!
+//! Only upload-archive is supported, see git-upload-archive(1) and
+//! network::upload_archive() for the client side.
+
+use anyhow::{bail, Context, Result};
+use std::io;
+use std::io::prelude::*;
+
+use crate::archive::{resolve_tree_ish, write_archive, Format};
+use crate::config;
+use crate::network::{pkt_line, read_text_line, BAND_DATA, BAND_ERROR};
+use crate::refs;
+use crate::repository::Repository;
+
+/// Maximum length of data in a sideband pkt-line: the maximum length of
+/// pkt-lines (65520), minus the length and the channel.
+const MAX_BAND_DATA: usize = 65520 - 4 - 1;
+
+/// Writes data to a sideband channel, each write sending one pkt-line.
+struct Sideband<W: Write> {
+    out: W,
+    band: u8,
+}
+
+impl<W: Write> Write for Sideband<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(MAX_BAND_DATA);
+        write!(self.out, "{:04x}", len + 4 + 1)?;
+        self.out.write_all(&[self.band])?;
+        self.out.write_all(&buf[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// What a client asks to archive.
+struct ArchiveRequest {
+    format: Format,
+    prefix: String,
+    tree_ish: String,
+}
+
+impl ArchiveRequest {
+    /// Parse the arguments sent by a client, those of git archive.
+    /// Like git, only what references point to can be archived (possibly
+    /// with a path, like main:dir), unless uploadArchive.allowUnreachable
+    /// is set.
+    fn parse(repo: &Repository, args: &[String]) -> Result<Self> {
+        let mut format = Format::Tar;
+        let mut prefix = String::new();
+        let mut tree_ish = None;
+        for arg in args {
+            if let Some(name) = arg.strip_prefix("--format=") {
+                format = Format::from_name(name)?;
+            } else if let Some(value) = arg.strip_prefix("--prefix=") {
+                prefix = value.to_owned();
+            } else if arg.starts_with('-') {
+                bail!("unsupported option {arg}");
+            } else if tree_ish.is_none() {
+                tree_ish = Some(arg.to_owned());
+            } else {
+                bail!("archiving paths is not supported: {arg}");
+            }
+        }
+        let Some(tree_ish) = tree_ish else {
+            bail!("no tree-ish to archive");
+        };
+        let name = tree_ish
+            .split_once(':')
+            .map_or(&tree_ish[..], |(name, _)| name);
+        if !config::get_bool(repo, "uploadarchive.allowunreachable")?.unwrap_or(false)
+            && refs::dwim(repo, name)?.is_none()
+        {
+            bail!("no such ref: {name}");
+        }
+        Ok(ArchiveRequest {
+            format,
+            prefix,
+            tree_ish,
+        })
+    }
+}
+
+/// Serve an archive to a client of the upload-archive service: read its
+/// arguments, accept them (ACK) or not (`NACK <reason>`), then send the
+/// archive on sideband channel 1, or an error on channel 3, and a flush-pkt.
+pub fn upload_archive(
+    repo: &Repository,
+    mut input: impl Read,
+    mut output: impl Write,
+) -> Result<()> {
+    let mut args = Vec::new();
+    while let Some(line) = read_text_line(&mut input).context("reading arguments")? {
+        match line.strip_prefix("argument ") {
+            Some(arg) => args.push(arg.to_owned()),
+            None => bail!("expected an argument, got {line:?}"),
+        }
+    }
+    let request = ArchiveRequest::parse(repo, &args).and_then(|request| {
+        let resolved = resolve_tree_ish(repo, &request.tree_ish)?;
+        Ok((request, resolved))
+    });
+    let (request, (tree, commit, mtime)) = match request {
+        Ok(request) => request,
+        Err(err) => {
+            output.write_all(pkt_line(&format!("NACK {err:#}\n")).as_bytes())?;
+            output.flush()?;
+            return Err(err);
+        }
+    };
+    output.write_all(pkt_line("ACK\n").as_bytes())?;
+    output.write_all(b"0000")?;
+
+    let band = Sideband {
+        out: &mut output,
+        band: BAND_DATA,
+    };
+    let mut data = io::BufWriter::with_capacity(MAX_BAND_DATA, band);
+    let prefix = &request.prefix;
+    let result = write_archive(
+        repo,
+        &mut data,
+        request.format,
+        &tree,
+        commit.as_deref(),
+        mtime,
+        prefix,
+    )
+    .and_then(|()| Ok(data.flush()?))
+    .with_context(|| format!("archiving {}", request.tree_ish));
+    drop(data);
+    if let Err(err) = &result {
+        let message = format!("{}{err:#}\n", char::from(BAND_ERROR));
+        output.write_all(pkt_line(&message).as_bytes())?;
+    }
+    output.write_all(b"0000")?;
+    output.flush()?;
+    result
+}
@@ -0,0 +1,56 @@
+//! Shallow repositories: commits whose parents were not fetched,
+//! listed in .git/shallow, see gitformat-shallow(5).
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io;
+
+use crate::repository::Repository;
+
+/// Read the list of shallow commits, empty if the repository is not shallow.
+pub fn list(repo: &Repository) -> Result<Vec<String>> {
+    let path = repo.common_dir().join("shallow");
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text.lines().map(String::from).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).context("reading .git/shallow"),
+    }
+}
+
+/// Tell if a commit is a shallow boundary, ie its parents should be ignored.
+///
+/// Note: this uses the list of shallow commits as it was the first time
+/// this function was called (for the repository), so only use it after any
+/// update().
+pub fn is_shallow(repo: &Repository, hash: &str) -> Result<bool> {
+    let cache = &repo.cache().shallow;
+    if let Some(shallow) = cache.get() {
+        return Ok(shallow.contains(hash));
+    }
+    let shallow = list(repo)?.into_iter().collect();
+    Ok(cache.get_or_init(|| shallow).contains(hash))
+}
+
+/// Update the list of shallow commits, adding and removing the given commits.
+pub fn update(repo: &Repository, shallow: &[String], unshallow: &[String]) -> Result<()> {
+    let mut commits: Vec<String> = list(repo)?
+        .into_iter()
+        .chain(shallow.iter().cloned())
+        .filter(|h| !unshallow.contains(h))
+        .collect();
+    commits.sort_unstable();
+    commits.dedup();
+
+    let path = repo.common_dir().join("shallow");
+    if commits.is_empty() {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(e).context("removing .git/shallow")
+            }
+            _ => Ok(()),
+        }
+    } else {
+        let text: String = commits.iter().map(|h| format!("{h}\n")).collect();
+        fs::write(path, text).context("writing .git/shallow")
+    }
+}
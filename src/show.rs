@@ -0,0 +1,138 @@
+//! Showing objects in a human-readable form, like git show.
+
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::io;
+use std::io::prelude::*;
+use std::str;
+
+use crate::abbrev::shorten;
+use crate::commit::split_ident;
+use crate::date;
+use crate::diff::{detect_renames, diff_trees, print_diff, rename_score, DiffFormat};
+use crate::notes;
+use crate::obj_read::{read_commit, read_tree, ObjReader};
+use crate::obj_type::ObjType;
+use crate::repository::Repository;
+use crate::tag::Tag;
+use crate::tree_entry::Mode;
+
+/// Print a commit: header and message, then the diff against its parent
+/// (there is no combined diff for merges).
+fn show_commit(
+    repo: &Repository,
+    hash: &str,
+    format: &DiffFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    let commit = read_commit(repo, hash)?;
+    let colors = &format.colors;
+    writeln!(out, "{}commit {hash}{}", colors.commit, colors.reset)?;
+    if commit.parents.len() > 1 {
+        let parents = commit
+            .parents
+            .iter()
+            .map(|p| shorten(repo, p))
+            .collect::<Result<Vec<_>>>()?;
+        writeln!(out, "Merge: {}", parents.join(" "))?;
+    }
+    writeln!(out, "Author: {}", commit.author)?;
+    let date = date::format(commit.author_time, &commit.author_zone);
+    writeln!(out, "Date:   {date}\n")?;
+    let message = commit
+        .message
+        .strip_suffix(b"\n")
+        .unwrap_or(&commit.message);
+    for line in message.split(|&c| c == b'\n') {
+        out.write_all(b"    ")?;
+        out.write_all(line)?;
+        out.write_all(b"\n")?;
+    }
+    notes::show(repo, hash, out)?;
+
+    let parent_tree = match &commit.parents[..] {
+        [] => None,
+        [parent] => Some(read_commit(repo, parent)?.tree),
+        _ => {
+            writeln!(out)?;
+            return Ok(());
+        }
+    };
+    let mut changes = diff_trees(repo, parent_tree.as_deref(), &commit.tree)?;
+    if let Some(min_score) = rename_score(repo)? {
+        changes = detect_renames(repo, changes, min_score)?;
+    }
+    if !changes.is_empty() {
+        writeln!(out)?;
+        print_diff(repo, &changes, format, out)?;
+    }
+    Ok(())
+}
+
+/// Print the header and message of an annotated tag, returning the object it points to.
+fn show_tag(repo: &Repository, object: ObjReader, out: &mut impl Write) -> Result<String> {
+    let tag = Tag::from_object(repo, object)?;
+    writeln!(out, "tag {}", tag.name)?;
+    if let Some(tagger) = &tag.tagger {
+        let (ident, time, zone) = split_ident(tagger);
+        writeln!(out, "Tagger: {ident}")?;
+        writeln!(out, "Date:   {}", date::format(time, zone))?;
+    }
+    writeln!(out)?;
+    out.write_all(tag.message.as_deref().unwrap_or_default())?;
+    Ok(tag.object)
+}
+
+/// Print objects like git show: commits with their diff, annotated tags followed
+/// by the object they point to, tree entries (after the name given for the tree),
+/// and blob contents as is. Each object comes with the name used to designate it.
+///
+/// Like git, commits are shown only once, with their diff in `format`.
+pub fn show_objects(
+    repo: &Repository,
+    objects: &[(String, String)],
+    format: &DiffFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut shown_one = false;
+    let mut commits_shown = HashSet::new();
+    for (name, hash) in objects {
+        let mut hash = hash.clone();
+        loop {
+            let mut object = repo
+                .read_object(&hash)
+                .with_context(|| format!("opening object {hash}"))?;
+            let obj_type = object.obj_type.clone();
+            if obj_type == ObjType::Commit && !commits_shown.insert(hash.clone()) {
+                break;
+            }
+            // Blobs are shown as they are, without separators.
+            if obj_type != ObjType::Blob && shown_one {
+                writeln!(out)?;
+            }
+            match obj_type {
+                ObjType::Blob => {
+                    io::copy(&mut object, out).with_context(|| format!("reading blob {hash}"))?;
+                }
+                ObjType::Tree => {
+                    writeln!(out, "tree {name}\n")?;
+                    for entry in read_tree(repo, &hash)? {
+                        out.write_all(&entry.name)?;
+                        let slash = if entry.mode == Mode::Dir { "/" } else { "" };
+                        writeln!(out, "{slash}")?;
+                    }
+                }
+                ObjType::Commit => show_commit(repo, &hash, format, out)?,
+                ObjType::Tag => {
+                    hash = show_tag(repo, object, out)
+                        .with_context(|| format!("reading tag {hash}"))?;
+                    shown_one = true;
+                    continue;
+                }
+            }
+            shown_one |= obj_type != ObjType::Blob;
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,207 @@
+//! Signing commits and tags with gpg or ssh-keygen, and checking signatures.
+//!
+//! See gitformat-signature(5). The format of new signatures is chosen by
+//! gpg.format ("openpgp", the default, or "ssh"; "x509" is not supported),
+//! the programs by gpg.program and gpg.ssh.program, and the key by
+//! user.signingKey. SSH signatures are checked against the keys in
+//! gpg.ssh.allowedSignersFile, see ssh-keygen(1) "ALLOWED SIGNERS".
+
+use anyhow::{bail, Context, Result};
+use rand::Rng;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::common::run;
+use crate::config;
+use crate::repository::Repository;
+
+/// First lines of the signatures we know about, with their format.
+const SIGNATURE_STARTS: [(&str, Format); 3] = [
+    ("-----BEGIN PGP SIGNATURE-----", Format::OpenPgp),
+    ("-----BEGIN PGP MESSAGE-----", Format::OpenPgp),
+    ("-----BEGIN SSH SIGNATURE-----", Format::Ssh),
+];
+
+/// The kinds of signatures.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    OpenPgp,
+    Ssh,
+}
+
+impl Format {
+    /// The format for new signatures, from gpg.format.
+    fn configured(repo: &Repository) -> Result<Self> {
+        match config::get(repo, "gpg.format")?.as_deref() {
+            None | Some("openpgp") => Ok(Format::OpenPgp),
+            Some("ssh") => Ok(Format::Ssh),
+            Some(other) => bail!("unsupported value for gpg.format: {other}"),
+        }
+    }
+
+    /// The program handling signatures of this format.
+    fn program(self, repo: &Repository) -> Result<String> {
+        let (key, default) = match self {
+            Format::OpenPgp => ("gpg.program", "gpg"),
+            Format::Ssh => ("gpg.ssh.program", "ssh-keygen"),
+        };
+        Ok(config::get(repo, key)?.unwrap_or_else(|| default.to_owned()))
+    }
+}
+
+/// Tell if a line starts a signature, and which kind.
+pub fn signature_start(line: &[u8]) -> Option<Format> {
+    SIGNATURE_STARTS
+        .iter()
+        .find(|(start, _)| line.starts_with(start.as_bytes()))
+        .map(|&(_, format)| format)
+}
+
+/// A temporary file, removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(content: &[u8]) -> Result<Self> {
+        let mut tmp_rand = [0u8; 20];
+        rand::rng().fill(&mut tmp_rand);
+        let path = env::temp_dir().join(format!("tmpsig{}", hex::encode(tmp_rand)));
+        fs::write(&path, content).with_context(|| format!("writing {}", path.display()))?;
+        Ok(TempFile(path))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Choose the key to sign with: the one given if any, otherwise
+/// user.signingKey, otherwise for OpenPGP the identity of the committer,
+/// as `Name <email>`.
+pub fn signing_key(repo: &Repository, key: Option<&str>, committer: &str) -> Result<String> {
+    if let Some(key) = key.filter(|key| !key.is_empty()) {
+        return Ok(key.to_owned());
+    }
+    if let Some(key) = config::get(repo, "user.signingKey")? {
+        return Ok(key);
+    }
+    match Format::configured(repo)? {
+        Format::OpenPgp => Ok(committer.to_owned()),
+        Format::Ssh => bail!("user.signingKey needs to be set for ssh signing"),
+    }
+}
+
+/// Sign a payload with the given key, returning the (armored) signature.
+/// For SSH, the key is the path of a private key, or of a public key whose
+/// private key is in ssh-agent.
+pub fn sign(repo: &Repository, payload: &[u8], key: &str) -> Result<String> {
+    let format = Format::configured(repo)?;
+    let program = format.program(repo)?;
+    let signature = match format {
+        Format::OpenPgp => {
+            let output = run(
+                Command::new(&program).args(["--status-fd=2", "-bsau", key]),
+                payload,
+            )?;
+            let status = String::from_utf8_lossy(&output.stderr);
+            let created = status
+                .lines()
+                .any(|line| line.starts_with("[GNUPG:] SIG_CREATED "));
+            if !output.status.success() || !created {
+                bail!("{program} failed to sign the data:\n{status}");
+            }
+            output.stdout
+        }
+        Format::Ssh => {
+            let file = TempFile::new(payload)?;
+            let output = run(
+                Command::new(&program)
+                    .args(["-Y", "sign", "-n", "git", "-f", key])
+                    .arg(&file.0),
+                b"",
+            )?;
+            let sig_file = TempFile(file.0.with_extension("sig"));
+            if !output.status.success() {
+                bail!(
+                    "{program} failed to sign the data:\n{}",
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+            fs::read(&sig_file.0).with_context(|| format!("reading {}", sig_file.0.display()))?
+        }
+    };
+    // Like git, carriage returns are dropped.
+    let signature = String::from_utf8(signature).context("signature is not UTF-8")?;
+    Ok(signature.replace('\r', ""))
+}
+
+/// The result of checking a signature.
+pub struct Verification {
+    pub good: bool,
+    /// What the program said about the signature, including who made it
+    pub output: String,
+}
+
+/// Check the signature of a payload.
+pub fn verify(repo: &Repository, payload: &[u8], signature: &str) -> Result<Verification> {
+    let format = signature_start(signature.as_bytes()).context("unknown signature format")?;
+    let program = format.program(repo)?;
+    let sig_file = TempFile::new(signature.as_bytes())?;
+    match format {
+        Format::OpenPgp => {
+            let output = run(
+                Command::new(&program)
+                    .args(["--keyid-format=long", "--status-fd=1", "--verify"])
+                    .arg(&sig_file.0)
+                    .arg("-"),
+                payload,
+            )?;
+            let status = String::from_utf8_lossy(&output.stdout);
+            let good = output.status.success()
+                && status
+                    .lines()
+                    .any(|line| line.starts_with("[GNUPG:] GOODSIG "));
+            Ok(Verification {
+                good,
+                output: String::from_utf8_lossy(&output.stderr).into_owned(),
+            })
+        }
+        Format::Ssh => {
+            let Some(allowed) = config::get(repo, "gpg.ssh.allowedSignersFile")? else {
+                bail!("gpg.ssh.allowedSignersFile needs to be configured for ssh signature verification");
+            };
+            let output = run(
+                Command::new(&program)
+                    .args(["-Y", "find-principals", "-f", &allowed, "-s"])
+                    .arg(&sig_file.0),
+                b"",
+            )?;
+            let principals = String::from_utf8_lossy(&output.stdout).into_owned();
+            let mut last_output = String::from("No principal matched.\n");
+            for principal in principals.lines().filter(|_| output.status.success()) {
+                let output = run(
+                    Command::new(&program)
+                        .args(["-Y", "verify", "-n", "git", "-f", &allowed, "-I", principal])
+                        .arg("-s")
+                        .arg(&sig_file.0),
+                    payload,
+                )?;
+                last_output = String::from_utf8_lossy(&output.stdout).into_owned();
+                last_output += &String::from_utf8_lossy(&output.stderr);
+                if output.status.success() {
+                    return Ok(Verification {
+                        good: true,
+                        output: last_output,
+                    });
+                }
+            }
+            Ok(Verification {
+                good: false,
+                output: last_output,
+            })
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! The state of the index and worktree, like git status --porcelain=v2: for
+//! each path with changes between HEAD and the index or between the index
+//! and the worktree, a line with both sides of those changes, then untracked
+//! files.
+
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::io::prelude::*;
+
+use crate::common::{quote_c, relative};
+use crate::config;
+use crate::diff::{
+    detect_renames, diff_to_worktree, diff_tree_to_index, kind, status_rename_score, worktree_side,
+    Change, Side, MAX_SCORE,
+};
+use crate::ignore::Ignores;
+use crate::index::{self, IndexEntry};
+use crate::json;
+use crate::ls_files::untracked;
+use crate::obj_read::read_commit;
+use crate::platform;
+use crate::refs;
+use crate::repository::Repository;
+use crate::tree_entry::Mode;
+
+/// Which untracked files to show, like git's --untracked-files.
+#[derive(Clone, Copy, PartialEq)]
+pub enum UntrackedFiles {
+    No,
+    /// Files, but only the directory for directories with no tracked files
+    Normal,
+    All,
+}
+
+/// Parse the mode of --untracked-files, or of status.showUntrackedFiles
+/// (where booleans are accepted too).
+pub fn parse_untracked_files(value: &str) -> Result<UntrackedFiles> {
+    match value {
+        "no" => Ok(UntrackedFiles::No),
+        "normal" => Ok(UntrackedFiles::Normal),
+        "all" => Ok(UntrackedFiles::All),
+        _ => match config::parse_bool("status.showuntrackedfiles", value) {
+            Ok(true) => Ok(UntrackedFiles::Normal),
+            Ok(false) => Ok(UntrackedFiles::No),
+            Err(_) => bail!("invalid untracked files mode '{value}'"),
+        },
+    }
+}
+
+/// Options of the status command.
+pub struct StatusOptions {
+    /// End entries with NUL rather than newlines, and don't quote paths
+    pub nul: bool,
+    /// Which untracked files to show: by default, from status.showUntrackedFiles
+    /// or normal
+    pub untracked: Option<UntrackedFiles>,
+}
+
+/// An entry of the output: its kind ("1" for ordinary changes, "2" for
+/// renames, "u" for unmerged paths, "?" for untracked files), then fields
+/// with their names in git's documentation, the path, and the original path
+/// for renames.
+struct Entry {
+    kind: &'static str,
+    fields: Vec<(&'static str, String)>,
+    path: Vec<u8>,
+    orig_path: Option<Vec<u8>>,
+}
+
+impl Entry {
+    /// Write the entry as a line (with `nul`, ended by NUL and with paths
+    /// separated by NUL, instead of a tab, and not quoted), or as a JSON
+    /// record with --format=json.
+    fn write(&self, nul: bool, out: &mut impl Write) -> io::Result<()> {
+        if json::enabled() {
+            let mut fields = vec![("kind", self.kind.into())];
+            fields.extend(
+                self.fields
+                    .iter()
+                    .map(|(name, value)| (*name, value.as_str().into())),
+            );
+            fields.push(("path", (&self.path[..]).into()));
+            if let Some(orig_path) = &self.orig_path {
+                fields.push(("origPath", (&orig_path[..]).into()));
+            }
+            return json::write_record(out, &fields);
+        }
+        let write_path = |path: &[u8], out: &mut dyn Write| match quote_c(path) {
+            Some(quoted) if !nul => out.write_all(quoted.as_bytes()),
+            _ => out.write_all(path),
+        };
+        out.write_all(self.kind.as_bytes())?;
+        for (_, value) in &self.fields {
+            write!(out, " {value}")?;
+        }
+        out.write_all(b" ")?;
+        write_path(&self.path, out)?;
+        if let Some(orig_path) = &self.orig_path {
+            out.write_all(if nul { b"\0" } else { b"\t" })?;
+            write_path(orig_path, out)?;
+        }
+        out.write_all(if nul { b"\0" } else { b"\n" })
+    }
+}
+
+/// A mode in octal on 6 digits, 000000 if there is nothing.
+fn mode_field(mode: Option<&Mode>) -> String {
+    format!("{:0>6}", mode.map_or("0", Mode::to_str))
+}
+
+/// The letter for a change: A for added, D for deleted, R for renamed, T
+/// for a change of type (eg from a file to a symlink), M for modified.
+fn change_code(change: &Change) -> char {
+    match (&change.old, &change.new) {
+        _ if change.renamed.is_some() => 'R',
+        (None, _) => 'A',
+        (_, None) => 'D',
+        (Some(old), Some(new)) if kind(&old.mode) != kind(&new.mode) => 'T',
+        _ => 'M',
+    }
+}
+
+/// Get the entry for an unmerged path, from its entries in the index.
+fn unmerged_entry(
+    repo: &Repository,
+    path: &[u8],
+    stages: &[Option<&IndexEntry>; 3],
+) -> Result<Entry> {
+    let xy = match stages.each_ref().map(Option::is_some) {
+        [true, false, false] => "DD",
+        [false, true, false] => "AU",
+        [true, true, false] => "UD",
+        [false, false, true] => "UA",
+        [true, false, true] => "DU",
+        [false, true, true] => "AA",
+        _ => "UU",
+    };
+    let zero = repo.hash_algo()?.zero_hash();
+    let mut fields = vec![("XY", xy.to_owned()), ("sub", "N...".to_owned())];
+    for (i, name) in ["m1", "m2", "m3"].into_iter().enumerate() {
+        fields.push((name, mode_field(stages[i].map(|entry| &entry.mode))));
+    }
+    let indexed = stages
+        .iter()
+        .rev()
+        .flatten()
+        .next()
+        .expect("one stage exists");
+    let side = Side {
+        mode: indexed.mode.clone(),
+        hash: hex::encode(indexed.hash),
+        file: None,
+    };
+    let worktree = worktree_side(repo, repo.work_tree()?, path, &side)?;
+    fields.push(("mW", mode_field(worktree.as_ref().map(|side| &side.mode))));
+    for (i, name) in ["h1", "h2", "h3"].into_iter().enumerate() {
+        let hash = stages[i].map_or_else(|| zero.to_owned(), |entry| hex::encode(entry.hash));
+        fields.push((name, hash));
+    }
+    Ok(Entry {
+        kind: "u",
+        fields,
+        path: path.to_vec(),
+        orig_path: None,
+    })
+}
+
+/// Get the entry for a path with changes in the index (`staged`) or in the
+/// worktree (`unstaged`), with what it is in the index if anything.
+fn changed_entry(
+    repo: &Repository,
+    path: &[u8],
+    staged: Option<&Change>,
+    unstaged: Option<&Change>,
+    indexed: Option<&IndexEntry>,
+) -> Result<Entry> {
+    let zero = repo.hash_algo()?.zero_hash();
+    let xy = [staged, unstaged].map(|change| change.map_or('.', change_code));
+    // What HEAD has is the old side of the change to the index if any, else
+    // what the index has.
+    let head = match staged {
+        Some(change) => change
+            .old
+            .as_ref()
+            .map(|side| (&side.mode, side.hash.clone())),
+        None => indexed.map(|entry| (&entry.mode, hex::encode(entry.hash))),
+    };
+    let index = indexed.map(|entry| (&entry.mode, hex::encode(entry.hash)));
+    let worktree_mode = match unstaged {
+        Some(change) => change.new.as_ref().map(|side| &side.mode),
+        None => index.as_ref().map(|(mode, _)| *mode),
+    };
+    let modes = [
+        head.as_ref().map(|(mode, _)| *mode),
+        index.as_ref().map(|(mode, _)| *mode),
+    ];
+    // We can't look inside submodules, so they are only shown when the
+    // commit they are at changed.
+    let sub = match modes.iter().flatten().any(|&mode| *mode == Mode::SubMod) {
+        true => "SC..",
+        false => "N...",
+    };
+    let hash =
+        |side: Option<(&Mode, String)>| side.map_or_else(|| zero.to_owned(), |(_, hash)| hash);
+    let mut fields = vec![
+        ("XY", xy.iter().collect()),
+        ("sub", sub.to_owned()),
+        ("mH", mode_field(modes[0])),
+        ("mI", mode_field(modes[1])),
+        ("mW", mode_field(worktree_mode)),
+        ("hH", hash(head)),
+        ("hI", hash(index)),
+    ];
+    let renamed = staged.and_then(|change| change.renamed.as_ref());
+    if let Some(rename) = renamed {
+        fields.push(("Xscore", format!("R{}", rename.score * 100 / MAX_SCORE)));
+    }
+    Ok(Entry {
+        kind: if renamed.is_some() { "2" } else { "1" },
+        fields,
+        path: path.to_vec(),
+        orig_path: renamed.map(|rename| rename.from.clone()),
+    })
+}
+
+/// List untracked files which are not ignored, sorted, with directories that
+/// have no tracked files instead of their contents unless `all` is set.
+fn untracked_paths(repo: &Repository, entries: &[IndexEntry], all: bool) -> Result<Vec<Vec<u8>>> {
+    let tracked = entries.iter().map(|e| &e.path[..]).collect();
+    let mut found = Vec::new();
+    let mut ignores = Ignores::new(repo)?;
+    untracked(
+        repo.work_tree()?,
+        b"",
+        &tracked,
+        Some(&mut ignores),
+        false,
+        &mut found,
+    )?;
+    let mut paths: Vec<Vec<u8>> = found
+        .into_iter()
+        .filter(|(_, ignored)| !ignored)
+        .map(|(path, _)| path)
+        .collect();
+    if !all {
+        let mut tracked_dirs = HashSet::new();
+        for entry in entries {
+            for (end, _) in entry.path.iter().enumerate().filter(|&(_, &c)| c == b'/') {
+                tracked_dirs.insert(&entry.path[..=end]);
+            }
+        }
+        for path in &mut paths {
+            let top = path.iter().enumerate().find(|&(end, &c)| {
+                c == b'/' && end + 1 < path.len() && !tracked_dirs.contains(&path[..=end])
+            });
+            if let Some((end, _)) = top {
+                path.truncate(end + 1);
+            }
+        }
+    }
+    paths.sort_unstable();
+    paths.dedup();
+    Ok(paths)
+}
+
+/// The "status --porcelain=v2" command - partial implementation: no branch
+/// headers, and no ignored files. Renames between HEAD and the index are
+/// detected unless status.renames (or else diff.renames) is false.
+pub fn status(repo: &Repository, options: &StatusOptions) -> Result<()> {
+    let head_tree = match refs::resolve(repo, "HEAD")? {
+        Some(head) => Some(read_commit(repo, &head)?.tree),
+        None => None,
+    };
+    let mut staged = diff_tree_to_index(repo, head_tree.as_deref())?;
+    if let Some(min_score) = status_rename_score(repo)? {
+        staged = detect_renames(repo, staged, min_score)?;
+    }
+    let unstaged = diff_to_worktree(repo, None)?;
+    let entries = index::read(repo)?;
+
+    // For each path, the changes in the index and in the worktree.
+    let mut changes: BTreeMap<&[u8], (Option<&Change>, Option<&Change>)> = BTreeMap::new();
+    for change in &staged {
+        changes.entry(&change.path).or_default().0 = Some(change);
+    }
+    for change in &unstaged {
+        changes.entry(&change.path).or_default().1 = Some(change);
+    }
+    let mut stages: HashMap<&[u8], [Option<&IndexEntry>; 3]> = HashMap::new();
+    let mut indexed = HashMap::new();
+    for entry in &entries {
+        match entry.stage {
+            0 => {
+                indexed.insert(&entry.path[..], entry);
+            }
+            stage => stages.entry(&entry.path).or_default()[usize::from(stage) - 1] = Some(entry),
+        }
+    }
+
+    // Like git, paths are shown relative to the current directory, except
+    // with -z.
+    let prefix = platform::bytes(repo.prefix().as_os_str());
+    let relative = |path: &[u8]| match relative(path, &prefix) {
+        _ if options.nul => path.to_vec(),
+        path if path.is_empty() => b"./".to_vec(),
+        path => path,
+    };
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    for (path, (staged, unstaged)) in changes {
+        let mut entry = match stages.get(path) {
+            Some(stages) => unmerged_entry(repo, path, stages)?,
+            None => changed_entry(repo, path, staged, unstaged, indexed.get(path).copied())?,
+        };
+        entry.path = relative(&entry.path);
+        entry.orig_path = entry.orig_path.as_deref().map(relative);
+        entry.write(options.nul, &mut stdout)?;
+    }
+    let untracked = match options.untracked {
+        Some(untracked) => untracked,
+        None => match config::get(repo, "status.showuntrackedfiles")? {
+            Some(value) => parse_untracked_files(&value)?,
+            None => UntrackedFiles::Normal,
+        },
+    };
+    if untracked != UntrackedFiles::No {
+        for path in untracked_paths(repo, &entries, untracked == UntrackedFiles::All)? {
+            let entry = Entry {
+                kind: "?",
+                fields: Vec::new(),
+                path: relative(&path),
+                orig_path: None,
+            };
+            entry.write(options.nul, &mut stdout)?;
+        }
+    }
+    Ok(stdout.flush()?)
+}
@@ -0,0 +1,161 @@
+//! Parsing and writing annotated tag objects, and finding their signature.
+//!
+//! See git-mktag(1) for the format, and the checks done on new tags.
+
+use anyhow::{bail, Context, Result};
+use std::str;
+
+use crate::obj_read::{ObjReader, MAX_METADATA_SIZE};
+use crate::obj_type::ObjType;
+use crate::object_id::is_hash;
+use crate::refs::is_valid_name;
+use crate::repository::Repository;
+use crate::sign::signature_start;
+
+/// The parsed content of an annotated tag.
+pub struct Tag {
+    /// The tagged object
+    pub object: String,
+    pub obj_type: ObjType,
+    /// Name of the tag, usually the same as the reference under refs/tags/
+    pub name: String,
+    /// Tagger name, email, date and time zone, as `Name <email> 1234567890 +0100`
+    /// (absent from some very old tags)
+    pub tagger: Option<String>,
+    /// What follows the blank line after the headers, including any signature,
+    /// or None if there is no blank line
+    pub message: Option<Vec<u8>>,
+}
+
+impl Tag {
+    /// Parse a tag from an object reader.
+    pub fn from_object(repo: &Repository, object: ObjReader) -> Result<Self> {
+        if object.obj_type != ObjType::Tag {
+            bail!("not a tag");
+        }
+        let data = object
+            .read_to_vec(MAX_METADATA_SIZE)
+            .context("reading tag")?;
+        Self::parse(repo, &data, false)
+    }
+
+    /// Parse the content of a tag object.
+    ///
+    /// The object, type and tag headers must come first and in this order.
+    /// When `strict`, like git mktag, the tagger must follow, with a valid
+    /// identity, the tag name must be a valid reference name and there must
+    /// be no other headers; otherwise later headers are ignored.
+    pub fn parse(repo: &Repository, data: &[u8], strict: bool) -> Result<Self> {
+        let (headers, message) = match data.windows(2).position(|w| w == b"\n\n") {
+            Some(end) => (&data[..=end], Some(data[end + 2..].to_vec())),
+            None => (data, None),
+        };
+        let Some(headers) = headers.strip_suffix(b"\n") else {
+            bail!("unterminated header");
+        };
+        let headers = str::from_utf8(headers).context("tag headers are not UTF-8")?;
+        let mut lines = headers.split('\n');
+        let mut header = |key: &str| {
+            let line = lines.next().unwrap_or_default();
+            line.strip_prefix(key)
+                .and_then(|rest| rest.strip_prefix(' '))
+                .map(str::to_owned)
+        };
+
+        let Some(object) = header("object").filter(|hash| is_hash(repo, hash)) else {
+            bail!("invalid format - expected 'object' line");
+        };
+        let Some(obj_type) = header("type") else {
+            bail!("invalid format - expected 'type' line");
+        };
+        let Ok(obj_type) = ObjType::from_bytes(obj_type.as_bytes()) else {
+            bail!("invalid 'type' value");
+        };
+        let Some(name) = header("tag") else {
+            bail!("invalid format - expected 'tag' line");
+        };
+        let tagger = header("tagger");
+        if strict {
+            if !is_valid_name(&name) {
+                bail!("invalid 'tag' name: {name}");
+            }
+            let Some(tagger) = &tagger else {
+                bail!("invalid format - expected 'tagger' line");
+            };
+            check_ident(tagger)?;
+            if lines.next().is_some() {
+                bail!("invalid format - extra header(s) after 'tagger'");
+            }
+        }
+        Ok(Tag {
+            object,
+            obj_type,
+            name,
+            tagger,
+            message,
+        })
+    }
+
+    /// Give the content of the tag object.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = format!(
+            "object {}\ntype {}\ntag {}\n",
+            self.object,
+            self.obj_type.to_str(),
+            self.name
+        );
+        if let Some(tagger) = &self.tagger {
+            out.push_str(&format!("tagger {tagger}\n"));
+        }
+        let mut out = out.into_bytes();
+        if let Some(message) = &self.message {
+            out.push(b'\n');
+            out.extend_from_slice(message);
+        }
+        out
+    }
+}
+
+/// Split the content of a tag into what was signed and the signature at the
+/// end of its message, if there is one.
+pub fn split_signature(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let mut start = None;
+    let mut pos = 0;
+    for line in data.split_inclusive(|&c| c == b'\n') {
+        if signature_start(line).is_some() {
+            start = Some(pos);
+        }
+        pos += line.len();
+    }
+    start.map(|start| data.split_at(start))
+}
+
+/// Check an identity with a date: `Name <email> 1234567890 +0100`.
+fn check_ident(ident: &str) -> Result<()> {
+    let Some((name, rest)) = ident.split_once('<') else {
+        bail!("invalid tagger line - missing email");
+    };
+    if !name.ends_with(' ') {
+        bail!("invalid tagger line - missing space before email");
+    }
+    let Some((email, date)) = rest.split_once('>') else {
+        bail!("invalid tagger line - bad email");
+    };
+    if email.contains(['<', '\n']) {
+        bail!("invalid tagger line - bad email");
+    }
+    let Some(date) = date.strip_prefix(' ') else {
+        bail!("invalid tagger line - missing space before date");
+    };
+    let (time, zone) = date.split_once(' ').unwrap_or((date, ""));
+    if time.is_empty() || !time.bytes().all(|c| c.is_ascii_digit()) {
+        bail!("invalid tagger line - bad date");
+    }
+    let zone_ok = zone.len() == 5
+        && zone.starts_with(['+', '-'])
+        && zone[1..].bytes().all(|c| c.is_ascii_digit());
+    if !zone_ok {
+        bail!("invalid tagger line - bad time zone");
+    }
+    Ok(())
+}
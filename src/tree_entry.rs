@@ -1,20 +1,26 @@
 //! Entries in tree objects
 
-use anyhow::{bail, Context, Result};
-use std::ffi::OsStr;
+use anyhow::{anyhow, bail, Context, Result};
+use std::cmp::Ordering;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
-use std::os::unix;
-use std::os::unix::ffi::OsStrExt;
-use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
+use std::str;
 
-use crate::obj_read::ObjReader;
+use crate::abbrev::shorten;
+use crate::attributes;
+use crate::filter;
+use crate::json;
+use crate::obj_read::{ObjReader, MAX_BLOB_SIZE};
 use crate::obj_type::ObjType;
+use crate::object_id::{HashAlgo, ObjectId};
+use crate::platform;
+use crate::repository::Repository;
 use crate::tree_read::TreeReader;
 
 /// Possible modes (types) for tree entries
+#[derive(Clone, PartialEq, Eq)]
 pub enum Mode {
     Dir,
     File,
@@ -39,10 +45,33 @@ impl Mode {
         }
     }
 
+    /// Get mode from the numeric value used in the index.
+    pub fn from_u32(mode: u32) -> Result<Self> {
+        match mode {
+            0o40000 => Ok(Mode::Dir),
+            0o100644 => Ok(Mode::File),
+            0o100755 => Ok(Mode::Exe),
+            0o120000 => Ok(Mode::SymLink),
+            0o160000 => Ok(Mode::SubMod),
+            m => bail!("unknown mode {m:o}"),
+        }
+    }
+
+    /// Give the numeric value used in the index.
+    pub fn to_u32(&self) -> u32 {
+        match self {
+            Mode::Dir => 0o40000,
+            Mode::File => 0o100644,
+            Mode::Exe => 0o100755,
+            Mode::SymLink => 0o120000,
+            Mode::SubMod => 0o160000,
+        }
+    }
+
     /// Determine mode based on filesystem metadata.
     pub fn from_metadata(meta: &fs::Metadata) -> Result<Self> {
         if meta.is_file() {
-            if meta.permissions().mode() & 0o111 != 0 {
+            if platform::is_executable(meta) {
                 Ok(Mode::Exe)
             } else {
                 Ok(Mode::File)
@@ -56,6 +85,29 @@ impl Mode {
         }
     }
 
+    /// Determine the mode of a file in the worktree, given the mode it has in
+    /// the index if any: without core.filemode, the executable bit is taken
+    /// from the index (new files are not executable), and without core.symlinks,
+    /// a file is a symlink if the index says so.
+    pub fn from_worktree(
+        repo: &Repository,
+        meta: &fs::Metadata,
+        indexed: Option<&Mode>,
+    ) -> Result<Self> {
+        let mode = Mode::from_metadata(meta)?;
+        if !matches!(mode, Mode::File | Mode::Exe) {
+            return Ok(mode);
+        }
+        Ok(match indexed {
+            Some(Mode::SymLink) if !platform::symlinks(repo)? => Mode::SymLink,
+            Some(indexed @ (Mode::File | Mode::Exe)) if !platform::filemode(repo)? => {
+                indexed.clone()
+            }
+            _ if !platform::filemode(repo)? => Mode::File,
+            _ => mode,
+        })
+    }
+
     /// Give the string to be used in tree objects.
     pub fn to_str(&self) -> &'static str {
         match self {
@@ -79,51 +131,207 @@ impl Mode {
     }
 }
 
+/// Check that a name from a tree can be written to the filesystem, like git's
+/// verify_path(): it must be a single path component, other than `.`, `..`
+/// and `.git` (in any case), so that untrusted trees can't write outside the
+/// worktree or into the repository.
+pub fn verify_name(name: &[u8]) -> Result<()> {
+    let display = String::from_utf8_lossy(name);
+    if name.is_empty() || name.contains(&b'/') || name.contains(&b'\0') {
+        bail!("invalid path '{display}'");
+    }
+    if name == b"." || name == b".." || name.eq_ignore_ascii_case(b".git") {
+        bail!("invalid path '{display}'");
+    }
+    Ok(())
+}
+
+/// Check that all components of a path (separated by slashes) are valid
+/// names, see verify_name().
+pub fn verify_path(path: &[u8]) -> Result<()> {
+    if path
+        .split(|&c| c == b'/')
+        .any(|name| verify_name(name).is_err())
+    {
+        bail!("invalid path '{}'", String::from_utf8_lossy(path));
+    }
+    Ok(())
+}
+
+/// Fail if a directory leading to a path (below the base directory) is a
+/// symlink: writing the path would then follow it, possibly outside the worktree.
+pub fn check_leading_dirs(base_path: &Path, path: &[u8]) -> Result<()> {
+    let mut dir = base_path.to_owned();
+    let mut names: Vec<&[u8]> = path.split(|&c| c == b'/').collect();
+    names.pop();
+    for name in names {
+        dir.push(platform::os_str(name));
+        if dir.is_symlink() {
+            bail!("beyond a symbolic link: {}", dir.display());
+        }
+    }
+    Ok(())
+}
+
 /// An entry in a tree.
 pub struct Entry {
     pub mode: Mode,
     pub name: Vec<u8>,
-    pub hash: [u8; 20],
+    pub hash: ObjectId,
 }
 
 impl Entry {
     /// Parse entry from a tree object's content.
     pub fn parse(object: &mut ObjReader) -> Result<Self> {
-        // <mode> <name>\0<20_byte_sha>
+        // <mode> <name>\0<binary_hash>
         let mode = object.read_up_to(b' ').context("reading mode")?;
         let name = object.read_up_to(b'\0').context("reading name")?;
-        let mut hash = [0u8; 20];
-        object.read_exact(&mut hash).context("reading hash")?;
+        let algo = object.hash_algo();
+        let hash = ObjectId::read_from(object, algo).context("reading hash")?;
 
         let mode = Mode::from_bytes(&mode)?;
         Ok(Entry { mode, name, hash })
     }
 
+    /// Parse all entries from a tree object's content, already in memory,
+    /// with hashes of the given function.
+    pub fn parse_all(mut data: &[u8], algo: HashAlgo) -> Result<Vec<Self>> {
+        let hash_len = algo.raw_len();
+        let mut entries = Vec::new();
+        while !data.is_empty() {
+            // <mode> <name>\0<binary_hash>
+            let Some(space) = data.iter().position(|&c| c == b' ') else {
+                bail!("no space after mode");
+            };
+            let Some(nul) = data.iter().position(|&c| c == b'\0') else {
+                bail!("no NUL after name");
+            };
+            let Some(hash) = data.get(nul + 1..nul + 1 + hash_len) else {
+                bail!("truncated hash");
+            };
+            if nul < space {
+                bail!("NUL before mode");
+            }
+            entries.push(Entry {
+                mode: Mode::from_bytes(&data[..space])?,
+                name: data[space + 1..nul].to_vec(),
+                hash: ObjectId::from_bytes(hash)?,
+            });
+            data = &data[nul + 1 + hash_len..];
+        }
+        Ok(entries)
+    }
+
+    /// Parse an entry in the format printed by ls-tree, as read by mktree:
+    /// `<mode> <object type> <hash>\t<name>`. Except for submodules, the
+    /// object must exist and have the given type.
+    pub fn parse_listed(repo: &Repository, line: &[u8]) -> Result<Self> {
+        let format_error = || anyhow!("input format error: {}", String::from_utf8_lossy(line));
+        let tab = line
+            .iter()
+            .position(|&c| c == b'\t')
+            .ok_or_else(format_error)?;
+        let (fields, name) = (&line[..tab], &line[tab + 1..]);
+        let fields = str::from_utf8(fields).map_err(|_| format_error())?;
+        let mut fields = fields.split(' ');
+        let (Some(mode), Some(obj_type), Some(hash), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(format_error());
+        };
+        // Leading zeroes are accepted, as ls-tree prints them for directories.
+        let mode = u32::from_str_radix(mode, 8)
+            .ok()
+            .and_then(|mode| Mode::from_u32(mode).ok())
+            .ok_or_else(format_error)?;
+        let id = ObjectId::from_hex(hash).map_err(|_| format_error())?;
+        let obj_type = ObjType::from_bytes(obj_type.as_bytes())?;
+
+        let display = String::from_utf8_lossy(name);
+        if name.contains(&b'/') {
+            bail!("path {display} contains slash");
+        }
+        if obj_type != mode.obj_type() {
+            bail!(
+                "entry '{display}' object type ({}) doesn't match mode type ({})",
+                obj_type.to_str(),
+                mode.obj_type().to_str()
+            );
+        }
+        if mode != Mode::SubMod {
+            let object = repo
+                .read_object(hash)
+                .with_context(|| format!("entry '{display}' object {hash} is unavailable"))?;
+            if object.obj_type != obj_type {
+                bail!(
+                    "entry '{display}' object {hash} is a {} but specified type was ({})",
+                    object.obj_type.to_str(),
+                    obj_type.to_str()
+                );
+            }
+        }
+        Ok(Entry {
+            mode,
+            name: name.to_vec(),
+            hash: id,
+        })
+    }
+
+    /// Compare entries in the order of tree objects: by name, as if
+    /// directories had a '/' appended to their name.
+    pub fn cmp_in_tree(&self, other: &Entry) -> Ordering {
+        fn key(entry: &Entry) -> impl Iterator<Item = u8> + '_ {
+            let slash = (entry.mode == Mode::Dir).then_some(b'/');
+            entry.name.iter().copied().chain(slash)
+        }
+        key(self).cmp(key(other))
+    }
+
     /// Write entry as it will be in the tree object.
     pub fn push_to_vec(&self, out: &mut Vec<u8>) {
-        // <mode> <name>\0<20_byte_sha>
+        // <mode> <name>\0<binary_hash>
         out.extend_from_slice(self.mode.to_str().as_bytes());
         out.push(b' ');
         out.extend_from_slice(&self.name);
         out.push(b'\0');
-        out.extend_from_slice(&self.hash);
+        out.extend_from_slice(self.hash.as_ref());
     }
 
-    /// Print the name of the entry to stdout.
+    /// Print the name of the entry to stdout (as a JSON record with --format=json).
     pub fn print_name(&self) -> Result<()> {
         let mut stdout = io::stdout().lock();
+        if json::enabled() {
+            return Ok(json::write_record(
+                &mut stdout,
+                &[("name", (&self.name[..]).into())],
+            )?);
+        }
         stdout.write_all(&self.name)?;
         stdout.write_all(b"\n")?;
         Ok(())
     }
 
     /// Print the entry to stdout in the format used by ls-tree and cat-file -p:
-    /// `<mode> <object type> <hash>\t<name>\n`
-    pub fn print(&self) -> Result<()> {
-        let mut stdout = io::stdout().lock();
+    /// `<mode> <object type> <hash>\t<name>\n`, with the hash abbreviated if asked,
+    /// or as a JSON record with the same fields with --format=json.
+    pub fn print(&self, repo: &Repository, abbrev: bool) -> Result<()> {
         let mode = self.mode.to_str();
         let otype = self.mode.obj_type().to_str();
-        let hash = hex::encode(self.hash);
+        let mut hash = hex::encode(self.hash);
+        if abbrev {
+            hash = shorten(repo, &hash)?;
+        }
+        let mut stdout = io::stdout().lock();
+        if json::enabled() {
+            let mode = format!("{mode:0>6}");
+            let fields = [
+                ("mode", mode.as_str().into()),
+                ("type", otype.into()),
+                ("hash", hash.as_str().into()),
+                ("name", (&self.name[..]).into()),
+            ];
+            return Ok(json::write_record(&mut stdout, &fields)?);
+        }
         write!(stdout, "{mode:0>6} {otype} {hash}\t")?;
         stdout.write_all(&self.name)?;
         stdout.write_all(b"\n")?;
@@ -132,47 +340,55 @@ impl Entry {
     }
 
     /// Create an actual file/dir/link in the filesystem from this entry.
-    pub fn actualise(&self, base_path: &Path) -> Result<()> {
+    ///
+    /// The name may be a path below the base directory (whose directories
+    /// must exist). Invalid paths, such as `..` or `.git`, are refused, and
+    /// so is writing through symlinks, see verify_path() and check_leading_dirs().
+    pub fn actualise(&self, repo: &Repository, base_path: &Path) -> Result<()> {
+        verify_path(&self.name)?;
+        check_leading_dirs(base_path, &self.name)?;
         let hash = hex::encode(self.hash);
-        let mut object =
-            ObjReader::from_hash(&hash).with_context(|| format!("opening object {hash}"))?;
-        let path = base_path.join(OsStr::from_bytes(&self.name));
+        let mut object = repo
+            .read_object(&hash)
+            .with_context(|| format!("opening object {hash}"))?;
+        let path = platform::join(base_path, &self.name);
 
         match self.mode {
             Mode::Dir => {
                 fs::create_dir(&path)
                     .with_context(|| format!("creating directory {}", path.display()))?;
                 let tree = TreeReader::from_object(object)?;
-                tree.actualise_entries(&path)
+                tree.actualise_entries(repo, &path)
                     .with_context(|| format!("checking out, subdr {}", path.display()))?;
             }
             Mode::File | Mode::Exe => {
-                let mut out = fs::File::create(&path)
+                // Don't follow a symlink created earlier with the same name.
+                let mut out = platform::create_no_follow(&path)
                     .with_context(|| format!("creating file {}", path.display()))?;
-                io::copy(&mut object, &mut out)
-                    .with_context(|| format!("copying object {hash} to file {}", path.display()))?;
+                if filter::converts(repo, &path)? {
+                    let content = object
+                        .read_to_vec(MAX_BLOB_SIZE)
+                        .with_context(|| format!("reading from object {hash}"))?;
+                    out.write_all(&filter::smudge(repo, &path, content)?)
+                        .with_context(|| format!("writing file {}", path.display()))?;
+                } else {
+                    io::copy(&mut object, &mut out).with_context(|| {
+                        format!("copying object {hash} to file {}", path.display())
+                    })?;
+                }
                 if let Mode::Exe = self.mode {
-                    let meta = out
-                        .metadata()
-                        .with_context(|| format!("stat {}", path.display()))?;
-                    let mut perms = meta.permissions();
-                    perms.set_mode(perms.mode() | 0o111);
-                    fs::set_permissions(&path, perms)
-                        .with_context(|| format!("making {} executable", path.display()))?;
+                    platform::set_executable(&out, &path)?;
+                }
+                // Files checked out next may be affected by its rules.
+                if path.ends_with(".gitattributes") {
+                    attributes::invalidate(repo);
                 }
             }
             Mode::SymLink => {
-                let mut target = Vec::new();
-                io::copy(&mut object, &mut target)
+                let target = object
+                    .read_to_vec(MAX_BLOB_SIZE)
                     .with_context(|| format!("reading from object {hash}"))?;
-                let target = OsStr::from_bytes(&target);
-                unix::fs::symlink(target, &path).with_context(|| {
-                    format!(
-                        "creating symlink {} -> {}",
-                        path.display(),
-                        Path::new(target).display()
-                    )
-                })?;
+                platform::symlink(repo, &target, &path)?;
             }
             Mode::SubMod => {
                 bail!("support for submodule not implemented");
@@ -3,8 +3,10 @@
 use anyhow::{bail, Context, Result};
 use std::path::Path;
 
-use crate::obj_read::ObjReader;
+use crate::interrupt;
+use crate::obj_read::{ObjReader, MAX_METADATA_SIZE};
 use crate::obj_type::ObjType;
+use crate::repository::Repository;
 use crate::tree_entry::Entry;
 
 /// As simple wrapper for an object reader, with tree-specific methods.
@@ -13,12 +15,6 @@ pub struct TreeReader {
 }
 
 impl TreeReader {
-    /// Create a tree reader from a tree hash.
-    pub fn from_hash(hash: &str) -> Result<Self> {
-        let object = ObjReader::from_hash(hash)?;
-        Self::from_object(object)
-    }
-
     /// Create a tree reader from an object reader.
     pub fn from_object(object: ObjReader) -> Result<Self> {
         if object.obj_type != ObjType::Tree {
@@ -27,25 +23,36 @@ impl TreeReader {
         Ok(TreeReader { object })
     }
 
-    /// Print this tree's entries to stdout.
-    pub fn print_entries(mut self, name_only: bool) -> Result<()> {
+    /// Read all of this tree's entries.
+    pub fn into_entries(self) -> Result<Vec<Entry>> {
+        let algo = self.object.hash_algo();
+        let data = self
+            .object
+            .read_to_vec(MAX_METADATA_SIZE)
+            .context("reading tree object")?;
+        Entry::parse_all(&data, algo).context("parsing tree entries")
+    }
+
+    /// Print this tree's entries to stdout, with abbreviated hashes if asked.
+    pub fn print_entries(mut self, repo: &Repository, name_only: bool, abbrev: bool) -> Result<()> {
         while !self.object.eof().context("reading tree object")? {
             let entry = Entry::parse(&mut self.object).context("parsing tree entry")?;
             if name_only {
                 entry.print_name()?;
             } else {
-                entry.print()?;
+                entry.print(repo, abbrev)?;
             }
         }
         Ok(())
     }
 
     /// Turn this tree object into an actual tree in the filesytem.
-    pub fn actualise_entries(mut self, base_path: &Path) -> Result<()> {
+    pub fn actualise_entries(mut self, repo: &Repository, base_path: &Path) -> Result<()> {
         while !self.object.eof().context("reading tree object")? {
+            interrupt::check()?;
             let entry = Entry::parse(&mut self.object).context("parsing tree entry")?;
             entry
-                .actualise(base_path)
+                .actualise(repo, base_path)
                 .context("creating entry on the filesystem")?;
         }
         Ok(())
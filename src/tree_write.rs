@@ -1,40 +1,86 @@
 //! Writing tree objects
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
 use std::fs;
 use std::io;
-use std::os::unix::ffi::OsStrExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::thread;
 
-use crate::common::git_dir;
+use crate::diff::Side;
+use crate::filter;
+use crate::ignore::Ignores;
+use crate::index;
 use crate::obj_type::ObjType;
 use crate::obj_write::write_object;
+use crate::object_id::ObjectId;
+use crate::platform;
+use crate::repository::Repository;
 use crate::tree_entry::{Entry, Mode};
 
-/// Hash and write to object storage the given entry.
-fn hash_entry(path: &Path, meta: &fs::Metadata) -> Result<String> {
-    if meta.is_dir() {
-        tree_from_dir(path).context("hashing subtree")
-    } else if meta.is_file() {
-        let mut file =
-            fs::File::open(path).with_context(|| format!("could not read {}", path.display()))?;
-
-        write_object(ObjType::Blob, &mut file, true).context("hashing file")
-    } else if meta.is_symlink() {
-        let dest = fs::read_link(path).context("readlink")?;
-        let mut content = io::Cursor::new(dest.as_os_str().as_bytes());
+/// Hash the content of a file from the worktree, applying filters (see
+/// filter::clean()), and write it to object storage if `write`.
+pub fn hash_file(repo: &Repository, path: &Path, write: bool) -> Result<String> {
+    if filter::converts(repo, path)? {
+        let content =
+            fs::read(path).with_context(|| format!("could not read {}", path.display()))?;
+        let mut content = io::Cursor::new(filter::clean(repo, path, content)?);
+        return write_object(repo, ObjType::Blob, &mut content, write);
+    }
+    let mut file =
+        fs::File::open(path).with_context(|| format!("could not read {}", path.display()))?;
+    write_object(repo, ObjType::Blob, &mut file, write)
+}
 
-        write_object(ObjType::Blob, &mut content, true).context("hashing symlink")
+/// Hash and write to object storage the given file or symlink.
+pub fn hash_blob(repo: &Repository, path: &Path, meta: &fs::Metadata) -> Result<String> {
+    if meta.is_file() {
+        hash_file(repo, path, true).context("hashing file")
     } else {
-        bail!("neither a regular file, nor a directory, nor a symlink");
+        let mut content = io::Cursor::new(platform::read_symlink(path)?);
+
+        write_object(repo, ObjType::Blob, &mut content, true).context("hashing symlink")
     }
 }
 
-/// The hash of the empty tree, used to detect and skip them.
-///
-/// This is more convenient than checking using read_dir as we need to
-/// ignore .git and recursively ignore "empty" directories.
-const EMPTY_TREE_HASH: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+/// Hash files in parallel, returning their hashes in the same order.
+fn hash_blobs(repo: &Repository, jobs: &[(PathBuf, fs::Metadata)]) -> Result<Vec<String>> {
+    let nb_threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = jobs.len().div_ceil(nb_threads).max(1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = jobs
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || -> Result<Vec<String>> {
+                    chunk
+                        .iter()
+                        .map(|(path, meta)| {
+                            hash_blob(repo, path, meta)
+                                .with_context(|| format!("hashing {}", path.display()))
+                        })
+                        .collect()
+                })
+            })
+            .collect();
+        let mut hashes = Vec::with_capacity(jobs.len());
+        for worker in workers {
+            hashes.extend(
+                worker
+                    .join()
+                    .map_err(|_| anyhow!("hashing thread panicked"))??,
+            );
+        }
+        Ok(hashes)
+    })
+}
+
+/// The content of a tree entry, before files are hashed.
+enum Planned {
+    /// A file or symlink, hashed by the job with that index
+    Blob(usize),
+    /// A directory, with its entries in order
+    Tree(Vec<(Vec<u8>, Mode, Planned)>),
+}
 
 /// Get entries for the given directory, sorted how git wants them.
 fn sorted_entries(dir: &Path) -> Result<Vec<(fs::DirEntry, fs::Metadata)>> {
@@ -51,14 +97,15 @@ fn sorted_entries(dir: &Path) -> Result<Vec<(fs::DirEntry, fs::Metadata)>> {
 
     // Sort as if directories had a '/' appended to their name.
     entries.sort_unstable_by(|a, b| {
-        let (mut a_name, a_is_dir) = (a.0.file_name().into_encoded_bytes(), a.1.is_dir());
-        let (mut b_name, b_is_dir) = (b.0.file_name().into_encoded_bytes(), b.1.is_dir());
+        let (a_name, a_is_dir) = (a.0.file_name(), a.1.is_dir());
+        let (b_name, b_is_dir) = (b.0.file_name(), b.1.is_dir());
+        let (mut a_name, mut b_name) = (platform::bytes(&a_name), platform::bytes(&b_name));
 
         if a_is_dir {
-            a_name.push(b'/');
+            a_name.to_mut().push(b'/');
         }
         if b_is_dir {
-            b_name.push(b'/');
+            b_name.to_mut().push(b'/');
         }
 
         a_name.cmp(&b_name)
@@ -67,36 +114,158 @@ fn sorted_entries(dir: &Path) -> Result<Vec<(fs::DirEntry, fs::Metadata)>> {
     Ok(entries)
 }
 
-/// Create a tree object for the given directory and return its hash.
-fn tree_from_dir(dir: &Path) -> Result<String> {
-    // We'll need everything in memory so we know the size before writing the object.
-    let mut out = Vec::new();
+/// List the entries of the given directory, recursively, adding files
+/// to the list of jobs for hashing.
+/// `rel` is the path of the directory relative to the root of the worktree,
+/// with a trailing '/' (empty for the root), used to match ignore rules if any,
+/// and to look up modes in `indexed`, see Mode::from_worktree().
+fn plan_dir(
+    repo: &Repository,
+    dir: &Path,
+    rel: &[u8],
+    mut ignores: Option<&mut Ignores>,
+    indexed: &HashMap<Vec<u8>, Mode>,
+    jobs: &mut Vec<(PathBuf, fs::Metadata)>,
+) -> Result<Vec<(Vec<u8>, Mode, Planned)>> {
+    let mut planned = Vec::new();
 
     let entries = sorted_entries(dir)?;
+    let mark = match ignores.as_deref_mut() {
+        Some(ignores) => ignores.enter_dir(dir, rel)?,
+        None => 0,
+    };
 
     for (entry, meta) in entries {
-        let name = entry.file_name().into_encoded_bytes();
+        let name = platform::bytes(&entry.file_name()).into_owned();
         if name == b".git" {
             continue;
         }
+        let path = [rel, &name].concat();
+        if let Some(ignores) = ignores.as_deref() {
+            if ignores.is_ignored(&path, meta.is_dir()) {
+                continue;
+            }
+        }
+
+        let content = if meta.is_dir() {
+            let sub_rel = [&path[..], b"/"].concat();
+            let sub = plan_dir(
+                repo,
+                &entry.path(),
+                &sub_rel,
+                ignores.as_deref_mut(),
+                indexed,
+                jobs,
+            )
+            .context("hashing subtree")?;
+            Planned::Tree(sub)
+        } else if meta.is_file() || meta.is_symlink() {
+            jobs.push((entry.path(), meta.clone()));
+            Planned::Blob(jobs.len() - 1)
+        } else {
+            bail!("neither a regular file, nor a directory, nor a symlink");
+        };
+        let mode = Mode::from_worktree(repo, &meta, indexed.get(&path))?;
+        planned.push((name, mode, content));
+    }
+    if let Some(ignores) = ignores {
+        ignores.leave_dir(mark);
+    }
+
+    Ok(planned)
+}
 
-        let hash = hash_entry(&entry.path(), &meta)?;
-        if hash == EMPTY_TREE_HASH {
+/// Create tree objects for planned entries, given the hashes of the files,
+/// and return the hash of the top-level one.
+fn write_planned(
+    repo: &Repository,
+    entries: Vec<(Vec<u8>, Mode, Planned)>,
+    hashes: &[String],
+) -> Result<String> {
+    // We'll need everything in memory so we know the size before writing the object.
+    let mut out = Vec::new();
+
+    for (name, mode, content) in entries {
+        let hash = match content {
+            Planned::Blob(job) => hashes[job].clone(),
+            Planned::Tree(sub) => write_planned(repo, sub, hashes)?,
+        };
+        // Skip empty trees: this is more convenient than checking using read_dir
+        // as we need to ignore .git and recursively ignore "empty" directories.
+        if hash == repo.hash_algo()?.empty_tree() {
             continue;
         }
-        let hash = hex::decode(hash).expect("hash is valid hex");
-        let hash: [u8; 20] = hash.try_into().expect("hash is 20 bytes");
-
-        let mode = Mode::from_metadata(&meta)?;
+        let hash = ObjectId::from_hex(&hash)?;
 
         Entry { mode, name, hash }.push_to_vec(&mut out);
     }
 
-    write_object(ObjType::Tree, &mut io::Cursor::new(out), true)
+    write_object(repo, ObjType::Tree, &mut io::Cursor::new(out), true)
 }
 
 /// Create a tree object for the git working directory and return its hash.
-pub fn tree_from_workdir() -> Result<String> {
-    let root = git_dir()?.parent().expect(".git has a parent");
-    tree_from_dir(root)
+/// Ignored files are left out if `use_ignores` is set, see Ignores.
+///
+/// Directories are listed first, then files are hashed in parallel,
+/// then trees are written.
+pub fn tree_from_workdir(repo: &Repository, use_ignores: bool) -> Result<String> {
+    let root = repo.work_tree()?;
+    let mut ignores = if use_ignores {
+        Some(Ignores::new(repo)?)
+    } else {
+        None
+    };
+    // Without core.filemode or core.symlinks, modes come from the index.
+    let indexed = match platform::filemode(repo)? && platform::symlinks(repo)? {
+        true => HashMap::new(),
+        false => index::read(repo)?
+            .into_iter()
+            .map(|e| (e.path, e.mode))
+            .collect(),
+    };
+    let mut jobs = Vec::new();
+    let planned = plan_dir(repo, root, b"", ignores.as_mut(), &indexed, &mut jobs)?;
+    let hashes = hash_blobs(repo, &jobs)?;
+    write_planned(repo, planned, &hashes)
+}
+
+/// Create tree objects for a list of paths with their content, sorted in index
+/// order, and return the hash of the top-level tree.
+pub fn tree_from_list(repo: &Repository, list: &[(Vec<u8>, Side)]) -> Result<String> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < list.len() {
+        let (path, side) = &list[i];
+        let entry = match path.iter().position(|&c| c == b'/') {
+            None => {
+                i += 1;
+                Entry {
+                    mode: side.mode.clone(),
+                    name: path.clone(),
+                    hash: ObjectId::from_hex(&side.hash)?,
+                }
+            }
+            Some(slash) => {
+                // In index order, all paths in a directory are together.
+                let dir = &path[..=slash];
+                let end = i + list[i..]
+                    .iter()
+                    .take_while(|(p, _)| p.starts_with(dir))
+                    .count();
+                let subtree: Vec<_> = list[i..end]
+                    .iter()
+                    .map(|(p, side)| (p[slash + 1..].to_vec(), side.clone()))
+                    .collect();
+                i = end;
+                Entry {
+                    mode: Mode::Dir,
+                    name: path[..slash].to_vec(),
+                    hash: ObjectId::from_hex(&tree_from_list(repo, &subtree)?)?,
+                }
+            }
+        };
+        entry.push_to_vec(&mut out);
+    }
+
+    write_object(repo, ObjType::Tree, &mut io::Cursor::new(out), true)
 }
@@ -6,13 +6,17 @@
 
 use anyhow::{bail, Context, Result};
 use flate2::bufread::ZlibDecoder;
-use sha1::{Digest, Sha1};
+use std::collections::HashMap;
 use std::io;
 use std::io::prelude::*;
 
-use crate::obj_read::ObjReader;
+use crate::fetch::have_object;
+use crate::interrupt;
 use crate::obj_type::ObjType;
 use crate::obj_write::ObjWriter;
+use crate::object_id::{Hasher, ObjectId};
+use crate::pack_index::apply_delta;
+use crate::repository::Repository;
 
 /// This wraps an existing BufRead into a new BufRead
 /// that also hashes the content as it's being read.
@@ -20,26 +24,30 @@ use crate::obj_write::ObjWriter;
 /// This needs to implement BufRead as we want to feed it to a ZlibDecoder, and
 /// only the bufread version supports reading data past the end of a zlib stream.
 struct HashingReader<R> {
-    hasher: Sha1,
+    hasher: Hasher,
     reader: R,
+    /// Number of bytes read so far
+    pos: u64,
 }
 
 impl<R: BufRead> HashingReader<R> {
     /// Create a hashing reader.
-    fn new(reader: R) -> Self {
-        let hasher = Sha1::new();
-        Self { hasher, reader }
+    fn new(repo: &Repository, reader: R) -> Result<Self> {
+        let hasher = repo.hash_algo()?.hasher();
+        Ok(Self {
+            hasher,
+            reader,
+            pos: 0,
+        })
     }
 
     /// Finish reading from this reader and check the final checksum.
     fn finish(mut self) -> Result<()> {
+        let algo = self.hasher.algo();
         let hash = self.hasher.finalize();
-        let mut foot = [0u8; 20];
-        self.reader
-            .read_exact(&mut foot)
-            .context("reading final checksum")?;
-        if hash != foot.into() {
-            bail!("checksum mismatch: exp {hash:?}, got {foot:?}");
+        let foot = ObjectId::read_from(&mut self.reader, algo).context("reading final checksum")?;
+        if hash != foot {
+            bail!("checksum mismatch: exp {hash}, got {foot}");
         }
         let Ok(0) = self.reader.read(&mut [0]) else {
             bail!("trailing data after final checksum");
@@ -65,6 +73,7 @@ impl<R: BufRead> BufRead for HashingReader<R> {
         let amt = std::cmp::min(amt, bytes.len());
         self.hasher.update(&bytes[..amt]);
         self.reader.consume(amt);
+        self.pos += amt as u64;
     }
 }
 
@@ -74,6 +83,7 @@ impl<R: Read> Read for HashingReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let n = self.reader.read(buf)?;
         self.hasher.update(&buf[..n]);
+        self.pos += n as u64;
         Ok(n)
     }
 }
@@ -108,14 +118,16 @@ impl PackObjType {
 }
 
 /// Read an undeltified object's data and write the object to loose storage.
-fn unpack_undeltified(reader: &mut impl BufRead, obj_type: ObjType, size: usize) -> Result<()> {
+fn unpack_undeltified(
+    repo: &Repository,
+    reader: &mut impl BufRead,
+    obj_type: ObjType,
+    size: usize,
+) -> Result<String> {
     let mut zdec = ZlibDecoder::new(reader);
-    let mut object = ObjWriter::new(obj_type, size, true).context("creating object")?;
+    let mut object = ObjWriter::new(repo, obj_type, size, true).context("creating object")?;
     io::copy(&mut zdec, &mut object).context("copying data to object")?;
-    object
-        .finish()
-        .context("writing object to object database")?;
-    Ok(())
+    object.finish().context("writing object to object database")
 }
 
 /// Read a byte from the given reader (convenience function).
@@ -144,6 +156,9 @@ fn read_size_and_opt_type(reader: &mut impl Read, type_bits: u8) -> Result<(u8,
 
     // keep reading size until the top bit is unset
     while byte & 0x80 != 0 {
+        if size_bits >= usize::BITS as u8 {
+            bail!("size too large");
+        }
         byte = read_byte(reader).context("reading continuation byte")?;
         size += ((byte & 0x7f) as usize) << size_bits;
         size_bits += 7;
@@ -152,6 +167,21 @@ fn read_size_and_opt_type(reader: &mut impl Read, type_bits: u8) -> Result<(u8,
     Ok((type_id, size))
 }
 
+/// Read the negative offset of an ofs_delta's base,
+/// see gitformat-pack(5) "offset encoding" and pack_index::read_ofs().
+fn read_base_offset(reader: &mut impl Read) -> Result<u64> {
+    let mut byte = read_byte(reader).context("reading base offset")?;
+    let mut ofs = (byte & 0x7f) as u64;
+    while byte & 0x80 != 0 {
+        byte = read_byte(reader).context("reading base offset")?;
+        let Some(shifted) = ofs.checked_add(1).and_then(|ofs| ofs.checked_mul(1 << 7)) else {
+            bail!("base offset too large");
+        };
+        ofs = shifted | (byte & 0x7f) as u64;
+    }
+    Ok(ofs)
+}
+
 /// Read the offset component of a copy instruction.
 /// See gitformat-pack(5) "Instruction to copy from base object".
 fn read_copy_offset(reader: &mut impl Read, bitmap: u8) -> Result<u64> {
@@ -178,30 +208,55 @@ fn read_copy_size(reader: &mut impl Read, bitmap: u8) -> Result<u64> {
     Ok(size)
 }
 
-/// Read a deltified object's instructions and write it out as a loose object.
+/// The base of a deltified object.
+enum Base {
+    /// An object given by its hash (ref_delta)
+    Hash(String),
+    /// The entry at this offset in the pack (ofs_delta)
+    Offset(u64),
+}
+
+/// A deltified object whose base was not available when it was read:
+/// it may come later in the pack, or be produced by another delta.
+struct PendingDelta {
+    /// Offset of the delta's entry in the pack
+    offset: u64,
+    base: Base,
+    instructions: Vec<u8>,
+}
+
+/// What reading an entry gave: an object written out, with its hash, or a
+/// delta to write later.
+enum Unpacked {
+    Object(String),
+    Pending(PendingDelta),
+}
+
+/// Read a deltified object's instructions and write it out as a loose object,
+/// returning its hash.
 ///
 /// This involves reconstructing the object from a base object and a series
 /// of instructions to either add new data or copy from the base object.
 ///
 /// See gitformat-pack(5) "Deltified representation".
-fn unpack_ref_delta(reader: &mut impl BufRead, instr_size: usize) -> Result<()> {
-    let mut hash = [0u8; 20];
-    reader
-        .read_exact(&mut hash)
-        .context("reading hash of base object")?;
-    let hash = hex::encode(hash);
-
+fn unpack_delta(
+    repo: &Repository,
+    reader: &mut impl BufRead,
+    instr_size: usize,
+    hash: &str,
+) -> Result<String> {
     let mut reader = &mut ZlibDecoder::new(reader);
     let (_, _) = read_size_and_opt_type(&mut reader, 0).context("reading base size")?;
     let (_, obj_size) = read_size_and_opt_type(&mut reader, 0).context("reading object size")?;
 
     // Only get the type from the base object, we'll open it again when copying data.
     // Save memory (not holding the whole content at once) at the expense of performance.
-    let base_obj_type = ObjReader::from_hash(&hash)
+    let base_obj_type = repo
+        .read_object(hash)
         .with_context(|| format!("opening base object {hash}"))?
         .obj_type;
-    let mut writer = ObjWriter::new(base_obj_type, obj_size, true)
-        .context("creating new object from ref_delta")?;
+    let mut writer = ObjWriter::new(repo, base_obj_type, obj_size, true)
+        .context("creating new object from delta")?;
 
     while reader.total_out() < instr_size as u64 {
         let first_byte = read_byte(reader).context("reading next instruction")?;
@@ -210,7 +265,8 @@ fn unpack_ref_delta(reader: &mut impl BufRead, instr_size: usize) -> Result<()>
             let offset = read_copy_offset(reader, first_byte).context("reading offset")?;
             let copy_size = read_copy_size(reader, first_byte).context("reading size")?;
 
-            let mut base_obj = ObjReader::from_hash(&hash)
+            let mut base_obj = repo
+                .read_object(hash)
                 .with_context(|| format!("opening base object {hash}"))?;
             io::copy(&mut base_obj.by_ref().take(offset), &mut io::sink())
                 .with_context(|| format!("skipping bytes in base object {hash}"))?;
@@ -229,32 +285,123 @@ fn unpack_ref_delta(reader: &mut impl BufRead, instr_size: usize) -> Result<()>
         }
     }
 
-    writer.finish().context("finalizing object")?;
+    // Read to the end of the zlib stream, so its checksum is consumed
+    // even if it wasn't in the decoder's buffer yet.
+    if io::copy(reader, &mut io::sink()).context("reading end of instructions")? != 0 {
+        bail!("trailing data after delta instructions");
+    }
+
+    writer.finish().context("finalizing object")
+}
 
+/// Get the hash of a delta's base if it is available: in the repository, or
+/// among the entries written out so far, by offset.
+fn base_hash(
+    repo: &Repository,
+    base: &Base,
+    offsets: &HashMap<u64, String>,
+) -> Result<Option<String>> {
+    Ok(match base {
+        Base::Hash(hash) => have_object(repo, hash)?.then(|| hash.clone()),
+        Base::Offset(offset) => offsets.get(offset).cloned(),
+    })
+}
+
+/// Write out deltified objects whose base was not available when they were read,
+/// in as many passes as needed to resolve chains of deltas.
+fn resolve_pending(
+    repo: &Repository,
+    mut pending: Vec<PendingDelta>,
+    offsets: &mut HashMap<u64, String>,
+) -> Result<()> {
+    while !pending.is_empty() {
+        let before = pending.len();
+        let mut unresolved = Vec::new();
+        for delta in pending {
+            let Some(base_hash) = base_hash(repo, &delta.base, offsets)? else {
+                unresolved.push(delta);
+                continue;
+            };
+            let mut base_obj = repo
+                .read_object(&base_hash)
+                .with_context(|| format!("opening base object {base_hash}"))?;
+            let mut base = Vec::new();
+            base_obj
+                .read_to_end(&mut base)
+                .with_context(|| format!("reading base object {base_hash}"))?;
+            let content = apply_delta(&base, &delta.instructions)
+                .with_context(|| format!("applying delta to {base_hash}"))?;
+            let mut writer = ObjWriter::new(repo, base_obj.obj_type, content.len(), true)
+                .context("creating new object from delta")?;
+            writer.write_all(&content).context("writing object")?;
+            let hash = writer.finish().context("finalizing object")?;
+            offsets.insert(delta.offset, hash);
+        }
+        if unresolved.len() == before {
+            match &unresolved[0].base {
+                Base::Hash(hash) => bail!("missing base object {hash}"),
+                Base::Offset(offset) => bail!("no object at base offset {offset}"),
+            }
+        }
+        pending = unresolved;
+    }
     Ok(())
 }
 
-/// Read an object entry and write it out as a loose object.
+/// Read an object entry starting at the given offset in the pack, and write
+/// it out as a loose object, unless it's a delta against an object we don't
+/// have yet. Offsets gives the hash of the entries written out so far.
 /// See gitformat-pack(5) "object entries, each of which looks like this"
-fn unpack_object(reader: &mut impl BufRead) -> Result<()> {
+fn unpack_object(
+    repo: &Repository,
+    reader: &mut impl BufRead,
+    offset: u64,
+    offsets: &HashMap<u64, String>,
+) -> Result<Unpacked> {
     // n-byte type and length (3-bit type, (n-1)*7+4-bit length)
     let (type_id, size) = read_size_and_opt_type(reader, 3).context("reading type and size")?;
-    let pack_type = PackObjType::from_byte(type_id)?;
+    let base = match PackObjType::from_byte(type_id)? {
+        Basic(obj_type) => {
+            return unpack_undeltified(repo, reader, obj_type, size).map(Unpacked::Object)
+        }
+        Delta(DeltaType::RefDelta) => {
+            let hash = ObjectId::read_from(reader, repo.hash_algo()?)
+                .context("reading hash of base object")?;
+            Base::Hash(hash.to_string())
+        }
+        Delta(DeltaType::OfsDelta) => {
+            let ofs = read_base_offset(reader)?;
+            match offset.checked_sub(ofs) {
+                Some(base) if ofs != 0 => Base::Offset(base),
+                _ => bail!("invalid base offset {ofs}"),
+            }
+        }
+    };
 
-    // compressed data
-    match pack_type {
-        Basic(obj_type) => unpack_undeltified(reader, obj_type, size),
-        Delta(DeltaType::RefDelta) => unpack_ref_delta(reader, size),
-        Delta(DeltaType::OfsDelta) => bail!("ofs_delta not supported"),
+    if let Some(hash) = base_hash(repo, &base, offsets)? {
+        return unpack_delta(repo, reader, size, &hash).map(Unpacked::Object);
     }
+    // The size can't be trusted before decompressing: don't reserve it.
+    let mut instructions = Vec::new();
+    ZlibDecoder::new(reader)
+        .read_to_end(&mut instructions)
+        .context("reading delta instructions")?;
+    if instructions.len() != size {
+        bail!("size mismatch: expected {size}, got {}", instructions.len());
+    }
+    Ok(Unpacked::Pending(PendingDelta {
+        offset,
+        base,
+        instructions,
+    }))
 }
 
 /// Read a packfile, write all its objects to loose storage,
 /// and return the number of objects written.
 ///
 /// See gitformat-pack(5) "pack-*.pack files have the following format"
-pub fn unpack_from<R: BufRead>(reader: R) -> Result<u32> {
-    let mut reader = HashingReader::new(reader);
+pub fn unpack_from<R: BufRead>(repo: &Repository, reader: R) -> Result<u32> {
+    let mut reader = HashingReader::new(repo, reader)?;
 
     // 4-byte signature "PACK" + 4-byte version number 2
     // 4-byte number of objects
@@ -269,13 +416,25 @@ pub fn unpack_from<R: BufRead>(reader: R) -> Result<u32> {
     let nb_obj = u32::from_be_bytes(last4);
 
     // object entries
+    let mut pending = Vec::new();
+    let mut offsets = HashMap::new();
     for i in 0..nb_obj {
-        unpack_object(&mut reader)
-            .with_context(|| format!("unpacking object {}/{}", i + 1, nb_obj))?;
+        interrupt::check()?;
+        let offset = reader.pos;
+        match unpack_object(repo, &mut reader, offset, &offsets)
+            .with_context(|| format!("unpacking object {}/{}", i + 1, nb_obj))?
+        {
+            Unpacked::Object(hash) => {
+                offsets.insert(offset, hash);
+            }
+            Unpacked::Pending(delta) => pending.push(delta),
+        }
     }
 
     // pack checksum
     reader.finish().context("end of packfile")?;
 
+    resolve_pending(repo, pending, &mut offsets).context("resolving deltas")?;
+
     Ok(nb_obj)
 }
@@ -0,0 +1,77 @@
+//! Updating files in the worktree to go from one tree to another.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::diff::Change;
+use crate::interrupt;
+use crate::object_id::ObjectId;
+use crate::platform;
+use crate::repository::Repository;
+use crate::tree_entry::{check_leading_dirs, verify_path, Entry, Mode};
+
+/// Remove a file, then its parent directories as long as they are empty.
+pub fn remove_path(root: &Path, path: &[u8]) -> Result<()> {
+    let file = platform::join(root, path);
+    let removed = match fs::symlink_metadata(&file) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir(&file),
+        Ok(_) => fs::remove_file(&file),
+        Err(e) => Err(e),
+    };
+    match removed {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("removing {}", file.display())),
+    }
+    for dir in file.ancestors().skip(1) {
+        if dir == root || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Apply changes (from diff_lists) to the files in the worktree:
+/// paths are deleted, created or replaced with the new side's content.
+///
+/// Local modifications to these paths are lost: callers should check
+/// the worktree is clean first.
+pub fn apply(repo: &Repository, changes: &[Change]) -> Result<()> {
+    let root = repo.work_tree()?;
+    // Remove everything first, so that a file can replace a directory
+    // and the other way around.
+    for change in changes {
+        if change.old.is_some() {
+            remove_path(root, &change.path)?;
+        }
+    }
+    for change in changes {
+        interrupt::check()?;
+        let Some(new) = &change.new else {
+            continue;
+        };
+        // Check before creating directories, which would follow symlinks.
+        verify_path(&change.path)?;
+        check_leading_dirs(root, &change.path)?;
+        let file = platform::join(root, &change.path);
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+        if new.mode == Mode::SubMod {
+            // Like git, leave an empty directory for submodules.
+            fs::create_dir_all(&file).with_context(|| format!("creating {}", file.display()))?;
+            continue;
+        }
+        let entry = Entry {
+            mode: new.mode.clone(),
+            name: change.path.clone(),
+            hash: ObjectId::from_hex(&new.hash)?,
+        };
+        entry
+            .actualise(repo, root)
+            .with_context(|| format!("writing {}", file.display()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,207 @@
+//! Linked worktrees, like git worktree: more worktrees for the same
+//! repository, each with its own HEAD, index and per-worktree references,
+//! see git-worktree(1).
+//!
+//! A linked worktree has a .git file pointing to its own git directory,
+//! .git/worktrees/`<id>`, which holds its HEAD and index along with two files
+//! pointing back: gitdir (the path to that .git file) and commondir (the main
+//! .git directory, relative to it). Everything else is shared, see
+//! Repository::git_path().
+
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::common::run;
+use crate::refs;
+use crate::repository::Repository;
+
+/// A worktree of the repository: the main one or a linked one.
+pub struct Worktree {
+    /// Root of the worktree, or of the repository if it is bare
+    pub path: PathBuf,
+    /// Its git directory
+    pub git_dir: PathBuf,
+    /// Its id in .git/worktrees, None for the main worktree
+    pub id: Option<String>,
+    /// The name of its HEAD from any worktree, eg "worktrees/`<id>`/HEAD"
+    pub head: String,
+    /// A bare repository has a main worktree with no files
+    pub bare: bool,
+}
+
+/// List the worktrees of the repository, the main one first, then linked
+/// ones by id.
+pub fn list(repo: &Repository) -> Result<Vec<Worktree>> {
+    let common_dir = repo.common_dir();
+    let bare = common_dir.file_name().is_some_and(|name| name != ".git");
+    let mut worktrees = vec![Worktree {
+        path: match bare {
+            true => common_dir.clone(),
+            false => common_dir.parent().unwrap_or(common_dir).to_owned(),
+        },
+        git_dir: common_dir.clone(),
+        id: None,
+        head: "main-worktree/HEAD".to_owned(),
+        bare,
+    }];
+    let dir = common_dir.join("worktrees");
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(worktrees),
+        Err(e) => return Err(e).with_context(|| format!("listing {}", dir.display())),
+    };
+    let mut ids = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("listing {}", dir.display()))?;
+        ids.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    ids.sort_unstable();
+    for id in ids {
+        let git_dir = dir.join(&id);
+        // The path to the .git file of the worktree
+        let Ok(dot_git) = fs::read_to_string(git_dir.join("gitdir")) else {
+            continue;
+        };
+        let dot_git = Path::new(dot_git.trim_end());
+        worktrees.push(Worktree {
+            path: dot_git.parent().unwrap_or(dot_git).to_owned(),
+            git_dir,
+            head: format!("worktrees/{id}/HEAD"),
+            id: Some(id),
+            bare: false,
+        });
+    }
+    Ok(worktrees)
+}
+
+/// Find the worktree where a branch (full name) is checked out, if any.
+pub fn checked_out(repo: &Repository, branch: &str) -> Result<Option<Worktree>> {
+    for worktree in list(repo)? {
+        if !worktree.bare
+            && refs::read_symbolic(repo, &worktree.head)
+                .ok()
+                .flatten()
+                .as_deref()
+                == Some(branch)
+        {
+            return Ok(Some(worktree));
+        }
+    }
+    Ok(None)
+}
+
+/// Run this program in a worktree with the given arguments, as if it was
+/// run from there, and capture its output.
+fn run_in(path: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    let exe = env::current_exe().context("finding the current executable")?;
+    let mut command = Command::new(exe);
+    command.args(args).current_dir(path);
+    for var in ["GIT_DIR", "GIT_WORK_TREE", "GIT_OBJECT_DIRECTORY"] {
+        command.env_remove(var);
+    }
+    let output = run(&mut command, b"")?;
+    if !output.status.success() {
+        bail!(
+            "{} failed in {}: {}",
+            args.join(" "),
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Check that a new worktree can be created at a path: it must not exist,
+/// or be an empty directory.
+pub fn check_path(path: &Path) -> Result<()> {
+    if fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some())
+        || path.exists() && !path.is_dir()
+    {
+        bail!("'{}' already exists", path.display());
+    }
+    Ok(())
+}
+
+/// Create a linked worktree at `path`, see check_path(), with HEAD set to `head` (the content of the file: a hash, or
+/// "ref: " and a branch name), then check it out like reset --hard does.
+/// Return what reset printed.
+pub fn add(repo: &Repository, path: &Path, head: &str) -> Result<Vec<u8>> {
+    check_path(path)?;
+    fs::create_dir_all(path).with_context(|| format!("creating {}", path.display()))?;
+    let path = fs::canonicalize(path)?;
+    let Some(name) = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+    else {
+        bail!("invalid worktree path {}", path.display());
+    };
+
+    // Like git, the id is the name of the directory, with a number added
+    // if needed to make it unique.
+    let worktrees = repo.common_dir().join("worktrees");
+    let mut id = name.clone();
+    let mut counter = 0;
+    while worktrees.join(&id).exists() {
+        counter += 1;
+        id = format!("{name}{counter}");
+    }
+    let git_dir = worktrees.join(&id);
+    fs::create_dir_all(&git_dir).with_context(|| format!("creating {}", git_dir.display()))?;
+    let files = [
+        (git_dir.join("commondir"), "../..\n".to_owned()),
+        (
+            git_dir.join("gitdir"),
+            format!("{}\n", path.join(".git").display()),
+        ),
+        (git_dir.join("HEAD"), format!("{head}\n")),
+        (
+            path.join(".git"),
+            format!("gitdir: {}\n", git_dir.display()),
+        ),
+    ];
+    for (file, content) in files {
+        fs::write(&file, content).with_context(|| format!("writing {}", file.display()))?;
+    }
+    let output = run_in(&path, &["reset", "--hard"]);
+    if output.is_err() {
+        let _ = fs::remove_dir_all(&path);
+        let _ = fs::remove_dir_all(&git_dir);
+    }
+    output
+}
+
+/// Remove a linked worktree (given by path) and its git directory. Unless
+/// forced, it must have no untracked files or changes from HEAD.
+pub fn remove(repo: &Repository, path: &Path, force: bool) -> Result<()> {
+    let full_path = fs::canonicalize(path)
+        .with_context(|| format!("'{}' is not a working tree", path.display()))?;
+    let Some(worktree) = list(repo)?.into_iter().find(|w| w.path == full_path) else {
+        bail!("'{}' is not a working tree", path.display());
+    };
+    if worktree.id.is_none() {
+        bail!("'{}' is a main working tree", path.display());
+    }
+    if !force {
+        let changes = run_in(&worktree.path, &["diff", "HEAD"])?;
+        let untracked = run_in(
+            &worktree.path,
+            &["ls-files", "--others", "--exclude-standard"],
+        )?;
+        if !changes.is_empty() || !untracked.is_empty() {
+            bail!(
+                "'{}' contains modified or untracked files, use --force to delete it",
+                path.display()
+            );
+        }
+    }
+    fs::remove_dir_all(&worktree.path)
+        .with_context(|| format!("removing {}", worktree.path.display()))?;
+    fs::remove_dir_all(&worktree.git_dir)
+        .with_context(|| format!("removing {}", worktree.git_dir.display()))?;
+    let _ = fs::remove_dir(repo.common_dir().join("worktrees"));
+    Ok(())
+}